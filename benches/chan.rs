@@ -1,106 +1,349 @@
-#![feature(test)]
-
-#[cfg(all(nightly, test))]
-mod bench {
-    #![cfg(nightly)]
-    extern crate test;
-
-    use self::test::Bencher;
-    use std::sync::mpsc::channel;
-    use std::sync::Arc;
-
-    use mco::chan;
-    use mco::std::queue::mpmc_bounded::Queue;
-    use std::thread;
-
-    #[bench]
-    fn bounded_mpmc(b: &mut Bencher) {
-        b.iter(|| {
-            let total_work = 1000_000;
-            let nthreads = 1;
-            let nmsgs = total_work / nthreads;
-            let q = Queue::with_capacity(nthreads * nmsgs);
-            assert_eq!(None, q.pop());
-            let (tx, rx) = chan!();
-
-            for _ in 0..nthreads {
-                let q = q.clone();
-                let tx = tx.clone();
-                thread::spawn(move || {
-                    let q = q;
-                    for i in 0..nmsgs {
-                        assert!(q.push(i).is_ok());
-                    }
-                    tx.send(()).unwrap();
-                });
-            }
+use criterion::{criterion_group, criterion_main, Bencher, Criterion};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+
+use mco::chan;
+use mco::std::queue::mpmc_bounded::Queue;
+use std::thread;
+
+fn bounded_mpmc(b: &mut Bencher) {
+    b.iter(|| {
+        let total_work = 1000_000;
+        let nthreads = 1;
+        let nmsgs = total_work / nthreads;
+        let q = Queue::with_capacity(nthreads * nmsgs);
+        assert_eq!(None, q.pop());
+        let (tx, rx) = chan!();
+
+        for _ in 0..nthreads {
+            let q = q.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let q = q;
+                for i in 0..nmsgs {
+                    assert!(q.push(i).is_ok());
+                }
+                tx.send(()).unwrap();
+            });
+        }
 
-            let mut completion_rxs = vec![];
-            for _ in 0..nthreads {
-                let (tx, rx) = channel();
-                completion_rxs.push(rx);
-                let q = q.clone();
-                thread::spawn(move || {
-                    let q = q;
-                    let mut i = 0;
-                    loop {
-                        match q.pop() {
-                            None => {}
-                            Some(_) => {
-                                i += 1;
-                                if i == nmsgs {
-                                    break;
-                                }
+        let mut completion_rxs = vec![];
+        for _ in 0..nthreads {
+            let (tx, rx) = channel();
+            completion_rxs.push(rx);
+            let q = q.clone();
+            thread::spawn(move || {
+                let q = q;
+                let mut i = 0;
+                loop {
+                    match q.pop() {
+                        None => {}
+                        Some(_) => {
+                            i += 1;
+                            if i == nmsgs {
+                                break;
                             }
                         }
                     }
-                    tx.send(i).unwrap();
-                });
-            }
+                }
+                tx.send(i).unwrap();
+            });
+        }
 
-            for rx in completion_rxs.iter_mut() {
-                assert_eq!(nmsgs, rx.recv().unwrap());
-            }
-            for _ in 0..nthreads {
-                rx.recv().unwrap();
+        for rx in completion_rxs.iter_mut() {
+            assert_eq!(nmsgs, rx.recv().unwrap());
+        }
+        for _ in 0..nthreads {
+            rx.recv().unwrap();
+        }
+    });
+}
+
+// #[bench]
+// the channel bench result show that it's 10 fold slow than our queue
+// not to mention the multi core contention
+#[allow(dead_code)]
+fn sys_stream_test(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx, rx) = channel();
+        let total_work: usize = 1000_000;
+        // create worker threads that generate mono increasing index
+        // in other thread the value should be still 100
+        thread::spawn(move || {
+            for i in 0..total_work {
+                tx.send(i).unwrap();
             }
         });
-    }
-
-    // #[bench]
-    // the channel bench result show that it's 10 fold slow than our queue
-    // not to mention the multi core contention
-    #[allow(dead_code)]
-    fn sys_stream_test(b: &mut Bencher) {
-        b.iter(|| {
-            let (tx, rx) = channel();
-            let total_work: usize = 1000_000;
-            // create worker threads that generate mono increasing index
-            // in other thread the value should be still 100
+
+        for i in 0..total_work {
+            assert_eq!(i, rx.recv().unwrap());
+        }
+    });
+}
+
+// improve performance  from 39,294 ns/iter to 12,207 ns/iter (my computer)
+//test bench_channel  ... bench:      12,207 ns/iter (+/- 118)
+fn bench_channel(b: &mut Bencher) {
+    b.iter(|| {
+        let (s, r) = chan!();
+        for _ in 0..1000 {
+            s.send(1);
+        }
+        for _ in 0..1000 {
+            let r = r.recv().unwrap();
+        }
+    });
+}
+
+// two busy channels serviced by a `select!` that is re-registered every iteration
+fn loop_select(b: &mut Bencher) {
+    b.iter(|| {
+        let (s1, r1) = chan!();
+        let (s2, r2) = chan!();
+        for i in 0..1000 {
+            s1.send(i).unwrap();
+            s2.send(i).unwrap();
+        }
+        let mut total = 0;
+        while total < 2000 {
+            mco::select! {
+                Ok(_) = r1.recv() => {},
+                Ok(_) = r2.recv() => {}
+            };
+            total += 1;
+        }
+    });
+}
+
+// the same two busy channels serviced by the persistent `select_loop!`
+fn select_loop(b: &mut Bencher) {
+    use std::ops::ControlFlow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    b.iter(|| {
+        let (s1, r1) = chan!();
+        let (s2, r2) = chan!();
+        for i in 0..1000 {
+            s1.send(i).unwrap();
+            s2.send(i).unwrap();
+        }
+        let total = AtomicUsize::new(0);
+        let count = || {
+            if total.fetch_add(1, Ordering::Relaxed) + 1 == 2000 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        };
+        mco::select_loop! {
+            Ok(_) = r1.recv() => count(),
+            Ok(_) = r2.recv() => count()
+        };
+    });
+}
+
+// 4 producer threads and 1 consumer over the mpmc channel
+fn mpmc_4_to_1(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx, rx) = mco::std::sync::channel::channel();
+        for _ in 0..4 {
+            let tx = tx.clone();
             thread::spawn(move || {
-                for i in 0..total_work {
+                for i in 0..10000 {
                     tx.send(i).unwrap();
                 }
             });
+        }
+        drop(tx);
+        assert_eq!(rx.iter().count(), 40000);
+    });
+}
 
-            for i in 0..total_work {
-                assert_eq!(i, rx.recv().unwrap());
+// the same 4 producer threads and 1 consumer over the specialized mpsc channel
+fn mpsc_4_to_1(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx, rx) = mco::std::sync::mpsc::channel();
+        for _ in 0..4 {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for i in 0..10000 {
+                    tx.send(i).unwrap();
+                }
+            });
+        }
+        drop(tx);
+        assert_eq!(rx.iter().count(), 40000);
+    });
+}
+
+// request/response pairs that allocate a general channel for each reply
+fn ping_pong_channel(b: &mut Bencher) {
+    let (tx, rx) = chan!();
+    mco::co!(move || {
+        for (v, reply) in rx.iter() {
+            let reply: mco::std::sync::channel::Sender<usize> = reply;
+            reply.send(v).unwrap();
+        }
+    });
+    b.iter(|| {
+        for i in 0..1000 {
+            let (reply_tx, reply_rx) = chan!();
+            tx.send((i, reply_tx)).unwrap();
+            assert_eq!(reply_rx.recv().unwrap(), i);
+        }
+    });
+}
+
+// the same request/response pairs that use a oneshot channel for each reply
+fn ping_pong_oneshot(b: &mut Bencher) {
+    use mco::std::sync::oneshot;
+    let (tx, rx) = chan!();
+    mco::co!(move || {
+        for (v, reply) in rx.iter() {
+            let reply: oneshot::Sender<usize> = reply;
+            reply.send(v).unwrap();
+        }
+    });
+    b.iter(|| {
+        for i in 0..1000 {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            tx.send((i, reply_tx)).unwrap();
+            assert_eq!(reply_rx.recv().unwrap(), i);
+        }
+    });
+}
+
+// round trips between two coroutines, the unblocked peer runs from the lifo slot
+fn ping_pong_coroutines(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx1, rx1) = chan!();
+        let (tx2, rx2) = chan!();
+        let h = mco::co!(move || {
+            for i in rx1.iter() {
+                tx2.send(i).unwrap();
             }
         });
-    }
-
-    // improve performance  from 39,294 ns/iter to 12,207 ns/iter (my computer)
-    //test bench_channel  ... bench:      12,207 ns/iter (+/- 118)
-    #[bench]
-    fn bench_channel(b: &mut Bencher) {
-        b.iter(|| {
-            let (s, r) = chan!();
-            for _ in 0..1000 {
-                s.send(1);
+        mco::co!(move || {
+            for i in 0..1000 {
+                tx1.send(i).unwrap();
+                assert_eq!(rx2.recv().unwrap(), i);
+            }
+        })
+        .join()
+        .unwrap();
+        h.join().unwrap();
+    });
+}
+
+// transfer 10k messages through a bounded channel one by one
+fn single_send(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx, rx) = chan!(128);
+        let h = mco::co!(move || {
+            for i in 0..10000 {
+                tx.send(i).unwrap();
             }
-            for _ in 0..1000 {
-                let r = r.recv().unwrap();
+        });
+        let mut sum = 0;
+        for i in rx.iter() {
+            sum += i;
+        }
+        h.join().unwrap();
+        sum
+    });
+}
+
+// transfer 10k messages between two threads through a bounded channel
+fn spsc_channel_threads(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx, rx) = chan!(128);
+        let h = thread::spawn(move || {
+            for i in 0..10000 {
+                tx.send(i).unwrap();
             }
         });
-    }
+        let sum: i32 = rx.iter().sum();
+        h.join().unwrap();
+        sum
+    });
+}
+
+// the same transfer through the spsc ring
+fn spsc_ring_threads(b: &mut Bencher) {
+    use mco::std::sync::spsc;
+    b.iter(|| {
+        let (mut tx, mut rx) = spsc::ring(128);
+        let h = thread::spawn(move || {
+            for i in 0..10000 {
+                tx.push_blocking(i).unwrap();
+            }
+        });
+        let mut sum = 0;
+        while let Ok(i) = rx.pop_blocking() {
+            sum += i;
+        }
+        h.join().unwrap();
+        sum
+    });
+}
+
+// the same transfer with send_all and recv_many
+fn batch_send(b: &mut Bencher) {
+    b.iter(|| {
+        let (tx, rx) = chan!(128);
+        let h = mco::co!(move || {
+            tx.send_all(0..10000).unwrap();
+        });
+        let mut sum = 0;
+        let mut buf = Vec::with_capacity(64);
+        while rx.recv_many(&mut buf, 64).is_ok() {
+            sum += buf.drain(..).sum::<i32>();
+        }
+        h.join().unwrap();
+        sum
+    });
+}
+
+// a producer pinned to worker 0 and a consumer pinned to worker 1, every
+// message unparks the consumer on the other worker, which is usually busy
+fn cross_worker_2_workers(b: &mut Bencher) {
+    use mco::coroutine::Builder;
+    use mco::runtime::{Config, Runtime};
+
+    let rt = Runtime::new(Config::new().workers(2)).unwrap();
+    b.iter(|| {
+        let (tx, rx) = chan!(1024);
+        let p = rt.spawn_with(Builder::new().pin(0), move || {
+            for i in 0..100_000usize {
+                tx.send(i).unwrap();
+            }
+        });
+        let c = rt.spawn_with(Builder::new().pin(1), move || rx.iter().sum::<usize>());
+        p.join().unwrap();
+        c.join().unwrap()
+    });
+}
+
+fn benches(c: &mut Criterion) {
+    c.bench_function("bounded_mpmc", bounded_mpmc);
+    c.bench_function("bench_channel", bench_channel);
+    c.bench_function("loop_select", loop_select);
+    c.bench_function("select_loop", select_loop);
+    c.bench_function("mpmc_4_to_1", mpmc_4_to_1);
+    c.bench_function("mpsc_4_to_1", mpsc_4_to_1);
+    c.bench_function("ping_pong_channel", ping_pong_channel);
+    c.bench_function("ping_pong_oneshot", ping_pong_oneshot);
+    c.bench_function("ping_pong_coroutines", ping_pong_coroutines);
+    c.bench_function("single_send", single_send);
+    c.bench_function("spsc_channel_threads", spsc_channel_threads);
+    c.bench_function("spsc_ring_threads", spsc_ring_threads);
+    c.bench_function("batch_send", batch_send);
+    c.bench_function("cross_worker_2_workers", cross_worker_2_workers);
+}
+
+criterion_group! {
+    name = chan_benches;
+    // the bodies are long, a few samples are enough
+    config = Criterion::default().sample_size(10);
+    targets = benches
 }
+criterion_main!(chan_benches);
@@ -1,15 +1,10 @@
-#![cfg(nightly)]
-#![feature(test)]
-
 #[macro_use]
 extern crate mco;
-extern crate test;
 
 use crate::coroutine::*;
+use criterion::{criterion_group, criterion_main, Bencher, Criterion};
 use mco::{config, coroutine};
-use test::Bencher;
 
-#[bench]
 fn yield_bench(b: &mut Bencher) {
     // don't print any panic info
     // when cancel the generator
@@ -23,7 +18,6 @@ fn yield_bench(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn spawn_bench(b: &mut Bencher) {
     b.iter(|| {
         let total_work = 1000;
@@ -47,7 +41,6 @@ fn spawn_bench(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn spawn_bench_1(b: &mut Bencher) {
     mco::config().set_pool_capacity(10000);
     b.iter(|| {
@@ -70,7 +63,6 @@ fn spawn_bench_1(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn smoke_bench(b: &mut Bencher) {
     mco::config().set_pool_capacity(10000);
     b.iter(|| {
@@ -94,7 +86,6 @@ fn smoke_bench(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn smoke_bench_1(b: &mut Bencher) {
     mco::config().set_pool_capacity(10000);
     b.iter(|| {
@@ -118,7 +109,6 @@ fn smoke_bench_1(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn smoke_bench_2(b: &mut Bencher) {
     mco::config().set_pool_capacity(10000);
     b.iter(|| {
@@ -142,7 +132,6 @@ fn smoke_bench_2(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn smoke_bench_3(b: &mut Bencher) {
     b.iter(|| {
         let mut vec = Vec::with_capacity(100);
@@ -170,3 +159,58 @@ fn smoke_bench_3(b: &mut Bencher) {
         }
     });
 }
+
+// spawn short lived coroutines with a non default stack size
+fn spawn_custom_stack(b: &mut Bencher) {
+    b.iter(|| {
+        let v = (0..1000)
+            .map(|_| spawn_with!(0x8000, || {}))
+            .collect::<Vec<_>>();
+        for h in v {
+            h.join().unwrap();
+        }
+    });
+}
+
+fn spawn_stack_pool(b: &mut Bencher) {
+    config().set_stack_pool(true);
+    spawn_custom_stack(b);
+}
+
+fn spawn_no_stack_pool(b: &mut Bencher) {
+    config().set_stack_pool(false);
+    spawn_custom_stack(b);
+    config().set_stack_pool(true);
+}
+
+fn spawn_join_small_ret_bench(b: &mut Bencher) {
+    mco::config().set_pool_capacity(10000);
+    b.iter(|| {
+        let a = [1usize; 4];
+        let v = (0..1000)
+            .map(|i| co!(move || a[i % 4] + i))
+            .collect::<Vec<_>>();
+        v.into_iter().map(|h| h.join().unwrap()).sum::<usize>()
+    });
+}
+
+fn benches(c: &mut Criterion) {
+    c.bench_function("yield_bench", yield_bench);
+    c.bench_function("spawn_bench", spawn_bench);
+    c.bench_function("spawn_bench_1", spawn_bench_1);
+    c.bench_function("smoke_bench", smoke_bench);
+    c.bench_function("smoke_bench_1", smoke_bench_1);
+    c.bench_function("smoke_bench_2", smoke_bench_2);
+    c.bench_function("smoke_bench_3", smoke_bench_3);
+    c.bench_function("spawn_stack_pool", spawn_stack_pool);
+    c.bench_function("spawn_no_stack_pool", spawn_no_stack_pool);
+    c.bench_function("spawn_join_small_ret_bench", spawn_join_small_ret_bench);
+}
+
+criterion_group! {
+    name = lib_benches;
+    // the bodies are long, a few samples are enough
+    config = Criterion::default().sample_size(10);
+    targets = benches
+}
+criterion_main!(lib_benches);
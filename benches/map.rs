@@ -1,14 +1,10 @@
-#![cfg(nightly)]
-#![feature(test)]
 #[macro_use]
 extern crate mco;
-extern crate test;
 
+use criterion::{criterion_group, criterion_main, Bencher, Criterion};
 use mco::std::sync::{Mutex, SyncHashMap};
 use std::collections::HashMap;
-use test::Bencher;
 
-#[bench]
 fn bench_sync_hash_map_write(b: &mut Bencher) {
     let m = SyncHashMap::new();
     let mut i = 0;
@@ -18,7 +14,6 @@ fn bench_sync_hash_map_write(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_mutex_hash_map_write(b: &mut Bencher) {
     let m = Mutex::new(HashMap::new());
     let mut i = 0;
@@ -28,7 +23,6 @@ fn bench_mutex_hash_map_write(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_sync_hash_map_read(b: &mut Bencher) {
     let m = SyncHashMap::new();
     for i in 0..1000000 {
@@ -39,7 +33,6 @@ fn bench_sync_hash_map_read(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_mutex_hash_map_read(b: &mut Bencher) {
     let m = Mutex::new(HashMap::new());
     for i in 0..1000000 {
@@ -49,3 +42,18 @@ fn bench_mutex_hash_map_read(b: &mut Bencher) {
         m.lock().unwrap().get(&0);
     });
 }
+
+fn benches(c: &mut Criterion) {
+    c.bench_function("bench_sync_hash_map_write", bench_sync_hash_map_write);
+    c.bench_function("bench_mutex_hash_map_write", bench_mutex_hash_map_write);
+    c.bench_function("bench_sync_hash_map_read", bench_sync_hash_map_read);
+    c.bench_function("bench_mutex_hash_map_read", bench_mutex_hash_map_read);
+}
+
+criterion_group! {
+    name = map_benches;
+    // the bodies are long, a few samples are enough
+    config = Criterion::default().sample_size(10);
+    targets = benches
+}
+criterion_main!(map_benches);
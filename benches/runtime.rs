@@ -0,0 +1,561 @@
+//! the runtime benchmarks, with criterion
+//!
+//! run them all, or the groups that match a filter:
+//! ```text
+//! cargo bench --bench runtime
+//! cargo bench --bench runtime -- channel
+//! ```
+//!
+//! each benchmark runs on a `Runtime` for each worker count in
+//! `MCO_BENCH_WORKERS`, a comma separated list that is `1,<cpus>` by default,
+//! e.g. `MCO_BENCH_WORKERS=1,2,8`. the id of a benchmark ends with the worker
+//! count. the measured loop runs in a coroutine of the runtime, so the
+//! numbers don't include the handoff from the bench thread
+//!
+//! the warmup: a new runtime spawns and joins a batch of coroutines first,
+//! so the workers are running and the stack pool is filled before anything
+//! is measured. then criterion runs each benchmark for `WARM_UP` before it
+//! takes the samples
+//!
+//! to compare a change against a baseline, save the baseline first:
+//! ```text
+//! git checkout main
+//! cargo bench --bench runtime -- --save-baseline main
+//! git checkout my-change
+//! cargo bench --bench runtime -- --baseline main
+//! ```
+//! the reports are in `target/criterion`, criterion tells if a change is
+//! beyond the noise
+//!
+//! the groups:
+//! - `spawn`: spawn and join a batch of coroutines
+//! - `channel`: the throughput of the spsc ring, the mpsc and the mpmc
+//!   channels, the receive of the 4KB messages by value and in place, and
+//!   the round trip latency of two channels
+//! - `select`: a `select!` of 2 arms and a `SelectSet` of 2 and 64 channels
+//! - `timer`: a short sleep, and a round trip where each side parks with a
+//!   timeout that is cancelled by the message. compare it with
+//!   `channel/round_trip` for the cost of the timers
+//! - `sweep`: put off the idle deadlines of 100k connections, in one
+//!   `DeadlineQueue` and with a watchdog coroutine per connection
+//! - `tcp`: a 64 byte echo round trip with a fixture server over loopback
+//! - `swap`: a read storm of `READERS` coroutines on a runtime of as many
+//!   workers, on a `Swap` and on an `RwLock`, with a writer that replaces
+//!   the value now and then
+
+#[macro_use]
+extern crate mco;
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc as std_mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mco::coroutine;
+use mco::net::{TcpListener, TcpStream};
+use mco::runtime::{Config, Runtime};
+use mco::select::SelectSet;
+use mco::std::sync::{channel, channel_buf, mpsc, spsc, RwLock, Swap};
+use mco::std::time::DeadlineQueue;
+
+const WARM_UP: Duration = Duration::from_secs(1);
+// the coroutines of a `spawn` round
+const BATCH: usize = 1000;
+// the messages of a `channel` throughput round
+const MSGS: usize = 10_000;
+// the producers and the consumers of the mpsc and mpmc channels
+const SIDES: usize = 4;
+// the 4KB messages of a `channel` round
+const PAGES: usize = 1000;
+type Page = [u8; 4096];
+// the connections of a `sweep` round
+const CONNS: usize = 100_000;
+// the readers and the workers of a `swap` round, and the reads of each
+const READERS: usize = 8;
+const READS: usize = 10_000;
+
+// the worker counts from `MCO_BENCH_WORKERS`
+fn worker_counts() -> Vec<usize> {
+    match std::env::var("MCO_BENCH_WORKERS") {
+        Ok(s) => s
+            .split(',')
+            .map(|n| {
+                n.trim()
+                    .parse()
+                    .expect("MCO_BENCH_WORKERS: bad worker count")
+            })
+            .collect(),
+        Err(_) => {
+            let cpus = num_cpus::get();
+            if cpus > 1 {
+                vec![1, cpus]
+            } else {
+                vec![1]
+            }
+        }
+    }
+}
+
+// a warmed up runtime for each worker count
+fn runtimes() -> Vec<(usize, Runtime)> {
+    worker_counts()
+        .into_iter()
+        .map(|workers| {
+            let rt = Runtime::new(Config::new().workers(workers)).unwrap();
+            in_runtime(&rt, || {
+                let hs: Vec<_> = (0..BATCH).map(|_| co!(|| ())).collect();
+                hs.into_iter().for_each(|h| h.join().unwrap());
+            });
+            (workers, rt)
+        })
+        .collect()
+}
+
+// run `f` in a coroutine of the runtime and wait for it
+fn in_runtime<F, T>(rt: &Runtime, f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    rt.spawn(f).join().unwrap()
+}
+
+// measure `iters` runs of `f` in a coroutine of the runtime
+fn measure<F>(rt: &Runtime, iters: u64, mut f: F) -> Duration
+where
+    F: FnMut() + Send + 'static,
+{
+    in_runtime(rt, move || {
+        let start = Instant::now();
+        for _ in 0..iters {
+            f();
+        }
+        start.elapsed()
+    })
+}
+
+fn spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for (workers, rt) in runtimes() {
+        group.bench_function(BenchmarkId::new("spawn_join", workers), |b| {
+            b.iter_custom(|iters| {
+                measure(&rt, iters, || {
+                    let hs: Vec<_> = (0..BATCH).map(|_| co!(|| ())).collect();
+                    hs.into_iter().for_each(|h| h.join().unwrap());
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn spsc_round() {
+    let (mut tx, mut rx) = spsc::ring(1024);
+    let h = co!(move || {
+        for i in 0..MSGS {
+            tx.push_blocking(i).unwrap();
+        }
+    });
+    for _ in 0..MSGS {
+        rx.pop_blocking().unwrap();
+    }
+    h.join().unwrap();
+}
+
+fn mpsc_round() {
+    let (tx, rx) = mpsc::channel();
+    let hs: Vec<_> = (0..SIDES)
+        .map(|_| {
+            let tx = tx.clone();
+            co!(move || {
+                for i in 0..MSGS / SIDES {
+                    tx.send(i).unwrap();
+                }
+            })
+        })
+        .collect();
+    for _ in 0..MSGS / SIDES * SIDES {
+        rx.recv().unwrap();
+    }
+    hs.into_iter().for_each(|h| h.join().unwrap());
+}
+
+fn mpmc_round() {
+    let (tx, rx) = channel_buf(1024);
+    let consumers: Vec<_> = (0..SIDES)
+        .map(|_| {
+            let rx = rx.clone();
+            co!(move || for _ in 0..MSGS / SIDES {
+                rx.recv().unwrap();
+            })
+        })
+        .collect();
+    let producers: Vec<_> = (0..SIDES)
+        .map(|_| {
+            let tx = tx.clone();
+            co!(move || {
+                for i in 0..MSGS / SIDES {
+                    tx.send(i).unwrap();
+                }
+            })
+        })
+        .collect();
+    producers.into_iter().for_each(|h| h.join().unwrap());
+    consumers.into_iter().for_each(|h| h.join().unwrap());
+}
+
+// a round of `PAGES` messages of 4KB, received by value or in their slots
+fn page_round(in_place: bool) {
+    let (tx, rx) = channel_buf::<Page>(64);
+    let h = co!(move || for i in 0..PAGES {
+        tx.send([i as u8; 4096]).unwrap();
+    });
+    for _ in 0..PAGES {
+        if in_place {
+            let page = rx.recv_ref().unwrap();
+            black_box(&*page);
+        } else {
+            let page = rx.recv().unwrap();
+            black_box(&page);
+        }
+    }
+    h.join().unwrap();
+}
+
+fn channel_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("channel");
+    for (workers, rt) in runtimes() {
+        group.throughput(Throughput::Elements(MSGS as u64));
+        group.bench_function(BenchmarkId::new("spsc", workers), |b| {
+            b.iter_custom(|iters| measure(&rt, iters, spsc_round))
+        });
+        group.bench_function(BenchmarkId::new("mpsc", workers), |b| {
+            b.iter_custom(|iters| measure(&rt, iters, mpsc_round))
+        });
+        group.bench_function(BenchmarkId::new("mpmc", workers), |b| {
+            b.iter_custom(|iters| measure(&rt, iters, mpmc_round))
+        });
+
+        // compare the two for the copies of the receive
+        group.throughput(Throughput::Elements(PAGES as u64));
+        group.bench_function(BenchmarkId::new("recv_4k", workers), |b| {
+            b.iter_custom(|iters| measure(&rt, iters, || page_round(false)))
+        });
+        group.bench_function(BenchmarkId::new("recv_ref_4k", workers), |b| {
+            b.iter_custom(|iters| measure(&rt, iters, || page_round(true)))
+        });
+
+        // one message there and back per iteration
+        group.throughput(Throughput::Elements(1));
+        group.bench_function(BenchmarkId::new("round_trip", workers), |b| {
+            b.iter_custom(|iters| {
+                in_runtime(&rt, move || {
+                    let (ping_tx, ping_rx) = channel::<u64>();
+                    let (pong_tx, pong_rx) = channel();
+                    let h = co!(move || {
+                        for v in ping_rx.iter() {
+                            pong_tx.send(v).unwrap();
+                        }
+                    });
+                    let start = Instant::now();
+                    for i in 0..iters {
+                        ping_tx.send(i).unwrap();
+                        pong_rx.recv().unwrap();
+                    }
+                    let spent = start.elapsed();
+                    drop(ping_tx);
+                    h.join().unwrap();
+                    spent
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn select_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("select");
+    group.throughput(Throughput::Elements(1));
+    for (workers, rt) in runtimes() {
+        // the selector coroutines of the macro are part of the cost
+        group.bench_function(BenchmarkId::new("macro_2", workers), |b| {
+            b.iter_custom(|iters| {
+                let (tx1, rx1) = channel();
+                let (_tx2, rx2) = channel::<usize>();
+                measure(&rt, iters, move || {
+                    tx1.send(1).unwrap();
+                    select! {
+                        v = rx1.recv() => assert_eq!(v, Ok(1)),
+                        _ = rx2.recv() => unreachable!()
+                    };
+                })
+            })
+        });
+        for &arms in &[2usize, 64] {
+            let name = format!("set_{}", arms);
+            group.bench_function(BenchmarkId::new(name, workers), |b| {
+                b.iter_custom(|iters| {
+                    in_runtime(&rt, move || {
+                        let (txs, rxs): (Vec<_>, Vec<_>) = (0..arms).map(|_| channel()).unzip();
+                        // a sender that picks a different arm each time
+                        let h = co!(move || {
+                            for i in 0..iters as usize {
+                                txs[i % arms].send(i).unwrap();
+                            }
+                        });
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            let mut set = SelectSet::new();
+                            for rx in rxs.iter() {
+                                set.add(rx, |v| v.unwrap());
+                            }
+                            set.select();
+                        }
+                        let spent = start.elapsed();
+                        h.join().unwrap();
+                        spent
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn timer_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("timer");
+    group.throughput(Throughput::Elements(1));
+    for (workers, rt) in runtimes() {
+        // a timer that is registered and fires
+        group.bench_function(BenchmarkId::new("sleep_1us", workers), |b| {
+            b.iter_custom(|iters| {
+                measure(&rt, iters, || coroutine::sleep(Duration::from_micros(1)))
+            })
+        });
+        // two timers that are registered and cancelled by the messages
+        group.bench_function(BenchmarkId::new("cancelled_round_trip", workers), |b| {
+            b.iter_custom(|iters| {
+                in_runtime(&rt, move || {
+                    const LONG: Duration = Duration::from_secs(60);
+                    let (ping_tx, ping_rx) = channel::<u64>();
+                    let (pong_tx, pong_rx) = channel();
+                    let h = co!(move || {
+                        while let Ok(v) = ping_rx.recv_timeout(LONG) {
+                            pong_tx.send(v).unwrap();
+                        }
+                    });
+                    let start = Instant::now();
+                    for i in 0..iters {
+                        ping_tx.send(i).unwrap();
+                        pong_rx.recv_timeout(LONG).unwrap();
+                    }
+                    let spent = start.elapsed();
+                    drop(ping_tx);
+                    h.join().unwrap();
+                    spent
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+// a sweep of the idle deadlines of `CONNS` connections, each one has a
+// request that puts its deadline off. the queue only writes the new deadline,
+// a watchdog coroutine per connection cancels and registers a timer
+fn sweep_benches(c: &mut Criterion) {
+    const IDLE: Duration = Duration::from_secs(60);
+    let mut group = c.benchmark_group("sweep");
+    group.throughput(Throughput::Elements(CONNS as u64));
+    group.sample_size(10);
+    for (workers, rt) in runtimes() {
+        group.bench_function(BenchmarkId::new("deadline_queue", workers), |b| {
+            b.iter_custom(|iters| {
+                in_runtime(&rt, move || {
+                    let q = Arc::new(DeadlineQueue::new());
+                    for conn in 0..CONNS {
+                        q.insert(conn, Instant::now() + IDLE);
+                    }
+                    let sweeper = q.clone();
+                    // it sleeps until the stop key, the others are put off
+                    let h = co!(move || while !sweeper.wait_next().contains(&usize::MAX) {});
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        for conn in 0..CONNS {
+                            q.update(&conn, Instant::now() + IDLE);
+                        }
+                    }
+                    let spent = start.elapsed();
+                    q.insert(usize::MAX, Instant::now());
+                    h.join().unwrap();
+                    spent
+                })
+            })
+        });
+        group.bench_function(BenchmarkId::new("watchdogs", workers), |b| {
+            b.iter_custom(|iters| {
+                in_runtime(&rt, move || {
+                    let done = Arc::new(AtomicUsize::new(0));
+                    let (txs, hs): (Vec<_>, Vec<_>) = (0..CONNS)
+                        .map(|_| {
+                            let (tx, rx) = channel::<()>();
+                            let done = done.clone();
+                            let h = co!(move || {
+                                while rx.recv_timeout(IDLE).is_ok() {
+                                    done.fetch_add(1, Ordering::Relaxed);
+                                }
+                            });
+                            (tx, h)
+                        })
+                        .unzip();
+                    let start = Instant::now();
+                    for i in 1..=iters as usize {
+                        for tx in txs.iter() {
+                            tx.send(()).unwrap();
+                        }
+                        // until each watchdog has its new timer
+                        while done.load(Ordering::Relaxed) < i * CONNS {
+                            coroutine::yield_now();
+                        }
+                    }
+                    let spent = start.elapsed();
+                    drop(txs);
+                    hs.into_iter().for_each(|h| h.join().unwrap());
+                    spent
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+// a read storm of `READERS` coroutines, each one on its own worker, with a
+// writer that replaces the value every 1000 reads of the first reader
+fn read_storm<R, W>(rt: &Runtime, iters: u64, read: R, write: W) -> Duration
+where
+    R: Fn() -> usize + Send + Sync + 'static,
+    W: Fn(usize) + Send + Sync + 'static,
+{
+    let read = Arc::new(read);
+    let write = Arc::new(write);
+    in_runtime(rt, move || {
+        let start = Instant::now();
+        let hs: Vec<_> = (0..READERS)
+            .map(|id| {
+                let (read, write) = (read.clone(), write.clone());
+                coroutine::Builder::new().pin(id).spawn(move || {
+                    for i in 0..iters as usize * READS {
+                        black_box(read());
+                        if id == 0 && i % 1000 == 0 {
+                            write(i);
+                        }
+                    }
+                })
+            })
+            .collect();
+        hs.into_iter().for_each(|h| h.join().unwrap());
+        start.elapsed()
+    })
+}
+
+fn swap_benches(c: &mut Criterion) {
+    #[derive(Default)]
+    struct Conf {
+        limits: [usize; 8],
+    }
+    let mut group = c.benchmark_group("swap");
+    group.throughput(Throughput::Elements((READERS * READS) as u64));
+    let rt = Runtime::new(Config::new().workers(READERS)).unwrap();
+    group.bench_function(BenchmarkId::new("swap_load", READERS), |b| {
+        b.iter_custom(|iters| {
+            let conf = Arc::new(Swap::new(Conf::default()));
+            let w = conf.clone();
+            read_storm(
+                &rt,
+                iters,
+                move || conf.load().limits[0],
+                move |i| w.store(Arc::new(Conf { limits: [i; 8] })),
+            )
+        })
+    });
+    group.bench_function(BenchmarkId::new("rwlock_read", READERS), |b| {
+        b.iter_custom(|iters| {
+            let conf = Arc::new(RwLock::new(Conf::default()));
+            let w = conf.clone();
+            read_storm(
+                &rt,
+                iters,
+                move || conf.read().unwrap().limits[0],
+                move |i| *w.write().unwrap() = Conf { limits: [i; 8] },
+            )
+        })
+    });
+    group.finish();
+}
+
+// the fixture echo server, it runs until the runtime is dropped
+fn echo_server(rt: &Runtime) -> SocketAddr {
+    let (tx, rx) = std_mpsc::channel();
+    rt.spawn(move || {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        tx.send(listener.local_addr().unwrap()).unwrap();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            co!(move || {
+                let mut buf = [0; 1024];
+                loop {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            if stream.write_all(&buf[..n]).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    rx.recv().unwrap()
+}
+
+fn tcp_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tcp");
+    group.throughput(Throughput::Bytes(64));
+    for (workers, rt) in runtimes() {
+        let addr = echo_server(&rt);
+        group.bench_function(BenchmarkId::new("echo_64", workers), |b| {
+            b.iter_custom(|iters| {
+                in_runtime(&rt, move || {
+                    let mut s = TcpStream::connect(addr).unwrap();
+                    s.set_nodelay(true).unwrap();
+                    let msg = [7u8; 64];
+                    let mut buf = [0u8; 64];
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        s.write_all(&msg).unwrap();
+                        s.read_exact(&mut buf).unwrap();
+                    }
+                    start.elapsed()
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn config() -> Criterion {
+    Criterion::default().warm_up_time(WARM_UP)
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = spawn, channel_benches, select_benches, timer_benches, sweep_benches, tcp_benches,
+        swap_benches
+}
+criterion_main!(benches);
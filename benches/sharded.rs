@@ -0,0 +1,103 @@
+// 8 workers, one write for every 1000 reads
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Once};
+
+use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion};
+use mco::std::sync::{RwLock, ShardedCounter, ShardedLock};
+
+const WORKERS: usize = 8;
+const OPS: usize = 10_000;
+
+fn setup() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        mco::config().set_workers(WORKERS);
+    });
+}
+
+fn run<F>(f: F)
+where
+    F: Fn(usize) + Clone + Send + 'static,
+{
+    let hs: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let f = f.clone();
+            mco::co!(move || {
+                for i in 0..OPS {
+                    f(i);
+                }
+            })
+        })
+        .collect();
+    for h in hs {
+        h.join().unwrap();
+    }
+}
+
+fn rwlock_read_heavy(b: &mut Bencher) {
+    setup();
+    let lock = Arc::new(RwLock::new(vec![0usize; 16]));
+    b.iter(|| {
+        let lock = lock.clone();
+        run(move |i| {
+            if i % 1000 == 0 {
+                lock.write().unwrap()[i % 16] += 1;
+            } else {
+                black_box(lock.read().unwrap()[i % 16]);
+            }
+        });
+    });
+}
+
+fn sharded_lock_read_heavy(b: &mut Bencher) {
+    setup();
+    let lock = Arc::new(ShardedLock::new(vec![0usize; 16]));
+    b.iter(|| {
+        let lock = lock.clone();
+        run(move |i| {
+            if i % 1000 == 0 {
+                lock.write().unwrap()[i % 16] += 1;
+            } else {
+                black_box(lock.read().unwrap()[i % 16]);
+            }
+        });
+    });
+}
+
+fn atomic_counter(b: &mut Bencher) {
+    setup();
+    let counter = Arc::new(AtomicU64::new(0));
+    b.iter(|| {
+        let c = counter.clone();
+        run(move |_| {
+            c.fetch_add(1, Ordering::Relaxed);
+        });
+        black_box(counter.load(Ordering::Relaxed));
+    });
+}
+
+fn sharded_counter(b: &mut Bencher) {
+    setup();
+    let counter = Arc::new(ShardedCounter::new());
+    b.iter(|| {
+        let c = counter.clone();
+        run(move |_| c.add(1));
+        black_box(counter.sum());
+    });
+}
+
+fn benches(c: &mut Criterion) {
+    c.bench_function("rwlock_read_heavy", rwlock_read_heavy);
+    c.bench_function("sharded_lock_read_heavy", sharded_lock_read_heavy);
+    c.bench_function("atomic_counter", atomic_counter);
+    c.bench_function("sharded_counter", sharded_counter);
+}
+
+criterion_group! {
+    name = sharded_benches;
+    // the bodies are long, a few samples are enough
+    config = Criterion::default().sample_size(10);
+    targets = benches
+}
+criterion_main!(sharded_benches);
@@ -1,31 +1,73 @@
-#![feature(test)]
+use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion};
+use mco::std::sync::WaitGroup;
+use mco::std::time::time::Time;
+use std::time::SystemTime;
 
-#[cfg(all(nightly, test))]
-mod bench {
-    #![feature(test)]
-    extern crate test;
-
-    use mco::std::sync::WaitGroup;
-    use mco::std::time::time::Time;
-    use test::Bencher;
+//test bench::single_thread_test ... bench:          44 ns/iter (+/- 1)
+fn single_thread_test(b: &mut Bencher) {
+    b.iter(|| {
+        let now = Time::now();
+    });
+}
 
-    //test bench::single_thread_test ... bench:          44 ns/iter (+/- 1)
-    #[bench]
-    fn single_thread_test(b: &mut Bencher) {
-        b.iter(|| {
-            let now = Time::now();
-        });
-    }
-
-    #[bench]
-    fn mult_thread_test(b: &mut Bencher) {
-        std::thread::spawn(move || {
-            for _ in 0..100000 {
-                let now = Time::now();
-            }
-        });
-        b.iter(|| {
+fn mult_thread_test(b: &mut Bencher) {
+    std::thread::spawn(move || {
+        for _ in 0..100000 {
             let now = Time::now();
-        });
-    }
+        }
+    });
+    b.iter(|| {
+        let now = Time::now();
+    });
+}
+
+// the baseline of `Time::now`
+fn system_time_now(b: &mut Bencher) {
+    b.iter(|| black_box(SystemTime::now()));
+}
+
+fn unix_nano(b: &mut Bencher) {
+    let now = Time::now();
+    b.iter(|| black_box(&now).unix_nano());
+}
+
+#[cfg(feature = "time-format")]
+fn format_rfc3339(b: &mut Bencher) {
+    use mco::std::time::RFC3339;
+    let now = Time::now();
+    b.iter(|| black_box(&now).format(RFC3339));
+}
+
+// the `Date` header of each response, formatted fresh
+fn http_date_format(b: &mut Bencher) {
+    b.iter(|| Time::now_utc().to_http_date());
+}
+
+// read from the cache that the timer updates each second
+fn http_date_cached(b: &mut Bencher) {
+    use mco::std::time::CachedHttpDate;
+    let cached = CachedHttpDate::new();
+    b.iter(|| {
+        let date = cached.get();
+        black_box(date.as_str().len())
+    });
+}
+
+fn benches(c: &mut Criterion) {
+    c.bench_function("single_thread_test", single_thread_test);
+    c.bench_function("mult_thread_test", mult_thread_test);
+    c.bench_function("system_time_now", system_time_now);
+    c.bench_function("unix_nano", unix_nano);
+    #[cfg(feature = "time-format")]
+    c.bench_function("format_rfc3339", format_rfc3339);
+    c.bench_function("http_date_format", http_date_format);
+    c.bench_function("http_date_cached", http_date_cached);
+}
+
+criterion_group! {
+    name = time_benches;
+    // the bodies are long, a few samples are enough
+    config = Criterion::default().sample_size(10);
+    targets = benches
 }
+criterion_main!(time_benches);
@@ -1,13 +1,9 @@
-#![cfg(nightly)]
-#![feature(test)]
 #[macro_use]
 extern crate mco;
-extern crate test;
 
+use criterion::{criterion_group, criterion_main, Bencher, Criterion};
 use mco::std::sync::{Mutex, SyncVec};
-use test::Bencher;
 
-#[bench]
 fn bench_sync_vec_push(b: &mut Bencher) {
     let m = SyncVec::new();
     let mut i = 0;
@@ -17,7 +13,6 @@ fn bench_sync_vec_push(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_mutex_vec_push(b: &mut Bencher) {
     let m = Mutex::new(Vec::new());
     let mut i = 0;
@@ -27,7 +22,6 @@ fn bench_mutex_vec_push(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_sync_vec_read(b: &mut Bencher) {
     let m = SyncVec::new();
     for i in 0..1000000 {
@@ -38,7 +32,6 @@ fn bench_sync_vec_read(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_mutex_vec_read(b: &mut Bencher) {
     let m = Mutex::new(Vec::new());
     for i in 0..1000000 {
@@ -49,7 +42,6 @@ fn bench_mutex_vec_read(b: &mut Bencher) {
     });
 }
 
-#[bench]
 fn bench_sync_vec_iter(b: &mut Bencher) {
     let m = SyncVec::new();
     for i in 0..1000000 {
@@ -57,3 +49,19 @@ fn bench_sync_vec_iter(b: &mut Bencher) {
     }
     b.iter(|| for x in &m {});
 }
+
+fn benches(c: &mut Criterion) {
+    c.bench_function("bench_sync_vec_push", bench_sync_vec_push);
+    c.bench_function("bench_mutex_vec_push", bench_mutex_vec_push);
+    c.bench_function("bench_sync_vec_read", bench_sync_vec_read);
+    c.bench_function("bench_mutex_vec_read", bench_mutex_vec_read);
+    c.bench_function("bench_sync_vec_iter", bench_sync_vec_iter);
+}
+
+criterion_group! {
+    name = vec_benches;
+    // the bodies are long, a few samples are enough
+    config = Criterion::default().sample_size(10);
+    targets = benches
+}
+criterion_main!(vec_benches);
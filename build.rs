@@ -4,9 +4,25 @@ const NIGHTLY: bool = true;
 #[rustversion::not(nightly)]
 const NIGHTLY: bool = false;
 
+// the default words of the return values kept in the join, see `INLINE_RET_WORDS`
+const INLINE_RET_WORDS: usize = 3;
+
 fn main() {
     // Set cfg flags depending on release channel
     if NIGHTLY {
         println!("cargo:rustc-cfg=nightly");
     }
+
+    println!("cargo:rerun-if-env-changed=MCO_INLINE_RET_WORDS");
+    let words = match std::env::var("MCO_INLINE_RET_WORDS") {
+        Ok(v) => match v.trim().parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => panic!(
+                "MCO_INLINE_RET_WORDS must be a positive number, got {:?}",
+                v
+            ),
+        },
+        Err(_) => INLINE_RET_WORDS,
+    };
+    println!("cargo:rustc-env=MCO_INLINE_RET_WORDS={}", words);
 }
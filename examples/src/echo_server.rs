@@ -0,0 +1,83 @@
+extern crate docopt;
+extern crate mco;
+#[macro_use]
+extern crate serde_derive;
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use docopt::Docopt;
+use mco::net::{TcpListener, TcpStream};
+
+const VERSION: &str = "0.1.0";
+
+const USAGE: &str = "
+Tcp echo server with one acceptor per worker.
+
+Usage:
+  echo_server [-t <threads>] [-p <port>] [-s <stack>] [-d <secs>]
+  echo_server (-h | --help)
+  echo_server (-v | --version)
+
+Options:
+  -h --help         Show this screen.
+  -v --version      Show version.
+  -t <threads>      number of threads to use [default: 4].
+  -p <address>      port of the server [default: 8080].
+  -s <stack>        stack size of the connection coroutines [default: 16384].
+  -d <secs>         shutdown after the seconds, 0 means run forever [default: 0].
+";
+
+#[derive(Debug, Deserialize)]
+struct Args {
+    flag_p: u16,
+    flag_t: usize,
+    flag_s: usize,
+    flag_d: u64,
+    flag_v: bool,
+}
+
+fn handle_client(mut stream: TcpStream) {
+    let mut buf = vec![0; 1024 * 4];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if let Err(e) = stream.write_all(&buf[0..n]) {
+                    return println!("write err = {:?}", e);
+                }
+            }
+            Err(e) => return println!("read err = {:?}", e),
+        }
+    }
+}
+
+/// simple test: echo hello | nc 127.0.0.1 8080
+fn main() {
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.deserialize())
+        .unwrap_or_else(|e| e.exit());
+
+    if args.flag_v {
+        return println!("echo_server: {}", VERSION);
+    }
+
+    mco::config().set_workers(args.flag_t);
+
+    let listener = TcpListener::bind(("0.0.0.0", args.flag_p)).unwrap();
+    // one acceptor coroutine for each worker
+    let server = listener
+        .accept_loop_with(0, args.flag_s, handle_client)
+        .unwrap();
+    println!("Starting tcp echo server on {:?}", server.local_addr());
+
+    if args.flag_d == 0 {
+        // the acceptors never exit without shutdown
+        return server.join();
+    }
+    mco::coroutine::sleep(Duration::from_secs(args.flag_d));
+    // stop accepting and wait for the connections in flight
+    server.shutdown();
+    server.join();
+    println!("echo server stopped");
+}
@@ -0,0 +1,109 @@
+//! serve the files of a directory with the zero-copy `TcpStream::send_file`
+//!
+//! cargo run --bin static_file -- [dir]
+extern crate httparse;
+#[macro_use]
+extern crate mco;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use httparse::Status;
+use mco::net::{TcpListener, TcpStream};
+
+// This example is for demonstration only, it only serves GET and never lists directories
+fn main() {
+    let root = std::env::args().nth(1).unwrap_or_else(|| ".".to_owned());
+    let root = Arc::new(PathBuf::from(root));
+    println!("serve {:?} on http://127.0.0.1:8080", root);
+    let listener = TcpListener::bind("0.0.0.0:8080").unwrap();
+    while let Ok((stream, _)) = listener.accept() {
+        let root = root.clone();
+        co!(move || {
+            if let Err(e) = serve(stream, &root) {
+                println!("err = {:?}", e);
+            }
+        });
+    }
+}
+
+fn serve(mut stream: TcpStream, root: &Path) -> std::io::Result<()> {
+    let mut buf = Vec::new();
+    let mut temp_buf = [0; 1024];
+    loop {
+        let mut path = String::new();
+        match req_done(&buf, &mut path) {
+            Some(i) => {
+                buf.drain(..i);
+                respond(&mut stream, root, &path)?;
+            }
+            None => match stream.read(&mut temp_buf)? {
+                0 => return Ok(()), // connection was closed
+                n => buf.extend_from_slice(&temp_buf[..n]),
+            },
+        }
+    }
+}
+
+fn respond(stream: &mut TcpStream, root: &Path, path: &str) -> std::io::Result<()> {
+    let file = resolve(root, path).and_then(|p| File::open(p).ok());
+    let file = match file.and_then(|f| f.metadata().ok().map(|m| (f, m))) {
+        Some((f, m)) if m.is_file() => (f, m.len()),
+        _ => {
+            let body = "Cannot find page\n";
+            let head = format!(
+                "HTTP/1.1 404 Not Found\r\nServer: mco\r\nContent-Length: {}\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes())?;
+            return stream.write_all(body.as_bytes());
+        }
+    };
+
+    let (file, len) = file;
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nServer: mco\r\nContent-Length: {}\r\n\r\n",
+        len
+    );
+    stream.write_all(head.as_bytes())?;
+    // the body goes from the page cache to the socket directly
+    let sent = stream.send_file(&file, 0, len)?;
+    if sent < len {
+        // the file is truncated while sending, the response can't be completed
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+// map the url path to a file under root, the `..` components are rejected
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = path
+        .split('?')
+        .next()
+        .unwrap_or("/")
+        .trim_start_matches('/');
+    let path = if path.is_empty() { "index.html" } else { path };
+    let path = Path::new(path);
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(root.join(path))
+}
+
+fn req_done(buf: &[u8], path: &mut String) -> Option<usize> {
+    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut req = httparse::Request::new(&mut headers);
+
+    if let Ok(Status::Complete(i)) = req.parse(buf) {
+        path.clear();
+        path.push_str(req.path.unwrap_or("/"));
+        return Some(i);
+    }
+
+    None
+}
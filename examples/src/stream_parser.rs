@@ -0,0 +1,39 @@
+use mco::coroutine::{self, GeneratorState};
+
+// the chunks of a stream as they come from the network, the records are
+// `key=value;` and split anywhere
+const CHUNKS: &[&str] = &["na", "me=mco;ver", "sion=0.1", ";empty=;", "lang=ru", "st;"];
+
+fn main() {
+    let mut chunks = CHUNKS.iter();
+    // the parser keeps its state in its locals, there is no state enum to
+    // resume from at each chunk boundary
+    let mut parser = coroutine::generator(move |yielder| {
+        let mut bytes = chunks.by_ref().flat_map(|c| c.chars());
+        let mut records = 0;
+        loop {
+            let mut key = String::new();
+            for c in bytes.by_ref() {
+                if c == '=' {
+                    break;
+                }
+                key.push(c);
+            }
+            if key.is_empty() {
+                return records;
+            }
+            let value: String = bytes.by_ref().take_while(|c| *c != ';').collect();
+            records += 1;
+            yielder.yield_val((key, value));
+        }
+    });
+    loop {
+        match parser.resume() {
+            GeneratorState::Yielded((key, value)) => println!("{} = {:?}", key, value),
+            GeneratorState::Complete(n) => {
+                println!("{} records", n);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! compare the datagrams per second of `send`/`recv_from` with the
+//! batched `send_mmsg`/`recv_mmsg`
+#[macro_use]
+extern crate mco;
+
+use std::time::{Duration, Instant};
+
+use mco::chan;
+use mco::net::{MsgBuf, OutMsg, UdpSocket};
+
+const PACKETS: usize = 1_000_000;
+const BATCH: usize = 16;
+const SIZE: usize = 64;
+// the datagrams in flight, small enough for the socket buffer
+const WINDOW: usize = 128;
+
+fn run(batched: bool) {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.connect(addr).unwrap();
+    // the receiver reports its progress so that nothing is dropped
+    let (ack_tx, ack_rx) = chan!();
+
+    let start = Instant::now();
+    let rx = co!(move || {
+        let mut count = 0;
+        let mut bufs = vec![[0u8; SIZE]; BATCH];
+        while count < PACKETS {
+            if batched {
+                let mut msgs: Vec<MsgBuf> = bufs.iter_mut().map(|b| MsgBuf::new(b)).collect();
+                count += server.recv_mmsg(&mut msgs).unwrap();
+            } else {
+                server.recv_from(&mut bufs[0]).unwrap();
+                count += 1;
+            }
+            let _ = ack_tx.send(count);
+        }
+    });
+
+    let tx = co!(move || {
+        let data = [0u8; SIZE];
+        let msgs = [OutMsg::new(&data); BATCH];
+        let (mut sent, mut acked) = (0, 0);
+        while sent < PACKETS {
+            while sent - acked + BATCH > WINDOW {
+                acked = ack_rx.recv().unwrap();
+            }
+            if batched {
+                sent += client.send_mmsg(&msgs).unwrap();
+            } else {
+                client.send(&data).unwrap();
+                sent += 1;
+            }
+        }
+    });
+
+    tx.join().unwrap();
+    rx.join().unwrap();
+    let dur = start.elapsed();
+    print_result(batched, dur);
+}
+
+fn print_result(batched: bool, dur: Duration) {
+    println!(
+        "{:>6}: {} datagrams in {:?}, {:.0} pps",
+        if batched { "mmsg" } else { "single" },
+        PACKETS,
+        dur,
+        PACKETS as f64 / dur.as_secs_f64()
+    );
+}
+
+fn main() {
+    run(false);
+    run(true);
+}
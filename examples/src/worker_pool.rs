@@ -0,0 +1,41 @@
+use mco::coroutine::sleep;
+use mco::std::pool::{Drain, WorkerPool};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn main() {
+    // 8 workers, at most 32 jobs wait in the queue
+    let pool = Arc::new(WorkerPool::new(8, 32, |n: u64| {
+        sleep(Duration::from_millis(10));
+        if n % 50 == 0 {
+            panic!("bad job {}", n);
+        }
+        println!("job:{}", n);
+    }));
+
+    // the producers block while the queue is full
+    let producers: Vec<_> = (0..4)
+        .map(|p| {
+            let pool = pool.clone();
+            mco::co!(move || {
+                for i in 0..100 {
+                    if pool.submit(p * 100 + i).is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+
+    // more workers for the burst, then back
+    pool.resize(16);
+    sleep(Duration::from_millis(100));
+    pool.resize(4);
+    println!("{:?}", pool.stats());
+
+    for p in producers {
+        p.join().unwrap();
+    }
+    let drained = pool.shutdown(Drain::Finish, Duration::from_secs(10));
+    println!("drained:{} {:?}", drained, pool.stats());
+}
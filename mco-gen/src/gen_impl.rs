@@ -12,7 +12,7 @@ use std::thread;
 use crate::reg_context::RegContext;
 use crate::rt::{Context, ContextStack, Error};
 use crate::scope::Scope;
-use crate::stack::{Func, Stack, StackBox};
+use crate::stack::{Func, Stack, StackBox, StackError};
 use crate::yield_::yield_now;
 
 // default stack size, in usize
@@ -141,6 +141,12 @@ impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
         self.gen.cancel()
     }
 
+    /// is started, the function is consumed by the first resume
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.gen.is_started()
+    }
+
     /// is finished
     #[inline]
     pub fn is_done(&self) -> bool {
@@ -151,6 +157,21 @@ impl<'a, A, T, const LOCAL: bool> GeneratorObj<'a, A, T, LOCAL> {
     pub fn stack_usage(&self) -> (usize, usize) {
         self.gen.stack_usage()
     }
+
+    /// get the committed stack memory in bytes
+    pub fn stack_committed(&self) -> usize {
+        self.gen.stack.committed_size()
+    }
+
+    /// give the unused stack memory of a done generator back to the os
+    /// the generator can still be re-initialized by `init_code` after that
+    pub fn decommit_stack(&self) {
+        assert!(
+            self.is_done(),
+            "can't decommit the stack of a running generator"
+        );
+        self.gen.stack.decommit();
+    }
 }
 
 impl<'a, T, const LOCAL: bool> Iterator for GeneratorObj<'a, (), T, LOCAL> {
@@ -253,6 +274,44 @@ impl<A: Any> Gn<A> {
         gen.init_code(f);
         Generator { gen }
     }
+
+    /// create a new generator that reserves a stack of `size` but only commits
+    /// the top `initial` words, the rest is committed when the stack grows into it
+    pub fn new_opt_lazy<'a, T: Any, F>(size: usize, initial: usize, f: F) -> Generator<'a, A, T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(Stack::new_lazy(size, initial));
+        gen.init_context();
+        gen.init_code(f);
+        Generator { gen }
+    }
+
+    /// like `new_opt`, but return the error if the stack can't be allocated
+    pub fn try_new_opt<'a, T: Any, F>(size: usize, f: F) -> Result<Generator<'a, A, T>, StackError>
+    where
+        F: FnOnce() -> T + Send + 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(Stack::try_new(size)?);
+        gen.init_context();
+        gen.init_code(f);
+        Ok(Generator { gen })
+    }
+
+    /// like `new_opt_lazy`, but return the error if the stack can't be reserved
+    pub fn try_new_opt_lazy<'a, T: Any, F>(
+        size: usize,
+        initial: usize,
+        f: F,
+    ) -> Result<Generator<'a, A, T>, StackError>
+    where
+        F: FnOnce() -> T + Send + 'a,
+    {
+        let mut gen = GeneratorImpl::<A, T>::new(Stack::try_new_lazy(size, initial)?);
+        gen.init_context();
+        gen.init_code(f);
+        Ok(Generator { gen })
+    }
 }
 
 /// `GeneratorImpl`
@@ -23,6 +23,7 @@ mod yield_;
 pub use crate::gen_impl::{Generator, Gn, LocalGenerator};
 pub use crate::rt::{get_local_data, is_generator, Error};
 pub use crate::scope::Scope;
+pub use crate::stack::StackError;
 pub use crate::yield_::{
     co_get_yield, co_set_para, co_yield_with, done, get_yield, yield_, yield_from, yield_with,
 };
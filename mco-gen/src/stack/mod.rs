@@ -272,7 +272,8 @@ impl SysStack {
     }
 
     /// Allocates a new stack of `size`.
-    fn allocate(mut size: usize, protected: bool) -> Result<SysStack, StackError> {
+    /// the lazy stack only reserves the address space, the memory is committed when touched
+    fn allocate(mut size: usize, protected: bool, lazy: bool) -> Result<SysStack, StackError> {
         let page_size = sys::page_size();
         let min_stack_size = sys::min_stack_size();
         let max_stack_size = sys::max_stack_size();
@@ -287,11 +288,19 @@ impl SysStack {
 
         if let Some(size) = size.checked_add(add) {
             if size <= max_stack_size {
-                let mut ret = unsafe { sys::allocate_stack(size) };
+                let mut ret = if lazy {
+                    unsafe { sys::allocate_stack_lazy(size) }
+                } else {
+                    unsafe { sys::allocate_stack(size) }
+                };
 
                 if protected {
                     if let Ok(stack) = ret {
                         ret = unsafe { sys::protect_stack(&stack) };
+                        // don't leak the mapping that can't be protected
+                        if ret.is_err() {
+                            unsafe { sys::deallocate_stack(stack.bottom(), stack.len()) };
+                        }
                     }
                 }
 
@@ -315,8 +324,38 @@ pub struct Stack {
 impl Stack {
     /// Allocate a new stack of `size`. If size = 0, this is a `dummy_stack`
     pub fn new(size: usize) -> Stack {
+        Self::alloc(size, false).expect("failed to alloc sys stack")
+    }
+
+    /// Like `new`, but return the error if the stack can't be allocated
+    pub fn try_new(size: usize) -> Result<Stack, StackError> {
+        Self::alloc(size, false)
+    }
+
+    /// Reserve a stack of `size` without committing the memory
+    /// only the top `initial` words are committed up front, the rest is committed
+    /// by the os when the stack grows into it
+    pub fn new_lazy(size: usize, initial: usize) -> Stack {
+        Self::try_new_lazy(size, initial).expect("failed to alloc sys stack")
+    }
+
+    /// Like `new_lazy`, but return the error if the stack can't be reserved
+    pub fn try_new_lazy(size: usize, initial: usize) -> Result<Stack, StackError> {
+        let stk = Self::alloc(size & !1, true)?;
+        let initial = std::cmp::min(initial, stk.size());
+        unsafe {
+            let top = (stk.buf.top as *mut usize).sub(initial);
+            // leave the stack box offset alone
+            ptr::write_bytes(top, 0, initial.saturating_sub(1));
+        }
+        Ok(stk)
+    }
+
+    fn alloc(size: usize, lazy: bool) -> Result<Stack, StackError> {
         let track = (size & 1) != 0;
-        let mut bytes = size * std::mem::size_of::<usize>();
+        let mut bytes = size
+            .checked_mul(std::mem::size_of::<usize>())
+            .ok_or_else(|| StackError::ExceedsMaximumSize(sys::max_stack_size()))?;
         // the minimal size
         let min_size = SysStack::min_size();
 
@@ -324,7 +363,7 @@ impl Stack {
             bytes = min_size;
         }
 
-        let buf = SysStack::allocate(bytes, true).expect("failed to alloc sys stack");
+        let buf = SysStack::allocate(bytes, true, lazy)?;
 
         let stk = Stack { buf };
 
@@ -345,7 +384,7 @@ impl Stack {
         let offset = stk.get_offset();
         unsafe { *offset = 1 };
 
-        stk
+        Ok(stk)
     }
 
     /// get used stack size
@@ -388,6 +427,22 @@ impl Stack {
         StackBox::<T>::new_unint(self, 1)
     }
 
+    /// get the committed stack size in bytes
+    pub fn committed_size(&self) -> usize {
+        unsafe { sys::committed_size(&self.buf) }
+    }
+
+    /// give the unused stack pages back to the os, the stack is still usable
+    /// the lowest page is kept to preserve the overflow footprint
+    pub fn decommit(&self) {
+        let page_size = sys::page_size();
+        let start = self.buf.bottom as usize + page_size;
+        let end = self.end() as usize & !(page_size - 1);
+        if end > start {
+            unsafe { sys::decommit_stack(start as *mut c_void, end - start) };
+        }
+    }
+
     // get offset
     fn get_offset(&self) -> *mut usize {
         unsafe { (self.buf.top as *mut usize).offset(-1) }
@@ -43,6 +43,23 @@ pub unsafe fn allocate_stack(size: usize) -> io::Result<SysStack> {
     }
 }
 
+pub unsafe fn allocate_stack_lazy(size: usize) -> io::Result<SysStack> {
+    const NULL: *mut libc::c_void = 0 as *mut libc::c_void;
+    const PROT: libc::c_int = libc::PROT_READ | libc::PROT_WRITE;
+    const TYPE: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_NORESERVE | MAP_STACK;
+
+    let ptr = libc::mmap(NULL, size, PROT, TYPE, -1, 0);
+
+    if ptr == libc::MAP_FAILED {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(SysStack::new(
+            (ptr as usize + size) as *mut c_void,
+            ptr as *mut c_void,
+        ))
+    }
+}
+
 pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
     let page_size = page_size();
 
@@ -61,6 +78,20 @@ pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
     }
 }
 
+pub unsafe fn decommit_stack(ptr: *mut c_void, size: usize) {
+    libc::madvise(ptr as *mut libc::c_void, size, libc::MADV_DONTNEED);
+}
+
+pub unsafe fn committed_size(stack: &SysStack) -> usize {
+    let page_size = page_size();
+    let mut vec = vec![0u8; stack.len() / page_size];
+    let ret = libc::mincore(stack.bottom(), stack.len(), vec.as_mut_ptr() as *mut _);
+    if ret != 0 {
+        return stack.len();
+    }
+    vec.iter().filter(|v| **v & 1 != 0).count() * page_size
+}
+
 pub unsafe fn deallocate_stack(ptr: *mut c_void, size: usize) {
     libc::munmap(ptr as *mut libc::c_void, size);
 }
@@ -9,7 +9,7 @@ use winapi::shared::minwindef::{DWORD, LPVOID};
 use winapi::um::memoryapi::{VirtualAlloc, VirtualFree, VirtualProtect};
 use winapi::um::sysinfoapi::GetSystemInfo;
 use winapi::um::winnt::{
-    MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_GUARD, PAGE_READONLY, PAGE_READWRITE,
+    MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, MEM_RESET, PAGE_GUARD, PAGE_READONLY, PAGE_READWRITE,
 };
 
 use super::SysStack;
@@ -31,6 +31,11 @@ pub unsafe fn allocate_stack(size: usize) -> io::Result<SysStack> {
     }
 }
 
+// the stack is always committed on windows
+pub unsafe fn allocate_stack_lazy(size: usize) -> io::Result<SysStack> {
+    allocate_stack(size)
+}
+
 pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
     const TYPE: DWORD = PAGE_READONLY | PAGE_GUARD;
 
@@ -52,6 +57,14 @@ pub unsafe fn protect_stack(stack: &SysStack) -> io::Result<SysStack> {
     }
 }
 
+pub unsafe fn decommit_stack(ptr: *mut c_void, size: usize) {
+    VirtualAlloc(ptr as LPVOID, size as SIZE_T, MEM_RESET, PAGE_READWRITE);
+}
+
+pub unsafe fn committed_size(stack: &SysStack) -> usize {
+    stack.len()
+}
+
 pub unsafe fn deallocate_stack(ptr: *mut c_void, _: usize) {
     VirtualFree(ptr as LPVOID, 0, MEM_RELEASE);
 }
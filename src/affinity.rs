@@ -0,0 +1,179 @@
+//! worker thread cpu affinity
+//!
+//! the workers are not pinned by default, set the affinity with
+//! `config().set_worker_affinity()` before the scheduler is started
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+static AFFINITY: Mutex<Affinity> = parking_lot::const_mutex(Affinity::None);
+// fast check for the runtime, set when the scheduler is started with an affinity
+pub(crate) static AFFINITY_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// a set of cpu ids
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CpuSet {
+    cpus: Vec<usize>,
+}
+
+impl CpuSet {
+    /// create a cpu set from the cpu ids
+    pub fn new<I: IntoIterator<Item = usize>>(cpus: I) -> Self {
+        let mut cpus: Vec<usize> = cpus.into_iter().collect();
+        cpus.sort_unstable();
+        cpus.dedup();
+        CpuSet { cpus }
+    }
+
+    /// the cpu ids in the set
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+}
+
+/// how the worker threads are pinned to cpus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Affinity {
+    /// don't pin the workers, this is the default
+    None,
+    /// pin each worker to one cpu, round robin over all the cpus
+    PerCore,
+    /// spread the workers over the given sockets, each worker is pinned
+    /// to all the cpus of its socket
+    Sockets(Vec<usize>),
+    /// pin the workers to the given cpu sets, round robin
+    Explicit(Vec<CpuSet>),
+}
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Affinity::None
+    }
+}
+
+pub(crate) fn set_affinity(affinity: Affinity) {
+    *AFFINITY.lock() = affinity;
+}
+
+pub(crate) fn get_affinity() -> Affinity {
+    AFFINITY.lock().clone()
+}
+
+/// the socket id of each online cpu, as (cpu, socket) pairs
+fn cpu_sockets() -> Vec<(usize, usize)> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut ret = Vec::new();
+        if let Ok(dir) = std::fs::read_dir("/sys/devices/system/cpu") {
+            for entry in dir.flatten() {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                let cpu = match name.strip_prefix("cpu").map(str::parse::<usize>) {
+                    Some(Ok(cpu)) => cpu,
+                    _ => continue,
+                };
+                let socket =
+                    std::fs::read_to_string(entry.path().join("topology/physical_package_id"))
+                        .ok()
+                        .and_then(|s| s.trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                ret.push((cpu, socket));
+            }
+        }
+        if !ret.is_empty() {
+            ret.sort_unstable();
+            return ret;
+        }
+    }
+    (0..num_cpus::get()).map(|cpu| (cpu, 0)).collect()
+}
+
+/// the cpu set and the socket of each worker, None means not pinned
+pub(crate) fn worker_placement(workers: usize) -> Vec<Option<(CpuSet, usize)>> {
+    let affinity = get_affinity();
+    AFFINITY_ENABLED.store(affinity != Affinity::None, Ordering::Relaxed);
+    let topology = cpu_sockets();
+    let socket_of = |cpu: usize| {
+        topology
+            .iter()
+            .find(|(c, _)| *c == cpu)
+            .map(|(_, s)| *s)
+            .unwrap_or(0)
+    };
+    (0..workers)
+        .map(|id| match &affinity {
+            Affinity::None => None,
+            Affinity::PerCore => {
+                let (cpu, socket) = topology[id % topology.len()];
+                Some((CpuSet::new(Some(cpu)), socket))
+            }
+            Affinity::Sockets(sockets) if !sockets.is_empty() => {
+                let socket = sockets[id % sockets.len()];
+                let cpus = topology
+                    .iter()
+                    .filter(|(_, s)| *s == socket)
+                    .map(|(c, _)| *c);
+                Some((CpuSet::new(cpus), socket))
+            }
+            Affinity::Explicit(sets) if !sets.is_empty() => {
+                let set = sets[id % sets.len()].clone();
+                let socket = set.cpus().first().map(|c| socket_of(*c)).unwrap_or(0);
+                Some((set, socket))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// pin the current thread to the cpu set
+pub(crate) fn pin_current(set: &CpuSet) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let mut cpu_set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in set.cpus() {
+            libc::CPU_SET(*cpu, &mut cpu_set);
+        }
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+        if libc::sched_setaffinity(0, size, &cpu_set) != 0 {
+            warn!(
+                "failed to set worker affinity, err={}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    warn!("worker affinity is not supported, cpus={:?}", set.cpus());
+}
+
+/// the cpu that the current thread runs on, None if it's not known
+#[inline]
+pub(crate) fn current_cpu() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let cpu = unsafe { libc::sched_getcpu() };
+        if cpu >= 0 {
+            return Some(cpu as usize);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_set() {
+        let set = CpuSet::new(vec![3, 1, 3, 2]);
+        assert_eq!(set.cpus(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn topology() {
+        let topology = cpu_sockets();
+        assert!(!topology.is_empty());
+        assert!(topology.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
@@ -0,0 +1,238 @@
+//! the blocking zones, see `coroutine::blocking_zone`
+//!
+//! a coroutine that calls a blocking function holds its worker for the whole
+//! call, the coroutines queued on the worker wait for it. a zone hands them
+//! to the other workers and, with `config().set_blocking_zone_workers()`,
+//! starts a temporary worker from the headroom of the max workers for as long
+//! as the call blocks, so the runtime keeps the number of the workers that
+//! run the coroutines. it's 0 by default, then no worker is started. the
+//! temporary worker is retired when the zone ends, it drains and parks like
+//! the workers of a shrink, so the next zone starts it again without a new
+//! thread. the temporary workers are counted apart from the ones of
+//! `set_workers`, a resize keeps them on top and a zone only retires its own
+//!
+//! the zones are counted by `stats().blocking_zones`, with a threshold set by
+//! `config().set_slow_blocking_zone()` the zones that take longer are logged
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::coroutine_impl::is_coroutine;
+use crate::scheduler::{get_scheduler, worker_id, Scheduler};
+use crate::timeout_list::now_instant;
+
+// the max temporary workers of a runtime at once, 0 for none
+static MAX_EXTRA: AtomicUsize = AtomicUsize::new(0);
+// the slow zone threshold in nanoseconds, 0 for off
+static SLOW: AtomicU64 = AtomicU64::new(0);
+// the zones, the slow ones, their total time and the temporary workers
+pub(crate) static ZONES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static SLOW_ZONES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static NANOS: AtomicU64 = AtomicU64::new(0);
+pub(crate) static EXTRA_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // the zones that the thread is in
+    static DEPTH: Cell<usize> = Cell::new(0);
+}
+
+// the thread is in a blocking zone
+pub(crate) fn in_zone() -> bool {
+    DEPTH.with(|d| d.get() != 0)
+}
+
+pub(crate) fn set_max_workers(n: usize) {
+    MAX_EXTRA.store(n, Ordering::Relaxed);
+}
+
+pub(crate) fn get_max_workers() -> usize {
+    MAX_EXTRA.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_slow(threshold: Duration) {
+    let nanos = threshold.as_nanos().min(u64::MAX as u128) as u64;
+    SLOW.store(nanos, Ordering::Relaxed);
+}
+
+pub(crate) fn get_slow() -> Duration {
+    Duration::from_nanos(SLOW.load(Ordering::Relaxed))
+}
+
+/// run a blocking call on the worker of the coroutine, e.g. a C function
+/// that borrows from the stack and so can't go to `spawn_blocking`
+///
+/// the coroutines queued on the worker move to the other workers and a
+/// temporary worker takes the place of this one while `f` runs, if
+/// `config().set_blocking_zone_workers()` allows one and the runtime has
+/// the headroom of its max workers, by default none is started. the worker
+/// is not reported as stalled during the zone. the coroutines pinned to the
+/// worker still wait for it
+///
+/// in a thread context `f` is just called, the zone is still counted. a
+/// panic of `f` ends the zone and is propagated to the caller
+///
+/// ```
+/// use mco::coroutine;
+///
+/// mco::config().set_max_workers(4).set_blocking_zone_workers(2);
+/// let h = mco::co!(|| {
+///     let mut buf = [0u8; 8];
+///     // stands for an ffi call that fills the borrowed buffer
+///     coroutine::blocking_zone(|| {
+///         std::thread::sleep(std::time::Duration::from_millis(10));
+///         buf[0] = 1;
+///     });
+///     buf[0]
+/// });
+/// assert_eq!(h.join().unwrap(), 1);
+/// ```
+pub fn blocking_zone<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let _zone = Zone::enter();
+    f()
+}
+
+struct Zone {
+    // the worker that blocks and its runtime, `None` in a thread context
+    worker: Option<(usize, &'static Scheduler)>,
+    // a temporary worker is started for the zone
+    extra: bool,
+    start: Instant,
+}
+
+impl Zone {
+    fn enter() -> Zone {
+        ZONES.fetch_add(1, Ordering::Relaxed);
+        DEPTH.with(|d| d.set(d.get() + 1));
+        let mut zone = Zone {
+            worker: None,
+            extra: false,
+            start: now_instant(),
+        };
+        if !is_coroutine() {
+            return zone;
+        }
+        let s = get_scheduler();
+        let id = worker_id();
+        if id >= s.max_workers() {
+            return zone;
+        }
+        zone.worker = Some((id, s));
+        s.rescue(id);
+        if let Some(slot) = s.run_slot(id) {
+            // blocked on purpose, it's not a stall
+            slot.idle();
+        }
+        zone.extra = grow(s);
+        zone
+    }
+}
+
+impl Drop for Zone {
+    fn drop(&mut self) {
+        let took = self.start.elapsed();
+        DEPTH.with(|d| d.set(d.get() - 1));
+        NANOS.fetch_add(took.as_nanos() as u64, Ordering::Relaxed);
+        let slow = SLOW.load(Ordering::Relaxed);
+        if slow != 0 && took.as_nanos() as u64 > slow {
+            SLOW_ZONES.fetch_add(1, Ordering::Relaxed);
+            match self.worker {
+                Some((id, _)) => warn!("blocking zone on worker {} took {:?}", id, took),
+                None => warn!("blocking zone took {:?}", took),
+            }
+        }
+        if let Some((id, s)) = self.worker {
+            if let Some(slot) = s.run_slot(id) {
+                slot.heartbeat();
+            }
+            if self.extra {
+                shrink(s);
+            }
+        }
+    }
+}
+
+// start a temporary worker, false if the runtime has none to spare
+fn grow(s: &'static Scheduler) -> bool {
+    let mut extra = s.zone_workers.lock();
+    if *extra >= MAX_EXTRA.load(Ordering::Relaxed) || s.is_shutdown() {
+        return false;
+    }
+    let n = s.worker_num() + 1;
+    if n > s.max_workers() || s.resize_to(n, false).is_err() {
+        return false;
+    }
+    *extra += 1;
+    EXTRA_WORKERS.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+// retire the temporary worker of a zone, the highest one drains and parks.
+// the workers that `set_workers` asked for are never retired here
+fn shrink(s: &'static Scheduler) {
+    let mut extra = s.zone_workers.lock();
+    if *extra == 0 {
+        // a resize up to the max workers gave it up already
+        return;
+    }
+    *extra -= 1;
+    let n = s.worker_num();
+    if n > 1 && !s.is_shutdown() {
+        // its pinned coroutines move to the remaining workers
+        let _ = s.resize_to(n - 1, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config;
+    use crate::runtime::{Config, Runtime};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn temporary_worker() {
+        config().set_blocking_zone_workers(1);
+        let rt = Runtime::new(Config::new().workers(1).max_workers(2)).unwrap();
+        let (tx, rx) = mpsc::channel();
+        let zones = ZONES.load(Ordering::Relaxed);
+        let h = rt.spawn(move || {
+            // the only worker blocks until the other coroutine runs
+            blocking_zone(|| rx.recv_timeout(Duration::from_secs(5)))
+        });
+        thread::sleep(Duration::from_millis(20));
+        rt.spawn(move || tx.send(()).unwrap());
+        assert_eq!(h.join().unwrap(), Ok(()));
+        assert!(ZONES.load(Ordering::Relaxed) > zones);
+        // the temporary worker is retired
+        assert_eq!(rt.workers(), 1);
+        assert_eq!(blocking_zone(|| 1 + 1), 2);
+    }
+
+    #[test]
+    fn resize_keeps_user_workers() {
+        config().set_blocking_zone_workers(1);
+        let rt = Runtime::new(Config::new().workers(1).max_workers(3)).unwrap();
+        let (in_tx, in_rx) = mpsc::channel();
+        let (out_tx, out_rx) = mpsc::channel();
+        let h = rt.spawn(move || {
+            blocking_zone(|| {
+                in_tx.send(()).unwrap();
+                out_rx.recv_timeout(Duration::from_secs(5))
+            })
+        });
+        in_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(rt.workers(), 2);
+        // the temporary worker stays on top of the new size
+        rt.set_workers(2).unwrap();
+        assert_eq!(rt.workers(), 3);
+        out_tx.send(()).unwrap();
+        assert_eq!(h.join().unwrap(), Ok(()));
+        // only the temporary worker is retired
+        assert_eq!(rt.workers(), 2);
+    }
+}
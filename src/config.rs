@@ -1,16 +1,53 @@
 //! `mco` Configuration interface
 //!
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::affinity::{self, Affinity};
+use crate::blocking_zone;
+use crate::determinism;
+use crate::thread_names;
+use crate::watchdog::{self, Stall};
 
 // default stack size, in usize
 // windows has a minimal size as 0x4a8!!!!
 const DEFAULT_STACK_SIZE: usize = 0x1000;
+// default stack size of the select coroutines, in usize
+const DEFAULT_SELECTOR_STACK_SIZE: usize = 0x800;
 const DEFAULT_POOL_CAPACITY: usize = 100;
+// default per worker stack pool capacity, in bytes
+const DEFAULT_STACK_POOL_CAPACITY: usize = 16 * 1024 * 1024;
+// default max bytes of one `TcpStream::send_file` syscall
+const DEFAULT_SEND_FILE_CHUNK: usize = 1024 * 1024;
 
 static WORKERS: AtomicUsize = AtomicUsize::new(0);
+static MAX_WORKERS: AtomicUsize = AtomicUsize::new(0);
 static STACK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_STACK_SIZE);
+static SELECTOR_STACK_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_SELECTOR_STACK_SIZE);
 static POOL_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_POOL_CAPACITY);
+static STACK_POOL_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_STACK_POOL_CAPACITY);
+static STACK_POOL: AtomicBool = AtomicBool::new(true);
+static SEND_FILE_CHUNK: AtomicUsize = AtomicUsize::new(DEFAULT_SEND_FILE_CHUNK);
+static WORKER_PANIC: AtomicUsize = AtomicUsize::new(WorkerPanic::Log as usize);
+
+/// what to do when a worker thread panics, see `Config::set_worker_panic`
+///
+/// the panics of the coroutines are caught and returned by the join handles,
+/// a worker only panics on a bug of the runtime or of an io driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerPanic {
+    /// log the panic, the worker is gone and the coroutines pinned to it
+    /// are stranded. the coroutines queued on it are moved to the others
+    Log,
+    /// start a replacement worker, it takes over the run queue, the timers
+    /// and the io registrations of the dead one. the coroutines pinned to
+    /// the dead worker are canceled when they are resumed next time, their
+    /// join handles return the cancel panic
+    Respawn,
+    /// abort the process, for the fail fast deployments
+    Abort,
+}
 
 /// `mco` Configuration type
 pub struct Config;
@@ -46,6 +83,41 @@ impl Config {
         }
     }
 
+    /// set the max worker thread number that `runtime::set_workers` can grow to
+    ///
+    /// the queues of the workers are allocated up front, the threads are started
+    /// when they are needed. if you pass 0 to it, one for each cpu is used. it's
+    /// never less than the worker number
+    pub fn set_max_workers(&self, workers: usize) -> &Self {
+        info!("set max workers={:?}", workers);
+        MAX_WORKERS.store(workers, Ordering::Relaxed);
+        self
+    }
+
+    /// get the max worker thread number
+    pub fn get_max_workers(&self) -> usize {
+        let max = match MAX_WORKERS.load(Ordering::Relaxed) {
+            0 => num_cpus::get(),
+            n => n,
+        };
+        max.max(self.get_workers())
+    }
+
+    /// set how the worker threads are pinned to cpus
+    ///
+    /// the workers are not pinned by default. when pinned, the workers steal
+    /// tasks from the workers on the same socket first
+    pub fn set_worker_affinity(&self, affinity: Affinity) -> &Self {
+        info!("set worker affinity={:?}", affinity);
+        affinity::set_affinity(affinity);
+        self
+    }
+
+    /// get the worker threads affinity
+    pub fn get_worker_affinity(&self) -> Affinity {
+        affinity::get_affinity()
+    }
+
     /// set the io worker thread number
     #[deprecated(since = "0.3.13", note = "use `set_workers` only")]
     pub fn set_io_workers(&self, _workers: usize) -> &Self {
@@ -84,4 +156,287 @@ impl Config {
     pub fn get_stack_size(&self) -> usize {
         STACK_SIZE.load(Ordering::Acquire)
     }
+
+    /// set the stack size of the select coroutines in usize, they are
+    /// spawned by `select!`, `cqueue_add!` and `cqueue_add_oneshot!` with
+    /// `Builder::selector`. the stacks have the guard page as the others
+    ///
+    /// the arm bodies of `select!` run on these stacks, set it to the default
+    /// stack size if they need a deep stack. if you pass 0 to it, will use
+    /// internal default
+    pub fn set_selector_stack_size(&self, size: usize) -> &Self {
+        info!("set selector stack size={:?}", size);
+        let size = if size == 0 {
+            DEFAULT_SELECTOR_STACK_SIZE
+        } else {
+            size
+        };
+        SELECTOR_STACK_SIZE.store(size, Ordering::Release);
+        self
+    }
+
+    /// get the stack size of the select coroutines
+    pub fn get_selector_stack_size(&self) -> usize {
+        SELECTOR_STACK_SIZE.load(Ordering::Acquire)
+    }
+
+    /// set the total bytes of cached stacks for each worker
+    ///
+    /// the stack pool caches finished coroutines that don't use the default stack size
+    /// if you pass 0 to it, will use internal default
+    pub fn set_stack_pool_capacity(&self, bytes: usize) -> &Self {
+        info!("set stack pool capacity={:?}", bytes);
+        STACK_POOL_CAPACITY.store(bytes, Ordering::Release);
+        self
+    }
+
+    /// get the stack pool capacity in bytes
+    pub fn get_stack_pool_capacity(&self) -> usize {
+        let size = STACK_POOL_CAPACITY.load(Ordering::Acquire);
+        if size != 0 {
+            size
+        } else {
+            DEFAULT_STACK_POOL_CAPACITY
+        }
+    }
+
+    /// enable or disable the stack pool, it's enabled by default
+    ///
+    /// disable it when debugging stack issues so that each coroutine gets a fresh stack
+    pub fn set_stack_pool(&self, enable: bool) -> &Self {
+        info!("set stack pool={:?}", enable);
+        STACK_POOL.store(enable, Ordering::Release);
+        self
+    }
+
+    /// get whether the stack pool is enabled
+    pub fn get_stack_pool(&self) -> bool {
+        STACK_POOL.load(Ordering::Acquire)
+    }
+
+    /// set the max bytes of one syscall of `TcpStream::send_file`, the
+    /// coroutine yields between the chunks so that a huge file doesn't hold
+    /// the worker. if you pass 0 to it, will use internal default
+    pub fn set_send_file_chunk(&self, bytes: usize) -> &Self {
+        info!("set send file chunk={:?}", bytes);
+        let bytes = if bytes == 0 {
+            DEFAULT_SEND_FILE_CHUNK
+        } else {
+            bytes
+        };
+        SEND_FILE_CHUNK.store(bytes, Ordering::Relaxed);
+        self
+    }
+
+    /// get the max bytes of one syscall of `TcpStream::send_file`
+    pub fn get_send_file_chunk(&self) -> usize {
+        SEND_FILE_CHUNK.load(Ordering::Relaxed)
+    }
+
+    /// set the time slice of the coroutines, it's off by default
+    ///
+    /// a watchdog thread logs the coroutines that run longer than the slice
+    /// without switching out, lists them in `coroutine::dump()` and flags
+    /// them so that their next `coroutine::checkpoint()` yields. pass a zero
+    /// duration to turn it off
+    pub fn set_time_slice(&self, slice: Duration) -> &Self {
+        info!("set time slice={:?}", slice);
+        watchdog::set_time_slice(slice);
+        self
+    }
+
+    /// get the time slice of the coroutines, zero for off
+    pub fn get_time_slice(&self) -> Duration {
+        watchdog::get_time_slice()
+    }
+
+    /// set the threshold of the stalled workers, it's off by default
+    ///
+    /// a worker that runs one coroutine or is stuck outside of one, e.g. in
+    /// a blocking ffi call, for longer than the threshold without a
+    /// heartbeat is logged, counted by `stats().stalls` and reported to the
+    /// `set_on_stall` callback once for each stall. a worker that waits for
+    /// the io events is never stalled. pass a zero duration to turn it off
+    pub fn set_stall_threshold(&self, threshold: Duration) -> &Self {
+        info!("set stall threshold={:?}", threshold);
+        watchdog::set_stall_threshold(threshold);
+        self
+    }
+
+    /// get the threshold of the stalled workers, zero for off
+    pub fn get_stall_threshold(&self) -> Duration {
+        watchdog::get_stall_threshold()
+    }
+
+    /// set the threshold of the blocking resumes, it's
+    /// `coroutine::DEBUG_BLOCKING_THRESHOLD` in the debug builds and off in
+    /// the release builds
+    ///
+    /// a coroutine that holds its worker longer than the threshold before it
+    /// gets to a yield point of the crate, most likely in a blocking call like
+    /// `std::thread::sleep` or `std::sync::Mutex::lock`, is logged with its
+    /// name when it switches out and counted by `stats().blocking_resumes`.
+    /// the resumes that enter a `coroutine::blocking_zone` are not checked.
+    /// pass a zero duration to turn it off
+    pub fn set_blocking_threshold(&self, threshold: Duration) -> &Self {
+        info!("set blocking threshold={:?}", threshold);
+        watchdog::set_blocking_threshold(threshold);
+        self
+    }
+
+    /// get the threshold of the blocking resumes, zero for off
+    pub fn get_blocking_threshold(&self) -> Duration {
+        watchdog::get_blocking_threshold()
+    }
+
+    /// move the coroutines queued on a stalled worker to the other workers,
+    /// it's off by default. the coroutines pinned to it are left there
+    pub fn set_stall_rescue(&self, rescue: bool) -> &Self {
+        info!("set stall rescue={:?}", rescue);
+        watchdog::set_stall_rescue(rescue);
+        self
+    }
+
+    /// get whether the coroutines are moved off a stalled worker
+    pub fn get_stall_rescue(&self) -> bool {
+        watchdog::get_stall_rescue()
+    }
+
+    /// set the max temporary workers that the blocking zones of a runtime
+    /// start at once, it's 0 by default
+    ///
+    /// a temporary worker runs the coroutines while a worker is blocked in
+    /// `coroutine::blocking_zone`, it's taken from the headroom of
+    /// `set_max_workers`, so a runtime that runs its max workers starts none
+    pub fn set_blocking_zone_workers(&self, workers: usize) -> &Self {
+        info!("set blocking zone workers={:?}", workers);
+        blocking_zone::set_max_workers(workers);
+        self
+    }
+
+    /// get the max temporary workers of the blocking zones
+    pub fn get_blocking_zone_workers(&self) -> usize {
+        blocking_zone::get_max_workers()
+    }
+
+    /// set the threshold of the slow blocking zones, it's off by default
+    ///
+    /// a `coroutine::blocking_zone` that takes longer is logged and counted
+    /// by `stats().slow_blocking_zones`. pass a zero duration to turn it off
+    pub fn set_slow_blocking_zone(&self, threshold: Duration) -> &Self {
+        info!("set slow blocking zone={:?}", threshold);
+        blocking_zone::set_slow(threshold);
+        self
+    }
+
+    /// get the threshold of the slow blocking zones, zero for off
+    pub fn get_slow_blocking_zone(&self) -> Duration {
+        blocking_zone::get_slow()
+    }
+
+    /// set the callback of the stalled workers, e.g. for the alerts, it
+    /// replaces the one set before
+    ///
+    /// it runs on the watchdog thread, a slow callback delays the next checks
+    pub fn set_on_stall<F>(&self, f: F) -> &Self
+    where
+        F: Fn(&Stall) + Send + Sync + 'static,
+    {
+        watchdog::set_on_stall(Some(Box::new(f)));
+        self
+    }
+
+    /// remove the callback of the stalled workers
+    pub fn clear_on_stall(&self) -> &Self {
+        watchdog::set_on_stall(None);
+        self
+    }
+
+    /// replay the scheduling of a test with a seed, it's off by default
+    ///
+    /// the choices of the runtime that depend on the timing, e.g. the victims
+    /// of the steals, the start of the `SelectSet` rounds and the order of the
+    /// timers due at once, come from generators seeded with it, so do the
+    /// numbers of `std::rand`. the default scheduler runs one worker, which
+    /// is the mode that the same seed replays the same interleaving in. the
+    /// other workers, a `Runtime` with more of them, the os preemption and the
+    /// io are not made deterministic. the seed is printed on each panic, and
+    /// the env var `MCO_SEED` replaces the seed that is passed here, so that a
+    /// failed run can be replayed:
+    /// ```text
+    /// MCO_SEED=42 cargo test my_racy_test
+    /// ```
+    /// it should be set before the first coroutine is spawned. `MCO_SEED`
+    /// turns it on for a program that doesn't set it
+    pub fn set_deterministic_seed(&self, seed: u64) -> &Self {
+        let seed = determinism::env_seed().unwrap_or(seed);
+        info!("set deterministic seed={:?}", seed);
+        determinism::set_seed(seed);
+        WORKERS.store(1, Ordering::Relaxed);
+        MAX_WORKERS.store(1, Ordering::Relaxed);
+        self
+    }
+
+    /// get the seed of the deterministic scheduling, `None` if it's off
+    pub fn get_deterministic_seed(&self) -> Option<u64> {
+        determinism::seed()
+    }
+
+    /// set the prefix of the names of the threads that the runtime starts,
+    /// it's `mco` by default
+    ///
+    /// the threads are named `<prefix>-worker-<id>`, `<prefix>-timer`,
+    /// `<prefix>-blocking-<n>`, `<prefix>-watchdog` and `<prefix>-bridge`,
+    /// so that `top -H`, gdb and perf tell them apart. linux cuts a name at
+    /// 15 bytes. it applies to the threads started after the call, so set it
+    /// before the first coroutine is spawned
+    pub fn set_thread_name_prefix(&self, prefix: &str) -> &Self {
+        info!("set thread name prefix={:?}", prefix);
+        thread_names::set_prefix(prefix);
+        self
+    }
+
+    /// get the prefix of the names of the threads
+    pub fn get_thread_name_prefix(&self) -> String {
+        thread_names::prefix()
+    }
+
+    /// name each worker thread after the coroutine that it runs, so that
+    /// `perf top` tells the samples of the coroutines apart, it's off by
+    /// default
+    ///
+    /// the name is cut to 15 bytes, a worker that runs an unnamed coroutine
+    /// gets its own name back. the rename is a syscall, it's only made when
+    /// the name changes from the last coroutine of the worker
+    #[cfg(all(feature = "co-thread-names", target_os = "linux"))]
+    pub fn set_coroutine_thread_names(&self, enable: bool) -> &Self {
+        info!("set coroutine thread names={:?}", enable);
+        thread_names::set_comm_enabled(enable);
+        self
+    }
+
+    /// get whether the worker threads take the names of the coroutines
+    #[cfg(all(feature = "co-thread-names", target_os = "linux"))]
+    pub fn get_coroutine_thread_names(&self) -> bool {
+        thread_names::comm_enabled()
+    }
+
+    /// set what to do when a worker thread panics, it's `WorkerPanic::Log`
+    /// by default. it applies to all the runtimes and can be changed at any time
+    ///
+    /// the worker panics are counted by `stats().worker_panics`
+    pub fn set_worker_panic(&self, policy: WorkerPanic) -> &Self {
+        info!("set worker panic={:?}", policy);
+        WORKER_PANIC.store(policy as usize, Ordering::Relaxed);
+        self
+    }
+
+    /// get what to do when a worker thread panics
+    pub fn get_worker_panic(&self) -> WorkerPanic {
+        match WORKER_PANIC.load(Ordering::Relaxed) {
+            1 => WorkerPanic::Respawn,
+            2 => WorkerPanic::Abort,
+            _ => WorkerPanic::Log,
+        }
+    }
 }
@@ -1,14 +1,28 @@
 // re-export coroutine interface
+pub use crate::blocking_zone::blocking_zone;
 pub use crate::cancel::trigger_cancel_panic;
 pub use crate::coroutine_impl::{
-    current, is_coroutine, park, park_timeout, spawn, try_current, Builder, Coroutine,
+    builder_defaults, context, current, is_coroutine, park, park_timeout, set_builder_defaults,
+    spawn, spawn_pinned, try_current, Builder, BuilderDefaults, Coroutine, CoroutineId,
+    ExecContext, SpawnError, StackKind, StartHandle, Tag,
 };
-pub use crate::join::JoinHandle;
+pub use crate::generator::{generator, Generator, GeneratorState, Yielder};
+pub use crate::hooks::{add_hooks, Exit, ExitHook, Hooks, StartHook};
+pub use crate::join::{AlreadyTaken, JoinHandle, INLINE_RET_WORDS};
+pub use crate::local::with_local_value;
 pub use crate::park::ParkError;
-pub use crate::scoped::scope;
-pub use crate::sleep::sleep;
+pub use crate::scoped::{
+    scope, scope_detached, scope_timeout, DetachedScope, ScopeHandle, ScopeTimedOut, Straggler,
+};
+pub use crate::sleep::{sleep, sleep_ctx};
+pub use crate::span::{Span, TraceParent, SPAWN_LATENCY};
+pub use crate::watchdog::{
+    checkpoint, dump, CoroutineInfo, Dump, Overrun, DEBUG_BLOCKING_THRESHOLD,
+};
 pub use crate::yield_now::yield_now;
 
+use std::convert::TryFrom;
+
 pub trait Spawn {
     /// spawn a new coroutine
     fn spawn<F, T>(self, f: F) -> JoinHandle<T>
@@ -17,6 +31,33 @@ pub trait Spawn {
         T: Send + 'static;
 }
 
+/// the spawn options accepted by the `spawn_with!` macro
+pub trait IntoBuilder {
+    /// convert to a coroutine builder
+    fn into_builder(self) -> Builder;
+}
+
+impl IntoBuilder for usize {
+    fn into_builder(self) -> Builder {
+        Builder::new().stack_size(self)
+    }
+}
+
+/// an untyped integer literal is an `i32`, a negative one panics
+impl IntoBuilder for i32 {
+    fn into_builder(self) -> Builder {
+        let size = usize::try_from(self)
+            .unwrap_or_else(|_| panic!("negative coroutine stack size: {}", self));
+        Builder::new().stack_size(size)
+    }
+}
+
+impl IntoBuilder for Builder {
+    fn into_builder(self) -> Builder {
+        self
+    }
+}
+
 impl Spawn for i32 {
     fn spawn<F, T>(self, f: F) -> JoinHandle<T>
     where
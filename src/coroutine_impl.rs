@@ -1,18 +1,33 @@
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use crate::affinity::{current_cpu, AFFINITY_ENABLED};
 use crate::cancel::Cancel;
 use crate::config::config;
 use crate::err;
+use crate::hooks::HookGuard;
 use crate::join::{make_join_handle, Join, JoinHandle};
 use crate::local::get_co_local_data;
-use crate::local::CoroutineLocal;
+use crate::local::{CoroutineLocal, LocalInit, LocalValues};
 use crate::park::Park;
-use crate::scheduler::get_scheduler;
-use crossbeam::atomic::AtomicCell;
-use mco_gen::{Generator, Gn};
+use crate::pool;
+use crate::scheduler::{
+    get_scheduler, is_current_sched, resized, respawned, runtimes_enabled, worker_id, Scheduler,
+};
+use crate::span::Span;
+use crate::stats;
+use crate::std::sync::{AtomicOption, MemoryBudget};
+#[cfg(all(feature = "co-thread-names", target_os = "linux"))]
+use crate::thread_names;
+use crate::watchdog;
+use mco_gen::{Generator, Gn, StackError};
+use parking_lot::Mutex;
 
 /// /////////////////////////////////////////////////////////////////////////////
 /// Coroutine framework types
@@ -60,7 +75,7 @@ impl Done {
         // assert!(co.is_done(), "unfinished coroutine detected");
         // just consume the coroutine
         // destroy the local storage
-        let local = unsafe { Box::from_raw(get_co_local(&co)) };
+        let mut local = unsafe { Box::from_raw(get_co_local(&co)) };
         let name = local.get_co().name();
 
         // recycle the coroutine
@@ -77,10 +92,62 @@ impl Done {
             );
         }
 
-        if size == config().get_stack_size() {
-            get_scheduler().pool.put(co);
+        let sched = local.get_co().inner.sched.load(Ordering::Relaxed);
+        if let Some(live) = unsafe { sched.as_ref() }.and_then(|s| s.live.as_ref()) {
+            live.fetch_sub(1, Ordering::Release);
         }
+        let pinned = local.get_co().inner.pinned.load(Ordering::Relaxed);
+        if pinned != !1 {
+            if let Some(s) = unsafe { sched.as_ref() } {
+                s.pinned_done(pinned);
+            }
+        }
+
+        let stack_size = local.get_co().stack_size();
+        if local.get_co().inner.growable {
+            let reserved = stack_size * std::mem::size_of::<usize>();
+            stats::GROWABLE_STACKS.fetch_sub(1, Ordering::Relaxed);
+            stats::GROWABLE_STACK_RESERVED.fetch_sub(reserved, Ordering::Relaxed);
+            stats::GROWABLE_STACK_COMMITTED.fetch_max(co.stack_committed(), Ordering::Relaxed);
+            drop(local);
+        } else if stack_size == config().get_stack_size() {
+            // the local storage is kept for the next spawn on the coroutine
+            local.finish();
+            get_scheduler().pool.put(co, local);
+        } else {
+            pool::put_stack(stack_size, size, co);
+            drop(local);
+        }
+        // the scheduler of a runtime may be freed with the last coroutine
+        unsafe { Scheduler::release(sched) };
+    }
+}
+
+// free a coroutine that is left in the queues of a runtime that is shut
+// down, see `Scheduler::drop_queued`
+pub(crate) fn drop_queued(co: CoroutineImpl) {
+    if co.is_started() {
+        // it can't be unwound off its workers
+        std::mem::forget(co);
+        return;
+    }
+    let local = unsafe { Box::from_raw(get_co_local(&co)) };
+    let inner = &local.get_co().inner;
+    if inner.growable {
+        let reserved = inner.stack_size * std::mem::size_of::<usize>();
+        stats::GROWABLE_STACKS.fetch_sub(1, Ordering::Relaxed);
+        stats::GROWABLE_STACK_RESERVED.fetch_sub(reserved, Ordering::Relaxed);
     }
+    let sched = inner.sched.load(Ordering::Relaxed);
+    if let Some(live) = unsafe { sched.as_ref() }.and_then(|s| s.live.as_ref()) {
+        live.fetch_sub(1, Ordering::Release);
+    }
+    // the closure is dropped without running it, the handle joins with the
+    // cancel error
+    drop(co);
+    local.get_join().trigger();
+    drop(local);
+    unsafe { Scheduler::release(sched) };
 }
 
 impl EventSource for Done {
@@ -99,16 +166,85 @@ fn get_co_local(co: &CoroutineImpl) -> *mut CoroutineLocal {
     co.get_local_data() as *mut CoroutineLocal
 }
 
+// the worker that the coroutine last ran on, !1 if none
+#[inline]
+pub(crate) fn last_worker_of(co: &CoroutineImpl) -> usize {
+    let local = unsafe { &*get_co_local(co) };
+    local.get_co().inner.last_worker.load(Ordering::Relaxed)
+}
+
+// the priority that the coroutine is scheduled with, see `Coroutine::priority`
+#[inline]
+pub(crate) fn priority_of(co: &CoroutineImpl) -> u8 {
+    let local = unsafe { &*get_co_local(co) };
+    local.get_co().priority()
+}
+
+#[inline]
+pub(crate) fn id_of(co: &CoroutineImpl) -> CoroutineId {
+    let local = unsafe { &*get_co_local(co) };
+    local.get_co().id()
+}
+
+// fast check for the schedule and the lock paths, set when a coroutine is
+// spawned with a priority
+#[inline]
+pub(crate) fn priority_enabled() -> bool {
+    PRIORITY_ENABLED.load(Ordering::Relaxed)
+}
+
 /// /////////////////////////////////////////////////////////////////////////////
 /// Coroutine
 /// /////////////////////////////////////////////////////////////////////////////
 
+/// the id of a coroutine, unique for the life of the process
+///
+/// the ids count up from 1 in the order that the coroutines are created and
+/// are never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CoroutineId(u64);
+
+impl CoroutineId {
+    fn next() -> CoroutineId {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        CoroutineId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// the id as a number
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for CoroutineId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
 /// The internal representation of a `Coroutine` handle
 struct Inner {
+    id: CoroutineId,
     name: Option<String>,
     stack_size: usize,
+    growable: bool,
+    // the worker that the coroutine last ran on
+    last_worker: AtomicUsize,
+    // the cpu that the coroutine last ran on
+    last_cpu: AtomicUsize,
+    // the worker that the coroutine is pinned to, `!1` for not pinned
+    pinned: AtomicUsize,
+    // the respawn epoch of the pinned worker when the coroutine is spawned
+    pin_epoch: AtomicUsize,
+    // the priority that the coroutine is spawned with
+    priority: AtomicU8,
+    // the priority lent by the waiters of a `Mutex` that it holds
+    boost: AtomicU8,
+    // the scheduler that the coroutine belongs to, null for the thread context
+    sched: AtomicPtr<Scheduler>,
     park: Park,
     cancel: Cancel,
+    tag: Option<Tag>,
 }
 
 #[derive(Clone)]
@@ -121,22 +257,120 @@ unsafe impl Send for Coroutine {}
 
 impl Coroutine {
     // Used only internally to construct a coroutine object without spawning
-    fn new(name: Option<String>, stack_size: usize) -> Coroutine {
+    fn new(name: Option<String>, stack_size: usize, growable: bool, tag: Option<Tag>) -> Coroutine {
         Coroutine {
             inner: Arc::new(Inner {
+                id: CoroutineId::next(),
                 name,
                 stack_size,
+                growable,
+                last_worker: AtomicUsize::new(!1),
+                last_cpu: AtomicUsize::new(!1),
+                pinned: AtomicUsize::new(!1),
+                pin_epoch: AtomicUsize::new(0),
+                priority: AtomicU8::new(0),
+                boost: AtomicU8::new(0),
+                sched: AtomicPtr::new(ptr::null_mut()),
                 park: Park::new(),
                 cancel: Cancel::new(),
+                tag,
             }),
         }
     }
 
+    // the handle of a new coroutine that reuses the one of a finished pooled
+    // coroutine if nothing else holds it, the pooled ones have a fixed stack
+    fn renew(
+        old: &mut Coroutine,
+        name: Option<String>,
+        stack_size: usize,
+        tag: Option<Tag>,
+    ) -> Coroutine {
+        let inner = match Arc::get_mut(&mut old.inner) {
+            Some(inner) => inner,
+            None => return Coroutine::new(name, stack_size, false, tag),
+        };
+        inner.id = CoroutineId::next();
+        inner.name = name;
+        inner.stack_size = stack_size;
+        *inner.last_worker.get_mut() = !1;
+        *inner.last_cpu.get_mut() = !1;
+        *inner.pinned.get_mut() = !1;
+        *inner.pin_epoch.get_mut() = 0;
+        *inner.priority.get_mut() = 0;
+        *inner.boost.get_mut() = 0;
+        *inner.sched.get_mut() = ptr::null_mut();
+        // the old cancel may still hold the `wait_co` of the park
+        inner.cancel = Cancel::new();
+        inner.park.reset();
+        inner.tag = tag;
+        old.clone()
+    }
+
+    /// Gets the id of the coroutine, the handles of the same coroutine
+    /// compare and hash by it
+    pub fn id(&self) -> CoroutineId {
+        self.inner.id
+    }
+
     /// Gets the coroutine stack size.
     pub fn stack_size(&self) -> usize {
         self.inner.stack_size
     }
 
+    /// Gets the id of the worker that the coroutine last ran on
+    ///
+    /// return None if the coroutine never ran on a worker
+    pub fn last_worker(&self) -> Option<usize> {
+        match self.inner.last_worker.load(Ordering::Relaxed) {
+            id if id == !1 => None,
+            id => Some(id),
+        }
+    }
+
+    /// Gets the id of the cpu that the coroutine last ran on
+    ///
+    /// it's only tracked when the worker affinity is set, it's only known on
+    /// linux
+    pub fn last_cpu(&self) -> Option<usize> {
+        match self.inner.last_cpu.load(Ordering::Relaxed) {
+            id if id == !1 => None,
+            id => Some(id),
+        }
+    }
+
+    /// Gets the priority that the coroutine is scheduled with
+    ///
+    /// it's the one of [`Builder::priority`], or the higher one of a
+    /// coroutine that waits for a [`Mutex`] that it holds
+    ///
+    /// [`Builder::priority`]: ./struct.Builder.html#method.priority
+    /// [`Mutex`]: ../std/sync/struct.Mutex.html
+    pub fn priority(&self) -> u8 {
+        let priority = self.inner.priority.load(Ordering::Relaxed);
+        priority.max(self.inner.boost.load(Ordering::Relaxed))
+    }
+
+    // lend the priority of a waiter, return true if it's raised. the
+    // coroutine is moved up if it's waiting in the priority queue
+    pub(crate) fn boost(&self, priority: u8) -> bool {
+        if priority <= self.priority()
+            || self.inner.boost.fetch_max(priority, Ordering::Relaxed) >= priority
+        {
+            return false;
+        }
+        let sched = self.inner.sched.load(Ordering::Relaxed);
+        if !sched.is_null() {
+            unsafe { &*sched }.raise_queued(self.id(), priority);
+        }
+        true
+    }
+
+    // give the lent priority back
+    pub(crate) fn unboost(&self) {
+        self.inner.boost.store(0, Ordering::Relaxed);
+    }
+
     /// Atomically makes the handle's token available if it is not already.
     pub fn unpark(&self) {
         self.inner.park.unpark();
@@ -157,6 +391,23 @@ impl Coroutine {
         self.inner.name.as_deref()
     }
 
+    /// Gets the coroutine tag, see [`Builder::tag`]
+    ///
+    /// [`Builder::tag`]: ./struct.Builder.html#method.tag
+    pub fn tag(&self) -> Option<&Tag> {
+        self.inner.tag.as_ref()
+    }
+
+    /// Gets the id of the worker that the coroutine is pinned to
+    ///
+    /// return None if the coroutine is not spawned with `Builder::pin`
+    pub fn pinned_worker(&self) -> Option<usize> {
+        match self.inner.pinned.load(Ordering::Relaxed) {
+            id if id == !1 => None,
+            id => Some(id),
+        }
+    }
+
     /// Get the internal cancel
     #[cfg(unix)]
     pub(crate) fn get_cancel(&self) -> &Cancel {
@@ -170,19 +421,128 @@ impl fmt::Debug for Coroutine {
     }
 }
 
+impl PartialEq for Coroutine {
+    fn eq(&self, other: &Coroutine) -> bool {
+        self.inner.id == other.inner.id
+    }
+}
+
+impl Eq for Coroutine {}
+
+impl Hash for Coroutine {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.id.hash(state)
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Builder
 ////////////////////////////////////////////////////////////////////////////////
 
+/// The tag of a coroutine, it's passed to the [`Hooks`] through the handle
+///
+/// [`Hooks`]: ./struct.Hooks.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Tag {
+    /// set by [`Builder::tag`](./struct.Builder.html#method.tag)
+    Num(u64),
+    /// set by [`Builder::tag_str`](./struct.Builder.html#method.tag_str)
+    Str(Arc<str>),
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tag::Num(n) => write!(f, "{}", n),
+            Tag::Str(s) => f.write_str(s),
+        }
+    }
+}
+
+/// The stack kind of a coroutine, the sizes are in usize
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackKind {
+    /// a stack that is allocated with the full size
+    Fixed(usize),
+    /// a stack that reserves `max` words of address space but only commits the
+    /// top `initial` words up front, the rest is committed by the os when the
+    /// coroutine grows into it. the guard page is still at the `max` boundary
+    ///
+    /// only unix supports the lazy commit, on windows it's the same as `Fixed(max)`
+    Growable { initial: usize, max: usize },
+}
+
+/// The process wide defaults of the coroutine [`Builder`]
+///
+/// the fields that are not set on a builder are taken from here, see
+/// [`set_builder_defaults`]
+///
+/// [`Builder`]: ./struct.Builder.html
+/// [`set_builder_defaults`]: ./fn.set_builder_defaults.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuilderDefaults {
+    /// the stack size in usize, `None` means `config().get_stack_size()`
+    pub stack_size: Option<usize>,
+    /// the coroutine name prefix, a per spawn sequence number is appended
+    /// as `prefix-seq`. `None` means the coroutines are not named
+    pub name_prefix: Option<String>,
+}
+
+static BUILDER_DEFAULTS: Mutex<Option<BuilderDefaults>> = parking_lot::const_mutex(None);
+// fast check for the spawn path
+static HAS_BUILDER_DEFAULTS: AtomicBool = AtomicBool::new(false);
+static SPAWN_SEQ: AtomicUsize = AtomicUsize::new(0);
+// fast check for the run path, set when a pinned coroutine is spawned
+static PINNED_ENABLED: AtomicBool = AtomicBool::new(false);
+// see `priority_enabled`
+static PRIORITY_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// set the defaults for all the coroutines spawned after this call,
+/// including the ones spawned by `co!` and the `spawn` free function
+///
+/// # Examples
+///
+/// ```
+/// use mco::coroutine::{self, BuilderDefaults};
+///
+/// coroutine::set_builder_defaults(BuilderDefaults {
+///     stack_size: Some(0x8000),
+///     name_prefix: Some("worker".to_owned()),
+/// });
+/// ```
+pub fn set_builder_defaults(defaults: BuilderDefaults) {
+    info!("set builder defaults={:?}", defaults);
+    let has = defaults != BuilderDefaults::default();
+    *BUILDER_DEFAULTS.lock() = Some(defaults);
+    HAS_BUILDER_DEFAULTS.store(has, Ordering::Release);
+}
+
+/// get the current builder defaults
+pub fn builder_defaults() -> BuilderDefaults {
+    BUILDER_DEFAULTS.lock().clone().unwrap_or_default()
+}
+
+// the prefixed name with the next spawn sequence number
+fn seq_name(prefix: &str) -> String {
+    let seq = SPAWN_SEQ.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{}", prefix, seq)
+}
+
 /// Coroutine factory, which can be used in order to configure the properties of
 /// a new coroutine.
 ///
 /// Methods can be chained on it in order to configure it.
 ///
-/// The two configurations available are:
+/// The configurations available are:
 ///
 /// - [`name`]: specifies an [associated name for the coroutine][naming-coroutines]
 /// - [`stack_size`]: specifies the [desired stack size for the coroutine][stack-size]
+/// - [`stack`]: specifies a fixed or growable [`StackKind`] for the coroutine
+/// - [`name_fn`]: specifies a function that generates the name when spawned
+///
+/// The configurations that are not set are taken from the process wide
+/// [`BuilderDefaults`], use [`default_template`] to get a builder that has
+/// them filled in explicitly.
 ///
 /// The [`spawn`] method will take ownership of the builder and create an
 /// `io::Result` to the coroutine handle with the given configuration.
@@ -211,16 +571,36 @@ impl fmt::Debug for Coroutine {
 ///
 /// [`coroutine::spawn`]: ./fn.spawn.html
 /// [`stack_size`]: ./struct.Builder.html#method.stack_size
+/// [`stack`]: ./struct.Builder.html#method.stack
+/// [`StackKind`]: ./enum.StackKind.html
 /// [`name`]: ./struct.Builder.html#method.name
+/// [`name_fn`]: ./struct.Builder.html#method.name_fn
+/// [`default_template`]: ./struct.Builder.html#method.default_template
+/// [`BuilderDefaults`]: ./struct.BuilderDefaults.html
 /// [`spawn`]: ./struct.Builder.html#method.spawn
 /// [naming-coroutines]: ./index.html#naming-coroutine
 /// [stack-size]: ./index.html#stack-siz
-#[derive(Default)]
 pub struct Builder {
     // A name for the coroutine-to-be, for identification in panic messages
     name: Option<String>,
+    // Generates the name when the coroutine is spawned
+    name_fn: Option<Box<dyn FnOnce() -> String + Send>>,
     // The size of the stack for the spawned coroutine
     stack_size: Option<usize>,
+    // The initial committed stack size for a growable stack
+    growable: Option<usize>,
+    // The worker that the coroutine always runs on
+    pin: Option<usize>,
+    // The priority that the coroutine is scheduled with
+    priority: u8,
+    // The values that live in the coroutine as long as the body
+    locals: Vec<LocalInit>,
+    // The tag that is passed to the hooks
+    tag: Option<Tag>,
+    // The budget that the stack is charged to
+    budget: Option<MemoryBudget>,
+    // The span that the coroutine runs in
+    span: Option<Span>,
 }
 
 impl Builder {
@@ -229,27 +609,258 @@ impl Builder {
     pub fn new() -> Builder {
         Builder {
             name: None,
+            name_fn: None,
             stack_size: None,
+            growable: None,
+            pin: None,
+            priority: 0,
+            locals: Vec::new(),
+            tag: None,
+            budget: None,
+            span: None,
+        }
+    }
+
+    /// Generates a builder with the current [`BuilderDefaults`] filled in,
+    /// the fields can be overridden by the chained methods.
+    ///
+    /// [`BuilderDefaults`]: ./struct.BuilderDefaults.html
+    pub fn default_template() -> Builder {
+        let defaults = builder_defaults();
+        let mut builder = Builder::new();
+        builder.stack_size = defaults.stack_size;
+        if let Some(prefix) = defaults.name_prefix {
+            builder = builder.name_fn(move || seq_name(&prefix));
         }
+        builder
+    }
+
+    /// Generates the preset of the select coroutines, with the small stack of
+    /// `config().get_selector_stack_size()`
+    ///
+    /// ```
+    /// use mco::coroutine::{self, Builder};
+    ///
+    /// let h = Builder::selector().spawn(|| coroutine::current().stack_size());
+    /// assert_eq!(h.join().unwrap(), mco::config().get_selector_stack_size());
+    /// ```
+    pub fn selector() -> Builder {
+        Builder::new().stack_size(config().get_selector_stack_size())
     }
 
     /// Names the thread-to-be. Currently the name is used for identification
     /// only in panic messages.
     pub fn name(mut self, name: String) -> Builder {
         self.name = Some(name);
+        self.name_fn = None;
+        self
+    }
+
+    /// Names the coroutine-to-be with the string returned by `f`, it's called
+    /// when the coroutine is spawned.
+    pub fn name_fn<F>(mut self, f: F) -> Builder
+    where
+        F: FnOnce() -> String + Send + 'static,
+    {
+        self.name = None;
+        self.name_fn = Some(Box::new(f));
         self
     }
 
     /// Sets the size of the stack for the new coroutine.
     pub fn stack_size(mut self, size: usize) -> Builder {
         self.stack_size = Some(size);
+        self.growable = None;
+        self
+    }
+
+    /// Sets the stack kind for the new coroutine.
+    pub fn stack(mut self, kind: StackKind) -> Builder {
+        match kind {
+            StackKind::Fixed(size) => {
+                self.stack_size = Some(size);
+                self.growable = None;
+            }
+            StackKind::Growable { initial, max } => {
+                self.stack_size = Some(max);
+                self.growable = Some(initial);
+            }
+        }
+        self
+    }
+
+    /// Pins the new coroutine to the worker, it's never run or stolen by the
+    /// other workers. the coroutines pinned to the same worker run on the same
+    /// thread, so they can share the values that are not `Send`, see
+    /// [`spawn_pinned`]
+    ///
+    /// panic when spawning if the worker id is not less than the worker number
+    ///
+    /// [`spawn_pinned`]: ./fn.spawn_pinned.html
+    pub fn pin(mut self, worker: usize) -> Builder {
+        self.pin = Some(worker);
+        self
+    }
+
+    /// Sets the priority of the new coroutine, the ready coroutines with a
+    /// higher priority run first. the default 0 is scheduled by the local
+    /// queues of the workers as usual, the others wait in one queue that is
+    /// shared by the workers. a coroutine that holds a [`Mutex`] is raised to
+    /// the priority of its waiters till it releases the lock
+    ///
+    /// a worker runs at most 16 of them in a row before it looks at the
+    /// other queues, so the default priority is not starved. the pinned
+    /// coroutines don't have a priority
+    ///
+    /// ```
+    /// use mco::coroutine::{self, Builder};
+    ///
+    /// let h = Builder::new().priority(2).spawn(|| coroutine::current().priority());
+    /// assert_eq!(h.join().unwrap(), 2);
+    /// ```
+    ///
+    /// [`Mutex`]: ../std/sync/struct.Mutex.html
+    pub fn priority(mut self, priority: u8) -> Builder {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets a value that lives in the coroutine as long as the body, it's got
+    /// by [`coroutine::with_local_value`], for example an arena of the coroutine.
+    ///
+    /// `init` is called in the coroutine before the body runs, in the order of
+    /// the `with_local` calls. the values are dropped in the reverse order in
+    /// the coroutine right after the body returns or panics, before the join
+    /// handle sees the result. the [`coroutine_local!`] values are dropped
+    /// after them, when the storage of the coroutine is released, that may be
+    /// after the join handle returns
+    ///
+    /// ```
+    /// use mco::coroutine::{self, Builder};
+    ///
+    /// let h = Builder::new()
+    ///     .with_local(|| String::from("arena"))
+    ///     .spawn(|| coroutine::with_local_value(|s: &String| s.len()).unwrap());
+    /// assert_eq!(h.join().unwrap(), 5);
+    /// ```
+    ///
+    /// [`coroutine::with_local_value`]: ./fn.with_local_value.html
+    /// [`coroutine_local!`]: ../macro.coroutine_local.html
+    pub fn with_local<T, F>(mut self, init: F) -> Builder
+    where
+        T: 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        self.locals.push(Box::new(move || Box::new(init())));
+        self
+    }
+
+    /// Tags the coroutine-to-be with a number, for example the id of the
+    /// tenant that it's spawned for. the tag is got by [`Coroutine::tag`] in
+    /// the [`Hooks`]
+    ///
+    /// ```
+    /// use mco::coroutine::{self, Builder, Tag};
+    ///
+    /// let h = Builder::new()
+    ///     .tag(7)
+    ///     .spawn(|| coroutine::current().tag().cloned());
+    /// assert_eq!(h.join().unwrap(), Some(Tag::Num(7)));
+    /// ```
+    ///
+    /// [`Coroutine::tag`]: ./struct.Coroutine.html#method.tag
+    /// [`Hooks`]: ./struct.Hooks.html
+    pub fn tag(mut self, tag: u64) -> Builder {
+        self.tag = Some(Tag::Num(tag));
+        self
+    }
+
+    /// Tags the coroutine-to-be with a string, see [`tag`](#method.tag)
+    pub fn tag_str(mut self, tag: &str) -> Builder {
+        self.tag = Some(Tag::Str(tag.into()));
+        self
+    }
+
+    /// Charges the stack size of the new coroutine to the budget, it's given
+    /// back when the coroutine is done. the spawn fails with
+    /// [`SpawnError::OverBudget`] when the budget is used up
+    ///
+    /// ```
+    /// use mco::coroutine::{Builder, SpawnError};
+    /// use mco::std::sync::MemoryBudget;
+    ///
+    /// let budget = MemoryBudget::new(0x8000);
+    /// let h = Builder::new().stack_size(0x8000).budget(&budget).spawn(|| 1);
+    /// let ret = Builder::new().stack_size(0x8000).budget(&budget).try_spawn(|| 2);
+    /// assert!(matches!(ret, Err(SpawnError::OverBudget)));
+    /// assert_eq!(h.join().unwrap(), 1);
+    /// ```
+    ///
+    /// [`SpawnError::OverBudget`]: ./enum.SpawnError.html#variant.OverBudget
+    pub fn budget(mut self, budget: &MemoryBudget) -> Builder {
+        self.budget = Some(budget.clone());
+        self
+    }
+
+    /// Runs the coroutine-to-be in the span, see [`go_traced!`] for the
+    /// child spans of a request
+    ///
+    /// [`Span::current`] returns the span in the coroutine, so the span and
+    /// its `Context` reach the code that the coroutine calls. the coroutine
+    /// is named after the span with the spawn sequence number, unless it's
+    /// named by the builder. the time from the spawn to the first run of the
+    /// body is recorded in the span, see [`Span::spawn_latency`]
+    ///
+    /// ```
+    /// use mco::coroutine::{self, Builder, Span};
+    ///
+    /// let span = Span::root("request");
+    /// let h = Builder::new().with_span(span.clone()).spawn(|| {
+    ///     let name = coroutine::current().name().unwrap().to_owned();
+    ///     (name, Span::current().unwrap().id())
+    /// });
+    /// let (name, id) = h.join().unwrap();
+    /// assert!(name.starts_with("request-"));
+    /// assert_eq!(id, span.id());
+    /// assert!(span.spawn_latency().is_some());
+    /// ```
+    ///
+    /// [`go_traced!`]: ../macro.go_traced.html
+    /// [`Span::current`]: ./struct.Span.html#method.current
+    /// [`Span::spawn_latency`]: ./struct.Span.html#method.spawn_latency
+    pub fn with_span(mut self, span: Span) -> Builder {
+        self.span = Some(span);
         self
     }
 
     /// Spawns a new coroutine, and returns a join handle for it.
     /// The join handle can be used to block on
     /// termination of the child coroutine, including recovering its panics.
-    fn spawn_impl<F, T>(self, f: F) -> (CoroutineImpl, JoinHandle<T>)
+    ///
+    /// it panics if the spawn fails, except in a destructor that runs while
+    /// the thread is unwinding where a panic would abort the process. there
+    /// the error is logged and the handle joins with the `SpawnError`
+    #[track_caller]
+    pub(crate) fn spawn_impl<F, T>(self, f: F) -> (Option<CoroutineImpl>, JoinHandle<T>)
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        match self.try_spawn_impl(f) {
+            Ok((co, handle)) => (Some(co), handle),
+            Err(e) if thread::panicking() => {
+                error!("can't spawn a coroutine while unwinding: {}", e);
+                (None, failed_join_handle(e))
+            }
+            Err(e) => panic!(
+                "can't spawn a coroutine: {}, use `Builder::try_spawn` to handle the error",
+                e
+            ),
+        }
+    }
+
+    #[track_caller]
+    fn try_spawn_impl<F, T>(self, f: F) -> Result<(CoroutineImpl, JoinHandle<T>), SpawnError>
     where
         F: FnOnce() -> T + Send + 'static,
         T: Send + 'static,
@@ -257,22 +868,80 @@ impl Builder {
         static DONE: Done = Done {};
 
         let sched = get_scheduler();
-        let Builder { name, stack_size } = self;
+        if sched.is_shutdown() {
+            return Err(SpawnError::ShuttingDown);
+        }
+        let Builder {
+            name,
+            name_fn,
+            mut stack_size,
+            growable,
+            pin,
+            priority,
+            mut locals,
+            tag,
+            budget,
+            span,
+        } = self;
+        if priority != 0 {
+            PRIORITY_ENABLED.store(true, Ordering::Relaxed);
+        }
+        if let Some(worker) = pin {
+            assert!(
+                worker < sched.worker_num(),
+                "can't pin coroutine to worker {}, only {} workers",
+                worker,
+                sched.worker_num()
+            );
+            PINNED_ENABLED.store(true, Ordering::Relaxed);
+        }
+        let mut name = name.or_else(|| name_fn.map(|f| f()));
+        if let Some(span) = span {
+            if name.is_none() {
+                name = Some(seq_name(span.name()));
+            }
+            let spawned = Instant::now();
+            // the first one, so that the other inits see the span
+            locals.insert(
+                0,
+                Box::new(move || {
+                    span.record_spawn_latency(spawned.elapsed());
+                    Box::new(span)
+                }),
+            );
+        }
+        if HAS_BUILDER_DEFAULTS.load(Ordering::Acquire) && (name.is_none() || stack_size.is_none())
+        {
+            let defaults = builder_defaults();
+            stack_size = stack_size.or(defaults.stack_size);
+            if name.is_none() {
+                name = defaults.name_prefix.map(|p| seq_name(&p));
+            }
+        }
         let stack_size = stack_size.unwrap_or_else(|| config().get_stack_size());
-        let _co = if stack_size == config().get_stack_size() {
-            let co = sched.pool.get();
+        let charge = match &budget {
+            Some(b) => Some(b.try_charge(stack_size).ok_or(SpawnError::OverBudget)?),
+            None => None,
+        };
+        let (_co, mut kept) = if growable.is_some() {
+            (None, None)
+        } else if stack_size == config().get_stack_size() {
+            let (co, local) = sched.pool.get()?;
             co.prefetch();
-            Some(co)
+            (Some(co), local)
         } else {
-            None
+            (pool::get_stack(stack_size), None)
         };
 
         // create a join resource, shared by waited coroutine and *this* coroutine
-        let panic = Arc::new(AtomicCell::new(None));
-        let join = Arc::new(Join::new(panic.clone()));
-        let packet = Arc::new(AtomicCell::new(None));
+        // the closure is kept on the coroutine stack and a small return value
+        // in the join, the join, the handle and the local storage of a pooled
+        // coroutine are reused, so that they don't allocate
+        let join = match kept.as_mut() {
+            Some(local) => Join::renew(local.parts_mut().1),
+            None => Arc::new(Join::new()),
+        };
         let their_join = join.clone();
-        let their_packet = packet.clone();
 
         let subscriber = EventSubscriber {
             resource: &DONE as &dyn EventSource as *const _ as *mut dyn EventSource,
@@ -282,10 +951,18 @@ impl Builder {
             // trigger the JoinHandler
             // we must declare the variable before calling f so that stack is prepared
             // to unwind these local data. for the panic err we would set it in the
-            // coroutine local data so that can return from the packet variable
+            // coroutine local data so that can return from the join
 
-            // set the return packet
-            their_packet.swap(Some(f()));
+            let ret = {
+                // the stack is given back to the budget after the exit hooks
+                let _charge = charge;
+                // the exit hooks run after the locals are dropped
+                let _hooks = HookGuard::enter();
+                let _locals = LocalValues::init(locals);
+                f()
+            };
+            // set the return value
+            their_join.set_ret(ret);
 
             their_join.trigger();
             subscriber
@@ -295,17 +972,51 @@ impl Builder {
             // re-init the closure
             c.init_code(closure);
             c
+        } else if let Some(initial) = growable {
+            let co = Gn::try_new_opt_lazy(stack_size, initial, closure)?;
+            let reserved = stack_size * std::mem::size_of::<usize>();
+            stats::GROWABLE_STACKS.fetch_add(1, Ordering::Relaxed);
+            stats::GROWABLE_STACK_RESERVED.fetch_add(reserved, Ordering::Relaxed);
+            co
         } else {
-            Gn::new_opt(stack_size, closure)
+            Gn::try_new_opt(pool::stack_class(stack_size), closure)?
         };
 
-        let handle = Coroutine::new(name, stack_size);
+        let handle = match kept.as_mut() {
+            Some(local) => Coroutine::renew(local.parts_mut().0, name, stack_size, tag),
+            None => Coroutine::new(name, stack_size, growable.is_some(), tag),
+        };
+        handle.inner.priority.store(priority, Ordering::Relaxed);
+        if let Some(worker) = pin {
+            handle.inner.pinned.store(worker, Ordering::Relaxed);
+            handle
+                .inner
+                .pin_epoch
+                .store(sched.pin_epoch(worker), Ordering::Relaxed);
+            sched.pinned_spawned(worker);
+        }
+        handle
+            .inner
+            .sched
+            .store(sched as *const _ as *mut _, Ordering::Relaxed);
+        if let Some(live) = sched.live.as_ref() {
+            live.fetch_add(1, Ordering::Relaxed);
+        }
+        sched.acquire();
         // create the local storage
-        let local = CoroutineLocal::new(handle.clone(), join.clone());
+        let local = match kept {
+            Some(mut local) => {
+                local.reset(handle.clone(), join.clone());
+                local
+            }
+            None => CoroutineLocal::new(handle.clone(), join.clone()),
+        };
         // attache the local storage to the coroutine
         co.set_local_data(Box::into_raw(local) as *mut u8);
+        #[cfg(feature = "flight-recorder")]
+        crate::flight::record(crate::flight::Event::Spawn, handle.id().as_u64(), 0);
 
-        (co, make_join_handle(handle, join, packet, panic))
+        Ok((co, make_join_handle(handle, join)))
     }
 
     /// Spawns a new coroutine by taking ownership of the `Builder`, and returns an
@@ -314,11 +1025,10 @@ impl Builder {
     /// The spawned coroutine may outlive the caller. The join handle can be used
     /// to block on termination of the child thread, including recovering its panics.
     ///
-    /// # Errors
+    /// # Panics
     ///
-    /// Unlike the [`spawn`] free function, this method yields an
-    /// `io::Result` to capture any failure to create the thread at
-    /// the OS level.
+    /// Panics at the caller if the stack can't be allocated or the runtime
+    /// is shut down, use [`try_spawn`] to get the [`SpawnError`] instead.
     ///
     /// # Safety
     ///
@@ -347,6 +1057,9 @@ impl Builder {
     /// [`TLS`]: ./index.html#TLS
     /// [`go!`]: ../macro.go.html
     /// [`spawn`]: ./fn.spawn.html
+    /// [`try_spawn`]: #method.try_spawn
+    /// [`SpawnError`]: ./enum.SpawnError.html
+    #[track_caller]
     pub fn spawn<F, T>(self, f: F) -> JoinHandle<T>
     where
         F: FnOnce() -> T + Send + 'static,
@@ -355,6 +1068,27 @@ impl Builder {
         return self.spawn_local(f);
     }
 
+    /// Like `spawn`, but return the error instead of panic when the
+    /// coroutine can't be created
+    ///
+    /// ```
+    /// use mco::coroutine::{Builder, SpawnError};
+    ///
+    /// // far beyond the max stack size
+    /// let r = Builder::new().stack_size(usize::MAX >> 4).try_spawn(|| {});
+    /// assert!(matches!(r, Err(SpawnError::StackAllocation(_))));
+    /// ```
+    #[track_caller]
+    pub fn try_spawn<F, T>(self, f: F) -> Result<JoinHandle<T>, SpawnError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (co, handle) = self.try_spawn_impl(f)?;
+        run_coroutine(co);
+        Ok(handle)
+    }
+
     /// first run the coroutine in current thread, you should allways use
     /// `spawn` instead of this API.
     ///
@@ -363,6 +1097,7 @@ impl Builder {
     /// Cancel would drop all the resource of the coroutine.
     /// Normally this is safe but for some cases you should
     /// take care of the side effect
+    #[track_caller]
     pub fn spawn_local<F, T>(self, f: F) -> JoinHandle<T>
     where
         F: FnOnce() -> T + Send + 'static,
@@ -371,9 +1106,144 @@ impl Builder {
         // we will still get optimizations in spawn_impl
         let (co, handle) = self.spawn_impl(f);
         // first run the coroutine in current thread
-        run_coroutine(co);
+        if let Some(co) = co {
+            run_coroutine(co);
+        }
         handle
     }
+
+    /// Spawns a new coroutine that is not scheduled until [`StartHandle::start`]
+    /// is called, the closure and the stack are bound up front.
+    ///
+    /// dropping the `StartHandle` without starting cancels the coroutine, the
+    /// values captured by the closure are dropped without running the body and
+    /// the join handle returns the cancel panic
+    ///
+    /// [`StartHandle::start`]: ./struct.StartHandle.html#method.start
+    #[track_caller]
+    pub fn spawn_suspended<F, T>(self, f: F) -> (JoinHandle<T>, StartHandle)
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (co, handle) = self.spawn_impl(move || {
+            // canceled before it's started
+            current_cancel_data().check_cancel();
+            f()
+        });
+        let start = StartHandle {
+            co: co.map_or_else(AtomicOption::none, AtomicOption::some),
+            handle: handle.coroutine().clone(),
+        };
+        (handle, start)
+    }
+}
+
+/// the error of [`Builder::try_spawn`]
+///
+/// [`Builder::try_spawn`]: ./struct.Builder.html#method.try_spawn
+#[derive(Debug)]
+pub enum SpawnError {
+    /// the stack of the coroutine can't be allocated
+    StackAllocation(io::Error),
+    /// the runtime of the coroutine is shut down
+    ShuttingDown,
+    /// the stack doesn't fit in the budget of [`Builder::budget`]
+    ///
+    /// [`Builder::budget`]: ./struct.Builder.html#method.budget
+    OverBudget,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpawnError::StackAllocation(e) => write!(f, "can't allocate the stack, {}", e),
+            SpawnError::ShuttingDown => write!(f, "the runtime is shut down"),
+            SpawnError::OverBudget => write!(f, "the stack is over the memory budget"),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpawnError::StackAllocation(e) => Some(e),
+            SpawnError::ShuttingDown | SpawnError::OverBudget => None,
+        }
+    }
+}
+
+// the handle of a coroutine that is never spawned, it's done and joins
+// with the error
+fn failed_join_handle<T>(e: SpawnError) -> JoinHandle<T> {
+    let join = Arc::new(Join::new());
+    join.set_panic_data(Box::new(e));
+    join.trigger();
+    make_join_handle(Coroutine::new(None, 0, false, None), join)
+}
+
+impl From<StackError> for SpawnError {
+    fn from(e: StackError) -> Self {
+        match e {
+            StackError::IoError(e) => SpawnError::StackAllocation(e),
+            e => SpawnError::StackAllocation(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                e.to_string(),
+            )),
+        }
+    }
+}
+
+/// the handle to start a coroutine spawned by [`Builder::spawn_suspended`]
+///
+/// [`Builder::spawn_suspended`]: ./struct.Builder.html#method.spawn_suspended
+pub struct StartHandle {
+    co: AtomicOption<CoroutineImpl>,
+    handle: Coroutine,
+}
+
+impl StartHandle {
+    /// put the coroutine to the scheduler, the later calls do nothing
+    pub fn start(&self) {
+        if let Some(co) = self.co.take() {
+            get_scheduler().schedule(co);
+        }
+    }
+
+    /// return true if the coroutine is started
+    pub fn is_started(&self) -> bool {
+        self.co.is_none()
+    }
+
+    /// the handle of the coroutine
+    pub fn coroutine(&self) -> &Coroutine {
+        &self.handle
+    }
+}
+
+impl Drop for StartHandle {
+    fn drop(&mut self) {
+        if let Some(co) = self.co.take() {
+            // unwind the closure right away, the body is never run
+            self.handle.cancel();
+            run_coroutine(co);
+        }
+    }
+}
+
+impl fmt::Debug for StartHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StartHandle")
+            .field("coroutine", &self.handle)
+            .field("started", &self.is_started())
+            .finish()
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -426,6 +1296,7 @@ impl Builder {
 /// [`join`]: struct.JoinHandle.html#method.join
 /// [`Builder::spawn`]: struct.Builder.html#method.spawn
 /// [`Builder`]: struct.Builder.html
+#[track_caller]
 pub fn spawn<F, T>(f: F) -> JoinHandle<T>
 where
     F: FnOnce() -> T + Send + 'static,
@@ -434,6 +1305,36 @@ where
     Builder::new().spawn(f)
 }
 
+// the closure of `spawn_pinned` never leaves the worker
+struct AssertSend<F>(F);
+
+unsafe impl<F> Send for AssertSend<F> {}
+
+impl<F: FnOnce() -> T, T> AssertSend<F> {
+    fn call(self) -> T {
+        (self.0)()
+    }
+}
+
+/// Spawns a new coroutine pinned to the worker of the current coroutine
+///
+/// the closure doesn't need to be `Send`, it can capture the `Rc`s and local
+/// channels of the caller since both of the coroutines always run on the same
+/// worker thread. panic if the current coroutine is not pinned, see `Builder::pin`
+#[track_caller]
+pub fn spawn_pinned<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + 'static,
+    T: Send + 'static,
+{
+    let worker = try_current()
+        .ok()
+        .and_then(|co| co.pinned_worker())
+        .expect("spawn_pinned must be called in a pinned coroutine");
+    let f = AssertSend(f);
+    Builder::new().pin(worker).spawn(move || f.call())
+}
+
 /// Gets a handle to the coroutine that invokes it.
 /// it will panic if you call it in a thead context
 #[inline]
@@ -465,6 +1366,62 @@ pub fn is_coroutine() -> bool {
     get_co_local_data().is_some()
 }
 
+/// where the current thread runs, see [`context`]
+///
+/// [`context`]: ./fn.context.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecContext {
+    /// in a coroutine, on the worker of the index. a coroutine that is
+    /// unparked by a foreign thread may run on it until it switches out, its
+    /// worker is `None` then
+    Coroutine { worker: Option<usize> },
+    /// on a worker outside of a coroutine, e.g. in a maintenance callback or
+    /// a destructor of a finished coroutine. a blocking call here holds up
+    /// all the coroutines of the worker, hand the work to a coroutine instead
+    Scheduler { worker: usize },
+    /// on a thread that is not a worker of any runtime, it may block
+    Foreign,
+}
+
+/// the execution context of the current thread, for the callbacks that come
+/// on any thread, e.g. from a C library: a coroutine can call the blocking
+/// apis of the crate directly, the scheduler context must not block and
+/// should send to a channel, a foreign thread may block or unpark
+///
+/// it reads two thread locals that the runtime sets on its threads, so it's
+/// cheap. it's not async-signal-safe, the first access of a thread local may
+/// allocate on some platforms
+/// ```
+/// use mco::coroutine::{self, ExecContext};
+///
+/// assert_eq!(coroutine::context(), ExecContext::Foreign);
+/// let ctx = mco::co!(coroutine::context).join().unwrap();
+/// assert!(matches!(ctx, ExecContext::Coroutine { worker: Some(_) }));
+/// ```
+#[inline]
+pub fn context() -> ExecContext {
+    let id = worker_id();
+    let worker = if id == !1 { None } else { Some(id) };
+    match worker {
+        _ if is_coroutine() => ExecContext::Coroutine { worker },
+        Some(worker) => ExecContext::Scheduler { worker },
+        None => ExecContext::Foreign,
+    }
+}
+
+// the blocking call of a thread context would hold up the worker outside of
+// its coroutines, and hang if it waits for one of them. panic instead
+#[inline]
+pub(crate) fn assert_not_scheduler(api: &str) {
+    if let ExecContext::Scheduler { worker } = context() {
+        panic!(
+            "{} is called in the scheduler context of worker {}, it would block the worker. \
+             check coroutine::context() and hand the work to a coroutine",
+            api, worker
+        );
+    }
+}
+
 /// get current coroutine cancel registration
 /// panic in a thread context
 #[inline]
@@ -488,6 +1445,13 @@ pub(crate) fn co_get_handle(co: &CoroutineImpl) -> Coroutine {
     local.get_co().clone()
 }
 
+#[cfg(feature = "flight-recorder")]
+#[inline]
+pub(crate) fn co_id(co: &CoroutineImpl) -> CoroutineId {
+    let local = unsafe { &*get_co_local(co) };
+    local.get_co().id()
+}
+
 /// timeout block the current coroutine until it's get unparked
 #[inline]
 fn park_timeout_impl(dur: Option<Duration>) {
@@ -513,7 +1477,69 @@ pub fn park_timeout(dur: Duration) {
 /// run the coroutine
 #[inline]
 pub(crate) fn run_coroutine(mut co: CoroutineImpl) {
-    match co.resume() {
+    if runtimes_enabled() {
+        let local = unsafe { &*get_co_local(&co) };
+        let sched = local.get_co().inner.sched.load(Ordering::Relaxed);
+        if !is_current_sched(sched) {
+            // hand it over to the runtime that it belongs to
+            return unsafe { &*sched }.schedule_remote(co);
+        }
+    }
+    if resized() {
+        let id = worker_id();
+        let sched = get_scheduler();
+        if id != !1 && !sched.is_active(id) {
+            // the worker is shrunk, hand it over to the active ones
+            let local = unsafe { &*get_co_local(&co) };
+            let pinned = local.get_co().inner.pinned.load(Ordering::Relaxed);
+            if pinned == !1 {
+                return sched.schedule_global(co);
+            }
+            return sched.schedule_pinned(pinned, co);
+        }
+    }
+    if PINNED_ENABLED.load(Ordering::Relaxed) {
+        let local = unsafe { &*get_co_local(&co) };
+        let pinned = local.get_co().inner.pinned.load(Ordering::Relaxed);
+        if pinned != !1 {
+            let inner = &local.get_co().inner;
+            if respawned()
+                && inner.pin_epoch.load(Ordering::Relaxed) != get_scheduler().pin_epoch(pinned)
+            {
+                // the worker that it's pinned to panicked and is respawned
+                local.get_co().cancel();
+            }
+            if get_scheduler().pin_target(pinned) != worker_id() {
+                // hand it over to the worker that it's pinned to
+                return get_scheduler().schedule_pinned(pinned, co);
+            }
+        }
+    }
+    {
+        let local = unsafe { &*get_co_local(&co) };
+        let inner = &local.get_co().inner;
+        // an unpark from outside of the workers looks at it
+        inner.last_worker.store(worker_id(), Ordering::Relaxed);
+        if AFFINITY_ENABLED.load(Ordering::Relaxed) {
+            if let Some(cpu) = current_cpu() {
+                inner.last_cpu.store(cpu, Ordering::Relaxed);
+            }
+        }
+    }
+    #[cfg(all(feature = "co-thread-names", target_os = "linux"))]
+    if thread_names::comm_enabled() && worker_id() != !1 {
+        thread_names::set_comm(unsafe { &*get_co_local(&co) }.get_co().name());
+    }
+    let slot = if watchdog::enabled() {
+        watchdog::enter(unsafe { &*get_co_local(&co) }.get_co())
+    } else {
+        None
+    };
+    let ret = co.resume();
+    if let Some(slot) = slot {
+        watchdog::leave(slot);
+    }
+    match ret {
         Some(ev) => ev.subscribe(co),
         None => {
             // panic happened here
@@ -525,6 +1551,7 @@ pub(crate) fn run_coroutine(mut co: CoroutineImpl) {
             }
             // trigger the join here
             join.trigger();
+            drop(join);
             Done::drop_coroutine(co);
         }
     }
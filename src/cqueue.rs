@@ -1,16 +1,38 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+#[cfg(target_os = "linux")]
+use std::io;
+use std::marker::PhantomData;
+use std::ops::ControlFlow;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
 use std::panic;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::cancel::Cancel;
+#[cfg(target_os = "linux")]
+use once_cell::sync::OnceCell;
+
+use crate::cancel::{trigger_cancel_panic, Cancel};
 use crate::coroutine_impl::{
-    current_cancel_data, run_coroutine, Coroutine, CoroutineImpl, EventSource,
+    current_cancel_data, run_coroutine, try_current, Builder, Coroutine, CoroutineImpl, EventSource,
 };
 use crate::join::JoinHandle;
-use crate::scoped::spawn_unsafe;
+use crate::local::{current_claim, swap_claim};
+use crate::park::ParkError;
+use crate::scoped::spawn_unsafe_with;
+use crate::stats;
+use crate::std::context::{Context, ContextError};
+use crate::std::sync::channel::RecvCtxError;
 use crate::std::sync::Mutex;
 use crate::std::sync::{AtomicOption, Blocker};
+use crate::std::time::Time;
+use crate::timeout_list::now_instant;
 use crate::yield_now::yield_with;
 
 use crate::std::queue::seg_queue::SegQueue as Queue;
@@ -29,6 +51,91 @@ pub enum PollError {
     Finished,
 }
 
+/// the error of `Cqueue::poll_ctx`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum PollCtxError {
+    /// the select coroutines are all finished, see `PollError::Finished`
+    Finished,
+    /// the context is canceled or its deadline is passed before an event
+    Done(ContextError),
+}
+
+impl fmt::Display for PollCtxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PollCtxError::Finished => f.write_str("polling a finished cqueue"),
+            PollCtxError::Done(e) => write!(f, "polling a cqueue: {}", e),
+        }
+    }
+}
+
+impl Error for PollCtxError {}
+
+/// what happens to a new event when the events that are not polled yet
+/// reach the capacity of the cqueue, see `CqueueConfig`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Overflow {
+    /// `send` waits for room, `send_timeout` waits at most its timeout and
+    /// `try_send` fails, the default
+    BlockSelector,
+    /// the oldest queued event that has no bottom half is dropped for the new
+    /// one, the new one is dropped if all of them have
+    DropOldest,
+    /// the new event is dropped, `send` returns at once without waiting for
+    /// the poller
+    DropNewest,
+}
+
+impl Default for Overflow {
+    fn default() -> Self {
+        Overflow::BlockSelector
+    }
+}
+
+impl Overflow {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Overflow::DropOldest,
+            2 => Overflow::DropNewest,
+            _ => Overflow::BlockSelector,
+        }
+    }
+}
+
+/// the options of a cqueue, see `scope_with` and `Cqueue::with_config`
+///
+/// the default is an unbounded event queue. a selector that only calls
+/// `send`, like the ones of `cqueue_add!`, has at most one event in it, the
+/// queue grows only with the events of `try_send` and `send_timeout`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct CqueueConfig {
+    /// the max number of the events that are queued but not polled yet, at
+    /// least 1
+    pub capacity: usize,
+    /// what happens to a new event once the capacity is reached
+    pub overflow: Overflow,
+}
+
+impl Default for CqueueConfig {
+    fn default() -> Self {
+        CqueueConfig {
+            capacity: usize::MAX,
+            overflow: Overflow::BlockSelector,
+        }
+    }
+}
+
+// what becomes of a new event
+#[derive(PartialEq, Eq)]
+enum Admit {
+    // it has its room in the queue
+    Queued,
+    // it's dropped by the overflow policy
+    Dropped,
+    // no room and the policy is to wait
+    Full,
+}
+
 /// This enumeration is the list of the possible reasons that an event
 /// is generated
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -55,6 +162,10 @@ pub struct Event {
     kind: EventKind,
     // the async coroutine that work on an select
     co: Option<CoroutineImpl>,
+    // the payload passed by `send_with`
+    payload: Option<Box<dyn Any + Send>>,
+    // the in-flight limit of the select coroutine of a normal event
+    inflight: Option<Arc<Inflight>>,
 }
 
 impl Event {
@@ -65,8 +176,117 @@ impl Event {
             run_coroutine(co);
         }
     }
+
+    /// take the payload that the select coroutine passed by `send_with`
+    /// return None if there is no payload or the payload is not a `T`
+    pub fn take<T: 'static>(&mut self) -> Option<T> {
+        match self.payload.take()?.downcast::<T>() {
+            Ok(v) => Some(*v),
+            Err(p) => {
+                // keep it for a later take with the right type
+                self.payload = Some(p);
+                None
+            }
+        }
+    }
+}
+
+/// the result of a `select_loop!` arm body
+/// tell the poller whether it should stop the loop
+pub trait LoopFlow {
+    /// return true if the select loop should be stopped
+    fn is_break(&self) -> bool;
+}
+
+impl LoopFlow for () {
+    #[inline]
+    fn is_break(&self) -> bool {
+        false
+    }
+}
+
+impl LoopFlow for ControlFlow<()> {
+    #[inline]
+    fn is_break(&self) -> bool {
+        matches!(self, ControlFlow::Break(_))
+    }
+}
+
+/// the result of a `cqueue_add!` selector expression
+/// tell the persistent selector whether its source is done
+///
+/// the selector exits its loop after a terminal result without sending the
+/// event, so a disconnected channel doesn't spin the selector and the cqueue
+/// finishes once all its selectors are done, see `Cqueue::take_finished`.
+/// the results of the other types are never terminal
+pub trait Terminal {
+    /// return true if the source would never produce a value any more
+    fn is_terminal(&self) -> bool;
+}
+
+impl<T> Terminal for Result<T, RecvError> {
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        self.is_err()
+    }
+}
+
+impl<T> Terminal for Result<T, TryRecvError> {
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        matches!(self, Err(TryRecvError::Disconnected))
+    }
 }
 
+impl<T> Terminal for Result<T, RecvTimeoutError> {
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        matches!(self, Err(RecvTimeoutError::Disconnected))
+    }
+}
+
+impl<T> Terminal for Result<T, RecvCtxError> {
+    // the context is never undone
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        self.is_err()
+    }
+}
+
+impl<T> Terminal for Result<(), SendError<T>> {
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        self.is_err()
+    }
+}
+
+// pick `Terminal` for the results that implement it and false for the
+// others by the method resolution, used by `cqueue_add!`
+#[doc(hidden)]
+pub struct TerminalProbe<'a, T>(pub &'a T);
+
+#[doc(hidden)]
+pub trait TerminalKind {
+    fn is_terminal(&self) -> bool;
+}
+
+impl<'a, T: Terminal> TerminalKind for TerminalProbe<'a, T> {
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        self.0.is_terminal()
+    }
+}
+
+#[doc(hidden)]
+pub trait NotTerminal {
+    #[inline]
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+impl<'a, T> NotTerminal for &TerminalProbe<'a, T> {}
+
 /// a handle type for the select coroutine
 /// you can only use the `remove` method to manually delete the coroutine
 pub struct Selector {
@@ -83,6 +303,18 @@ impl Selector {
 
 /// each select coroutine would use this struct to communicate with
 /// the cqueue. the struct is created in `add` for each select coroutine
+///
+/// `send` waits until the poller runs the bottom half of the event, so each
+/// select coroutine has at most one such event in the queue. `try_send` and
+/// `send_timeout` only queue the event and the select coroutine keeps running,
+/// they fail when the events that are not polled yet reach the capacity of the
+/// cqueue, see `Cqueue::set_capacity`. `send` also waits for room in this case.
+/// with a drop policy of `Cqueue::set_overflow` none of them waits or fails,
+/// the overflowing events are dropped instead
+///
+/// a select coroutine that is added by `add_with` also has a limit of its own
+/// events that are not polled yet, `send` and `send_timeout` wait for the
+/// poller to take the previous ones and `try_send` fails
 pub struct EventSender<'a> {
     // index of the select coroutine
     id: usize,
@@ -90,8 +322,12 @@ pub struct EventSender<'a> {
     token: usize,
     // the select coroutine can use it to pass extra data to the caller
     extra: AtomicUsize,
+    // the payload for the next event
+    payload: AtomicOption<Box<dyn Any + Send>>,
+    // the limit of the events that are not polled yet, set by `add_with`
+    inflight: Option<Arc<Inflight>>,
     // the mpsc event queue to collect the events
-    cqueue: &'a Cqueue,
+    cqueue: &'a Inner,
 }
 
 unsafe impl<'a> Send for EventSender<'a> {}
@@ -102,29 +338,143 @@ impl<'a> EventSender<'a> {
         self.token
     }
 
-    /// send out the event
+    /// send out the event and wait until the poller runs the bottom half
     pub fn send(&self, extra: usize) {
         let cancel = current_cancel_data();
         cancel.check_cancel();
+        if let Some(inflight) = &self.inflight {
+            inflight.take(None);
+        }
+        if self.cqueue.admit(None) == Admit::Dropped {
+            return self.discard();
+        }
         self.extra.store(extra, Ordering::Relaxed);
         yield_with(self);
     }
-}
 
-impl<'a> EventSource for EventSender<'a> {
-    fn subscribe(&mut self, co: CoroutineImpl) {
-        self.cqueue.ev_queue.push(Event {
+    /// queue the event without waiting for the poller, the event has no bottom half
+    /// return the extra back if the event queue is full
+    pub fn try_send(&self, extra: usize) -> Result<(), SendError<usize>> {
+        if let Some(inflight) = &self.inflight {
+            if !inflight.try_take() {
+                return Err(SendError(extra));
+            }
+        }
+        match self.cqueue.try_admit() {
+            Admit::Queued => {}
+            Admit::Dropped => {
+                self.discard();
+                return Ok(());
+            }
+            Admit::Full => {
+                self.untake_inflight();
+                return Err(SendError(extra));
+            }
+        }
+        self.cqueue.push(self.event(EventKind::Normal, extra, None));
+        Ok(())
+    }
+
+    /// queue the event without waiting for the poller, wait for room at most `dur`
+    /// return the extra back if the event queue is still full
+    pub fn send_timeout(&self, extra: usize, dur: Duration) -> Result<(), SendError<usize>> {
+        let cancel = current_cancel_data();
+        cancel.check_cancel();
+        let deadline = now_instant() + dur;
+        if let Some(inflight) = &self.inflight {
+            if !inflight.take(Some(deadline)) {
+                return Err(SendError(extra));
+            }
+        }
+        match self.cqueue.admit(Some(deadline)) {
+            Admit::Queued => {}
+            Admit::Dropped => {
+                self.discard();
+                return Ok(());
+            }
+            Admit::Full => {
+                self.untake_inflight();
+                return Err(SendError(extra));
+            }
+        }
+        self.cqueue.push(self.event(EventKind::Normal, extra, None));
+        Ok(())
+    }
+
+    // give back the place of an event that is not queued
+    fn untake_inflight(&self) {
+        if let Some(inflight) = &self.inflight {
+            inflight.release();
+        }
+    }
+
+    // the event is dropped by the overflow policy, so is its payload
+    fn discard(&self) {
+        self.untake_inflight();
+        drop(self.payload.take());
+    }
+
+    /// the number of the events that are queued but not polled yet
+    pub fn pending(&self) -> usize {
+        self.cqueue.pending.load(Ordering::Acquire)
+    }
+
+    fn event(&self, kind: EventKind, extra: usize, co: Option<CoroutineImpl>) -> Event {
+        Event {
             id: self.id,
             token: self.token,
-            extra: self.extra.load(Ordering::Relaxed),
-            kind: EventKind::Normal,
-            co: Some(co),
-        });
-        if let Some(w) = self.cqueue.to_wake.take() {
-            let _ = w.unpark();
+            extra,
+            kind,
+            co,
+            payload: self.payload.take(),
+            inflight: match kind {
+                EventKind::Normal => self.inflight.clone(),
+                EventKind::Done => None,
+            },
         }
     }
 
+    /// run the expression of a `select!` arm, return `None` if another arm
+    /// of the select commits first
+    ///
+    /// the receives of the mpmc channels in `f` commit just before they take
+    /// a message, a receive that loses leaves the message in the channel
+    /// and waits until the select is done. any other expression commits when
+    /// it returns, its value is dropped if it loses
+    #[doc(hidden)]
+    pub fn select_arm<R, F: FnOnce() -> R>(&self, f: F) -> Option<R> {
+        let claim = &self.cqueue.claim;
+        let _scope = ClaimScope(swap_claim(Some((NonNull::from(claim), self.id))));
+        let ret = f();
+        if claim.take(self.id) {
+            Some(ret)
+        } else {
+            None
+        }
+    }
+
+    /// give up the commit of a `select!` arm whose pattern doesn't match, so
+    /// that the other arms can still win
+    #[doc(hidden)]
+    pub fn select_give_up(&self) {
+        self.cqueue.claim.release(self.id);
+    }
+
+    /// send out the event with a payload, the poller can get it by `Event::take`
+    /// the payload is dropped with the event if the poller never takes it
+    pub fn send_with<P: Send + 'static>(&self, extra: usize, payload: P) {
+        self.payload.store(Box::new(payload));
+        self.send(extra);
+    }
+}
+
+impl<'a> EventSource for EventSender<'a> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let extra = self.extra.load(Ordering::Relaxed);
+        self.cqueue
+            .push(self.event(EventKind::Normal, extra, Some(co)));
+    }
+
     fn yield_back(&self, _cancel: &'static Cancel) {
         // ignore the cancel to let the bottom half get processed
     }
@@ -133,22 +483,178 @@ impl<'a> EventSource for EventSender<'a> {
 impl<'a> Drop for EventSender<'a> {
     // when the select coroutine finished will trigger this drop
     fn drop(&mut self) {
-        self.cqueue.ev_queue.push(Event {
-            id: self.id,
-            token: self.token,
-            extra: self.extra.load(Ordering::Relaxed),
-            kind: EventKind::Done,
-            co: None,
-        });
+        let extra = self.extra.load(Ordering::Relaxed);
+        self.cqueue
+            .ev_queue
+            .push(self.event(EventKind::Done, extra, None));
         self.cqueue.cnt.fetch_sub(1, Ordering::Relaxed);
+        self.cqueue.signal();
         if let Some(w) = self.cqueue.to_wake.take() {
             let _ = w.unpark();
         }
     }
 }
 
-/// cqueue interface for general select model
-pub struct Cqueue {
+// the limit of the events of a select coroutine that are not polled yet
+struct Inflight {
+    max: usize,
+    // the normal events that are queued but not polled yet
+    count: AtomicUsize,
+    // the select coroutine that waits for its events to be polled
+    waiter: AtomicOption<Arc<Blocker>>,
+}
+
+impl Inflight {
+    fn new(max: usize) -> Self {
+        Inflight {
+            max: max.max(1),
+            count: AtomicUsize::new(0),
+            waiter: AtomicOption::none(),
+        }
+    }
+
+    fn try_take(&self) -> bool {
+        let mut n = self.count.load(Ordering::Acquire);
+        loop {
+            if n >= self.max {
+                return false;
+            }
+            match self
+                .count
+                .compare_exchange_weak(n, n + 1, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(cur) => n = cur,
+            }
+        }
+    }
+
+    // wait for a place until the deadline, return false if timeout
+    fn take(&self, deadline: Option<Instant>) -> bool {
+        loop {
+            if self.try_take() {
+                return true;
+            }
+            let timeout = match deadline {
+                None => None,
+                Some(d) => match d.checked_duration_since(now_instant()) {
+                    Some(left) if left > Duration::ZERO => Some(left),
+                    _ => return false,
+                },
+            };
+            let cur = Blocker::current();
+            self.waiter.swap(cur.clone());
+            // re-check the place
+            if self.try_take() {
+                return true;
+            }
+            if cur.park(timeout).is_err() {
+                current_cancel_data().check_cancel();
+            }
+        }
+    }
+
+    // an event is polled
+    fn release(&self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+        if let Some(w) = self.waiter.take() {
+            let _ = w.unpark();
+        }
+    }
+}
+
+impl fmt::Debug for Inflight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Inflight")
+            .field("max", &self.max)
+            .field("count", &self.count.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+// restore the claim of the outer arm when the expression of an arm is done
+struct ClaimScope(Option<(NonNull<Claim>, usize)>);
+
+impl Drop for ClaimScope {
+    fn drop(&mut self) {
+        swap_claim(self.0.take());
+    }
+}
+
+const NO_WINNER: usize = usize::MAX;
+
+// the commit of the `select!` arms, only one of them wins
+pub(crate) struct Claim {
+    // the id of the selector that commits
+    winner: AtomicUsize,
+    // the selectors that wait for a winner that may still give up
+    waiters: Mutex<Vec<Arc<Blocker>>>,
+}
+
+impl Claim {
+    fn new() -> Self {
+        Claim {
+            winner: AtomicUsize::new(NO_WINNER),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    // true if the selector wins or has won already
+    fn take(&self, id: usize) -> bool {
+        match self
+            .winner
+            .compare_exchange(NO_WINNER, id, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => true,
+            Err(winner) => winner == id,
+        }
+    }
+
+    fn release(&self, id: usize) {
+        if self
+            .winner
+            .compare_exchange(id, NO_WINNER, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            let waiters = std::mem::take(&mut *self.waiters.lock().unwrap());
+            waiters.iter().for_each(|w| {
+                let _ = w.unpark();
+            });
+        }
+    }
+}
+
+/// commit the operation of the running `select!` arm, it's true outside of
+/// an arm or when the arm wins. an operation that gets false must undo what
+/// it reserved and call `select_wait`
+pub(crate) fn select_commit() -> bool {
+    match current_claim() {
+        Some((claim, id)) => unsafe { claim.as_ref() }.take(id),
+        None => true,
+    }
+}
+
+/// wait until the winner of the running `select!` arm gives up, then the
+/// operation can retry. the select cancels the waiting arm once it's done
+pub(crate) fn select_wait() {
+    let claim = match current_claim() {
+        Some((claim, _)) => unsafe { &*claim.as_ptr() },
+        None => return,
+    };
+    let cur = Blocker::current();
+    claim.waiters.lock().unwrap().push(cur.clone());
+    // a release that drained the waiters before the push
+    if claim.winner.load(Ordering::Acquire) == NO_WINNER {
+        return;
+    }
+    if cur.park(None) == Err(ParkError::Canceled) {
+        trigger_cancel_panic();
+    }
+}
+
+// the cqueue state, it's boxed so that the select coroutines can refer to it
+// even if an owned `Cqueue` is moved
+struct Inner {
     // the mpsc queue that transfer event
     ev_queue: Queue<Event>,
     // thread/coroutine for wake up
@@ -161,50 +667,477 @@ pub struct Cqueue {
     total: AtomicUsize,
     // panic status
     is_panicking: AtomicBool,
+    // the normal events that are queued but not polled yet
+    pending: AtomicUsize,
+    // the max pending events
+    capacity: AtomicUsize,
+    // the `Overflow` policy at the capacity
+    overflow: AtomicU8,
+    // the events that are dropped by the overflow policy
+    dropped: AtomicU64,
+    // the most pending events so far
+    high_watermark: AtomicUsize,
+    // the select coroutines that wait for room
+    space_waiters: Queue<Arc<Blocker>>,
+    // the tokens of the finished select coroutines, not taken yet
+    finished: Mutex<Vec<usize>>,
+    // the commit of the `select!` arms
+    claim: Claim,
+    // the eventfd that is readable while there are queued events
+    #[cfg(target_os = "linux")]
+    fd: OnceCell<OwnedFd>,
 }
 
-impl Cqueue {
+impl Inner {
+    fn push(&self, ev: Event) {
+        self.ev_queue.push(ev);
+        self.signal();
+        if let Some(w) = self.to_wake.take() {
+            let _ = w.unpark();
+        }
+    }
+
+    // an event is queued, make the fd readable
+    #[inline]
+    fn signal(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(fd) = self.fd.get() {
+            let one = 1u64;
+            // EAGAIN means the counter is already far from zero
+            unsafe {
+                libc::write(
+                    fd.as_raw_fd(),
+                    &one as *const u64 as *const libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+        }
+    }
+
+    // reset the fd once the queue is drained, a push that races with the
+    // reset is seen by the re-check and signals again
+    #[inline]
+    fn unsignal(&self) {
+        #[cfg(target_os = "linux")]
+        if let Some(fd) = self.fd.get() {
+            if !self.ev_queue.is_empty() {
+                return;
+            }
+            let mut cnt = 0u64;
+            unsafe {
+                libc::read(
+                    fd.as_raw_fd(),
+                    &mut cnt as *mut u64 as *mut libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+            if !self.ev_queue.is_empty() {
+                self.signal();
+            }
+        }
+    }
+
+    // pop an event, the room of a normal event is released
+    fn pop(&self) -> Option<Event> {
+        let ev = self.ev_queue.pop();
+        self.unsignal();
+        let ev = ev?;
+        if ev.kind == EventKind::Normal {
+            self.pending.fetch_sub(1, Ordering::AcqRel);
+            self.wake_space_waiters();
+            if let Some(inflight) = &ev.inflight {
+                inflight.release();
+            }
+        }
+        Some(ev)
+    }
+
+    fn wake_space_waiters(&self) {
+        // the waiters retry for the room, the stale ones are harmless
+        while let Some(w) = self.space_waiters.pop() {
+            let _ = w.unpark();
+        }
+    }
+
+    fn try_reserve(&self) -> bool {
+        let capacity = self.capacity.load(Ordering::Acquire);
+        let mut n = self.pending.load(Ordering::Acquire);
+        loop {
+            if n >= capacity {
+                return false;
+            }
+            match self
+                .pending
+                .compare_exchange_weak(n, n + 1, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => {
+                    self.high_watermark.fetch_max(n + 1, Ordering::Relaxed);
+                    return true;
+                }
+                Err(cur) => n = cur,
+            }
+        }
+    }
+
+    fn overflow(&self) -> Overflow {
+        Overflow::from_u8(self.overflow.load(Ordering::Acquire))
+    }
+
+    // take the room of a new event, or apply the overflow policy
+    fn try_admit(&self) -> Admit {
+        if self.try_reserve() {
+            return Admit::Queued;
+        }
+        match self.overflow() {
+            Overflow::BlockSelector => Admit::Full,
+            Overflow::DropOldest if self.evict_oldest() => Admit::Queued,
+            Overflow::DropOldest | Overflow::DropNewest => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Admit::Dropped
+            }
+        }
+    }
+
+    // drop the oldest queued event that has no bottom half, the new event
+    // takes over its room. the events before it are queued again, they are
+    // the done events and at most one `send` of each select coroutine
+    fn evict_oldest(&self) -> bool {
+        let mut kept = Vec::new();
+        let mut evicted = false;
+        while let Some(ev) = self.ev_queue.pop() {
+            if ev.kind == EventKind::Normal && ev.co.is_none() {
+                if let Some(inflight) = &ev.inflight {
+                    inflight.release();
+                }
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                evicted = true;
+                break;
+            }
+            kept.push(ev);
+        }
+        for ev in kept {
+            self.push(ev);
+        }
+        evicted
+    }
+
+    // wait for room until the deadline, `Full` if timeout
+    fn admit(&self, deadline: Option<Instant>) -> Admit {
+        loop {
+            match self.try_admit() {
+                Admit::Full => {}
+                admit => return admit,
+            }
+            let timeout = match deadline {
+                None => None,
+                Some(d) => {
+                    let now = now_instant();
+                    if now >= d {
+                        return Admit::Full;
+                    }
+                    Some(d - now)
+                }
+            };
+            let cur = Blocker::current();
+            self.space_waiters.push(cur.clone());
+            // re-check the room
+            if self.try_reserve() {
+                return Admit::Queued;
+            }
+            if cur.park(timeout).is_err() {
+                current_cancel_data().check_cancel();
+            }
+        }
+    }
+}
+
+/// the marker of a cqueue that is borrowed in a [`scope`]
+///
+/// [`scope`]: ./fn.scope.html
+#[derive(Debug)]
+pub struct Scoped;
+
+/// the marker of a cqueue that is created by [`Cqueue::new`]
+///
+/// [`Cqueue::new`]: ./struct.Cqueue.html#method.new
+#[derive(Debug)]
+pub struct Owned;
+
+/// cqueue interface for general select model
+///
+/// a cqueue is either borrowed in a [`scope`], where the select coroutines can
+/// borrow the local data of the caller, or owned by [`Cqueue::new`], where the
+/// select coroutines must be `'static` so that the cqueue can be stored in a
+/// struct and polled across many calls.
+///
+/// dropping the cqueue, or calling `close`, cancels all the unfinished select
+/// coroutines and waits until they are unwound
+///
+/// [`scope`]: ./fn.scope.html
+/// [`Cqueue::new`]: ./struct.Cqueue.html#method.new
+pub struct Cqueue<S = Scoped> {
+    inner: Box<Inner>,
+    _marker: PhantomData<S>,
+}
+
+impl Cqueue<Owned> {
+    /// create an owned cqueue, the select coroutines must be `'static`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mco::cqueue::Cqueue;
+    /// use mco::std::sync::channel::channel;
+    ///
+    /// let (tx, rx) = channel();
+    /// let cqueue = Cqueue::new();
+    /// cqueue.add(0, move |es| {
+    ///     for v in rx.iter() {
+    ///         es.send(v);
+    ///     }
+    /// });
+    /// tx.send(42).unwrap();
+    /// assert_eq!(cqueue.poll(None).unwrap().extra, 42);
+    /// cqueue.close();
+    /// ```
+    pub fn new() -> Self {
+        Cqueue::new_inner()
+    }
+
+    /// create an owned cqueue with the event queue capacity, see `set_capacity`
+    pub fn with_capacity(capacity: usize) -> Self {
+        let cqueue = Cqueue::new_inner();
+        cqueue.set_capacity(capacity);
+        cqueue
+    }
+
+    /// create an owned cqueue with the capacity and the overflow policy of
+    /// the event queue
+    pub fn with_config(config: CqueueConfig) -> Self {
+        let cqueue = Cqueue::new_inner();
+        cqueue.configure(config);
+        cqueue
+    }
+
+    /// register a `'static` select coroutine with the cqueue
+    pub fn add<F>(&self, token: usize, f: F) -> Selector
+    where
+        F: FnOnce(EventSender) + Send + 'static,
+    {
+        self.add_impl(token, None, None, f)
+    }
+
+    /// register a `'static` select coroutine that has at most `max_inflight`
+    /// events not polled yet, see `Cqueue::<Scoped>::add_with`
+    pub fn add_with<F>(&self, token: usize, max_inflight: usize, f: F) -> Selector
+    where
+        F: FnOnce(EventSender) + Send + 'static,
+    {
+        self.add_impl(token, None, Some(max_inflight), f)
+    }
+}
+
+impl Default for Cqueue<Owned> {
+    fn default() -> Self {
+        Cqueue::new()
+    }
+}
+
+impl Cqueue<Scoped> {
     /// register a select coroutine with the cqueue
     /// should use `cqueue_add` and `cqueue_add_oneshot` macros to
     /// create select coroutines correctly
-    fn add_impl<'a, F>(&self, token: usize, f: F) -> Selector
+    pub fn add<'a, F>(&self, token: usize, f: F) -> Selector
     where
         F: FnOnce(EventSender) + Send + 'a,
     {
+        self.add_impl(token, None, None, f)
+    }
+
+    /// register a select coroutine that has at most `max_inflight` events
+    /// not polled yet, at least 1
+    ///
+    /// once the limit is reached `send` and `send_timeout` wait until the
+    /// poller takes the previous events and `try_send` fails, so a selector
+    /// that pumps a fast source stops producing events instead of filling
+    /// the event queue. the other selectors are not limited by it, unlike
+    /// `set_capacity`
+    ///
+    /// ```
+    /// use mco::cqueue;
+    /// use std::time::Duration;
+    ///
+    /// cqueue::scope(|cqueue| {
+    ///     cqueue.add_with(0, 2, |es| {
+    ///         for i in 0..10 {
+    ///             es.send_timeout(i, Duration::from_secs(10)).unwrap();
+    ///         }
+    ///     });
+    ///     let mut got = Vec::new();
+    ///     while let Ok(ev) = cqueue.poll(None) {
+    ///         got.push(ev.extra);
+    ///     }
+    ///     assert_eq!(got, (0..10).collect::<Vec<_>>());
+    /// });
+    /// ```
+    pub fn add_with<'a, F>(&self, token: usize, max_inflight: usize, f: F) -> Selector
+    where
+        F: FnOnce(EventSender) + Send + 'a,
+    {
+        self.add_impl(token, None, Some(max_inflight), f)
+    }
+
+    /// register a select coroutine that is not `Send`, used by `select! { local; .. }`
+    /// the select coroutine is pinned to the worker of the current coroutine,
+    /// panic if the current coroutine is not pinned
+    #[doc(hidden)]
+    pub fn add_local<'a, F>(&self, token: usize, f: F) -> Selector
+    where
+        F: FnOnce(EventSender) + 'a,
+    {
+        let worker = try_current()
+            .ok()
+            .and_then(|co| co.pinned_worker())
+            .expect("local select must be used in a pinned coroutine");
+        self.add_impl(token, Some(worker), None, f)
+    }
+}
+
+impl<S> Cqueue<S> {
+    fn new_inner() -> Self {
+        Cqueue {
+            inner: Box::new(Inner {
+                ev_queue: Queue::new(),
+                to_wake: AtomicOption::none(),
+                cnt: AtomicUsize::new(0),
+                selectors: Mutex::new(Vec::new()),
+                total: AtomicUsize::new(0),
+                is_panicking: AtomicBool::new(false),
+                pending: AtomicUsize::new(0),
+                capacity: AtomicUsize::new(usize::MAX),
+                overflow: AtomicU8::new(Overflow::BlockSelector as u8),
+                dropped: AtomicU64::new(0),
+                high_watermark: AtomicUsize::new(0),
+                space_waiters: Queue::new(),
+                finished: Mutex::new(Vec::new()),
+                claim: Claim::new(),
+                #[cfg(target_os = "linux")]
+                fd: OnceCell::new(),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// register a select coroutine with the cqueue
+    /// should use `cqueue_add` and `cqueue_add_oneshot` macros to
+    /// create select coroutines correctly
+    ///
+    /// `f` is only allowed to be `!Send` when it's pinned to the current worker
+    fn add_impl<'a, F>(
+        &self,
+        token: usize,
+        pin: Option<usize>,
+        max_inflight: Option<usize>,
+        f: F,
+    ) -> Selector
+    where
+        F: FnOnce(EventSender) + 'a,
+    {
+        let inner = &*self.inner;
         let sender = EventSender {
-            id: self.total.load(Ordering::Relaxed),
+            id: inner.total.load(Ordering::Relaxed),
             token,
             extra: 0.into(),
-            cqueue: self,
+            payload: AtomicOption::none(),
+            inflight: max_inflight.map(|max| Arc::new(Inflight::new(max))),
+            cqueue: inner,
+        };
+        let builder = match pin {
+            Some(worker) => Builder::selector().pin(worker),
+            None => Builder::selector(),
+        };
+        let live = LiveSelector::new();
+        let h = unsafe {
+            spawn_unsafe_with(builder, move || {
+                let _live = live;
+                f(sender)
+            })
         };
-        let h = unsafe { spawn_unsafe(move || f(sender)) };
         let co = h.coroutine().clone();
-        self.cnt.fetch_add(1, Ordering::Relaxed);
+        inner.cnt.fetch_add(1, Ordering::Relaxed);
 
-        self.total.fetch_add(1, Ordering::Relaxed);
-        self.selectors.lock().unwrap().push(Some(h));
+        inner.total.fetch_add(1, Ordering::Relaxed);
+        inner.selectors.lock().unwrap().push(Some(h));
         Selector { co }
     }
 
-    /// register a select coroutine with the cqueue
-    /// should use `cqueue_add` and `cqueue_add_oneshot` macros to
-    /// create select coroutines correctly
-    pub fn add<'a, F>(&self, token: usize, f: F) -> Selector
-    where
-        F: FnOnce(EventSender) + Send + 'a,
-    {
-        self.add_impl(token, f)
+    /// set the max number of the events that are queued but not polled yet,
+    /// at least 1. it's unbounded by default, `send` already limits each
+    /// select coroutine to one event. what happens to an event beyond it is
+    /// set by `set_overflow`
+    pub fn set_capacity(&self, capacity: usize) {
+        self.inner
+            .capacity
+            .store(capacity.max(1), Ordering::Release);
+        self.inner.wake_space_waiters();
+    }
+
+    /// the max number of the events that are queued but not polled yet
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity.load(Ordering::Acquire)
+    }
+
+    /// set what happens to a new event once the capacity is reached, it's
+    /// `Overflow::BlockSelector` by default
+    pub fn set_overflow(&self, overflow: Overflow) {
+        self.inner.overflow.store(overflow as u8, Ordering::Release);
+        // the waiters for room drop their events now
+        self.inner.wake_space_waiters();
+    }
+
+    /// what happens to a new event once the capacity is reached
+    pub fn overflow(&self) -> Overflow {
+        self.inner.overflow()
+    }
+
+    fn configure(&self, config: CqueueConfig) {
+        self.set_capacity(config.capacity);
+        self.set_overflow(config.overflow);
+    }
+
+    /// the events that are dropped by the overflow policy
+    pub fn dropped_events(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// the most events that are queued but not polled yet at any time
+    pub fn high_watermark(&self) -> usize {
+        self.inner.high_watermark.load(Ordering::Relaxed)
     }
 
     // when the select coroutine is done, check the panic status
     // if it's panicked, re throw the panic data
+    // a select coroutine is finished
+    fn finish(&self, ev: Event) {
+        self.inner.finished.lock().unwrap().push(ev.token);
+        self.check_panic(ev.id);
+    }
+
+    /// take the tokens of the select coroutines that are finished since the
+    /// last call, in the order that `poll` sees them
+    ///
+    /// e.g. a `cqueue_add!` selector finishes when its channel is disconnected
+    pub fn take_finished(&self) -> Vec<usize> {
+        std::mem::take(&mut *self.inner.finished.lock().unwrap())
+    }
+
     fn check_panic(&self, id: usize) {
-        if self.is_panicking.load(Ordering::Relaxed) {
+        if self.inner.is_panicking.load(Ordering::Relaxed) {
             return;
         }
 
         use mco_gen::Error;
-        match self.selectors.lock().unwrap()[id]
+        match self.inner.selectors.lock().unwrap()[id]
             .take()
             .expect("join handler not set")
             .join()
@@ -217,7 +1150,7 @@ impl Cqueue {
                         return;
                     }
                 }
-                self.is_panicking.store(true, Ordering::Relaxed);
+                self.inner.is_panicking.store(true, Ordering::Relaxed);
                 panic::resume_unwind(panic);
             }
         }
@@ -229,10 +1162,51 @@ impl Cqueue {
     /// if any panic in select coroutine detected during the poll
     /// it will propagate the panic to the caller
     pub fn poll(&self, timeout: Option<Duration>) -> Result<Event, PollError> {
+        // a timeout too long for an `Instant` never comes
+        self.poll_deadline(timeout.and_then(|dur| now_instant().checked_add(dur)))
+    }
+
+    /// poll an event like `poll`, but until the absolute `deadline`, the
+    /// wall clock is read once so a loop with a fixed deadline doesn't drift
+    ///
+    /// a deadline in the past checks the queued events and returns `Timeout`
+    /// ```
+    /// use mco::std::time::Time;
+    /// use std::time::Duration;
+    ///
+    /// let deadline = Time::now().add(Duration::from_millis(10));
+    /// mco::cqueue::scope(|cqueue| {
+    ///     cqueue.add(0, |_| mco::coroutine::sleep(Duration::from_secs(10)));
+    ///     let ret = cqueue.poll_until(&deadline);
+    ///     assert_eq!(ret.unwrap_err(), mco::cqueue::PollError::Timeout);
+    /// });
+    /// ```
+    pub fn poll_until(&self, deadline: &Time) -> Result<Event, PollError> {
+        self.poll_deadline(deadline.instant())
+    }
+
+    /// poll an event like `poll`, it returns early with `Done` once the
+    /// context is canceled or its deadline is passed
+    ///
+    /// the cancellation of the context only wakes a poll in a coroutine, a
+    /// thread sees the deadline but not the cancel
+    pub fn poll_ctx(&self, ctx: &Context) -> Result<Event, PollCtxError> {
+        match ctx.guard(false, || self.poll_deadline(ctx.deadline())) {
+            Ok(Ok(ev)) => Ok(ev),
+            Ok(Err(PollError::Finished)) => Err(PollCtxError::Finished),
+            // the timer of the context may not have fired yet
+            Ok(Err(PollError::Timeout)) => Err(PollCtxError::Done(
+                ctx.err().unwrap_or(ContextError::DeadlineExceeded),
+            )),
+            Err(e) => Err(PollCtxError::Done(e)),
+        }
+    }
+
+    fn poll_deadline(&self, deadline: Option<Instant>) -> Result<Event, PollError> {
         macro_rules! run_ev {
             ($ev:ident) => {{
                 if $ev.kind == EventKind::Done {
-                    self.check_panic($ev.id);
+                    self.finish($ev);
                     continue;
                 }
                 $ev.continue_bottom();
@@ -240,27 +1214,35 @@ impl Cqueue {
             }};
         }
 
-        let deadline = timeout.map(|dur| Instant::now() + dur);
         loop {
-            match self.ev_queue.pop() {
+            match self.inner.pop() {
                 Some(mut ev) => run_ev!(ev),
                 None => {
-                    if self.cnt.load(Ordering::Relaxed) == 0 {
+                    if self.inner.cnt.load(Ordering::Relaxed) == 0 {
                         return Err(PollError::Finished);
                     }
                 }
             }
 
+            // the time left of the deadline, it's never counted from the
+            // wake ups in between
+            let timeout = match deadline {
+                Some(d) => match d.checked_duration_since(now_instant()) {
+                    Some(left) => Some(left),
+                    None => return Err(PollError::Timeout),
+                },
+                None => None,
+            };
             let cur = Blocker::current();
             // register the waiter
-            self.to_wake.swap(cur.clone());
+            self.inner.to_wake.swap(cur.clone());
             // re-check the queue
-            match self.ev_queue.pop() {
+            match self.inner.pop() {
                 None => {
                     cur.park(timeout).ok();
                 }
                 Some(mut ev) => {
-                    if let Some(w) = self.to_wake.take() {
+                    if let Some(w) = self.inner.to_wake.take() {
                         let _ = w.unpark();
                     }
                     cur.park(timeout).ok();
@@ -270,19 +1252,102 @@ impl Cqueue {
 
             // check the timeout
             match deadline {
-                Some(d) if Instant::now() >= d => return Err(PollError::Timeout),
+                Some(d) if now_instant() >= d => return Err(PollError::Timeout),
                 _ => {}
             }
         }
     }
+
+    /// poll up to `max` events, it blocks only for the first one
+    /// the bottom halves of the returned events are already run
+    pub fn poll_batch(
+        &self,
+        max: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>, PollError> {
+        self.batch(max, || self.poll(timeout))
+    }
+
+    /// poll up to `max` events like `poll_batch`, but until the absolute
+    /// `deadline`, see `poll_until`
+    pub fn poll_batch_until(&self, max: usize, deadline: &Time) -> Result<Vec<Event>, PollError> {
+        self.batch(max, || self.poll_until(deadline))
+    }
+
+    // poll the first event by `first`, then take the queued ones
+    fn batch<F>(&self, max: usize, first: F) -> Result<Vec<Event>, PollError>
+    where
+        F: FnOnce() -> Result<Event, PollError>,
+    {
+        let mut events = Vec::new();
+        if max == 0 {
+            return Ok(events);
+        }
+        events.push(first()?);
+        while events.len() < max {
+            match self.inner.pop() {
+                Some(mut ev) => {
+                    if ev.kind == EventKind::Done {
+                        self.finish(ev);
+                        continue;
+                    }
+                    ev.continue_bottom();
+                    events.push(ev);
+                }
+                None => break,
+            }
+        }
+        Ok(events)
+    }
+
+    /// poll an event without blocking, the same as `poll(Some(Duration::ZERO))`
+    /// return `Timeout` if there is no event queued
+    pub fn poll_nonblocking(&self) -> Result<Event, PollError> {
+        self.poll(Some(Duration::ZERO))
+    }
+
+    /// the eventfd for the external event loops, created on the first call
+    ///
+    /// it's level triggered, the fd stays readable while the events remain
+    /// in the queue, a spurious wakeup is possible when a push races with the
+    /// poll. the loop polls the fd for reading and then drains the cqueue by
+    /// `poll_nonblocking` until `Timeout`, nothing has to be read from it.
+    /// the fd is owned by the cqueue and closed with it
+    #[cfg(target_os = "linux")]
+    pub fn readiness_fd(&self) -> io::Result<BorrowedFd<'_>> {
+        let fd = self.inner.fd.get_or_try_init(|| {
+            let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        })?;
+        // the events that are queued before the fd exists
+        if !self.inner.ev_queue.is_empty() {
+            self.inner.signal();
+        }
+        Ok(fd.as_fd())
+    }
+
+    /// cancel all the unfinished select coroutines and wait until they are
+    /// unwound, it's the same as drop the cqueue
+    pub fn close(self) {}
 }
 
-impl Drop for Cqueue {
-    // this would cancel all unfinished select coroutines
-    // and wait until all of them return back
-    fn drop(&mut self) {
+impl<S> Cqueue<S> {
+    /// cancel the unfinished select coroutines when the guard is dropped, and
+    /// wait until all of them return back. used by `select!` for the selectors
+    /// that borrow the locals of the scope
+    #[doc(hidden)]
+    pub fn guard(&self) -> SelectGuard<'_, S> {
+        SelectGuard(self)
+    }
+
+    // cancel all unfinished select coroutines and wait until all of them return back
+    fn finish(&self) {
         // first cancel all the select coroutines if they are running
-        self.selectors
+        self.inner
+            .selectors
             .lock()
             .unwrap()
             .iter()
@@ -292,10 +1357,6 @@ impl Drop for Cqueue {
                 _ => {}
             });
 
-        // if self.is_panicking {
-        //     return;
-        // }
-
         // run the rest event
         loop {
             match self.poll(None) {
@@ -308,6 +1369,180 @@ impl Drop for Cqueue {
     }
 }
 
+impl<S> Drop for Cqueue<S> {
+    // this would cancel all unfinished select coroutines
+    // and wait until all of them return back
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[doc(hidden)]
+pub struct SelectGuard<'a, S>(&'a Cqueue<S>);
+
+impl<'a, S> Drop for SelectGuard<'a, S> {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+// counts a select coroutine in `stats().selectors` until it's dropped
+struct LiveSelector;
+
+impl LiveSelector {
+    fn new() -> Self {
+        stats::SELECTORS.fetch_add(1, Ordering::Relaxed);
+        LiveSelector
+    }
+}
+
+impl Drop for LiveSelector {
+    fn drop(&mut self) {
+        stats::SELECTORS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// the wait between the rounds of `select! { biased; .. }` when no arm is ready
+#[doc(hidden)]
+pub struct ProbeBackoff(u32);
+
+impl ProbeBackoff {
+    // yield for the first rounds
+    const YIELDS: u32 = 16;
+
+    pub fn new() -> Self {
+        ProbeBackoff(0)
+    }
+
+    // yield, then sleep for a doubling time from 1us up to about 1ms
+    pub fn wait(&mut self) {
+        if self.0 < Self::YIELDS {
+            crate::yield_now::yield_now();
+        } else {
+            let shift = (self.0 - Self::YIELDS).min(10);
+            crate::sleep::sleep(Duration::from_micros(1 << shift));
+        }
+        self.0 = self.0.saturating_add(1);
+    }
+}
+
+/// the event of a `TypedCqueue`, with the typed token of its select coroutine
+#[derive(Debug)]
+pub struct TypedEvent<T> {
+    /// the token passed to `TypedCqueue::add`
+    pub token: T,
+    /// the event, its `token` field is the index of the typed token
+    pub event: Event,
+}
+
+/// a cqueue with the typed tokens, like an enum, instead of the bare `usize`
+///
+/// each distinct token is given a `usize` token of the underlying cqueue in
+/// the order they are first added, `poll` maps it back. the select coroutines
+/// that need their typed token can capture it, it's `Copy`
+///
+/// ```
+/// use mco::cqueue::{self, PollError};
+///
+/// #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+/// enum Source {
+///     Heartbeat,
+///     Data,
+/// }
+///
+/// cqueue::scope_typed(|cq| {
+///     cq.add(Source::Heartbeat, |es| es.send(0));
+///     cq.add(Source::Data, |es| es.send(42));
+///     let mut got = Vec::new();
+///     while let Ok(ev) = cq.poll(None) {
+///         got.push((ev.token, ev.event.extra));
+///     }
+///     got.sort_by_key(|(_, extra)| *extra);
+///     assert_eq!(got, [(Source::Heartbeat, 0), (Source::Data, 42)]);
+/// });
+/// ```
+pub struct TypedCqueue<'a, T, S = Scoped> {
+    cqueue: &'a Cqueue<S>,
+    // the typed tokens, indexed by the usize tokens
+    tokens: RefCell<Vec<T>>,
+}
+
+impl<'a, T: Copy + Eq, S> TypedCqueue<'a, T, S> {
+    /// use the typed tokens on the cqueue, the select coroutines that are
+    /// added to it directly are polled with their `usize` token as an index
+    pub fn new(cqueue: &'a Cqueue<S>) -> Self {
+        TypedCqueue {
+            cqueue,
+            tokens: RefCell::new(Vec::new()),
+        }
+    }
+
+    // the usize token of the typed one
+    fn index(&self, token: T) -> usize {
+        let mut tokens = self.tokens.borrow_mut();
+        match tokens.iter().position(|t| *t == token) {
+            Some(i) => i,
+            None => {
+                tokens.push(token);
+                tokens.len() - 1
+            }
+        }
+    }
+
+    /// the typed token of a `usize` token
+    pub fn token(&self, index: usize) -> Option<T> {
+        self.tokens.borrow().get(index).copied()
+    }
+
+    /// the underlying cqueue
+    pub fn cqueue(&self) -> &'a Cqueue<S> {
+        self.cqueue
+    }
+
+    /// poll an event with its typed token, see `Cqueue::poll`
+    ///
+    /// panic if the event is from a select coroutine added to the cqueue
+    /// directly with a `usize` token that is not given to a typed one
+    pub fn poll(&self, timeout: Option<Duration>) -> Result<TypedEvent<T>, PollError> {
+        let event = self.cqueue.poll(timeout)?;
+        let token = self
+            .token(event.token)
+            .expect("cqueue event without a typed token");
+        Ok(TypedEvent { token, event })
+    }
+}
+
+impl<'a, T: Copy + Eq> TypedCqueue<'a, T, Scoped> {
+    /// register a select coroutine with the typed token
+    pub fn add<'b, F>(&self, token: T, f: F) -> Selector
+    where
+        F: FnOnce(EventSender) + Send + 'b,
+    {
+        self.cqueue.add(self.index(token), f)
+    }
+}
+
+impl<'a, T: Copy + Eq> TypedCqueue<'a, T, Owned> {
+    /// register a `'static` select coroutine with the typed token
+    pub fn add<F>(&self, token: T, f: F) -> Selector
+    where
+        F: FnOnce(EventSender) + Send + 'static,
+    {
+        self.cqueue.add(self.index(token), f)
+    }
+}
+
+/// like `scope`, but the select coroutines are added with the typed tokens
+pub fn scope_typed<'a, T, F, R>(f: F) -> R
+where
+    T: Copy + Eq,
+    F: FnOnce(&TypedCqueue<T>) -> R + 'a,
+{
+    let cqueue = Cqueue::new_inner();
+    let typed = TypedCqueue::new(&cqueue);
+    f(&typed)
+}
+
 /// Create a new `scope`, for select coroutines.
 ///
 /// Scopes, in particular, support scoped select coroutine spawning.
@@ -316,13 +1551,40 @@ pub fn scope<'a, F, R>(f: F) -> R
 where
     F: FnOnce(&Cqueue) -> R + 'a,
 {
-    let cqueue = Cqueue {
-        ev_queue: Queue::new(),
-        to_wake: AtomicOption::none(),
-        cnt: AtomicUsize::new(0),
-        selectors: Mutex::new(Vec::new()),
-        total: AtomicUsize::new(0),
-        is_panicking: AtomicBool::new(false),
-    };
+    let cqueue = Cqueue::new_inner();
+    f(&cqueue)
+}
+
+/// like `scope`, but the event queue has the capacity and the overflow
+/// policy of the config
+///
+/// ```
+/// use mco::cqueue::{self, CqueueConfig, Overflow};
+///
+/// let config = CqueueConfig {
+///     capacity: 4,
+///     overflow: Overflow::DropOldest,
+/// };
+/// cqueue::scope_with(config, |cqueue| {
+///     cqueue.add(0, |es| {
+///         for i in 0..10 {
+///             es.try_send(i).unwrap();
+///         }
+///     });
+///     let mut got = Vec::new();
+///     while let Ok(ev) = cqueue.poll(None) {
+///         got.push(ev.extra);
+///     }
+///     // the poller may take some of the events before they are dropped
+///     assert!(got.ends_with(&[6, 7, 8, 9]));
+///     assert_eq!(got.len() as u64 + cqueue.dropped_events(), 10);
+/// });
+/// ```
+pub fn scope_with<'a, F, R>(config: CqueueConfig, f: F) -> R
+where
+    F: FnOnce(&Cqueue) -> R + 'a,
+{
+    let cqueue = Cqueue::new_inner();
+    cqueue.configure(config);
     f(&cqueue)
 }
@@ -0,0 +1,111 @@
+//! the deterministic scheduling of the tests, see
+//! `config().set_deterministic_seed`
+//!
+//! with a seed the choices of the runtime that would depend on the order of
+//! the threads or on the heap layouts come from seeded generators: the order
+//! that a worker tries the victims of a steal, the start of a `SelectSet`
+//! round, the order of the timers that are due in the same tick, and the
+//! seeds of the coroutine random numbers of `std::rand`. each thread draws
+//! from its own stream, so the draws of one worker don't shift with those of
+//! the timer thread. with one worker the same seed replays the same order of
+//! the coroutines for the same program, as long as the timers and the io
+//! complete in the same order, e.g. on the virtual clock of `test-util`
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Once;
+
+// the env var that replaces the seed set by the code, to replay a failure
+pub(crate) const SEED_ENV: &str = "MCO_SEED";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SEED: AtomicU64 = AtomicU64::new(0);
+// bumped by each new seed, so that the thread streams are reseeded
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+static HOOK: Once = Once::new();
+
+thread_local! {
+    // the generation and the state of the stream of this thread
+    static STREAM: Cell<(u64, u64)> = Cell::new((0, 0));
+}
+
+#[inline]
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn seed() -> Option<u64> {
+    if enabled() {
+        Some(SEED.load(Ordering::Relaxed))
+    } else {
+        None
+    }
+}
+
+// the seed of `MCO_SEED`, if it's set
+pub(crate) fn env_seed() -> Option<u64> {
+    let s = std::env::var(SEED_ENV).ok()?;
+    match s.trim().parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => {
+            warn!("{}={:?} is not a u64, ignored", SEED_ENV, s);
+            None
+        }
+    }
+}
+
+pub(crate) fn set_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Release);
+    crate::std::rand::restart_seeds();
+    HOOK.call_once(print_seed_on_panic);
+}
+
+// print the seed before the report of each panic, the cancel panics are
+// left to the hook of the scheduler that hides them
+fn print_seed_on_panic() {
+    let old = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        if let Some(&mco_gen::Error::Cancel) = info.payload().downcast_ref::<mco_gen::Error>() {
+            return old(info);
+        }
+        if let Some(seed) = seed() {
+            eprintln!(
+                "mco: the deterministic seed is {}, replay it with {}={}",
+                seed, SEED_ENV, seed
+            );
+        }
+        old(info);
+    }));
+}
+
+// splitmix64, the same as `std::rand`
+#[inline]
+pub(crate) fn mix(s: u64) -> u64 {
+    let mut z = s.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// the next number of the stream of this thread, the stream starts from the
+// seed and the `key` of its first draw, e.g. the worker id
+pub(crate) fn next_u64(key: u64) -> u64 {
+    let gen = GENERATION.load(Ordering::Relaxed);
+    STREAM.with(|s| {
+        let (g, mut state) = s.get();
+        if g != gen {
+            state = mix(SEED.load(Ordering::Relaxed) ^ mix(key));
+        }
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        s.set((gen, state));
+        mix(state)
+    })
+}
+
+// a number in `0..n` of the stream `key`, `n` must not be 0
+#[inline]
+pub(crate) fn below(key: u64, n: usize) -> usize {
+    (next_u64(key) % n as u64) as usize
+}
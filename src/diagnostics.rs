@@ -0,0 +1,431 @@
+//! the machine readable snapshot of the runtime, enabled by the
+//! `diagnostics` feature
+//!
+//! `snapshot` takes the state of the default runtime as a `Snapshot` that
+//! implements `Serialize`, `write_snapshot` streams it as JSON. on unix
+//! `install_signal_dump` writes it to a file of its own each time the process
+//! gets a signal, like the thread dump of a JVM, so that an incident ticket
+//! has the same artifact each time
+//!
+//! the format is stable, the fields are only added and `version` is bumped
+//! when one changes its meaning. the durations are in nanoseconds and a
+//! coroutine is its id, the fields of a snapshot:
+//! - `version`: 1
+//! - `time`: the RFC3339 wall clock with the milliseconds
+//! - `stats`: the fields of `stats::stats()`
+//! - `global_queued`: the coroutines in the global queue
+//! - `workers`: each worker has the `id`, the `thread` name, the `state`
+//!   (new, active, draining, parked or dead), the `queued` coroutines and
+//!   the `running` coroutine with its `running_name`. the running coroutine
+//!   is only recorded while the watchdog is on, see `config().set_time_slice()`
+//! - `overruns`: the coroutines of `coroutine::dump()`
+//! - `channels`: the live channels of `std::sync::channel_dump()` with the
+//!   coroutines parked on them, only with the `chan-registry` feature
+
+use std::io::{self, Write};
+
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+
+use crate::coroutine_impl::CoroutineId;
+use crate::scheduler::default_scheduler_started;
+use crate::stats::{self, Stats};
+#[cfg(feature = "chan-registry")]
+use crate::std::sync::{channel_dump, ChannelInfo, ParkedInfo};
+use crate::std::time::Time;
+use crate::thread_names;
+use crate::watchdog::{self, Overrun};
+
+#[cfg(unix)]
+pub use self::signal::{install_signal_dump, Signal};
+
+/// the version of the snapshot format
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// the state of a worker of the default runtime
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WorkerState {
+    /// the id of the worker
+    pub id: usize,
+    /// the name of the worker thread
+    pub thread: String,
+    /// new, active, draining, parked or dead
+    pub state: &'static str,
+    /// the coroutines in the local queue of the worker
+    pub queued: usize,
+    /// the coroutine that it runs, only recorded with the watchdog on
+    pub running: Option<CoroutineId>,
+    /// the name of the running coroutine
+    pub running_name: Option<String>,
+}
+
+/// a snapshot of the runtime, see the module docs for its format
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Snapshot {
+    /// the wall clock of the snapshot
+    pub time: Time,
+    /// the runtime statistics
+    pub stats: Stats,
+    /// the coroutines in the global queue of the default runtime
+    pub global_queued: usize,
+    /// the workers of the default runtime, empty if it's not started
+    pub workers: Vec<WorkerState>,
+    /// the coroutines that overrun the time slice
+    pub overruns: Vec<Overrun>,
+    /// the live channels
+    #[cfg(feature = "chan-registry")]
+    pub channels: Vec<ChannelInfo>,
+}
+
+/// take a snapshot of the default runtime
+///
+/// it only reads the counters and takes the short locks of the runtime, so
+/// it's safe to take while the runtime is under load. the queue lengths are
+/// read while the workers run, they are hints
+pub fn snapshot() -> Snapshot {
+    let mut global_queued = 0;
+    let mut workers = Vec::new();
+    if let Some(s) = default_scheduler_started() {
+        global_queued = s.global_queued();
+        for id in 0..s.max_workers() {
+            let (state, queued) = s.worker_state(id);
+            let running = s.run_slot(id).and_then(|slot| slot.running());
+            workers.push(WorkerState {
+                id,
+                thread: thread_names::worker_name(id),
+                state,
+                queued,
+                running: running.as_ref().map(|r| r.0),
+                running_name: running.and_then(|r| r.1),
+            });
+        }
+    }
+    Snapshot {
+        time: Time::now_utc(),
+        stats: stats::stats(),
+        global_queued,
+        workers,
+        overruns: watchdog::dump().overruns,
+        #[cfg(feature = "chan-registry")]
+        channels: channel_dump(),
+    }
+}
+
+/// write a snapshot to `w` as one line of JSON
+///
+/// it's streamed to `w` as it's serialized, wrap a file in a `BufWriter`
+pub fn write_snapshot<W: Write>(mut w: W) -> io::Result<()> {
+    serde_json::to_writer(&mut w, &snapshot())?;
+    w.write_all(b"\n")?;
+    w.flush()
+}
+
+fn nanos(d: std::time::Duration) -> u64 {
+    d.as_nanos().min(u64::MAX as u128) as u64
+}
+
+impl Serialize for Snapshot {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Snapshot", 7)?;
+        s.serialize_field("version", &SNAPSHOT_VERSION)?;
+        s.serialize_field("time", &self.time.display_millis().to_string())?;
+        s.serialize_field("stats", &StatsSer(&self.stats))?;
+        s.serialize_field("global_queued", &self.global_queued)?;
+        s.serialize_field("workers", &self.workers)?;
+        let overruns: Vec<_> = self.overruns.iter().map(OverrunSer).collect();
+        s.serialize_field("overruns", &overruns)?;
+        #[cfg(feature = "chan-registry")]
+        {
+            let channels: Vec<_> = self.channels.iter().map(ChannelSer).collect();
+            s.serialize_field("channels", &channels)?;
+        }
+        s.end()
+    }
+}
+
+impl Serialize for WorkerState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("WorkerState", 6)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("thread", &self.thread)?;
+        s.serialize_field("state", self.state)?;
+        s.serialize_field("queued", &self.queued)?;
+        s.serialize_field("running", &self.running.map(|id| id.as_u64()))?;
+        s.serialize_field("running_name", &self.running_name)?;
+        s.end()
+    }
+}
+
+struct StatsSer<'a>(&'a Stats);
+
+impl<'a> Serialize for StatsSer<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let st = self.0;
+        let mut s = serializer.serialize_struct("Stats", 21)?;
+        s.serialize_field("growable_stacks", &st.growable_stacks)?;
+        s.serialize_field("growable_stack_reserved", &st.growable_stack_reserved)?;
+        s.serialize_field("growable_stack_committed", &st.growable_stack_committed)?;
+        s.serialize_field("steals", &st.steals)?;
+        s.serialize_field("lifo_hits", &st.lifo_hits)?;
+        s.serialize_field("local_overflows", &st.local_overflows)?;
+        s.serialize_field("active_workers", &st.active_workers)?;
+        s.serialize_field("draining_workers", &st.draining_workers)?;
+        s.serialize_field("worker_panics", &st.worker_panics)?;
+        s.serialize_field("selectors", &st.selectors)?;
+        s.serialize_field("overruns", &st.overruns)?;
+        s.serialize_field("stalls", &st.stalls)?;
+        s.serialize_field("stall_rescued", &st.stall_rescued)?;
+        s.serialize_field("blocking_resumes", &st.blocking_resumes)?;
+        s.serialize_field("maintenance_runs", &st.maintenance_runs)?;
+        s.serialize_field("maintenance_panics", &st.maintenance_panics)?;
+        s.serialize_field("maintenance_time", &nanos(st.maintenance_time))?;
+        s.serialize_field("blocking_zones", &st.blocking_zones)?;
+        s.serialize_field("slow_blocking_zones", &st.slow_blocking_zones)?;
+        s.serialize_field("blocking_zone_time", &nanos(st.blocking_zone_time))?;
+        s.serialize_field("blocking_zone_workers", &st.blocking_zone_workers)?;
+        s.end()
+    }
+}
+
+struct OverrunSer<'a>(&'a Overrun);
+
+impl<'a> Serialize for OverrunSer<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let o = self.0;
+        let mut s = serializer.serialize_struct("Overrun", 4)?;
+        s.serialize_field("id", &o.id.as_u64())?;
+        s.serialize_field("name", &o.name)?;
+        s.serialize_field("worker", &o.worker)?;
+        s.serialize_field("running", &nanos(o.running))?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "chan-registry")]
+struct ChannelSer<'a>(&'a ChannelInfo);
+
+#[cfg(feature = "chan-registry")]
+impl<'a> Serialize for ChannelSer<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let c = self.0;
+        let mut s = serializer.serialize_struct("Channel", 5)?;
+        s.serialize_field("id", &c.id)?;
+        s.serialize_field("location", &c.location.to_string())?;
+        s.serialize_field("senders", &c.senders)?;
+        s.serialize_field("receivers", &c.receivers)?;
+        let parked: Vec<_> = c.parked.iter().map(ParkedSer).collect();
+        s.serialize_field("parked", &parked)?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "chan-registry")]
+struct ParkedSer<'a>(&'a ParkedInfo);
+
+#[cfg(feature = "chan-registry")]
+impl<'a> Serialize for ParkedSer<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let p = self.0;
+        let mut s = serializer.serialize_struct("Parked", 5)?;
+        s.serialize_field("id", &p.id.map(|id| id.as_u64()))?;
+        s.serialize_field("name", &p.name)?;
+        s.serialize_field("is_coroutine", &p.is_coroutine)?;
+        s.serialize_field("sending", &p.sending)?;
+        s.serialize_field("parked_for", &nanos(p.parked_for))?;
+        s.end()
+    }
+}
+
+#[cfg(unix)]
+mod signal {
+    use std::fs::File;
+    use std::io::{self, BufWriter, Read};
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::thread;
+
+    use crate::std::time::Time;
+    use crate::thread_names;
+
+    /// the signals of `install_signal_dump`
+    pub use nix::sys::signal::Signal;
+
+    // the write end of the pipe to the dump thread, -1 before the install
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn errno() -> *mut libc::c_int {
+        libc::__errno_location()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    unsafe fn errno() -> *mut libc::c_int {
+        libc::__error()
+    }
+
+    // only wakes the dump thread, a write is async signal safe
+    extern "C" fn on_signal(_: libc::c_int) {
+        let fd = PIPE.load(Ordering::Relaxed);
+        if fd < 0 {
+            return;
+        }
+        unsafe {
+            let saved = *errno();
+            let b = 1u8;
+            // a full pipe has a dump pending already
+            libc::write(fd, &b as *const u8 as *const libc::c_void, 1);
+            *errno() = saved;
+        }
+    }
+
+    // the path of a dump, `{pid}` and `{time}` are replaced
+    fn dump_path(template: &str) -> String {
+        let t = Time::now_utc();
+        let time = format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}.{:03}Z",
+            t.year(),
+            t.month() as u8,
+            t.day(),
+            t.hour(),
+            t.minute(),
+            t.second(),
+            t.millisecond()
+        );
+        template
+            .replace("{pid}", &std::process::id().to_string())
+            .replace("{time}", &time)
+    }
+
+    fn write_dump(template: &str) {
+        let path = dump_path(template);
+        let ret = File::create(&path)
+            .and_then(|f| super::write_snapshot(BufWriter::with_capacity(8 * 1024, f)));
+        match ret {
+            Ok(()) => info!("the runtime snapshot is written to {}", path),
+            Err(e) => warn!("can't write the runtime snapshot to {}: {}", path, e),
+        }
+    }
+
+    /// write a snapshot to a file of its own each time the process gets
+    /// `signal`, e.g. `SIGQUIT`
+    ///
+    /// `{pid}` in `path_template` is replaced with the process id and
+    /// `{time}` with the utc time like `20060102T150405.000Z`, e.g.
+    /// `/var/log/app/mco-{pid}-{time}.json`. the signal handler only wakes a
+    /// thread named `mco-diagnostics` that takes the snapshot and streams it
+    /// to the file, the signals that come in while it writes are merged into
+    /// one more dump
+    ///
+    /// it can be installed once, a second call fails with `AlreadyExists`.
+    /// the handler replaces the one of the signal, e.g. the core dump of
+    /// `SIGQUIT`
+    pub fn install_signal_dump(signal: Signal, path_template: &str) -> io::Result<()> {
+        if PIPE.load(Ordering::Acquire) >= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the signal dump is already installed",
+            ));
+        }
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for fd in fds.iter() {
+            unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) };
+        let mut rx = unsafe { File::from_raw_fd(fds[0]) };
+        if PIPE
+            .compare_exchange(-1, fds[1], Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            unsafe { libc::close(fds[1]) };
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "the signal dump is already installed",
+            ));
+        }
+
+        let template = path_template.to_owned();
+        thread::Builder::new()
+            .name(thread_names::name("diagnostics"))
+            .spawn(move || {
+                let mut buf = [0u8; 64];
+                // each read takes all the pending signals
+                while let Ok(n) = rx.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    write_dump(&template);
+                }
+            })?;
+
+        let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+        sa.sa_sigaction = on_signal as libc::sighandler_t;
+        sa.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut sa.sa_mask) };
+        if unsafe { libc::sigaction(signal as libc::c_int, &sa, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_json() {
+        co!(|| ()).join().unwrap();
+        let mut out = Vec::new();
+        write_snapshot(&mut out).unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(v["version"], 1);
+        assert!(v["stats"]["active_workers"].as_u64().unwrap() >= 1);
+        let workers = v["workers"].as_array().unwrap();
+        assert!(!workers.is_empty());
+        assert_eq!(workers[0]["state"], "active");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signal_dump() {
+        use std::time::{Duration, Instant};
+
+        let dir = tempdir::TempDir::new("mco-dump").unwrap();
+        let template = dir.path().join("dump-{pid}-{time}.json");
+        install_signal_dump(Signal::SIGUSR2, template.to_str().unwrap()).unwrap();
+        let again = install_signal_dump(Signal::SIGUSR2, "unused");
+        assert_eq!(again.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+
+        unsafe { libc::raise(libc::SIGUSR2) };
+        let start = Instant::now();
+        let path = loop {
+            let found = std::fs::read_dir(dir.path())
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .find(|p| p.extension().map_or(false, |e| e == "json"));
+            match found {
+                Some(p) => break p,
+                None if start.elapsed() < Duration::from_secs(5) => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                None => panic!("no dump is written"),
+            }
+        };
+        // the file may still be written
+        let v = loop {
+            let data = std::fs::read(&path).unwrap();
+            if data.ends_with(b"\n") {
+                break serde_json::from_slice::<serde_json::Value>(&data).unwrap();
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(v["version"], 1);
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(&format!("dump-{}-", std::process::id())));
+    }
+}
@@ -0,0 +1,310 @@
+//! one error type for the whole crate
+//!
+//! the modules keep their own errors for the precise handling, they all
+//! convert to `mco::Error` so that an application can mix them with `?`:
+//! ```
+//! use mco::std::sync::channel::channel;
+//!
+//! fn handler() -> Result<u32, mco::Error> {
+//!     let (tx, rx) = channel();
+//!     tx.send(1)?;
+//!     let h = mco::co!(move || rx.recv());
+//!     let v = h.join()??;
+//!     Ok(v)
+//! }
+//! assert_eq!(handler().unwrap(), 1);
+//! ```
+
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+use crate::coroutine::{ScopeTimedOut, SpawnError};
+use crate::cqueue::PollError;
+use crate::runtime::ResizeError;
+use crate::std::context::ContextError;
+use crate::std::errors;
+use crate::std::sync::channel::{AckError, RecvCtxError, SendAllError, SendCtxError};
+
+/// why a channel operation failed, see `Error::Channel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ChannelError {
+    /// the channel is empty and all the senders are gone
+    Disconnected,
+    /// all the receivers are gone or the channel is closed, the message is dropped
+    Closed,
+    /// the channel is empty
+    Empty,
+    /// the bounded channel is full
+    Full,
+    /// the receiver dropped the acknowledgement of `Sender::send_and_wait`
+    Unacknowledged,
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelError::Disconnected => f.write_str("receiving on a closed channel"),
+            ChannelError::Closed => f.write_str("sending on a closed channel"),
+            ChannelError::Empty => f.write_str("receiving on an empty channel"),
+            ChannelError::Full => f.write_str("sending on a full channel"),
+            ChannelError::Unacknowledged => {
+                f.write_str("the message is dropped without acknowledgement")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+/// the error of the crate, all the module errors convert to it
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// a channel operation failed
+    Channel(ChannelError),
+    /// a timeout or a deadline is passed
+    Timeout,
+    /// the coroutine or the context is canceled
+    Canceled,
+    /// an io error
+    Io(io::Error),
+    /// the coroutine can't be spawned
+    Spawn(SpawnError),
+    /// the workers can't be resized
+    Resize(ResizeError),
+    /// the coroutine panicked, with the panic message
+    Panic(String),
+    /// the text errors of `mco::std`, like the parse errors of `Time::parse`
+    Other(errors::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Channel(e) => fmt::Display::fmt(e, f),
+            Error::Timeout => f.write_str("timed out"),
+            Error::Canceled => f.write_str("canceled"),
+            Error::Io(e) => fmt::Display::fmt(e, f),
+            Error::Spawn(e) => fmt::Display::fmt(e, f),
+            Error::Resize(e) => fmt::Display::fmt(e, f),
+            Error::Panic(msg) => write!(f, "the coroutine panicked: {}", msg),
+            Error::Other(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Channel(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Spawn(e) => Some(e),
+            Error::Resize(e) => Some(e),
+            Error::Other(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// return true if it's a timeout, including the io timeouts
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            Error::Timeout => true,
+            Error::Io(e) => e.kind() == io::ErrorKind::TimedOut,
+            _ => false,
+        }
+    }
+}
+
+impl From<ChannelError> for Error {
+    fn from(e: ChannelError) -> Self {
+        Error::Channel(e)
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(_: RecvError) -> Self {
+        Error::Channel(ChannelError::Disconnected)
+    }
+}
+
+impl From<TryRecvError> for Error {
+    fn from(e: TryRecvError) -> Self {
+        match e {
+            TryRecvError::Empty => Error::Channel(ChannelError::Empty),
+            TryRecvError::Disconnected => Error::Channel(ChannelError::Disconnected),
+        }
+    }
+}
+
+impl From<RecvTimeoutError> for Error {
+    fn from(e: RecvTimeoutError) -> Self {
+        match e {
+            RecvTimeoutError::Timeout => Error::Timeout,
+            RecvTimeoutError::Disconnected => Error::Channel(ChannelError::Disconnected),
+        }
+    }
+}
+
+impl<T> From<SendError<T>> for Error {
+    fn from(_: SendError<T>) -> Self {
+        Error::Channel(ChannelError::Closed)
+    }
+}
+
+impl<T> From<TrySendError<T>> for Error {
+    fn from(e: TrySendError<T>) -> Self {
+        match e {
+            TrySendError::Full(_) => Error::Channel(ChannelError::Full),
+            TrySendError::Disconnected(_) => Error::Channel(ChannelError::Closed),
+        }
+    }
+}
+
+impl<T> From<SendAllError<T>> for Error {
+    fn from(_: SendAllError<T>) -> Self {
+        Error::Channel(ChannelError::Closed)
+    }
+}
+
+impl From<RecvCtxError> for Error {
+    fn from(e: RecvCtxError) -> Self {
+        match e {
+            RecvCtxError::Disconnected => Error::Channel(ChannelError::Disconnected),
+            RecvCtxError::Done(e) => e.into(),
+        }
+    }
+}
+
+impl<T> From<SendCtxError<T>> for Error {
+    fn from(e: SendCtxError<T>) -> Self {
+        match e {
+            SendCtxError::Disconnected(_) => Error::Channel(ChannelError::Closed),
+            SendCtxError::Done(e) => e.into(),
+        }
+    }
+}
+
+impl<T> From<AckError<T>> for Error {
+    fn from(e: AckError<T>) -> Self {
+        match e {
+            AckError::Disconnected(_) => Error::Channel(ChannelError::Closed),
+            AckError::Dropped => Error::Channel(ChannelError::Unacknowledged),
+            AckError::Timeout => Error::Timeout,
+        }
+    }
+}
+
+impl From<ContextError> for Error {
+    fn from(e: ContextError) -> Self {
+        match e {
+            ContextError::Canceled => Error::Canceled,
+            ContextError::DeadlineExceeded => Error::Timeout,
+        }
+    }
+}
+
+impl From<PollError> for Error {
+    fn from(e: PollError) -> Self {
+        match e {
+            PollError::Timeout => Error::Timeout,
+            // no select coroutine would send any more event
+            PollError::Finished => Error::Channel(ChannelError::Disconnected),
+        }
+    }
+}
+
+impl From<ScopeTimedOut> for Error {
+    fn from(_: ScopeTimedOut) -> Self {
+        Error::Timeout
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<SpawnError> for Error {
+    fn from(e: SpawnError) -> Self {
+        Error::Spawn(e)
+    }
+}
+
+impl From<ResizeError> for Error {
+    fn from(e: ResizeError) -> Self {
+        Error::Resize(e)
+    }
+}
+
+impl From<errors::Error> for Error {
+    fn from(e: errors::Error) -> Self {
+        Error::Other(e)
+    }
+}
+
+// the panic of `JoinHandle::join`, the cancel panic is a cancellation
+impl From<Box<dyn Any + Send>> for Error {
+    fn from(p: Box<dyn Any + Send>) -> Self {
+        if let Some(mco_gen::Error::Cancel) = p.downcast_ref::<mco_gen::Error>() {
+            return Error::Canceled;
+        }
+        match p.downcast_ref::<&str>() {
+            Some(msg) => Error::Panic(msg.to_string()),
+            None => Error::Panic(
+                p.downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| "unknown panic".to_owned()),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::sync::channel::{bounded, channel};
+    use std::error::Error as _;
+    use std::time::Duration;
+
+    #[test]
+    fn question_mark() {
+        fn recv_one() -> Result<u32, Error> {
+            let (tx, rx) = bounded(1);
+            tx.try_send(1)?;
+            Ok(rx.recv_timeout(Duration::from_millis(10))?)
+        }
+        assert_eq!(recv_one().unwrap(), 1);
+
+        fn timeout() -> Result<u32, Error> {
+            let (_tx, rx) = channel::<u32>();
+            Ok(rx.recv_timeout(Duration::from_millis(10))?)
+        }
+        assert!(timeout().unwrap_err().is_timeout());
+    }
+
+    #[test]
+    fn conversions() {
+        let (tx, rx) = channel::<u32>();
+        drop(rx);
+        let e: Error = tx.send(1).unwrap_err().into();
+        assert!(matches!(e, Error::Channel(ChannelError::Closed)));
+        assert!(e.source().is_some());
+
+        let e: Error = co!(|| panic!("boom")).join().unwrap_err().into();
+        assert_eq!(e.to_string(), "the coroutine panicked: boom");
+
+        let h = co!(|| crate::coroutine::sleep(Duration::from_secs(10)));
+        h.coroutine().cancel();
+        let e: Error = h.join().unwrap_err().into();
+        assert!(matches!(e, Error::Canceled));
+
+        let e: Error = io::Error::new(io::ErrorKind::TimedOut, "timeout").into();
+        assert!(e.is_timeout());
+    }
+}
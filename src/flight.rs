@@ -0,0 +1,282 @@
+//! the flight recorder of the scheduler events, see
+//! `runtime::flight_recorder_dump`
+//!
+//! each worker records to a ring of its own, the other threads (the timer
+//! thread, the threads that unpark the coroutines) and the workers beyond
+//! `WORKER_RINGS` share one more ring. an event takes a slot by bumping the
+//! head of its ring and is written between two stamps of the slot, so a
+//! record is wait free and doesn't allocate. a dump skips the slots that are
+//! half written, and the slots that are overwritten while it reads them
+//!
+//! the time of an event is the coarse monotonic clock, with the resolution
+//! of the timer tick of the kernel
+
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use crate::coroutine_impl::{co_id, CoroutineImpl};
+use crate::local::get_co_local_data;
+use crate::scheduler::worker_id;
+use once_cell::sync::OnceCell;
+
+/// the events that each ring keeps, the older ones are overwritten
+pub const FLIGHT_SLOTS: usize = 1024;
+// the workers with a ring of their own
+const WORKER_RINGS: usize = 32;
+// the worker of the events that are not recorded by a worker
+const NO_WORKER: u16 = !1usize as u16;
+// the arg of a steal from the global queue
+pub(crate) const GLOBAL_QUEUE: u32 = u32::MAX;
+
+// allocated by the first event, the dump doesn't allocate them
+static RINGS: OnceCell<Box<[Ring]>> = OnceCell::new();
+
+/// the kind of a recorded event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event {
+    // a new coroutine
+    Spawn = 1,
+    // the coroutine parks, the arg is 1 with a timeout
+    Park,
+    // the coroutine is made ready, the arg is 1 when it runs on the caller
+    Unpark,
+    // the coroutine is stolen, the arg is the victim worker or `GLOBAL_QUEUE`
+    Steal,
+    // the timeout of the coroutine fires
+    Timer,
+}
+
+impl Event {
+    fn from_u8(v: u8) -> Option<Event> {
+        Some(match v {
+            1 => Event::Spawn,
+            2 => Event::Park,
+            3 => Event::Unpark,
+            4 => Event::Steal,
+            5 => Event::Timer,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    // the seq of the event plus one, 0 while it's written
+    stamp: AtomicU64,
+    time: AtomicU64,
+    // the kind, the worker and the arg
+    meta: AtomicU64,
+    co: AtomicU64,
+}
+
+struct Ring {
+    head: AtomicU64,
+    slots: Box<[Slot]>,
+}
+
+impl Ring {
+    fn new() -> Self {
+        Ring {
+            head: AtomicU64::new(0),
+            slots: (0..FLIGHT_SLOTS).map(|_| Slot::default()).collect(),
+        }
+    }
+
+    // the event of `seq`, if it's still in the ring and is written
+    fn read(&self, seq: u64) -> Option<Record> {
+        let slot = &self.slots[seq as usize % FLIGHT_SLOTS];
+        let stamp = slot.stamp.load(Ordering::Acquire);
+        let time = slot.time.load(Ordering::Relaxed);
+        let meta = slot.meta.load(Ordering::Relaxed);
+        let co = slot.co.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        if stamp != seq + 1 || slot.stamp.load(Ordering::Relaxed) != stamp {
+            return None;
+        }
+        Some(Record {
+            time,
+            kind: Event::from_u8(meta as u8)?,
+            worker: (meta >> 8) as u16,
+            arg: (meta >> 32) as u32,
+            co,
+        })
+    }
+
+    // the seqs of the events that may still be in the ring
+    fn seqs(&self) -> std::ops::Range<u64> {
+        let head = self.head.load(Ordering::Acquire);
+        head.saturating_sub(FLIGHT_SLOTS as u64)..head
+    }
+}
+
+struct Record {
+    time: u64,
+    kind: Event,
+    worker: u16,
+    arg: u32,
+    co: u64,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Event::Spawn => write!(f, "spawn #{}", self.co),
+            Event::Park if self.arg == 1 => write!(f, "park #{} with a timeout", self.co),
+            Event::Park => write!(f, "park #{}", self.co),
+            Event::Unpark if self.arg == 1 => write!(f, "unpark #{} and run it", self.co),
+            Event::Unpark => write!(f, "unpark #{}", self.co),
+            Event::Steal if self.arg == GLOBAL_QUEUE => {
+                write!(f, "steal #{} from the global queue", self.co)
+            }
+            Event::Steal => write!(f, "steal #{} from worker {}", self.co, self.arg),
+            Event::Timer => write!(f, "timeout of #{}", self.co),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn now() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_COARSE, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[cfg(not(target_os = "linux"))]
+fn now() -> u64 {
+    static BASE: once_cell::sync::Lazy<std::time::Instant> =
+        once_cell::sync::Lazy::new(std::time::Instant::now);
+    BASE.elapsed().as_nanos() as u64
+}
+
+fn rings() -> &'static [Ring] {
+    RINGS.get_or_init(|| (0..=WORKER_RINGS).map(|_| Ring::new()).collect())
+}
+
+/// record an event of the coroutine with the id `co`
+#[inline]
+pub(crate) fn record(kind: Event, co: u64, arg: u32) {
+    let worker = worker_id();
+    let ring = &rings()[worker.min(WORKER_RINGS)];
+    let seq = ring.head.fetch_add(1, Ordering::Relaxed);
+    let slot = &ring.slots[seq as usize % FLIGHT_SLOTS];
+    slot.stamp.store(0, Ordering::Relaxed);
+    fence(Ordering::Release);
+    slot.time.store(now(), Ordering::Relaxed);
+    let meta = kind as u64 | (worker as u16 as u64) << 8 | (arg as u64) << 32;
+    slot.meta.store(meta, Ordering::Relaxed);
+    slot.co.store(co, Ordering::Relaxed);
+    slot.stamp.store(seq + 1, Ordering::Release);
+}
+
+/// record an event of `co`
+#[inline]
+pub(crate) fn record_co(kind: Event, co: &CoroutineImpl, arg: u32) {
+    record(kind, co_id(co).as_u64(), arg);
+}
+
+/// record an event of the running coroutine, if any
+#[inline]
+pub(crate) fn record_current(kind: Event, arg: u32) {
+    if let Some(local) = get_co_local_data() {
+        let id = unsafe { local.as_ref() }.get_co().id();
+        record(kind, id.as_u64(), arg);
+    }
+}
+
+/// write the recorded scheduler events to `w`, the oldest first, ring by ring
+///
+/// each event is the spawn, the park, the unpark, the steal or the timeout
+/// of a coroutine, with the coroutine id and the time before the newest
+/// event of all the rings. each ring keeps the last `FLIGHT_SLOTS` events of
+/// its worker, the workers go on recording while it runs
+///
+/// it doesn't allocate and takes no lock, so it can be called from a panic
+/// hook or a signal handler, as long as `w` doesn't allocate either
+///
+/// ```
+/// let h = mco::co!(|| ());
+/// let id = h.id();
+/// h.join().unwrap();
+/// let mut out = Vec::new();
+/// mco::runtime::flight_recorder_dump(&mut out).unwrap();
+/// let out = String::from_utf8(out).unwrap();
+/// assert!(out.contains(&format!("spawn #{}", id)));
+/// ```
+pub fn flight_recorder_dump<W: Write>(w: &mut W) -> io::Result<()> {
+    let rings = match RINGS.get() {
+        Some(rings) => rings,
+        None => return writeln!(w, "flight recorder: no events"),
+    };
+    let newest = rings
+        .iter()
+        .flat_map(|ring| ring.seqs().filter_map(move |seq| ring.read(seq)))
+        .map(|r| r.time)
+        .max()
+        .unwrap_or(0);
+    writeln!(w, "flight recorder, the time is before the newest event:")?;
+    for (i, ring) in rings.iter().enumerate() {
+        let seqs = ring.seqs();
+        if seqs.is_empty() {
+            continue;
+        }
+        if i < WORKER_RINGS {
+            writeln!(w, "worker {}:", i)?;
+        } else {
+            writeln!(w, "other threads:")?;
+        }
+        for r in seqs.filter_map(|seq| ring.read(seq)) {
+            let ago = newest.saturating_sub(r.time) as f64 / 1_000_000.0;
+            if i < WORKER_RINGS || r.worker == NO_WORKER {
+                writeln!(w, "  -{:.3}ms {}", ago, r)?;
+            } else {
+                writeln!(w, "  -{:.3}ms worker {} {}", ago, r.worker, r)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump() -> String {
+        let mut out = Vec::new();
+        flight_recorder_dump(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn records_the_events() {
+        let (tx, rx) = crate::std::sync::channel::channel::<()>();
+        let h = co!(move || rx.recv().unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        tx.send(()).unwrap();
+        let id = h.id();
+        h.join().unwrap();
+        let out = dump();
+        for e in ["spawn", "park", "unpark"].iter() {
+            assert!(out.contains(&format!("{} #{}", e, id)), "{}", out);
+        }
+    }
+
+    #[test]
+    fn keeps_the_last_events() {
+        // on a thread of its own the events go to the shared ring
+        let out = std::thread::spawn(|| {
+            for i in 0..FLIGHT_SLOTS as u64 * 2 {
+                record(Event::Spawn, 1 << 40 | i, 0);
+            }
+            dump()
+        })
+        .join()
+        .unwrap();
+        let last = FLIGHT_SLOTS as u64 * 2 - 1;
+        assert!(out.contains(&format!("spawn #{}\n", 1u64 << 40 | last)));
+        assert!(!out.contains(&format!("spawn #{}\n", 1u64 << 40)));
+    }
+}
@@ -0,0 +1,225 @@
+//! the generators, see `coroutine::generator`
+//!
+//! a generator runs its closure on a stack of its own like a coroutine, but
+//! it's not scheduled, the closure only runs inside `Generator::resume` and
+//! gives the control back to the caller at each `Yielder::yield_val`. so it's
+//! driven by any code, a thread or a coroutine, and the state between the
+//! values is just the locals of the closure instead of a hand written state
+//! machine
+//!
+//! # Send
+//!
+//! a `Generator` is `Send` when its closure and values are `Send` and it
+//! borrows nothing, so it can be resumed on another thread than the one
+//! that it was suspended on. as for the coroutines that move between the
+//! workers, the closure must not hold the thread bound state across a
+//! `yield_val`, like a `std::sync::MutexGuard` or a reference into a
+//! `thread_local!`, the compiler can't see the locals of a suspended stack.
+//! the `Yielder` itself never leaves the closure, it's neither `Send` nor
+//! `Sync`
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use crate::config::config;
+use mco_gen::{Gn, Scope};
+
+/// what `Generator::resume` returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GeneratorState<Y, R> {
+    /// the generator is suspended with a value
+    Yielded(Y),
+    /// the closure returned
+    Complete(R),
+}
+
+/// the handle that the closure of a generator yields with
+pub struct Yielder<'s, Y, R> {
+    scope: Scope<'s, (), GeneratorState<Y, R>>,
+    // it only works on the stack of its generator
+    _local: PhantomData<*mut ()>,
+}
+
+impl<'s, Y, R> Yielder<'s, Y, R> {
+    /// suspend the generator, `resume` returns `GeneratorState::Yielded(v)`,
+    /// the next `resume` continues from here
+    ///
+    /// when the generator is dropped here it doesn't return, the stack of the
+    /// closure is unwound instead
+    pub fn yield_val(&mut self, v: Y) {
+        self.scope.yield_with(GeneratorState::Yielded(v));
+    }
+}
+
+impl<'s, Y, R> fmt::Debug for Yielder<'s, Y, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Yielder {{ .. }}")
+    }
+}
+
+/// a resumable closure on its own stack, see `coroutine::generator`
+pub struct Generator<'a, Y, R> {
+    gen: mco_gen::Generator<'a, (), GeneratorState<Y, R>>,
+}
+
+impl<'a, Y, R> Generator<'a, Y, R> {
+    /// run the closure until its next `yield_val` or its return
+    ///
+    /// a panic of the closure is propagated to the caller, the generator is
+    /// complete after it
+    ///
+    /// # Panics
+    ///
+    /// when the generator is already complete
+    pub fn resume(&mut self) -> GeneratorState<Y, R> {
+        match self.gen.resume() {
+            Some(state) => state,
+            None => panic!("the generator is resumed after it's complete"),
+        }
+    }
+
+    /// the closure returned or panicked
+    pub fn is_complete(&self) -> bool {
+        self.gen.is_done()
+    }
+}
+
+/// the yielded values of a generator that returns nothing
+impl<'a, Y> Iterator for Generator<'a, Y, ()> {
+    type Item = Y;
+
+    fn next(&mut self) -> Option<Y> {
+        if self.is_complete() {
+            return None;
+        }
+        match self.resume() {
+            GeneratorState::Yielded(v) => Some(v),
+            GeneratorState::Complete(()) => None,
+        }
+    }
+}
+
+impl<'a, Y, R> fmt::Debug for Generator<'a, Y, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Generator")
+            .field("complete", &self.is_complete())
+            .finish()
+    }
+}
+
+/// create a generator of `f` with a stack of `config().get_stack_size()`,
+/// `f` doesn't run before the first `resume`
+///
+/// the generator has nothing to do with the scheduler, `resume` runs `f` on
+/// the caller's thread, in a coroutine as well as in a thread. a blocking
+/// call of `f` blocks the caller, in a coroutine that's a park of it. a
+/// generator that's dropped before it's complete unwinds the stack of `f`,
+/// so the locals of `f` are dropped, except when the caller's thread is
+/// already panicking, then they are leaked. see the module docs for the
+/// rules of `Send`
+///
+/// ```
+/// use mco::coroutine::{self, GeneratorState};
+///
+/// let mut g = coroutine::generator(|yielder| {
+///     yielder.yield_val(1);
+///     yielder.yield_val(2);
+///     3
+/// });
+/// assert_eq!(g.resume(), GeneratorState::Yielded(1));
+/// assert_eq!(g.resume(), GeneratorState::Yielded(2));
+/// assert_eq!(g.resume(), GeneratorState::Complete(3));
+/// assert!(g.is_complete());
+/// ```
+pub fn generator<'a, Y, R, F>(f: F) -> Generator<'a, Y, R>
+where
+    F: FnOnce(&mut Yielder<Y, R>) -> R + Send + 'a,
+    Y: Send + 'a,
+    R: Send + 'a,
+{
+    let gen = Gn::<()>::new_scoped_opt(config().get_stack_size(), move |scope| {
+        let mut yielder = Yielder {
+            scope,
+            _local: PhantomData,
+        };
+        GeneratorState::Complete(f(&mut yielder))
+    });
+    Generator { gen }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct Guard(Arc<AtomicUsize>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn drop_unwinds_suspended() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let d = dropped.clone();
+        let mut g = generator(move |y| {
+            let _guard = Guard(d);
+            for i in 0.. {
+                y.yield_val(i);
+            }
+            unreachable!()
+        });
+        assert_eq!(g.resume(), GeneratorState::<i32, ()>::Yielded(0));
+        assert_eq!(g.resume(), GeneratorState::Yielded(1));
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+        drop(g);
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+        // never started, only the closure is dropped
+        let guard = Guard(dropped.clone());
+        let g = generator(move |y| {
+            let _guard = guard;
+            y.yield_val(());
+        });
+        drop(g);
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn resume_anywhere() {
+        let mut g = generator(|y| {
+            let mut sum = 0;
+            for i in 1..=4 {
+                sum += i;
+                y.yield_val(i);
+            }
+            sum
+        });
+        assert_eq!(g.resume(), GeneratorState::Yielded(1));
+        // on another thread
+        let mut g = std::thread::spawn(move || {
+            assert_eq!(g.resume(), GeneratorState::Yielded(2));
+            g
+        })
+        .join()
+        .unwrap();
+        // in a coroutine that parks inside the generator
+        let mut g = co!(move || {
+            let mut inner = generator(|y| {
+                crate::coroutine::sleep(Duration::from_millis(1));
+                y.yield_val(());
+            });
+            assert_eq!(inner.next(), Some(()));
+            assert_eq!(g.resume(), GeneratorState::Yielded(3));
+            g
+        })
+        .join()
+        .unwrap();
+        assert_eq!(g.resume(), GeneratorState::Yielded(4));
+        assert_eq!(g.resume(), GeneratorState::Complete(10));
+        assert!(g.is_complete());
+    }
+}
@@ -0,0 +1,117 @@
+//! the process wide hooks that run when a coroutine starts and exits
+//!
+//! the hooks run in the coroutine context on the worker thread, `on_start`
+//! right before the body and `on_exit` right after it returns, panics or is
+//! canceled. a coroutine sees the hooks that are registered when it starts,
+//! so each `on_start` is paired with exactly one `on_exit`
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::{current, Coroutine};
+
+/// the start hook, it gets the handle of the started coroutine
+pub type StartHook = Box<dyn Fn(&Coroutine) + Send + Sync>;
+
+/// the exit hook, it gets the handle of the exited coroutine and how the body ended
+pub type ExitHook = Box<dyn Fn(&Coroutine, &Exit) + Send + Sync>;
+
+/// the hooks registered by [`add_hooks`]
+///
+/// [`add_hooks`]: ./fn.add_hooks.html
+#[derive(Default)]
+pub struct Hooks {
+    pub on_start: Option<StartHook>,
+    pub on_exit: Option<ExitHook>,
+}
+
+/// how the body of a coroutine ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exit {
+    /// the time from `on_start` to the end of the body
+    pub elapsed: Duration,
+    /// the body panicked or was canceled
+    pub panicked: bool,
+}
+
+static HOOKS: Mutex<Vec<Arc<Hooks>>> = parking_lot::const_mutex(Vec::new());
+// fast check for the spawn path
+static HAS_HOOKS: AtomicBool = AtomicBool::new(false);
+
+/// register the hooks for all the coroutines that start after this call,
+/// the hooks run in the order of the registration
+///
+/// a panic in a hook is caught and logged, it doesn't affect the coroutine
+///
+/// ```
+/// use std::sync::atomic::{AtomicIsize, Ordering};
+/// use std::sync::Arc;
+/// use mco::coroutine::{self, Hooks};
+///
+/// let live = Arc::new(AtomicIsize::new(0));
+/// let (l1, l2) = (live.clone(), live.clone());
+/// coroutine::add_hooks(Hooks {
+///     on_start: Some(Box::new(move |_| {
+///         l1.fetch_add(1, Ordering::Relaxed);
+///     })),
+///     on_exit: Some(Box::new(move |_, _| {
+///         l2.fetch_sub(1, Ordering::Relaxed);
+///     })),
+/// });
+/// ```
+pub fn add_hooks(hooks: Hooks) {
+    HOOKS.lock().push(Arc::new(hooks));
+    HAS_HOOKS.store(true, Ordering::Release);
+}
+
+// run the exit hooks when dropped, also on unwind
+pub(crate) struct HookGuard {
+    hooks: Vec<Arc<Hooks>>,
+    start: Instant,
+}
+
+impl HookGuard {
+    /// run the start hooks, nothing is done if no hooks are registered
+    #[inline]
+    pub fn enter() -> Option<HookGuard> {
+        if !HAS_HOOKS.load(Ordering::Acquire) {
+            return None;
+        }
+        let hooks = HOOKS.lock().clone();
+        let co = current();
+        for h in hooks.iter() {
+            if let Some(f) = h.on_start.as_ref() {
+                guarded("on_start", &co, || f(&co));
+            }
+        }
+        Some(HookGuard {
+            hooks,
+            start: Instant::now(),
+        })
+    }
+}
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        let exit = Exit {
+            elapsed: self.start.elapsed(),
+            panicked: thread::panicking(),
+        };
+        let co = current();
+        for h in self.hooks.iter() {
+            if let Some(f) = h.on_exit.as_ref() {
+                guarded("on_exit", &co, || f(&co, &exit));
+            }
+        }
+    }
+}
+
+fn guarded<F: FnOnce()>(kind: &str, co: &Coroutine, f: F) {
+    if panic::catch_unwind(AssertUnwindSafe(f)).is_err() {
+        error!("coroutine {} hook panicked, coroutine={:?}", kind, co);
+    }
+}
@@ -0,0 +1,250 @@
+//! the byte streams over the channels
+//!
+//! `ChannelWriter` cuts the written bytes into chunks and sends them to the
+//! channel, `ChannelReader` reads them back as a continuous stream. they block
+//! through the channel, so a coroutine is parked and a thread is blocked. use
+//! a bounded channel to hold back a writer that is faster than the reader.
+//! the chunks are `Vec<u8>` by default, use `Bytes` to share them without
+//! copying, e.g. when the receiver forwards them to many sockets
+//! for example:
+//! ```
+//! use std::io::{Read, Write};
+//! use mco::io::{ChannelReader, ChannelWriter};
+//! use mco::std::sync::channel::bounded;
+//!
+//! let (tx, rx) = bounded::<Vec<u8>>(4);
+//! let h = mco::co!(move || {
+//!     let mut w = ChannelWriter::new(tx);
+//!     w.write_all(b"hello world").unwrap();
+//! });
+//! let mut s = String::new();
+//! ChannelReader::new(rx).read_to_string(&mut s).unwrap();
+//! assert_eq!(s, "hello world");
+//! h.join().unwrap();
+//! ```
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::std::sync::channel::{Receiver, Sender};
+
+// the default chunk size of `ChannelWriter`
+const DEFAULT_CHUNK: usize = 8 * 1024;
+
+/// read the chunks of a channel as a byte stream
+///
+/// a chunk is kept across the reads until it's consumed, the read returns
+/// `Ok(0)` once all the senders are gone and the chunks are drained
+#[derive(Debug)]
+pub struct ChannelReader<B = Vec<u8>> {
+    rx: Receiver<B>,
+    buf: B,
+    pos: usize,
+}
+
+impl<B: AsRef<[u8]> + Default> ChannelReader<B> {
+    pub fn new(rx: Receiver<B>) -> Self {
+        ChannelReader {
+            rx,
+            buf: B::default(),
+            pos: 0,
+        }
+    }
+
+    /// the receiver, the bytes buffered by the reader are lost
+    pub fn into_inner(self) -> Receiver<B> {
+        self.rx
+    }
+}
+
+impl<B: AsRef<[u8]> + Default> Read for ChannelReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let data = self.fill_buf()?;
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<B: AsRef<[u8]> + Default> BufRead for ChannelReader<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // skip the empty chunks, they are not the end of the stream
+        while self.pos == self.buf.as_ref().len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(&[]),
+            }
+        }
+        Ok(&self.buf.as_ref()[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.as_ref().len());
+    }
+}
+
+/// write a byte stream to a channel in chunks
+///
+/// a chunk is sent when it's full or flushed, the rest is flushed when the
+/// writer is dropped. the write fails with `BrokenPipe` once all the
+/// receivers are gone
+#[derive(Debug)]
+pub struct ChannelWriter<B: From<Vec<u8>> = Vec<u8>> {
+    tx: Sender<B>,
+    buf: Vec<u8>,
+    chunk: usize,
+}
+
+impl<B: From<Vec<u8>>> ChannelWriter<B> {
+    /// the writer with 8KB chunks
+    pub fn new(tx: Sender<B>) -> Self {
+        Self::with_chunk_size(tx, DEFAULT_CHUNK)
+    }
+
+    /// the writer with the max chunk size, 0 is taken as 1
+    pub fn with_chunk_size(tx: Sender<B>, chunk: usize) -> Self {
+        let chunk = chunk.max(1);
+        ChannelWriter {
+            tx,
+            buf: Vec::with_capacity(chunk),
+            chunk,
+        }
+    }
+
+    /// the max chunk size
+    pub fn chunk_size(&self) -> usize {
+        self.chunk
+    }
+
+    /// flush and return the sender
+    pub fn into_inner(mut self) -> io::Result<Sender<B>> {
+        self.flush()?;
+        // release the buffer, only the sender is left to move out
+        self.buf = Vec::new();
+        let me = std::mem::ManuallyDrop::new(self);
+        Ok(unsafe { std::ptr::read(&me.tx) })
+    }
+
+    fn send_buf(&mut self) -> io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(self.chunk));
+        self.tx
+            .send(B::from(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the channel is closed"))
+    }
+}
+
+impl<B: From<Vec<u8>>> Write for ChannelWriter<B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buf.len() == self.chunk {
+            self.send_buf()?;
+        }
+        let n = buf.len().min(self.chunk - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == self.chunk {
+            self.send_buf()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.send_buf()
+    }
+}
+
+impl<B: From<Vec<u8>>> Drop for ChannelWriter<B> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::rand;
+    use crate::std::sync::channel::bounded;
+
+    #[test]
+    fn round_trip_frames() {
+        // the frames are a u32 length and the bytes
+        let frames: Vec<Vec<u8>> = (0..2000)
+            .map(|i| vec![i as u8; rand::range(0..3000)])
+            .collect();
+        let expect = frames.clone();
+
+        let (tx, rx) = bounded::<Vec<u8>>(2);
+        let h = co!(move || {
+            let mut w = ChannelWriter::with_chunk_size(tx, 1021);
+            for f in frames {
+                w.write_all(&(f.len() as u32).to_le_bytes()).unwrap();
+                w.write_all(&f).unwrap();
+            }
+        });
+
+        let mut r = ChannelReader::new(rx);
+        let mut got = Vec::new();
+        let mut len = [0; 4];
+        loop {
+            match r.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => panic!("{}", e),
+            }
+            let mut f = vec![0; u32::from_le_bytes(len) as usize];
+            r.read_exact(&mut f).unwrap();
+            got.push(f);
+        }
+        h.join().unwrap();
+        assert!(expect.iter().map(|f| f.len()).sum::<usize>() > 2 * 1024 * 1024);
+        assert_eq!(got, expect);
+    }
+
+    #[test]
+    fn flush_and_close() {
+        let (tx, rx) = bounded::<Vec<u8>>(4);
+        let mut w = ChannelWriter::with_chunk_size(tx, 4);
+        w.write_all(b"abcdef").unwrap();
+        // only the full chunk is sent
+        assert_eq!(rx.try_recv().unwrap(), b"abcd");
+        assert!(rx.try_recv().is_err());
+        w.flush().unwrap();
+        assert_eq!(rx.try_recv().unwrap(), b"ef");
+
+        let tx = w.into_inner().unwrap();
+        tx.send(Vec::new()).unwrap();
+        tx.send(b"xyz".to_vec()).unwrap();
+        drop(tx);
+        let mut r = ChannelReader::new(rx);
+        let mut buf = [0; 2];
+        assert_eq!(r.read(&mut buf).unwrap(), 2);
+        assert_eq!(r.read(&mut buf).unwrap(), 1);
+        assert_eq!(r.read(&mut buf).unwrap(), 0);
+
+        let (tx, rx) = bounded::<Vec<u8>>(4);
+        drop(rx);
+        let mut w = ChannelWriter::with_chunk_size(tx, 2);
+        let e = w.write_all(b"abc").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn bytes_chunks() {
+        use crate::std::bytes::Bytes;
+
+        let (tx, rx) = bounded::<Bytes>(4);
+        let mut w = ChannelWriter::with_chunk_size(tx, 3);
+        w.write_all(b"hello").unwrap();
+        drop(w);
+        let mut s = String::new();
+        ChannelReader::new(rx).read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+    }
+}
@@ -0,0 +1,834 @@
+//! the framed codecs over a blocking stream
+//!
+//! `LengthDelimited` reads and writes the frames that start with their
+//! length, `LinesCodec` the lines that end with `\n`. both block the calling
+//! coroutine in the reads and writes of the stream, and reuse their buffers
+//! across the frames
+//!
+//! the timeouts are those of the stream, e.g. `TcpStream::set_read_timeout`
+//! or an `IdleTimeout`. a read or a write that fails with `WouldBlock`,
+//! `TimedOut` or `Interrupted` keeps what is done so far, calling it again
+//! goes on with the same frame. after any other error the stream is out of
+//! step with the frames and the codec should be dropped
+//!
+//! ```no_run
+//! use mco::io::codec::LengthDelimited;
+//! use mco::net::TcpStream;
+//!
+//! let s = TcpStream::connect("127.0.0.1:8080").unwrap();
+//! let mut framed = LengthDelimited::new(s, 1024 * 1024);
+//! framed.write_frame(b"hello").unwrap();
+//! while let Some(frame) = framed.read_frame().unwrap() {
+//!     println!("{} bytes", frame.len());
+//! }
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str;
+
+use super::write_all_progress;
+
+// the least that a read asks the stream for
+const CHUNK: usize = 4096;
+
+/// the max payload of a `LengthDelimited` frame, unless set by the builder
+pub const DEFAULT_MAX_FRAME: usize = 8 * 1024 * 1024;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+// the bytes read from the stream and not yet taken
+struct ReadBuf {
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl ReadBuf {
+    fn new() -> Self {
+        ReadBuf {
+            buf: Vec::new(),
+            start: 0,
+            end: 0,
+        }
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    // read at least one byte for the `want` more bytes, 0 on eof
+    fn fill<R: Read>(&mut self, r: &mut R, want: usize) -> io::Result<usize> {
+        if self.start == self.end {
+            self.start = 0;
+            self.end = 0;
+        }
+        let need = want.max(CHUNK);
+        if self.buf.len() - self.end < need {
+            // move the rest to the front before growing
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+            if self.buf.len() - self.end < need {
+                self.buf.resize(self.end + need, 0);
+            }
+        }
+        loop {
+            match r.read(&mut self.buf[self.end..]) {
+                Ok(n) => {
+                    self.end += n;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+// a frame that is written out, what is left of it is written first next time
+struct WriteBuf {
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl WriteBuf {
+    fn new() -> Self {
+        WriteBuf {
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn write_out<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        if let Err(e) = write_all_progress(w, &self.buf[self.pos..]) {
+            self.pos += e.bytes_completed();
+            return Err(e.into());
+        }
+        self.buf.clear();
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+/// the length field of a `LengthDelimited` frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthHeader {
+    /// 2 bytes
+    U16,
+    /// 4 bytes
+    U32,
+    /// the unsigned LEB128 of protobuf, 1 to 10 bytes
+    Varint,
+}
+
+/// the options of a `LengthDelimited`, see `LengthDelimited::builder`
+///
+/// a frame on the wire is made of the `length_offset` bytes of its prefix,
+/// the length field and the payload. the payload is the value of the length
+/// field plus the `length_adjustment` bytes long, a frame is the prefix and
+/// the payload for `read_frame` and `write_frame`
+///
+/// ```
+/// use mco::io::codec::{LengthDelimited, LengthHeader};
+///
+/// // a type byte, then a u16 length that counts itself
+/// let mut framed = LengthDelimited::builder()
+///     .header(LengthHeader::U16)
+///     .length_offset(1)
+///     .length_adjustment(-2)
+///     .max_frame(1024)
+///     .build(Vec::new());
+/// framed.write_frame(b"\x07abc").unwrap();
+/// assert_eq!(framed.get_ref(), b"\x07\x00\x05abc");
+/// ```
+#[derive(Debug, Clone)]
+pub struct LengthDelimitedBuilder {
+    header: LengthHeader,
+    big_endian: bool,
+    offset: usize,
+    adjustment: isize,
+    max_frame: usize,
+}
+
+impl LengthDelimitedBuilder {
+    /// the default options, the same as `LengthDelimited::builder`
+    pub fn new() -> Self {
+        LengthDelimitedBuilder::default()
+    }
+
+    /// the length field, it's `U32` by default
+    pub fn header(mut self, header: LengthHeader) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// the fixed size length fields are big endian by default
+    pub fn little_endian(mut self) -> Self {
+        self.big_endian = false;
+        self
+    }
+
+    /// the bytes before the length field, they are the start of the frame
+    pub fn length_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// what is added to the length field to get the payload length, e.g.
+    /// minus the size of the header when the length counts it
+    pub fn length_adjustment(mut self, adjustment: isize) -> Self {
+        self.adjustment = adjustment;
+        self
+    }
+
+    /// the max payload, a longer frame fails with `InvalidData` when it's
+    /// read and with `InvalidInput` when it's written
+    pub fn max_frame(mut self, max_frame: usize) -> Self {
+        self.max_frame = max_frame;
+        self
+    }
+
+    /// wrap the stream
+    pub fn build<S>(self, stream: S) -> LengthDelimited<S> {
+        LengthDelimited {
+            inner: stream,
+            opts: self,
+            rbuf: ReadBuf::new(),
+            wbuf: WriteBuf::new(),
+        }
+    }
+
+    fn max_field(&self) -> u64 {
+        match self.header {
+            LengthHeader::U16 => u16::MAX as u64,
+            LengthHeader::U32 => u32::MAX as u64,
+            LengthHeader::Varint => u64::MAX,
+        }
+    }
+
+    // the length field and the payload length of the frame at the start of
+    // `data`, or the bytes that are needed for them
+    fn decode(&self, data: &[u8]) -> io::Result<Decoded> {
+        let at = self.offset;
+        let (field, len) = match self.header {
+            LengthHeader::U16 => match data.get(at..at + 2) {
+                Some(b) => {
+                    let b = [b[0], b[1]];
+                    let field = if self.big_endian {
+                        u16::from_be_bytes(b)
+                    } else {
+                        u16::from_le_bytes(b)
+                    };
+                    (field as u64, 2)
+                }
+                None => return Ok(Decoded::Need(at + 2)),
+            },
+            LengthHeader::U32 => match data.get(at..at + 4) {
+                Some(b) => {
+                    let b = [b[0], b[1], b[2], b[3]];
+                    let field = if self.big_endian {
+                        u32::from_be_bytes(b)
+                    } else {
+                        u32::from_le_bytes(b)
+                    };
+                    (field as u64, 4)
+                }
+                None => return Ok(Decoded::Need(at + 4)),
+            },
+            LengthHeader::Varint => {
+                let mut field = 0u64;
+                let mut i = 0;
+                loop {
+                    let b = match data.get(at + i) {
+                        Some(&b) => b,
+                        None => return Ok(Decoded::Need(at + i + 1)),
+                    };
+                    if i == 9 && b > 1 {
+                        return Err(invalid_data("varint length overflows u64".to_owned()));
+                    }
+                    field |= ((b & 0x7f) as u64) << (7 * i);
+                    i += 1;
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                }
+                (field, i)
+            }
+        };
+        let payload = field as i128 + self.adjustment as i128;
+        if payload < 0 {
+            return Err(invalid_data(format!(
+                "length field {} with the adjustment {} is negative",
+                field, self.adjustment
+            )));
+        }
+        if payload > self.max_frame as i128 {
+            return Err(invalid_data(format!(
+                "frame of {} bytes is larger than the max {}",
+                payload, self.max_frame
+            )));
+        }
+        Ok(Decoded::Frame {
+            head: at + len,
+            payload: payload as usize,
+        })
+    }
+
+    fn encode(&self, field: u64, out: &mut Vec<u8>) {
+        match self.header {
+            LengthHeader::U16 if self.big_endian => {
+                out.extend_from_slice(&(field as u16).to_be_bytes())
+            }
+            LengthHeader::U16 => out.extend_from_slice(&(field as u16).to_le_bytes()),
+            LengthHeader::U32 if self.big_endian => {
+                out.extend_from_slice(&(field as u32).to_be_bytes())
+            }
+            LengthHeader::U32 => out.extend_from_slice(&(field as u32).to_le_bytes()),
+            LengthHeader::Varint => {
+                let mut v = field;
+                while v >= 0x80 {
+                    out.push(v as u8 | 0x80);
+                    v >>= 7;
+                }
+                out.push(v as u8);
+            }
+        }
+    }
+}
+
+impl Default for LengthDelimitedBuilder {
+    fn default() -> Self {
+        LengthDelimitedBuilder {
+            header: LengthHeader::U32,
+            big_endian: true,
+            offset: 0,
+            adjustment: 0,
+            max_frame: DEFAULT_MAX_FRAME,
+        }
+    }
+}
+
+enum Decoded {
+    // the bytes that are needed in all, at least
+    Need(usize),
+    Frame { head: usize, payload: usize },
+}
+
+/// the frames that start with their length, a 4 byte big endian length by
+/// default, see `LengthDelimitedBuilder` for the others
+pub struct LengthDelimited<S> {
+    inner: S,
+    opts: LengthDelimitedBuilder,
+    rbuf: ReadBuf,
+    wbuf: WriteBuf,
+}
+
+impl LengthDelimited<()> {
+    /// the options of a new codec
+    pub fn builder() -> LengthDelimitedBuilder {
+        LengthDelimitedBuilder::default()
+    }
+}
+
+impl<S> LengthDelimited<S> {
+    /// wrap the stream, with a 4 byte big endian length and at most
+    /// `max_frame` bytes of payload
+    pub fn new(stream: S, max_frame: usize) -> Self {
+        LengthDelimited::builder()
+            .max_frame(max_frame)
+            .build(stream)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// unwrap the stream, the bytes read ahead and the rest of a frame that
+    /// is not yet written are lost
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// the bytes that are read ahead of the frames
+    pub fn read_buffer(&self) -> &[u8] {
+        self.rbuf.data()
+    }
+}
+
+impl<S: Read> LengthDelimited<S> {
+    /// read the next frame, `None` on eof between the frames
+    ///
+    /// a frame that is larger than the max fails with `InvalidData` as soon
+    /// as its length is read, the eof in a frame with `UnexpectedEof`
+    pub fn read_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.read_frame_ref()?.map(|f| f.to_vec()))
+    }
+
+    /// read the next frame into the buffer of the codec, the same as
+    /// `read_frame` without copying it out
+    pub fn read_frame_ref(&mut self) -> io::Result<Option<&[u8]>> {
+        loop {
+            let avail = self.rbuf.end - self.rbuf.start;
+            let need = match self.opts.decode(self.rbuf.data())? {
+                Decoded::Frame { head, payload } if avail >= head + payload => {
+                    let start = self.rbuf.start;
+                    let offset = self.opts.offset;
+                    // move the prefix next to the payload
+                    let from = start + head - offset;
+                    self.rbuf.buf.copy_within(start..start + offset, from);
+                    self.rbuf.start += head + payload;
+                    return Ok(Some(&self.rbuf.buf[from..start + head + payload]));
+                }
+                Decoded::Frame { head, payload } => head + payload,
+                Decoded::Need(need) => need,
+            };
+            if self.rbuf.fill(&mut self.inner, need - avail)? == 0 {
+                if avail == 0 {
+                    return Ok(None);
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("eof after {} bytes of a frame", avail),
+                ));
+            }
+        }
+    }
+}
+
+impl<S: Write> LengthDelimited<S> {
+    /// write a frame, the prefix and the payload
+    ///
+    /// the rest of an earlier frame that failed in the middle is written
+    /// first, the frame is not taken if that fails. a payload that is larger
+    /// than the max fails with `InvalidInput`
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.wbuf.write_out(&mut self.inner)?;
+        let opts = &self.opts;
+        if frame.len() < opts.offset {
+            return Err(invalid_input(format!(
+                "frame of {} bytes is shorter than the prefix of {}",
+                frame.len(),
+                opts.offset
+            )));
+        }
+        let (prefix, payload) = frame.split_at(opts.offset);
+        if payload.len() > opts.max_frame {
+            return Err(invalid_input(format!(
+                "frame of {} bytes is larger than the max {}",
+                payload.len(),
+                opts.max_frame
+            )));
+        }
+        let field = payload.len() as i128 - opts.adjustment as i128;
+        if field < 0 || field > opts.max_field() as i128 {
+            return Err(invalid_input(format!(
+                "length field {} doesn't fit in {:?}",
+                field, opts.header
+            )));
+        }
+        let buf = &mut self.wbuf.buf;
+        buf.extend_from_slice(prefix);
+        opts.encode(field as u64, buf);
+        buf.extend_from_slice(payload);
+        self.wbuf.write_out(&mut self.inner)
+    }
+
+    /// write the rest of a frame that failed in the middle and flush the
+    /// stream
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wbuf.write_out(&mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for LengthDelimited<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LengthDelimited")
+            .field("inner", &self.inner)
+            .field("opts", &self.opts)
+            .field("read_ahead", &self.rbuf.data().len())
+            .finish()
+    }
+}
+
+/// the lines that end with `\n`, a `\r` before it is dropped
+pub struct LinesCodec<S> {
+    inner: S,
+    max_line: usize,
+    rbuf: ReadBuf,
+    wbuf: WriteBuf,
+    // where to go on looking for the `\n`
+    scanned: usize,
+}
+
+impl<S> LinesCodec<S> {
+    /// wrap the stream, a line is at most `max_line` bytes without the end
+    pub fn new(stream: S, max_line: usize) -> Self {
+        LinesCodec {
+            inner: stream,
+            max_line,
+            rbuf: ReadBuf::new(),
+            wbuf: WriteBuf::new(),
+            scanned: 0,
+        }
+    }
+
+    /// the max length of a line
+    pub fn max_line(&self) -> usize {
+        self.max_line
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// unwrap the stream, the bytes read ahead and the rest of a line that
+    /// is not yet written are lost
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// the bytes that are read ahead of the lines
+    pub fn read_buffer(&self) -> &[u8] {
+        self.rbuf.data()
+    }
+
+    fn too_long(&self) -> io::Error {
+        invalid_data(format!("line is longer than the max {}", self.max_line))
+    }
+}
+
+fn to_str(line: &[u8]) -> io::Result<&str> {
+    str::from_utf8(line).map_err(|e| invalid_data(e.to_string()))
+}
+
+impl<S: Read> LinesCodec<S> {
+    /// read the next line without its end, `None` on eof between the lines
+    ///
+    /// the last line may have no end. a line that is longer than the max
+    /// fails with `InvalidData` as soon as it's known, so does a line that
+    /// is not utf-8
+    pub fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.read_line_ref()?.map(|l| l.to_owned()))
+    }
+
+    /// read the next line into the buffer of the codec, the same as
+    /// `read_line` without copying it out
+    pub fn read_line_ref(&mut self) -> io::Result<Option<&str>> {
+        loop {
+            let data = self.rbuf.data();
+            if let Some(i) = data[self.scanned..].iter().position(|&b| b == b'\n') {
+                let end = self.scanned + i;
+                let start = self.rbuf.start;
+                let len = match data[..end].last() {
+                    Some(b'\r') => end - 1,
+                    _ => end,
+                };
+                if len > self.max_line {
+                    return Err(self.too_long());
+                }
+                self.rbuf.start += end + 1;
+                self.scanned = 0;
+                return to_str(&self.rbuf.buf[start..start + len]).map(Some);
+            }
+            // the `\r` of the end may be there already
+            if data.len() > self.max_line + 1 {
+                return Err(self.too_long());
+            }
+            self.scanned = data.len();
+            if self.rbuf.fill(&mut self.inner, 1)? == 0 {
+                let start = self.rbuf.start;
+                let len = self.rbuf.end - start;
+                if len == 0 {
+                    return Ok(None);
+                }
+                if len > self.max_line {
+                    return Err(self.too_long());
+                }
+                self.rbuf.start = self.rbuf.end;
+                self.scanned = 0;
+                return to_str(&self.rbuf.buf[start..start + len]).map(Some);
+            }
+        }
+    }
+}
+
+impl<S: Write> LinesCodec<S> {
+    /// write a line and its `\n`
+    ///
+    /// the rest of an earlier line that failed in the middle is written
+    /// first, the line is not taken if that fails. a line that is longer
+    /// than the max or has a `\n` fails with `InvalidInput`
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.wbuf.write_out(&mut self.inner)?;
+        if line.len() > self.max_line {
+            return Err(invalid_input(format!(
+                "line of {} bytes is longer than the max {}",
+                line.len(),
+                self.max_line
+            )));
+        }
+        if line.contains('\n') {
+            return Err(invalid_input("line has a \\n".to_owned()));
+        }
+        self.wbuf.buf.extend_from_slice(line.as_bytes());
+        self.wbuf.buf.push(b'\n');
+        self.wbuf.write_out(&mut self.inner)
+    }
+
+    /// write the rest of a line that failed in the middle and flush the
+    /// stream
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wbuf.write_out(&mut self.inner)?;
+        self.inner.flush()
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for LinesCodec<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LinesCodec")
+            .field("inner", &self.inner)
+            .field("max_line", &self.max_line)
+            .field("read_ahead", &self.rbuf.data().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    // a reader that gives at most `chunk` bytes at a time, and fails with
+    // `WouldBlock` before every other read if `stall` is set
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        stall: bool,
+        stalled: bool,
+    }
+
+    impl Trickle {
+        fn new(data: Vec<u8>, chunk: usize) -> Self {
+            Trickle {
+                data,
+                pos: 0,
+                chunk,
+                stall: false,
+                stalled: false,
+            }
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.stall {
+                self.stalled = !self.stalled;
+                if self.stalled {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+            }
+            let n = self.chunk.min(buf.len()).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    fn frames() -> Vec<Vec<u8>> {
+        vec![
+            b"\x01".to_vec(),
+            b"\x02hello".to_vec(),
+            vec![3; 300],
+            vec![4; 20_000],
+        ]
+    }
+
+    fn round_trip(builder: LengthDelimitedBuilder) {
+        let mut w = builder.clone().build(Vec::new());
+        for f in frames() {
+            w.write_frame(&f).unwrap();
+        }
+        let wire = w.into_inner();
+        for &chunk in &[1, 3, 7, 5000] {
+            let mut r = builder.clone().build(Trickle::new(wire.clone(), chunk));
+            for f in frames() {
+                assert_eq!(r.read_frame().unwrap().unwrap(), f);
+            }
+            assert!(r.read_frame().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn split_frames() {
+        round_trip(LengthDelimited::builder());
+        round_trip(LengthDelimited::builder().header(LengthHeader::U16));
+        round_trip(LengthDelimited::builder().little_endian());
+        round_trip(LengthDelimited::builder().header(LengthHeader::Varint));
+        // a type byte before a length that counts the whole header
+        round_trip(
+            LengthDelimited::builder()
+                .header(LengthHeader::U16)
+                .length_offset(1)
+                .length_adjustment(-3),
+        );
+    }
+
+    #[test]
+    fn varint_header() {
+        let mut w = LengthDelimited::builder()
+            .header(LengthHeader::Varint)
+            .build(Vec::new());
+        w.write_frame(&[0; 300]).unwrap();
+        assert_eq!(&w.get_ref()[..2], &[0xac, 0x02]);
+        assert_eq!(w.get_ref().len(), 302);
+
+        // 11 bytes of continuation
+        let mut r = LengthDelimited::builder()
+            .header(LengthHeader::Varint)
+            .build(Trickle::new(vec![0xff; 11], 1));
+        let e = r.read_frame().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_and_truncated() {
+        // the length is checked before the payload is read
+        let mut wire = 1000u32.to_be_bytes().to_vec();
+        wire.extend_from_slice(&[0; 10]);
+        let mut r = LengthDelimited::new(Trickle::new(wire, 1), 100);
+        let e = r.read_frame().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(r.get_ref().pos, 4);
+
+        let mut w = LengthDelimited::new(Vec::new(), 100);
+        let e = w.write_frame(&[0; 101]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(w.get_ref().is_empty());
+        let e = LengthDelimited::builder()
+            .header(LengthHeader::U16)
+            .build(Vec::new())
+            .write_frame(&[0; 70_000])
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+        // the eof in the header and in the payload
+        let mut r = LengthDelimited::new(Trickle::new(vec![0, 0], 1), 100);
+        let e = r.read_frame().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        let mut r = LengthDelimited::new(Trickle::new(vec![0, 0, 0, 5, 1, 2], 1), 100);
+        let e = r.read_frame().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn resume_after_would_block() {
+        let mut w = LengthDelimited::new(Vec::new(), DEFAULT_MAX_FRAME);
+        for f in frames() {
+            w.write_frame(&f).unwrap();
+        }
+        let mut t = Trickle::new(w.into_inner(), 3);
+        t.stall = true;
+        let mut r = LengthDelimited::new(t, DEFAULT_MAX_FRAME);
+        let mut got = Vec::new();
+        loop {
+            match r.read_frame() {
+                Ok(Some(f)) => got.push(f),
+                Ok(None) => break,
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::WouldBlock),
+            }
+        }
+        assert_eq!(got, frames());
+    }
+
+    #[test]
+    fn lines() {
+        let text = b"one\r\ntwo\n\nthree is long\nlast".to_vec();
+        for &chunk in &[1, 2, 4096] {
+            let mut r = LinesCodec::new(Trickle::new(text.clone(), chunk), 16);
+            assert_eq!(r.read_line().unwrap().unwrap(), "one");
+            assert_eq!(r.read_line().unwrap().unwrap(), "two");
+            assert_eq!(r.read_line().unwrap().unwrap(), "");
+            assert_eq!(r.read_line().unwrap().unwrap(), "three is long");
+            assert_eq!(r.read_line().unwrap().unwrap(), "last");
+            assert!(r.read_line().unwrap().is_none());
+        }
+
+        // too long with and without its end, not utf-8
+        let mut r = LinesCodec::new(Trickle::new(b"12345\n".to_vec(), 1), 4);
+        assert_eq!(
+            r.read_line().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        let mut r = LinesCodec::new(Trickle::new(vec![b'x'; 100], 1), 4);
+        assert_eq!(
+            r.read_line().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(r.get_ref().pos <= 6);
+        let mut r = LinesCodec::new(Trickle::new(b"\xff\n".to_vec(), 1), 4);
+        assert_eq!(
+            r.read_line().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut w = LinesCodec::new(Vec::new(), 4);
+        w.write_line("abcd").unwrap();
+        assert_eq!(
+            w.write_line("abcde").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            w.write_line("a\nb").unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(w.get_ref(), b"abcd\n");
+    }
+
+    #[test]
+    fn tcp_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h = co!(move || {
+            let (s, _) = listener.accept().unwrap();
+            let mut s = LengthDelimited::new(s, 1024);
+            s.write_frame(b"first").unwrap();
+            // half of a frame, then the rest after the peer timed out
+            let wire = [0, 0, 0, 6, b's', b'e'];
+            s.get_mut().write_all(&wire).unwrap();
+            crate::coroutine::sleep(Duration::from_millis(100));
+            s.get_mut().write_all(b"cond").unwrap();
+        });
+
+        let c = TcpStream::connect(addr).unwrap();
+        c.set_read_timeout(Some(Duration::from_millis(30))).unwrap();
+        let mut c = LengthDelimited::new(c, 1024);
+        assert_eq!(c.read_frame().unwrap().unwrap(), b"first");
+        let e = c.read_frame().unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        ));
+        c.get_ref().set_read_timeout(None).unwrap();
+        assert_eq!(c.read_frame().unwrap().unwrap(), b"second");
+        h.join().unwrap();
+        assert!(c.read_frame().unwrap().is_none());
+    }
+}
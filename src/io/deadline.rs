@@ -0,0 +1,185 @@
+//! the per operation timeouts and the deadline of any stream
+//!
+//! `WithDeadline` wraps a `Read` or `Write` and runs each call under a
+//! `Context` with the time left, the timer cancels the blocked call at the
+//! checkpoint where it parks, like `Context::wrap`. it works for every stream
+//! whose blocking calls park the coroutine, the streams of this crate, the
+//! unix sockets, the pipes of `CoIo`, `ChannelReader` and the TLS streams
+//! built on them, so it's the way to put a deadline on the streams that have
+//! no timeout setters of their own
+//!
+//! a call that times out fails with `ErrorKind::TimedOut` and moves no data,
+//! the wrapper can be used again after it. in a thread context the blocked
+//! call can't be canceled, only a deadline that is already passed fails it
+//!
+//! for example:
+//! ```no_run
+//! use std::io::Read;
+//! use std::time::Duration;
+//! use mco::io::WithDeadline;
+//! use mco::os::unix::net::UnixStream;
+//!
+//! let s = UnixStream::connect("/tmp/app.sock").unwrap();
+//! let mut s = WithDeadline::new(s).read_timeout(Duration::from_secs(5));
+//! let mut buf = [0; 1024];
+//! // fails with `TimedOut` if nothing comes in 5s
+//! let n = s.read(&mut buf).unwrap();
+//! ```
+
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use crate::std::context::Context;
+use crate::std::time::Time;
+use crate::timeout_list::now_instant;
+
+/// a stream with the read and write timeouts and a deadline
+#[derive(Debug)]
+pub struct WithDeadline<T> {
+    inner: T,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl<T> WithDeadline<T> {
+    /// wrap the stream, it has no timeout and no deadline
+    pub fn new(inner: T) -> Self {
+        WithDeadline {
+            inner,
+            read_timeout: None,
+            write_timeout: None,
+            deadline: None,
+        }
+    }
+
+    /// the timeout of each read
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// the timeout of each write and flush
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// change the timeout of each read, `None` for no timeout
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// change the timeout of each write and flush, `None` for no timeout
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+
+    /// the time that all the calls fail at, a call waits for the shorter of
+    /// its timeout and the time left. a time in the past fails them at once
+    pub fn set_deadline(&mut self, deadline: Time) {
+        self.deadline = deadline.instant();
+    }
+
+    /// remove the deadline, the timeouts are kept
+    pub fn clear_deadline(&mut self) {
+        self.deadline = None;
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// take back the stream
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // the context of a call, `None` if it has no time limit
+    fn context(&self, timeout: Option<Duration>) -> Option<Context> {
+        let end = timeout.and_then(|t| now_instant().checked_add(t));
+        let end = match (end, self.deadline) {
+            (Some(a), Some(b)) => a.min(b),
+            (a, b) => a.or(b)?,
+        };
+        Some(Context::background().with_deadline(end))
+    }
+}
+
+impl<T: Read> Read for WithDeadline<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.context(self.read_timeout) {
+            Some(ctx) => ctx.wrap(|| self.inner.read(buf)),
+            None => self.inner.read(buf),
+        }
+    }
+}
+
+impl<T: Write> Write for WithDeadline<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.context(self.write_timeout) {
+            Some(ctx) => ctx.wrap(|| self.inner.write(buf)),
+            None => self.inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.context(self.write_timeout) {
+            Some(ctx) => ctx.wrap(|| self.inner.flush()),
+            None => self.inner.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::ChannelReader;
+    use crate::std::sync::channel::channel;
+
+    #[test]
+    fn read_times_out_and_recovers() {
+        let (tx, rx) = channel();
+        let h = co!(move || {
+            let mut r =
+                WithDeadline::new(ChannelReader::new(rx)).read_timeout(Duration::from_millis(50));
+            let mut buf = [0; 4];
+            let err = r.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            // usable after the timeout
+            r.read_exact(&mut buf).unwrap();
+            buf
+        });
+        crate::coroutine::sleep(Duration::from_millis(100));
+        tx.send(b"ping".to_vec()).unwrap();
+        assert_eq!(&h.join().unwrap(), b"ping");
+    }
+
+    #[test]
+    fn deadline() {
+        let (tx, rx) = channel();
+        let h = co!(move || {
+            let mut r =
+                WithDeadline::new(ChannelReader::new(rx)).read_timeout(Duration::from_secs(10));
+            r.set_deadline(Time::now_utc().add(Duration::from_millis(50)));
+            let mut buf = [0; 4];
+            // the deadline is before the timeout
+            let start = now_instant();
+            let err = r.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert!(start.elapsed() < Duration::from_secs(5));
+            // a passed deadline fails at once, even with the data there
+            tx.send(b"pong".to_vec()).unwrap();
+            let err = r.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            r.clear_deadline();
+            r.read_exact(&mut buf).unwrap();
+            buf
+        });
+        assert_eq!(&h.join().unwrap(), b"pong");
+    }
+}
@@ -1,20 +1,47 @@
 use std::io;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 
 use super::sys::{Selector, SysEvent};
-use crate::scheduler::WORKER_ID;
+use crate::maintenance;
+use crate::scheduler::{get_scheduler, WORKER_ID};
+
+// the description of the last io driver error
+static LAST_ERROR: Lazy<Mutex<Option<(io::ErrorKind, String)>>> = Lazy::new(|| Mutex::new(None));
+
+// log and record an io driver error, see `last_driver_error`
+pub(crate) fn report_driver_error(err: &io::Error) {
+    error!("io driver error: {}", err);
+    *LAST_ERROR.lock() = Some((err.kind(), err.to_string()));
+}
+
+/// the last error of the io driver, like an fd that is closed while a
+/// coroutine is parked on it or a failed wait of the selector. the coroutines
+/// that are affected get the error from their io call
+pub fn last_driver_error() -> Option<io::Error> {
+    LAST_ERROR
+        .lock()
+        .as_ref()
+        .map(|(kind, msg)| io::Error::new(*kind, msg.clone()))
+}
 
 /// Single threaded IO event loop.
 pub struct EventLoop {
     selector: Selector,
+    stopped: AtomicBool,
 }
 
 impl EventLoop {
     pub fn new(io_workers: usize) -> io::Result<EventLoop> {
-        Selector::new(io_workers).map(|selector| EventLoop { selector })
+        Selector::new(io_workers).map(|selector| EventLoop {
+            selector,
+            stopped: AtomicBool::new(false),
+        })
     }
 
-    /// Keep spinning the event loop indefinitely, and notify the handler whenever
+    /// Keep spinning the event loop until it's stopped, and notify the handler whenever
     /// any of the registered handles are ready.
     pub fn run(&self, id: usize) -> io::Result<()> {
         use std::mem::MaybeUninit;
@@ -27,15 +54,43 @@ impl EventLoop {
         let mut events_buf = unsafe { events_buf.assume_init() };
         // wake up every 1 second
         let mut next_expire = Some(1_000_000_000);
-        loop {
-            next_expire = match self.selector.select(id, &mut events_buf, next_expire) {
-                Ok(v) => v.or(Some(1_000_000_000)),
+        let s = get_scheduler();
+        // the heartbeats of the worker for the stall watchdog
+        let slot = s.run_slot(id);
+        while !self.stopped.load(Ordering::Acquire) {
+            // the new initializers, a new worker runs them before it runs
+            // any coroutine
+            s.worker_inits.catch_up(id, s.worker_num());
+            // a worker that waits for the io events is never stalled
+            if let Some(slot) = slot {
+                slot.idle();
+            }
+            let ret = self.selector.select(id, &mut events_buf, next_expire);
+            if let Some(slot) = slot {
+                slot.heartbeat();
+            }
+            next_expire = match ret {
+                // the maintenance callbacks may want an earlier wake up
+                Ok(v) => maintenance::tick(id, v.or(Some(1_000_000_000))),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
                 Err(e) => {
-                    error!("selector error={:?}", e);
+                    // the selector can't be trusted any more, don't leave
+                    // the coroutines parked on it forever
+                    report_driver_error(&e);
+                    self.selector.fail_all(id, &e);
                     continue;
                 }
             }
         }
+        Ok(())
+    }
+
+    // stop all the event loops, they exit after the current round
+    pub fn stop(&self, io_workers: usize) {
+        self.stopped.store(true, Ordering::Release);
+        for id in 0..io_workers {
+            self.selector.wakeup(id);
+        }
     }
 
     // get the internal selector
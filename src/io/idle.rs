@@ -0,0 +1,267 @@
+//! drop a connection that is idle for too long
+//!
+//! `IdleTimeout` wraps a stream, each successful read or write is an activity.
+//! once the stream is idle for the given duration the blocking read or write
+//! fails with `ErrorKind::TimedOut`
+//!
+//! each wrapper has one deadline in the `DeadlineQueue` that is shared by
+//! the crate and swept by the `mco-deadline` thread. an activity only stores its
+//! time, the deadline is checked when it fires and put off to the last
+//! activity then, so the busy streams are placed about once per idle
+//! duration and the timeouts of the stream are never touched. a due deadline
+//! cancels the coroutine that is blocked in the io, in a thread the deadline
+//! is only checked before the call
+//! for example:
+//! ```no_run
+//! use std::io::Read;
+//! use std::time::Duration;
+//! use mco::io::IdleTimeout;
+//! use mco::net::TcpStream;
+//!
+//! let s = TcpStream::connect("127.0.0.1:8080").unwrap();
+//! let mut s = IdleTimeout::new(s, Duration::from_secs(30));
+//! let mut buf = [0; 1024];
+//! // fails with `TimedOut` if the peer is silent for 30s
+//! let n = s.read(&mut buf).unwrap();
+//! ```
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::{current, current_cancel_data, is_coroutine, Coroutine};
+use crate::std::context::is_cancel_panic;
+use crate::std::time::deadline_queue::{due_at, Due, DueKey};
+use crate::timeout_list::now_instant;
+
+// the times are kept as the nanoseconds since this
+static BASE: Lazy<Instant> = Lazy::new(now_instant);
+
+fn nanos_of(t: Instant) -> u64 {
+    t.saturating_duration_since(*BASE).as_nanos() as u64
+}
+
+fn instant_of(nanos: u64) -> Instant {
+    *BASE + Duration::from_nanos(nanos)
+}
+
+struct Slot {
+    // the deadline is in the queue
+    armed: bool,
+    // the coroutine that is blocked in the io
+    blocked: Option<Coroutine>,
+}
+
+// the deadline of a wrapper
+struct Entry {
+    idle: AtomicU64,
+    last: AtomicU64,
+    slot: Mutex<Slot>,
+}
+
+impl Entry {
+    fn deadline(&self) -> Instant {
+        let last = self.last.load(Ordering::Acquire);
+        instant_of(last.saturating_add(self.idle.load(Ordering::Relaxed)))
+    }
+}
+
+impl Due for Entry {
+    // put the deadline off to the last activity or cancel the blocked io
+    fn due(self: Arc<Self>) {
+        let mut slot = self.slot.lock();
+        if !slot.armed {
+            return;
+        }
+        let deadline = self.deadline();
+        if now_instant() < deadline {
+            due_at(DueKey::new(&self), deadline);
+            return;
+        }
+        slot.armed = false;
+        if let Some(co) = slot.blocked.take() {
+            let _ = co.cancel();
+        }
+    }
+}
+
+/// a stream that fails the blocking io after it's idle for too long
+pub struct IdleTimeout<S> {
+    inner: S,
+    entry: Arc<Entry>,
+}
+
+impl<S> IdleTimeout<S> {
+    /// wrap the stream, it's idle from now on
+    pub fn new(inner: S, idle: Duration) -> Self {
+        let entry = Entry {
+            idle: AtomicU64::new(idle.as_nanos() as u64),
+            last: AtomicU64::new(nanos_of(now_instant())),
+            slot: Mutex::new(Slot {
+                armed: false,
+                blocked: None,
+            }),
+        };
+        IdleTimeout {
+            inner,
+            entry: Arc::new(entry),
+        }
+    }
+
+    /// change the idle duration, the time since the last activity counts
+    pub fn set_idle(&mut self, idle: Duration) {
+        let slot = self.entry.slot.lock();
+        self.entry
+            .idle
+            .store(idle.as_nanos() as u64, Ordering::Relaxed);
+        // a shorter one is placed again, a longer one is found when it fires
+        if slot.armed {
+            due_at(DueKey::new(&self.entry), self.entry.deadline());
+        }
+    }
+
+    /// the idle duration
+    pub fn idle(&self) -> Duration {
+        Duration::from_nanos(self.entry.idle.load(Ordering::Relaxed))
+    }
+
+    /// the time since the last successful read or write
+    pub fn time_since_activity(&self) -> Duration {
+        let last = instant_of(self.entry.last.load(Ordering::Acquire));
+        now_instant().saturating_duration_since(last)
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// unwrap the stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    // run the io until it's done or the stream is idle for too long
+    fn run<T, F>(&mut self, f: F) -> io::Result<T>
+    where
+        F: FnOnce(&mut S) -> io::Result<T>,
+    {
+        let co = is_coroutine();
+        {
+            let mut slot = self.entry.slot.lock();
+            let deadline = self.entry.deadline();
+            if now_instant() >= deadline {
+                return Err(idle_timeout());
+            }
+            if !slot.armed {
+                slot.armed = true;
+                due_at(DueKey::new(&self.entry), deadline);
+            }
+            if co {
+                slot.blocked = Some(current());
+            }
+        }
+
+        let inner = &mut self.inner;
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| f(inner)));
+        // the deadline has canceled the io if the coroutine is taken
+        let canceled = co && self.entry.slot.lock().blocked.take().is_none();
+        if canceled {
+            current_cancel_data().clear_cancel_bit();
+        }
+        let ret = match ret {
+            Ok(Ok(t)) => Ok(t),
+            Ok(Err(_)) if canceled => Err(idle_timeout()),
+            Ok(Err(e)) if e.kind() == io::ErrorKind::WouldBlock && self.is_idle() => {
+                Err(idle_timeout())
+            }
+            Ok(Err(e)) => Err(e),
+            Err(p) if canceled && is_cancel_panic(&*p) => Err(idle_timeout()),
+            Err(p) => panic::resume_unwind(p),
+        };
+        if ret.is_ok() {
+            self.entry
+                .last
+                .store(nanos_of(now_instant()), Ordering::Release);
+        }
+        ret
+    }
+
+    fn is_idle(&self) -> bool {
+        now_instant() >= self.entry.deadline()
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for IdleTimeout<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IdleTimeout")
+            .field("inner", &self.inner)
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+fn idle_timeout() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "idle timeout")
+}
+
+impl<S: Read> Read for IdleTimeout<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.run(|s| s.read(buf))
+    }
+}
+
+impl<S: Write> Write for IdleTimeout<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.run(|s| s.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn idle_reset_by_activity() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h = co!(move || {
+            let (s, _) = listener.accept().unwrap();
+            let mut s = IdleTimeout::new(s, Duration::from_millis(100));
+            let mut buf = [0; 4];
+            // the peer writes every 50ms, so it's never idle
+            for _ in 0..4 {
+                s.read_exact(&mut buf).unwrap();
+                assert!(s.time_since_activity() < Duration::from_millis(50));
+            }
+            let start = Instant::now();
+            let e = s.read(&mut buf).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+            assert!(start.elapsed() >= Duration::from_millis(90));
+            // fails at once after the deadline
+            let e = s.write(b"late").unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        });
+
+        let mut c = TcpStream::connect(addr).unwrap();
+        for _ in 0..4 {
+            crate::coroutine::sleep(Duration::from_millis(50));
+            c.write_all(b"ping").unwrap();
+        }
+        h.join().unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! the io events that a registration waits for, see `CoIo::modify_interest`
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign};
+
+/// a set of the io events, the flags are combined with `|`
+///
+/// ```
+/// use mco::io::Interest;
+///
+/// let i = Interest::READABLE | Interest::HUP;
+/// assert!(i.contains(Interest::READABLE));
+/// assert!(!i.contains(Interest::WRITABLE));
+/// assert_eq!(format!("{:?}", i), "READABLE | HUP");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Interest(u8);
+
+impl Interest {
+    /// the fd has data to read
+    pub const READABLE: Interest = Interest(1);
+    /// the fd can take more data
+    pub const WRITABLE: Interest = Interest(1 << 1);
+    /// an error is pending on the fd, epoll and kqueue always report it
+    pub const ERROR: Interest = Interest(1 << 2);
+    /// the peer closed its side of the fd
+    pub const HUP: Interest = Interest(1 << 3);
+
+    const NAMES: [(Interest, &'static str); 4] = [
+        (Interest::READABLE, "READABLE"),
+        (Interest::WRITABLE, "WRITABLE"),
+        (Interest::ERROR, "ERROR"),
+        (Interest::HUP, "HUP"),
+    ];
+
+    /// no events
+    pub const fn empty() -> Interest {
+        Interest(0)
+    }
+
+    /// all the events, the interest of a new registration
+    pub const fn all() -> Interest {
+        Interest(0b1111)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// the interest of the bits, the unknown ones are dropped
+    pub const fn from_bits_truncate(bits: u8) -> Interest {
+        Interest(bits & Interest::all().0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// true if all the events of `other` are in the set
+    pub const fn contains(self, other: Interest) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Interest {
+    type Output = Interest;
+
+    fn bitor(self, other: Interest) -> Interest {
+        Interest(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Interest {
+    fn bitor_assign(&mut self, other: Interest) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for Interest {
+    type Output = Interest;
+
+    fn bitand(self, other: Interest) -> Interest {
+        Interest(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for Interest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("(empty)");
+        }
+        let mut first = true;
+        for (flag, name) in Interest::NAMES.iter() {
+            if self.contains(*flag) {
+                if !first {
+                    f.write_str(" | ")?;
+                }
+                f.write_str(name)?;
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
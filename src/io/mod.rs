@@ -11,7 +11,15 @@ pub(crate) mod sys;
 // export the generic IO wrapper
 pub mod co_io_err;
 
+pub mod codec;
+
+mod chan_io;
+mod deadline;
 mod event_loop;
+mod idle;
+#[cfg(unix)]
+mod interest;
+mod progress;
 
 use std::io;
 use std::ops::Deref;
@@ -19,7 +27,14 @@ use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::coroutine_impl::is_coroutine;
 
-pub(crate) use self::event_loop::EventLoop;
+pub use self::chan_io::{ChannelReader, ChannelWriter};
+pub use self::deadline::WithDeadline;
+pub(crate) use self::event_loop::{report_driver_error, EventLoop};
+pub use self::event_loop::last_driver_error;
+pub use self::idle::IdleTimeout;
+pub use self::progress::{read_exact_progress, write_all_progress, PartialIoError};
+#[cfg(unix)]
+pub use self::interest::Interest;
 pub use self::sys::co_io::CoIo;
 #[cfg(unix)]
 pub use self::sys::wait_io::WaitIo;
@@ -0,0 +1,229 @@
+//! the `read_exact` and `write_all` that report how far they got
+//!
+//! the std ones drop the count of the bytes that are moved before an error,
+//! so a read that times out in the middle of a frame leaves the stream out of
+//! step with the caller. these return it in a `PartialIoError`, the caller
+//! goes on from that offset once the error is handled, e.g. after a
+//! `TimedOut` of the read timeout or of a `WithDeadline`
+//!
+//! ```
+//! use mco::io::{read_exact_progress, PartialIoError};
+//! use std::io::{self, Read};
+//!
+//! // gives 3 bytes, then times out once
+//! struct Flaky(&'static [u8], bool);
+//!
+//! impl Read for Flaky {
+//!     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+//!         if self.1 {
+//!             self.1 = false;
+//!             return Err(io::ErrorKind::TimedOut.into());
+//!         }
+//!         let n = buf.len().min(3).min(self.0.len());
+//!         buf[..n].copy_from_slice(&self.0[..n]);
+//!         self.0 = &self.0[n..];
+//!         self.1 = true;
+//!         Ok(n)
+//!     }
+//! }
+//!
+//! let mut s = Flaky(b"abcdef", false);
+//! let mut buf = [0; 6];
+//! let mut done = 0;
+//! while let Err(e) = read_exact_progress(&mut s, &mut buf[done..]) {
+//!     assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+//!     done += e.bytes_completed();
+//! }
+//! assert_eq!(&buf, b"abcdef");
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// an io error in the middle of a `read_exact_progress` or a
+/// `write_all_progress`, with the bytes that are moved before it
+pub struct PartialIoError {
+    error: io::Error,
+    bytes_completed: usize,
+}
+
+impl PartialIoError {
+    pub fn new(error: io::Error, bytes_completed: usize) -> Self {
+        PartialIoError {
+            error,
+            bytes_completed,
+        }
+    }
+
+    /// the bytes at the front of the buffer that are read or written, the
+    /// next call starts after them
+    pub fn bytes_completed(&self) -> usize {
+        self.bytes_completed
+    }
+
+    pub fn kind(&self) -> io::ErrorKind {
+        self.error.kind()
+    }
+
+    pub fn error(&self) -> &io::Error {
+        &self.error
+    }
+
+    pub fn into_error(self) -> io::Error {
+        self.error
+    }
+}
+
+impl fmt::Display for PartialIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} after {} bytes", self.error, self.bytes_completed)
+    }
+}
+
+impl fmt::Debug for PartialIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PartialIoError")
+            .field("error", &self.error)
+            .field("bytes_completed", &self.bytes_completed)
+            .finish()
+    }
+}
+
+impl Error for PartialIoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl From<PartialIoError> for io::Error {
+    fn from(e: PartialIoError) -> Self {
+        e.error
+    }
+}
+
+/// fill the whole buffer like `Read::read_exact`, the error has the bytes
+/// that are read into the front of `buf` before it
+///
+/// `Interrupted` is retried, an eof before the end fails with
+/// `UnexpectedEof`
+pub fn read_exact_progress<R: Read + ?Sized>(
+    r: &mut R,
+    buf: &mut [u8],
+) -> Result<(), PartialIoError> {
+    let mut done = 0;
+    while done < buf.len() {
+        match r.read(&mut buf[done..]) {
+            Ok(0) => {
+                let e = io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer");
+                return Err(PartialIoError::new(e, done));
+            }
+            Ok(n) => done += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(PartialIoError::new(e, done)),
+        }
+    }
+    Ok(())
+}
+
+/// write the whole buffer like `Write::write_all`, the error has the bytes
+/// at the front of `buf` that are written before it
+///
+/// `Interrupted` is retried, a write of 0 bytes fails with `WriteZero`
+pub fn write_all_progress<W: Write + ?Sized>(w: &mut W, buf: &[u8]) -> Result<(), PartialIoError> {
+    let mut done = 0;
+    while done < buf.len() {
+        match w.write(&buf[done..]) {
+            Ok(0) => {
+                let e = io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer");
+                return Err(PartialIoError::new(e, done));
+            }
+            Ok(n) => done += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(PartialIoError::new(e, done)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{TcpListener, TcpStream};
+    use std::time::Duration;
+
+    // a writer that takes 2 bytes at a time and fails every third call
+    struct Choppy {
+        out: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Write for Choppy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls % 3 == 0 {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(2);
+            self.out.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_resumes() {
+        let mut w = Choppy {
+            out: Vec::new(),
+            calls: 0,
+        };
+        let data = b"0123456789";
+        let mut done = 0;
+        while let Err(e) = write_all_progress(&mut w, &data[done..]) {
+            assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+            assert_eq!(e.bytes_completed(), 4);
+            done += e.bytes_completed();
+        }
+        assert_eq!(w.out, data);
+
+        let mut r: &[u8] = b"abc";
+        let mut buf = [0; 4];
+        let e = read_exact_progress(&mut r, &mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(e.bytes_completed(), 3);
+        assert_eq!(&buf[..3], b"abc");
+    }
+
+    #[test]
+    fn tcp_read_resumes_after_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let h = co!(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            // a frame of 4 bytes of length and 6 of payload, 3 bytes first
+            s.write_all_progress(&[0, 0, 0]).unwrap();
+            crate::coroutine::sleep(Duration::from_millis(100));
+            s.write_all_progress(&[6, b'h', b'e', b'l', b'l', b'o', b'!'])
+                .unwrap();
+        });
+
+        let mut c = TcpStream::connect(addr).unwrap();
+        c.set_read_timeout(Some(Duration::from_millis(30))).unwrap();
+        let mut len = [0; 4];
+        let e = c.read_exact_progress(&mut len).unwrap_err();
+        assert!(matches!(
+            e.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+        ));
+        assert_eq!(e.bytes_completed(), 3);
+        c.set_read_timeout(None).unwrap();
+        c.read_exact_progress(&mut len[3..]).unwrap();
+        let mut payload = vec![0; u32::from_be_bytes(len) as usize];
+        c.read_exact_progress(&mut payload).unwrap();
+        assert_eq!(payload, b"hello!");
+        h.join().unwrap();
+    }
+}
@@ -5,11 +5,15 @@
 
 use std::io::{self, Read, Write};
 use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::time::Duration;
 
 use self::io_impl::co_io_err::Error;
 use self::io_impl::net as net_impl;
-use crate::io as io_impl;
+use super::wait_io::PollOnce;
+use super::{co_io_result, modify_socket};
+use crate::coroutine_impl::is_coroutine;
+use crate::io::{self as io_impl, Interest};
 use crate::std::sync::atomic_dur::AtomicDuration;
 use crate::yield_now::yield_with;
 
@@ -43,6 +47,8 @@ pub struct CoIo<T: AsRawFd> {
     ctx: io_impl::IoContext,
     read_timeout: AtomicDuration,
     write_timeout: AtomicDuration,
+    // the bits of the `Interest` that the fd is registered for
+    interest: AtomicU8,
 }
 
 impl<T: AsRawFd> io_impl::AsIoData for CoIo<T> {
@@ -82,6 +88,7 @@ impl<T: AsRawFd> CoIo<T> {
             ctx: io_impl::IoContext::new(),
             read_timeout: AtomicDuration::new(None),
             write_timeout: AtomicDuration::new(None),
+            interest: AtomicU8::new(Interest::all().bits()),
         })
     }
 
@@ -93,6 +100,7 @@ impl<T: AsRawFd> CoIo<T> {
             ctx: io_impl::IoContext::new(),
             read_timeout: AtomicDuration::new(None),
             write_timeout: AtomicDuration::new(None),
+            interest: AtomicU8::new(Interest::all().bits()),
         }
     }
 
@@ -149,6 +157,94 @@ impl<T: AsRawFd> CoIo<T> {
         self.ctx.set_nonblocking(nb);
         Ok(())
     }
+
+    /// the io events that the fd is registered for, all of them by `new`
+    pub fn interest(&self) -> Interest {
+        Interest::from_bits_truncate(self.interest.load(Ordering::Relaxed))
+    }
+
+    /// change the io events that the fd is registered for, the reads, the
+    /// writes, `wait_io` and `poll_once` only wake up for them after it
+    ///
+    /// it's race free with the event delivery of the registration: the ready
+    /// flag is cleared and the new interest is armed in one call to the
+    /// driver, that reports the readiness the fd already has for it. so the
+    /// event of a read or a write that follows and gets `WouldBlock` is
+    /// never lost, it's seen by that call or wakes the next `poll_once`. an
+    /// event that was delivered before may still wake it once, spuriously
+    pub fn modify_interest(&self, interest: Interest) -> io::Result<()> {
+        self.io.reset();
+        modify_socket(&self.io, interest)?;
+        self.interest.store(interest.bits(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// wait for one event of the interest, for at most `timeout` if it's
+    /// some. return false if the timeout expires first
+    ///
+    /// the event is consumed, call the inner io until it's `WouldBlock`
+    /// before the next `poll_once`. an event that arrives after the last
+    /// `WouldBlock` is never lost, see `modify_interest`. it's canceled like
+    /// the reads, and in a thread context it polls the fd
+    ///
+    /// ```no_run
+    /// use mco::io::{CoIo, Interest};
+    /// use std::os::unix::net::UnixDatagram;
+    /// use std::time::Duration;
+    ///
+    /// let (a, _b) = UnixDatagram::pair().unwrap();
+    /// let a = CoIo::new(a).unwrap();
+    /// a.modify_interest(Interest::READABLE).unwrap();
+    /// mco::co!(move || {
+    ///     while a.poll_once(Some(Duration::from_secs(1))).unwrap() {
+    ///         let mut buf = [0u8; 64];
+    ///         while let Ok(n) = a.inner().recv(&mut buf) {
+    ///             println!("got {:?}", &buf[..n]);
+    ///         }
+    ///     }
+    /// });
+    /// ```
+    pub fn poll_once(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        if !is_coroutine() {
+            return self.poll_thread(timeout);
+        }
+        if self.io.io_flag.swap(false, Ordering::Acquire) {
+            return Ok(true);
+        }
+        let blocker = PollOnce::new(&self.io, timeout);
+        yield_with(&blocker);
+        match co_io_result(&self.io) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        self.io.io_flag.store(false, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    // the blocking `poll_once` of a thread
+    fn poll_thread(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let interest = self.interest();
+        let mut events = 0;
+        if interest.contains(Interest::READABLE) {
+            events |= libc::POLLIN;
+        }
+        if interest.contains(Interest::WRITABLE) {
+            events |= libc::POLLOUT;
+        }
+        let mut fd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        let ms = timeout.map_or(-1, |t| {
+            t.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+        });
+        match unsafe { libc::poll(&mut fd, 1, ms) } {
+            n if n < 0 => Err(io::Error::last_os_error()),
+            n => Ok(n > 0),
+        }
+    }
 }
 
 impl<T: AsRawFd + Read> Read for CoIo<T> {
@@ -259,4 +355,24 @@ mod tests {
         let mut buf = [0u8; 100];
         io.read(&mut buf).unwrap();
     }
+
+    #[test]
+    fn poll_once() {
+        use std::os::unix::net::UnixDatagram;
+
+        let (a, b) = UnixDatagram::pair().unwrap();
+        let a = CoIo::new(a).unwrap();
+        a.modify_interest(Interest::READABLE).unwrap();
+        assert_eq!(a.interest(), Interest::READABLE);
+        let h = co!(move || {
+            let ms = Duration::from_millis(20);
+            assert!(!a.poll_once(Some(ms)).unwrap());
+            b.send(b"hi").unwrap();
+            assert!(a.poll_once(Some(ms)).unwrap());
+            let mut buf = [0u8; 8];
+            let n = a.inner().recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"hi");
+        });
+        h.join().unwrap();
+    }
 }
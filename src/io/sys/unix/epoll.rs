@@ -1,17 +1,19 @@
 use std::os::unix::io::RawFd;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{cmp, io, isize, ptr};
 
-use super::{from_nix_error, timeout_handler, EventData, IoData, TimerList};
+use super::{from_nix_error, timeout_handler, EventData, FdTable, IoData, TimerList};
 use crate::coroutine_impl::run_coroutine;
+use crate::io::Interest;
 use crate::scheduler::get_scheduler;
 use crate::std::queue::seg_queue::SegQueue as mpsc;
 use crate::timeout_list::{now, ns_to_ms};
 use libc::{eventfd, EFD_NONBLOCK};
 use nix::sys::epoll::*;
 use nix::unistd::{close, read, write};
+use parking_lot::Mutex;
 use smallvec::SmallVec;
 
 fn create_eventfd() -> io::Result<RawFd> {
@@ -29,6 +31,12 @@ struct SingleSelector {
     evfd: RawFd,
     timer_list: TimerList,
     free_ev: mpsc<Arc<EventData>>,
+    // the registered fds, see `FdTable`
+    fds: FdTable,
+    // the selector that takes the new io when the worker is dead, !0 if none
+    redirect: AtomicUsize,
+    // the selectors of the dead workers whose timers are run by this one
+    adopted: Mutex<Vec<usize>>,
 }
 
 impl SingleSelector {
@@ -47,6 +55,9 @@ impl SingleSelector {
             evfd,
             free_ev: mpsc::new(),
             timer_list: TimerList::new(),
+            fds: FdTable::new(),
+            redirect: AtomicUsize::new(!0),
+            adopted: Mutex::new(Vec::new()),
         })
     }
 }
@@ -60,12 +71,15 @@ impl Drop for SingleSelector {
 
 pub struct Selector {
     vec: Vec<SingleSelector>,
+    // the new io is registered to the first `active` selectors only
+    active: AtomicUsize,
 }
 
 impl Selector {
     pub fn new(io_workers: usize) -> io::Result<Self> {
         let mut s = Selector {
             vec: Vec::with_capacity(io_workers),
+            active: AtomicUsize::new(io_workers),
         };
 
         for _ in 0..io_workers {
@@ -89,23 +103,19 @@ impl Selector {
         // //info!("select; timeout={:?}", timeout_ms);
 
         // Wait for epoll events for at most timeout_ms milliseconds
-        let mask = self.vec.len() + id;
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         let epfd = single_selector.epfd;
         // first register thread handle
         let scheduler = get_scheduler();
-        scheduler
-            .workers
-            .parked
-            .fetch_or(mask as u64, Ordering::Relaxed);
+        scheduler.workers.park(id);
+        // the tasks queued before the park bit is set don't wake us, poll only
+        let timeout_ms = if scheduler.has_ready(id) { 0 } else { timeout_ms };
 
-        let n = epoll_wait(epfd, events, timeout_ms).map_err(from_nix_error)?;
+        let n = epoll_wait(epfd, events, timeout_ms);
 
         // clear the park stat after comeback
-        scheduler
-            .workers
-            .parked
-            .fetch_and((mask - self.vec.len()) as u64, Ordering::Relaxed);
+        scheduler.workers.unpark(id);
+        let n = n.map_err(from_nix_error)?;
 
         for event in events[..n].iter() {
             if event.data() == 0 {
@@ -121,6 +131,7 @@ impl Selector {
             let data = unsafe { &mut *(event.data() as *mut EventData) };
             // //info!("select got event, data={:p}", data);
             data.io_flag.store(true, Ordering::Release);
+            data.selectors.wake_all();
 
             // first check the atomic co, this may be grab by the worker first
             let co = match data.co.take() {
@@ -149,13 +160,92 @@ impl Selector {
         // free the unused event_data
         self.free_unused_event_data(id);
 
+        // the fds closed behind the back of the parked coroutines
+        single_selector.fds.check_closed();
+
         // deal with the timer list
-        let next_expire = single_selector
+        let now = now();
+        let mut next_expire = single_selector
             .timer_list
-            .schedule_timer(now(), &timeout_handler);
+            .schedule_timer(now, &timeout_handler);
+        // and the ones that are left by the dead workers
+        for &dead in single_selector.adopted.lock().iter() {
+            self.free_unused_event_data(dead);
+            let dead = unsafe { self.vec.get_unchecked(dead) };
+            if let Some(t) = dead.timer_list.schedule_timer(now, &timeout_handler) {
+                next_expire = Some(next_expire.map_or(t, |n| n.min(t)));
+            }
+        }
         Ok(next_expire)
     }
 
+    // move the io of the selector of a dead worker to the selector `to`,
+    // the new io goes to `to` and `to` runs the timers that are left. it's
+    // called by the thread of the dead worker after its event loop is gone
+    //
+    // the io is registered again for all the events, the interest that is
+    // set by `modify_fd` is not kept. the readiness that the fds already have
+    // is reported again by the edge triggered registration. an io that is
+    // added at the same time as the hand over may be left on the dead one
+    pub fn hand_over(&self, dead: usize, to: usize) {
+        let from = unsafe { self.vec.get_unchecked(dead) };
+        let into = unsafe { self.vec.get_unchecked(to) };
+        from.redirect.store(to, Ordering::Release);
+        // the selectors that the dead worker has adopted go along with it
+        let adopted = std::mem::take(&mut *from.adopted.lock());
+        for &id in adopted.iter() {
+            unsafe { self.vec.get_unchecked(id) }
+                .redirect
+                .store(to, Ordering::Release);
+        }
+        {
+            let mut into_adopted = into.adopted.lock();
+            into_adopted.push(dead);
+            into_adopted.extend(adopted);
+        }
+        from.fds.move_to(&into.fds, |data| {
+            let mut empty = EpollEvent::empty();
+            epoll_ctl(from.epfd, EpollOp::EpollCtlDel, data.fd, &mut empty).ok();
+            data.sel.store(to, Ordering::Release);
+            let mut info = EpollEvent::new(
+                EpollFlags::EPOLLIN
+                    | EpollFlags::EPOLLOUT
+                    | EpollFlags::EPOLLRDHUP
+                    | EpollFlags::EPOLLET,
+                Arc::as_ptr(data) as _,
+            );
+            if let Err(e) = epoll_ctl(into.epfd, EpollOp::EpollCtlAdd, data.fd, &mut info) {
+                let e = from_nix_error(e);
+                error!("can't move fd {} to selector {}: {}", data.fd, to, e);
+                data.wake_with_error(e);
+            }
+        });
+        self.wakeup(to);
+    }
+
+    // the selector is broken, wake all the coroutines parked on it with the error
+    pub fn fail_all(&self, id: usize, err: &io::Error) {
+        unsafe { self.vec.get_unchecked(id) }.fds.fail_all(err);
+    }
+
+    // register the new io to the first `n` selectors, the io that is already
+    // registered stays on its selector
+    #[inline]
+    pub fn set_active(&self, n: usize) {
+        self.active.store(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn next_id(&self, fd: usize) -> usize {
+        let id = fd % self.active.load(Ordering::Relaxed);
+        // the selector of a dead worker passes the new io on
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        match single_selector.redirect.load(Ordering::Acquire) {
+            to if to == !0 => id,
+            to => to,
+        }
+    }
+
     // this will post an os event so that we can wake up the event loop
     #[inline]
     pub fn wakeup(&self, id: usize) {
@@ -176,8 +266,10 @@ impl Selector {
         );
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
+        let id = self.next_id(fd as usize);
+        io_data.sel.store(id, Ordering::Relaxed);
         let single_selector = unsafe { self.vec.get_unchecked(id) };
+        single_selector.fds.insert(&io_data)?;
         let epfd = single_selector.epfd;
         //info!("add fd to epoll select, fd={:?}", fd);
         epoll_ctl(epfd, EpollOp::EpollCtlAdd, fd, &mut info)
@@ -185,6 +277,30 @@ impl Selector {
             .map(|_| io_data)
     }
 
+    // change the events that the io is registered for, it stays on its
+    // selector. the kernel reports the readiness that the fd already has for
+    // the new events
+    #[inline]
+    pub fn modify_fd(&self, io_data: &IoData, interest: Interest) -> io::Result<()> {
+        let mut flags = EpollFlags::EPOLLET;
+        if interest.contains(Interest::READABLE) {
+            flags |= EpollFlags::EPOLLIN;
+        }
+        if interest.contains(Interest::WRITABLE) {
+            flags |= EpollFlags::EPOLLOUT;
+        }
+        if interest.contains(Interest::ERROR) {
+            flags |= EpollFlags::EPOLLERR;
+        }
+        if interest.contains(Interest::HUP) {
+            flags |= EpollFlags::EPOLLRDHUP;
+        }
+        let mut info = EpollEvent::new(flags, io_data.as_ref() as *const _ as _);
+        let id = io_data.sel.load(Ordering::Relaxed);
+        let epfd = unsafe { self.vec.get_unchecked(id) }.epfd;
+        epoll_ctl(epfd, EpollOp::EpollCtlMod, io_data.fd, &mut info).map_err(from_nix_error)
+    }
+
     #[inline]
     pub fn del_fd(&self, io_data: &IoData) {
         use std::ops::Deref;
@@ -202,11 +318,21 @@ impl Selector {
         }
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
-        let single_selector = unsafe { self.vec.get_unchecked(id) };
-        let epfd = single_selector.epfd;
-        //info!("del fd from epoll select, fd={:?}", fd);
-        epoll_ctl(epfd, EpollOp::EpollCtlDel, fd, &mut info).ok();
+        let mut id = io_data.sel.load(Ordering::Acquire);
+        let single_selector = loop {
+            let single_selector = unsafe { self.vec.get_unchecked(id) };
+            //info!("del fd from epoll select, fd={:?}", fd);
+            // the fd is not ours if it's rejected as registered by another io
+            if single_selector.fds.remove(io_data) {
+                epoll_ctl(single_selector.epfd, EpollOp::EpollCtlDel, fd, &mut info).ok();
+                break single_selector;
+            }
+            // or it's moved by a hand over meanwhile
+            match io_data.sel.load(Ordering::Acquire) {
+                to if to == id => break single_selector,
+                to => id = to,
+            }
+        };
 
         // after EpollCtlDel push the unused event data
         single_selector.free_ev.push(io_data.deref().clone());
@@ -223,7 +349,7 @@ impl Selector {
     // register the io request to the timeout list
     #[inline]
     pub fn add_io_timer(&self, io: &IoData, timeout: Duration) {
-        let id = io.fd as usize % self.vec.len();
+        let id = io.sel.load(Ordering::Relaxed);
         // //info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
@@ -1,14 +1,16 @@
 use std::os::unix::io::RawFd;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::{io, ptr};
 
-use super::{timeout_handler, EventData, IoData, TimerList};
+use super::{timeout_handler, EventData, FdTable, IoData, TimerList};
 use crate::coroutine_impl::run_coroutine;
+use crate::io::Interest;
 use crate::scheduler::get_scheduler;
 use crate::std::queue::seg_queue::SegQueue as mpsc;
 use crate::timeout_list::{now, ns_to_dur};
+use parking_lot::Mutex;
 
 pub type SysEvent = libc::kevent;
 
@@ -32,6 +34,12 @@ struct SingleSelector {
     kqfd: RawFd,
     timer_list: TimerList,
     free_ev: mpsc<Arc<EventData>>,
+    // the registered fds, see `FdTable`
+    fds: FdTable,
+    // the selector that takes the new io when the worker is dead, !0 if none
+    redirect: AtomicUsize,
+    // the selectors of the dead workers whose timers are run by this one
+    adopted: Mutex<Vec<usize>>,
 }
 
 impl SingleSelector {
@@ -59,6 +67,9 @@ impl SingleSelector {
             kqfd: kqfd,
             free_ev: mpsc::new(),
             timer_list: TimerList::new(),
+            fds: FdTable::new(),
+            redirect: AtomicUsize::new(!0),
+            adopted: Mutex::new(Vec::new()),
         })
     }
 }
@@ -71,12 +82,15 @@ impl Drop for SingleSelector {
 
 pub struct Selector {
     vec: Vec<SingleSelector>,
+    // the new io is registered to the first `active` selectors only
+    active: AtomicUsize,
 }
 
 impl Selector {
     pub fn new(io_workers: usize) -> io::Result<Self> {
         let mut s = Selector {
             vec: Vec::with_capacity(io_workers),
+            active: AtomicUsize::new(io_workers),
         };
 
         for _ in 0..io_workers {
@@ -107,14 +121,20 @@ impl Selector {
             .unwrap_or(ptr::null_mut());
         // //info!("select; timeout={:?}", timeout_ms);
 
-        let mask = self.vec.len() + id;
         let single_selector = unsafe { self.vec.get_unchecked(id) };
         // first register thread handle
         let scheduler = get_scheduler();
-        scheduler
-            .workers
-            .parked
-            .fetch_or(mask as u64, Ordering::Relaxed);
+        scheduler.workers.park(id);
+        // the tasks queued before the park bit is set don't wake us, poll only
+        let zero = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let timeout = if scheduler.has_ready(id) {
+            &zero as *const _
+        } else {
+            timeout
+        };
 
         // Wait for epoll events for at most timeout_ms milliseconds
         let kqfd = single_selector.kqfd;
@@ -130,10 +150,7 @@ impl Selector {
         };
 
         // clear the park stat after comeback
-        scheduler
-            .workers
-            .parked
-            .fetch_and((mask - self.vec.len()) as u64, Ordering::Relaxed);
+        scheduler.workers.unpark(id);
 
         if n < 0 {
             return Err(io::Error::last_os_error());
@@ -153,6 +170,7 @@ impl Selector {
             let data = unsafe { &mut *(event.udata as *mut EventData) };
             // //info!("select got event, data={:p}", data);
             data.io_flag.store(true, Ordering::Release);
+            data.selectors.wake_all();
 
             // first check the atomic co, this may be grab by the worker first
             let co = match data.co.take() {
@@ -181,13 +199,98 @@ impl Selector {
         // free the unused event_data
         self.free_unused_event_data(id);
 
+        // the fds closed behind the back of the parked coroutines
+        single_selector.fds.check_closed();
+
         // deal with the timer list
-        let next_expire = single_selector
+        let now = now();
+        let mut next_expire = single_selector
             .timer_list
-            .schedule_timer(now(), &timeout_handler);
+            .schedule_timer(now, &timeout_handler);
+        // and the ones that are left by the dead workers
+        for &dead in single_selector.adopted.lock().iter() {
+            self.free_unused_event_data(dead);
+            let dead = unsafe { self.vec.get_unchecked(dead) };
+            if let Some(t) = dead.timer_list.schedule_timer(now, &timeout_handler) {
+                next_expire = Some(next_expire.map_or(t, |n| n.min(t)));
+            }
+        }
         Ok(next_expire)
     }
 
+    // move the io of the selector of a dead worker to the selector `to`,
+    // the new io goes to `to` and `to` runs the timers that are left. it's
+    // called by the thread of the dead worker after its event loop is gone
+    //
+    // the io is registered again for both filters, the interest that is set
+    // by `modify_fd` is not kept. the readiness that the fds already have is
+    // reported again by the added filters. an io that is added at the same
+    // time as the hand over may be left on the dead one
+    pub fn hand_over(&self, dead: usize, to: usize) {
+        let from = unsafe { self.vec.get_unchecked(dead) };
+        let into = unsafe { self.vec.get_unchecked(to) };
+        from.redirect.store(to, Ordering::Release);
+        // the selectors that the dead worker has adopted go along with it
+        let adopted = std::mem::take(&mut *from.adopted.lock());
+        for &id in adopted.iter() {
+            unsafe { self.vec.get_unchecked(id) }
+                .redirect
+                .store(to, Ordering::Release);
+        }
+        {
+            let mut into_adopted = into.adopted.lock();
+            into_adopted.push(dead);
+            into_adopted.extend(adopted);
+        }
+        from.fds.move_to(&into.fds, |data| {
+            let fd = data.fd;
+            let del = [
+                kevent!(fd, libc::EVFILT_READ, libc::EV_DELETE, ptr::null_mut()),
+                kevent!(fd, libc::EVFILT_WRITE, libc::EV_DELETE, ptr::null_mut()),
+            ];
+            let flags = libc::EV_ADD | libc::EV_CLEAR;
+            let udata = Arc::as_ptr(data);
+            let add = [
+                kevent!(fd, libc::EVFILT_READ, flags, udata),
+                kevent!(fd, libc::EVFILT_WRITE, flags, udata),
+            ];
+            let n = unsafe {
+                libc::kevent(from.kqfd, del.as_ptr(), 2, ptr::null_mut(), 0, ptr::null());
+                data.sel.store(to, Ordering::Release);
+                libc::kevent(into.kqfd, add.as_ptr(), 2, ptr::null_mut(), 0, ptr::null())
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                error!("can't move fd {} to selector {}: {}", fd, to, e);
+                data.wake_with_error(e);
+            }
+        });
+        self.wakeup(to);
+    }
+
+    // the selector is broken, wake all the coroutines parked on it with the error
+    pub fn fail_all(&self, id: usize, err: &io::Error) {
+        unsafe { self.vec.get_unchecked(id) }.fds.fail_all(err);
+    }
+
+    // register the new io to the first `n` selectors, the io that is already
+    // registered stays on its selector
+    #[inline]
+    pub fn set_active(&self, n: usize) {
+        self.active.store(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn next_id(&self, fd: usize) -> usize {
+        let id = fd % self.active.load(Ordering::Relaxed);
+        // the selector of a dead worker passes the new io on
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        match single_selector.redirect.load(Ordering::Acquire) {
+            to if to == !0 => id,
+            to => to,
+        }
+    }
+
     // this will post an os event so that we can wakeup the event loop
     #[inline]
     pub fn wakeup(&self, id: usize) {
@@ -210,8 +313,11 @@ impl Selector {
     #[inline]
     pub fn add_fd(&self, io_data: IoData) -> io::Result<IoData> {
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
-        let kqfd = unsafe { self.vec.get_unchecked(id) }.kqfd;
+        let id = self.next_id(fd as usize);
+        io_data.sel.store(id, Ordering::Relaxed);
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        single_selector.fds.insert(&io_data)?;
+        let kqfd = single_selector.kqfd;
         //info!("add fd to kqueue select, fd={:?}", fd);
 
         let flags = libc::EV_ADD | libc::EV_CLEAR;
@@ -238,6 +344,39 @@ impl Selector {
         Ok(io_data)
     }
 
+    // change the filters that the io is registered for, it stays on its
+    // selector. a filter that is added again fires for the readiness that the
+    // fd already has. the errors and the eof are reported by the filters
+    #[inline]
+    pub fn modify_fd(&self, io_data: &IoData, interest: Interest) -> io::Result<()> {
+        let fd = io_data.fd;
+        let id = io_data.sel.load(Ordering::Relaxed);
+        let kqfd = unsafe { self.vec.get_unchecked(id) }.kqfd;
+        let udata = io_data.as_ref() as *const _;
+        let filters = [
+            (libc::EVFILT_READ, interest.contains(Interest::READABLE)),
+            (libc::EVFILT_WRITE, interest.contains(Interest::WRITABLE)),
+        ];
+        for &(filter, on) in filters.iter() {
+            let flags = if on {
+                libc::EV_ADD | libc::EV_CLEAR
+            } else {
+                libc::EV_DELETE
+            };
+            let change = [kevent!(fd, filter, flags, udata)];
+            let n =
+                unsafe { libc::kevent(kqfd, change.as_ptr(), 1, ptr::null_mut(), 0, ptr::null()) };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                // the filter is already deleted
+                if on || err.raw_os_error() != Some(libc::ENOENT) {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn del_fd(&self, io_data: &IoData) {
         use std::ops::Deref;
@@ -253,9 +392,6 @@ impl Selector {
         });
 
         let fd = io_data.fd;
-        let id = fd as usize % self.vec.len();
-        let single_selector = unsafe { self.vec.get_unchecked(id) };
-        let kqfd = single_selector.kqfd;
         //info!("del fd from kqueue select, fd={:?}", fd);
 
         let filter = libc::EV_DELETE;
@@ -263,17 +399,30 @@ impl Selector {
             kevent!(fd, libc::EVFILT_READ, filter, ptr::null_mut()),
             kevent!(fd, libc::EVFILT_WRITE, filter, ptr::null_mut()),
         ];
-        // ignore the error
-        unsafe {
-            libc::kevent(
-                kqfd,
-                changes.as_ptr(),
-                changes.len() as libc::c_int,
-                ptr::null_mut(),
-                0,
-                ptr::null(),
-            );
-        }
+        let mut id = io_data.sel.load(Ordering::Acquire);
+        let single_selector = loop {
+            let single_selector = unsafe { self.vec.get_unchecked(id) };
+            // ignore the error, the fd is not ours if it's rejected as
+            // registered by another io
+            if single_selector.fds.remove(io_data) {
+                unsafe {
+                    libc::kevent(
+                        single_selector.kqfd,
+                        changes.as_ptr(),
+                        changes.len() as libc::c_int,
+                        ptr::null_mut(),
+                        0,
+                        ptr::null(),
+                    );
+                }
+                break single_selector;
+            }
+            // or it's moved by a hand over meanwhile
+            match io_data.sel.load(Ordering::Acquire) {
+                to if to == id => break single_selector,
+                to => id = to,
+            }
+        };
 
         // after EpollCtlDel push the unused event data
         single_selector.free_ev.push(io_data.deref().clone());
@@ -290,7 +439,7 @@ impl Selector {
     // register the io request to the timeout list
     #[inline]
     pub fn add_io_timer(&self, io: &IoData, timeout: Duration) {
-        let id = io.fd as usize % self.vec.len();
+        let id = io.sel.load(Ordering::Relaxed);
         // //info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
@@ -20,34 +20,68 @@ pub mod net;
 pub mod wait_io;
 
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
 use std::{fmt, io, ptr};
 
+use parking_lot::Mutex;
+
 use crate::coroutine_impl::{run_coroutine, CoroutineImpl};
-use crate::scheduler::get_scheduler;
+use crate::io::{report_driver_error, Interest};
+use crate::scheduler::{get_scheduler, Scheduler};
+use crate::select::WakerList;
 use crate::std::sync::AtomicOption;
-use crate::timeout_list::{TimeOutList, TimeoutHandle};
+use crate::timeout_list::{now, TimeOutList, TimeoutHandle};
 use crate::yield_now::{get_co_para, set_co_para};
 
 pub use self::select::{Selector, SysEvent};
 
 #[inline]
 pub fn add_socket<T: AsRawFd + ?Sized>(t: &T) -> io::Result<IoData> {
-    get_scheduler().get_selector().add_fd(IoData::new(t))
+    let sched = get_scheduler();
+    if sched.is_shutdown() {
+        return Err(runtime_shutdown());
+    }
+    let mut io = IoData::new(t);
+    // the io belongs to the runtime that registers it, and keeps it till
+    // the io is dropped
+    if let Some(data) = Arc::get_mut(&mut io.0) {
+        data.sched = sched;
+        sched.acquire();
+    }
+    sched.get_selector().add_fd(io)
+}
+
+// change the events that the io is registered for
+#[inline]
+fn modify_socket(io: &IoData, interest: Interest) -> io::Result<()> {
+    let sched = io.scheduler();
+    if sched.is_shutdown() {
+        return Err(runtime_shutdown());
+    }
+    sched.get_selector().modify_fd(io, interest)
 }
 
 #[inline]
 fn del_socket(io: &IoData) {
     // transfer the io to the selector
-    get_scheduler().get_selector().del_fd(io);
+    io.scheduler().get_selector().del_fd(io);
+}
+
+#[inline]
+fn runtime_shutdown() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "the runtime of the io is shut down")
 }
 
 // deal with the io result
 #[inline]
-fn co_io_result() -> io::Result<()> {
+fn co_io_result(io: &EventData) -> io::Result<()> {
+    if io.scheduler().is_shutdown() {
+        return Err(runtime_shutdown());
+    }
     match get_co_para() {
         None => Ok(()),
         Some(err) => Err(err),
@@ -86,6 +120,104 @@ fn timeout_handler(data: TimerData) {
     drop(data); // explicitly consume the data
 }
 
+// how often the parked fds are checked for being closed, in ns
+const CHECK_CLOSED_INTERVAL: u64 = 1_000_000_000;
+
+// the fds registered to one selector. it finds the coroutines parked on an fd
+// that is closed behind the back of its wrapper, which the os never reports,
+// and rejects a second wrapper of the same fd that would corrupt the interest
+// set of the first one
+pub struct FdTable {
+    fds: Mutex<HashMap<RawFd, Weak<EventData>>>,
+    last_check: AtomicU64,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        FdTable {
+            fds: Mutex::new(HashMap::new()),
+            last_check: AtomicU64::new(0),
+        }
+    }
+
+    // register the io, fail if another live wrapper holds the same fd
+    pub fn insert(&self, io: &IoData) -> io::Result<()> {
+        let mut fds = self.fds.lock();
+        if let Some(old) = fds.get(&io.fd) {
+            if old.strong_count() > 0 {
+                let msg = format!("fd {} is already registered by another io object", io.fd);
+                error!("{}", msg);
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+            }
+        }
+        fds.insert(io.fd, Arc::downgrade(&io.0));
+        Ok(())
+    }
+
+    // unregister the io, return false if the fd is not registered by it
+    pub fn remove(&self, io: &EventData) -> bool {
+        let mut fds = self.fds.lock();
+        match fds.get(&io.fd) {
+            Some(w) if ptr::eq(w.as_ptr(), io) => {
+                fds.remove(&io.fd);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // wake the coroutines parked on the closed fds with an error, it's
+    // called by the selector thread and runs at most once per interval.
+    // an fd that is closed and reused by a new file can't be detected
+    pub fn check_closed(&self) {
+        let now = now();
+        if now.saturating_sub(self.last_check.load(Ordering::Relaxed)) < CHECK_CLOSED_INTERVAL {
+            return;
+        }
+        self.last_check.store(now, Ordering::Relaxed);
+        let closed: Vec<_> = self
+            .fds
+            .lock()
+            .iter()
+            .filter_map(|(fd, w)| w.upgrade().map(|data| (*fd, data)))
+            .filter(|(fd, data)| {
+                (data.co.is_some() || !data.selectors.is_empty())
+                    && unsafe { libc::fcntl(*fd, libc::F_GETFD) } < 0
+            })
+            .collect();
+        for (fd, data) in closed {
+            let msg = format!("fd {} is closed while a coroutine is parked on it", fd);
+            let err = io::Error::new(io::ErrorKind::Other, msg);
+            report_driver_error(&err);
+            data.wake_with_error(err);
+        }
+    }
+
+    // move all the live io to the table of another selector, `f` registers
+    // each of them to that selector. it's done under the lock, a `remove` of
+    // the moved io waits for it and then finds the io gone
+    pub fn move_to<F: FnMut(&Arc<EventData>)>(&self, into: &FdTable, mut f: F) {
+        let mut fds = self.fds.lock();
+        for (fd, w) in fds.drain() {
+            if let Some(data) = w.upgrade() {
+                into.fds.lock().insert(fd, w);
+                f(&data);
+            }
+        }
+    }
+
+    // the selector is broken, wake all the parked coroutines with the error
+    pub fn fail_all(&self, err: &io::Error) {
+        let all: Vec<_> = self.fds.lock().values().filter_map(Weak::upgrade).collect();
+        for data in all {
+            data.wake_with_error(io::Error::new(
+                io::ErrorKind::Other,
+                format!("io driver error: {}", err),
+            ));
+        }
+    }
+}
+
 // the timeout data
 pub struct TimerData {
     event_data: *mut EventData,
@@ -101,6 +233,12 @@ pub struct EventData {
     pub io_flag: AtomicBool,
     pub timer: RefCell<Option<TimerHandle>>,
     pub co: AtomicOption<CoroutineImpl>,
+    // the selector that the fd is registered to
+    pub sel: AtomicUsize,
+    // the selects waiting for the readiness, see `net::SocketReady`
+    pub selectors: WakerList,
+    // the scheduler that the fd is registered to, null for the default one
+    sched: *const Scheduler,
 }
 
 unsafe impl Send for EventData {}
@@ -114,6 +252,23 @@ impl EventData {
             io_flag: AtomicBool::new(false),
             timer: RefCell::new(None),
             co: AtomicOption::none(),
+            sel: AtomicUsize::new(0),
+            selectors: WakerList::new(),
+            sched: ptr::null(),
+        }
+    }
+
+    // the io is ready, or the runtime is shut down so the op returns an error
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.io_flag.load(Ordering::Acquire) || self.scheduler().is_shutdown()
+    }
+
+    #[inline]
+    fn scheduler(&self) -> &'static Scheduler {
+        match unsafe { self.sched.as_ref() } {
+            Some(s) => s,
+            None => get_scheduler(),
         }
     }
 
@@ -123,6 +278,24 @@ impl EventData {
         }
     }
 
+    // resume the parked coroutine with the error, if any
+    fn wake_with_error(&self, err: io::Error) {
+        self.selectors.wake_all();
+        let mut co = match self.co.take() {
+            None => return,
+            Some(co) => co,
+        };
+        if let Some(h) = self.timer.borrow_mut().take() {
+            unsafe {
+                // tell the timer function not to cancel the io
+                h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
+            }
+            h.remove();
+        }
+        set_co_para(&mut co, err);
+        run_coroutine(co);
+    }
+
     #[inline]
     pub fn schedule(&self) {
         //info!("event schedul");
@@ -180,6 +353,7 @@ impl fmt::Debug for IoData {
 impl Drop for IoData {
     fn drop(&mut self) {
         del_socket(self);
+        unsafe { Scheduler::release(self.sched) };
     }
 }
 
@@ -1,8 +1,18 @@
 mod socket_read;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+mod socket_sendfile;
 mod socket_write;
 mod socket_write_vectored;
 mod tcp_listener_accpet;
 mod tcp_stream_connect;
+#[cfg(target_os = "linux")]
+mod udp_mmsg;
 mod udp_recv_from;
 mod udp_send_to;
 mod unix_listener_accpet;
@@ -10,11 +20,21 @@ mod unix_recv_from;
 mod unix_send_to;
 mod unix_stream_connect;
 
-pub use self::socket_read::SocketRead;
+pub use self::socket_read::{recv_uninit, SocketRead};
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub use self::socket_sendfile::{sendfile, SocketSendFile};
 pub use self::socket_write::SocketWrite;
 pub use self::socket_write_vectored::SocketWriteVectored;
 pub use self::tcp_listener_accpet::TcpListenerAccept;
 pub use self::tcp_stream_connect::TcpStreamConnect;
+#[cfg(target_os = "linux")]
+pub use self::udp_mmsg::{recv_mmsg, send_mmsg, set_gro, set_gso, UdpRecvMmsg, UdpSendMmsg};
 pub use self::udp_recv_from::UdpRecvFrom;
 pub use self::udp_send_to::UdpSendTo;
 pub use self::unix_listener_accpet::UnixListenerAccept;
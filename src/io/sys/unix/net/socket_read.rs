@@ -1,46 +1,99 @@
 use std::io;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
-use super::super::{co_io_result, from_nix_error, IoData};
+use super::super::{co_io_result, IoData};
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
 use crate::io::AsIoData;
 use crate::scheduler::get_scheduler;
 use crate::yield_now::yield_with;
-use nix::unistd::read;
+
+/// `recv` into a buffer that is not initialized, only the bytes of the
+/// returned count are written
+pub fn recv_uninit(fd: libc::c_int, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+    let ret = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
 
 pub struct SocketRead<'a> {
     io_data: &'a IoData,
-    buf: &'a mut [u8],
+    // the buffer may be uninitialized, it's only written by the reads
+    buf: *mut u8,
+    len: usize,
     timeout: Option<Duration>,
+    // leave the data in the socket
+    peek: bool,
+    _buf: PhantomData<&'a mut [u8]>,
 }
 
 impl<'a> SocketRead<'a> {
     pub fn new<T: AsIoData>(s: &'a T, buf: &'a mut [u8], timeout: Option<Duration>) -> Self {
         SocketRead {
             io_data: s.as_io_data(),
-            buf,
+            buf: buf.as_mut_ptr(),
+            len: buf.len(),
+            timeout,
+            peek: false,
+            _buf: PhantomData,
+        }
+    }
+
+    /// the read into a buffer that is not initialized, only the bytes of
+    /// the returned count are written
+    pub fn uninit<T: AsIoData>(
+        s: &'a T,
+        buf: &'a mut [MaybeUninit<u8>],
+        timeout: Option<Duration>,
+    ) -> Self {
+        SocketRead {
+            io_data: s.as_io_data(),
+            buf: buf.as_mut_ptr() as *mut u8,
+            len: buf.len(),
             timeout,
+            peek: false,
+            _buf: PhantomData,
+        }
+    }
+
+    /// the read of `MSG_PEEK`, it waits for the same readiness as the read
+    pub fn peek<T: AsIoData>(s: &'a T, buf: &'a mut [u8], timeout: Option<Duration>) -> Self {
+        SocketRead {
+            peek: true,
+            ..Self::new(s, buf, timeout)
         }
     }
 
     pub fn done(&mut self) -> io::Result<usize> {
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
 
             // finish the read operation
-            match read(self.io_data.fd, self.buf) {
-                Ok(n) => return Ok(n),
-                Err(e) => {
-                    if e == nix::Error::Sys(nix::errno::Errno::EAGAIN) {
-                        // do nothing
-                    } else {
-                        return Err(from_nix_error(e));
-                    }
+            let ret = unsafe {
+                if self.peek {
+                    libc::recv(
+                        self.io_data.fd,
+                        self.buf as *mut libc::c_void,
+                        self.len,
+                        libc::MSG_PEEK,
+                    )
+                } else {
+                    libc::read(self.io_data.fd, self.buf as *mut libc::c_void, self.len)
                 }
+            };
+            if ret >= 0 {
+                return Ok(ret as usize);
+            }
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EAGAIN) {
+                return Err(e);
             }
 
             if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
@@ -72,7 +125,7 @@ impl<'a> EventSource for SocketRead<'a> {
         // till here the io may be done in other thread
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             return io_data.schedule();
         }
 
@@ -0,0 +1,146 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use super::super::{co_io_result, IoData};
+use crate::coroutine_impl::{CoroutineImpl, EventSource};
+use crate::io::AsIoData;
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+#[inline]
+fn would_block(e: &io::Error) -> bool {
+    // raw_os_error is faster than kind
+    let raw_err = e.raw_os_error();
+    raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK)
+}
+
+/// send at most `len` bytes of the file from `offset` to the socket,
+/// the file offset is not changed
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn sendfile(sock: RawFd, file: RawFd, offset: u64, len: usize) -> io::Result<usize> {
+    let mut off = offset as libc::off_t;
+    let ret = unsafe { libc::sendfile(sock, file, &mut off, len) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+/// send at most `len` bytes of the file from `offset` to the socket,
+/// the file offset is not changed
+#[cfg(target_os = "freebsd")]
+pub fn sendfile(sock: RawFd, file: RawFd, offset: u64, len: usize) -> io::Result<usize> {
+    let mut sent: libc::off_t = 0;
+    let ret = unsafe {
+        libc::sendfile(
+            file,
+            sock,
+            offset as libc::off_t,
+            len,
+            std::ptr::null_mut(),
+            &mut sent,
+            0,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        // a partial send is reported with EAGAIN
+        if sent == 0 || !would_block(&e) {
+            return Err(e);
+        }
+    }
+    Ok(sent as usize)
+}
+
+/// send at most `len` bytes of the file from `offset` to the socket,
+/// the file offset is not changed
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub fn sendfile(sock: RawFd, file: RawFd, offset: u64, len: usize) -> io::Result<usize> {
+    let mut sent = len as libc::off_t;
+    let ret = unsafe {
+        libc::sendfile(
+            file,
+            sock,
+            offset as libc::off_t,
+            &mut sent,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        // a partial send is reported with EAGAIN
+        if sent == 0 || !would_block(&e) {
+            return Err(e);
+        }
+    }
+    Ok(sent as usize)
+}
+
+pub struct SocketSendFile<'a> {
+    io_data: &'a IoData,
+    file: RawFd,
+    offset: u64,
+    len: usize,
+    timeout: Option<Duration>,
+}
+
+impl<'a> SocketSendFile<'a> {
+    pub fn new<T: AsIoData>(
+        s: &'a T,
+        file: RawFd,
+        offset: u64,
+        len: usize,
+        timeout: Option<Duration>,
+    ) -> Self {
+        SocketSendFile {
+            io_data: s.as_io_data(),
+            file,
+            offset,
+            len,
+            timeout,
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result(&self.io_data)?;
+
+            // clear the io_flag
+            self.io_data.io_flag.store(false, Ordering::Relaxed);
+
+            match sendfile(self.io_data.fd, self.file, self.offset, self.len) {
+                Ok(n) => return Ok(n),
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a> EventSource for SocketSendFile<'a> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, dur);
+        }
+        self.io_data.co.swap(co);
+
+        // there is event, re-run the coroutine
+        if io_data.is_ready() {
+            io_data.schedule();
+        }
+    }
+}
@@ -26,7 +26,7 @@ impl<'a> SocketWrite<'a> {
 
     pub fn done(&mut self) -> io::Result<usize> {
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
@@ -64,7 +64,7 @@ impl<'a> EventSource for SocketWrite<'a> {
         self.io_data.co.swap(co);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             io_data.schedule();
         }
     }
@@ -34,7 +34,7 @@ impl<'a> SocketWriteVectored<'a> {
         use std::io::Write;
 
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
@@ -73,7 +73,7 @@ impl<'a> EventSource for SocketWriteVectored<'a> {
         self.io_data.co.swap(co);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             io_data.schedule();
         }
     }
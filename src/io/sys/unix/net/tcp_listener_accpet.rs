@@ -23,7 +23,7 @@ impl<'a> TcpListenerAccept<'a> {
 
     pub fn done(&mut self) -> io::Result<(TcpStream, SocketAddr)> {
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
@@ -63,7 +63,7 @@ impl<'a> EventSource for TcpListenerAccept<'a> {
         self.io_data.co.swap(co);
 
         // there is event happened
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             return io_data.schedule();
         }
 
@@ -75,7 +75,7 @@ impl TcpStreamConnect {
         }
 
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
@@ -114,7 +114,7 @@ impl EventSource for TcpStreamConnect {
         io_data.co.swap(co);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             return io_data.schedule();
         }
 
@@ -0,0 +1,266 @@
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::{self, io, mem, ptr};
+
+use socket2::SockAddr;
+
+use super::super::{co_io_result, IoData};
+use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
+use crate::io::AsIoData;
+use crate::net::{MsgBuf, OutMsg, UdpSocket};
+use crate::scheduler::get_scheduler;
+use crate::yield_now::yield_with;
+
+// the max datagrams of one syscall, the headers live on the coroutine stack
+const MMSG_BATCH: usize = 16;
+
+// not exported by libc for all the linux targets
+const UDP_SEGMENT: libc::c_int = 103;
+const UDP_GRO: libc::c_int = 104;
+
+#[inline]
+fn would_block(e: &io::Error) -> bool {
+    // raw_os_error is faster than kind
+    let raw_err = e.raw_os_error();
+    raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK)
+}
+
+/// receive up to `MMSG_BATCH` datagrams with one `recvmmsg`
+///
+/// it only blocks for the first datagram if the socket is blocking
+pub fn recv_mmsg(socket: &std::net::UdpSocket, msgs: &mut [MsgBuf]) -> io::Result<usize> {
+    let n = msgs.len().min(MMSG_BATCH);
+    if n == 0 {
+        return Ok(0);
+    }
+    let mut iovs: [libc::iovec; MMSG_BATCH] = unsafe { mem::zeroed() };
+    let mut addrs: [libc::sockaddr_storage; MMSG_BATCH] = unsafe { mem::zeroed() };
+    let mut hdrs: [libc::mmsghdr; MMSG_BATCH] = unsafe { mem::zeroed() };
+    for (((msg, iov), addr), hdr) in msgs
+        .iter_mut()
+        .zip(iovs.iter_mut())
+        .zip(addrs.iter_mut())
+        .zip(hdrs.iter_mut())
+    {
+        iov.iov_base = msg.buf.as_mut_ptr() as *mut libc::c_void;
+        iov.iov_len = msg.buf.len();
+        hdr.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+        hdr.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        hdr.msg_hdr.msg_iov = iov as *mut libc::iovec;
+        hdr.msg_hdr.msg_iovlen = 1;
+    }
+
+    let ret = unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            hdrs.as_mut_ptr(),
+            n as libc::c_uint,
+            libc::MSG_WAITFORONE,
+            ptr::null_mut(),
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let ret = ret as usize;
+    for ((msg, addr), hdr) in msgs.iter_mut().zip(addrs.iter()).zip(hdrs.iter()).take(ret) {
+        msg.len = hdr.msg_len as usize;
+        msg.addr = unsafe { SockAddr::new(*addr, hdr.msg_hdr.msg_namelen) }.as_socket();
+    }
+    Ok(ret)
+}
+
+/// send up to `MMSG_BATCH` datagrams with one `sendmmsg`
+pub fn send_mmsg(socket: &std::net::UdpSocket, msgs: &[OutMsg]) -> io::Result<usize> {
+    let n = msgs.len().min(MMSG_BATCH);
+    if n == 0 {
+        return Ok(0);
+    }
+    let mut iovs: [libc::iovec; MMSG_BATCH] = unsafe { mem::zeroed() };
+    let mut addrs: [libc::sockaddr_storage; MMSG_BATCH] = unsafe { mem::zeroed() };
+    let mut hdrs: [libc::mmsghdr; MMSG_BATCH] = unsafe { mem::zeroed() };
+    for (((msg, iov), addr), hdr) in msgs
+        .iter()
+        .zip(iovs.iter_mut())
+        .zip(addrs.iter_mut())
+        .zip(hdrs.iter_mut())
+    {
+        iov.iov_base = msg.buf.as_ptr() as *mut libc::c_void;
+        iov.iov_len = msg.buf.len();
+        hdr.msg_hdr.msg_iov = iov as *mut libc::iovec;
+        hdr.msg_hdr.msg_iovlen = 1;
+        if let Some(to) = msg.addr {
+            let to = SockAddr::from(to);
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    to.as_ptr() as *const u8,
+                    addr as *mut _ as *mut u8,
+                    to.len() as usize,
+                );
+            }
+            hdr.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+            hdr.msg_hdr.msg_namelen = to.len();
+        }
+    }
+
+    let ret =
+        unsafe { libc::sendmmsg(socket.as_raw_fd(), hdrs.as_mut_ptr(), n as libc::c_uint, 0) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+fn set_udp_opt(socket: &std::net::UdpSocket, opt: libc::c_int, val: libc::c_int) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            opt,
+            &val as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// let the kernel coalesce the incoming datagrams of a flow
+pub fn set_gro(socket: &std::net::UdpSocket, on: bool) -> io::Result<()> {
+    set_udp_opt(socket, UDP_GRO, on as libc::c_int)
+}
+
+/// let the kernel split each sent buffer into datagrams of `segment_size`,
+/// 0 turns it off
+pub fn set_gso(socket: &std::net::UdpSocket, segment_size: u16) -> io::Result<()> {
+    set_udp_opt(socket, UDP_SEGMENT, segment_size as libc::c_int)
+}
+
+pub struct UdpRecvMmsg<'a, 'b> {
+    io_data: &'a IoData,
+    msgs: &'a mut [MsgBuf<'b>],
+    socket: &'a std::net::UdpSocket,
+    timeout: Option<Duration>,
+}
+
+impl<'a, 'b> UdpRecvMmsg<'a, 'b> {
+    pub fn new(socket: &'a UdpSocket, msgs: &'a mut [MsgBuf<'b>]) -> Self {
+        UdpRecvMmsg {
+            io_data: socket.as_io_data(),
+            msgs,
+            socket: socket.inner(),
+            timeout: socket.read_timeout().unwrap(),
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result(&self.io_data)?;
+
+            // clear the io_flag
+            self.io_data.io_flag.store(false, Ordering::Relaxed);
+
+            match recv_mmsg(self.socket, self.msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a, 'b> EventSource for UdpRecvMmsg<'a, 'b> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let handle = co_get_handle(&co);
+        let cancel = handle.get_cancel();
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, dur);
+        }
+        self.io_data.co.swap(co);
+
+        // there is event, re-run the coroutine
+        if io_data.is_ready() {
+            return io_data.schedule();
+        }
+
+        // register the cancel io data
+        cancel.set_io(io_data);
+        // re-check the cancel status
+        if cancel.is_canceled() {
+            let _ = cancel.cancel();
+        }
+    }
+}
+
+pub struct UdpSendMmsg<'a, 'b> {
+    io_data: &'a IoData,
+    msgs: &'a [OutMsg<'b>],
+    socket: &'a std::net::UdpSocket,
+    timeout: Option<Duration>,
+}
+
+impl<'a, 'b> UdpSendMmsg<'a, 'b> {
+    pub fn new(socket: &'a UdpSocket, msgs: &'a [OutMsg<'b>]) -> Self {
+        UdpSendMmsg {
+            io_data: socket.as_io_data(),
+            msgs,
+            socket: socket.inner(),
+            timeout: socket.write_timeout().unwrap(),
+        }
+    }
+
+    pub fn done(&mut self) -> io::Result<usize> {
+        loop {
+            co_io_result(&self.io_data)?;
+
+            // clear the io_flag
+            self.io_data.io_flag.store(false, Ordering::Relaxed);
+
+            match send_mmsg(self.socket, self.msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) if would_block(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            if self.io_data.io_flag.swap(false, Ordering::Relaxed) {
+                continue;
+            }
+
+            // the result is still WouldBlock, need to try again
+            yield_with(self);
+        }
+    }
+}
+
+impl<'a, 'b> EventSource for UdpSendMmsg<'a, 'b> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            get_scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, dur);
+        }
+        self.io_data.co.swap(co);
+
+        // there is event, re-run the coroutine
+        if io_data.is_ready() {
+            io_data.schedule();
+        }
+    }
+}
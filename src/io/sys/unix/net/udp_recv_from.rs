@@ -15,6 +15,8 @@ pub struct UdpRecvFrom<'a> {
     buf: &'a mut [u8],
     socket: &'a std::net::UdpSocket,
     timeout: Option<Duration>,
+    // leave the datagram in the socket
+    peek: bool,
 }
 
 impl<'a> UdpRecvFrom<'a> {
@@ -24,17 +26,31 @@ impl<'a> UdpRecvFrom<'a> {
             buf,
             socket: socket.inner(),
             timeout: socket.read_timeout().unwrap(),
+            peek: false,
+        }
+    }
+
+    /// the `peek_from`, it waits for the same readiness as the `recv_from`
+    pub fn peek(socket: &'a UdpSocket, buf: &'a mut [u8]) -> Self {
+        UdpRecvFrom {
+            peek: true,
+            ..Self::new(socket, buf)
         }
     }
 
     pub fn done(&mut self) -> io::Result<(usize, SocketAddr)> {
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
 
-            match self.socket.recv_from(self.buf) {
+            let ret = if self.peek {
+                self.socket.peek_from(self.buf)
+            } else {
+                self.socket.recv_from(self.buf)
+            };
+            match ret {
                 Ok(n) => return Ok(n),
                 Err(e) => {
                     // raw_os_error is faster than kind
@@ -71,7 +87,7 @@ impl<'a> EventSource for UdpRecvFrom<'a> {
         self.io_data.co.swap(co);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             return io_data.schedule();
         }
 
@@ -31,7 +31,7 @@ impl<'a> UnixSendTo<'a> {
 
     pub fn done(&mut self) -> io::Result<usize> {
         loop {
-            co_io_result()?;
+            co_io_result(&self.io_data)?;
 
             // clear the io_flag
             self.io_data.io_flag.store(false, Ordering::Relaxed);
@@ -71,7 +71,7 @@ impl<'a> EventSource for UnixSendTo<'a> {
         self.io_data.co.swap(co);
 
         // there is event, re-run the coroutine
-        if io_data.io_flag.load(Ordering::Acquire) {
+        if io_data.is_ready() {
             io_data.schedule();
         }
     }
@@ -3,6 +3,7 @@
 //! context to wait on the io events
 //!
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use crate::cancel::Cancel;
 use crate::coroutine_impl::{co_get_handle, CoroutineImpl, EventSource};
@@ -44,6 +45,47 @@ impl<'a> EventSource for RawIoBlock<'a> {
     }
 }
 
+// wait for an event of the io for at most the timeout, it's canceled like
+// the reads and the writes
+pub(crate) struct PollOnce<'a> {
+    io_data: &'a io_impl::IoData,
+    timeout: Option<Duration>,
+}
+
+impl<'a> PollOnce<'a> {
+    pub(crate) fn new(io_data: &'a io_impl::IoData, timeout: Option<Duration>) -> Self {
+        PollOnce { io_data, timeout }
+    }
+}
+
+impl<'a> EventSource for PollOnce<'a> {
+    fn subscribe(&mut self, co: CoroutineImpl) {
+        let handle = co_get_handle(&co);
+        let cancel = handle.get_cancel();
+        let io_data = (*self.io_data).clone();
+
+        if let Some(dur) = self.timeout {
+            io_data
+                .scheduler()
+                .get_selector()
+                .add_io_timer(self.io_data, dur);
+        }
+        self.io_data.co.swap(co);
+
+        // there is event, re-run the coroutine
+        if io_data.is_ready() {
+            return io_data.schedule();
+        }
+
+        // register the cancel io data
+        cancel.set_io(io_data);
+        // re-check the cancel status
+        if cancel.is_canceled() {
+            let _ = cancel.cancel();
+        }
+    }
+}
+
 /// This is trait that can block on io events but doing nothong about io
 pub trait WaitIo {
     /// reset the io before io operation
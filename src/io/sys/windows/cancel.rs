@@ -1,7 +1,5 @@
 use std::io;
 use std::io::ErrorKind;
-use std::panic::catch_unwind;
-use std::sync::LockResult;
 
 use crate::cancel::CancelIo;
 use crate::std::sync::Mutex;
@@ -66,21 +64,14 @@ impl CancelIo for CancelIoImpl {
     }
 
     fn cancel(&self) -> Result<(), std::io::Error> {
-        match self.0.lock() {
-            Ok(mut v) => {
-                v.take().map(|d| match d.cancel() {
-                    Ok(_) => {
-                        return Ok(());
-                    }
-                    Err(e) => {
-                        return Err("failed to get CancelIo lock".to_string());
-                    }
-                });
-                return Ok(());
-            }
-            Err(e) => {
-                return Err(std::io::Error::new(ErrorKind::Other, e.to_string()));
-            }
+        let data = self
+            .0
+            .lock()
+            .map_err(|e| std::io::Error::new(ErrorKind::Other, e.to_string()))?
+            .take();
+        match data {
+            Some(d) => d.cancel(),
+            None => Ok(()),
         }
     }
 }
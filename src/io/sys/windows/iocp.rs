@@ -1,6 +1,6 @@
 use std::cell::UnsafeCell;
 use std::os::windows::io::AsRawSocket;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 use std::{io, ptr};
 
@@ -9,7 +9,7 @@ use crate::scheduler::get_scheduler;
 use crate::timeout_list::{now, ns_to_dur, TimeOutList, TimeoutHandle};
 use crate::yield_now::set_co_para;
 use miow::iocp::{CompletionPort, CompletionStatus};
-use smallvec::SmallVec;
+use parking_lot::Mutex;
 use winapi::shared::ntdef::*;
 use winapi::shared::ntstatus::STATUS_CANCELLED;
 use winapi::shared::winerror::*;
@@ -66,10 +66,17 @@ impl EventData {
 // buffer to receive the system events
 pub type SysEvent = CompletionStatus;
 
+// the longest wait of a selector that polls the ports of the dead workers
+const ADOPTED_POLL: Duration = Duration::from_millis(10);
+
 struct SingleSelector {
     /// The actual completion port that's used to manage all I/O
     port: CompletionPort,
     timer_list: TimerList,
+    // the selector that takes the new io when the worker is dead, !0 if none
+    redirect: AtomicUsize,
+    // the selectors of the dead workers that are polled by this one
+    adopted: Mutex<Vec<usize>>,
 }
 
 impl SingleSelector {
@@ -78,18 +85,23 @@ impl SingleSelector {
         CompletionPort::new(1).map(|cp| SingleSelector {
             port: cp,
             timer_list: TimerList::new(),
+            redirect: AtomicUsize::new(!0),
+            adopted: Mutex::new(Vec::new()),
         })
     }
 }
 
 pub struct Selector {
     vec: Vec<SingleSelector>,
+    // the new io is registered to the first `active` selectors only
+    active: AtomicUsize,
 }
 
 impl Selector {
     pub fn new(io_workers: usize) -> io::Result<Self> {
         let mut s = Selector {
             vec: Vec::with_capacity(io_workers),
+            active: AtomicUsize::new(io_workers),
         };
 
         for _ in 0..io_workers {
@@ -108,13 +120,22 @@ impl Selector {
     ) -> io::Result<Option<u64>> {
         let timeout = timeout.map(ns_to_dur);
         // //info!("select; timeout={:?}", timeout);
-        let mask = self.vec.len() + id;
         let single_selector = unsafe { self.vec.get_unchecked(id) };
+        let adopted = single_selector.adopted.lock().clone();
+        // the ports of the dead workers are polled between the waits
+        let timeout = if adopted.is_empty() {
+            timeout
+        } else {
+            Some(timeout.map_or(ADOPTED_POLL, |t| t.min(ADOPTED_POLL)))
+        };
         let scheduler = get_scheduler();
-        scheduler
-            .workers
-            .parked
-            .fetch_or(mask as u64, Ordering::Relaxed);
+        scheduler.workers.park(id);
+        // the tasks queued before the park bit is set don't wake us, poll only
+        let timeout = if scheduler.has_ready(id) {
+            Some(Duration::from_millis(0))
+        } else {
+            timeout
+        };
         let n = match single_selector.port.get_many(events, timeout) {
             Ok(statuses) => statuses.len(),
             Err(ref e) if e.raw_os_error() == Some(WAIT_TIMEOUT as i32) => 0,
@@ -122,84 +143,83 @@ impl Selector {
         };
 
         // clear the park stat after comeback
-        scheduler
-            .workers
-            .parked
-            .fetch_and((mask - self.vec.len()) as u64, Ordering::Relaxed);
-
-        for status in events[..n].iter() {
-            // need to check the status for each io
-            let overlapped = status.overlapped();
-            if overlapped.is_null() {
-                // this is just a wakeup event, ignore it
-                continue;
-            }
-
-            let data = unsafe { &mut *(overlapped as *mut EventData) };
-            // when cancel failed the coroutine will continue to finish
-            // it's unsafe to ref any local stack value!
-            // if cancel not take the coroutine, then it's possible that
-            // the coroutine will never come back because there is no event
-            let mut co = data.co.take().expect("can't get co in selector");
-            co.prefetch();
-
-            // it's safe to remove the timer since we are
-            // running the timer_list in the same thread
-            // this is not true when running in multi-thread environment
-            data.timer.take().map(|h| {
-                unsafe {
-                    // tell the timer function not to cancel the io
-                    // it's not always true that you can really remove the timer entry
-                    // it's safe in multi-thread env because it only access its own data
-                    h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
-                }
-                // NOT SAFE for multi-thread!!
-                h.remove()
-            });
-
-            let overlapped = unsafe { &*overlapped };
-            // //info!("select got overlapped, status = {}", overlapped.Internal);
-
-            const STATUS_CANCELLED_U32: u32 = STATUS_CANCELLED as u32;
-            // check the status
-            match overlapped.Internal as u32 {
-                ERROR_OPERATION_ABORTED | STATUS_CANCELLED_U32 => {
-                    //warn!("coroutine timeout, stat=0x{:x}", overlapped.Internal);
-                    set_co_para(&mut co, io::Error::new(io::ErrorKind::TimedOut, "timeout"));
-                    // timer data is popped already
-                }
-                NO_ERROR => {
-                    // do nothing here
-                    // need a way to detect timeout, it's not safe to del timer here
-                    // according to windows API it's can't cancel the completed io operation
-                    // the timeout function would remove the timer handle
-                }
-                err => {
-                    error!("iocp err=0x{:08x}", err);
-                    unsafe {
-                        // convert the ntstatus to winerr
-                        let mut size: u32 = 0;
-                        let o = overlapped as *const _ as *mut _;
-                        GetOverlappedResult(data.handle, o, &mut size, i32::from(FALSE));
-                    }
-                    set_co_para(&mut co, io::Error::last_os_error());
-                }
-            }
-
-            // schedule the coroutine
-            run_coroutine(co);
+        scheduler.workers.unpark(id);
+
+        run_events(&events[..n]);
+
+        // the completions of the sockets registered on the dead workers
+        for &dead in adopted.iter() {
+            let dead = unsafe { self.vec.get_unchecked(dead) };
+            let n = match dead.port.get_many(events, Some(Duration::from_millis(0))) {
+                Ok(statuses) => statuses.len(),
+                Err(ref e) if e.raw_os_error() == Some(WAIT_TIMEOUT as i32) => 0,
+                Err(e) => return Err(e),
+            };
+            run_events(&events[..n]);
         }
 
         // run all the local tasks
         scheduler.run_queued_tasks(id);
 
         // deal with the timer list
-        let next_expire = single_selector
+        let now = now();
+        let mut next_expire = single_selector
             .timer_list
-            .schedule_timer(now(), &timeout_handler);
+            .schedule_timer(now, &timeout_handler);
+        // and the ones that are left by the dead workers
+        for &dead in adopted.iter() {
+            let dead = unsafe { self.vec.get_unchecked(dead) };
+            if let Some(t) = dead.timer_list.schedule_timer(now, &timeout_handler) {
+                next_expire = Some(next_expire.map_or(t, |n| n.min(t)));
+            }
+        }
         Ok(next_expire)
     }
 
+    // let the selector `to` poll the port of a dead worker and run its
+    // timers, the new io goes to `to`. the sockets can't be associated with
+    // another port, so their completions are still posted to the dead one
+    pub fn hand_over(&self, dead: usize, to: usize) {
+        let from = unsafe { self.vec.get_unchecked(dead) };
+        let into = unsafe { self.vec.get_unchecked(to) };
+        from.redirect.store(to, Ordering::Release);
+        // the selectors that the dead worker has adopted go along with it
+        let adopted = std::mem::take(&mut *from.adopted.lock());
+        for &id in adopted.iter() {
+            unsafe { self.vec.get_unchecked(id) }
+                .redirect
+                .store(to, Ordering::Release);
+        }
+        {
+            let mut into_adopted = into.adopted.lock();
+            into_adopted.push(dead);
+            into_adopted.extend(adopted);
+        }
+        self.wakeup(to);
+    }
+
+    // the iocp reports the errors with the completion of each operation,
+    // there is nothing parked on the port itself
+    pub fn fail_all(&self, _id: usize, _err: &io::Error) {}
+
+    // register the new io to the first `n` selectors, the io that is already
+    // registered stays on its selector
+    #[inline]
+    pub fn set_active(&self, n: usize) {
+        self.active.store(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn next_id(&self, fd: usize) -> usize {
+        let id = fd % self.active.load(Ordering::Relaxed);
+        // the selector of a dead worker passes the new io on
+        let single_selector = unsafe { self.vec.get_unchecked(id) };
+        match single_selector.redirect.load(Ordering::Acquire) {
+            to if to == !0 => id,
+            to => to,
+        }
+    }
+
     // this will post an os event so that we can wakeup the event loop
     #[inline]
     pub fn wakeup(&self, id: usize) {
@@ -215,14 +235,14 @@ impl Selector {
     pub fn add_socket<T: AsRawSocket + ?Sized>(&self, t: &T) -> io::Result<()> {
         // the token para is not used, just pass the handle
         let fd = (t.as_raw_socket() as usize) >> 2;
-        let id = fd as usize % self.vec.len();
+        let id = self.next_id(fd);
         unsafe { self.vec.get_unchecked(id) }.port.add_socket(fd, t)
     }
 
     // register the io request to the timeout list
     #[inline]
     pub fn add_io_timer(&self, io: &mut EventData, timeout: Duration) {
-        let id = (io.handle as usize % self.vec.len()) >> 2;
+        let id = self.next_id(io.handle as usize >> 2);
         // //info!("io timeout = {:?}", dur);
         let (h, b_new) = unsafe { self.vec.get_unchecked(id) }
             .timer_list
@@ -235,6 +255,72 @@ impl Selector {
     }
 }
 
+// complete the io of the events
+fn run_events(events: &[SysEvent]) {
+    for status in events.iter() {
+        // need to check the status for each io
+        let overlapped = status.overlapped();
+        if overlapped.is_null() {
+            // this is just a wakeup event, ignore it
+            continue;
+        }
+
+        let data = unsafe { &mut *(overlapped as *mut EventData) };
+        // when cancel failed the coroutine will continue to finish
+        // it's unsafe to ref any local stack value!
+        // if cancel not take the coroutine, then it's possible that
+        // the coroutine will never come back because there is no event
+        let mut co = data.co.take().expect("can't get co in selector");
+        co.prefetch();
+
+        // it's safe to remove the timer since we are
+        // running the timer_list in the same thread
+        // this is not true when running in multi-thread environment
+        data.timer.take().map(|h| {
+            unsafe {
+                // tell the timer function not to cancel the io
+                // it's not always true that you can really remove the timer entry
+                // it's safe in multi-thread env because it only access its own data
+                h.with_mut_data(|value| value.data.event_data = ptr::null_mut());
+            }
+            // NOT SAFE for multi-thread!!
+            h.remove()
+        });
+
+        let overlapped = unsafe { &*overlapped };
+        // //info!("select got overlapped, status = {}", overlapped.Internal);
+
+        const STATUS_CANCELLED_U32: u32 = STATUS_CANCELLED as u32;
+        // check the status
+        match overlapped.Internal as u32 {
+            ERROR_OPERATION_ABORTED | STATUS_CANCELLED_U32 => {
+                //warn!("coroutine timeout, stat=0x{:x}", overlapped.Internal);
+                set_co_para(&mut co, io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+                // timer data is popped already
+            }
+            NO_ERROR => {
+                // do nothing here
+                // need a way to detect timeout, it's not safe to del timer here
+                // according to windows API it's can't cancel the completed io operation
+                // the timeout function would remove the timer handle
+            }
+            err => {
+                error!("iocp err=0x{:08x}", err);
+                unsafe {
+                    // convert the ntstatus to winerr
+                    let mut size: u32 = 0;
+                    let o = overlapped as *const _ as *mut _;
+                    GetOverlappedResult(data.handle, o, &mut size, i32::from(FALSE));
+                }
+                set_co_para(&mut co, io::Error::last_os_error());
+            }
+        }
+
+        // schedule the coroutine
+        run_coroutine(co);
+    }
+}
+
 unsafe fn cancel_io(handle: HANDLE, overlapped: *mut OVERLAPPED) -> io::Result<()> {
     let ret = CancelIoEx(handle, overlapped);
     if ret == 0 {
@@ -55,7 +55,7 @@ impl<'a> EventSource for SocketRead<'a> {
             let socket: std::net::TcpStream = FromRawSocket::from_raw_socket(self.socket);
             let ret = socket.read_overlapped(self.buf, self.io_data.get_overlapped());
             // don't close the socket
-            socket.into_raw_socket();
+            let _ = socket.into_raw_socket();
             ret
         });
 
@@ -63,7 +63,7 @@ impl<'a> EventSource for SocketRead<'a> {
         cancel.set_io(CancelIoData::new(&self.io_data));
         // re-check the cancel status
         if cancel.is_canceled() {
-            unsafe { cancel.cancel() };
+            let _ = cancel.cancel();
         }
     }
 }
@@ -46,7 +46,7 @@ impl<'a> EventSource for SocketWrite<'a> {
             let socket: std::net::TcpStream = FromRawSocket::from_raw_socket(self.socket);
             let ret = socket.write_overlapped(self.buf, self.io_data.get_overlapped());
             // don't close the socket
-            socket.into_raw_socket();
+            let _ = socket.into_raw_socket();
             ret
         });
     }
@@ -78,7 +78,7 @@ impl<'a> EventSource for TcpListenerAccept<'a> {
         cancel.set_io(CancelIoData::new(&self.io_data));
         // re-check the cancel status
         if cancel.is_canceled() {
-            unsafe { cancel.cancel() };
+            let _ = cancel.cancel();
         }
     }
 }
@@ -58,7 +58,7 @@ impl<'a> EventSource for PipeRead<'a> {
             let pipe: NamedPipe = FromRawHandle::from_raw_handle(self.pipe);
             let ret = pipe.read_overlapped(self.buf, self.io_data.get_overlapped());
             // don't close the socket
-            pipe.into_raw_handle();
+            let _ = pipe.into_raw_handle();
             ret
         });
 
@@ -66,7 +66,7 @@ impl<'a> EventSource for PipeRead<'a> {
         cancel.set_io(CancelIoData::new(&self.io_data));
         // re-check the cancel status
         if cancel.is_canceled() {
-            unsafe { cancel.cancel() };
+            let _ = cancel.cancel();
         }
     }
 }
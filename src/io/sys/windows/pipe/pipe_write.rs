@@ -44,7 +44,7 @@ impl<'a> EventSource for PipeWrite<'a> {
             let pipe: NamedPipe = FromRawHandle::from_raw_handle(self.pipe);
             let ret = pipe.write_overlapped(self.buf, self.io_data.get_overlapped());
             // don't close the socket
-            pipe.into_raw_handle();
+            let _ = pipe.into_raw_handle();
             ret
         });
     }
@@ -1,13 +1,95 @@
 use std::any::Any;
+use std::error;
 use std::fmt;
+use std::marker::PhantomData;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread::Result;
 
-use crate::coroutine_impl::Coroutine;
+use crate::coroutine_impl::{Coroutine, CoroutineId};
+use crate::select::{Selectable, Waker, WakerList};
 use crate::std::sync::{AtomicOption, Blocker};
 use crossbeam::atomic::AtomicCell;
 use mco_gen::Error;
+use parking_lot::Mutex;
+
+type Callback = Box<dyn FnOnce(&Join) + Send>;
+
+/// the return values up to this many words are kept in the join itself, the
+/// bigger or over aligned ones are boxed
+///
+/// it's 3 by default, set `MCO_INLINE_RET_WORDS` at build time to change it
+pub const INLINE_RET_WORDS: usize = parse_words(env!("MCO_INLINE_RET_WORDS"));
+
+// the build script checks that it's a number
+const fn parse_words(s: &str) -> usize {
+    let s = s.as_bytes();
+    let mut n = 0;
+    let mut i = 0;
+    while i < s.len() {
+        n = n * 10 + (s[i] - b'0') as usize;
+        i += 1;
+    }
+    n
+}
+
+// the return value of a coroutine with its type erased
+struct Ret {
+    data: MaybeUninit<[usize; INLINE_RET_WORDS]>,
+    drop: unsafe fn(*mut u8),
+}
+
+// the coroutine's return type is `Send`, it's checked by the spawn
+unsafe impl Send for Ret {}
+
+impl Ret {
+    fn inline<T>() -> bool {
+        mem::size_of::<T>() <= mem::size_of::<[usize; INLINE_RET_WORDS]>()
+            && mem::align_of::<T>() <= mem::align_of::<usize>()
+    }
+
+    fn new<T>(t: T) -> Self {
+        let mut data = MaybeUninit::uninit();
+        unsafe {
+            if Self::inline::<T>() {
+                ptr::write(data.as_mut_ptr() as *mut T, t);
+                Ret {
+                    data,
+                    drop: drop_in_place::<T>,
+                }
+            } else {
+                ptr::write(data.as_mut_ptr() as *mut Box<T>, Box::new(t));
+                Ret {
+                    data,
+                    drop: drop_in_place::<Box<T>>,
+                }
+            }
+        }
+    }
+
+    // safety: `T` must be the type that the value is created with
+    unsafe fn take<T>(self) -> T {
+        let mut ret = ManuallyDrop::new(self);
+        let p = ret.data.as_mut_ptr() as *mut u8;
+        if Self::inline::<T>() {
+            ptr::read(p as *mut T)
+        } else {
+            *ptr::read(p as *mut Box<T>)
+        }
+    }
+}
+
+impl Drop for Ret {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.data.as_mut_ptr() as *mut u8) }
+    }
+}
+
+unsafe fn drop_in_place<T>(p: *mut u8) {
+    ptr::drop_in_place(p as *mut T)
+}
 
 pub struct Join {
     // the coroutine that waiting for this join handler
@@ -16,20 +98,46 @@ pub struct Join {
     // when set to false, the coroutine is done
     state: AtomicBool,
 
+    // the return value, it's set before the trigger and kept here so that a
+    // spawn doesn't allocate a slot for it
+    ret: Mutex<Option<Ret>>,
+
     // use to set the panic err
     // this is the only place that could set the panic Error
     // we use to communicate with JoinHandle so that can return the panic info
     // this must be ready before the trigger
-    panic: Arc<AtomicCell<Option<Box<dyn Any + Send>>>>,
+    panic: AtomicCell<Option<Box<dyn Any + Send>>>,
+
+    // the callback of `JoinHandle::on_complete`, the state is changed under
+    // the same lock so that the callback runs exactly once
+    callback: Mutex<Option<Callback>>,
+
+    // the wakers of the `SelectSet`s that wait for it
+    selectors: WakerList,
 }
 
 // this is the join resource type
 impl Join {
-    pub fn new(panic: Arc<AtomicCell<Option<Box<dyn Any + Send>>>>) -> Self {
+    pub fn new() -> Self {
         Join {
             to_wake: AtomicOption::none(),
             state: AtomicBool::new(true),
-            panic,
+            ret: Mutex::new(None),
+            panic: AtomicCell::new(None),
+            callback: Mutex::new(None),
+            selectors: WakerList::new(),
+        }
+    }
+
+    // reuse the join of a finished coroutine for a new one if nothing else
+    // holds it, else it's a new one
+    pub(crate) fn renew(old: &mut Arc<Join>) -> Arc<Join> {
+        match Arc::get_mut(old) {
+            Some(join) => {
+                *join = Join::new();
+                old.clone()
+            }
+            None => Arc::new(Join::new()),
         }
     }
 
@@ -38,11 +146,35 @@ impl Join {
         self.panic.swap(Some(panic));
     }
 
+    // set the return value of the coroutine
+    pub fn set_ret<T>(&self, t: T) {
+        *self.ret.lock() = Some(Ret::new(t));
+    }
+
+    // take the return value, the panic or the cancel if there is none
+    //
+    // safety: `T` must be the return type of the coroutine
+    unsafe fn take<T>(&self) -> Result<T> {
+        let ret = self.ret.lock().take();
+        match ret {
+            Some(r) => Ok(r.take()),
+            None => Err(self.panic.take().unwrap_or_else(|| Box::new(Error::Cancel))),
+        }
+    }
+
     pub fn trigger(&self) {
-        self.state.store(false, Ordering::Release);
+        let callback = {
+            let mut callback = self.callback.lock();
+            self.state.store(false, Ordering::Release);
+            callback.take()
+        };
+        if let Some(f) = callback {
+            f(self);
+        }
         if let Some(w) = self.to_wake.take() {
             let _ = w.unpark();
         }
+        self.selectors.wake_all();
     }
 
     fn wait(&self) {
@@ -61,14 +193,39 @@ impl Join {
             cur.park(None).ok();
         }
     }
+
+    // register the callback, run it at once if the coroutine is already done
+    fn on_complete(&self, f: Callback) {
+        {
+            let mut callback = self.callback.lock();
+            if self.state.load(Ordering::Acquire) {
+                *callback = Some(f);
+                return;
+            }
+        }
+        f(self);
+    }
+}
+
+/// the error of `JoinHandle::join_select` when the result is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlreadyTaken;
+
+impl fmt::Display for AlreadyTaken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the coroutine result is already taken")
+    }
 }
 
+impl error::Error for AlreadyTaken {}
+
 /// A join handle to a coroutine
 pub struct JoinHandle<T> {
     co: Coroutine,
     join: Arc<Join>,
-    packet: Arc<AtomicCell<Option<T>>>,
-    panic: Arc<AtomicCell<Option<Box<dyn Any + Send>>>>,
+    // set when the result is taken by `join_select`
+    taken: AtomicBool,
+    _ret: PhantomData<T>,
 }
 
 unsafe impl<T> Send for JoinHandle<T> {}
@@ -76,17 +233,14 @@ unsafe impl<T> Send for JoinHandle<T> {}
 unsafe impl<T> Sync for JoinHandle<T> {}
 
 /// create a JoinHandle
-pub fn make_join_handle<T>(
-    co: Coroutine,
-    join: Arc<Join>,
-    packet: Arc<AtomicCell<Option<T>>>,
-    panic: Arc<AtomicCell<Option<Box<dyn Any + Send>>>>,
-) -> JoinHandle<T> {
+///
+/// the join must be the one that the coroutine sets its return value of `T`
+pub fn make_join_handle<T>(co: Coroutine, join: Arc<Join>) -> JoinHandle<T> {
     JoinHandle {
         co,
         join,
-        packet,
-        panic,
+        taken: AtomicBool::new(false),
+        _ret: PhantomData,
     }
 }
 
@@ -96,6 +250,11 @@ impl<T> JoinHandle<T> {
         &self.co
     }
 
+    /// the id of the coroutine
+    pub fn id(&self) -> CoroutineId {
+        self.co.id()
+    }
+
     /// return true if the coroutine is finished
     pub fn is_done(&self) -> bool {
         !self.join.state.load(Ordering::Acquire)
@@ -107,13 +266,123 @@ impl<T> JoinHandle<T> {
     }
 
     /// Join the coroutine, returning the result it produced.
+    ///
+    /// the error is `AlreadyTaken` if the result is taken by `join_select`
     pub fn join(self) -> Result<T> {
         self.join.wait();
+        if self.taken.load(Ordering::Acquire) {
+            return Err(Box::new(AlreadyTaken));
+        }
+        self.take()
+    }
+
+    /// wait for the coroutine by reference, so that it can be an arm of `select!`
+    ///
+    /// it returns at once if the coroutine is already done. the result is taken
+    /// exactly once, the later calls and `join` return `AlreadyTaken`. the wait
+    /// is cancel safe, a losing `select!` arm doesn't take the result
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate mco;
+    /// use std::time::Duration;
+    ///
+    /// # fn main() {
+    /// let fast = co!(|| 1);
+    /// let slow = co!(|| {
+    ///     mco::coroutine::sleep(Duration::from_millis(100));
+    ///     2
+    /// });
+    /// let mut first = 0;
+    /// select! {
+    ///     Ok(Ok(v)) = fast.join_select() => first = v,
+    ///     Ok(Ok(v)) = slow.join_select() => first = v
+    /// };
+    /// assert_eq!(first, 1);
+    /// assert!(fast.join_select().is_err());
+    /// assert_eq!(slow.join().unwrap(), 2);
+    /// # }
+    /// ```
+    pub fn join_select(&self) -> std::result::Result<Result<T>, AlreadyTaken> {
+        self.join.wait();
+        self.take_once()
+    }
+
+    /// detach the coroutine, it keeps running and its result is dropped
+    ///
+    /// this is the same as dropping the handle, but says so
+    pub fn detach(self) {}
 
-        // take the result
-        self.packet
-            .take()
-            .ok_or_else(|| self.panic.take().unwrap_or_else(|| Box::new(Error::Cancel)))
+    /// detach the coroutine and run `f` with its result when it's done
+    ///
+    /// `f` runs exactly once, in the context of the finished coroutine right
+    /// after its closure returns. when the coroutine panics or is canceled `f`
+    /// runs on the worker thread that drops it. if the coroutine is already
+    /// done, `f` runs at once on the caller's context before `on_complete`
+    /// returns. `f` should not block for long nor panic
+    ///
+    /// ```
+    /// use mco::std::sync::channel::channel;
+    ///
+    /// let (tx, rx) = channel();
+    /// mco::co!(|| 42).on_complete(move |ret| tx.send(ret.unwrap()).unwrap());
+    /// assert_eq!(rx.recv().unwrap(), 42);
+    /// ```
+    pub fn on_complete<F>(mut self, f: F)
+    where
+        T: Send + 'static,
+        F: FnOnce(Result<T>) + Send + 'static,
+    {
+        // the result is left to the callback
+        *self.taken.get_mut() = true;
+        self.join
+            .on_complete(Box::new(move |join| f(unsafe { join.take::<T>() })));
+    }
+
+    // take the result for `join_select`, only once
+    fn take_once(&self) -> std::result::Result<Result<T>, AlreadyTaken> {
+        if self.taken.swap(true, Ordering::AcqRel) {
+            return Err(AlreadyTaken);
+        }
+        Ok(self.take())
+    }
+
+    // take the result
+    fn take(&self) -> Result<T> {
+        unsafe { self.join.take::<T>() }
+    }
+}
+
+// the completion for `select`, the result is taken like `join_select`
+impl<'a, T> Selectable for &'a JoinHandle<T> {
+    type Output = std::result::Result<Result<T>, AlreadyTaken>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        if self.is_done() {
+            Some(self.take_once())
+        } else {
+            None
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.join.selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.join.selectors.deregister(waker);
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        // the join is kept with the pooled coroutine for a later spawn, so the
+        // result that is not taken is dropped with the handle
+        if !*self.taken.get_mut() && self.is_done() {
+            let ret = self.join.ret.lock().take();
+            drop(ret);
+            drop(self.join.panic.take());
+        }
     }
 }
 
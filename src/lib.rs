@@ -30,28 +30,51 @@
 extern crate log;
 extern crate core;
 
+mod affinity;
+mod blocking_zone;
 mod cancel;
 mod config;
+mod determinism;
+#[cfg(feature = "flight-recorder")]
+mod flight;
+mod generator;
+mod hooks;
 mod join;
+mod lifecycle;
 mod local;
+mod maintenance;
 mod park;
+mod per_worker;
 mod pool;
 mod sleep;
+mod span;
+mod thread_names;
 #[macro_use]
 mod macros;
 mod coroutine_impl;
+mod error;
 mod scheduler;
 mod scoped;
 mod timeout_list;
+mod watchdog;
 mod yield_now;
 pub extern crate mco_gen;
 pub mod coroutine;
 pub mod cqueue;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
 pub mod io;
 pub mod net;
 pub mod os;
+pub mod prelude;
+pub mod runtime;
+pub mod select;
+pub mod signal;
+pub mod stats;
 #[macro_use]
 pub mod std;
 
-pub use crate::config::{config, Config};
+pub use crate::affinity::{Affinity, CpuSet};
+pub use crate::config::{config, Config, WorkerPanic};
+pub use crate::error::{ChannelError, Error};
 pub use crate::local::LocalKey;
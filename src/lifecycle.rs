@@ -0,0 +1,239 @@
+//! the lifecycle hooks, see `runtime::on_start` and `runtime::on_shutdown`
+//!
+//! the hooks are process wide, so a library registers them once and they run
+//! for each runtime. the start hooks run in the order of the registration
+//! when a runtime has started its workers, the shutdown hooks run in the
+//! reverse order in `Runtime::shutdown` before the workers exit. each one is
+//! isolated: a panic is caught and logged, and a shutdown hook that takes
+//! longer than its timeout is left running while the shutdown goes on
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::Builder;
+use crate::runtime::Runtime;
+use crate::scheduler::{default_scheduler_started, set_current_sched, Scheduler};
+use crate::std::sync::channel::{channel, RecvTimeoutError};
+
+/// the time that a shutdown hook of `on_shutdown` is waited for
+pub const SHUTDOWN_HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static START: Lazy<Mutex<Vec<Arc<Hook>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+// the start hooks ran for the default runtime, set under the lock of `START`
+static DEFAULT_STARTED: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN: Lazy<Mutex<Vec<Arc<Hook>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+struct Hook {
+    id: u64,
+    name: String,
+    f: Box<dyn Fn() + Send + Sync>,
+    timeout: Duration,
+}
+
+/// the registration of a lifecycle hook, it's unregistered when the guard is
+/// dropped
+///
+/// a run that is already started when the guard is dropped still finishes
+#[must_use]
+pub struct LifecycleGuard {
+    hook: Arc<Hook>,
+    shutdown: bool,
+}
+
+impl LifecycleGuard {
+    /// the name of the hook
+    pub fn name(&self) -> &str {
+        &self.hook.name
+    }
+}
+
+impl Drop for LifecycleGuard {
+    fn drop(&mut self) {
+        let hooks = if self.shutdown { &SHUTDOWN } else { &START };
+        hooks.lock().retain(|h| h.id != self.hook.id);
+    }
+}
+
+impl fmt::Debug for LifecycleGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LifecycleGuard")
+            .field("name", &self.hook.name)
+            .field("shutdown", &self.shutdown)
+            .finish()
+    }
+}
+
+fn new_hook<F>(name: &str, timeout: Duration, f: F) -> Arc<Hook>
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    Arc::new(Hook {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        name: name.to_owned(),
+        f: Box::new(f),
+        timeout,
+    })
+}
+
+/// run `f` once for each runtime when it has started its workers, until the
+/// returned guard is dropped
+///
+/// the start hooks run in the order of the registration, on the thread that
+/// starts the runtime and before the runtime is handed out. the coroutines
+/// that `f` spawns run on that runtime. if the default runtime is already
+/// started `f` runs for it before `on_start` returns, it's not started by
+/// this call. a panic of `f` is caught and logged with `name`, the other
+/// hooks and the runtime are not affected
+///
+/// ```
+/// use mco::runtime::{self, Config, Runtime};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// let started = Arc::new(AtomicUsize::new(0));
+/// let s = started.clone();
+/// let guard = runtime::on_start("count", move || {
+///     s.fetch_add(1, Ordering::Relaxed);
+/// });
+/// let rt = Runtime::new(Config::new().workers(1)).unwrap();
+/// assert!(started.load(Ordering::Relaxed) >= 1);
+/// drop(guard);
+/// drop(rt);
+/// ```
+pub fn on_start<F>(name: &str, f: F) -> LifecycleGuard
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let hook = new_hook(name, Duration::from_secs(0), f);
+    let started = {
+        let mut hooks = START.lock();
+        hooks.push(hook.clone());
+        DEFAULT_STARTED.load(Ordering::Relaxed)
+    };
+    if let Some(s) = default_scheduler_started().filter(|_| started) {
+        let old = set_current_sched(s);
+        run_start_hook(&hook);
+        set_current_sched(old);
+    }
+    LifecycleGuard {
+        hook,
+        shutdown: false,
+    }
+}
+
+/// run `f` in `Runtime::shutdown` of each runtime, until the returned guard
+/// is dropped. the shutdown waits for it `SHUTDOWN_HOOK_TIMEOUT` at most
+///
+/// see [`on_shutdown_timeout`]
+///
+/// [`on_shutdown_timeout`]: ./fn.on_shutdown_timeout.html
+pub fn on_shutdown<F>(name: &str, f: F) -> LifecycleGuard
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    on_shutdown_timeout(name, SHUTDOWN_HOOK_TIMEOUT, f)
+}
+
+/// run `f` in `Runtime::shutdown` of each runtime, until the returned guard
+/// is dropped. the shutdown waits for it `timeout` at most
+///
+/// the shutdown hooks run in the reverse order of the registration, one at a
+/// time and before the `GlobalChannel`s are closed and the workers exit. `f`
+/// runs in a coroutine of the runtime, so it can spawn the coroutines and
+/// wait on the channels of the runtime. a hook that is not finished in
+/// `timeout` is logged with `name` and left running, the shutdown goes on
+/// with the next hook. a panic of `f` is caught and logged with `name`
+///
+/// a runtime that is just dropped doesn't run the hooks, and the default
+/// runtime is never shut down
+///
+/// ```
+/// use mco::runtime::{self, Config, Runtime};
+/// use mco::std::sync::channel;
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let flushed = Arc::new(AtomicBool::new(false));
+/// let f = flushed.clone();
+/// let guard = runtime::on_shutdown_timeout("flush", Duration::from_secs(1), move || {
+///     // a teardown that waits for another coroutine
+///     let (tx, rx) = channel();
+///     mco::co!(move || tx.send(true).unwrap());
+///     f.store(rx.recv().unwrap(), Ordering::Relaxed);
+/// });
+/// let rt = Runtime::new(Config::new().workers(1)).unwrap();
+/// assert!(rt.shutdown(Duration::from_secs(1)));
+/// assert!(flushed.load(Ordering::Relaxed));
+/// drop(guard);
+/// ```
+pub fn on_shutdown_timeout<F>(name: &str, timeout: Duration, f: F) -> LifecycleGuard
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let hook = new_hook(name, timeout, f);
+    SHUTDOWN.lock().push(hook.clone());
+    LifecycleGuard {
+        hook,
+        shutdown: true,
+    }
+}
+
+fn run_start_hook(hook: &Hook) {
+    if panic::catch_unwind(AssertUnwindSafe(|| (hook.f)())).is_err() {
+        error!("start hook {:?} panicked", hook.name);
+    }
+}
+
+// run the start hooks for the runtime of the scheduler on the current thread,
+// an `on_start` after this runs its hook itself if it's the default runtime
+pub(crate) fn run_start(s: &'static Scheduler, default: bool) {
+    let hooks = {
+        let hooks = START.lock();
+        if default {
+            DEFAULT_STARTED.store(true, Ordering::Relaxed);
+        }
+        hooks.clone()
+    };
+    if hooks.is_empty() {
+        return;
+    }
+    let old = set_current_sched(s);
+    for hook in hooks.iter() {
+        run_start_hook(hook);
+    }
+    set_current_sched(old);
+}
+
+// run the shutdown hooks of the runtime in the reverse order, each one in a
+// coroutine of the runtime that is waited for its timeout
+pub(crate) fn run_shutdown(rt: &Runtime) {
+    let hooks = SHUTDOWN.lock().clone();
+    for hook in hooks.iter().rev() {
+        let (tx, rx) = channel();
+        let h = hook.clone();
+        let builder = Builder::new().name(format!("shutdown-{}", hook.name));
+        rt.spawn_with(builder, move || {
+            let ret = panic::catch_unwind(AssertUnwindSafe(|| (h.f)()));
+            let _ = tx.send(ret.is_ok());
+        });
+        match rx.recv_timeout(hook.timeout) {
+            Ok(true) => {}
+            Ok(false) => error!("shutdown hook {:?} panicked", hook.name),
+            Err(RecvTimeoutError::Timeout) => warn!(
+                "shutdown hook {:?} is not finished in {:?}, shutting down without it",
+                hook.name, hook.timeout
+            ),
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("shutdown hook {:?} is canceled", hook.name)
+            }
+        }
+    }
+}
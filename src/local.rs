@@ -1,11 +1,12 @@
-use std::any::TypeId;
-use std::cell::RefCell;
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::hash::{BuildHasherDefault, Hasher};
 use std::ptr::NonNull;
 use std::sync::Arc;
 
 use crate::coroutine_impl::Coroutine;
+use crate::cqueue::Claim;
 use crate::join::Join;
 use mco_gen::get_local_data;
 
@@ -20,6 +21,11 @@ pub struct CoroutineLocal {
     join: Arc<Join>,
     // real local data hash map
     local_data: LocalMap,
+    // the values of `Builder::with_local`, in the order they are set
+    values: RefCell<Vec<Box<dyn Any>>>,
+    // the claim of the `select!` arm whose expression is running, and the
+    // id of the arm's selector
+    claim: Cell<Option<(NonNull<Claim>, usize)>>,
 }
 
 impl CoroutineLocal {
@@ -29,6 +35,8 @@ impl CoroutineLocal {
             co,
             join,
             local_data: RefCell::new(HashMap::default()),
+            values: RefCell::new(Vec::new()),
+            claim: Cell::new(None),
         })
     }
 
@@ -41,6 +49,28 @@ impl CoroutineLocal {
     pub fn get_join(&self) -> Arc<Join> {
         self.join.clone()
     }
+
+    // the handle and the join that a new coroutine may reuse
+    pub(crate) fn parts_mut(&mut self) -> (&mut Coroutine, &mut Arc<Join>) {
+        (&mut self.co, &mut self.join)
+    }
+
+    // set the handle and the join of a new coroutine that reuses the storage
+    pub(crate) fn reset(&mut self, co: Coroutine, join: Arc<Join>) {
+        self.co = co;
+        self.join = join;
+    }
+
+    // drop the data of a finished coroutine and keep the storage, the result
+    // is dropped too if the join handle is gone
+    pub(crate) fn finish(&mut self) {
+        self.local_data.get_mut().clear();
+        self.values.get_mut().clear();
+        self.claim.set(None);
+        if let Some(join) = Arc::get_mut(&mut self.join) {
+            *join = Join::new();
+        }
+    }
 }
 
 #[inline]
@@ -50,6 +80,24 @@ pub fn get_co_local_data() -> Option<NonNull<CoroutineLocal>> {
     NonNull::new(ptr as *mut CoroutineLocal)
 }
 
+// set the `select!` claim of the running coroutine, return the old one
+pub(crate) fn swap_claim(
+    claim: Option<(NonNull<Claim>, usize)>,
+) -> Option<(NonNull<Claim>, usize)> {
+    match get_co_local_data() {
+        Some(local) => unsafe { local.as_ref() }.claim.replace(claim),
+        None => None,
+    }
+}
+
+// the `select!` claim of the running coroutine, `None` outside of the
+// expression of an arm
+#[inline]
+pub(crate) fn current_claim() -> Option<(NonNull<Claim>, usize)> {
+    let local = get_co_local_data()?;
+    unsafe { local.as_ref() }.claim.get()
+}
+
 fn with<F: FnOnce(&LocalMap) -> R, R>(f: F) -> R {
     match get_co_local_data() {
         Some(v) => f(&(unsafe { v.as_ref() }.local_data)),
@@ -57,6 +105,57 @@ fn with<F: FnOnce(&LocalMap) -> R, R>(f: F) -> R {
     }
 }
 
+/// the init of a `Builder::with_local` value
+pub(crate) type LocalInit = Box<dyn FnOnce() -> Box<dyn Any> + Send>;
+
+/// the `Builder::with_local` values of the running coroutine body, they are
+/// dropped in the reverse order when the guard is dropped, also on unwinding
+pub(crate) struct LocalValues;
+
+impl LocalValues {
+    pub fn init(inits: Vec<LocalInit>) -> LocalValues {
+        if let Some(local) = get_co_local_data() {
+            let local = unsafe { local.as_ref() };
+            for init in inits {
+                // the init may access the values set before it
+                let v = init();
+                local.values.borrow_mut().push(v);
+            }
+        }
+        LocalValues
+    }
+}
+
+impl Drop for LocalValues {
+    fn drop(&mut self) {
+        if let Some(local) = get_co_local_data() {
+            let local = unsafe { local.as_ref() };
+            // the drop may access the values that are not dropped yet
+            loop {
+                let v = local.values.borrow_mut().pop();
+                match v {
+                    Some(v) => drop(v),
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+/// call `f` with the value of type `T` that is set by `Builder::with_local`
+/// for the current coroutine, the last one wins if there are more than one.
+/// return `None` in a thread context or when there is no such value
+///
+/// the reference is only lent to `f`, the values are dropped when the body of
+/// the coroutine returns
+#[inline]
+pub fn with_local_value<T: 'static, R, F: FnOnce(&T) -> R>(f: F) -> Option<R> {
+    let local = get_co_local_data()?;
+    let values = unsafe { local.as_ref() }.values.borrow();
+    let v = values.iter().rev().find_map(|v| v.downcast_ref::<T>())?;
+    Some(f(v))
+}
+
 pub type LocalMap = RefCell<HashMap<TypeId, Box<dyn Opaque>, BuildHasherDefault<IdHasher>>>;
 
 pub trait Opaque {}
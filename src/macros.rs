@@ -51,20 +51,98 @@ macro_rules! spawn {
 /// this macro is just a convenient wrapper for [`spawn`].
 /// However the supplied coroutine block is not wrapped in `unsafe` block
 ///
+/// the options can also be a builder expression, the fields that are not set
+/// are taken from the builder defaults:
+/// ```
+/// use mco::coroutine::Builder;
+///
+/// mco::spawn_with!(Builder::new().stack_size(0x4000), || {}).join().unwrap();
+/// ```
+///
+/// or a list of `key: value` options in any order, each of them is optional.
+/// `name` takes anything that implements `ToString`, `stack` is the stack
+/// size and `stack_kind` is a `StackKind`. the other keys are `name_fn`,
+/// `pin`, `priority`, `with_local`, `tag`, `tag_str`, `budget` and
+/// `with_span`, they call the `Builder` method of the same name, so `pin: 2`
+/// is `Builder::pin(2)`:
+/// ```
+/// let h = mco::spawn_with!(name: "conn", stack: 0x4000, tag: 7, || {
+///     let co = mco::coroutine::current();
+///     (co.name().map(|s| s.to_owned()), co.stack_size())
+/// });
+/// assert_eq!(h.join().unwrap(), (Some("conn".to_owned()), 0x4000));
+/// ```
+///
+/// a key that is not one of them fails to compile with the list of the keys:
+/// ```compile_fail
+/// mco::spawn_with!(nmae: "conn", || {});
+/// ```
+///
 /// [`spawn`]: coroutine/fn.spawn.html
 #[macro_export]
 macro_rules! spawn_with {
-    // for stack_size
-    ($stack_size:expr, $func:expr) => {{
-        fn _go_check<F, T>(stack_size: usize, f: F) -> F
+    // fold the options into the builder one by one
+    (@opts ($builder:expr) name : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.name(::std::string::ToString::to_string(&$v))) $($rest)+)
+    };
+    (@opts ($builder:expr) stack : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.stack_size($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) stack_kind : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.stack($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) name_fn : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.name_fn($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) pin : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.pin($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) priority : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.priority($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) with_local : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.with_local($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) tag : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.tag($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) tag_str : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.tag_str($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) budget : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.budget($v)) $($rest)+)
+    };
+    (@opts ($builder:expr) with_span : $v:expr, $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($builder.with_span($v)) $($rest)+)
+    };
+    // before the closure arm, a key can't be parsed as an expression
+    (@opts ($builder:expr) $key:ident : $($rest:tt)+) => {
+        compile_error!(concat!(
+            "unknown spawn_with! option `",
+            stringify!($key),
+            "`, the options are name, name_fn, stack, stack_kind, pin, priority, with_local, tag, tag_str, budget and with_span"
+        ))
+    };
+    (@opts ($builder:expr) $func:expr) => {
+        $crate::spawn_with!($builder, $func)
+    };
+
+    // for the `key: value` options, tried first so that they are not parsed as an expression
+    ($key:ident : $($rest:tt)+) => {
+        $crate::spawn_with!(@opts ($crate::coroutine::Builder::new()) $key : $($rest)+)
+    };
+
+    // for stack_size or a builder expression
+    ($opt:expr, $func:expr) => {{
+        fn _go_check<F, T>(f: F) -> F
         where
             F: FnOnce() -> T + Send + 'static,
             T: Send + 'static,
         {
             f
         }
-        let f = _go_check($stack_size, $func);
-        let builder = $crate::coroutine::Builder::new().stack_size($stack_size);
+        let f = _go_check($func);
+        let builder = $crate::coroutine::IntoBuilder::into_builder($opt);
         unsafe { builder.spawn(f) }
     }};
 
@@ -85,14 +163,93 @@ macro_rules! spawn_with {
     }};
 }
 
+/// macro used to spawn a coroutine in a child span of a request
+///
+/// the first argument is a `Span`, the coroutine runs in a child span of it,
+/// or a `Context`, the coroutine runs in a child span of the current one
+/// with that context. the child span is named after the parent or by the
+/// optional name, and the coroutine is named after the child span with the
+/// spawn sequence number. see `Builder::with_span` for what the coroutine
+/// gets. the spans of a request:
+/// ```
+/// use mco::coroutine::Span;
+/// use mco::std::context::Context;
+/// use std::time::Duration;
+///
+/// let ctx = Context::background().with_timeout(Duration::from_secs(5));
+/// let request = Span::root("request").with_context(ctx);
+/// let h = mco::go_traced!(request, "handler", || {
+///     let handler = Span::current().unwrap();
+///     mco::go_traced!(handler, "db", || {
+///         // the context is inherited by the child spans
+///         let db = Span::current().unwrap();
+///         mco::go_traced!(db.context(), "query", || {
+///             let name = mco::coroutine::current().name().unwrap().to_owned();
+///             (Span::current().unwrap(), name)
+///         })
+///         .join()
+///         .unwrap()
+///     })
+///     .join()
+///     .unwrap()
+/// });
+/// let (query, name) = h.join().unwrap();
+/// assert!(name.starts_with("query-"));
+/// let mut chain = vec![query.name().to_owned()];
+/// let mut span = &query;
+/// while let Some(parent) = span.parent() {
+///     assert_eq!(parent.trace_id(), request.trace_id());
+///     chain.push(parent.name().to_owned());
+///     span = parent;
+/// }
+/// assert_eq!(chain, ["query", "db", "handler", "request"]);
+/// assert_eq!(query.context().deadline(), request.context().deadline());
+/// assert!(query.spawn_latency().is_some());
+/// ```
+#[macro_export]
+macro_rules! go_traced {
+    ($parent:expr, $func:expr) => {{
+        let span = $crate::coroutine::TraceParent::child_span(&$parent, None);
+        $crate::coroutine::Builder::new()
+            .with_span(span)
+            .spawn($func)
+    }};
+
+    ($parent:expr, $name:expr, $func:expr) => {{
+        let span = $crate::coroutine::TraceParent::child_span(&$parent, Some($name));
+        $crate::coroutine::Builder::new()
+            .with_span(span)
+            .spawn($func)
+    }};
+}
+
 /// macro used to create the select coroutine
 /// that will run in a infinite loop, and generate
 /// as many events as possible
+///
+/// the loop exits when the expression returns a terminal result, like a
+/// receive on a disconnected channel, see `cqueue::Terminal`. the terminal
+/// result is not sent to the poller
+///
+/// each event waits for the poller, so the selector has one event in the
+/// queue at most and a fast source can't overrun the poller. the bottom half
+/// runs on the select coroutine, when it blocks, e.g. on a send to a full
+/// bounded channel, only that coroutine parks and `poll` returns its event,
+/// the other selectors of the cqueue go on. the selector takes its next
+/// value after the bottom half is done, so the backpressure of the blocked
+/// channel reaches the source of the selector. a selector that queues its
+/// events by `try_send` can be limited by `Cqueue::add_with`
 #[macro_export]
 macro_rules! cqueue_add {
     ($cqueue:ident, $token:expr, $name:pat = $top:expr => $bottom:expr) => {{
         $crate::co!($cqueue, $token, |es| loop {
-            let $name = $top;
+            #[allow(unused_imports)]
+            use $crate::cqueue::{NotTerminal as _, TerminalKind as _};
+            let _ret = $top;
+            if (&$crate::cqueue::TerminalProbe(&_ret)).is_terminal() {
+                break;
+            }
+            let $name = _ret;
             es.send(es.get_token());
             $bottom
         })
@@ -132,12 +289,350 @@ macro_rules! cqueue_add_oneshot {
 ///         }
 ///     };
 /// ```
+///
+/// a `complete` arm can be put at the end, it runs when none of the arms can
+/// produce a value any more, that is the result of each arm's expression does
+/// not match its pattern, e.g. `Ok(v) = rx.recv()` for disconnected channels.
+/// in this form the macro returns `Some(index)` of the event, or `None` after
+/// the `complete` arm is run. the `complete` arm is evaluated in the caller's
+/// context, so `break` works for a fan-in loop:
+/// ```rust
+/// use mco::{chan, select};
+///
+///     let (s1, r1) = chan!();
+///     let (s2, r2) = chan!(i32, usize::MAX);
+///     s1.send(1);
+///     drop(s1);
+///     drop(s2);
+///     loop {
+///         select! {
+///             Ok(v) = r1.recv() => println!("r1 {}", v),
+///             Ok(v) = r2.recv() => println!("r2 {}", v),
+///             complete => break,
+///         };
+///     }
+/// ```
+///
+/// a `deadline(t)` arm can be put at the end instead, `t` is an absolute
+/// `std::time::Time` that is borrowed. its body runs when no arm sends its
+/// event before `t`, and the macro returns `Some(index)` or `None`. the time
+/// left is counted from the wall clock once for each select, so a loop with
+/// a fixed overall deadline doesn't drift:
+/// ```rust
+/// use mco::{chan, select};
+/// use mco::std::time::Time;
+/// use std::time::Duration;
+///
+///     let (s, r) = chan!();
+///     let deadline = Time::now().add(Duration::from_millis(50));
+///     let mut got = 0;
+///     loop {
+///         if got < 3 {
+///             s.send(got).unwrap();
+///         }
+///         select! {
+///             Ok(_) = r.recv() => got += 1,
+///             deadline(deadline) => break,
+///         };
+///     }
+///     assert_eq!(got, 3);
+/// ```
+///
+/// an arm of the form `$pat = any_of($sources).$method($args)` selects over
+/// many homogeneous sources, e.g. a `Vec` of receivers. it expands to one
+/// selector for each item of `$sources`, and `$pat` is matched against the
+/// `(index, result)` pair, the index is the position of the item. the arm body
+/// is shared by the items so it can't move or mutably borrow the captured values.
+/// the return value is the index of the arm, not of the item:
+/// ```rust
+/// use mco::{chan, select};
+///
+///     let (txs, rxs): (Vec<_>, Vec<_>) = (0..8).map(|_| chan!()).unzip();
+///     txs[5].send(42).unwrap();
+///     select! {
+///         (i, Ok(v)) = any_of(&rxs).recv() => println!("{} from rxs[{}]", v, i),
+///     };
+/// ```
+///
+/// an arm can have a guard, `$pat = $expr, if $guard => $body`, the arm is
+/// disabled when the guard is false: its selector is not started, so its
+/// expression is not evaluated, a `send` doesn't take the room of a bounded
+/// channel and the arm can't win. the guards are evaluated once for each
+/// select, in the caller's context and in the order of the arms, before any
+/// selector starts. a disabled arm keeps its index. when all the arms are
+/// disabled the `complete` arm runs at once, the `deadline` arm runs at the
+/// deadline, the `default` arm of `biased` runs, and a select without any of
+/// them panics. so a `loop` around the `select!` pauses an arm by flipping
+/// its guard:
+/// ```rust
+/// use mco::{chan, select};
+///
+///     let (tx, rx) = chan!();
+///     let (ctl_tx, ctl_rx) = chan!();
+///     let mut paused = true;
+///     let mut got = Vec::new();
+///     ctl_tx.send(false).unwrap();
+///     tx.send(1).unwrap();
+///     while got.is_empty() {
+///         select! {
+///             Ok(v) = rx.recv(), if !paused => got.push(v),
+///             Ok(p) = ctl_rx.recv() => paused = p,
+///         };
+///     }
+///     assert_eq!(got, [1]);
+/// ```
+///
+/// each arm is expanded to exactly one selector closure, so the generated code
+/// grows linearly with the number of arms. for reference, a select with 50
+/// channel arms adds about 0.2s to a debug build compared to a single `any_of`
+/// arm over the same 50 channels. the arms are collected by a recursive macro,
+/// one level for each arm, so a select with more than about 120 arms needs a
+/// higher `#![recursion_limit]` in the calling crate
+///
+/// by default the expression of each arm is evaluated in its own selector
+/// coroutine as soon as the select starts, all of them run at the same time
+/// and the first one that commits wins. the body runs only for the winner. a
+/// `recv` of an mpmc channel commits just before it takes a message, so the
+/// message of a losing `recv` stays in its channel for the next receiver and
+/// no message is lost nor seen twice. any other expression commits when it
+/// returns, so it takes effect even if it loses, e.g. a `send`, or a receive
+/// of another kind of channel whose message is then dropped.
+/// the selectors capture the caller's values by reference, so two arms can't
+/// both take `&mut` to the same value
+///
+/// with a leading `biased;` no selector is spawned, the arms are probed in
+/// order on the caller's stack. the expression of an arm is evaluated only
+/// when the arms before it don't match, and the body of the first match runs
+/// in place, so the arms may borrow the same value mutably and a body can
+/// `return` from the caller. the expressions must not block, like `try_recv`,
+/// when no arm matches the caller yields and then sleeps for a growing time
+/// up to about 1ms before the next round. a `default` arm at the end runs when
+/// no arm matches in the first round, then the macro returns `Option<usize>`.
+/// `any_of` and `complete` are not supported in this mode, and the bodies are
+/// inside the probe loop, so use a label to `break` an outer loop:
+/// ```rust
+/// use mco::{chan, select};
+///
+///     let (tx, rx) = chan!();
+///     let (tx2, rx2) = chan!();
+///     let mut seen = Vec::new();
+///     tx2.send(2).unwrap();
+///     let id = select! { biased;
+///         Ok(v) = rx.try_recv() => seen.push(v),
+///         Ok(v) = rx2.try_recv() => seen.push(v * 10),
+///     };
+///     assert_eq!(id, 1);
+///     let ret = select! { biased;
+///         Ok(v) = rx.try_recv() => seen.push(v),
+///         default => seen.clear(),
+///     };
+///     assert_eq!(ret, None);
+///     assert!(seen.is_empty());
+///     # let _: &mco::std::sync::channel::Sender<i32> = &tx;
+/// ```
+///
+/// with a leading `local;` the arms can use the values that are not `Send`,
+/// such as the receivers of `std::sync::local`. the selectors are pinned to
+/// the worker of the caller, which must be a pinned coroutine:
+/// ```rust
+/// use mco::coroutine::Builder;
+/// use mco::select;
+/// use mco::std::sync::local;
+///
+/// Builder::new().pin(0).spawn(|| {
+///     let (tx, rx) = local::channel();
+///     tx.send(std::rc::Rc::new(1)).unwrap();
+///     select! { local;
+///         Ok(v) = rx.recv() => assert_eq!(*v, 1),
+///     };
+/// }).join().unwrap();
+/// ```
 #[macro_export]
 macro_rules! select {
-    (
-        $($name:pat = $top:expr => $bottom:expr), +$(,)?
-    ) => ($crate::select_token!($($name = $top => $bottom), +););
+    (biased; $($body:tt)+) => ($crate::select_arms!(@arms biased [] [] $($body)+));
+    (local; $($body:tt)+) => ($crate::select_arms!(@arms add_local [] [] $($body)+));
+    ($($body:tt)+) => ($crate::select_arms!(@arms add [] [] $($body)+));
 }
+
+/// collect the arms of `select!` with their guards, then expand each of them
+/// to one selector
+#[doc(hidden)]
+#[macro_export]
+macro_rules! select_arms {
+    // `biased` probes the arms in order on the caller's stack, without selectors
+    (@arms biased [$((one ($name:pat) ($top:expr) ($bottom:expr)))+] [$($guard:tt)+] $(,)?) => ({
+        #[allow(unused_parens)]
+        let _guards = [$($guard),+];
+        if !_guards.iter().any(|g| *g) {
+            panic!("all the arms of `select!` are disabled");
+        }
+        let mut _backoff = $crate::cqueue::ProbeBackoff::new();
+        #[allow(unused_assignments, unreachable_code)]
+        let _token = loop {
+            let mut _i = 0usize;
+            $(
+                #[allow(irrefutable_let_patterns)]
+                if _guards[_i] {
+                    if let $name = $top {
+                        $bottom;
+                        break _i;
+                    }
+                }
+                _i += 1;
+            )+
+            _backoff.wait();
+        };
+        _token
+    });
+    (@arms biased [$((one ($name:pat) ($top:expr) ($bottom:expr)))+] [$($guard:tt)+] default => $default:expr $(,)?) => ({
+        #[allow(unused_parens)]
+        let _guards = [$($guard),+];
+        #[allow(unused_assignments, unreachable_code)]
+        let _token = loop {
+            let mut _i = 0usize;
+            $(
+                #[allow(irrefutable_let_patterns)]
+                if _guards[_i] {
+                    if let $name = $top {
+                        $bottom;
+                        break Some(_i);
+                    }
+                }
+                _i += 1;
+            )+
+            break None;
+        };
+        if _token.is_none() {
+            $default;
+        }
+        _token
+    });
+    (@arms biased [$($arm:tt)+] [$($guard:tt)+] complete => $complete:expr $(,)?) => (
+        compile_error!("`select! { biased; .. }` can't tell when the arms are done, use `default`")
+    );
+    // without `complete` the event is sent even if the pattern doesn't match
+    (@arms $add:ident [$($arm:tt)+] [$($guard:tt)+] $(,)?) => ({
+        #[allow(unused_parens)]
+        let _guards = [$($guard),+];
+        if !_guards.iter().any(|g| *g) {
+            panic!("all the arms of `select!` are disabled");
+        }
+        $crate::cqueue::scope(|cqueue| {
+            let mut _token = 0;
+            $($crate::select_arms!(@add always $add cqueue _token _guards $arm);)+
+            match cqueue.poll(None) {
+                Ok(ev) => ev.token,
+                _ => unreachable!("select error"),
+            }
+        })
+    });
+    (@arms $add:ident [$($arm:tt)+] [$($guard:tt)+] complete => $complete:expr $(,)?) => ({
+        #[allow(unused_parens)]
+        let _guards = [$($guard),+];
+        let _ret = $crate::cqueue::scope(|cqueue| {
+            let mut _token = 0;
+            // a selector that doesn't match finishes without an event
+            $($crate::select_arms!(@add matched $add cqueue _token _guards $arm);)+
+            match cqueue.poll(None) {
+                Ok(ev) => Some(ev.token),
+                Err($crate::cqueue::PollError::Finished) => None,
+                Err(_) => unreachable!("select error"),
+            }
+        });
+        if _ret.is_none() {
+            $complete;
+        }
+        _ret
+    });
+    (@arms biased [$($arm:tt)+] [$($guard:tt)+] deadline($t:expr) => $body:expr $(,)?) => (
+        compile_error!("`select! { biased; .. }` doesn't wait, use `default`")
+    );
+    (@arms $add:ident [$($arm:tt)+] [$($guard:tt)+] deadline($t:expr) => $body:expr $(,)?) => ({
+        #[allow(unused_parens)]
+        let _guards = [$($guard),+];
+        let _ret = $crate::cqueue::scope(|cqueue| {
+            let mut _token = 0;
+            $($crate::select_arms!(@add always $add cqueue _token _guards $arm);)+
+            if !_guards.iter().any(|g| *g) {
+                // nothing can win, wait for the deadline
+                cqueue.add(0, |_| loop {
+                    $crate::coroutine::park()
+                });
+            }
+            match cqueue.poll_until(&$t) {
+                Ok(ev) => Some(ev.token),
+                Err(_) => None,
+            }
+        });
+        if _ret.is_none() {
+            $body;
+        }
+        _ret
+    });
+    (@arms $add:ident [$($arms:tt)*] [$($guards:tt)*] $name:pat = any_of($src:expr).$method:ident($($args:tt)*), if $guard:expr => $bottom:expr $(, $($rest:tt)*)?) => (
+        $crate::select_arms!(@arms $add [$($arms)* (any ($name) ($src) ($method) ($($args)*) ($bottom))] [$($guards)* ($guard)] $($($rest)*)?)
+    );
+    (@arms $add:ident [$($arms:tt)*] [$($guards:tt)*] $name:pat = any_of($src:expr).$method:ident($($args:tt)*) => $bottom:expr $(, $($rest:tt)*)?) => (
+        $crate::select_arms!(@arms $add [$($arms)* (any ($name) ($src) ($method) ($($args)*) ($bottom))] [$($guards)* (true)] $($($rest)*)?)
+    );
+    (@arms $add:ident [$($arms:tt)*] [$($guards:tt)*] $name:pat = $top:expr, if $guard:expr => $bottom:expr $(, $($rest:tt)*)?) => (
+        $crate::select_arms!(@arms $add [$($arms)* (one ($name) ($top) ($bottom))] [$($guards)* ($guard)] $($($rest)*)?)
+    );
+    (@arms $add:ident [$($arms:tt)*] [$($guards:tt)*] $name:pat = $top:expr => $bottom:expr $(, $($rest:tt)*)?) => (
+        $crate::select_arms!(@arms $add [$($arms)* (one ($name) ($top) ($bottom))] [$($guards)* (true)] $($($rest)*)?)
+    );
+
+    // `$add` is `add`, or `add_local` for the selectors that are not `Send`
+    // a disabled arm is never registered, it keeps its token
+    (@add $mode:ident $add:ident $cqueue:ident $token:ident $guards:ident (one ($name:pat) ($top:expr) ($bottom:expr))) => {
+        if $guards[$token] {
+            $cqueue.$add($token, |es| {
+                $crate::select_arms!(@body $mode es ($name) ($top) ($bottom))
+            });
+        }
+        $token += 1;
+    };
+    (@add $mode:ident $add:ident $cqueue:ident $token:ident $guards:ident (any ($name:pat) ($src:expr) ($method:ident) ($($args:tt)*) ($bottom:expr))) => {
+        let _srcs: ::std::vec::Vec<_> = if $guards[$token] {
+            ::std::iter::IntoIterator::into_iter($src).collect()
+        } else {
+            ::std::vec::Vec::new()
+        };
+        let _f = |_i: usize, es: $crate::cqueue::EventSender| {
+            $crate::select_arms!(@body $mode es ($name) ((_i, _srcs[_i].$method($($args)*))) ($bottom))
+        };
+        for _i in 0.._srcs.len() {
+            let _f = &_f;
+            $cqueue.$add($token, move |es| _f(_i, es));
+        }
+        // the selectors borrow `_srcs` and `_f`, finish them before the two are dropped
+        let _guard = $cqueue.guard();
+        $token += 1;
+    };
+
+    // the body runs only for the arm that commits, see `EventSender::select_arm`
+    (@body always $es:ident ($name:pat) ($top:expr) ($bottom:expr)) => {{
+        if let ::std::option::Option::Some(_ret) = $es.select_arm(|| $top) {
+            #[allow(irrefutable_let_patterns)]
+            if let $name = _ret {
+                $bottom;
+            }
+            $es.send($es.get_token());
+        }
+    }};
+    (@body matched $es:ident ($name:pat) ($top:expr) ($bottom:expr)) => {{
+        if let ::std::option::Option::Some(_ret) = $es.select_arm(|| $top) {
+            #[allow(irrefutable_let_patterns)]
+            if let $name = _ret {
+                $bottom;
+                $es.send($es.get_token());
+            } else {
+                $es.select_give_up();
+            }
+        }
+    }};
+}
+
 /// macro used to select for only one event
 /// it will return the index of which event happens first
 /// for example:
@@ -154,43 +649,158 @@ macro_rules! select {
 /// ```
 #[macro_export]
 macro_rules! select_token {
+    (
+        $($name:pat = $top:expr => $bottom:expr), +$(,)?
+    ) => ($crate::select_arms!(@arms add [] [] $($name = $top => $bottom),+));
+}
+
+/// macro used to select for events in a loop
+/// the select coroutines are registered only once with `cqueue_add!` style
+/// persistent selectors, and the arm bodies are run each time an event happens
+///
+/// an arm body can evaluate to `()` to keep looping, or to a
+/// `std::ops::ControlFlow<()>` where `ControlFlow::Break(())` stops the loop
+///
+/// an arm is deregistered when the result of its expression no longer matches
+/// its pattern, so use `Ok(v) = rx.recv()` to stop servicing a disconnected
+/// channel instead of spinning on the error. the loop also ends when all the
+/// arms are deregistered
+///
+/// for example:
+/// ```rust
+/// use mco::{chan, select_loop};
+/// use std::ops::ControlFlow;
+///
+///     let (s, r) = chan!();
+///     s.send(1);
+///     s.send(2);
+///     drop(s);
+///     select_loop! {
+///         Ok(v) = r.recv() => {
+///             println!("{:?}", v);
+///             if v == 2 {
+///                 return ControlFlow::Break(());
+///             }
+///             ControlFlow::Continue(())
+///         }
+///     };
+/// ```
+#[macro_export]
+macro_rules! select_loop {
     (
         $($name:pat = $top:expr => $bottom:expr), +$(,)?
     ) => ({
         $crate::cqueue::scope(|cqueue| {
+            let _stop = ::std::sync::atomic::AtomicBool::new(false);
             let mut _token = 0;
             $(
-                $crate::cqueue_add_oneshot!(cqueue, _token, $name = $top => $bottom);
+                $crate::co!(cqueue, _token, |es| loop {
+                    match $top {
+                        $name => {
+                            es.send(es.get_token());
+                            if $crate::cqueue::LoopFlow::is_break(&(|| $bottom)()) {
+                                _stop.store(true, ::std::sync::atomic::Ordering::Relaxed);
+                            }
+                        }
+                        #[allow(unreachable_patterns)]
+                        _ => break,
+                    }
+                });
                 _token += 1;
             )+
-            match cqueue.poll(None) {
-                Ok(ev) => return ev.token,
-                _ => unreachable!("select error"),
+            while cqueue.poll(None).is_ok() {
+                if _stop.load(::std::sync::atomic::Ordering::Relaxed) {
+                    break;
+                }
             }
         })
     });
 }
 
 /// macro used to join all scoped sub coroutines
+///
+/// the branches run concurrently, the results are returned as a tuple in
+/// the input order no matter which branch finishes first
 /// for example:
 /// ```rust
 /// use mco::join;
-/// join!({  },
-///       {  },
-///       {  }
+/// let (a, b, c) = join!(
+///     {
+///         mco::coroutine::sleep(std::time::Duration::from_millis(10));
+///         1
+///     },
+///     2,
+///     "three"
 /// );
+/// assert_eq!((a, b, c), (1, 2, "three"));
 /// ```
 #[macro_export]
 macro_rules! join {
+    // each branch gets its own handle, `h` is a distinct ident in each expansion
+    (@collect $s:ident [$($h:ident = $body:expr;)*]) => {{
+        $(
+            let $h = $crate::co!($s, || $body);
+        )*
+        ( $( $h.join(), )* )
+    }};
+    (@collect $s:ident [$($acc:tt)*] $body:expr $(, $rest:expr)*) => {
+        $crate::join!(@collect $s [$($acc)* h = $body;] $($rest),*)
+    };
+    (
+        $($body:expr),+ $(,)?
+    ) => ({
+        use $crate::coroutine;
+        coroutine::scope(|s| $crate::join!(@collect s [] $($body),+))
+    })
+}
+
+/// run the branches concurrently, return the value of the first finished one
+///
+/// the other branches are canceled and joined before it returns. they exit
+/// at their next blocking call of the library, a branch that is blocked in a
+/// call that can't be canceled, or that never blocks, keeps running until it
+/// reaches one, so `race!` returns no earlier than that. a panic of any
+/// branch is propagated
+///
+/// ```rust
+/// use mco::race;
+/// use mco::coroutine::sleep;
+/// use std::time::Duration;
+///
+/// let v = race!(
+///     {
+///         sleep(Duration::from_millis(10));
+///         1
+///     },
+///     {
+///         sleep(Duration::from_millis(500));
+///         2
+///     }
+/// );
+/// assert_eq!(v, 1);
+/// ```
+#[macro_export]
+macro_rules! race {
     (
-        $($body:expr),+
+        $($body:expr),+ $(,)?
     ) => ({
         use $crate::coroutine;
         coroutine::scope(|s| {
-            $(
-                $crate::co!(s, || $body);
-            )+
+            let (tx, rx) = $crate::std::sync::channel::channel();
+            $({
+                let tx = tx.clone();
+                $crate::co!(s, || {
+                    let tx = tx;
+                    let _ = tx.send($body);
+                });
+            })+
+            drop(tx);
+            // it's disconnected only if all the branches panicked
+            let ret = rx.recv().ok();
+            s.cancel();
+            ret
         })
+        .expect("race! without any result")
     })
 }
 
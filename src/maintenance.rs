@@ -0,0 +1,205 @@
+//! the maintenance callbacks, see `runtime::register_maintenance`
+//!
+//! the callbacks run on the event loop of worker 0 of any runtime, between
+//! the rounds of the io driver. the worker shortens its wait in the driver to
+//! the next due callback, so they run even if no coroutine is runnable. a
+//! callback is late by up to one `MAINTENANCE_TICK` when the worker is idle,
+//! and by the coroutines that the worker runs before it comes back otherwise
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::scheduler::default_scheduler;
+use crate::timeout_list::now_instant;
+
+/// the granularity of the maintenance callbacks, a shorter interval is
+/// rounded up to it
+pub const MAINTENANCE_TICK: Duration = Duration::from_millis(10);
+
+/// the time that a maintenance callback should finish in, a debug build
+/// panics on the worker when a callback takes longer
+pub const SLOW_MAINTENANCE: Duration = Duration::from_millis(50);
+
+// the runs, the panics and the total time of all the callbacks
+pub(crate) static RUNS: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static PANICS: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static NANOS: AtomicU64 = AtomicU64::new(0);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+// the number of the callbacks, so that an idle tick is one load
+static LEN: AtomicUsize = AtomicUsize::new(0);
+static ENTRIES: Lazy<Mutex<Vec<Arc<Entry>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+// only one worker 0 runs the callbacks at a time
+static TICKING: Mutex<()> = parking_lot::const_mutex(());
+
+struct Entry {
+    id: u64,
+    f: Box<dyn Fn() + Send + Sync>,
+    interval: Duration,
+    next: Mutex<Instant>,
+    runs: AtomicUsize,
+    nanos: AtomicU64,
+    removed: AtomicBool,
+}
+
+/// the registration of a maintenance callback, it's unregistered when the
+/// guard is dropped
+///
+/// a run that is already started when the guard is dropped still finishes
+#[must_use]
+pub struct MaintenanceGuard {
+    entry: Arc<Entry>,
+}
+
+impl MaintenanceGuard {
+    /// the times that the callback has run
+    pub fn runs(&self) -> usize {
+        self.entry.runs.load(Ordering::Relaxed)
+    }
+
+    /// the total time that the callback has run for
+    pub fn total_time(&self) -> Duration {
+        Duration::from_nanos(self.entry.nanos.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for MaintenanceGuard {
+    fn drop(&mut self) {
+        self.entry.removed.store(true, Ordering::Release);
+        let mut entries = ENTRIES.lock();
+        entries.retain(|e| e.id != self.entry.id);
+        LEN.store(entries.len(), Ordering::Release);
+    }
+}
+
+impl fmt::Debug for MaintenanceGuard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MaintenanceGuard")
+            .field("interval", &self.entry.interval)
+            .field("runs", &self.runs())
+            .field("total_time", &self.total_time())
+            .finish()
+    }
+}
+
+/// run `f` every `interval` on a maintenance tick of the runtime, until the
+/// returned guard is dropped
+///
+/// the timing is best effort, see `MAINTENANCE_TICK`. `f` runs on a worker
+/// thread outside of any coroutine and holds up the coroutines of that
+/// worker, so it must not block: no channel waits, locks held by coroutines,
+/// sleeps or blocking io. a callback that takes longer than
+/// `SLOW_MAINTENANCE` fails a debug assertion. a panic of `f` is caught and
+/// counted in `stats().maintenance_panics`, `f` keeps its registration. the
+/// default runtime is started if it's not yet
+///
+/// ```
+/// use mco::runtime::register_maintenance;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let ticks = Arc::new(AtomicUsize::new(0));
+/// let t = ticks.clone();
+/// let guard = register_maintenance(Duration::from_millis(10), move || {
+///     t.fetch_add(1, Ordering::Relaxed);
+/// });
+/// std::thread::sleep(Duration::from_millis(100));
+/// drop(guard);
+/// assert!(ticks.load(Ordering::Relaxed) > 0);
+/// ```
+pub fn register_maintenance<F>(interval: Duration, f: F) -> MaintenanceGuard
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let interval = interval.max(MAINTENANCE_TICK);
+    let entry = Arc::new(Entry {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        f: Box::new(f),
+        interval,
+        next: Mutex::new(now_instant() + interval),
+        runs: AtomicUsize::new(0),
+        nanos: AtomicU64::new(0),
+        removed: AtomicBool::new(false),
+    });
+    {
+        let mut entries = ENTRIES.lock();
+        entries.push(entry.clone());
+        LEN.store(entries.len(), Ordering::Release);
+    }
+    // the workers are started and worker 0 sees the new deadline
+    let s = default_scheduler();
+    s.get_selector().wakeup(0);
+    MaintenanceGuard { entry }
+}
+
+// run the due callbacks on worker 0, return the timeout of the next wait in
+// the io driver in ns, no longer than `timeout`
+pub(crate) fn tick(id: usize, timeout: Option<u64>) -> Option<u64> {
+    if id != 0 || LEN.load(Ordering::Acquire) == 0 {
+        return timeout;
+    }
+    if let Some(_ticking) = TICKING.try_lock() {
+        run_due();
+    }
+
+    let now = now_instant();
+    let next = ENTRIES.lock().iter().map(|e| *e.next.lock()).min();
+    let till_next = match next {
+        Some(next) => next.saturating_duration_since(now).as_nanos() as u64,
+        None => return timeout,
+    };
+    Some(timeout.map_or(till_next, |t| t.min(till_next)))
+}
+
+fn run_due() {
+    let now = now_instant();
+    let due: Vec<_> = ENTRIES
+        .lock()
+        .iter()
+        .filter(|e| {
+            let mut next = e.next.lock();
+            if *next > now {
+                return false;
+            }
+            // don't catch up on the missed runs
+            *next += e.interval;
+            if *next <= now {
+                *next = now + e.interval;
+            }
+            true
+        })
+        .cloned()
+        .collect();
+
+    let mut slowest = Duration::from_secs(0);
+    for e in due {
+        if e.removed.load(Ordering::Acquire) {
+            continue;
+        }
+        let start = Instant::now();
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| (e.f)()));
+        let spent = start.elapsed();
+        let nanos = spent.as_nanos() as u64;
+        e.runs.fetch_add(1, Ordering::Relaxed);
+        e.nanos.fetch_add(nanos, Ordering::Relaxed);
+        RUNS.fetch_add(1, Ordering::Relaxed);
+        NANOS.fetch_add(nanos, Ordering::Relaxed);
+        if ret.is_err() {
+            PANICS.fetch_add(1, Ordering::Relaxed);
+            error!("maintenance callback panicked");
+        }
+        slowest = slowest.max(spent);
+    }
+    debug_assert!(
+        slowest <= SLOW_MAINTENANCE,
+        "a maintenance callback took {:?}, it must not block",
+        slowest
+    );
+}
@@ -0,0 +1,166 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{TcpListener, TcpStream};
+use crate::config::config;
+use crate::coroutine_impl::Builder;
+use crate::join::JoinHandle;
+use crate::sleep::sleep;
+use crate::std::sync::WaitGroup;
+
+/// the acceptor coroutines started by `TcpListener::accept_loop`
+///
+/// `shutdown` stops accepting new connections, `join` waits for the
+/// acceptors and all the in flight handlers to finish
+pub struct AcceptLoop {
+    addr: SocketAddr,
+    acceptors: Vec<JoinHandle<()>>,
+    conns: WaitGroup,
+}
+
+impl AcceptLoop {
+    pub(crate) fn start<F>(
+        listener: TcpListener,
+        n_acceptors: usize,
+        stack_size: Option<usize>,
+        handler: F,
+    ) -> io::Result<AcceptLoop>
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        let n = if n_acceptors == 0 {
+            config().get_workers()
+        } else {
+            n_acceptors
+        };
+        let addr = listener.local_addr()?;
+        let mut listeners = Vec::with_capacity(n);
+        for _ in 1..n {
+            listeners.push(Self::another(&listener, addr)?);
+        }
+        listeners.push(listener);
+
+        let handler = Arc::new(handler);
+        let conns = WaitGroup::new();
+        let acceptors = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(i, listener)| {
+                let handler = handler.clone();
+                let conns = conns.clone();
+                Builder::new()
+                    .name(format!("acceptor-{}", i))
+                    .spawn(move || accept(listener, stack_size, handler, conns))
+            })
+            .collect();
+
+        Ok(AcceptLoop {
+            addr,
+            acceptors,
+            conns,
+        })
+    }
+
+    // with SO_REUSEPORT each acceptor has its own listener and the kernel
+    // balances the connections between them
+    #[cfg(unix)]
+    fn another(listener: &TcpListener, addr: SocketAddr) -> io::Result<TcpListener> {
+        TcpListener::bind(addr).or_else(|_| listener.try_clone())
+    }
+
+    #[cfg(not(unix))]
+    fn another(listener: &TcpListener, _addr: SocketAddr) -> io::Result<TcpListener> {
+        listener.try_clone()
+    }
+
+    /// the address the acceptors are listening on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// stop accepting new connections, the listeners are closed
+    /// the handlers in flight are not affected
+    pub fn shutdown(&self) {
+        for j in self.acceptors.iter() {
+            j.coroutine().cancel();
+        }
+    }
+
+    /// wait for the acceptors to exit and the handlers in flight to finish
+    ///
+    /// the acceptors only exit after `shutdown`, so this would block forever
+    /// if `shutdown` is never called
+    pub fn join(self) {
+        for j in self.acceptors {
+            // the canceled acceptors return a Cancel panic
+            let _ = j.join();
+        }
+        self.conns.wait();
+    }
+}
+
+fn accept<F>(listener: TcpListener, stack_size: Option<usize>, handler: Arc<F>, conns: WaitGroup)
+where
+    F: Fn(TcpStream) + Send + Sync + 'static,
+{
+    // the wait after an accept error that the next accept would hit again
+    let mut delay = Duration::ZERO;
+    for stream in listener.incoming() {
+        match stream {
+            Ok(s) => {
+                delay = Duration::ZERO;
+                let handler = handler.clone();
+                let conns = conns.clone();
+                let mut builder = Builder::new();
+                if let Some(size) = stack_size {
+                    builder = builder.stack_size(size);
+                }
+                // the connection is closed when its handler can't run
+                if let Err(e) = builder.try_spawn(move || {
+                    handler(s);
+                    drop(conns);
+                }) {
+                    error!("accept: can't spawn the handler, err={}", e);
+                }
+            }
+            Err(e) => {
+                error!("accept err={}", e);
+                // the listener stays readable while the fds are exhausted,
+                // back off like net/http does instead of spinning
+                if is_exhausted(&e) {
+                    delay = if delay == Duration::ZERO {
+                        MIN_BACKOFF
+                    } else {
+                        (delay * 2).min(MAX_BACKOFF)
+                    };
+                    sleep(delay);
+                }
+            }
+        }
+    }
+}
+
+const MIN_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+// the accept errors of the process or system limits
+#[cfg(unix)]
+fn is_exhausted(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EMFILE) | Some(libc::ENFILE) | Some(libc::ENOBUFS) | Some(libc::ENOMEM)
+    )
+}
+
+#[cfg(windows)]
+fn is_exhausted(e: &io::Error) -> bool {
+    use winapi::shared::winerror::{WSAEMFILE, WSAENOBUFS};
+    matches!(e.raw_os_error(), Some(c) if c == WSAEMFILE as i32 || c == WSAENOBUFS as i32)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_exhausted(_e: &io::Error) -> bool {
+    false
+}
@@ -1,8 +1,14 @@
 //! Networking primitives
 //!
 
+mod accept_loop;
+#[cfg(unix)]
+mod ready;
 mod tcp;
 mod udp;
 
+pub use self::accept_loop::AcceptLoop;
+#[cfg(unix)]
+pub use self::ready::SocketReady;
 pub use self::tcp::{TcpListener, TcpStream};
-pub use self::udp::UdpSocket;
+pub use self::udp::{MsgBuf, OutMsg, UdpSocket};
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io;
+use std::os::unix::io::RawFd;
+
+use crate::io as io_impl;
+use crate::select::{Selectable, Waker};
+
+/// the readiness of a socket as a source of a `SelectSet`, see
+/// `TcpStream::readable` and `UdpSocket::readable`
+///
+/// it's ready when the read or the write would not block, including the
+/// errors and the hang ups, no data is taken. the readiness is checked with
+/// the os each time, so a readiness that is left from an earlier select or
+/// taken by another reader since is never reported
+pub struct SocketReady<'a> {
+    io: &'a io_impl::IoData,
+    fd: RawFd,
+    events: libc::c_short,
+}
+
+impl<'a> SocketReady<'a> {
+    pub(crate) fn readable(io: &'a io_impl::IoData, fd: RawFd) -> Self {
+        SocketReady {
+            io,
+            fd,
+            events: libc::POLLIN,
+        }
+    }
+
+    pub(crate) fn writable(io: &'a io_impl::IoData, fd: RawFd) -> Self {
+        SocketReady {
+            io,
+            fd,
+            events: libc::POLLOUT,
+        }
+    }
+}
+
+impl<'a> Selectable for SocketReady<'a> {
+    type Output = io::Result<()>;
+
+    fn try_select(&mut self) -> Option<io::Result<()>> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events: self.events,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut pfd, 1, 0) } {
+            0 => None,
+            n if n < 0 => {
+                let err = io::Error::last_os_error();
+                match err.kind() {
+                    io::ErrorKind::Interrupted => None,
+                    _ => Some(Err(err)),
+                }
+            }
+            _ if pfd.revents & libc::POLLNVAL != 0 => {
+                Some(Err(io::Error::from_raw_os_error(libc::EBADF)))
+            }
+            _ => Some(Ok(())),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.io.selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.io.selectors.deregister(waker);
+    }
+}
+
+impl<'a> fmt::Debug for SocketReady<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SocketReady")
+            .field("fd", &self.fd)
+            .field("writable", &(self.events == libc::POLLOUT))
+            .finish()
+    }
+}
@@ -1,12 +1,27 @@
+use std::fs::File;
 use std::io::{self, ErrorKind, Read, Write};
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+use std::io::{Seek, SeekFrom};
+#[cfg(unix)]
+use std::mem::MaybeUninit;
 use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
 use std::time::Duration;
 
+use super::AcceptLoop;
+use crate::config::config;
 use crate::coroutine_impl::is_coroutine;
 use crate::io as io_impl;
 use crate::io::net as net_impl;
+use crate::std::bytes::{self, Bytes, BytesMut};
+use crate::std::context::{Context, ContextError};
 use crate::std::sync::atomic_dur::AtomicDuration;
-use crate::yield_now::yield_with;
+use crate::yield_now::{yield_now, yield_with};
 
 // ===== TcpStream =====
 //
@@ -41,6 +56,28 @@ impl TcpStream {
         &self.sys
     }
 
+    /// convert a std TcpStream to a coroutine one, it's set to non-blocking mode
+    /// and registered with the io driver
+    ///
+    /// this is useful when the socket comes from another framework,
+    /// e.g. the socket activation of systemd
+    pub fn from_std(s: net::TcpStream) -> io::Result<TcpStream> {
+        TcpStream::new(s)
+    }
+
+    /// convert back to a std TcpStream, it's deregistered from the io driver and
+    /// set to blocking mode
+    ///
+    /// on windows the socket is still associated with the completion port,
+    /// the std socket should only be used for blocking io
+    pub fn into_std(self) -> io::Result<net::TcpStream> {
+        let TcpStream { io, sys, .. } = self;
+        // deregister from the selector
+        drop(io);
+        sys.set_nonblocking(false)?;
+        Ok(sys)
+    }
+
     pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
         if !is_coroutine() {
             let s = net::TcpStream::connect(addr)?;
@@ -83,6 +120,35 @@ impl TcpStream {
         c.done()
     }
 
+    /// connect with the context, each address is tried with the time left
+    /// before the deadline of the context
+    pub fn connect_ctx<A: ToSocketAddrs>(addr: A, ctx: &Context) -> io::Result<TcpStream> {
+        let connect = || {
+            if ctx.deadline().is_none() {
+                return TcpStream::connect(addr);
+            }
+            let mut last_err = None;
+            for addr in addr.to_socket_addrs()? {
+                match ctx.remaining() {
+                    Some(left) if left > Duration::from_secs(0) => {
+                        match TcpStream::connect_timeout(&addr, left) {
+                            Ok(s) => return Ok(s),
+                            Err(e) => last_err = Some(e),
+                        }
+                    }
+                    _ => return Err(ContextError::DeadlineExceeded.into()),
+                }
+            }
+            Err(last_err.unwrap_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "could not resolve to any addresses",
+                )
+            }))
+        };
+        ctx.guard(false, connect)?
+    }
+
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
         self.sys.peer_addr()
     }
@@ -128,6 +194,42 @@ impl TcpStream {
         self.sys.take_error()
     }
 
+    /// read the data like `read` without taking it, the next read returns
+    /// it again, e.g. to sniff the protocol before the dispatch
+    ///
+    /// it parks the coroutine until the data comes in, for the read timeout
+    /// of the stream at most. the readiness is left to the next read, which
+    /// tries the socket first, so no data is missed after a peek
+    #[cfg(unix)]
+    pub fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+        {
+            return self.sys.peek(buf);
+        }
+
+        self.io.reset();
+        // this is an earlier return try for nonblocking peek
+        match self.sys.peek(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::SocketRead::peek(self, buf, self.read_timeout.get());
+        yield_with(&reader);
+        reader.done()
+    }
+
     pub fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
         self.sys.set_read_timeout(dur)?;
         self.read_timeout.swap(dur);
@@ -161,6 +263,196 @@ impl TcpStream {
         self.sys.ttl()
     }
 
+    /// the readability of the stream as a source of a `SelectSet`, or an
+    /// arm of `select!` by `wait`. no data is read
+    /// ```no_run
+    /// use mco::net::TcpStream;
+    /// use mco::select::SelectSet;
+    ///
+    /// let stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+    /// let (_tx, ctrl) = mco::chan!();
+    /// let mut set = SelectSet::new();
+    /// set.add(stream.readable(), |r| r.map(|_| None));
+    /// set.add(&ctrl, |m| Ok(m.ok()));
+    /// let _: std::io::Result<Option<u32>> = set.select();
+    /// ```
+    #[cfg(unix)]
+    pub fn readable(&self) -> super::SocketReady<'_> {
+        super::SocketReady::readable(&self.io, self.sys.as_raw_fd())
+    }
+
+    /// the writability of the stream as a source of a `SelectSet`, see
+    /// `readable`
+    #[cfg(unix)]
+    pub fn writable(&self) -> super::SocketReady<'_> {
+        super::SocketReady::writable(&self.io, self.sys.as_raw_fd())
+    }
+
+    /// read with the context, the deadline of the context caps the read
+    /// timeout of the stream
+    pub fn read_ctx(&mut self, ctx: &Context, buf: &mut [u8]) -> io::Result<usize> {
+        ctx.guard(false, || {
+            let timeout = ctx.timeout(self.read_timeout.get());
+            self.read_with_timeout(buf, timeout)
+        })?
+    }
+
+    /// write with the context, the deadline of the context caps the write
+    /// timeout of the stream
+    pub fn write_ctx(&mut self, ctx: &Context, buf: &[u8]) -> io::Result<usize> {
+        ctx.guard(false, || {
+            let timeout = ctx.timeout(self.write_timeout.get());
+            self.write_with_timeout(buf, timeout)
+        })?
+    }
+
+    /// fill the whole buffer like `read_exact`, the error has the bytes that
+    /// are read before a timeout or any other error, so the caller can go on
+    /// from there, see `io::read_exact_progress`
+    pub fn read_exact_progress(&mut self, buf: &mut [u8]) -> Result<(), io_impl::PartialIoError> {
+        io_impl::read_exact_progress(self, buf)
+    }
+
+    /// write the whole buffer like `write_all`, the error has the bytes that
+    /// are written before it, see `io::write_all_progress`
+    pub fn write_all_progress(&mut self, buf: &[u8]) -> Result<(), io_impl::PartialIoError> {
+        io_impl::write_all_progress(self, buf)
+    }
+
+    /// read into the spare capacity of `buf` and extend its length, return
+    /// the bytes read. at least 4KB is reserved if `buf` is full, the spare
+    /// bytes are received into directly without zeroing them first
+    #[cfg(unix)]
+    pub fn read_buf(&mut self, buf: &mut BytesMut) -> io::Result<usize> {
+        bytes::read_uninit(buf, |spare| self.read_uninit(spare))
+    }
+
+    /// read into the spare capacity of `buf` and extend its length, return
+    /// the bytes read. at least 4KB is reserved if `buf` is full, at most 64KB
+    /// of the spare bytes are zeroed and read into at a time
+    #[cfg(not(unix))]
+    pub fn read_buf(&mut self, buf: &mut BytesMut) -> io::Result<usize> {
+        buf.read_from(self)
+    }
+
+    #[cfg(unix)]
+    fn read_uninit(&mut self, buf: &mut [MaybeUninit<u8>]) -> io::Result<usize> {
+        let fd = self.sys.as_raw_fd();
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+        {
+            return net_impl::recv_uninit(fd, buf);
+        }
+
+        self.io.reset();
+        // this is an earlier return try for nonblocking read
+        match net_impl::recv_uninit(fd, buf) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::SocketRead::uninit(self, buf, self.read_timeout.get());
+        yield_with(&reader);
+        reader.done()
+    }
+
+    /// write all the buffers by the vectored writes without copying them
+    /// into one, e.g. a frame header and the shared payload
+    pub fn write_all_bytes(&mut self, bufs: &[Bytes]) -> io::Result<()> {
+        bytes::write_all_bytes(self, bufs)
+    }
+
+    /// send `len` bytes of `file` from `offset`, return the bytes sent, it's
+    /// less than `len` only when the file ends
+    ///
+    /// it's zero-copy with `sendfile(2)` on linux, freebsd and macos, the
+    /// coroutine is parked when the socket buffer is full and the offset of
+    /// `file` is not changed. each syscall sends at most
+    /// `config().get_send_file_chunk()` bytes and the coroutine yields between
+    /// the chunks. on the other platforms the file is read into a buffer and
+    /// written instead, which moves the offset of `file`
+    ///
+    /// like `write_all`, the bytes sent before an error are not reported
+    pub fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<u64> {
+        let chunk = config().get_send_file_chunk() as u64;
+        let mut sent = 0;
+        while sent < len {
+            let n = (len - sent).min(chunk) as usize;
+            match self.send_file_chunk(file, offset + sent, n)? {
+                0 => break,
+                n => sent += n as u64,
+            }
+            if sent < len && is_coroutine() {
+                yield_now();
+            }
+        }
+        Ok(sent)
+    }
+
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "ios"
+    ))]
+    fn send_file_chunk(&mut self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        let fd = file.as_raw_fd();
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+        {
+            return net_impl::sendfile(self.sys.as_raw_fd(), fd, offset, len);
+        }
+
+        self.io.reset();
+        // this is an earlier return try for nonblocking sendfile
+        match net_impl::sendfile(self.sys.as_raw_fd(), fd, offset, len) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut sender =
+            net_impl::SocketSendFile::new(self, fd, offset, len, self.write_timeout.get());
+        yield_with(&sender);
+        sender.done()
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "macos",
+        target_os = "ios"
+    )))]
+    fn send_file_chunk(&mut self, file: &File, offset: u64, len: usize) -> io::Result<usize> {
+        let mut file = file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![0; len.min(64 * 1024)];
+        let n = file.read(&mut buf)?;
+        self.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
     // convert std::net::TcpStream to Self without add_socket
     pub(crate) fn from_stream(s: net::TcpStream, io: io_impl::IoData) -> Self {
         TcpStream {
@@ -171,10 +463,12 @@ impl TcpStream {
             write_timeout: AtomicDuration::new(None),
         }
     }
-}
 
-impl Read for TcpStream {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    fn read_with_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<usize> {
         if self
             .ctx
             .check_nonblocking(|b| self.sys.set_nonblocking(b))?
@@ -202,14 +496,12 @@ impl Read for TcpStream {
             }
         }
 
-        let mut reader = net_impl::SocketRead::new(self, buf, self.read_timeout.get());
+        let mut reader = net_impl::SocketRead::new(self, buf, timeout);
         yield_with(&reader);
         reader.done()
     }
-}
 
-impl Write for TcpStream {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    fn write_with_timeout(&mut self, buf: &[u8], timeout: Option<Duration>) -> io::Result<usize> {
         if self
             .ctx
             .check_nonblocking(|b| self.sys.set_nonblocking(b))?
@@ -236,10 +528,22 @@ impl Write for TcpStream {
             }
         }
 
-        let mut writer = net_impl::SocketWrite::new(self, buf, self.write_timeout.get());
+        let mut writer = net_impl::SocketWrite::new(self, buf, timeout);
         yield_with(&writer);
         writer.done()
     }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_with_timeout(buf, self.read_timeout.get())
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_with_timeout(buf, self.write_timeout.get())
+    }
 
     #[cfg(unix)]
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
@@ -336,6 +640,28 @@ impl TcpListener {
         &self.sys
     }
 
+    /// convert a std TcpListener to a coroutine one, it's set to non-blocking mode
+    /// and registered with the io driver
+    ///
+    /// this is useful when the socket comes from another framework,
+    /// e.g. the socket activation of systemd
+    pub fn from_std(s: net::TcpListener) -> io::Result<TcpListener> {
+        TcpListener::new(s)
+    }
+
+    /// convert back to a std TcpListener, it's deregistered from the io driver and
+    /// set to blocking mode
+    ///
+    /// on windows the socket is still associated with the completion port,
+    /// the std socket should only be used for blocking io
+    pub fn into_std(self) -> io::Result<net::TcpListener> {
+        let TcpListener { io, sys, .. } = self;
+        // deregister from the selector
+        drop(io);
+        sys.set_nonblocking(false)?;
+        Ok(sys)
+    }
+
     pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
         use socket2::{Domain, Socket, Type};
         let mut addrs = addr.to_socket_addrs()?;
@@ -399,6 +725,51 @@ impl TcpListener {
         a.done()
     }
 
+    /// spawn `n_acceptors` acceptor coroutines, each accepted stream is passed
+    /// to `handler` in a new coroutine. `0` means one acceptor per worker
+    ///
+    /// on unix every acceptor binds its own listener with SO_REUSEPORT,
+    /// so the kernel spreads the connections over them
+    /// for example:
+    /// ```no_run
+    /// use mco::net::TcpListener;
+    /// use std::io::{Read, Write};
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+    /// let server = listener
+    ///     .accept_loop(0, |mut s| {
+    ///         let mut buf = [0; 1024];
+    ///         while let Ok(n) = s.read(&mut buf) {
+    ///             if n == 0 || s.write_all(&buf[..n]).is_err() {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// // stop accepting and wait for the connections to finish
+    /// server.shutdown();
+    /// server.join();
+    /// ```
+    pub fn accept_loop<F>(self, n_acceptors: usize, handler: F) -> io::Result<AcceptLoop>
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        AcceptLoop::start(self, n_acceptors, None, handler)
+    }
+
+    /// the same as `accept_loop`, the handler coroutines use the given stack size
+    pub fn accept_loop_with<F>(
+        self,
+        n_acceptors: usize,
+        stack_size: usize,
+        handler: F,
+    ) -> io::Result<AcceptLoop>
+    where
+        F: Fn(TcpStream) + Send + Sync + 'static,
+    {
+        AcceptLoop::start(self, n_acceptors, Some(stack_size), handler)
+    }
+
     pub fn incoming(&self) -> Incoming {
         Incoming { listener: self }
     }
@@ -466,6 +837,8 @@ impl<'a> Iterator for Incoming<'a> {
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 
+/// the fd is deregistered from the io driver and left in non-blocking mode,
+/// use `into_std` to get a blocking socket
 #[cfg(unix)]
 impl IntoRawFd for TcpStream {
     fn into_raw_fd(self) -> RawFd {
@@ -481,6 +854,9 @@ impl AsRawFd for TcpStream {
     }
 }
 
+/// the TcpStream takes over the fd, it's set to non-blocking mode and registered
+/// with the io driver, the fd must not be registered by anyone else.
+/// panics if the registration fails, use `from_std` to get the error instead
 #[cfg(unix)]
 impl FromRawFd for TcpStream {
     unsafe fn from_raw_fd(fd: RawFd) -> TcpStream {
@@ -489,6 +865,8 @@ impl FromRawFd for TcpStream {
     }
 }
 
+/// the fd is deregistered from the io driver and left in non-blocking mode,
+/// use `into_std` to get a blocking socket
 #[cfg(unix)]
 impl IntoRawFd for TcpListener {
     fn into_raw_fd(self) -> RawFd {
@@ -504,6 +882,9 @@ impl AsRawFd for TcpListener {
     }
 }
 
+/// the TcpListener takes over the fd, it's set to non-blocking mode and registered
+/// with the io driver, the fd must not be registered by anyone else.
+/// panics if the registration fails, use `from_std` to get the error instead
 #[cfg(unix)]
 impl FromRawFd for TcpListener {
     unsafe fn from_raw_fd(fd: RawFd) -> TcpListener {
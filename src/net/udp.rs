@@ -7,6 +7,66 @@ use crate::io::net as net_impl;
 use crate::std::sync::atomic_dur::AtomicDuration;
 use crate::yield_now::yield_with;
 
+/// a caller owned buffer that receives one datagram of `UdpSocket::recv_mmsg`
+#[derive(Debug)]
+pub struct MsgBuf<'a> {
+    pub(crate) buf: &'a mut [u8],
+    pub(crate) len: usize,
+    pub(crate) addr: Option<SocketAddr>,
+}
+
+impl<'a> MsgBuf<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        MsgBuf {
+            buf,
+            len: 0,
+            addr: None,
+        }
+    }
+
+    /// the received datagram
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// the length of the received datagram
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the source of the received datagram
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+}
+
+/// one datagram of `UdpSocket::send_mmsg`, the address is not needed
+/// for a connected socket
+#[derive(Debug, Clone, Copy)]
+pub struct OutMsg<'a> {
+    pub buf: &'a [u8],
+    pub addr: Option<SocketAddr>,
+}
+
+impl<'a> OutMsg<'a> {
+    /// a datagram for the connected peer
+    pub fn new(buf: &'a [u8]) -> Self {
+        OutMsg { buf, addr: None }
+    }
+
+    /// a datagram for `addr`
+    pub fn to(buf: &'a [u8], addr: SocketAddr) -> Self {
+        OutMsg {
+            buf,
+            addr: Some(addr),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UdpSocket {
     io: io_impl::IoData,
@@ -36,6 +96,28 @@ impl UdpSocket {
         &self.sys
     }
 
+    /// convert a std UdpSocket to a coroutine one, it's set to non-blocking mode
+    /// and registered with the io driver
+    ///
+    /// this is useful when the socket comes from another framework,
+    /// e.g. the socket activation of systemd
+    pub fn from_std(s: net::UdpSocket) -> io::Result<UdpSocket> {
+        UdpSocket::new(s)
+    }
+
+    /// convert back to a std UdpSocket, it's deregistered from the io driver and
+    /// set to blocking mode
+    ///
+    /// on windows the socket is still associated with the completion port,
+    /// the std socket should only be used for blocking io
+    pub fn into_std(self) -> io::Result<net::UdpSocket> {
+        let UdpSocket { io, sys, .. } = self;
+        // deregister from the selector
+        drop(io);
+        sys.set_nonblocking(false)?;
+        Ok(sys)
+    }
+
     pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
         net::UdpSocket::bind(addr).and_then(UdpSocket::new)
     }
@@ -139,6 +221,40 @@ impl UdpSocket {
         reader.done()
     }
 
+    /// receive a datagram like `recv_from` without taking it, the next
+    /// receive returns it again
+    ///
+    /// it parks the coroutine until a datagram comes in, like `recv_from`
+    #[cfg(unix)]
+    pub fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        if self
+            .ctx
+            .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+            || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+        {
+            return self.sys.peek_from(buf);
+        }
+
+        self.io.reset();
+        // this is an earlier return try for nonblocking peek
+        match self.sys.peek_from(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) => {
+                // raw_os_error is faster than kind
+                let raw_err = e.raw_os_error();
+                if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                    // do nothing here
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut reader = net_impl::UdpRecvFrom::peek(self, buf);
+        yield_with(&reader);
+        reader.done()
+    }
+
     pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
         if self
             .ctx
@@ -205,6 +321,141 @@ impl UdpSocket {
         reader.done()
     }
 
+    /// receive a batch of datagrams into `msgs`, return how many are received
+    ///
+    /// it waits for the first datagram and takes the others that are ready.
+    /// on linux it's one `recvmmsg` for up to 16 datagrams, on the other
+    /// platforms it loops on `recv_from`
+    pub fn recv_mmsg(&self, msgs: &mut [MsgBuf]) -> io::Result<usize> {
+        if msgs.is_empty() {
+            return Ok(0);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            if self
+                .ctx
+                .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+                || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+            {
+                // this can't be nonblocking!!
+                return net_impl::recv_mmsg(&self.sys, msgs);
+            }
+
+            self.io.reset();
+            // this is an earlier return try for nonblocking read
+            match net_impl::recv_mmsg(&self.sys, msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            let mut reader = net_impl::UdpRecvMmsg::new(self, msgs);
+            yield_with(&reader);
+            reader.done()
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let (len, addr) = self.recv_from(msgs[0].buf)?;
+            msgs[0].len = len;
+            msgs[0].addr = Some(addr);
+            // the socket is only non-blocking in a coroutine
+            if !crate::coroutine_impl::is_coroutine() {
+                return Ok(1);
+            }
+            let mut n = 1;
+            for msg in msgs[1..].iter_mut() {
+                match self.sys.recv_from(msg.buf) {
+                    Ok((len, addr)) => {
+                        msg.len = len;
+                        msg.addr = Some(addr);
+                        n += 1;
+                    }
+                    Err(_) => break,
+                }
+            }
+            Ok(n)
+        }
+    }
+
+    /// send a batch of datagrams, return how many are sent
+    ///
+    /// on linux it's one `sendmmsg` for up to 16 datagrams, on the other
+    /// platforms it loops on `send_to` and `send`
+    pub fn send_mmsg(&self, msgs: &[OutMsg]) -> io::Result<usize> {
+        if msgs.is_empty() {
+            return Ok(0);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            if self
+                .ctx
+                .check_nonblocking(|b| self.sys.set_nonblocking(b))?
+                || !self.ctx.check_context(|b| self.sys.set_nonblocking(b))?
+            {
+                // this can't be nonblocking!!
+                return net_impl::send_mmsg(&self.sys, msgs);
+            }
+
+            self.io.reset();
+            // this is an earlier return try for nonblocking write
+            match net_impl::send_mmsg(&self.sys, msgs) {
+                Ok(n) => return Ok(n),
+                Err(e) => {
+                    // raw_os_error is faster than kind
+                    let raw_err = e.raw_os_error();
+                    if raw_err == Some(libc::EAGAIN) || raw_err == Some(libc::EWOULDBLOCK) {
+                        // do nothing here
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+
+            let mut writer = net_impl::UdpSendMmsg::new(self, msgs);
+            yield_with(&writer);
+            writer.done()
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            for (i, msg) in msgs.iter().enumerate() {
+                let ret = match msg.addr {
+                    Some(addr) => self.send_to(msg.buf, addr),
+                    None => self.send(msg.buf),
+                };
+                // report the sent ones first, like a partial write
+                if let Err(e) = ret {
+                    return if i == 0 { Err(e) } else { Ok(i) };
+                }
+            }
+            Ok(msgs.len())
+        }
+    }
+
+    /// turn on the UDP generic receive offload, the kernel may coalesce
+    /// the datagrams of a flow into one buffer
+    #[cfg(target_os = "linux")]
+    pub fn set_gro(&self, on: bool) -> io::Result<()> {
+        net_impl::set_gro(&self.sys, on)
+    }
+
+    /// turn on the UDP generic segmentation offload, each sent buffer is
+    /// split into datagrams of `segment_size` by the kernel, 0 turns it off
+    #[cfg(target_os = "linux")]
+    pub fn set_gso(&self, segment_size: u16) -> io::Result<()> {
+        net_impl::set_gso(&self.sys, segment_size)
+    }
+
     pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
         self.ctx.set_nonblocking(nonblocking);
         Ok(())
@@ -270,6 +521,20 @@ impl UdpSocket {
         self.sys.set_ttl(ttl)
     }
 
+    /// the readability of the socket as a source of a `SelectSet`, or an arm
+    /// of `select!` by `wait`. no datagram is taken, see
+    /// `TcpStream::readable`
+    #[cfg(unix)]
+    pub fn readable(&self) -> super::SocketReady<'_> {
+        super::SocketReady::readable(&self.io, self.sys.as_raw_fd())
+    }
+
+    /// the writability of the socket as a source of a `SelectSet`
+    #[cfg(unix)]
+    pub fn writable(&self) -> super::SocketReady<'_> {
+        super::SocketReady::writable(&self.io, self.sys.as_raw_fd())
+    }
+
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub fn join_multicast_v4(&self, multiaddr: &Ipv4Addr, interface: &Ipv4Addr) -> io::Result<()> {
         self.sys.join_multicast_v4(multiaddr, interface)
@@ -307,6 +572,8 @@ impl io_impl::AsIoData for UdpSocket {
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 
+/// the fd is deregistered from the io driver and left in non-blocking mode,
+/// use `into_std` to get a blocking socket
 #[cfg(unix)]
 impl IntoRawFd for UdpSocket {
     fn into_raw_fd(self) -> RawFd {
@@ -322,6 +589,9 @@ impl AsRawFd for UdpSocket {
     }
 }
 
+/// the UdpSocket takes over the fd, it's set to non-blocking mode and registered
+/// with the io driver, the fd must not be registered by anyone else.
+/// panics if the registration fails, use `from_std` to get the error instead
 #[cfg(unix)]
 impl FromRawFd for UdpSocket {
     unsafe fn from_raw_fd(fd: RawFd) -> UdpSocket {
@@ -62,6 +62,32 @@ impl Park {
         }
     }
 
+    // make it a new one for the coroutine that reuses the handle, the
+    // `wait_co` is kept if no timer holds it
+    pub(crate) fn reset(&mut self) {
+        self.release();
+        match Arc::get_mut(&mut self.wait_co) {
+            Some(wait_co) => drop(wait_co.take()),
+            None => self.wait_co = Arc::new(AtomicOption::none()),
+        }
+        *self.state.get_mut() = 0;
+        *self.check_cancel.get_mut() = true;
+        self.timeout = AtomicDuration::new(None);
+        *self.wait_kernel.get_mut() = false;
+    }
+
+    // wait the kernel finish
+    fn release(&self) {
+        if !self.wait_kernel.load(Ordering::Acquire) {
+            return;
+        }
+
+        while self.state.load(Ordering::Acquire) & 0x02 == 0x02 {
+            yield_now();
+        }
+        self.set_timeout_handle(None);
+    }
+
     // ignore cancel, if true, caller have to do the check instead
     pub fn ignore_cancel(&self, ignore: bool) {
         self.check_cancel.store(!ignore, Ordering::Relaxed);
@@ -161,10 +187,12 @@ impl Park {
     #[inline]
     fn wake_up(&self, b_sync: bool) {
         if let Some(co) = self.wait_co.take() {
+            #[cfg(feature = "flight-recorder")]
+            crate::flight::record_co(crate::flight::Event::Unpark, &co, b_sync as u32);
             if b_sync {
                 run_coroutine(co);
             } else {
-                get_scheduler().schedule(co);
+                get_scheduler().schedule_lifo(co);
             }
         }
     }
@@ -190,6 +218,8 @@ impl Park {
             self.state.fetch_and(!0x02, Ordering::Release);
         }
 
+        #[cfg(feature = "flight-recorder")]
+        crate::flight::record_current(crate::flight::Event::Park, dur.is_some() as u32);
         // what if the state is set before yield?
         // the subscribe would re-check it
         yield_with(self);
@@ -227,15 +257,7 @@ impl<'a> Drop for DropGuard<'a> {
 
 impl Drop for Park {
     fn drop(&mut self) {
-        // wait the kernel finish
-        if !self.wait_kernel.load(Ordering::Acquire) {
-            return;
-        }
-
-        while self.state.load(Ordering::Acquire) & 0x02 == 0x02 {
-            yield_now();
-        }
-        self.set_timeout_handle(None);
+        self.release();
     }
 }
 
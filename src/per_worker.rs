@@ -0,0 +1,245 @@
+//! the per worker state, see `runtime::for_each_worker`,
+//! `runtime::worker_local` and `runtime::register_worker_init`
+//!
+//! the state of a worker, like an arena, a buffer pool or a connection cache,
+//! is used by the coroutines that the worker runs without any contention.
+//! `for_each_worker` visits the workers that run now, a registered
+//! initializer also runs on each worker thread that starts later, so the
+//! workers that are added by `set_workers` have their state before they run
+//! any coroutine
+//!
+//! ```
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use mco::runtime::{self, WorkerContext};
+//!
+//! // the requests of each worker, only its own worker counts them
+//! runtime::register_worker_init(|_: WorkerContext| {
+//!     runtime::worker_local::<AtomicUsize>().get_or_init(|| AtomicUsize::new(0));
+//! });
+//! mco::co!(|| {
+//!     let n = runtime::worker_local::<AtomicUsize>();
+//!     n.get().unwrap().fetch_add(1, Ordering::Relaxed);
+//! })
+//! .join()
+//! .unwrap();
+//! let counts = runtime::worker_local::<AtomicUsize>();
+//! let total: usize = counts.iter().map(|(_, n)| n.load(Ordering::Relaxed)).sum();
+//! assert_eq!(total, 1);
+//! ```
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::Builder;
+use crate::scheduler::{get_scheduler, worker_id, Scheduler};
+use crate::scoped::scope;
+
+/// the worker that a per worker closure runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerContext {
+    id: usize,
+    workers: usize,
+}
+
+impl WorkerContext {
+    /// the id of the worker, from 0 to the max workers of the runtime
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// the number of the active workers when the closure started
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+}
+
+/// run `f` once on each active worker of the current runtime, block until
+/// all of them are done
+///
+/// each call runs in a coroutine that is pinned to its worker, so `f` sees
+/// the state of its worker in `worker_local`. the calls run in parallel,
+/// the panic of one of them is propagated to the caller after all are done
+///
+/// the workers that are added while it runs are not visited, use
+/// `register_worker_init` for the state that each worker must have
+pub fn for_each_worker<F: Fn(WorkerContext) + Sync>(f: F) {
+    let workers = get_scheduler().worker_num();
+    let f = &f;
+    scope(|s| {
+        for id in 0..workers {
+            let ctx = WorkerContext { id, workers };
+            // the scope joins the coroutines before `f` is dropped
+            unsafe { s.spawn_with(Builder::new().pin(id), move || f(ctx)) };
+        }
+    });
+}
+
+/// run `f` once on each worker thread of the current runtime, the ones that
+/// run now and the ones that start later, like the workers added by
+/// `set_workers`
+///
+/// the active workers run it in pinned coroutines before it returns, as in
+/// `for_each_worker`, and a panic there is propagated to the caller. the
+/// parked workers and the new ones run it in the worker thread ahead of the
+/// next round of their io driver, a new worker before its first coroutine.
+/// there `f` must not block, and a panic takes the worker down as any panic
+/// of the worker, see `WorkerPanic`. each worker runs the initializers once
+/// in the order that they are registered, they are never unregistered
+pub fn register_worker_init<F>(f: F)
+where
+    F: Fn(WorkerContext) + Send + Sync + 'static,
+{
+    let s = get_scheduler();
+    s.worker_inits.push(Arc::new(f));
+    for_each_worker(|ctx| s.worker_inits.catch_up(ctx.id, ctx.workers));
+}
+
+type WorkerInit = Arc<dyn Fn(WorkerContext) + Send + Sync>;
+
+// the initializers of a scheduler
+pub(crate) struct WorkerInits {
+    inits: Mutex<Vec<WorkerInit>>,
+    // the number of the initializers, so that a worker checks with one load
+    len: AtomicUsize,
+    // the initializers that each worker has taken to run
+    taken: Box<[AtomicUsize]>,
+}
+
+impl WorkerInits {
+    pub(crate) fn new(max_workers: usize) -> Self {
+        WorkerInits {
+            inits: Mutex::new(Vec::new()),
+            len: AtomicUsize::new(0),
+            taken: (0..max_workers).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    fn push(&self, f: WorkerInit) {
+        let mut inits = self.inits.lock();
+        inits.push(f);
+        self.len.store(inits.len(), Ordering::Release);
+    }
+
+    // run the initializers that the worker has not run yet, on the worker
+    #[inline]
+    pub(crate) fn catch_up(&self, id: usize, workers: usize) {
+        let taken = &self.taken[id];
+        if taken.load(Ordering::Relaxed) < self.len.load(Ordering::Acquire) {
+            self.run_new(taken, WorkerContext { id, workers });
+        }
+    }
+
+    #[cold]
+    fn run_new(&self, taken: &AtomicUsize, ctx: WorkerContext) {
+        loop {
+            // taken under the lock, so each one runs once per worker
+            let new = {
+                let inits = self.inits.lock();
+                let from = taken.load(Ordering::Relaxed);
+                if from >= inits.len() {
+                    return;
+                }
+                taken.store(inits.len(), Ordering::Relaxed);
+                inits[from..].to_vec()
+            };
+            for f in new {
+                f(ctx);
+            }
+        }
+    }
+}
+
+// the slots of the `worker_local`s, by the scheduler and the type
+type Slots = Arc<dyn Any + Send + Sync>;
+static LOCALS: Lazy<Mutex<HashMap<(usize, TypeId), Slots>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// drop the slots of a scheduler that is freed, out of the lock since a
+// `T` may use the `worker_local`s when it's dropped
+pub(crate) fn forget_scheduler(s: usize) {
+    let gone: Vec<Slots> = {
+        let mut locals = LOCALS.lock();
+        let keys: Vec<_> = locals.keys().filter(|k| k.0 == s).cloned().collect();
+        keys.iter().filter_map(|k| locals.remove(k)).collect()
+    };
+    drop(gone);
+}
+
+/// the `T` of each worker of the current runtime, one per runtime and type
+///
+/// the slots live as long as the runtime, each one is set once by
+/// `get_or_init` on its worker, the other threads can read all of them. so
+/// a `T` that is changed by its worker needs its own interior mutability,
+/// which has no contention as long as the other threads only read it now
+/// and then
+pub fn worker_local<T: Send + Sync + 'static>() -> WorkerLocal<T> {
+    let s = get_scheduler();
+    let key = (s as *const Scheduler as usize, TypeId::of::<T>());
+    let slots = LOCALS
+        .lock()
+        .entry(key)
+        .or_insert_with(|| {
+            let slots: Vec<OnceCell<T>> = (0..s.max_workers()).map(|_| OnceCell::new()).collect();
+            Arc::new(slots)
+        })
+        .clone();
+    let slots = slots.downcast().expect("the slots of another type");
+    WorkerLocal { slots }
+}
+
+/// the handle of the per worker `T`, see `worker_local`
+pub struct WorkerLocal<T> {
+    slots: Arc<Vec<OnceCell<T>>>,
+}
+
+impl<T> WorkerLocal<T> {
+    /// the `T` of the worker that runs the caller, it's made by `init` the
+    /// first time
+    ///
+    /// # Panics
+    ///
+    /// when it's not called on a worker of the runtime of the handle
+    pub fn get_or_init<F: FnOnce() -> T>(&self, init: F) -> &T {
+        match self.slots.get(worker_id()) {
+            Some(slot) => slot.get_or_init(init),
+            None => panic!("worker_local is used off the workers"),
+        }
+    }
+
+    /// the `T` of the worker that runs the caller, `None` if it's not set or
+    /// the caller is not on a worker
+    pub fn get(&self) -> Option<&T> {
+        self.get_worker(worker_id())
+    }
+
+    /// the `T` of the worker `id`, if it's set
+    pub fn get_worker(&self, id: usize) -> Option<&T> {
+        self.slots.get(id).and_then(OnceCell::get)
+    }
+
+    /// the ids of the workers that have the `T` set, with their `T`
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        let slots = self.slots.iter().enumerate();
+        slots.filter_map(|(id, slot)| slot.get().map(|t| (id, t)))
+    }
+}
+
+impl<T> Clone for WorkerLocal<T> {
+    fn clone(&self) -> Self {
+        WorkerLocal {
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for WorkerLocal<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -1,18 +1,23 @@
 use crate::config::config;
 use crate::coroutine_impl::CoroutineImpl;
+use crate::local::CoroutineLocal;
 use crossbeam::queue::ArrayQueue as Queue;
-use mco_gen::Gn;
+use mco_gen::{Gn, StackError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ptr;
 
 /// the raw coroutine pool, with stack and register prepared
-/// you need to tack care of the local storage
+/// a finished coroutine is kept with its local storage, so that the next
+/// spawn can reuse it
 pub struct CoroutinePool {
     // the pool must support mpmc operation!
     pool: Queue<CoroutineImpl>,
 }
 
 impl CoroutinePool {
-    fn create_dummy_coroutine() -> CoroutineImpl {
-        Gn::new_opt(config().get_stack_size(), move || {
+    fn create_dummy_coroutine() -> Result<CoroutineImpl, StackError> {
+        Gn::try_new_opt(config().get_stack_size(), move || {
             unreachable!("dummy coroutine should never be called");
         })
     }
@@ -21,26 +26,128 @@ impl CoroutinePool {
         let capacity = config().get_pool_capacity();
         let pool = Queue::new(capacity);
         for _ in 0..capacity {
-            let co = Self::create_dummy_coroutine();
+            let co = Self::create_dummy_coroutine().expect("failed to alloc sys stack");
             pool.push(co).unwrap();
         }
 
         CoroutinePool { pool }
     }
 
-    /// get a raw coroutine from the pool
+    /// get a raw coroutine from the pool, with the local storage of the
+    /// last coroutine that used it if any
     #[inline]
-    pub fn get(&self) -> CoroutineImpl {
+    pub fn get(&self) -> Result<(CoroutineImpl, Option<Box<CoroutineLocal>>), StackError> {
         match self.pool.pop() {
-            Some(co) => co,
-            None => Self::create_dummy_coroutine(),
+            Some(mut co) => {
+                let local = take_local(&mut co);
+                Ok((co, local))
+            }
+            None => Ok((Self::create_dummy_coroutine()?, None)),
         }
     }
 
-    /// put a raw coroutine into the pool
+    /// put a finished coroutine into the pool with its local storage
     #[inline]
-    pub fn put(&self, co: CoroutineImpl) {
+    pub fn put(&self, mut co: CoroutineImpl, local: Box<CoroutineLocal>) {
+        co.set_local_data(Box::into_raw(local) as *mut u8);
         // discard the co if push failed
-        self.pool.push(co).ok();
+        if let Err(mut co) = self.pool.push(co) {
+            drop(take_local(&mut co));
+        }
+    }
+}
+
+impl Drop for CoroutinePool {
+    fn drop(&mut self) {
+        while let Some(mut co) = self.pool.pop() {
+            drop(take_local(&mut co));
+        }
+    }
+}
+
+// take the local storage that is kept with a pooled coroutine, the dummy
+// ones have none
+fn take_local(co: &mut CoroutineImpl) -> Option<Box<CoroutineLocal>> {
+    let local = co.get_local_data() as *mut CoroutineLocal;
+    co.set_local_data(ptr::null_mut());
+    if local.is_null() {
+        None
+    } else {
+        Some(unsafe { Box::from_raw(local) })
+    }
+}
+
+// cached stacks that are bigger than this would give the memory back to the os
+const DECOMMIT_STACK_BYTES: usize = 64 * 1024;
+
+thread_local! {
+    static STACK_POOL: RefCell<StackPool> = RefCell::new(StackPool::default());
+}
+
+/// the per worker stack pool for the coroutines that don't use the default stack size
+/// finished coroutines are cached by stack size class, so the stacks and their
+/// guard pages are reused as is
+#[derive(Default)]
+struct StackPool {
+    // size class -> finished coroutines
+    slots: HashMap<usize, Vec<CoroutineImpl>>,
+    // total cached stack bytes
+    bytes: usize,
+}
+
+/// get the stack size class, in usize
+/// the stacks with tracking bit would not be pooled
+#[inline]
+pub fn stack_class(size: usize) -> usize {
+    if size & 1 == 1 || !config().get_stack_pool() {
+        size
+    } else {
+        // a size that can't be rounded up fails in the allocation
+        size.checked_next_power_of_two().unwrap_or(size)
     }
 }
+
+/// get a cached coroutine with the stack size class
+#[inline]
+pub fn get_stack(size: usize) -> Option<CoroutineImpl> {
+    if size & 1 == 1 || !config().get_stack_pool() {
+        return None;
+    }
+    let class = stack_class(size);
+    STACK_POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        let co = pool.slots.get_mut(&class)?.pop()?;
+        pool.bytes -= class * std::mem::size_of::<usize>();
+        Some(co)
+    })
+}
+
+/// put a finished coroutine into the stack pool, it's dropped if the pool is full
+/// `size` is the requested stack size and `cap` is the real stack size, in usize
+pub fn put_stack(size: usize, cap: usize, co: CoroutineImpl) {
+    if size & 1 == 1 || !config().get_stack_pool() {
+        return;
+    }
+    let class = size.next_power_of_two();
+    // the stack may be not allocated by the size class when the pool was disabled
+    if cap < class {
+        return;
+    }
+    let bytes = class * std::mem::size_of::<usize>();
+    if bytes >= DECOMMIT_STACK_BYTES {
+        co.decommit_stack();
+    }
+    // the pool may be dropped when the thread exit
+    let _ = STACK_POOL.try_with(|pool| {
+        let mut pool = pool.borrow_mut();
+        if pool.bytes + bytes > config().get_stack_pool_capacity() {
+            return;
+        }
+        let slot = pool.slots.entry(class).or_default();
+        if slot.len() >= config().get_pool_capacity() {
+            return;
+        }
+        slot.push(co);
+        pool.bytes += bytes;
+    });
+}
@@ -0,0 +1,21 @@
+//! the coroutine versions of the common std items, so that they are the near
+//! ones. `use mco::prelude::*` shadows the `Mutex`, `RwLock` and `Condvar` of
+//! a `use std::sync::*`, see `std::compat` for the rest
+//!
+//! ```
+//! use mco::prelude::*;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let m = Arc::new(Mutex::new(0));
+//! let m1 = m.clone();
+//! let h = mco::co!(move || {
+//!     sleep(Duration::from_millis(1));
+//!     *m1.lock().unwrap() += 1;
+//! });
+//! h.join().unwrap();
+//! assert_eq!(*m.lock().unwrap(), 1);
+//! ```
+
+pub use crate::coroutine::{blocking_zone, checkpoint, sleep, yield_now, JoinHandle};
+pub use crate::std::sync::{channel, Condvar, Mutex, Receiver, RwLock, Sender};
@@ -0,0 +1,401 @@
+//! runtimes that own their worker threads, io driver and timer thread
+//!
+//! the free functions like `coroutine::spawn` use the default runtime, it's
+//! started lazily and configured by `mco::config()`. a `Runtime` is an extra
+//! scheduler with its own workers, the coroutines spawned by it and all their
+//! children run on its workers only. the channels and the sync primitives
+//! work across the runtimes, the sockets and the timers belong to the runtime
+//! that creates them
+//!
+//! the default runtime is started exactly once, by the first spawn or the
+//! first use of the io or the timers from any thread, the threads that it
+//! doesn't own and the early calls before `main` configures it included. it
+//! runs with the config of that moment and is never shut down. a `Runtime` is
+//! shut down when it's dropped, a spawn on one of its threads after that fails
+//! with `SpawnError::ShuttingDown` from `Builder::try_spawn` and panics from
+//! `spawn` and `co!`. in a destructor that runs while the thread is unwinding
+//! the panic would abort the process, there the handle joins with the error
+//! instead
+//!
+//! for example:
+//! ```
+//! use mco::runtime::{Config, Runtime};
+//! use mco::std::sync::channel;
+//!
+//! let rt = Runtime::new(Config::new().workers(2)).unwrap();
+//! let (tx, rx) = channel();
+//! let h = rt.spawn(move || tx.send(42).unwrap());
+//! assert_eq!(rx.recv().unwrap(), 42);
+//! h.join().unwrap();
+//! ```
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::affinity;
+use crate::coroutine_impl::Builder;
+use crate::join::JoinHandle;
+use crate::lifecycle;
+use crate::scheduler::{
+    current_running, default_scheduler, enable_runtimes, is_current_sched, set_current_sched,
+    start_threads, SchedRef, Scheduler,
+};
+use crate::scoped::{scope, Scope};
+use crate::sleep::sleep;
+use crate::std::sync::close_globals;
+use crate::watchdog;
+
+#[cfg(feature = "flight-recorder")]
+pub use crate::flight::{flight_recorder_dump, FLIGHT_SLOTS};
+pub use crate::io::last_driver_error;
+pub use crate::lifecycle::{
+    on_shutdown, on_shutdown_timeout, on_start, LifecycleGuard, SHUTDOWN_HOOK_TIMEOUT,
+};
+pub use crate::maintenance::{
+    register_maintenance, MaintenanceGuard, MAINTENANCE_TICK, SLOW_MAINTENANCE,
+};
+pub use crate::per_worker::{
+    for_each_worker, register_worker_init, worker_local, WorkerContext, WorkerLocal,
+};
+pub use crate::watchdog::Stall;
+
+/// the configuration of a `Runtime`
+///
+/// the stack size and the pool settings are shared with the default
+/// runtime, see `mco::config()`
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    workers: usize,
+    max_workers: usize,
+}
+
+impl Config {
+    /// the default config, one worker for each cpu
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// set the worker thread number, 0 means one for each cpu
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// set the max worker thread number that `Runtime::set_workers` can
+    /// grow to, 0 means one for each cpu. it's never less than the workers
+    pub fn max_workers(mut self, workers: usize) -> Self {
+        self.max_workers = workers;
+        self
+    }
+}
+
+/// what to do with the coroutines pinned to the surplus workers on a shrink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinnedPolicy {
+    /// fail the shrink with `ResizeError::Pinned`
+    Fail,
+    /// move them to the active workers, the coroutines pinned to the same
+    /// worker are still moved to the same one. they stay there if the worker
+    /// is active again
+    Migrate,
+}
+
+/// the error of `set_workers`
+#[derive(Debug)]
+pub enum ResizeError {
+    /// the worker number is 0 or more than the max workers
+    OutOfRange { workers: usize, max: usize },
+    /// there are coroutines pinned to the surplus worker
+    Pinned { worker: usize, coroutines: usize },
+    /// the new worker threads can't be spawned
+    Spawn(io::Error),
+}
+
+impl fmt::Display for ResizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResizeError::OutOfRange { workers, max } => {
+                write!(f, "can't set {} workers, the max is {}", workers, max)
+            }
+            ResizeError::Pinned { worker, coroutines } => write!(
+                f,
+                "can't shrink worker {}, {} coroutines are pinned to it",
+                worker, coroutines
+            ),
+            ResizeError::Spawn(e) => write!(f, "can't spawn the worker thread, {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ResizeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ResizeError::Spawn(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// grow or shrink the workers of the default runtime to `n`, the shrink fails
+/// if there are coroutines pinned to the surplus workers
+///
+/// the new threads are started up to `config().set_max_workers`. the surplus
+/// workers finish their running coroutines, hand the queued ones over to the
+/// active workers and then park. a parked worker keeps the sockets that are
+/// registered on it and forwards their events to the active workers, the new
+/// sockets are registered on the active workers only. it returns before the
+/// surplus workers are drained, see `stats().draining_workers`
+///
+/// ```
+/// use mco::runtime;
+///
+/// mco::config().set_max_workers(4);
+/// runtime::set_workers(4).unwrap();
+/// assert_eq!(mco::stats::stats().active_workers, 4);
+/// runtime::set_workers(1).unwrap();
+/// ```
+pub fn set_workers(n: usize) -> Result<(), ResizeError> {
+    set_workers_with(n, PinnedPolicy::Fail)
+}
+
+/// grow or shrink the workers of the default runtime to `n` with the policy
+/// for the pinned coroutines, see [`set_workers`]
+///
+/// [`set_workers`]: ./fn.set_workers.html
+pub fn set_workers_with(n: usize, policy: PinnedPolicy) -> Result<(), ResizeError> {
+    default_scheduler().set_workers(n, policy == PinnedPolicy::Migrate)
+}
+
+/// true if the runtime of the current thread is started and not shut down.
+/// it's the runtime of the worker or of the coroutine, else the default
+/// runtime, which is not started by this call
+///
+/// ```
+/// use mco::runtime;
+///
+/// mco::co!(|| assert!(runtime::is_running())).join().unwrap();
+/// assert!(runtime::is_running());
+/// ```
+pub fn is_running() -> bool {
+    current_running()
+}
+
+// let the current thread spawn coroutines for the scheduler
+struct Enter(*const Scheduler);
+
+impl Enter {
+    fn new(sched: &Scheduler) -> Self {
+        Enter(set_current_sched(sched))
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        set_current_sched(self.0);
+    }
+}
+
+/// a scheduler with its own worker threads
+///
+/// it's torn down when dropped, the worker threads and the timer thread are
+/// joined and the coroutines that are not finished are never resumed again.
+/// dropping it on a thread of the runtime itself panics. the queued coroutines
+/// that never ran are dropped, their handles join with the cancel error. the
+/// internal state, the io driver with its fds included, is freed when no
+/// coroutine and no socket of the runtime is left, a coroutine that is parked
+/// or a socket that is still open keeps it till it's gone
+pub struct Runtime {
+    sched: SchedRef,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Runtime {
+    /// start a runtime with the config
+    pub fn new(config: Config) -> io::Result<Runtime> {
+        let workers = if config.workers == 0 {
+            num_cpus::get()
+        } else {
+            config.workers
+        };
+        let max = match config.max_workers {
+            0 => num_cpus::get(),
+            n => n,
+        };
+        let placement = affinity::worker_placement(max.max(workers));
+        let mut sched = Scheduler::new(workers, placement)?;
+        sched.live = Some(AtomicUsize::new(0));
+        // it's freed by the last `SchedRef`, the threads only use it till
+        // they are joined
+        let sched: &'static Scheduler = unsafe { &*Box::into_raw(sched) };
+        enable_runtimes();
+        match start_threads(sched) {
+            Ok(threads) => {
+                // let the first round of the workers see the coroutines that
+                // are spawned before they are parked
+                for id in 0..workers {
+                    sched.get_selector().wakeup(id);
+                }
+                lifecycle::run_start(sched, false);
+                let sched = SchedRef::new(sched);
+                Ok(Runtime { sched, threads })
+            }
+            Err(e) => {
+                // the started threads exit by themselves, the scheduler is
+                // leaked since they are not joined
+                sched.stop();
+                Err(e)
+            }
+        }
+    }
+
+    /// the number of the active workers, a worker that panicked and is not
+    /// respawned is not counted, see `mco::config().set_worker_panic`
+    pub fn workers(&self) -> usize {
+        self.sched.worker_counts().0
+    }
+
+    /// let the worker `id` panic in its next round, for testing the
+    /// `WorkerPanic` policy. it needs the `test-util` feature
+    #[cfg(feature = "test-util")]
+    #[doc(hidden)]
+    pub fn inject_worker_panic(&self, id: usize) {
+        self.sched.inject_panic(id);
+    }
+
+    /// the number of the workers that are still handing their coroutines
+    /// over after a shrink
+    pub fn draining_workers(&self) -> usize {
+        self.sched.worker_counts().1
+    }
+
+    /// grow or shrink the workers of the runtime, see [`set_workers`]
+    ///
+    /// [`set_workers`]: ./fn.set_workers.html
+    pub fn set_workers(&self, n: usize) -> Result<(), ResizeError> {
+        self.set_workers_with(n, PinnedPolicy::Fail)
+    }
+
+    /// grow or shrink the workers of the runtime with the policy for the
+    /// pinned coroutines
+    pub fn set_workers_with(&self, n: usize, policy: PinnedPolicy) -> Result<(), ResizeError> {
+        self.sched.set_workers(n, policy == PinnedPolicy::Migrate)
+    }
+
+    /// spawn a coroutine on the runtime
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with(Builder::new(), f)
+    }
+
+    /// spawn a coroutine on the runtime with the builder
+    ///
+    /// the coroutine never runs on the current thread, it's put to the
+    /// global queue of the runtime
+    pub fn spawn_with<F, T>(&self, builder: Builder, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (co, handle) = {
+            let _enter = Enter::new(&self.sched);
+            builder.spawn_impl(f)
+        };
+        if let Some(co) = co {
+            self.sched.schedule_global(co);
+        }
+        handle
+    }
+
+    /// run `f` with a coroutine scope on the runtime, block until `f` and
+    /// all the coroutines spawned in the scope are finished
+    ///
+    /// the panic of `f` is propagated to the caller
+    pub fn block_on_scope<'a, F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&Scope<'a>) -> T + Send + 'a,
+        T: Send + 'a,
+    {
+        let mut ret = None;
+        {
+            let ret = &mut ret;
+            let closure: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+                *ret = Some(scope(f));
+            });
+            // the closure can borrow from the caller since we wait for it below
+            let closure: Box<dyn FnOnce() + Send> = unsafe { mem::transmute(closure) };
+            if let Err(e) = self.spawn(closure).join() {
+                panic::resume_unwind(e);
+            }
+        }
+        ret.expect("the scope is not finished")
+    }
+
+    /// the number of the coroutines of the runtime that are not finished
+    pub fn live_coroutines(&self) -> usize {
+        self.sched
+            .live
+            .as_ref()
+            .map_or(0, |l| l.load(Ordering::Acquire))
+    }
+
+    /// wait at most `timeout` for the coroutines to finish, then tear down
+    /// the runtime. return true if all the coroutines are finished
+    ///
+    /// the hooks of `on_shutdown` run first, they are waited for their own
+    /// timeouts and not counted in `timeout`. then the `GlobalChannel`s in
+    /// use are closed, so the receivers that loop on them drain the messages
+    /// and exit
+    ///
+    /// panic if it's called on a thread of the runtime itself
+    pub fn shutdown(self, timeout: Duration) -> bool {
+        self.check_thread();
+        lifecycle::run_shutdown(&self);
+        close_globals();
+        let deadline = Instant::now() + timeout;
+        while self.live_coroutines() != 0 && Instant::now() < deadline {
+            sleep(Duration::from_millis(1));
+        }
+        self.live_coroutines() == 0
+    }
+
+    // the worker threads can't join themselves
+    fn check_thread(&self) {
+        assert!(
+            !is_current_sched(&*self.sched),
+            "can't shut down a runtime on its own thread"
+        );
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            self.check_thread();
+        }
+        self.sched.stop();
+        self.threads.extend(self.sched.take_threads());
+        for t in self.threads.drain(..) {
+            let _ = t.join();
+        }
+        watchdog::unwatch(&self.sched);
+        self.sched.drop_queued();
+        // the reference of the runtime is dropped with `sched`
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Runtime")
+            .field("workers", &self.workers())
+            .field("live_coroutines", &self.live_coroutines())
+            .finish()
+    }
+}
@@ -1,15 +1,34 @@
+use std::cell::Cell;
+use std::collections::BinaryHeap;
 use std::io;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Once};
 use std::thread;
 use std::time::Duration;
 
-use crate::config::config;
-use crate::coroutine_impl::{run_coroutine, CoroutineImpl};
+use parking_lot::Mutex;
+
+use crate::affinity;
+use crate::config::{config, WorkerPanic};
+use crate::coroutine_impl::{
+    drop_queued, id_of, last_worker_of, priority_enabled, priority_of, run_coroutine, CoroutineId,
+    CoroutineImpl,
+};
+use crate::determinism;
 use crate::io::{EventLoop, Selector};
+use crate::lifecycle;
+use crate::per_worker::{self, WorkerInits};
 use crate::pool::CoroutinePool;
+use crate::runtime::ResizeError;
+use crate::stats;
+use crate::std::queue::seg_queue::SegQueue;
 use crate::std::sync::AtomicOption;
+use crate::thread_names;
 use crate::timeout_list;
+use crate::watchdog::{self, RunSlot};
 use crate::yield_now::set_co_para;
 use crossbeam::deque;
 use crossbeam::utils::Backoff;
@@ -32,6 +51,94 @@ pub static WORKER_ID: AtomicUsize = AtomicUsize::new(!1);
 #[cfg(not(nightly))]
 thread_local! { pub static WORKER_ID: AtomicUsize = AtomicUsize::new(!1); }
 
+/// get the worker id of the current thread, `!1` for non worker threads
+#[inline]
+pub fn worker_id() -> usize {
+    #[cfg(nightly)]
+    let id = WORKER_ID.load(Ordering::Relaxed);
+    #[cfg(not(nightly))]
+    let id = WORKER_ID.with(|id| id.load(Ordering::Relaxed));
+    id
+}
+
+// the scheduler of the runtime that the thread belongs to, null for the default one
+#[cfg(nightly)]
+#[thread_local]
+static CURRENT_SCHED: AtomicPtr<Scheduler> = AtomicPtr::new(ptr::null_mut());
+
+#[cfg(not(nightly))]
+thread_local! { static CURRENT_SCHED: AtomicPtr<Scheduler> = AtomicPtr::new(ptr::null_mut()); }
+
+// set when a `Runtime` is created, until then there is only the default scheduler
+static RUNTIMES_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+fn current_sched() -> *const Scheduler {
+    #[cfg(nightly)]
+    let s = CURRENT_SCHED.load(Ordering::Relaxed);
+    #[cfg(not(nightly))]
+    let s = CURRENT_SCHED.with(|s| s.load(Ordering::Relaxed));
+    s
+}
+
+// set the scheduler of the current thread, return the old one
+#[inline]
+pub(crate) fn set_current_sched(sched: *const Scheduler) -> *const Scheduler {
+    let sched = sched as *mut Scheduler;
+    #[cfg(nightly)]
+    let old = CURRENT_SCHED.swap(sched, Ordering::Relaxed);
+    #[cfg(not(nightly))]
+    let old = CURRENT_SCHED.with(|s| s.swap(sched, Ordering::Relaxed));
+    old
+}
+
+// return true if there are schedulers other than the default one
+#[inline]
+pub(crate) fn runtimes_enabled() -> bool {
+    RUNTIMES_ENABLED.load(Ordering::Relaxed)
+}
+
+// return true if the scheduler is the one of the current thread
+// the threads that don't belong to a runtime use the default scheduler
+#[inline]
+pub(crate) fn is_current_sched(sched: *const Scheduler) -> bool {
+    let cur = current_sched();
+    if cur.is_null() {
+        ptr::eq(sched, unsafe { SCHED })
+    } else {
+        ptr::eq(sched, cur)
+    }
+}
+
+/// a counted reference of a scheduler, the scheduler of a runtime is freed
+/// with the last one after the runtime is dropped
+pub(crate) struct SchedRef(*const Scheduler);
+
+unsafe impl Send for SchedRef {}
+unsafe impl Sync for SchedRef {}
+
+impl SchedRef {
+    pub(crate) fn new(s: &Scheduler) -> Self {
+        s.acquire();
+        SchedRef(s)
+    }
+}
+
+impl std::ops::Deref for SchedRef {
+    type Target = Scheduler;
+
+    #[inline]
+    fn deref(&self) -> &Scheduler {
+        unsafe { &*self.0 }
+    }
+}
+
+impl Drop for SchedRef {
+    fn drop(&mut self) {
+        unsafe { Scheduler::release(self.0) }
+    }
+}
+
 // here we use Arc<AtomicOption<>> for that in the select implementation
 // other event may try to consume the coroutine while timer thread consume it
 type TimerData = Arc<AtomicOption<CoroutineImpl>>;
@@ -56,53 +163,276 @@ fn filter_cancel_panic() {
 
 static mut SCHED: *const Scheduler = std::ptr::null();
 
+// the max coroutines that run from the lifo slot in a row
+// so that the other coroutines in the local queue would not starve
+const LIFO_BUDGET: usize = 16;
+// the max length of the local queue, the rest goes to the global queue
+const LOCAL_QUEUE_CAPACITY: usize = 256;
+// the max coroutines that run from the priority queue in a row
+// so that the coroutines of the default priority would not starve
+const PRIO_BUDGET: usize = 16;
+
+/// the per worker slot for the coroutine that would run next
+/// a coroutine unblocked by the running coroutine is put here, so that
+/// message passing between two coroutines doesn't go through the run queue
+#[derive(Default)]
+struct LifoSlot {
+    co: AtomicOption<CoroutineImpl>,
+    // how many coroutines run from the slot in a row
+    runs: AtomicUsize,
+}
+
+impl LifoSlot {
+    #[inline]
+    fn pop(&self, local: &deque::Worker<CoroutineImpl>) -> Option<CoroutineImpl> {
+        match self.co.take() {
+            Some(co) if self.runs.load(Ordering::Relaxed) < LIFO_BUDGET => {
+                self.runs.fetch_add(1, Ordering::Relaxed);
+                stats::LIFO_HITS.fetch_add(1, Ordering::Relaxed);
+                Some(co)
+            }
+            Some(co) => {
+                // out of budget, give the local queue a chance
+                self.runs.store(0, Ordering::Relaxed);
+                local.push(co);
+                None
+            }
+            None => {
+                self.runs.store(0, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+}
+
+// a coroutine in the priority queue
+struct Prio {
+    priority: u8,
+    seq: u64,
+    co: CoroutineImpl,
+}
+
+impl PartialEq for Prio {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority && self.seq == other.seq
+    }
+}
+
+impl Eq for Prio {}
+
+impl PartialOrd for Prio {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Prio {
+    // the higher priority first, then the earlier one
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// the run queue of the coroutines with a priority, shared by the workers
+/// the priority is the one when the coroutine is queued
+#[derive(Default)]
+struct PrioQueue {
+    heap: Mutex<(BinaryHeap<Prio>, u64)>,
+    len: AtomicUsize,
+}
+
+impl PrioQueue {
+    fn push(&self, priority: u8, co: CoroutineImpl) {
+        let mut heap = self.heap.lock();
+        let seq = heap.1;
+        heap.1 += 1;
+        heap.0.push(Prio { priority, seq, co });
+        self.len.fetch_add(1, Ordering::Release);
+    }
+
+    #[inline]
+    fn pop(&self) -> Option<CoroutineImpl> {
+        if self.is_empty() {
+            return None;
+        }
+        let co = self.heap.lock().0.pop().map(|p| p.co);
+        if co.is_some() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        co
+    }
+
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Acquire) == 0
+    }
+
+    // raise the priority of a queued coroutine, it's rare so the heap is
+    // just rebuilt
+    fn raise(&self, id: CoroutineId, priority: u8) {
+        if self.is_empty() {
+            return;
+        }
+        let mut heap = self.heap.lock();
+        let mut queued = std::mem::take(&mut heap.0).into_vec();
+        for p in queued.iter_mut().filter(|p| id_of(&p.co) == id) {
+            p.priority = p.priority.max(priority);
+        }
+        heap.0 = BinaryHeap::from(queued);
+    }
+}
+
+// the bit of the worker in the parked mask, the workers above 64 are never
+// marked and wake up by themselves
+#[inline]
+fn park_bit(id: usize) -> u64 {
+    if id < 64 {
+        1 << id
+    } else {
+        0
+    }
+}
+
 pub struct ParkStatus {
-    pub parked: AtomicU64,
-    workers: u64,
+    parked: AtomicU64,
 }
 
 impl ParkStatus {
-    fn new(workers: u64) -> Self {
+    fn new() -> Self {
         let parked = AtomicU64::new(0);
-        ParkStatus { parked, workers }
+        ParkStatus { parked }
+    }
+
+    // the worker is going to wait for the io events
+    #[inline]
+    pub(crate) fn park(&self, id: usize) {
+        self.parked.fetch_or(park_bit(id), Ordering::Relaxed);
+        // the queues are checked after it, see `Scheduler::schedule_remote`
+        fence(Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub(crate) fn unpark(&self, id: usize) {
+        self.parked.fetch_and(!park_bit(id), Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn is_parked(&self, parked: u64, id: usize) -> bool {
+        parked & park_bit(id) != 0
+    }
+
+    // wake the worker only if it's parked, a running worker checks its
+    // queues before it parks again. the workers above 64 are always woken
+    #[inline]
+    fn wake(&self, scheduler: &Scheduler, id: usize) {
+        let bit = park_bit(id);
+        if bit == 0 || self.parked.fetch_and(!bit, Ordering::Relaxed) & bit != 0 {
+            scheduler.get_selector().wakeup(id);
+        }
     }
 
     #[inline]
     fn wake_one(&self, scheduler: &Scheduler) {
+        // a batch wakes the workers once at the end
+        if WAKE_BATCH.with(|b| b.get().map(|n| b.set(Some(n + 1)))).is_some() {
+            return;
+        }
+        // only the active workers take the tasks
+        let active = scheduler.worker_num();
+        let mask = if active >= 64 {
+            !0
+        } else {
+            (1u64 << active) - 1
+        };
         // when the worker thread is idle, the corresponding bit would set to 1
-        let parked = self.parked.load(Ordering::Relaxed);
-        // find the right most set bit
-        let rms = parked & !parked.wrapping_sub(1);
-        let first_thread = rms.trailing_zeros() as u64;
+        let parked = self.parked.load(Ordering::Relaxed) & mask;
         // if all threads are busy, we would not send any signal to wake up
         // any worker thread. In case worker thread missing the signal it will
         // wake up itself every 1 second, this is a rarely case
-        if first_thread < self.workers {
+        if parked != 0 {
+            // find the right most set bit
+            let first_thread = parked.trailing_zeros() as usize;
             // mark the thread as busy in advance (clear to 0)
             // the worker thread would set it to 1 when idle
-            let mask = self.workers + first_thread;
-            self.parked.fetch_and(!mask, Ordering::Relaxed);
-            scheduler.get_selector().wakeup(first_thread as usize);
+            self.unpark(first_thread);
+            scheduler.get_selector().wakeup(first_thread);
         }
     }
 }
 
+thread_local! {
+    // the deferred wakes of the current batch, none if not in a batch
+    static WAKE_BATCH: Cell<Option<usize>> = Cell::new(None);
+}
+
+// run `f` with the worker wakes deferred, one parked worker is woken for each
+// coroutine that `f` queued when it returns, up to the parked workers. `f`
+// must not block, the deferred wakes are kept by the thread
+//
+// the unpark of a coroutine normally wakes a parked worker at once, a loop
+// that unparks many of them, e.g. `send_all`, would wake them one by one
+pub(crate) fn batch_wakes<R, F: FnOnce() -> R>(f: F) -> R {
+    // a nested batch is folded into the outer one
+    if WAKE_BATCH.with(|b| b.get().is_some()) {
+        return f();
+    }
+    struct Flush;
+    impl Drop for Flush {
+        fn drop(&mut self) {
+            let n = WAKE_BATCH.with(|b| b.take()).unwrap_or(0);
+            if n == 0 {
+                return;
+            }
+            let sched = get_scheduler();
+            for _ in 0..n.min(sched.worker_num()) {
+                sched.workers.wake_one(sched);
+            }
+        }
+    }
+    WAKE_BATCH.with(|b| b.set(Some(0)));
+    let _flush = Flush;
+    f()
+}
+
 #[inline(never)]
 fn init_scheduler() {
+    if let (None, Some(seed)) = (determinism::seed(), determinism::env_seed()) {
+        config().set_deterministic_seed(seed);
+    }
     let workers = config().get_workers();
-    let b: Box<Scheduler> = Scheduler::new(workers);
+    let max = config().get_max_workers().max(workers);
+    let placement = affinity::worker_placement(max);
+    let b: Box<Scheduler> = Scheduler::new(workers, placement).expect("can't create event_loop");
     unsafe {
         SCHED = Box::into_raw(b);
     }
-    filter_cancel_panic();
+    start_threads(unsafe { &*SCHED }).expect("can't start the scheduler threads");
+    lifecycle::run_start(unsafe { &*SCHED }, true);
+}
 
+// start the timer thread and the active worker threads of the scheduler
+pub(crate) fn start_threads(s: &'static Scheduler) -> io::Result<Vec<thread::JoinHandle<()>>> {
+    static FILTER: Once = Once::new();
+    FILTER.call_once(filter_cancel_panic);
+
+    // the scheduler is shared by the threads, it's only freed after they
+    // are joined
+    let sched = s as *const Scheduler as usize;
+    let workers = s.worker_num();
+    let mut threads = Vec::with_capacity(workers + 1);
     // timer thread
-    thread::spawn(move || {
-        let s = unsafe { &*SCHED };
+    let name = thread_names::name("timer");
+    let t = thread::Builder::new().name(name).spawn(move || {
+        let s = unsafe { &*(sched as *const Scheduler) };
+        set_current_sched(s);
         // timer function
         let timer_event_handler = |co: Arc<AtomicOption<CoroutineImpl>>| {
             // just re-push the co to the visit list
             if let Some(mut c) = co.take() {
+                #[cfg(feature = "flight-recorder")]
+                crate::flight::record_co(crate::flight::Event::Timer, &c, 0);
                 // set the timeout result for the coroutine
                 set_co_para(&mut c, io::Error::new(io::ErrorKind::TimedOut, "timeout"));
                 // s.schedule_global(c);
@@ -111,21 +441,70 @@ fn init_scheduler() {
         };
 
         s.timer_thread.run(&timer_event_handler);
-    });
+    })?;
+    threads.push(t);
+
+    watchdog::watch(s);
 
-    // io event loop thread
+    let mut resize = s.resize.lock();
     for id in 0..workers {
-        thread::spawn(move || {
-            let s = unsafe { &*SCHED };
-            s.event_loop.run(id as usize).unwrap_or_else(|e| {
-                panic!("event_loop failed running, err={}", e);
-            });
-        });
+        threads.push(start_worker(s, id, resize.placement[id].take())?);
+        resize.started += 1;
     }
+    Ok(threads)
+}
+
+// io event loop thread
+fn start_worker(
+    s: &'static Scheduler,
+    id: usize,
+    place: Option<(affinity::CpuSet, usize)>,
+) -> io::Result<thread::JoinHandle<()>> {
+    let sched = s as *const Scheduler as usize;
+    let name = thread_names::worker_name(id);
+    thread::Builder::new().name(name).spawn(move || {
+        if let Some((cpus, _)) = &place {
+            affinity::pin_current(cpus);
+        }
+        let s = unsafe { &*(sched as *const Scheduler) };
+        set_current_sched(s);
+        watchdog::set_worker_slot(&s.run_slots[id]);
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| s.event_loop.run(id)));
+        let reason = match ret {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => format!("event_loop failed running, err={}", e),
+            Err(p) => match p.downcast_ref::<&str>() {
+                Some(msg) => msg.to_string(),
+                None => p
+                    .downcast_ref::<String>()
+                    .cloned()
+                    .unwrap_or_else(|| "unknown panic".to_owned()),
+            },
+        };
+        s.worker_panicked(id, place, &reason);
+    })
+}
+
+// mark that there are schedulers other than the default one
+pub(crate) fn enable_runtimes() {
+    RUNTIMES_ENABLED.store(true, Ordering::Relaxed);
 }
 
+/// get the scheduler of the current thread, the default one is started
+/// lazily if the thread doesn't belong to a runtime
 #[inline]
 pub fn get_scheduler() -> &'static Scheduler {
+    if runtimes_enabled() {
+        let s = current_sched();
+        if !s.is_null() {
+            return unsafe { &*s };
+        }
+    }
+    default_scheduler()
+}
+
+#[inline]
+pub(crate) fn default_scheduler() -> &'static Scheduler {
     unsafe {
         if likely(!SCHED.is_null()) {
             return &*SCHED;
@@ -136,6 +515,22 @@ pub fn get_scheduler() -> &'static Scheduler {
     unsafe { &*SCHED }
 }
 
+// the default scheduler if it's started
+pub(crate) fn default_scheduler_started() -> Option<&'static Scheduler> {
+    unsafe { SCHED.as_ref() }
+}
+
+// the scheduler of the current thread is started and not shut down
+pub(crate) fn current_running() -> bool {
+    if runtimes_enabled() {
+        let s = current_sched();
+        if !s.is_null() {
+            return !unsafe { &*s }.is_shutdown();
+        }
+    }
+    default_scheduler_started().map_or(false, |s| !s.is_shutdown())
+}
+
 #[inline]
 fn steal_global<T>(global: &deque::Injector<T>, local: &deque::Worker<T>) -> Option<T> {
     static GLOBABLE_LOCK: AtomicUsize = AtomicUsize::new(0);
@@ -149,7 +544,10 @@ fn steal_global<T>(global: &deque::Injector<T>, local: &deque::Worker<T>) -> Opt
     let backoff = Backoff::new();
     let ret = loop {
         match global.steal_batch_and_pop(local) {
-            deque::Steal::Success(t) => break Some(t),
+            deque::Steal::Success(t) => {
+                stats::STEALS.fetch_add(1, Ordering::Relaxed);
+                break Some(t);
+            }
             deque::Steal::Empty => break None,
             deque::Steal::Retry => backoff.snooze(),
         }
@@ -163,67 +561,621 @@ fn steal_local<T>(stealer: &deque::Stealer<T>, local: &deque::Worker<T>) -> Opti
     let backoff = Backoff::new();
     loop {
         match stealer.steal_batch_and_pop(local) {
-            deque::Steal::Success(t) => return Some(t),
+            deque::Steal::Success(t) => {
+                stats::STEALS.fetch_add(1, Ordering::Relaxed);
+                return Some(t);
+            }
             deque::Steal::Empty => return None,
             deque::Steal::Retry => backoff.snooze(),
         }
     }
 }
 
+// the states of the workers
+// the thread is not started
+const WORKER_NEW: usize = 0;
+// the worker runs the tasks
+const WORKER_ACTIVE: usize = 1;
+// the worker is going to hand its tasks over to the active ones
+const WORKER_DRAINING: usize = 2;
+// the worker only forwards the io events of the sockets registered on it
+const WORKER_PARKED: usize = 3;
+// the worker thread panicked and is not respawned
+const WORKER_DEAD: usize = 4;
+
+// set on the first resize, the workers are all active till then
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+pub(crate) fn resized() -> bool {
+    RESIZED.load(Ordering::Relaxed)
+}
+
+// set when a panicked worker is respawned, the pinned coroutines check
+// whether their worker is still the same one
+static RESPAWNED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+pub(crate) fn respawned() -> bool {
+    RESPAWNED.load(Ordering::Relaxed)
+}
+
+// the workers that are not started yet and the threads started by resizing
+struct Resize {
+    placement: Vec<Option<(affinity::CpuSet, usize)>>,
+    started: usize,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
 #[repr(align(128))]
 pub struct Scheduler {
     pub pool: CoroutinePool,
     event_loop: EventLoop,
     global_queue: deque::Injector<CoroutineImpl>,
+    // the coroutines with a priority, see `Builder::priority`
+    prio_queue: PrioQueue,
     local_queues: Vec<deque::Worker<CoroutineImpl>>,
+    lifo_slots: Vec<LifoSlot>,
+    // the pinned coroutines that are woken up on the other workers
+    pinned_queues: Vec<SegQueue<CoroutineImpl>>,
+    // the live coroutines pinned to each worker
+    pinned_live: Vec<AtomicUsize>,
+    // the worker that takes the pinned coroutines of a parked worker, itself
+    // for the others. it always points to a lower id
+    pin_redirect: Vec<AtomicUsize>,
+    // bumped when the worker is respawned after a panic
+    pin_epochs: Vec<AtomicUsize>,
+    // what the workers run, for the watchdog
+    run_slots: Vec<RunSlot>,
     pub(crate) workers: ParkStatus,
     timer_thread: TimerThread,
     stealers: Vec<Vec<(usize, deque::Stealer<CoroutineImpl>)>>,
+    // the max number of the workers
     workers_len: usize,
+    // the first `active` workers run the tasks
+    active: AtomicUsize,
+    states: Vec<AtomicUsize>,
+    resize: Mutex<Resize>,
+    // the initializers of `register_worker_init`
+    pub(crate) worker_inits: WorkerInits,
+    // the temporary workers of the blocking zones
+    pub(crate) zone_workers: Mutex<usize>,
+    // the live coroutines, only counted for the runtimes
+    pub(crate) live: Option<AtomicUsize>,
+    // the references to the scheduler of a runtime, see `SchedRef`
+    refs: AtomicUsize,
+    shutdown: AtomicBool,
+    // the worker to panic in the next round, for the tests
+    #[cfg(feature = "test-util")]
+    inject_panic: AtomicUsize,
 }
 
 impl Scheduler {
-    pub fn new(workers: usize) -> Box<Self> {
-        let mut local_queues = Vec::with_capacity(workers);
-        (0..workers).for_each(|_| local_queues.push(deque::Worker::new_fifo()));
-        let mut stealers = Vec::with_capacity(workers);
-        for id in 0..workers {
-            let mut stealers_l = Vec::with_capacity(workers);
+    /// create the scheduler with `workers` active workers, the workers can
+    /// grow up to the number of the placements
+    pub fn new(
+        workers: usize,
+        placement: Vec<Option<(affinity::CpuSet, usize)>>,
+    ) -> io::Result<Box<Self>> {
+        let max = placement.len();
+        assert!(
+            workers > 0 && workers <= max,
+            "the workers must be in 1..={}",
+            max
+        );
+        let socket = |id: usize| placement.get(id).and_then(|p| p.as_ref()).map(|p| p.1);
+        let mut local_queues = Vec::with_capacity(max);
+        (0..max).for_each(|_| local_queues.push(deque::Worker::new_fifo()));
+        let mut stealers = Vec::with_capacity(max);
+        for id in 0..max {
+            let mut stealers_l = Vec::with_capacity(max);
             for (i, worker) in local_queues.iter().enumerate() {
                 if i != id {
                     stealers_l.push((i, worker.stealer()));
                 }
             }
             stealers_l.rotate_left(id);
+            // steal from the workers on the same socket first
+            stealers_l.sort_by_key(|(i, _)| socket(*i) != socket(id));
             stealers.push(stealers_l);
         }
-        Box::new(Scheduler {
+        let event_loop = EventLoop::new(max)?;
+        event_loop.get_selector().set_active(workers);
+        let states = (0..max)
+            .map(|id| {
+                AtomicUsize::new(if id < workers {
+                    WORKER_ACTIVE
+                } else {
+                    WORKER_NEW
+                })
+            })
+            .collect();
+        Ok(Box::new(Scheduler {
             pool: CoroutinePool::new(),
-            event_loop: EventLoop::new(workers).expect("can't create event_loop"),
+            event_loop,
             global_queue: deque::Injector::new(),
+            prio_queue: PrioQueue::default(),
             local_queues,
+            lifo_slots: (0..max).map(|_| LifoSlot::default()).collect(),
+            pinned_queues: (0..max).map(|_| SegQueue::new()).collect(),
+            pinned_live: (0..max).map(|_| AtomicUsize::new(0)).collect(),
+            pin_redirect: (0..max).map(AtomicUsize::new).collect(),
+            pin_epochs: (0..max).map(|_| AtomicUsize::new(0)).collect(),
+            run_slots: (0..max).map(|_| RunSlot::new()).collect(),
             timer_thread: TimerThread::new(),
-            workers: ParkStatus::new(workers as u64),
+            workers: ParkStatus::new(),
             stealers,
-            workers_len: workers,
-        })
+            workers_len: max,
+            active: AtomicUsize::new(workers),
+            states,
+            resize: Mutex::new(Resize {
+                placement,
+                started: 0,
+                threads: Vec::new(),
+            }),
+            worker_inits: WorkerInits::new(max),
+            zone_workers: Mutex::new(0),
+            live: None,
+            refs: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            #[cfg(feature = "test-util")]
+            inject_panic: AtomicUsize::new(!1),
+        }))
+    }
+
+    // the running state of the worker
+    #[inline]
+    pub(crate) fn run_slot(&self, id: usize) -> Option<&RunSlot> {
+        self.run_slots.get(id)
+    }
+
+    /// the number of the active workers
+    #[inline]
+    pub fn worker_num(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// the max number of the workers
+    #[inline]
+    pub fn max_workers(&self) -> usize {
+        self.workers_len
+    }
+
+    /// the number of the active workers and the draining ones, the draining
+    /// workers are still handing their tasks over. the dead workers are not
+    /// counted as active
+    pub fn worker_counts(&self) -> (usize, usize) {
+        let active = self.worker_num();
+        let (mut draining, mut dead) = (0, 0);
+        for (id, s) in self.states.iter().enumerate() {
+            match s.load(Ordering::Acquire) {
+                WORKER_DRAINING => draining += 1,
+                WORKER_DEAD if id < active => dead += 1,
+                _ => {}
+            }
+        }
+        (active - dead, draining)
+    }
+
+    // the state name and the queued coroutines of the worker, for the
+    // diagnostics. the length is read while the worker runs, so it's a hint
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn worker_state(&self, id: usize) -> (&'static str, usize) {
+        let state = match self.states[id].load(Ordering::Acquire) {
+            WORKER_NEW => "new",
+            WORKER_ACTIVE => "active",
+            WORKER_DRAINING => "draining",
+            WORKER_PARKED => "parked",
+            _ => "dead",
+        };
+        (state, self.local_queues[id].len())
+    }
+
+    // the coroutines in the global queue, for the diagnostics
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn global_queued(&self) -> usize {
+        self.global_queue.len()
+    }
+
+    #[inline]
+    pub(crate) fn is_active(&self, id: usize) -> bool {
+        match self.states.get(id) {
+            Some(s) => s.load(Ordering::Acquire) == WORKER_ACTIVE,
+            None => false,
+        }
+    }
+
+    /// return true if the runtime of the scheduler is shut down
+    #[inline]
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    // stop the timer thread and the workers, the coroutines left are never run
+    pub(crate) fn stop(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        self.timer_thread.stop();
+        self.event_loop.stop(self.workers_len);
+    }
+
+    // the threads started by resizing
+    pub(crate) fn take_threads(&self) -> Vec<thread::JoinHandle<()>> {
+        std::mem::take(&mut self.resize.lock().threads)
+    }
+
+    // take a reference of the scheduler of a runtime, the default one is
+    // never freed so it's not counted
+    #[inline]
+    pub(crate) fn acquire(&self) {
+        if self.live.is_some() {
+            self.refs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // drop a reference, the last one frees the scheduler of a runtime
+    //
+    // safety: the scheduler is from `Box::into_raw` if it's of a runtime,
+    // the caller owns a reference that is taken by `acquire`. a null one is
+    // ignored
+    #[inline]
+    pub(crate) unsafe fn release(s: *const Scheduler) {
+        let sched = match s.as_ref() {
+            Some(sched) => sched,
+            None => return,
+        };
+        if sched.live.is_some() && sched.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            per_worker::forget_scheduler(s as usize);
+            // the selector closes its fds and the pool frees its stacks
+            drop(Box::from_raw(s as *mut Scheduler));
+        }
+    }
+
+    // free the coroutines left in the queues after the workers are joined.
+    // the ones that never ran are dropped and their handles join with the
+    // cancel error. the ones that have run can't be unwound off the workers,
+    // they are leaked with their references. the parked coroutines keep
+    // their references as well, so the scheduler is only freed when no
+    // coroutine is left behind
+    pub(crate) fn drop_queued(&self) {
+        loop {
+            match self.global_queue.steal() {
+                deque::Steal::Success(co) => drop_queued(co),
+                deque::Steal::Empty => break,
+                deque::Steal::Retry => {}
+            }
+        }
+        for q in self.local_queues.iter() {
+            while let Some(co) = q.pop() {
+                drop_queued(co);
+            }
+        }
+        for slot in self.lifo_slots.iter() {
+            if let Some(co) = slot.co.take() {
+                drop_queued(co);
+            }
+        }
+        for q in self.pinned_queues.iter() {
+            while let Some(co) = q.pop() {
+                drop_queued(co);
+            }
+        }
+        while let Some(co) = self.prio_queue.pop() {
+            drop_queued(co);
+        }
+    }
+
+    // the worker that the coroutines pinned to `id` run on
+    #[inline]
+    pub(crate) fn pin_target(&self, mut id: usize) -> usize {
+        if !resized() {
+            return id;
+        }
+        loop {
+            let to = unsafe { self.pin_redirect.get_unchecked(id) }.load(Ordering::Acquire);
+            if to == id {
+                return id;
+            }
+            id = to;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn pinned_spawned(&self, id: usize) {
+        self.pinned_live[id].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn pinned_done(&self, id: usize) {
+        self.pinned_live[id].fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // the times that the worker is respawned
+    #[inline]
+    pub(crate) fn pin_epoch(&self, id: usize) -> usize {
+        self.pin_epochs[id].load(Ordering::Acquire)
+    }
+
+    // the worker thread `id` panicked, apply the `WorkerPanic` policy
+    fn worker_panicked(
+        &'static self,
+        id: usize,
+        place: Option<(affinity::CpuSet, usize)>,
+        reason: &str,
+    ) {
+        stats::WORKER_PANICS.fetch_add(1, Ordering::Relaxed);
+        let policy = config().get_worker_panic();
+        error!("worker {} panicked: {}, {:?}", id, reason, policy);
+        match policy {
+            WorkerPanic::Abort => process::abort(),
+            _ if self.is_shutdown() => {}
+            WorkerPanic::Respawn => {
+                // the pinned coroutines may hold the thread locals of the dead one
+                RESPAWNED.store(true, Ordering::Relaxed);
+                self.pin_epochs[id].fetch_add(1, Ordering::AcqRel);
+                let mut resize = self.resize.lock();
+                match start_worker(self, id, place) {
+                    Ok(t) => resize.threads.push(t),
+                    Err(e) => {
+                        error!("can't respawn worker {}: {}", id, e);
+                        drop(resize);
+                        self.hand_over(id);
+                    }
+                }
+            }
+            WorkerPanic::Log => self.hand_over(id),
+        }
+    }
+
+    // mark the worker dead and move its queued coroutines to the global
+    // queue, the pinned ones can't run anywhere else. the io registered on
+    // it and its io timers go to a worker that is still running
+    fn hand_over(&self, id: usize) {
+        let _ = self.states[id].compare_exchange(
+            WORKER_ACTIVE,
+            WORKER_DEAD,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        );
+        let local = unsafe { self.local_queues.get_unchecked(id) };
+        let lifo = unsafe { self.lifo_slots.get_unchecked(id) };
+        if let Some(co) = lifo.co.take() {
+            self.schedule_global(co);
+        }
+        while let Some(co) = local.pop() {
+            self.schedule_global(co);
+        }
+        // a parked worker still runs its selector
+        let running = |w: &usize| {
+            *w != id
+                && match self.states[*w].load(Ordering::Acquire) {
+                    WORKER_ACTIVE | WORKER_DRAINING | WORKER_PARKED => true,
+                    _ => false,
+                }
+        };
+        let to = (0..self.workers_len)
+            .filter(running)
+            .find(|&w| self.is_active(w))
+            .or_else(|| (0..self.workers_len).find(running));
+        match to {
+            Some(to) => self.get_selector().hand_over(id, to),
+            None => error!("no worker is left for the io of worker {}", id),
+        }
+    }
+
+    // move the queued coroutines of a stalled worker to the global queue,
+    // return how many are moved. the pinned ones can't run anywhere else
+    pub(crate) fn rescue(&self, id: usize) -> usize {
+        let stealer = self
+            .stealers
+            .iter()
+            .flat_map(|s| s.iter())
+            .find(|(i, _)| *i == id)
+            .map(|(_, s)| s);
+        let mut moved = 0;
+        let lifo = unsafe { self.lifo_slots.get_unchecked(id) };
+        if let Some(co) = lifo.co.take() {
+            self.schedule_global(co);
+            moved += 1;
+        }
+        if let Some(stealer) = stealer {
+            loop {
+                match stealer.steal() {
+                    deque::Steal::Success(co) => {
+                        self.schedule_global(co);
+                        moved += 1;
+                    }
+                    deque::Steal::Empty => break,
+                    deque::Steal::Retry => {}
+                }
+            }
+        }
+        moved
+    }
+
+    // let the worker panic in its next round
+    #[cfg(feature = "test-util")]
+    pub(crate) fn inject_panic(&self, id: usize) {
+        self.inject_panic.store(id, Ordering::Release);
+        self.get_selector().wakeup(id);
+    }
+
+    // the live coroutines pinned to the workers whose redirection reaches `id`
+    fn pinned_across(&self, id: usize) -> usize {
+        (0..self.workers_len)
+            .filter(|&w| {
+                let mut w = w;
+                loop {
+                    if w == id {
+                        return true;
+                    }
+                    let to = self.pin_redirect[w].load(Ordering::Acquire);
+                    if to == w {
+                        return false;
+                    }
+                    w = to;
+                }
+            })
+            .map(|w| self.pinned_live[w].load(Ordering::Acquire))
+            .sum()
+    }
+
+    /// grow or shrink the active workers to `n`
+    ///
+    /// the new workers are started or the parked ones are woken up. the
+    /// surplus workers finish the running coroutines, hand the queued ones
+    /// over to the active workers and park in the io driver. the sockets
+    /// registered on a parked worker stay there, it only forwards their
+    /// events. the pinned coroutines on a surplus worker fail the shrink
+    /// unless `migrate` is true, then they are moved to the active workers
+    ///
+    /// the temporary workers of the blocking zones stay on top of `n`, the
+    /// ones over the max workers are given up
+    pub(crate) fn set_workers(&'static self, n: usize, migrate: bool) -> Result<(), ResizeError> {
+        if n == 0 || n > self.workers_len {
+            return Err(ResizeError::OutOfRange {
+                workers: n,
+                max: self.workers_len,
+            });
+        }
+        let mut extra = self.zone_workers.lock();
+        let keep = (*extra).min(self.workers_len - n);
+        self.resize_to(n + keep, migrate)?;
+        *extra = keep;
+        Ok(())
+    }
+
+    // resize to `n` workers, the temporary workers included, the caller
+    // holds the `zone_workers` lock
+    pub(crate) fn resize_to(&'static self, n: usize, migrate: bool) -> Result<(), ResizeError> {
+        if n == 0 || n > self.workers_len {
+            return Err(ResizeError::OutOfRange {
+                workers: n,
+                max: self.workers_len,
+            });
+        }
+        let mut resize = self.resize.lock();
+        let active = self.worker_num();
+        RESIZED.store(true, Ordering::Relaxed);
+        if n > active {
+            // start the new threads first, it's the only step that fails
+            while resize.started < n {
+                let id = resize.started;
+                self.states[id].store(WORKER_ACTIVE, Ordering::Release);
+                let place = resize.placement[id].take();
+                match start_worker(self, id, place) {
+                    Ok(t) => resize.threads.push(t),
+                    Err(e) => {
+                        self.states[id].store(WORKER_NEW, Ordering::Release);
+                        return Err(ResizeError::Spawn(e));
+                    }
+                }
+                resize.started += 1;
+            }
+            for id in active..n {
+                // keep the migrated coroutines where they are if any of them is alive
+                if self.pinned_across(id) == 0 {
+                    self.pin_redirect[id].store(id, Ordering::Release);
+                }
+                self.states[id].store(WORKER_ACTIVE, Ordering::Release);
+                self.get_selector().wakeup(id);
+            }
+        } else if n < active {
+            if !migrate {
+                for id in n..active {
+                    if self.pin_target(id) != id {
+                        continue;
+                    }
+                    let coroutines = self.pinned_across(id);
+                    if coroutines != 0 {
+                        return Err(ResizeError::Pinned {
+                            worker: id,
+                            coroutines,
+                        });
+                    }
+                }
+            }
+            for id in n..active {
+                self.pin_redirect[id].store(id % n, Ordering::Release);
+                self.states[id].store(WORKER_DRAINING, Ordering::Release);
+            }
+        }
+        self.active.store(n, Ordering::Release);
+        self.get_selector().set_active(n);
+        for id in n..active {
+            self.get_selector().wakeup(id);
+        }
+        info!("set workers={}, was {}", n, active);
+        Ok(())
+    }
+
+    // hand the queued tasks of an inactive worker over to the active ones
+    fn drain(&self, id: usize) {
+        let local = unsafe { self.local_queues.get_unchecked(id) };
+        let lifo = unsafe { self.lifo_slots.get_unchecked(id) };
+        let pinned = unsafe { self.pinned_queues.get_unchecked(id) };
+        if let Some(co) = lifo.co.take() {
+            self.schedule_global(co);
+        }
+        while let Some(co) = local.pop() {
+            self.schedule_global(co);
+        }
+        while let Some(co) = pinned.pop() {
+            self.schedule_pinned(self.pin_target(id), co);
+        }
+        // it fails if the worker is active again
+        let _ = self.states[id].compare_exchange(
+            WORKER_DRAINING,
+            WORKER_PARKED,
+            Ordering::AcqRel,
+            Ordering::Relaxed,
+        );
     }
 
     pub fn run_queued_tasks(&self, id: usize) {
+        #[cfg(feature = "test-util")]
+        if self.inject_panic.load(Ordering::Relaxed) == id {
+            self.inject_panic.store(!1, Ordering::Release);
+            panic!("injected panic of worker {}", id);
+        }
+        if resized() && !self.is_active(id) {
+            return self.drain(id);
+        }
         let local = unsafe { self.local_queues.get_unchecked(id) };
         let stealers = unsafe { self.stealers.get_unchecked(id) };
+        let lifo = unsafe { self.lifo_slots.get_unchecked(id) };
+        let pinned = unsafe { self.pinned_queues.get_unchecked(id) };
+        let mut prio_runs = 0;
         loop {
-            // Pop a task from the local queue
-            let co = local.pop().or_else(|| {
+            // Pop the pinned task first, then the priority queue, the lifo
+            // slot and the local queue
+            let mut co = pinned.pop();
+            if co.is_none() {
+                co = self.pop_prio(&mut prio_runs);
+            }
+            let co = co.or_else(|| lifo.pop(local));
+            let co = co.or_else(|| local.pop()).or_else(|| {
                 // Try stealing a of task from other local queues.
                 let parked_threads = self.workers.parked.load(Ordering::Relaxed);
+                // a deterministic run tries the victims from a seeded start
+                let skip = match stealers.len() {
+                    n if n > 1 && determinism::enabled() => determinism::below(id as u64, n),
+                    _ => 0,
+                };
                 stealers
                     .iter()
+                    .cycle()
+                    .skip(skip)
+                    .take(stealers.len())
                     .map(|s| {
-                        if parked_threads & (self.workers_len + s.0) as u64 != 0 {
+                        if self.workers.is_parked(parked_threads, s.0) {
                             return None;
                         }
-                        steal_local(&s.1, local)
+                        let co = steal_local(&s.1, local);
+                        #[cfg(feature = "flight-recorder")]
+                        if let Some(co) = co.as_ref() {
+                            crate::flight::record_co(crate::flight::Event::Steal, co, s.0 as u32);
+                        }
+                        co
                     })
                     .find_map(|r| r)
                     // Try stealing a batch of tasks from the global queue.
@@ -231,40 +1183,165 @@ impl Scheduler {
                         if self.global_queue.is_empty() {
                             None
                         } else {
-                            steal_global(&self.global_queue, local)
+                            let co = steal_global(&self.global_queue, local);
+                            #[cfg(feature = "flight-recorder")]
+                            if let Some(co) = co.as_ref() {
+                                let arg = crate::flight::GLOBAL_QUEUE;
+                                crate::flight::record_co(crate::flight::Event::Steal, co, arg);
+                            }
+                            co
                         }
                     })
             });
 
             if let Some(co) = co {
                 run_coroutine(co);
+                if self.is_shutdown() {
+                    break;
+                }
+                if resized() && !self.is_active(id) {
+                    // the worker is shrunk, the running coroutine is finished
+                    return self.drain(id);
+                }
             } else {
                 // do a re-check
-                if self.global_queue.is_empty() {
+                if self.global_queue.is_empty() && self.prio_queue.is_empty() {
                     break;
                 }
             }
         }
     }
 
+    // pop the priority queue till the budget is used up, the budget is
+    // renewed when the other queues have had a chance
+    #[inline]
+    fn pop_prio(&self, runs: &mut usize) -> Option<CoroutineImpl> {
+        if *runs >= PRIO_BUDGET {
+            *runs = 0;
+            return None;
+        }
+        let co = self.prio_queue.pop();
+        if co.is_some() {
+            *runs += 1;
+        }
+        co
+    }
+
+    // the coroutine is raised to `priority`, see `Coroutine::boost`
+    pub(crate) fn raise_queued(&self, id: CoroutineId, priority: u8) {
+        self.prio_queue.raise(id, priority);
+    }
+
+    // put the coroutine with a priority to the priority queue, give the
+    // others back
+    #[inline]
+    fn schedule_prio(&self, co: CoroutineImpl) -> Option<CoroutineImpl> {
+        if !priority_enabled() {
+            return Some(co);
+        }
+        match priority_of(&co) {
+            0 => Some(co),
+            priority => {
+                self.prio_queue.push(priority, co);
+                self.workers.wake_one(self);
+                None
+            }
+        }
+    }
+
     /// put the coroutine to correct queue so that next time it can be scheduled
     #[inline]
     pub fn schedule(&self, co: CoroutineImpl) {
-        #[cfg(nightly)]
-        let id = WORKER_ID.load(Ordering::Relaxed);
-        #[cfg(not(nightly))]
-        let id = WORKER_ID.with(|id| id.load(Ordering::Relaxed));
+        let co = match self.schedule_prio(co) {
+            Some(co) => co,
+            None => return,
+        };
+        let id = worker_id();
+        if id == !1 {
+            self.schedule_global(co);
+        } else {
+            self.schedule_local(id, co);
+        }
+    }
 
+    /// put the coroutine to the lifo slot so that it would run right after the
+    /// current coroutine, the old one in the slot is moved to the local queue
+    #[inline]
+    pub fn schedule_lifo(&self, co: CoroutineImpl) {
+        let co = match self.schedule_prio(co) {
+            Some(co) => co,
+            None => return,
+        };
+        let id = worker_id();
         if id == !1 {
+            self.schedule_remote(co);
+        } else if let Some(old) = unsafe { self.lifo_slots.get_unchecked(id) }.co.swap(co) {
+            self.schedule_local(id, old);
+            // let a parked worker steal it when a batch queues more than one
+            WAKE_BATCH.with(|b| b.get().map(|n| b.set(Some(n + 1))));
+        }
+    }
+
+    // push the coroutine to the local queue, overflow to the global queue
+    #[inline]
+    fn schedule_local(&self, id: usize, co: CoroutineImpl) {
+        let local = unsafe { self.local_queues.get_unchecked(id) };
+        if local.len() >= LOCAL_QUEUE_CAPACITY {
+            stats::LOCAL_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
             self.schedule_global(co);
         } else {
-            unsafe { self.local_queues.get_unchecked(id) }.push(co);
+            local.push(co);
         }
     }
 
+    /// put the pinned coroutine to the queue of its worker, it's never stolen
+    #[inline]
+    pub fn schedule_pinned(&self, id: usize, co: CoroutineImpl) {
+        let id = self.pin_target(id);
+        unsafe { self.pinned_queues.get_unchecked(id) }.push(co);
+        if id != worker_id() {
+            self.workers.wake(self, id);
+        }
+    }
+
+    // return true if there are tasks that a parking worker would miss, the
+    // coroutines queued before the worker marks itself parked don't wake it
+    #[inline]
+    pub(crate) fn has_ready(&self, id: usize) -> bool {
+        !unsafe { self.pinned_queues.get_unchecked(id) }.is_empty()
+            || (self.is_active(id) && !(self.global_queue.is_empty() && self.prio_queue.is_empty()))
+    }
+
+    // an unpark from outside of the workers, the coroutine goes to the global
+    // queue. the worker that it last ran on takes it without a wake if that
+    // worker is running, it checks the global queue before it parks. only a
+    // parked one is woken otherwise
+    #[inline]
+    pub(crate) fn schedule_remote(&self, co: CoroutineImpl) {
+        let co = match self.schedule_prio(co) {
+            Some(co) => co,
+            None => return,
+        };
+        let last = last_worker_of(&co);
+        self.global_queue.push(co);
+        let bit = park_bit(last);
+        if bit != 0 && self.is_active(last) {
+            // pairs with the park bit and the `has_ready` check of the worker
+            fence(Ordering::SeqCst);
+            if self.workers.parked.load(Ordering::Relaxed) & bit == 0 {
+                return;
+            }
+        }
+        self.workers.wake_one(self);
+    }
+
     /// put the coroutine to global queue so that next time it can be scheduled
     #[inline]
     pub fn schedule_global(&self, co: CoroutineImpl) {
+        let co = match self.schedule_prio(co) {
+            Some(co) => co,
+            None => return,
+        };
         self.global_queue.push(co);
         // signal one waiting thread if any
         self.workers.wake_one(self);
@@ -284,6 +1361,22 @@ impl Scheduler {
         self.timer_thread.del_timer(handle);
     }
 
+    // run the timers that are due after the virtual clock is moved
+    // the io timers are checked by the workers when they wake up
+    #[cfg(feature = "test-util")]
+    pub(crate) fn run_timers(&self) {
+        self.timer_thread.sync();
+        for id in 0..self.workers_len {
+            self.get_selector().wakeup(id);
+        }
+    }
+
+    // the timers that wait for the clock, see `clock::pending_timers`
+    #[cfg(feature = "test-util")]
+    pub(crate) fn pending_timers(&self) -> usize {
+        self.timer_thread.pending()
+    }
+
     #[inline]
     pub fn get_selector(&self) -> &Selector {
         self.event_loop.get_selector()
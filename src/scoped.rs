@@ -1,29 +1,42 @@
 // modified from crossbeam
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
 use std::fmt;
 use std::mem;
 use std::panic;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
-use crate::coroutine_impl::{spawn, Coroutine};
+use crate::coroutine_impl::{current_cancel_data, is_coroutine, Builder, Coroutine};
 use crate::join::JoinHandle;
+use crate::std::sync::{channel, Sender, SyncFlag};
 use crossbeam::atomic::AtomicCell;
+use parking_lot::Mutex;
 
-/// Like `coroutine::spawn`, but without the closure bounds.
-pub unsafe fn spawn_unsafe<'a, F>(f: F) -> JoinHandle<()>
+/// Like `Builder::spawn`, but without the closure bounds, the caller
+/// must pin the builder if the closure is not `Send`.
+pub unsafe fn spawn_unsafe_with<'a, F>(builder: Builder, f: F) -> JoinHandle<()>
 where
-    F: FnOnce() + Send + 'a,
+    F: FnOnce() + 'a,
 {
     let closure: Box<dyn FnOnce() + 'a> = Box::new(f);
     let closure: Box<dyn FnOnce() + Send> = mem::transmute(closure);
-    spawn(move || closure())
+    builder.spawn(move || closure())
 }
 
 pub struct Scope<'a> {
     dtors: RefCell<Option<DtorChain<'a>>>,
+    // the children in spawn order, canceled when the scope is unwinding
+    children: RefCell<Vec<(Coroutine, Rc<RefCell<JoinState>>)>>,
+    // the cancel panics of the children are not propagated once set
+    canceled: Rc<Cell<bool>>,
+    // held by each child of `scope_timeout`, disconnected when all are done
+    alive: Option<Sender<()>>,
 }
 
 struct DtorChain<'a> {
@@ -37,7 +50,7 @@ enum JoinState {
 }
 
 impl JoinState {
-    fn join(&mut self) {
+    fn join(&mut self, ignore_cancel: bool) {
         let mut state = JoinState::Joined;
         mem::swap(self, &mut state);
         if let JoinState::Running(handle) = state {
@@ -45,12 +58,53 @@ impl JoinState {
 
             // TODO: when panic happened, the logic need to refine
             if !thread::panicking() {
-                res.unwrap_or_else(|e| panic::resume_unwind(e));
+                res.unwrap_or_else(|e| {
+                    let is_cancel = matches!(e.downcast_ref(), Some(mco_gen::Error::Cancel));
+                    if !(ignore_cancel && is_cancel) {
+                        panic::resume_unwind(e)
+                    }
+                });
             }
         }
     }
+
+    fn is_done(&self) -> bool {
+        match self {
+            JoinState::Running(handle) => handle.is_done(),
+            JoinState::Joined => true,
+        }
+    }
+}
+
+/// a child of the scope that is still running at the deadline of `scope_timeout`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Straggler {
+    /// the spawn order of the child in the scope
+    pub index: usize,
+    pub name: Option<String>,
+}
+
+/// the error of `scope_timeout`, the stragglers are canceled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeTimedOut {
+    pub stragglers: Vec<Straggler>,
 }
 
+impl fmt::Display for ScopeTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "scope timed out, canceled")?;
+        for s in &self.stragglers {
+            match &s.name {
+                Some(name) => write!(f, " #{}({})", s.index, name)?,
+                None => write!(f, " #{}", s.index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for ScopeTimedOut {}
+
 /// A handle to a scoped coroutine
 pub struct ScopedJoinHandle<T> {
     inner: Rc<RefCell<JoinState>>,
@@ -61,19 +115,227 @@ pub struct ScopedJoinHandle<T> {
 /// Create a new `scope`, for deferred destructors.
 ///
 /// Scopes, in particular, support scoped coroutine spawning.
+/// if `f` panics the children are canceled before they are joined
 ///
 pub fn scope<'a, F, R>(f: F) -> R
 where
     F: FnOnce(&Scope<'a>) -> R,
 {
-    let mut scope = Scope {
-        dtors: RefCell::new(None),
-    };
+    let mut scope = Scope::new(None);
     let ret = f(&scope);
     scope.drop_all();
     ret
 }
 
+/// like `scope`, but the children that are still running after `dur` are
+/// canceled and reported in the error
+///
+/// the canceled children exit at their next blocking call of the library,
+/// a child that never blocks can't be canceled
+pub fn scope_timeout<'a, F, R>(dur: Duration, f: F) -> Result<R, ScopeTimedOut>
+where
+    F: FnOnce(&Scope<'a>) -> R,
+{
+    let (tx, rx) = channel::<()>();
+    let mut scope = Scope::new(Some(tx));
+    let ret = f(&scope);
+    // only the children hold the sender now
+    scope.alive = None;
+    let stragglers = match rx.recv_timeout(dur) {
+        Err(RecvTimeoutError::Timeout) => scope.cancel_running(),
+        _ => Vec::new(),
+    };
+    scope.drop_all();
+    if stragglers.is_empty() {
+        Ok(ret)
+    } else {
+        Err(ScopeTimedOut { stragglers })
+    }
+}
+
+/// the spawner of `scope_detached`, the children must be `'static`
+pub struct DetachedScope {
+    shared: Arc<Detached>,
+}
+
+/// the handle of the children of `scope_detached`, it can be shared with
+/// the coroutines that watch them
+///
+/// dropping it doesn't wait for the children or cancel them
+#[derive(Clone)]
+pub struct ScopeHandle {
+    shared: Arc<Detached>,
+}
+
+struct Detached {
+    // the running children, and one for the body while it spawns
+    remaining: AtomicUsize,
+    // the children in spawn order and if they are done
+    children: Mutex<Vec<(Coroutine, Arc<AtomicBool>)>>,
+    // fired when the body and all the children are done
+    done: SyncFlag,
+}
+
+impl Detached {
+    fn exit(&self) {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.done.fire();
+        }
+    }
+
+    fn cancel(&self) {
+        for (co, done) in self.children.lock().iter() {
+            if !done.load(Ordering::Acquire) {
+                co.cancel();
+            }
+        }
+    }
+}
+
+// counts a child or the body out when it exits, even by a panic
+struct ExitGuard<'a> {
+    shared: &'a Detached,
+    done: Option<Arc<AtomicBool>>,
+}
+
+impl<'a> Drop for ExitGuard<'a> {
+    fn drop(&mut self) {
+        if let Some(done) = &self.done {
+            done.store(true, Ordering::Release);
+        } else if thread::panicking() {
+            // the body panics, don't leave the children running
+            self.shared.cancel();
+        }
+        self.shared.exit();
+    }
+}
+
+/// like `scope`, but it returns at once with a handle to watch the children
+/// instead of joining them
+///
+/// the handle may be waited on by any thread or coroutine, not only the one
+/// that spawns the children, so the children can't borrow from its stack.
+/// if `f` panics the spawned children are canceled
+/// ```
+/// use std::time::Duration;
+///
+/// let handle = mco::coroutine::scope_detached(|scope| {
+///     for i in 0..4 {
+///         scope.spawn(move || mco::coroutine::sleep(Duration::from_millis(i * 10)));
+///     }
+/// });
+/// let watch = handle.clone();
+/// mco::co!(move || while !watch.wait_timeout(Duration::from_millis(5)) {
+///     println!("{} to go", watch.remaining());
+/// });
+/// handle.wait();
+/// assert_eq!(handle.remaining(), 0);
+/// ```
+pub fn scope_detached<F>(f: F) -> ScopeHandle
+where
+    F: FnOnce(&DetachedScope),
+{
+    let shared = Arc::new(Detached {
+        remaining: AtomicUsize::new(1),
+        children: Mutex::new(Vec::new()),
+        done: SyncFlag::new(),
+    });
+    let scope = DetachedScope {
+        shared: shared.clone(),
+    };
+    {
+        let _body = ExitGuard {
+            shared: &shared,
+            done: None,
+        };
+        f(&scope);
+    }
+    ScopeHandle { shared }
+}
+
+impl DetachedScope {
+    /// spawn a child of the scope, its join handle may be dropped since the
+    /// scope handle watches it
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_with(Builder::new(), f)
+    }
+
+    /// spawn a child of the scope with the builder, e.g. to name it
+    pub fn spawn_with<F, T>(&self, builder: Builder, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let shared = self.shared.clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let exit = done.clone();
+        // the body holds one count until it returns, it never drops to 0 here
+        shared.remaining.fetch_add(1, Ordering::AcqRel);
+        let h = builder.spawn(move || {
+            let _exit = ExitGuard {
+                shared: &shared,
+                done: Some(exit),
+            };
+            f()
+        });
+        self.shared
+            .children
+            .lock()
+            .push((h.coroutine().clone(), done));
+        h
+    }
+}
+
+impl ScopeHandle {
+    /// the number of the children that are not done
+    pub fn remaining(&self) -> usize {
+        // the body is done once there is a handle
+        self.shared.remaining.load(Ordering::Acquire)
+    }
+
+    /// return true if all the children are done
+    pub fn is_done(&self) -> bool {
+        self.shared.done.is_fired()
+    }
+
+    /// wait until all the children are done
+    pub fn wait(&self) {
+        self.shared.done.wait()
+    }
+
+    /// wait until all the children are done, return false if they are not
+    /// done after `dur`
+    pub fn wait_timeout(&self, dur: Duration) -> bool {
+        self.shared.done.wait_timeout(dur)
+    }
+
+    /// cancel the children that are still running
+    ///
+    /// they exit at their next blocking call of the library, `wait` returns
+    /// once they are all gone. a child that never blocks can't be canceled
+    pub fn cancel(&self) {
+        self.shared.cancel()
+    }
+}
+
+impl fmt::Debug for DetachedScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DetachedScope {{ ... }}")
+    }
+}
+
+impl fmt::Debug for ScopeHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScopeHandle")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
 impl<'a> fmt::Debug for Scope<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Scope {{ ... }}")
@@ -87,6 +349,31 @@ impl<T> fmt::Debug for ScopedJoinHandle<T> {
 }
 
 impl<'a> Scope<'a> {
+    fn new(alive: Option<Sender<()>>) -> Self {
+        Scope {
+            dtors: RefCell::new(None),
+            children: RefCell::new(Vec::new()),
+            canceled: Rc::new(Cell::new(false)),
+            alive,
+        }
+    }
+
+    // cancel the children that are not done, return them
+    fn cancel_running(&self) -> Vec<Straggler> {
+        self.canceled.set(true);
+        let mut stragglers = Vec::new();
+        for (index, (co, state)) in self.children.borrow().iter().enumerate() {
+            if !state.borrow().is_done() {
+                co.cancel();
+                stragglers.push(Straggler {
+                    index,
+                    name: co.name().map(ToOwned::to_owned),
+                });
+            }
+        }
+        stragglers
+    }
+
     // This method is carefully written in a transactional style, so
     // that it can be called directly and, if any dtor panics, can be
     // resumed in the unwinding this causes. By initially running the
@@ -109,6 +396,16 @@ impl<'a> Scope<'a> {
         }
     }
 
+    /// cancel the children that are still running, used by `race!`
+    ///
+    /// the children exit at their next blocking call of the library, the
+    /// cancel panics are not propagated when the scope joins them. a child
+    /// that is blocked in a call that can't be canceled keeps running until
+    /// that call returns
+    pub fn cancel(&self) {
+        self.cancel_running();
+    }
+
     /// Schedule code to be executed when exiting the scope.
     ///
     /// This is akin to having a destructor on the stack, except that it is
@@ -131,16 +428,18 @@ impl<'a> Scope<'a> {
     /// before the current stack frame goes away, allowing you to reference the parent stack frame
     /// directly. This is ensured by having the parent join on the child coroutine before the
     /// scope exits.
-    fn spawn_impl<F, T>(&self, f: F) -> ScopedJoinHandle<T>
+    fn spawn_impl<F, T>(&self, builder: Builder, f: F) -> ScopedJoinHandle<T>
     where
         F: FnOnce() -> T + Send + 'a,
         T: Send + 'a,
     {
         let their_packet = Arc::new(AtomicCell::new(None));
         let my_packet = their_packet.clone();
+        let alive = self.alive.clone();
 
         let join_handle = unsafe {
-            spawn_unsafe(move || {
+            spawn_unsafe_with(builder, move || {
+                let _alive = alive;
                 their_packet.swap(Some(f()));
             })
         };
@@ -148,10 +447,14 @@ impl<'a> Scope<'a> {
         let co = join_handle.coroutine().clone();
         let deferred_handle = Rc::new(RefCell::new(JoinState::Running(join_handle)));
         let my_handle = deferred_handle.clone();
+        self.children
+            .borrow_mut()
+            .push((co.clone(), deferred_handle.clone()));
 
+        let canceled = self.canceled.clone();
         self.defer(move || {
             let mut state = deferred_handle.borrow_mut();
-            state.join();
+            state.join(canceled.get());
         });
 
         ScopedJoinHandle {
@@ -173,14 +476,23 @@ impl<'a> Scope<'a> {
         F: FnOnce() -> T + Send + 'a,
         T: Send + 'a,
     {
-        self.spawn_impl(f)
+        self.spawn_impl(Builder::new(), f)
+    }
+
+    /// Create a scoped coroutine with the builder, e.g. to name it
+    pub unsafe fn spawn_with<F, T>(&self, builder: Builder, f: F) -> ScopedJoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'a,
+        T: Send + 'a,
+    {
+        self.spawn_impl(builder, f)
     }
 }
 
 impl<T> ScopedJoinHandle<T> {
     /// Join the scoped coroutine, returning the result it produced.
     pub fn join(self) -> T {
-        self.inner.borrow_mut().join();
+        self.inner.borrow_mut().join(false);
         self.packet.take().unwrap()
     }
 
@@ -192,6 +504,19 @@ impl<T> ScopedJoinHandle<T> {
 
 impl<'a> Drop for Scope<'a> {
     fn drop(&mut self) {
-        self.drop_all()
+        if !thread::panicking() {
+            return self.drop_all();
+        }
+        // the body is unwinding, don't wait for the children forever
+        self.cancel_running();
+        if is_coroutine() {
+            // the parent may be canceled itself, join without another cancel panic
+            let cancel = current_cancel_data();
+            cancel.disable_cancel();
+            self.drop_all();
+            cancel.enable_cancel();
+        } else {
+            self.drop_all();
+        }
     }
 }
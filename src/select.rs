@@ -0,0 +1,516 @@
+//! the sources that a coroutine or a thread can wait on together
+//!
+//! a [`Selectable`] is anything that can be tried without blocking and that
+//! wakes a registered [`Waker`] when it may be ready. the channel receivers,
+//! the sends of `Sender::select_send`, the `Ticker`s, the `JoinHandle`s, the
+//! `CancellationToken`s, `DeadlineQueue::wait_expired` and the [`After`]
+//! deadlines implement it, so a [`SelectSet`] can wait for any mix of them. a
+//! `Selectable` is also a blocking call of its own with [`Selectable::wait`],
+//! which makes it an arm of `select!` like any other blocking call
+//!
+//! the waiting side follows the two phase protocol, so no wakeup is lost:
+//! 1. try all the sources, return the first ready one
+//! 2. register the waker on all the sources
+//! 3. try all the sources again, a source that turned ready before it saw
+//!    the waker is caught here
+//! 4. park until a source wakes the waker or the earliest deadline passes,
+//!    then deregister and start over
+//!
+//! the source side makes its state visible first and then wakes all the
+//! registered wakers, a [`WakerList`] does the bookkeeping. a wakeup may be
+//! spurious, the waiting side just tries again
+//!
+//! a source for a third party primitive, a flag that is set once:
+//! ```
+//! use mco::select::{SelectSet, Selectable, Waker, WakerList};
+//! use std::sync::atomic::{AtomicBool, Ordering};
+//! use std::sync::Arc;
+//!
+//! #[derive(Default)]
+//! struct Flag {
+//!     set: AtomicBool,
+//!     wakers: WakerList,
+//! }
+//!
+//! impl Flag {
+//!     fn set(&self) {
+//!         // the state first, then the wakers
+//!         self.set.store(true, Ordering::SeqCst);
+//!         self.wakers.wake_all();
+//!     }
+//! }
+//!
+//! impl Selectable for &Flag {
+//!     type Output = ();
+//!
+//!     fn try_select(&mut self) -> Option<()> {
+//!         if self.set.load(Ordering::SeqCst) {
+//!             Some(())
+//!         } else {
+//!             None
+//!         }
+//!     }
+//!
+//!     fn register(&self, waker: &Waker) {
+//!         self.wakers.register(waker);
+//!     }
+//!
+//!     fn deregister(&self, waker: &Waker) {
+//!         self.wakers.deregister(waker);
+//!     }
+//! }
+//!
+//! let flag = Arc::new(Flag::default());
+//! let f = flag.clone();
+//! mco::co!(move || f.set());
+//! let (_tx, rx) = mco::chan!();
+//! let mut set = SelectSet::new();
+//! set.add(&*flag, |_| "flag");
+//! set.add(&rx, |_| "channel");
+//! assert_eq!(set.select(), "flag");
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::cancel::trigger_cancel_panic;
+use crate::determinism;
+use crate::park::ParkError;
+use crate::std::rand;
+use crate::std::sync::SyncBlocker;
+
+/// the handle that a source wakes when it may be ready, see [`Selectable`]
+///
+/// the clones are the same waker, a waker is parked on by one waiting side
+#[derive(Clone)]
+pub struct Waker {
+    blocker: Arc<SyncBlocker>,
+}
+
+impl Waker {
+    fn new() -> Self {
+        Waker {
+            blocker: SyncBlocker::current(),
+        }
+    }
+
+    /// wake the waiting side, it's fine to wake it more than once
+    pub fn wake(&self) {
+        if !self.blocker.is_unparked() {
+            let _ = self.blocker.unpark();
+        }
+    }
+
+    /// return true if the two are the same waker
+    pub fn same(&self, other: &Waker) -> bool {
+        Arc::ptr_eq(&self.blocker, &other.blocker)
+    }
+}
+
+impl fmt::Debug for Waker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Waker")
+            .field("woken", &self.blocker.is_unparked())
+            .finish()
+    }
+}
+
+/// the registered wakers of a source
+///
+/// `wake_all` is one load when no waker is registered, so a source can call
+/// it on every state change
+#[derive(Default)]
+pub struct WakerList {
+    len: AtomicUsize,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl WakerList {
+    pub fn new() -> Self {
+        WakerList::default()
+    }
+
+    /// register the waker, it stays until `deregister`
+    pub fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        wakers.push(waker.clone());
+        self.len.store(wakers.len(), Ordering::SeqCst);
+    }
+
+    /// deregister the waker
+    pub fn deregister(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        wakers.retain(|w| !w.same(waker));
+        self.len.store(wakers.len(), Ordering::SeqCst);
+    }
+
+    /// wake all the registered wakers, they stay registered
+    #[inline]
+    pub fn wake_all(&self) {
+        if self.len.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let wakers = self.wakers.lock().clone();
+        wakers.iter().for_each(Waker::wake);
+    }
+
+    /// the number of the registered wakers
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// return true if no waker is registered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for WakerList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WakerList")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// a source that can be waited on together with the others
+///
+/// see the [module docs](index.html) for the protocol and an implementation
+pub trait Selectable {
+    /// the result of the operation
+    type Output;
+
+    /// try the operation without blocking, `None` if it's not ready. a
+    /// source that returns `Some` must have done the operation, like taking
+    /// the message
+    fn try_select(&mut self) -> Option<Self::Output>;
+
+    /// register the waker, it must be woken when `try_select` may succeed
+    fn register(&self, waker: &Waker);
+
+    /// deregister the waker
+    fn deregister(&self, waker: &Waker);
+
+    /// the time when the source turns ready by itself, the waiting side
+    /// parks no longer than that
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// block until the operation is done
+    fn wait(&mut self) -> Self::Output
+    where
+        Self: Sized,
+    {
+        let mut set = SelectSet::new();
+        set.add(self, |v| v);
+        set.select()
+    }
+}
+
+impl<S: Selectable + ?Sized> Selectable for &mut S {
+    type Output = S::Output;
+
+    fn try_select(&mut self) -> Option<S::Output> {
+        (**self).try_select()
+    }
+
+    fn register(&self, waker: &Waker) {
+        (**self).register(waker)
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        (**self).deregister(waker)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        (**self).deadline()
+    }
+}
+
+/// a source that turns ready at a point in time
+///
+/// ```
+/// use mco::select::{After, Selectable};
+/// use std::time::{Duration, Instant};
+///
+/// let start = Instant::now();
+/// After::new(Duration::from_millis(10)).wait();
+/// assert!(start.elapsed() >= Duration::from_millis(10));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct After {
+    at: Instant,
+}
+
+impl After {
+    /// ready after `dur` from now
+    pub fn new(dur: Duration) -> Self {
+        After {
+            at: Instant::now() + dur,
+        }
+    }
+
+    /// ready at `at`
+    pub fn at(at: Instant) -> Self {
+        After { at }
+    }
+}
+
+impl Selectable for After {
+    type Output = ();
+
+    fn try_select(&mut self) -> Option<()> {
+        if Instant::now() >= self.at {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn register(&self, _waker: &Waker) {}
+
+    fn deregister(&self, _waker: &Waker) {}
+
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.at)
+    }
+}
+
+// a source with its result mapped, the sources of a set differ in types
+trait Arm<R> {
+    fn try_select(&mut self) -> Option<R>;
+    fn register(&self, waker: &Waker);
+    fn deregister(&self, waker: &Waker);
+    fn deadline(&self) -> Option<Instant>;
+}
+
+struct Mapped<S, F> {
+    source: S,
+    f: F,
+}
+
+impl<S, F, R> Arm<R> for Mapped<S, F>
+where
+    S: Selectable,
+    F: FnMut(S::Output) -> R,
+{
+    fn try_select(&mut self) -> Option<R> {
+        self.source.try_select().map(&mut self.f)
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.source.register(waker)
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.source.deregister(waker)
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.source.deadline()
+    }
+}
+
+/// a dynamic set of sources of any types
+///
+/// each source is added with a function that maps its output to the common
+/// result. the set can be selected many times, the sources are tried from a
+/// rotating start so that a busy one doesn't starve the others
+/// ```
+/// use mco::select::{After, SelectSet};
+/// use mco::std::sync::CancellationToken;
+/// use std::time::Duration;
+///
+/// enum Event {
+///     Msg(u32),
+///     Closed,
+///     Canceled,
+///     Timeout,
+/// }
+///
+/// let (tx, rx) = mco::chan!();
+/// let token = CancellationToken::new();
+/// let mut set = SelectSet::new();
+/// set.add(&rx, |r| r.map_or(Event::Closed, Event::Msg));
+/// set.add(&token, |_| Event::Canceled);
+/// set.add(After::new(Duration::from_secs(10)), |_| Event::Timeout);
+///
+/// tx.send(1).unwrap();
+/// assert!(matches!(set.select(), Event::Msg(1)));
+/// token.cancel();
+/// assert!(matches!(set.select(), Event::Canceled));
+/// ```
+pub struct SelectSet<'a, R> {
+    arms: Vec<Box<dyn Arm<R> + 'a>>,
+    next: usize,
+}
+
+impl<'a, R> SelectSet<'a, R> {
+    pub fn new() -> Self {
+        SelectSet {
+            arms: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// add a source, `f` maps its output to the result of the set
+    pub fn add<S, F>(&mut self, source: S, f: F) -> &mut Self
+    where
+        S: Selectable + 'a,
+        F: FnMut(S::Output) -> R + 'a,
+    {
+        self.arms.push(Box::new(Mapped { source, f }));
+        self
+    }
+
+    /// the number of the sources
+    pub fn len(&self) -> usize {
+        self.arms.len()
+    }
+
+    /// return true if there is no source
+    pub fn is_empty(&self) -> bool {
+        self.arms.is_empty()
+    }
+
+    /// try all the sources once without blocking
+    pub fn try_select(&mut self) -> Option<R> {
+        let n = self.arms.len();
+        // a deterministic run starts each round from a seeded arm
+        if n > 1 && determinism::enabled() {
+            self.next = rand::range(0..n);
+        }
+        for i in 0..n {
+            let idx = (self.next + i) % n;
+            if let Some(r) = self.arms[idx].try_select() {
+                self.next = (idx + 1) % n;
+                return Some(r);
+            }
+        }
+        None
+    }
+
+    /// block until a source is ready, it never returns for an empty set
+    pub fn select(&mut self) -> R {
+        self.select_deadline(None)
+            .expect("select without a deadline never times out")
+    }
+
+    /// block until a source is ready or `dur` passes
+    pub fn select_timeout(&mut self, dur: Duration) -> Option<R> {
+        self.select_deadline(Some(Instant::now() + dur))
+    }
+
+    fn select_deadline(&mut self, deadline: Option<Instant>) -> Option<R> {
+        loop {
+            if let Some(r) = self.try_select() {
+                return Some(r);
+            }
+            let waker = Waker::new();
+            self.arms.iter().for_each(|a| a.register(&waker));
+            // re-check, a source may turn ready before it sees the waker
+            if let Some(r) = self.try_select() {
+                self.arms.iter().for_each(|a| a.deregister(&waker));
+                return Some(r);
+            }
+            let at = self
+                .arms
+                .iter()
+                .filter_map(|a| a.deadline())
+                .chain(deadline)
+                .min();
+            let dur = at.map(|at| at.saturating_duration_since(Instant::now()));
+            let ret = waker.blocker.park(dur);
+            self.arms.iter().for_each(|a| a.deregister(&waker));
+            if ret == Err(ParkError::Canceled) {
+                trigger_cancel_panic();
+            }
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return self.try_select();
+                }
+            }
+        }
+    }
+}
+
+impl<'a, R> Default for SelectSet<'a, R> {
+    fn default() -> Self {
+        SelectSet::new()
+    }
+}
+
+impl<'a, R> fmt::Debug for SelectSet<'a, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SelectSet")
+            .field("len", &self.arms.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::sync::channel::channel;
+    use crate::std::sync::CancellationToken;
+
+    #[test]
+    fn mixed_sources() {
+        let (tx, rx) = channel::<u32>();
+        let token = CancellationToken::new();
+        let h = co!(|| 7);
+        let mut set = SelectSet::new();
+        set.add(&rx, |r| r.unwrap())
+            .add(&token, |_| 100)
+            .add(&h, |r| r.unwrap().unwrap() + 1000);
+        assert_eq!(set.select(), 1007);
+        // the result is taken, the handle stays ready
+        assert!((&h).try_select().unwrap().is_err());
+
+        let mut set = SelectSet::new();
+        set.add(&rx, |r| r.unwrap()).add(&token, |_| 100);
+
+        let t = token.clone();
+        let tx2 = tx.clone();
+        co!(move || {
+            crate::coroutine::sleep(Duration::from_millis(10));
+            tx2.send(1).unwrap();
+            crate::coroutine::sleep(Duration::from_millis(10));
+            t.cancel();
+        });
+        assert_eq!(set.select(), 1);
+        assert_eq!(set.select(), 100);
+        drop(tx);
+    }
+
+    #[test]
+    fn timeout_and_after() {
+        let (_tx, rx) = channel::<u32>();
+        let mut set = SelectSet::new();
+        set.add(&rx, |_| 1);
+        let start = Instant::now();
+        assert!(set.select_timeout(Duration::from_millis(20)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        set.add(After::new(Duration::from_millis(10)), |_| 2);
+        assert_eq!(set.select(), 2);
+    }
+
+    #[test]
+    fn select_send() {
+        let (tx, rx) = crate::std::sync::channel::bounded::<u32>(1);
+        tx.send(0).unwrap();
+        let h = co!(move || {
+            crate::coroutine::sleep(Duration::from_millis(10));
+            rx.recv().unwrap() + rx.recv().unwrap()
+        });
+        tx.select_send(5).wait().unwrap();
+        assert_eq!(h.join().unwrap(), 5);
+        assert!(tx.select_send(1).wait().is_err());
+    }
+}
@@ -0,0 +1,231 @@
+//! wait for ctrl-c in a coroutine
+//!
+//! the first `ctrl_c` call installs the handler, after that ctrl-c doesn't
+//! terminate the process anymore. on windows it's the console control handler
+//! of `SetConsoleCtrlHandler` that catches ctrl-c and ctrl-break, it runs on a
+//! thread of its own so it wakes the waiters directly. on unix it's the
+//! `SIGINT` handler, it only writes to a pipe that is read by a thread named
+//! `mco-signal` which wakes the waiters
+//!
+//! ```no_run
+//! mco::co!(|| {
+//!     mco::signal::ctrl_c().unwrap();
+//!     println!("ctrl-c, shutting down");
+//! })
+//! .join()
+//! .unwrap();
+//! ```
+
+use std::io;
+
+use once_cell::sync::Lazy;
+
+use crate::std::sync::{Condvar, Mutex};
+
+struct Events {
+    // the ctrl-c events seen so far
+    seen: u64,
+    // the waiters of the next one
+    waiting: usize,
+}
+
+static EVENTS: Lazy<(Mutex<Events>, Condvar)> = Lazy::new(|| {
+    let events = Events {
+        seen: 0,
+        waiting: 0,
+    };
+    (Mutex::new(events), Condvar::new())
+});
+
+// the result of the install, it's done once
+static INSTALL: Lazy<Result<(), (io::ErrorKind, String)>> =
+    Lazy::new(|| imp::install().map_err(|e| (e.kind(), e.to_string())));
+
+// a ctrl-c is received, wake all the waiters
+fn notify() {
+    let (events, cond) = &*EVENTS;
+    events.lock().unwrap().seen += 1;
+    let _ = cond.notify_all();
+}
+
+/// wait for the next ctrl-c
+///
+/// it blocks the coroutine, or the thread outside of a coroutine, till a
+/// ctrl-c is received after the call. all the waiters are woken by the same
+/// ctrl-c. the error is from installing the handler
+pub fn ctrl_c() -> io::Result<()> {
+    if let Err((kind, msg)) = &*INSTALL {
+        return Err(io::Error::new(*kind, msg.clone()));
+    }
+    let (events, cond) = &*EVENTS;
+    let mut guard = events.lock().unwrap();
+    let start = guard.seen;
+    guard.waiting += 1;
+    while guard.seen == start {
+        guard = cond.wait(guard).unwrap();
+    }
+    guard.waiting -= 1;
+    Ok(())
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+    use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    // it's called on a new thread, the other events are left to the default
+    // handler that terminates the process
+    pub(super) unsafe extern "system" fn handler(ctrl: DWORD) -> BOOL {
+        match ctrl {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+                super::notify();
+                TRUE
+            }
+            _ => FALSE,
+        }
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        if unsafe { SetConsoleCtrlHandler(Some(handler), TRUE) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::thread;
+
+    use crate::thread_names;
+
+    // the write end of the pipe to the signal thread, -1 before the install
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn errno() -> *mut libc::c_int {
+        libc::__errno_location()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    unsafe fn errno() -> *mut libc::c_int {
+        libc::__error()
+    }
+
+    // only wakes the signal thread, a write is async signal safe
+    extern "C" fn on_signal(_: libc::c_int) {
+        let fd = PIPE.load(Ordering::Relaxed);
+        if fd < 0 {
+            return;
+        }
+        unsafe {
+            let saved = *errno();
+            let b = 1u8;
+            // a full pipe has a wakeup pending already
+            libc::write(fd, &b as *const u8 as *const libc::c_void, 1);
+            *errno() = saved;
+        }
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        for fd in fds.iter() {
+            unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        unsafe { libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) };
+        let mut rx = unsafe { File::from_raw_fd(fds[0]) };
+        PIPE.store(fds[1], Ordering::Release);
+
+        thread::Builder::new()
+            .name(thread_names::name("signal"))
+            .spawn(move || {
+                let mut buf = [0u8; 64];
+                // the signals that come in together are merged into one
+                while let Ok(n) = rx.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    super::notify();
+                }
+            })?;
+
+        let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+        sa.sa_sigaction = on_signal as libc::sighandler_t;
+        sa.sa_flags = libc::SA_RESTART;
+        unsafe { libc::sigemptyset(&mut sa.sa_mask) };
+        if unsafe { libc::sigaction(libc::SIGINT, &sa, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::io;
+
+    pub(super) fn install() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "ctrl-c is not supported on this platform",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    // send a ctrl-c to the process
+    #[cfg(unix)]
+    fn raise() {
+        unsafe { libc::raise(libc::SIGINT) };
+    }
+
+    // a console ctrl-c goes to every process of the console, e.g. the test
+    // runner, so the handler is called the way the system calls it
+    #[cfg(windows)]
+    fn raise() {
+        let _ = unsafe { imp::handler(winapi::um::wincon::CTRL_C_EVENT) };
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn ctrl_c_wakes_all() {
+        let woken = Arc::new(AtomicUsize::new(0));
+        let hs: Vec<_> = (0..4)
+            .map(|_| {
+                let woken = woken.clone();
+                co!(move || {
+                    ctrl_c().unwrap();
+                    woken.fetch_add(1, Ordering::Relaxed);
+                })
+            })
+            .collect();
+        // the waiters are parked, nothing wakes them but the ctrl-c
+        let start = Instant::now();
+        while EVENTS.0.lock().unwrap().waiting < 4 && start.elapsed() < Duration::from_secs(5) {
+            thread::yield_now();
+        }
+        assert_eq!(woken.load(Ordering::Relaxed), 0);
+        raise();
+        for h in hs {
+            h.join().unwrap();
+        }
+        assert_eq!(woken.load(Ordering::Relaxed), 4);
+    }
+}
@@ -3,8 +3,11 @@ use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
-use crate::coroutine_impl::{co_cancel_data, is_coroutine, CoroutineImpl, EventSource};
+use crate::coroutine_impl::{
+    assert_not_scheduler, co_cancel_data, is_coroutine, CoroutineImpl, EventSource,
+};
 use crate::scheduler::get_scheduler;
+use crate::std::context::{Context, ContextError};
 use crate::yield_now::{get_co_para, yield_with};
 
 struct Sleep {
@@ -29,8 +32,12 @@ impl EventSource for Sleep {
 }
 
 /// block the current coroutine until timeout
+///
+/// it's `thread::sleep` in a thread context, and panics in the scheduler
+/// context of a worker, see `coroutine::context()`
 pub fn sleep(dur: Duration) {
     if !is_coroutine() {
+        assert_not_scheduler("coroutine::sleep");
         return thread::sleep(dur);
     }
 
@@ -39,3 +46,19 @@ pub fn sleep(dur: Duration) {
     // consume the timeout error
     get_co_para();
 }
+
+/// block the current coroutine until timeout or the context is done, return
+/// the error if the context is done first
+pub fn sleep_ctx(ctx: &Context, dur: Duration) -> Result<(), ContextError> {
+    ctx.guard(false, || match ctx.remaining() {
+        Some(left) if left < dur => {
+            sleep(left);
+            Err(ContextError::DeadlineExceeded)
+        }
+        _ => {
+            sleep(dur);
+            Ok(())
+        }
+    })
+    .and_then(|r| r)
+}
@@ -0,0 +1,231 @@
+//! the spans of the request tracing, see `Builder::with_span` and `go_traced!`
+//!
+//! a span names a piece of work, it has an id, the id of its trace that is
+//! the id of the root span, the parent span and the `Context` of the work. a
+//! coroutine that is spawned with a span runs in it: `Span::current` returns
+//! it, the coroutine is named from the span and the time from the spawn to
+//! the first run of the body is recorded in the span. the spans are not
+//! exported anywhere, a hook or the end of a request reads their fields and
+//! hands them to the tracing of the application
+//!
+//! a span keeps its parents alive, so the spans of a request live as long as
+//! the last one of them
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::local::with_local_value;
+use crate::std::context::Context;
+
+/// the field that `Builder::with_span` records the spawn latency in
+pub const SPAWN_LATENCY: &str = "spawn_latency";
+
+// the ids start at 1
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+// no spawn latency is recorded
+const NO_LATENCY: u64 = u64::MAX;
+
+struct Inner {
+    id: u64,
+    trace_id: u64,
+    name: String,
+    parent: Option<Span>,
+    start: Instant,
+    fields: Mutex<Vec<(&'static str, String)>>,
+    latency: AtomicU64,
+}
+
+/// a named piece of work in a trace, it's cheap to clone and the clones are
+/// the same span
+#[derive(Clone)]
+pub struct Span {
+    inner: Arc<Inner>,
+    ctx: Context,
+}
+
+impl Span {
+    fn new(name: &str, parent: Option<&Span>) -> Span {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let inner = Inner {
+            id,
+            trace_id: parent.map_or(id, |p| p.trace_id()),
+            name: name.to_owned(),
+            parent: parent.cloned(),
+            start: Instant::now(),
+            fields: Mutex::new(Vec::new()),
+            latency: AtomicU64::new(NO_LATENCY),
+        };
+        Span {
+            inner: Arc::new(inner),
+            ctx: parent.map_or_else(Context::background, |p| p.ctx.clone()),
+        }
+    }
+
+    /// the root span of a new trace, with the background context
+    pub fn root(name: &str) -> Span {
+        Span::new(name, None)
+    }
+
+    /// a child span in the same trace, with the context of this one
+    pub fn child(&self, name: &str) -> Span {
+        Span::new(name, Some(self))
+    }
+
+    /// the same span with the context, the children that are created after
+    /// it inherit the context
+    pub fn with_context(mut self, ctx: Context) -> Span {
+        self.ctx = ctx;
+        self
+    }
+
+    /// the span of the current coroutine, `None` in a thread context or when
+    /// the coroutine is not spawned with a span
+    pub fn current() -> Option<Span> {
+        with_local_value(|s: &Span| s.clone())
+    }
+
+    /// the id of the span, unique in the process
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// the id of the root span
+    pub fn trace_id(&self) -> u64 {
+        self.inner.trace_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// the parent span, `None` for a root span
+    pub fn parent(&self) -> Option<&Span> {
+        self.inner.parent.as_ref()
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// the time since the span is created
+    pub fn elapsed(&self) -> Duration {
+        self.inner.start.elapsed()
+    }
+
+    /// set the field, the old value of the key is replaced
+    pub fn record<V: ToString>(&self, key: &'static str, value: V) {
+        let value = value.to_string();
+        let mut fields = self.inner.fields.lock();
+        match fields.iter_mut().find(|(k, _)| *k == key) {
+            Some(f) => f.1 = value,
+            None => fields.push((key, value)),
+        }
+    }
+
+    /// the value of the field
+    pub fn field(&self, key: &str) -> Option<String> {
+        let fields = self.inner.fields.lock();
+        fields.iter().find(|(k, _)| *k == key).map(|f| f.1.clone())
+    }
+
+    /// the fields in the order that they are first recorded
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        self.inner.fields.lock().clone()
+    }
+
+    /// the time from the spawn of the coroutine of the span to the first run
+    /// of its body, it's also recorded in the `SPAWN_LATENCY` field
+    pub fn spawn_latency(&self) -> Option<Duration> {
+        match self.inner.latency.load(Ordering::Relaxed) {
+            NO_LATENCY => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    pub(crate) fn record_spawn_latency(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(NO_LATENCY as u128 - 1) as u64;
+        self.inner.latency.store(nanos, Ordering::Relaxed);
+        self.record(SPAWN_LATENCY, format!("{:?}", latency));
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Span")
+            .field("id", &self.id())
+            .field("trace_id", &self.trace_id())
+            .field("name", &self.name())
+            .field("parent", &self.parent().map(|p| p.id()))
+            .field("fields", &*self.inner.fields.lock())
+            .finish()
+    }
+}
+
+/// what `go_traced!` spawns a child span of
+pub trait TraceParent {
+    /// the span of the new coroutine, it's named `name` or after the parent
+    fn child_span(&self, name: Option<&str>) -> Span;
+}
+
+/// a child of the span
+impl TraceParent for Span {
+    fn child_span(&self, name: Option<&str>) -> Span {
+        self.child(name.unwrap_or_else(|| self.name()))
+    }
+}
+
+/// a child of the span of the current coroutine with the context, or a root
+/// span named `co` with the context if there is no current span
+impl TraceParent for Context {
+    fn child_span(&self, name: Option<&str>) -> Span {
+        let span = match Span::current() {
+            Some(s) => s.child(name.unwrap_or_else(|| s.name())),
+            None => Span::root(name.unwrap_or("co")),
+        };
+        span.with_context(self.clone())
+    }
+}
+
+impl<T: TraceParent + ?Sized> TraceParent for &T {
+    fn child_span(&self, name: Option<&str>) -> Span {
+        (**self).child_span(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine_impl::{current, Builder};
+
+    #[test]
+    fn span_of_coroutine() {
+        let root = Span::root("root");
+        root.record("user", 7);
+        root.record("user", 8);
+        assert_eq!(root.fields(), [("user", "8".to_owned())]);
+        assert!(Span::current().is_none());
+
+        let span = root.child("named");
+        let h = Builder::new()
+            .name("mine".to_owned())
+            .with_local(|| Span::current().map(|s| s.id()))
+            .with_span(span.clone())
+            .spawn(|| {
+                let seen = with_local_value(|s: &Option<u64>| *s).unwrap();
+                (current().name().map(str::to_owned), seen)
+            });
+        // the builder name wins, the other inits see the span
+        assert_eq!(
+            h.join().unwrap(),
+            (Some("mine".to_owned()), Some(span.id()))
+        );
+        assert_eq!(span.parent().unwrap().id(), root.id());
+        assert_eq!(span.trace_id(), root.id());
+        assert!(span.field(SPAWN_LATENCY).is_some());
+        assert!(root.spawn_latency().is_none());
+    }
+}
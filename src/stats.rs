@@ -0,0 +1,135 @@
+//! `mco` runtime statistics
+//!
+//! the counters are updated by the runtime with relaxed atomics
+//! use [`stats`] to get a snapshot of them
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::blocking_zone;
+use crate::maintenance;
+use crate::scheduler::default_scheduler_started;
+use crate::thread_names;
+use crate::watchdog::{BLOCKED, OVERRUNS, RESCUED, STALLS};
+
+// running coroutines with a growable stack
+pub(crate) static GROWABLE_STACKS: AtomicUsize = AtomicUsize::new(0);
+// address space reserved by the running growable stacks, in bytes
+pub(crate) static GROWABLE_STACK_RESERVED: AtomicUsize = AtomicUsize::new(0);
+// the max committed size of finished growable stacks, in bytes
+pub(crate) static GROWABLE_STACK_COMMITTED: AtomicUsize = AtomicUsize::new(0);
+// successful steals from other workers or the global queue
+pub(crate) static STEALS: AtomicUsize = AtomicUsize::new(0);
+// coroutines that run from the lifo slot
+pub(crate) static LIFO_HITS: AtomicUsize = AtomicUsize::new(0);
+// coroutines pushed to the global queue because the local queue is full
+pub(crate) static LOCAL_OVERFLOWS: AtomicUsize = AtomicUsize::new(0);
+// worker threads that panicked, of all the runtimes
+pub(crate) static WORKER_PANICS: AtomicUsize = AtomicUsize::new(0);
+// live select coroutines of the cqueues
+pub(crate) static SELECTORS: AtomicUsize = AtomicUsize::new(0);
+// lock holders raised to the priority of a waiter
+pub(crate) static PRIORITY_BOOSTS: AtomicUsize = AtomicUsize::new(0);
+
+/// a snapshot of the runtime statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    /// number of running coroutines with a growable stack
+    pub growable_stacks: usize,
+    /// address space reserved by the running growable stacks, in bytes
+    pub growable_stack_reserved: usize,
+    /// the max committed memory of a growable stack, in bytes
+    /// it's sampled when the coroutine finishes
+    pub growable_stack_committed: usize,
+    /// successful steals from other workers or the global queue
+    pub steals: usize,
+    /// coroutines that run from the lifo slot of the workers
+    pub lifo_hits: usize,
+    /// coroutines pushed to the global queue because the local queue is full
+    pub local_overflows: usize,
+    /// the active workers of the default runtime, 0 if it's not started
+    pub active_workers: usize,
+    /// the workers of the default runtime that are still handing their
+    /// coroutines over after a shrink
+    pub draining_workers: usize,
+    /// the worker threads that panicked, of all the runtimes
+    pub worker_panics: usize,
+    /// the live select coroutines of `select!` and the cqueues
+    pub selectors: usize,
+    /// the times a `Mutex` holder is raised to the priority of a waiter,
+    /// see `Builder::priority`
+    pub priority_boosts: usize,
+    /// the times the watchdog flagged a coroutine as overrunning the time
+    /// slice, see `config().set_time_slice()`
+    pub overruns: usize,
+    /// the stalls of the workers that the watchdog found, see
+    /// `config().set_stall_threshold()`
+    pub stalls: usize,
+    /// the coroutines moved off the stalled workers
+    pub stall_rescued: usize,
+    /// the resumes that held their worker over
+    /// `config().set_blocking_threshold()`
+    pub blocking_resumes: usize,
+    /// the runs of the maintenance callbacks, see
+    /// `runtime::register_maintenance`
+    pub maintenance_runs: usize,
+    /// the maintenance callbacks that panicked
+    pub maintenance_panics: usize,
+    /// the total time that the maintenance callbacks have run for
+    pub maintenance_time: Duration,
+    /// the calls of `coroutine::blocking_zone`
+    pub blocking_zones: usize,
+    /// the blocking zones over `config().set_slow_blocking_zone()`
+    pub slow_blocking_zones: usize,
+    /// the total time of the blocking zones
+    pub blocking_zone_time: Duration,
+    /// the temporary workers that the blocking zones started
+    pub blocking_zone_workers: usize,
+}
+
+/// get a snapshot of the runtime statistics
+pub fn stats() -> Stats {
+    let (active_workers, draining_workers) =
+        default_scheduler_started().map_or((0, 0), |s| s.worker_counts());
+    Stats {
+        growable_stacks: GROWABLE_STACKS.load(Ordering::Relaxed),
+        growable_stack_reserved: GROWABLE_STACK_RESERVED.load(Ordering::Relaxed),
+        growable_stack_committed: GROWABLE_STACK_COMMITTED.load(Ordering::Relaxed),
+        steals: STEALS.load(Ordering::Relaxed),
+        lifo_hits: LIFO_HITS.load(Ordering::Relaxed),
+        local_overflows: LOCAL_OVERFLOWS.load(Ordering::Relaxed),
+        active_workers,
+        draining_workers,
+        worker_panics: WORKER_PANICS.load(Ordering::Relaxed),
+        selectors: SELECTORS.load(Ordering::Relaxed),
+        priority_boosts: PRIORITY_BOOSTS.load(Ordering::Relaxed),
+        overruns: OVERRUNS.load(Ordering::Relaxed),
+        stalls: STALLS.load(Ordering::Relaxed),
+        stall_rescued: RESCUED.load(Ordering::Relaxed),
+        blocking_resumes: BLOCKED.load(Ordering::Relaxed),
+        maintenance_runs: maintenance::RUNS.load(Ordering::Relaxed),
+        maintenance_panics: maintenance::PANICS.load(Ordering::Relaxed),
+        maintenance_time: Duration::from_nanos(maintenance::NANOS.load(Ordering::Relaxed)),
+        blocking_zones: blocking_zone::ZONES.load(Ordering::Relaxed),
+        slow_blocking_zones: blocking_zone::SLOW_ZONES.load(Ordering::Relaxed),
+        blocking_zone_time: Duration::from_nanos(blocking_zone::NANOS.load(Ordering::Relaxed)),
+        blocking_zone_workers: blocking_zone::EXTRA_WORKERS.load(Ordering::Relaxed),
+    }
+}
+
+/// the names of the timer thread and the active worker threads of the
+/// default runtime, so that the dashboards match `top -H` and perf. it's
+/// empty if the default runtime is not started
+///
+/// the names are made with the prefix of `config().set_thread_name_prefix`
+/// at the call, all the runtimes name their threads the same way
+pub fn thread_names() -> Vec<String> {
+    let workers = match default_scheduler_started() {
+        Some(s) => s.worker_counts().0,
+        None => return Vec::new(),
+    };
+    let mut names = Vec::with_capacity(workers + 1);
+    names.push(thread_names::name("timer"));
+    names.extend((0..workers).map(thread_names::worker_name));
+    names
+}
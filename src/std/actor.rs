@@ -0,0 +1,320 @@
+//! actor style mailboxes
+//!
+//! an actor is a coroutine that owns its state and handles the messages of
+//! a bounded mailbox one by one. the `Addr` is the handle to send messages,
+//! `call` is a request/response built on the oneshot channel
+//! for example:
+//! ```
+//! use mco::std::actor;
+//! use mco::std::sync::oneshot;
+//!
+//! enum Msg {
+//!     Add(u64),
+//!     Get(oneshot::Sender<u64>),
+//! }
+//!
+//! let addr = actor::spawn(0u64, |sum: &mut u64, msg| match msg {
+//!     Msg::Add(n) => *sum += n,
+//!     Msg::Get(tx) => {
+//!         let _ = tx.send(*sum);
+//!     }
+//! });
+//! addr.send(Msg::Add(1)).unwrap();
+//! addr.send(Msg::Add(2)).unwrap();
+//! assert_eq!(addr.call(Msg::Get).unwrap(), 3);
+//! ```
+
+use std::any::type_name;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, SendError};
+use std::sync::Arc;
+
+use crate::coroutine_impl::Builder;
+use crate::std::sync::{oneshot, with_fairness, Fairness, Receiver, Sender, SyncFlag};
+
+/// the default mailbox capacity of `spawn`
+pub const MAILBOX_CAPACITY: usize = 64;
+
+const RUNNING: usize = 0;
+const DRAIN: usize = 1;
+const DISCARD: usize = 2;
+
+/// what to do with the messages left in the mailbox when the actor is stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopPolicy {
+    /// handle the messages already in the mailbox, then exit
+    Drain,
+    /// exit after the current message, the messages left are dropped
+    /// so the blocked `call`s return an error
+    Discard,
+}
+
+enum Envelope<M> {
+    Msg(M),
+    // wake up the actor that blocked on an empty mailbox
+    Stop,
+}
+
+struct Shared {
+    // RUNNING, DRAIN or DISCARD
+    stopping: AtomicUsize,
+    // fired when the actor coroutine exits
+    done: SyncFlag,
+}
+
+// fire the done flag even if the handler panics
+struct DoneGuard(Arc<Shared>);
+
+impl Drop for DoneGuard {
+    fn drop(&mut self) {
+        self.0.done.fire();
+    }
+}
+
+/// spawn an actor that owns `state`, the messages are handled by `handler`
+/// the mailbox holds `MAILBOX_CAPACITY` messages, `send` blocks when it's full
+///
+/// the actor coroutine is named after the state type, it exits when it's stopped
+/// or all the `Addr`s are dropped
+pub fn spawn<S, M, F>(state: S, handler: F) -> Addr<M>
+where
+    S: Send + 'static,
+    M: Send + 'static,
+    F: FnMut(&mut S, M) + Send + 'static,
+{
+    spawn_with(state, MAILBOX_CAPACITY, handler)
+}
+
+/// the same as `spawn` with the given mailbox capacity
+pub fn spawn_with<S, M, F>(state: S, capacity: usize, handler: F) -> Addr<M>
+where
+    S: Send + 'static,
+    M: Send + 'static,
+    F: FnMut(&mut S, M) + Send + 'static,
+{
+    // fifo hands the messages over in order and returns the message to
+    // the blocked senders when the actor is gone
+    let (tx, rx) = with_fairness(capacity.max(1), Fairness::Fifo);
+    let shared = Arc::new(Shared {
+        stopping: AtomicUsize::new(RUNNING),
+        done: SyncFlag::new(),
+    });
+    let guard = DoneGuard(shared.clone());
+    Builder::new()
+        .name(type_name::<S>().to_owned())
+        .spawn(move || run(state, rx, handler, guard));
+    Addr { tx, shared }
+}
+
+fn run<S, M, F>(mut state: S, rx: Receiver<Envelope<M>>, mut handler: F, guard: DoneGuard)
+where
+    F: FnMut(&mut S, M),
+{
+    let shared = &guard.0;
+    loop {
+        match shared.stopping.load(Ordering::Acquire) {
+            DISCARD => break,
+            DRAIN => {
+                while let Ok(env) = rx.try_recv() {
+                    if let Envelope::Msg(m) = env {
+                        handler(&mut state, m);
+                    }
+                }
+                break;
+            }
+            _ => {}
+        }
+        match rx.recv() {
+            Ok(Envelope::Msg(m)) => handler(&mut state, m),
+            // check the policy again
+            Ok(Envelope::Stop) => {}
+            // all the addrs are dropped
+            Err(_) => break,
+        }
+    }
+    // the messages left are dropped with the mailbox
+    drop(rx);
+}
+
+/// the address of an actor
+pub struct Addr<M> {
+    tx: Sender<Envelope<M>>,
+    shared: Arc<Shared>,
+}
+
+impl<M> Addr<M> {
+    /// send a message to the actor, block if the mailbox is full
+    /// return the message if the actor is stopped
+    pub fn send(&self, msg: M) -> Result<(), SendError<M>> {
+        if self.is_stopped() {
+            return Err(SendError(msg));
+        }
+        self.tx.send(Envelope::Msg(msg)).map_err(unwrap_msg)
+    }
+
+    /// send a message without blocking, return the message if the mailbox is full
+    /// or the actor is stopped
+    pub fn try_send(&self, msg: M) -> Result<(), SendError<M>> {
+        if self.is_stopped() {
+            return Err(SendError(msg));
+        }
+        self.tx.try_send(Envelope::Msg(msg)).map_err(unwrap_msg)
+    }
+
+    /// send a request built from the reply sender and wait for the reply
+    ///
+    /// return an error if the actor stops before it replies, or the
+    /// handler drops the reply sender
+    pub fn call<R, F>(&self, f: F) -> Result<R, RecvError>
+    where
+        F: FnOnce(oneshot::Sender<R>) -> M,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.send(f(tx)).map_err(|_| RecvError)?;
+        rx.recv()
+    }
+
+    /// stop the actor with the policy, it doesn't wait for the actor to exit
+    ///
+    /// the new messages are rejected after this call, only the first stop takes effect
+    pub fn stop(&self, policy: StopPolicy) {
+        let to = match policy {
+            StopPolicy::Drain => DRAIN,
+            StopPolicy::Discard => DISCARD,
+        };
+        let stopped =
+            self.shared
+                .stopping
+                .compare_exchange(RUNNING, to, Ordering::AcqRel, Ordering::Acquire);
+        if stopped.is_ok() {
+            // a full mailbox means the actor is busy, it will see the flag
+            let _ = self.tx.try_send(Envelope::Stop);
+        }
+    }
+
+    /// whether the actor is stopped or exited
+    pub fn is_stopped(&self) -> bool {
+        self.shared.stopping.load(Ordering::Acquire) != RUNNING || self.shared.done.is_fired()
+    }
+
+    /// wait for the actor coroutine to exit
+    pub fn wait(&self) {
+        self.shared.done.wait();
+    }
+}
+
+fn unwrap_msg<M>(e: SendError<Envelope<M>>) -> SendError<M> {
+    match e.0 {
+        Envelope::Msg(m) => SendError(m),
+        Envelope::Stop => unreachable!("stop is never returned to the sender"),
+    }
+}
+
+impl<M> Clone for Addr<M> {
+    fn clone(&self) -> Self {
+        Addr {
+            tx: self.tx.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<M> fmt::Debug for Addr<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Addr")
+            .field("stopped", &self.is_stopped())
+            .field("remain", &self.tx.remain())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::{current, sleep};
+    use std::time::Duration;
+
+    enum Msg {
+        Add(u64),
+        Get(oneshot::Sender<u64>),
+        Sleep(Duration),
+    }
+
+    fn counter() -> Addr<Msg> {
+        spawn_with(0u64, 4, |sum: &mut u64, msg| match msg {
+            Msg::Add(n) => *sum += n,
+            Msg::Get(tx) => {
+                let _ = tx.send(*sum);
+            }
+            Msg::Sleep(d) => sleep(d),
+        })
+    }
+
+    #[test]
+    fn send_and_call() {
+        let addr = counter();
+        let handles: Vec<_> = (1..=10)
+            .map(|i| {
+                let addr = addr.clone();
+                co!(move || addr.send(Msg::Add(i)).unwrap())
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(addr.call(Msg::Get).unwrap(), 55);
+        drop(addr);
+    }
+
+    #[test]
+    fn stop_drain() {
+        let addr = counter();
+        addr.send(Msg::Sleep(Duration::from_millis(50))).unwrap();
+        addr.send(Msg::Add(1)).unwrap();
+        addr.send(Msg::Add(2)).unwrap();
+        let (tx, rx) = oneshot::channel();
+        addr.send(Msg::Get(tx)).unwrap();
+        addr.stop(StopPolicy::Drain);
+        assert!(addr.is_stopped());
+        assert!(addr.send(Msg::Add(3)).is_err());
+        // the queued messages are still handled
+        assert_eq!(rx.recv().unwrap(), 3);
+        addr.wait();
+    }
+
+    #[test]
+    fn stop_discard_blocked_callers() {
+        let addr = counter();
+        addr.send(Msg::Sleep(Duration::from_millis(100))).unwrap();
+        // more callers than the mailbox capacity, some block in send
+        let callers: Vec<_> = (0..10)
+            .map(|_| {
+                let addr = addr.clone();
+                co!(move || addr.call(Msg::Get))
+            })
+            .collect();
+        sleep(Duration::from_millis(20));
+        addr.stop(StopPolicy::Discard);
+        for c in callers {
+            assert_eq!(c.join().unwrap(), Err(RecvError));
+        }
+        addr.wait();
+    }
+
+    #[test]
+    fn exit_with_addrs() {
+        let (done, exited) = oneshot::channel::<()>();
+        let addr = spawn(
+            done,
+            |_: &mut oneshot::Sender<()>, tx: oneshot::Sender<String>| {
+                let _ = tx.send(current().name().unwrap_or_default().to_owned());
+            },
+        );
+        let name = addr.call(|tx| tx).unwrap();
+        assert!(name.ends_with("oneshot::Sender<()>"));
+        // the state is dropped when the actor exits
+        drop(addr);
+        assert!(exited.recv().is_err());
+    }
+}
@@ -1,6 +1,11 @@
 use crate::std::errors::Result;
 use crate::std::sync::channel;
+use crate::thread_names;
 use std::panic::set_hook;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// the numbers of the names of the blocking threads
+static BLOCKING_SEQ: AtomicUsize = AtomicUsize::new(0);
 
 /// will spawn a thread to doing and return value by channel
 /// for example:
@@ -38,7 +43,9 @@ where
     T: Send + 'static,
 {
     let (s, r) = channel::<Result<T>>();
-    std::thread::Builder::new().spawn(move || {
+    let seq = BLOCKING_SEQ.fetch_add(1, Ordering::Relaxed);
+    let name = thread_names::name(&format!("blocking-{}", seq));
+    std::thread::Builder::new().name(name).spawn(move || {
         let send_e = s.clone();
         set_hook(Box::new(move |panic_info| {
             let e = err!(
@@ -0,0 +1,734 @@
+//! the reference counted byte buffers
+//!
+//! `Bytes` is an immutable slice of a shared buffer, cloning and slicing it
+//! only bump the reference count, so a message can be sent to many coroutines
+//! without a copy, e.g. by a `std::sync::broadcast` channel. `BytesMut` is the unique writable part of a buffer, it's
+//! filled by the reads and then frozen into `Bytes`. the parts split from one
+//! `BytesMut` share the same allocation, which is freed when all of them are
+//! dropped
+//! for example:
+//! ```
+//! use mco::std::bytes::BytesMut;
+//!
+//! let mut buf = BytesMut::with_capacity(64);
+//! buf.extend_from_slice(b"hello world");
+//! let hello = buf.split_to(5).freeze();
+//! let other = hello.clone();
+//! assert_eq!(hello, b"hello"[..]);
+//! assert_eq!(other, hello);
+//! assert_eq!(buf, b" world"[..]);
+//! ```
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, IoSlice, Read, Write};
+use std::mem::{ManuallyDrop, MaybeUninit};
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+use std::ptr::{self, NonNull};
+use std::slice;
+use std::sync::Arc;
+
+// the read size when a `BytesMut` is full
+const MIN_READ: usize = 4 * 1024;
+// the most spare bytes that are zeroed for one read
+const MAX_READ: usize = 64 * 1024;
+// the max buffers of one vectored write
+const MAX_IOV: usize = 64;
+
+// the allocation shared by the parts
+struct Shared {
+    ptr: *mut u8,
+    cap: usize,
+}
+
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe { drop(Vec::from_raw_parts(self.ptr, 0, self.cap)) };
+    }
+}
+
+/// a cheap to clone immutable byte slice over a shared buffer
+#[derive(Clone)]
+pub struct Bytes {
+    ptr: *const u8,
+    len: usize,
+    // none for the empty bytes
+    shared: Option<Arc<Shared>>,
+}
+
+// the bytes of a shared buffer are never written after they are frozen
+unsafe impl Send for Bytes {}
+unsafe impl Sync for Bytes {}
+
+impl Bytes {
+    /// the empty bytes, it doesn't allocate
+    pub const fn new() -> Self {
+        Bytes {
+            ptr: NonNull::dangling().as_ptr(),
+            len: 0,
+            shared: None,
+        }
+    }
+
+    /// copy the slice into a new buffer
+    pub fn copy_from_slice(data: &[u8]) -> Self {
+        Bytes::from(data.to_vec())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// the bytes of the range, it shares the buffer
+    ///
+    /// panics if the range is out of bounds
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Bytes {
+        let (begin, end) = bounds(range, self.len);
+        Bytes {
+            ptr: unsafe { self.ptr.add(begin) },
+            len: end - begin,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// split off `[0, at)` and return it, `self` is left with `[at, len)`
+    ///
+    /// panics if `at > len`
+    pub fn split_to(&mut self, at: usize) -> Bytes {
+        assert!(
+            at <= self.len,
+            "split_to out of bounds: {} > {}",
+            at,
+            self.len
+        );
+        let head = Bytes {
+            ptr: self.ptr,
+            len: at,
+            shared: self.shared.clone(),
+        };
+        self.ptr = unsafe { self.ptr.add(at) };
+        self.len -= at;
+        head
+    }
+
+    /// split off `[at, len)` and return it, `self` is left with `[0, at)`
+    ///
+    /// panics if `at > len`
+    pub fn split_off(&mut self, at: usize) -> Bytes {
+        assert!(
+            at <= self.len,
+            "split_off out of bounds: {} > {}",
+            at,
+            self.len
+        );
+        let tail = Bytes {
+            ptr: unsafe { self.ptr.add(at) },
+            len: self.len - at,
+            shared: self.shared.clone(),
+        };
+        self.len = at;
+        tail
+    }
+
+    /// keep the first `len` bytes, nothing is done if it's already shorter
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+impl Default for Bytes {
+    fn default() -> Self {
+        Bytes::new()
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for Bytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Borrow<[u8]> for Bytes {
+    fn borrow(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(v: Vec<u8>) -> Self {
+        BytesMut::from(v).freeze()
+    }
+}
+
+impl From<String> for Bytes {
+    fn from(s: String) -> Self {
+        Bytes::from(s.into_bytes())
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(s: &[u8]) -> Self {
+        Bytes::copy_from_slice(s)
+    }
+}
+
+impl From<&str> for Bytes {
+    fn from(s: &str) -> Self {
+        Bytes::copy_from_slice(s.as_bytes())
+    }
+}
+
+impl From<BytesMut> for Bytes {
+    fn from(b: BytesMut) -> Self {
+        b.freeze()
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Bytes) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl Eq for Bytes {}
+
+impl PartialEq<[u8]> for Bytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        self[..] == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for Bytes {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl PartialOrd for Bytes {
+    fn partial_cmp(&self, other: &Bytes) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Bytes {
+    fn cmp(&self, other: &Bytes) -> Ordering {
+        self[..].cmp(&other[..])
+    }
+}
+
+impl Hash for Bytes {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self[..].hash(state)
+    }
+}
+
+impl fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_bytes(self, f)
+    }
+}
+
+/// the unique writable part of a buffer
+///
+/// the bytes in `[len, capacity)` are not initialized, they are filled by
+/// `extend_from_slice`, the `Write` impl or the reads of the streams, e.g.
+/// [`TcpStream::read_buf`]
+///
+/// [`TcpStream::read_buf`]: ../../net/struct.TcpStream.html#method.read_buf
+pub struct BytesMut {
+    ptr: *mut u8,
+    len: usize,
+    cap: usize,
+    // none for the buffer that never allocated
+    shared: Option<Arc<Shared>>,
+}
+
+// the parts of a shared buffer never overlap
+unsafe impl Send for BytesMut {}
+unsafe impl Sync for BytesMut {}
+
+impl BytesMut {
+    /// the empty buffer, it doesn't allocate
+    pub const fn new() -> Self {
+        BytesMut {
+            ptr: NonNull::dangling().as_ptr(),
+            len: 0,
+            cap: 0,
+            shared: None,
+        }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        BytesMut::from(Vec::with_capacity(cap))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// make room for at least `additional` more bytes
+    ///
+    /// the whole buffer is reused if the parts split from it are all dropped,
+    /// otherwise the bytes are moved to a new buffer
+    pub fn reserve(&mut self, additional: usize) {
+        if self.cap - self.len >= additional {
+            return;
+        }
+        let need = self.len.checked_add(additional).expect("capacity overflow");
+        if let Some(shared) = self.shared.as_mut().and_then(Arc::get_mut) {
+            if shared.cap >= need {
+                unsafe { ptr::copy(self.ptr, shared.ptr, self.len) };
+                self.ptr = shared.ptr;
+                self.cap = shared.cap;
+                return;
+            }
+        }
+        let mut v = Vec::with_capacity(need.max(self.cap * 2));
+        v.extend_from_slice(self);
+        *self = BytesMut::from(v);
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) {
+        self.reserve(data.len());
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(self.len), data.len());
+        }
+        self.len += data.len();
+    }
+
+    /// keep the first `len` bytes, the capacity is not changed
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// split off `[0, at)` and return it, `self` is left with the rest of the
+    /// bytes and the capacity
+    ///
+    /// panics if `at > len`
+    pub fn split_to(&mut self, at: usize) -> BytesMut {
+        assert!(
+            at <= self.len,
+            "split_to out of bounds: {} > {}",
+            at,
+            self.len
+        );
+        let head = BytesMut {
+            ptr: self.ptr,
+            len: at,
+            cap: at,
+            shared: self.shared.clone(),
+        };
+        self.ptr = unsafe { self.ptr.add(at) };
+        self.len -= at;
+        self.cap -= at;
+        head
+    }
+
+    /// split off `[at, len)` and the rest of the capacity and return it,
+    /// `self` is left with `[0, at)`
+    ///
+    /// panics if `at > len`
+    pub fn split_off(&mut self, at: usize) -> BytesMut {
+        assert!(
+            at <= self.len,
+            "split_off out of bounds: {} > {}",
+            at,
+            self.len
+        );
+        let tail = BytesMut {
+            ptr: unsafe { self.ptr.add(at) },
+            len: self.len - at,
+            cap: self.cap - at,
+            shared: self.shared.clone(),
+        };
+        self.len = at;
+        self.cap = at;
+        tail
+    }
+
+    /// split off all the bytes, `self` is left with the spare capacity
+    pub fn split(&mut self) -> BytesMut {
+        self.split_to(self.len)
+    }
+
+    /// convert to the immutable bytes without a copy
+    pub fn freeze(self) -> Bytes {
+        let me = ManuallyDrop::new(self);
+        Bytes {
+            ptr: me.ptr,
+            len: me.len,
+            shared: unsafe { ptr::read(&me.shared) },
+        }
+    }
+
+    /// read from `r` into the spare capacity and extend the length, return
+    /// the bytes read. at least 4KB is reserved if the buffer is full
+    ///
+    /// a `Read` may look at the slice it's lent, so at most 64KB of the
+    /// spare bytes are zeroed and read into at a time, the reads of
+    /// [`TcpStream::read_buf`] skip the zeroing
+    ///
+    /// [`TcpStream::read_buf`]: ../../net/struct.TcpStream.html#method.read_buf
+    pub fn read_from<R: Read + ?Sized>(&mut self, r: &mut R) -> io::Result<usize> {
+        read_uninit(self, |spare| {
+            let len = spare.len().min(MAX_READ);
+            let spare = unsafe {
+                ptr::write_bytes(spare.as_mut_ptr(), 0, len);
+                slice::from_raw_parts_mut(spare.as_mut_ptr() as *mut u8, len)
+            };
+            r.read(spare)
+        })
+    }
+
+    /// the uninitialized bytes in `[len, capacity)`
+    pub fn spare_capacity_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        unsafe {
+            slice::from_raw_parts_mut(
+                self.ptr.add(self.len) as *mut MaybeUninit<u8>,
+                self.cap - self.len,
+            )
+        }
+    }
+
+    /// set the length of the bytes
+    ///
+    /// # Safety
+    ///
+    /// `len` must not be greater than the capacity, and the bytes before it
+    /// must be initialized
+    pub unsafe fn set_len(&mut self, len: usize) {
+        debug_assert!(len <= self.cap);
+        self.len = len;
+    }
+}
+
+impl Default for BytesMut {
+    fn default() -> Self {
+        BytesMut::new()
+    }
+}
+
+impl Clone for BytesMut {
+    fn clone(&self) -> Self {
+        BytesMut::from(&self[..])
+    }
+}
+
+impl Deref for BytesMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for BytesMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for BytesMut {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for BytesMut {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for BytesMut {
+    fn from(v: Vec<u8>) -> Self {
+        let mut v = ManuallyDrop::new(v);
+        let (ptr, len, cap) = (v.as_mut_ptr(), v.len(), v.capacity());
+        BytesMut {
+            ptr,
+            len,
+            cap,
+            shared: Some(Arc::new(Shared { ptr, cap })),
+        }
+    }
+}
+
+impl From<&[u8]> for BytesMut {
+    fn from(s: &[u8]) -> Self {
+        BytesMut::from(s.to_vec())
+    }
+}
+
+impl PartialEq for BytesMut {
+    fn eq(&self, other: &BytesMut) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl Eq for BytesMut {}
+
+impl PartialEq<[u8]> for BytesMut {
+    fn eq(&self, other: &[u8]) -> bool {
+        self[..] == *other
+    }
+}
+
+impl fmt::Debug for BytesMut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_bytes(self, f)
+    }
+}
+
+impl Write for BytesMut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// the begin and end of the range, panics if it's out of bounds
+fn bounds(range: impl RangeBounds<usize>, len: usize) -> (usize, usize) {
+    let begin = match range.start_bound() {
+        Bound::Included(&n) => n,
+        Bound::Excluded(&n) => n + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&n) => n + 1,
+        Bound::Excluded(&n) => n,
+        Bound::Unbounded => len,
+    };
+    assert!(begin <= end, "range start {} > end {}", begin, end);
+    assert!(end <= len, "range end out of bounds: {} > {}", end, len);
+    (begin, end)
+}
+
+// the same as the byte string literals, e.g. b"a\n"
+fn fmt_bytes(b: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("b\"")?;
+    for &c in b {
+        for e in std::ascii::escape_default(c) {
+            fmt::Write::write_char(f, e as char)?;
+        }
+    }
+    f.write_str("\"")
+}
+
+/// read into the spare capacity of the buffer, at least 4KB is reserved if
+/// it's full. `read` gets the uninitialized spare bytes, it must write the
+/// first ones of the count that it returns
+pub(crate) fn read_uninit<F>(buf: &mut BytesMut, read: F) -> io::Result<usize>
+where
+    F: FnOnce(&mut [MaybeUninit<u8>]) -> io::Result<usize>,
+{
+    if buf.len() == buf.capacity() {
+        buf.reserve(MIN_READ);
+    }
+    let spare = buf.spare_capacity_mut();
+    let len = spare.len();
+    let n = read(spare)?;
+    // a bad count would make the bytes past the spare ones readable
+    assert!(n <= len, "read {} bytes into a buffer of {}", n, len);
+    unsafe { buf.set_len(buf.len() + n) };
+    Ok(n)
+}
+
+/// write all the buffers by the vectored writes, at most 64 buffers at a time
+pub(crate) fn write_all_bytes<W: Write>(w: &mut W, bufs: &[Bytes]) -> io::Result<()> {
+    // the first buffer that is not fully written and the written bytes of it
+    let (mut i, mut off) = (0, 0);
+    let mut iov = [IoSlice::new(&[]); MAX_IOV];
+    while i < bufs.len() {
+        let mut n = 0;
+        for (k, b) in bufs[i..].iter().take(MAX_IOV).enumerate() {
+            let b = if k == 0 { &b[off..] } else { &b[..] };
+            if !b.is_empty() {
+                iov[n] = IoSlice::new(b);
+                n += 1;
+            }
+        }
+        if n == 0 {
+            return Ok(());
+        }
+        let mut written = match w.write_vectored(&iov[..n]) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        while i < bufs.len() && written >= bufs[i].len() - off {
+            written -= bufs[i].len() - off;
+            i += 1;
+            off = 0;
+        }
+        off += written;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_parts() {
+        let mut buf = BytesMut::with_capacity(16);
+        buf.extend_from_slice(b"abcdefgh");
+        let ptr = buf.as_ptr();
+        let mut head = buf.split_to(3);
+        head[0] = b'x';
+        assert_eq!(head, b"xbc"[..]);
+        assert_eq!(buf, b"defgh"[..]);
+        assert_eq!(buf.capacity(), 13);
+
+        let b = head.freeze();
+        let c = b.clone();
+        // no copy, they share the allocation
+        assert_eq!(c.as_ptr(), ptr);
+        assert_eq!(b.slice(1..), b"bc"[..]);
+        assert_eq!(b.slice(..=0), b"x"[..]);
+
+        let mut all = Bytes::from("hello world");
+        let hello = all.split_to(5);
+        let world = all.split_off(1);
+        assert_eq!(hello, b"hello"[..]);
+        assert_eq!(all, b" "[..]);
+        assert_eq!(world, b"world"[..]);
+        assert_eq!(format!("{:?}", Bytes::from("a\"\n")), r#"b"a\"\n""#);
+        assert!(Bytes::new().is_empty());
+    }
+
+    #[test]
+    fn reserve_reuses_buffer() {
+        let mut buf = BytesMut::with_capacity(8);
+        buf.extend_from_slice(b"abcdef");
+        let ptr = buf.as_ptr();
+        let head = buf.split_to(4);
+        // the head is alive, the bytes move to a new buffer
+        buf.reserve(4);
+        assert_ne!(buf.as_ptr(), ptr);
+        assert_eq!(buf, b"ef"[..]);
+        drop(head);
+
+        let mut buf = BytesMut::with_capacity(8);
+        buf.extend_from_slice(b"abcdef");
+        drop(buf.split_to(4));
+        // the head is gone, the whole buffer is reused
+        buf.reserve(4);
+        assert_eq!(buf.as_ptr(), ptr_of(&buf));
+        assert_eq!(buf.capacity(), 8);
+        assert_eq!(buf, b"ef"[..]);
+
+        let mut empty = BytesMut::new();
+        empty.extend_from_slice(b"x");
+        assert_eq!(empty, b"x"[..]);
+    }
+
+    fn ptr_of(b: &BytesMut) -> *const u8 {
+        b.shared.as_ref().unwrap().ptr
+    }
+
+    #[test]
+    fn vectored_write() {
+        // writes at most 3 bytes at a time
+        struct Slow(Vec<u8>);
+        impl Write for Slow {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..n]);
+                Ok(n)
+            }
+
+            fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+                let mut n = 0;
+                for b in bufs {
+                    n += self.write(&b[..b.len().min(3 - n)])?;
+                    if n == 3 {
+                        break;
+                    }
+                }
+                Ok(n)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let bufs: Vec<Bytes> = (0..100)
+            .map(|i| Bytes::from(vec![i as u8; i % 5]))
+            .collect();
+        let mut w = Slow(Vec::new());
+        write_all_bytes(&mut w, &bufs).unwrap();
+        let expect: Vec<u8> = bufs.iter().flat_map(|b| b.iter().copied()).collect();
+        assert_eq!(w.0, expect);
+    }
+
+    #[test]
+    fn read_into_spare() {
+        let mut buf = BytesMut::new();
+        let mut r = &b"hello"[..];
+        assert_eq!(buf.read_from(&mut r).unwrap(), 5);
+        assert_eq!(buf, b"hello"[..]);
+        assert!(buf.capacity() >= MIN_READ);
+        assert_eq!(buf.read_from(&mut r).unwrap(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "into a buffer of")]
+    fn read_count_too_large() {
+        // a faulty `Read` that claims more than it's lent
+        struct Liar;
+
+        impl Read for Liar {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                Ok(buf.len() + 1)
+            }
+        }
+
+        let mut buf = BytesMut::new();
+        let _ = buf.read_from(&mut Liar);
+    }
+}
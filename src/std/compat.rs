@@ -0,0 +1,69 @@
+//! the std calls that block a worker and what to call in a coroutine instead
+//!
+//! a coroutine that blocks its thread holds the worker, the coroutines queued
+//! on it wait until the call returns. the debug builds log such a resume with
+//! the name of the coroutine, see `config().set_blocking_threshold()`
+//!
+//! | std                               | in a coroutine                      |
+//! |-----------------------------------|-------------------------------------|
+//! | `std::thread::sleep`              | `mco::coroutine::sleep`             |
+//! | `std::thread::yield_now`          | `mco::coroutine::yield_now`         |
+//! | `std::sync::Mutex`                | `mco::std::sync::Mutex`             |
+//! | `std::sync::RwLock`               | `mco::std::sync::RwLock`            |
+//! | `std::sync::Condvar`              | `mco::std::sync::Condvar`           |
+//! | `std::sync::mpsc`                 | `mco::std::sync::channel`           |
+//! | `std::net`                        | `mco::net`                          |
+//! | a blocking ffi or file call       | `mco::coroutine::blocking_zone`     |
+//!
+//! the replacements also work in a thread context, `use mco::prelude::*`
+//! brings the common ones in. clippy can reject the std ones in the crates
+//! that run on the workers, with its `disallowed-methods` and
+//! `disallowed-types` lints in a `clippy.toml`:
+//!
+//! ```toml
+//! disallowed-methods = [
+//!     { path = "std::thread::sleep", reason = "use mco::coroutine::sleep" },
+//!     { path = "std::thread::yield_now", reason = "use mco::coroutine::yield_now" },
+//! ]
+//! disallowed-types = [
+//!     { path = "std::sync::Mutex", reason = "use mco::std::sync::Mutex" },
+//!     { path = "std::sync::RwLock", reason = "use mco::std::sync::RwLock" },
+//!     { path = "std::sync::Condvar", reason = "use mco::std::sync::Condvar" },
+//! ]
+//! ```
+//!
+//! the shadows below have the signatures of the std ones, so a `use` swaps
+//! them in without touching the calls
+
+use crate::blocking_zone::in_zone;
+use crate::coroutine_impl::is_coroutine;
+
+pub use crate::sleep::sleep;
+pub use crate::yield_now::yield_now;
+
+/// assert that a blocking call here doesn't hold a worker, i.e. the caller
+/// is a thread or a coroutine in a `coroutine::blocking_zone`
+///
+/// put it in front of the blocking calls of a library that may be called
+/// from the coroutines. it panics only in the debug builds, in the release
+/// builds it does nothing
+///
+/// ```
+/// use mco::coroutine;
+/// use mco::std::compat::assert_not_blocking_zone;
+///
+/// fn read_config() -> String {
+///     assert_not_blocking_zone();
+///     std::fs::read_to_string("/dev/null").unwrap()
+/// }
+///
+/// read_config();
+/// let h = mco::co!(|| coroutine::blocking_zone(read_config));
+/// assert_eq!(h.join().unwrap(), "");
+/// ```
+#[inline]
+pub fn assert_not_blocking_zone() {
+    if cfg!(debug_assertions) && is_coroutine() && !in_zone() {
+        panic!("a blocking call in a coroutine, wrap it in coroutine::blocking_zone");
+    }
+}
@@ -1,89 +1,515 @@
-use std::any::Any;
-use std::cell::UnsafeCell;
-use std::collections::HashMap;
-use std::hash::Hash;
-use std::sync::Arc;
-use std::sync::atomic::Ordering;
-use crate::std::time::time::Time;
-use crate::std::errors::{Error, Result};
-use crate::std::lazy::sync::Lazy;
-use crate::std::map::SyncHashMap;
-use crate::std::sync::{AtomicOption, Mutex, Receiver, Sender};
-
-/// A Context carries a deadline, a cancellation signal, and other values across
-/// API boundaries.
-///
-/// Context's methods may be called by multiple goroutines simultaneously.
-pub trait Context {
-    fn deadline(&self) -> (Time, bool);
-    fn done(&self) -> &Receiver<()>;
-    fn err(&self) -> Option<Error>;
+//! the context carries a deadline and a cancellation signal across the api
+//! boundaries, like the `context` package of go
+//!
+//! a context is derived from its parent by `with_cancel`, `with_deadline` or
+//! `with_timeout`, canceling a context cancels all the contexts derived from
+//! it. the blocking calls of the crate take the context by their `_ctx`
+//! variants, e.g. `Receiver::recv_ctx` and `TcpStream::read_ctx`, and any
+//! other blocking call is covered by `Context::wrap`. they return early with
+//! the `ContextError` when the context is done
+//!
+//! the deadline is passed to the calls as their timeout, the cancellation
+//! links the blocked coroutine into the context by a node on the stack of the
+//! call and wakes it by the cancellation checkpoints of the coroutine. the
+//! calls that don't take a timeout share one deadline of the context in the
+//! timer wheel of the crate. in a thread context the context is only checked
+//! before the call
+//! for example:
+//! ```
+//! use std::time::Duration;
+//! use mco::std::context::{Context, ContextError};
+//!
+//! let (_tx, rx) = mco::chan!();
+//! let ctx = Context::background().with_cancel();
+//! let c = ctx.clone();
+//! let h = mco::co!(move || rx.recv_ctx(&c).map(|()| ()));
+//! mco::coroutine::sleep(Duration::from_millis(10));
+//! ctx.cancel();
+//! assert!(h.join().unwrap().is_err());
+//! assert_eq!(ctx.err(), Some(ContextError::Canceled));
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::{current, current_cancel_data, is_coroutine, Coroutine};
+use crate::std::time::deadline_queue::{due_at, due_cancel, Due, DueKey};
+use crate::timeout_list::now_instant;
+
+/// the reason that a context is done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContextError {
+    /// the context or one of its parents is canceled
+    Canceled,
+    /// the deadline of the context is passed
+    DeadlineExceeded,
+}
+
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContextError::Canceled => f.write_str("context canceled"),
+            ContextError::DeadlineExceeded => f.write_str("context deadline exceeded"),
+        }
+    }
+}
+
+impl Error for ContextError {}
+
+/// `TimedOut` for the deadline and `Other` for the cancellation, the
+/// `ContextError` is the inner error
+impl From<ContextError> for io::Error {
+    fn from(e: ContextError) -> Self {
+        let kind = match e {
+            ContextError::Canceled => io::ErrorKind::Other,
+            ContextError::DeadlineExceeded => io::ErrorKind::TimedOut,
+        };
+        io::Error::new(kind, e)
+    }
 }
 
-pub trait Canceler {
-    fn cancel(&self, err: Option<Error>);
-    fn done(&self) -> &Receiver<()>;
+// the values of `Inner::err`
+const OK: usize = 0;
+const CANCELED: usize = 1;
+const DEADLINE: usize = 2;
+
+fn to_err(v: usize) -> Option<ContextError> {
+    match v {
+        CANCELED => Some(ContextError::Canceled),
+        DEADLINE => Some(ContextError::DeadlineExceeded),
+        _ => None,
+    }
 }
 
-/// CLOSE_CHAN is a reusable closed channel.
-static CLOSE_RECV: Lazy<Receiver<()>> = Lazy::new(|| {
-    let (s, r) = chan!();
-    r
-});
-
-pub struct CancelCtx {
-    context: Option<Box<dyn Context>>,
-    send: Sender<()>,
-    done: AtomicOption<Receiver<()>>,
-    children: SyncHashMap<String, Box<dyn Canceler>>,
-    err: AtomicOption<Error>,
+// a call that is blocked with the context, it's on the stack of the call
+// and linked while the call runs
+struct Waiter {
+    co: Coroutine,
+    prev: *mut Waiter,
+    next: *mut Waiter,
+    linked: bool,
 }
 
-unsafe impl Send for CancelCtx {}
+struct State {
+    // the list of the blocked calls
+    waiters: *mut Waiter,
+    children: Vec<Weak<Inner>>,
+    // the deadline is in the timer wheel, for the calls that don't take a
+    // timeout
+    timer: bool,
+}
 
-unsafe impl Sync for CancelCtx {}
+// the waiters are only touched under the lock
+unsafe impl Send for State {}
 
-impl CancelCtx {
-    pub fn new_arc(parent: Option<Box<dyn Context>>) -> Arc<Self> {
-        Arc::new(Self::new(parent))
+impl State {
+    fn link(&mut self, w: &mut Waiter) {
+        w.prev = ptr::null_mut();
+        w.next = self.waiters;
+        if let Some(next) = unsafe { w.next.as_mut() } {
+            next.prev = w;
+        }
+        w.linked = true;
+        self.waiters = w;
     }
 
-    pub fn new(parent: Option<Box<dyn Context>>) -> Self {
-        let (s, r) = chan!();
-        CancelCtx {
-            context: parent,
-            send: s,
-            done: AtomicOption::some(r),
-            children: SyncHashMap::new(),
-            err: AtomicOption::none(),
+    fn unlink(&mut self, w: &mut Waiter) {
+        match unsafe { w.prev.as_mut() } {
+            Some(prev) => prev.next = w.next,
+            None => self.waiters = w.next,
+        }
+        if let Some(next) = unsafe { w.next.as_mut() } {
+            next.prev = w.prev;
         }
+        w.linked = false;
     }
 }
 
-impl Canceler for CancelCtx {
-    fn cancel(&self, err: Option<Error>) {
-        if err.is_none() {
-            panic!("context: internal error: missing cancel error")
+struct Inner {
+    // keep the parents alive, they cancel the children
+    _parent: Option<Arc<Inner>>,
+    // the earliest deadline of this context and its parents
+    deadline: Option<Instant>,
+    err: AtomicUsize,
+    state: Mutex<State>,
+}
+
+impl Inner {
+    fn new(parent: Option<Arc<Inner>>, deadline: Option<Instant>) -> Arc<Inner> {
+        let deadline = match (parent.as_ref().and_then(|p| p.deadline), deadline) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let inner = Arc::new(Inner {
+            _parent: parent.clone(),
+            deadline,
+            err: AtomicUsize::new(OK),
+            state: Mutex::new(State {
+                waiters: ptr::null_mut(),
+                children: Vec::new(),
+                timer: false,
+            }),
+        });
+        if let Some(parent) = parent {
+            let mut state = parent.state.lock();
+            match parent.err.load(Ordering::Acquire) {
+                OK => {
+                    let children = &mut state.children;
+                    // drop the dead children before the vec grows
+                    if children.len() == children.capacity() {
+                        children.retain(|c| c.strong_count() > 0);
+                    }
+                    children.push(Arc::downgrade(&inner));
+                }
+                err => inner.err.store(err, Ordering::Release),
+            }
+        }
+        inner
+    }
+
+    fn err(&self) -> Option<ContextError> {
+        match to_err(self.err.load(Ordering::Acquire)) {
+            None => match self.deadline {
+                Some(d) if now_instant() >= d => Some(ContextError::DeadlineExceeded),
+                _ => None,
+            },
+            err => err,
+        }
+    }
+
+    // mark the context done and wake all the blocked calls
+    fn fire(self: &Arc<Self>, err: ContextError) {
+        let v = match err {
+            ContextError::Canceled => CANCELED,
+            ContextError::DeadlineExceeded => DEADLINE,
+        };
+        let (children, timer) = {
+            let mut state = self.state.lock();
+            if self
+                .err
+                .compare_exchange(OK, v, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+            {
+                return;
+            }
+            while let Some(w) = unsafe { state.waiters.as_mut() } {
+                state.unlink(w);
+                w.co.cancel();
+            }
+            (
+                std::mem::take(&mut state.children),
+                std::mem::replace(&mut state.timer, false),
+            )
+        };
+        if timer {
+            due_cancel(&DueKey::new(self));
+        }
+        for child in children {
+            if let Some(child) = child.upgrade() {
+                child.fire(err);
+            }
+        }
+    }
+
+    // link the call into the context, return false if the context is done
+    fn register(self: &Arc<Self>, w: &mut Waiter, timer: bool) -> bool {
+        let mut state = self.state.lock();
+        if self.err.load(Ordering::Acquire) != OK {
+            return false;
+        }
+        state.link(w);
+        if timer && !state.timer {
+            if let Some(deadline) = self.deadline {
+                state.timer = true;
+                due_at(DueKey::new(self), deadline);
+            }
+        }
+        true
+    }
+
+    // return false if the context has fired on the call
+    fn deregister(&self, w: &mut Waiter) -> bool {
+        let mut state = self.state.lock();
+        if !w.linked {
+            return false;
+        }
+        state.unlink(w);
+        true
+    }
+}
+
+impl Due for Inner {
+    fn due(self: Arc<Self>) {
+        self.fire(ContextError::DeadlineExceeded);
+    }
+}
+
+/// a deadline and a cancellation signal, it's cheap to clone and the clones
+/// are the same context
+#[derive(Clone, Default)]
+pub struct Context {
+    // none for the background context
+    inner: Option<Arc<Inner>>,
+}
+
+impl Context {
+    /// the context that is never done, it's the root of the others
+    pub fn background() -> Context {
+        Context { inner: None }
+    }
+
+    /// a child context that is done when `cancel` is called on it or when
+    /// the parent is done
+    pub fn with_cancel(&self) -> Context {
+        Context {
+            inner: Some(Inner::new(self.inner.clone(), None)),
         }
-        if self.err.is_some() {
-            return;// already canceled
+    }
+
+    /// a child context that is also done at the deadline, the deadline of
+    /// the parent is kept if it's earlier
+    pub fn with_deadline(&self, deadline: Instant) -> Context {
+        Context {
+            inner: Some(Inner::new(self.inner.clone(), Some(deadline))),
         }
-        self.err.swap(err.clone().unwrap());
-        if let Some(v) = self.done.get() {
-            let _=self.send.send(());
-        } else {
-            self.done.swap(CLOSE_RECV.clone());
+    }
+
+    /// a child context that is also done after the timeout
+    pub fn with_timeout(&self, timeout: Duration) -> Context {
+        self.with_deadline(now_instant() + timeout)
+    }
+
+    /// cancel the context and the ones derived from it, the blocked calls
+    /// with them return early. nothing is done for the background context
+    pub fn cancel(&self) {
+        if let Some(inner) = self.inner.as_ref() {
+            inner.fire(ContextError::Canceled);
         }
-        for (_, mut v) in self.children.iter_mut() {
-            v.cancel(err.clone());
+    }
+
+    /// the deadline of the context
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.as_ref().and_then(|i| i.deadline)
+    }
+
+    /// the time left before the deadline, zero if it's passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline()
+            .map(|d| d.saturating_duration_since(now_instant()))
+    }
+
+    // the shorter one of the timeout and the time left
+    pub(crate) fn timeout(&self, timeout: Option<Duration>) -> Option<Duration> {
+        match (timeout, self.remaining()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
         }
-        self.children.clear();
     }
 
-    fn done(&self) -> &Receiver<()> {
-        self.done.get().unwrap()
+    /// why the context is done, none if it's not done
+    pub fn err(&self) -> Option<ContextError> {
+        self.inner.as_ref().and_then(|i| i.err())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.err().is_some()
+    }
+
+    /// run the blocking call with the context, it returns early with the
+    /// `ContextError` as the io error when the context is done
+    ///
+    /// ```no_run
+    /// use std::io::Read;
+    /// use std::time::Duration;
+    /// use mco::net::TcpStream;
+    /// use mco::std::context::Context;
+    ///
+    /// let ctx = Context::background().with_timeout(Duration::from_secs(1));
+    /// let mut s = TcpStream::connect("127.0.0.1:8080").unwrap();
+    /// let mut buf = [0; 1024];
+    /// let n = ctx.wrap(|| s.read(&mut buf)).unwrap();
+    /// ```
+    pub fn wrap<T, F>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce() -> io::Result<T>,
+    {
+        match self.guard(true, f) {
+            Ok(ret) => ret,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// run `f` with the cancellation of the context, `f` applies the deadline
+    /// by itself unless `timer` is true
+    pub(crate) fn guard<T, F>(&self, timer: bool, f: F) -> Result<T, ContextError>
+    where
+        F: FnOnce() -> T,
+    {
+        let inner = match self.inner.as_ref() {
+            None => return Ok(f()),
+            Some(inner) => inner,
+        };
+        if let Some(e) = inner.err() {
+            return Err(e);
+        }
+        if !is_coroutine() {
+            return Ok(f());
+        }
+        let mut w = Waiter {
+            co: current(),
+            prev: ptr::null_mut(),
+            next: ptr::null_mut(),
+            linked: false,
+        };
+        if !inner.register(&mut w, timer) {
+            return Err(inner.err().unwrap_or(ContextError::Canceled));
+        }
+        let ret = panic::catch_unwind(AssertUnwindSafe(f));
+        // the node is not moved while it's linked
+        if inner.deregister(&mut w) {
+            return Ok(ret.unwrap_or_else(|p| panic::resume_unwind(p)));
+        }
+        // the context has canceled the coroutine, the cancel is for this call only
+        current_cancel_data().clear_cancel_bit();
+        match ret {
+            Ok(t) => Ok(t),
+            Err(p) if is_cancel_panic(&*p) => Err(inner.err().unwrap_or(ContextError::Canceled)),
+            Err(p) => panic::resume_unwind(p),
+        }
     }
 }
 
+pub(crate) fn is_cancel_panic(p: &(dyn std::any::Any + Send)) -> bool {
+    matches!(
+        p.downcast_ref::<mco_gen::Error>(),
+        Some(mco_gen::Error::Cancel)
+    )
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("deadline", &self.deadline())
+            .field("err", &self.err())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::sleep;
+
+    #[test]
+    fn cancel_children() {
+        let parent = Context::background().with_cancel();
+        let child = parent.with_timeout(Duration::from_secs(10));
+        let grand = child.with_cancel();
+        assert_eq!(grand.err(), None);
+        // a child canceled alone doesn't affect the parent
+        grand.cancel();
+        assert_eq!(grand.err(), Some(ContextError::Canceled));
+        assert_eq!(child.err(), None);
+
+        let grand = child.with_cancel();
+        parent.cancel();
+        assert_eq!(child.err(), Some(ContextError::Canceled));
+        assert_eq!(grand.err(), Some(ContextError::Canceled));
+        // derived from a done context
+        assert!(parent.with_cancel().is_done());
+        assert_eq!(Context::background().err(), None);
+    }
 
-pub struct TimerCtx {}
\ No newline at end of file
+    #[test]
+    fn deadline() {
+        let parent = Context::background().with_timeout(Duration::from_millis(50));
+        let child = parent.with_timeout(Duration::from_secs(10));
+        assert_eq!(child.deadline(), parent.deadline());
+
+        let (_tx, rx) = crate::chan!();
+        let c = child.clone();
+        let h = co!(move || {
+            let start = Instant::now();
+            let ret: Result<(), _> = rx.recv_ctx(&c);
+            (start.elapsed(), ret)
+        });
+        let (elapsed, ret) = h.join().unwrap();
+        assert!(matches!(
+            ret,
+            Err(crate::std::sync::channel::RecvCtxError::Done(
+                ContextError::DeadlineExceeded
+            ))
+        ));
+        assert!(elapsed >= Duration::from_millis(40));
+        assert_eq!(child.err(), Some(ContextError::DeadlineExceeded));
+    }
+
+    #[test]
+    fn cancel_unlinks_all_waiters() {
+        let ctx = Context::background().with_timeout(Duration::from_secs(10));
+        let hs: Vec<_> = (0..3)
+            .map(|_| {
+                let c = ctx.clone();
+                co!(move || c.wrap(|| {
+                    crate::coroutine::park();
+                    Ok(())
+                }))
+            })
+            .collect();
+        let inner = ctx.inner.as_ref().unwrap();
+        let linked = || {
+            let state = inner.state.lock();
+            let (mut n, mut w) = (0, state.waiters);
+            while let Some(node) = unsafe { w.as_ref() } {
+                n += 1;
+                w = node.next;
+            }
+            n
+        };
+        while linked() < 3 {
+            sleep(Duration::from_millis(1));
+        }
+        // the calls without a timeout share the one deadline of the context
+        assert!(inner.state.lock().timer);
+        ctx.cancel();
+        for h in hs {
+            assert_eq!(h.join().unwrap().unwrap_err().kind(), io::ErrorKind::Other);
+        }
+        let state = inner.state.lock();
+        assert!(state.waiters.is_null());
+        assert!(!state.timer);
+    }
+
+    #[test]
+    fn wrap_and_sleep() {
+        let ctx = Context::background().with_timeout(Duration::from_millis(20));
+        let c = ctx.clone();
+        let h = co!(move || {
+            // the park has no timeout, the deadline is fired by the timer
+            let e = c.wrap(|| {
+                crate::coroutine::park();
+                Ok(())
+            });
+            assert_eq!(e.unwrap_err().kind(), io::ErrorKind::TimedOut);
+            // the coroutine is not canceled after the call
+            sleep(Duration::from_millis(1));
+            crate::coroutine::sleep_ctx(&c, Duration::from_secs(1))
+        });
+        assert_eq!(h.join().unwrap(), Err(ContextError::DeadlineExceeded));
+
+        let ctx = Context::background().with_cancel();
+        let c = ctx.clone();
+        let h = co!(move || crate::coroutine::sleep_ctx(&c, Duration::from_secs(10)));
+        sleep(Duration::from_millis(10));
+        ctx.cancel();
+        assert_eq!(h.join().unwrap(), Err(ContextError::Canceled));
+    }
+}
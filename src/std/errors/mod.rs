@@ -106,12 +106,14 @@ impl From<&Box<dyn std::error::Error>> for Error {
     }
 }
 
+#[cfg(feature = "time-format")]
 impl From<time::error::InvalidFormatDescription> for Error {
     fn from(arg: time::error::InvalidFormatDescription) -> Self {
         return new(arg.to_string());
     }
 }
 
+#[cfg(feature = "time-format")]
 impl From<time::error::Parse> for Error {
     fn from(arg: time::error::Parse) -> Self {
         return new(arg.to_string());
@@ -1,3 +1,4 @@
+pub mod actor;
 pub mod queue;
 #[macro_use]
 pub mod sync;
@@ -10,8 +11,12 @@ pub mod time;
 #[macro_use]
 pub mod map;
 pub mod blocking;
+pub mod bytes;
+pub mod compat;
+pub mod context;
 pub mod lazy;
 pub mod pool;
+pub mod rand;
 pub mod strings;
 pub mod vec;
 pub mod wrapper;
@@ -4,6 +4,10 @@ use crate::std::sync::{Receiver, Sender};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+mod worker_pool;
+
+pub use self::worker_pool::{Drain, PoolStats, WorkerPool};
+
 pub struct Task {
     pub f: Box<dyn Fn() -> Result<(), Error>>,
 }
@@ -0,0 +1,438 @@
+//! a fixed set of worker coroutines fed by a bounded job queue
+//!
+//! `submit` blocks while the queue is full, so the producers slow down to
+//! the pace of the workers. `shutdown` stops the intake, finishes or drops
+//! the queued jobs and waits for the workers, the ones that are still busy
+//! at the timeout are canceled. a handler that panics doesn't take a worker
+//! away, the worker is replaced by a new one and the panic is counted
+//!
+//! ```
+//! use mco::std::pool::{Drain, WorkerPool};
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let sum = Arc::new(AtomicUsize::new(0));
+//! let s = sum.clone();
+//! let pool = WorkerPool::new(4, 16, move |n: usize| {
+//!     s.fetch_add(n, Ordering::Relaxed);
+//! });
+//! for n in 1..=100 {
+//!     pool.submit(n).unwrap();
+//! }
+//! assert!(pool.shutdown(Drain::Finish, Duration::from_secs(10)));
+//! assert_eq!(sum.load(Ordering::Relaxed), 5050);
+//! assert_eq!(pool.stats().completed, 100);
+//! assert!(pool.submit(1).is_err());
+//! ```
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::SendError;
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::Builder;
+use crate::join::JoinHandle;
+use crate::std::context::is_cancel_panic;
+use crate::std::sync::{bounded, Receiver, Sender, SyncFlag};
+
+/// what `WorkerPool::shutdown` does with the jobs that are still queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drain {
+    /// the workers run the queued jobs, then exit
+    Finish,
+    /// the queued jobs are dropped, the workers exit after their current job
+    Discard,
+}
+
+/// the counters of a `WorkerPool`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// the jobs that are accepted by `submit` and `try_submit`
+    pub submitted: u64,
+    /// the jobs whose handler returned
+    pub completed: u64,
+    /// the jobs whose handler panicked, each one replaced its worker
+    pub panicked: u64,
+    /// the jobs that are dropped by `Drain::Discard`
+    pub discarded: u64,
+    /// the jobs that are queued and not taken by a worker yet
+    pub queued: usize,
+    /// the live workers
+    pub workers: usize,
+}
+
+enum Job<J> {
+    Run(J),
+    // wake an idle worker to check if it's one too many
+    Poison,
+}
+
+struct Shared<J> {
+    rx: Receiver<Job<J>>,
+    handler: Box<dyn Fn(J) + Send + Sync>,
+    // the workers that `resize` asks for
+    target: AtomicUsize,
+    live: AtomicUsize,
+    stopping: AtomicBool,
+    discard: AtomicBool,
+    // fired when the last worker exits after the shutdown
+    exited: SyncFlag,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    discarded: AtomicU64,
+    queued: AtomicUsize,
+}
+
+impl<J: Send + 'static> Shared<J> {
+    // spawn a worker for a slot that is already counted in `live`
+    fn spawn_worker(self: &Arc<Self>) {
+        let shared = self.clone();
+        let h = Builder::new()
+            .name("pool_worker".to_owned())
+            .spawn(move || work(shared));
+        let mut handles = self.handles.lock();
+        handles.retain(|h| !h.is_done());
+        handles.push(h);
+    }
+
+    // spawn the workers up to the target
+    fn grow(self: &Arc<Self>) {
+        let mut n = self.live.load(Ordering::SeqCst);
+        while n < self.target.load(Ordering::SeqCst) {
+            match self
+                .live
+                .compare_exchange_weak(n, n + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    self.spawn_worker();
+                    n += 1;
+                }
+                Err(cur) => n = cur,
+            }
+        }
+    }
+
+    // give up the slot of a worker if there are more than the target, true
+    // if the worker should exit
+    fn retire(&self) -> bool {
+        let mut n = self.live.load(Ordering::SeqCst);
+        while n > self.target.load(Ordering::SeqCst) {
+            match self
+                .live
+                .compare_exchange_weak(n, n - 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    self.left(n - 1);
+                    return true;
+                }
+                Err(cur) => n = cur,
+            }
+        }
+        false
+    }
+
+    // a worker gave up its slot
+    fn left(&self, live: usize) {
+        if live == 0 && self.stopping.load(Ordering::SeqCst) {
+            self.exited.fire();
+        }
+    }
+}
+
+// give up the slot when the worker exits, also by the cancel panic
+struct SlotGuard<J: Send + 'static> {
+    shared: Arc<Shared<J>>,
+    // the slot is already given up or taken over
+    released: bool,
+}
+
+impl<J: Send + 'static> Drop for SlotGuard<J> {
+    fn drop(&mut self) {
+        if !self.released {
+            let live = self.shared.live.fetch_sub(1, Ordering::SeqCst) - 1;
+            self.shared.left(live);
+        }
+    }
+}
+
+fn work<J: Send + 'static>(shared: Arc<Shared<J>>) {
+    let mut guard = SlotGuard {
+        shared,
+        released: false,
+    };
+    let shared = guard.shared.clone();
+    loop {
+        let job = match shared.rx.recv() {
+            Ok(Job::Run(job)) => job,
+            Ok(Job::Poison) => {
+                if shared.retire() {
+                    guard.released = true;
+                    return;
+                }
+                continue;
+            }
+            // closed by the shutdown and drained
+            Err(_) => return,
+        };
+        shared.queued.fetch_sub(1, Ordering::SeqCst);
+        if shared.discard.load(Ordering::SeqCst) {
+            shared.discarded.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        match panic::catch_unwind(AssertUnwindSafe(|| (shared.handler)(job))) {
+            Ok(()) => {
+                shared.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(p) if is_cancel_panic(&*p) => panic::resume_unwind(p),
+            Err(_) => {
+                shared.panicked.fetch_add(1, Ordering::Relaxed);
+                error!("a pool job panicked, its worker is replaced");
+                // the new worker takes over the slot
+                guard.released = true;
+                shared.spawn_worker();
+                return;
+            }
+        }
+        if shared.retire() {
+            guard.released = true;
+            return;
+        }
+    }
+}
+
+/// a pool of `workers` coroutines that run `handler` for each job of a
+/// bounded queue
+///
+/// the pool is shared by reference, e.g. in an `Arc`. dropping it closes the
+/// queue, the workers finish the queued jobs and exit without being waited
+pub struct WorkerPool<J: Send + 'static> {
+    tx: Sender<Job<J>>,
+    shared: Arc<Shared<J>>,
+}
+
+impl<J: Send + 'static> WorkerPool<J> {
+    /// start `workers` coroutines that take the jobs of a queue of
+    /// `queue_cap` jobs, at least 1
+    pub fn new<F>(workers: usize, queue_cap: usize, handler: F) -> Self
+    where
+        F: Fn(J) + Send + Sync + 'static,
+    {
+        let (tx, rx) = bounded(queue_cap.max(1));
+        let shared = Arc::new(Shared {
+            rx,
+            handler: Box::new(handler),
+            target: AtomicUsize::new(workers),
+            live: AtomicUsize::new(0),
+            stopping: AtomicBool::new(false),
+            discard: AtomicBool::new(false),
+            exited: SyncFlag::new(),
+            handles: Mutex::new(Vec::with_capacity(workers)),
+            submitted: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+        });
+        shared.grow();
+        WorkerPool { tx, shared }
+    }
+
+    /// queue the job, block while the queue is full. the job is given back
+    /// after the shutdown
+    pub fn submit(&self, job: J) -> Result<(), SendError<J>> {
+        self.queue(job, |tx, job| tx.send(job))
+    }
+
+    /// queue the job without blocking, the job is given back if the queue is
+    /// full or after the shutdown
+    pub fn try_submit(&self, job: J) -> Result<(), SendError<J>> {
+        self.queue(job, |tx, job| tx.try_send(job))
+    }
+
+    fn queue<F>(&self, job: J, send: F) -> Result<(), SendError<J>>
+    where
+        F: FnOnce(&Sender<Job<J>>, Job<J>) -> Result<(), SendError<Job<J>>>,
+    {
+        if self.shared.stopping.load(Ordering::SeqCst) {
+            return Err(SendError(job));
+        }
+        // counted before a worker may take it
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
+        match send(&self.tx, Job::Run(job)) {
+            Ok(()) => {
+                self.shared.submitted.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(SendError(job)) => {
+                self.shared.queued.fetch_sub(1, Ordering::SeqCst);
+                match job {
+                    Job::Run(job) => Err(SendError(job)),
+                    Job::Poison => unreachable!("poison is never submitted"),
+                }
+            }
+        }
+    }
+
+    /// the jobs that are queued and not taken by a worker yet
+    pub fn len(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the live workers
+    pub fn workers(&self) -> usize {
+        self.shared.live.load(Ordering::SeqCst)
+    }
+
+    /// grow or shrink the pool to `workers` coroutines, it does nothing
+    /// after the shutdown
+    ///
+    /// the new workers are spawned at once. the idle workers beyond the
+    /// number are woken to exit, the busy ones exit after their current job
+    pub fn resize(&self, workers: usize) {
+        if self.shared.stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        self.shared.target.store(workers, Ordering::SeqCst);
+        self.shared.grow();
+        let extra = self.workers().saturating_sub(workers);
+        for _ in 0..extra {
+            // a full queue has no idle worker to wake
+            if self.tx.try_send(Job::Poison).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// stop the intake and wait at most `timeout` for the workers to exit,
+    /// return false if some are still running by then, they are canceled
+    ///
+    /// only the first call drains the queue, the later ones only wait
+    pub fn shutdown(&self, drain: Drain, timeout: Duration) -> bool {
+        let shared = &self.shared;
+        if !shared.stopping.swap(true, Ordering::SeqCst) {
+            if drain == Drain::Discard {
+                shared.discard.store(true, Ordering::SeqCst);
+            }
+            // the workers get the disconnect once the queue is drained
+            self.tx.close();
+            if drain == Drain::Discard {
+                while let Ok(job) = shared.rx.try_recv() {
+                    if let Job::Run(job) = job {
+                        shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        shared.discarded.fetch_add(1, Ordering::Relaxed);
+                        drop(job);
+                    }
+                }
+            }
+            shared.left(shared.live.load(Ordering::SeqCst));
+        }
+        if shared.exited.wait_timeout(timeout) {
+            return true;
+        }
+        for h in shared.handles.lock().iter() {
+            if !h.is_done() {
+                h.coroutine().cancel();
+            }
+        }
+        false
+    }
+
+    /// return true after `shutdown`
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.stopping.load(Ordering::SeqCst)
+    }
+
+    /// the counters of the pool
+    pub fn stats(&self) -> PoolStats {
+        let shared = &self.shared;
+        PoolStats {
+            submitted: shared.submitted.load(Ordering::Relaxed),
+            completed: shared.completed.load(Ordering::Relaxed),
+            panicked: shared.panicked.load(Ordering::Relaxed),
+            discarded: shared.discarded.load(Ordering::Relaxed),
+            queued: self.len(),
+            workers: self.workers(),
+        }
+    }
+}
+
+impl<J: Send + 'static> fmt::Debug for WorkerPool<J> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("stats", &self.stats())
+            .field("shutdown", &self.is_shutdown())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::sleep;
+
+    #[test]
+    fn panics_and_resize() {
+        let pool = WorkerPool::new(2, 4, |n: u32| {
+            if n % 10 == 0 {
+                panic!("job {}", n);
+            }
+        });
+        for n in 1..=50 {
+            pool.submit(n).unwrap();
+        }
+        pool.resize(6);
+        assert_eq!(pool.workers(), 6);
+        pool.resize(1);
+        // the idle workers see the poison and exit
+        for _ in 0..100 {
+            if pool.workers() == 1 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.workers(), 1);
+        assert!(pool.shutdown(Drain::Finish, Duration::from_secs(10)));
+        let stats = pool.stats();
+        assert_eq!(stats.panicked, 5);
+        assert_eq!(stats.completed, 45);
+        assert_eq!(stats.submitted, 50);
+        assert_eq!((stats.queued, stats.workers), (0, 0));
+        pool.resize(3);
+        assert_eq!(pool.workers(), 0);
+    }
+
+    #[test]
+    fn discard_and_timeout() {
+        let pool = WorkerPool::new(1, 8, |d: u64| sleep(Duration::from_millis(d)));
+        pool.submit(50).unwrap();
+        sleep(Duration::from_millis(10));
+        for _ in 0..8 {
+            pool.try_submit(1).unwrap();
+        }
+        // the queue is full
+        assert_eq!(pool.try_submit(1).unwrap_err().0, 1);
+        assert_eq!(pool.len(), 8);
+        assert!(pool.shutdown(Drain::Discard, Duration::from_secs(10)));
+        let stats = pool.stats();
+        assert_eq!((stats.completed, stats.discarded), (1, 8));
+
+        // a job that outlives the timeout is canceled
+        let pool = WorkerPool::new(1, 1, |d: u64| sleep(Duration::from_millis(d)));
+        pool.submit(60_000).unwrap();
+        sleep(Duration::from_millis(10));
+        assert!(!pool.shutdown(Drain::Finish, Duration::from_millis(20)));
+        assert!(pool.shutdown(Drain::Finish, Duration::from_secs(10)));
+        assert_eq!(pool.stats().completed, 0);
+    }
+}
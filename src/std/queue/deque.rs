@@ -0,0 +1,402 @@
+//! The work-stealing deque that the runtime schedules the coroutines with.
+//!
+//! A [`Worker`] is owned by one thread or coroutine that pushes and pops at
+//! one end, the [`Stealer`]s take from the other end from anywhere. It's the
+//! Chase-Lev deque of crossbeam, so the parking and the backoff match the
+//! runtime's own queues.
+//!
+//! The memory orderings: a value pushed by the owner is released by the push
+//! and acquired by the pop or the steal that takes it, so everything the
+//! owner wrote before the push is visible to the taker. Each value is taken
+//! exactly once, a pop and a steal racing for the last value are decided by
+//! one compare-and-swap and the loser sees it gone. `Steal::Retry` means the
+//! race was lost to another stealer, the deque may still have values.
+//!
+//! [`BlockingDeque`] adds the side that waits: the values are pushed to it
+//! from anywhere by a [`DequeHandle`], and the owner parks in `pop_blocking`
+//! until there is one.
+//!
+//! # Examples
+//!
+//! ```
+//! use mco::std::queue::deque::{Steal, Worker};
+//!
+//! let w = Worker::new_lifo();
+//! let s = w.stealer();
+//! w.push(1);
+//! w.push(2);
+//! w.push(3);
+//! assert_eq!(s.steal(), Steal::Success(1));
+//! assert_eq!(w.pop(), Some(3));
+//! ```
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam::deque;
+
+use crate::select::{SelectSet, Selectable, Waker, WakerList};
+
+pub use crossbeam::deque::Steal;
+
+/// The owner side of a deque, it's `Send` but not `Sync`.
+pub struct Worker<T>(deque::Worker<T>);
+
+impl<T> Worker<T> {
+    /// A deque that pops the value pushed last, like the runtime's own queues.
+    pub fn new_lifo() -> Self {
+        Worker(deque::Worker::new_lifo())
+    }
+
+    /// A deque that pops the value pushed first.
+    pub fn new_fifo() -> Self {
+        Worker(deque::Worker::new_fifo())
+    }
+
+    /// A stealer of the deque, it can be cloned and shared.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer(self.0.stealer())
+    }
+
+    /// Push a value to the owner's end.
+    pub fn push(&self, t: T) {
+        self.0.push(t)
+    }
+
+    /// Pop a value from the owner's end.
+    pub fn pop(&self) -> Option<T> {
+        self.0.pop()
+    }
+
+    /// Return true if the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The number of the values in the deque.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<T> fmt::Debug for Worker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Worker").field("len", &self.len()).finish()
+    }
+}
+
+/// The stealing side of a deque.
+pub struct Stealer<T>(deque::Stealer<T>);
+
+impl<T> Stealer<T> {
+    /// Steal a value from the other end of the owner.
+    pub fn steal(&self) -> Steal<T> {
+        self.0.steal()
+    }
+
+    /// Steal about half of the values into `dest`.
+    pub fn steal_batch(&self, dest: &Worker<T>) -> Steal<()> {
+        self.0.steal_batch(&dest.0)
+    }
+
+    /// Steal about half of the values, move them into `dest` and pop one of
+    /// them.
+    ///
+    /// The batch is taken by one compare-and-swap, it's all or nothing. The
+    /// values are acquired from the victim before they are released into
+    /// `dest`, so a stealer of `dest` sees them as pushed by its owner.
+    /// `Success` is the popped value, the rest are in `dest`.
+    pub fn steal_batch_and_pop(&self, dest: &Worker<T>) -> Steal<T> {
+        self.0.steal_batch_and_pop(&dest.0)
+    }
+
+    /// Return true if the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The number of the values in the deque.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Stealer(self.0.clone())
+    }
+}
+
+impl<T> fmt::Debug for Stealer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stealer").field("len", &self.len()).finish()
+    }
+}
+
+struct Shared<T> {
+    injector: deque::Injector<T>,
+    stealer: deque::Stealer<T>,
+    wakers: WakerList,
+}
+
+/// A deque whose owner can wait for the values pushed from anywhere.
+///
+/// The owner pushes and pops its own end as a `Worker` does, the values from
+/// the others go through a shared queue and are moved to the deque in
+/// batches when the owner runs out.
+///
+/// ```
+/// use mco::std::queue::deque::BlockingDeque;
+/// use std::time::Duration;
+///
+/// let q = BlockingDeque::new();
+/// let h = q.handle();
+/// mco::co!(move || h.push(1));
+/// assert_eq!(q.pop_blocking(Some(Duration::from_secs(5))), Some(1));
+/// assert_eq!(q.pop_blocking(Some(Duration::from_millis(10))), None);
+/// ```
+pub struct BlockingDeque<T> {
+    local: deque::Worker<T>,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> BlockingDeque<T> {
+    pub fn new() -> Self {
+        let local = deque::Worker::new_lifo();
+        let shared = Arc::new(Shared {
+            injector: deque::Injector::new(),
+            stealer: local.stealer(),
+            wakers: WakerList::new(),
+        });
+        BlockingDeque { local, shared }
+    }
+
+    /// A handle that pushes to the deque and steals from it.
+    pub fn handle(&self) -> DequeHandle<T> {
+        DequeHandle {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Push a value to the owner's end.
+    pub fn push(&self, t: T) {
+        self.local.push(t);
+    }
+
+    /// Pop a value without blocking, the owner's end first and then the
+    /// values pushed by the handles.
+    pub fn pop(&self) -> Option<T> {
+        if let Some(t) = self.local.pop() {
+            return Some(t);
+        }
+        loop {
+            match self.shared.injector.steal_batch_and_pop(&self.local) {
+                Steal::Success(t) => return Some(t),
+                Steal::Empty => return None,
+                Steal::Retry => {}
+            }
+        }
+    }
+
+    /// Pop a value, park until there is one or the `timeout` passes.
+    ///
+    /// A value stolen by a handle while the owner waits is not seen by it.
+    pub fn pop_blocking(&self, timeout: Option<Duration>) -> Option<T> {
+        if let Some(t) = self.pop() {
+            return Some(t);
+        }
+        let mut set = SelectSet::new();
+        set.add(Pop(self), |t| t);
+        match timeout {
+            Some(dur) => set.select_timeout(dur),
+            None => Some(set.select()),
+        }
+    }
+
+    /// Return true if there is no value in the deque and in the shared queue.
+    pub fn is_empty(&self) -> bool {
+        self.local.is_empty() && self.shared.injector.is_empty()
+    }
+
+    /// The number of the values in the deque and in the shared queue.
+    pub fn len(&self) -> usize {
+        self.local.len() + self.shared.injector.len()
+    }
+}
+
+impl<T> Default for BlockingDeque<T> {
+    fn default() -> Self {
+        BlockingDeque::new()
+    }
+}
+
+impl<T> fmt::Debug for BlockingDeque<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockingDeque")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+// the pop of the owner as a source of a select
+struct Pop<'a, T>(&'a BlockingDeque<T>);
+
+impl<'a, T> Selectable for Pop<'a, T> {
+    type Output = T;
+
+    fn try_select(&mut self) -> Option<T> {
+        self.0.pop()
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.0.shared.wakers.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.0.shared.wakers.deregister(waker);
+    }
+}
+
+/// The shared side of a `BlockingDeque`, it can be cloned and sent anywhere.
+pub struct DequeHandle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> DequeHandle<T> {
+    /// Push a value and wake the owner if it waits.
+    pub fn push(&self, t: T) {
+        self.shared.injector.push(t);
+        self.shared.wakers.wake_all();
+    }
+
+    /// Steal a value from the owner's deque.
+    pub fn steal(&self) -> Steal<T> {
+        self.shared.stealer.steal()
+    }
+
+    /// Steal about half of the values of the owner's deque into `dest` and
+    /// pop one of them, see `Stealer::steal_batch_and_pop`
+    pub fn steal_batch_and_pop(&self, dest: &Worker<T>) -> Steal<T> {
+        self.shared.stealer.steal_batch_and_pop(&dest.0)
+    }
+}
+
+impl<T> Clone for DequeHandle<T> {
+    fn clone(&self) -> Self {
+        DequeHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for DequeHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DequeHandle {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    const STEALERS: usize = 4;
+
+    #[test]
+    fn owner_and_stealers() {
+        const N: usize = 100_000;
+        let w = Worker::new_lifo();
+        let taken: Arc<Vec<AtomicUsize>> = Arc::new((0..N).map(|_| AtomicUsize::new(0)).collect());
+        let done = Arc::new(AtomicBool::new(false));
+        let hs: Vec<_> = (0..STEALERS)
+            .map(|i| {
+                let s = w.stealer();
+                let taken = taken.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let dest = Worker::new_lifo();
+                    while !done.load(Ordering::Acquire) || !s.is_empty() {
+                        let got = if i % 2 == 0 {
+                            s.steal()
+                        } else {
+                            s.steal_batch_and_pop(&dest)
+                        };
+                        if let Steal::Success(v) = got {
+                            taken[v].fetch_add(1, Ordering::Relaxed);
+                        }
+                        while let Some(v) = dest.pop() {
+                            taken[v].fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for v in 0..N {
+            w.push(v);
+            // keep the deque at the boundary of one or two values
+            if v % 3 == 0 {
+                if let Some(v) = w.pop() {
+                    taken[v].fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        while let Some(v) = w.pop() {
+            taken[v].fetch_add(1, Ordering::Relaxed);
+        }
+        done.store(true, Ordering::Release);
+        for h in hs {
+            h.join().unwrap();
+        }
+        assert!(taken.iter().all(|t| t.load(Ordering::Relaxed) == 1));
+    }
+
+    #[test]
+    fn last_value_race() {
+        for _ in 0..10_000 {
+            let w = Worker::new_fifo();
+            let s = w.stealer();
+            w.push(1);
+            let h = thread::spawn(move || loop {
+                match s.steal() {
+                    Steal::Success(v) => break Some(v),
+                    Steal::Empty => break None,
+                    Steal::Retry => {}
+                }
+            });
+            let mine = w.pop();
+            let theirs = h.join().unwrap();
+            assert!(mine.is_some() != theirs.is_some());
+        }
+    }
+
+    #[test]
+    fn blocking_pop() {
+        let q = BlockingDeque::new();
+        let h = q.handle();
+        assert_eq!(q.pop_blocking(Some(Duration::from_millis(10))), None);
+
+        let producer = crate::coroutine::spawn(move || {
+            for i in 0..100 {
+                h.push(i);
+                if i % 10 == 0 {
+                    crate::coroutine::sleep(Duration::from_millis(1));
+                }
+            }
+        });
+        let mut sum = 0;
+        for _ in 0..100 {
+            sum += q.pop_blocking(Some(Duration::from_secs(5))).unwrap();
+        }
+        producer.join().unwrap();
+        assert_eq!(sum, (0..100).sum());
+
+        // the owner's own values and the stolen ones
+        q.push(1);
+        q.push(2);
+        assert_eq!(q.handle().steal(), Steal::Success(1));
+        assert_eq!(q.pop(), Some(2));
+        assert!(q.is_empty());
+    }
+}
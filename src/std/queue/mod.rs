@@ -1,6 +1,7 @@
 #![cfg_attr(all(nightly, test), feature(test))]
 
 pub mod array_queue;
+pub mod deque;
 pub mod mpsc_list;
 pub mod mpsc_list_v1;
 pub mod seg_queue;
@@ -1,7 +1,8 @@
 use core::cell::UnsafeCell;
 use core::fmt;
 use core::marker::PhantomData;
-use core::mem::MaybeUninit;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
 use core::ptr;
 use core::sync::atomic::{self, AtomicPtr, AtomicUsize, Ordering};
 
@@ -153,6 +154,15 @@ pub struct SegQueue<T> {
     /// The tail of the queue.
     tail: CachePadded<Position<T>>,
 
+    /// The number of the blocks that are installed and not retired yet.
+    blocks: AtomicUsize,
+
+    /// The blocks kept for `push_no_alloc`, linked by their `next`.
+    spare: AtomicPtr<Block<T>>,
+
+    /// The number of the spare blocks.
+    spares: AtomicUsize,
+
     /// Indicates that dropping a `SegQueue<T>` may drop values of type `T`.
     _marker: PhantomData<T>,
 }
@@ -181,6 +191,9 @@ impl<T> SegQueue<T> {
                 block: AtomicPtr::new(ptr::null_mut()),
                 index: AtomicUsize::new(0),
             }),
+            blocks: AtomicUsize::new(0),
+            spare: AtomicPtr::new(ptr::null_mut()),
+            spares: AtomicUsize::new(0),
             _marker: PhantomData,
         }
     }
@@ -231,6 +244,7 @@ impl<T> SegQueue<T> {
                     .compare_exchange(block, new, Ordering::Release, Ordering::Relaxed)
                     .is_ok()
                 {
+                    self.blocks.fetch_add(1, Ordering::Relaxed);
                     self.head.block.store(new, Ordering::Release);
                     block = new;
                 } else {
@@ -256,6 +270,7 @@ impl<T> SegQueue<T> {
                         let next_block = Box::into_raw(next_block.unwrap());
                         let next_index = new_tail.wrapping_add(1 << SHIFT);
 
+                        self.blocks.fetch_add(1, Ordering::Relaxed);
                         self.tail.block.store(next_block, Ordering::Release);
                         self.tail.index.store(next_index, Ordering::Release);
                         (*block).next.store(next_block, Ordering::Release);
@@ -277,6 +292,172 @@ impl<T> SegQueue<T> {
         }
     }
 
+    /// Pushes an element into the queue without allocating, the element is returned if it
+    /// would take a new block and there is no spare one.
+    ///
+    /// It never blocks nor allocates, it spins only while another push installs the next block.
+    /// The spare blocks are kept by `reserve_blocks`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mco::std::queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    /// assert_eq!(q.push_no_alloc(1), Err(1));
+    ///
+    /// q.reserve_blocks(1);
+    /// assert_eq!(q.push_no_alloc(1), Ok(()));
+    /// assert_eq!(q.pop(), Some(1));
+    /// ```
+    pub fn push_no_alloc(&self, value: T) -> Result<(), T> {
+        let backoff = Backoff::new();
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block: *mut Block<T> = ptr::null_mut();
+
+        loop {
+            let offset = (tail >> SHIFT) % LAP;
+
+            // Wait until the next block is installed.
+            if offset == BLOCK_CAP {
+                backoff.snooze();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+
+            // The next block or the first one come from the spares.
+            if (offset + 1 == BLOCK_CAP || block.is_null()) && next_block.is_null() {
+                next_block = self.take_spare();
+                if next_block.is_null() {
+                    return Err(value);
+                }
+            }
+
+            if block.is_null() {
+                let new = next_block;
+                if self
+                    .tail
+                    .block
+                    .compare_exchange(block, new, Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    next_block = ptr::null_mut();
+                    self.blocks.fetch_add(1, Ordering::Relaxed);
+                    self.head.block.store(new, Ordering::Release);
+                    block = new;
+                } else {
+                    tail = self.tail.index.load(Ordering::Acquire);
+                    block = self.tail.block.load(Ordering::Acquire);
+                    continue;
+                }
+            }
+
+            let new_tail = tail + (1 << SHIFT);
+
+            match self.tail.index.compare_exchange_weak(
+                tail,
+                new_tail,
+                Ordering::SeqCst,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next_index = new_tail.wrapping_add(1 << SHIFT);
+
+                        self.blocks.fetch_add(1, Ordering::Relaxed);
+                        self.tail.block.store(next_block, Ordering::Release);
+                        self.tail.index.store(next_index, Ordering::Release);
+                        (*block).next.store(next_block, Ordering::Release);
+                        next_block = ptr::null_mut();
+                    }
+
+                    let slot = (*block).slots.get_unchecked(offset);
+                    slot.value.get().write(MaybeUninit::new(value));
+                    slot.state.fetch_or(WRITE, Ordering::Release);
+
+                    // Another push has installed the block that was taken for it.
+                    if !next_block.is_null() {
+                        self.put_spare(next_block);
+                    }
+                    return Ok(());
+                },
+                Err(t) => {
+                    tail = t;
+                    block = self.tail.block.load(Ordering::Acquire);
+                    backoff.spin();
+                }
+            }
+        }
+    }
+
+    /// Allocates the spare blocks for `push_no_alloc` until there are `n` of them.
+    ///
+    /// A block holds 31 elements, so `n` blocks let `push_no_alloc` take at least `31 * n`
+    /// elements beyond the current block before they run out.
+    pub fn reserve_blocks(&self, n: usize) {
+        while self.spares.load(Ordering::Acquire) < n {
+            let block = Box::into_raw(Box::new(Block::<T>::new()));
+            self.put_spare(block);
+        }
+    }
+
+    /// Returns the number of the spare blocks for `push_no_alloc`.
+    pub fn spare_blocks(&self) -> usize {
+        self.spares.load(Ordering::Acquire)
+    }
+
+    // take one spare block, null if there is none. the whole list is taken
+    // at once and the rest is put back, so there is no ABA on the list
+    fn take_spare(&self) -> *mut Block<T> {
+        let list = self.spare.swap(ptr::null_mut(), Ordering::AcqRel);
+        if list.is_null() {
+            return list;
+        }
+        self.spares.fetch_sub(1, Ordering::AcqRel);
+        unsafe {
+            let rest = (*list).next.swap(ptr::null_mut(), Ordering::Relaxed);
+            if !rest.is_null() {
+                self.put_chain(rest, false);
+            }
+        }
+        list
+    }
+
+    // put back one block
+    fn put_spare(&self, block: *mut Block<T>) {
+        self.spares.fetch_add(1, Ordering::AcqRel);
+        self.put_chain(block, true);
+    }
+
+    // push a chain of the blocks to the spare list, `single` if it's one
+    fn put_chain(&self, chain: *mut Block<T>, single: bool) {
+        let mut last = chain;
+        if !single {
+            unsafe {
+                loop {
+                    let next = (*last).next.load(Ordering::Relaxed);
+                    if next.is_null() {
+                        break;
+                    }
+                    last = next;
+                }
+            }
+        }
+        let mut head = self.spare.load(Ordering::Acquire);
+        loop {
+            unsafe { (*last).next.store(head, Ordering::Relaxed) };
+            match self
+                .spare
+                .compare_exchange_weak(head, chain, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
     /// Pops an element from the queue.
     ///
     /// If the queue is empty, `None` is returned.
@@ -293,6 +474,49 @@ impl<T> SegQueue<T> {
     /// assert!(q.pop().is_none());
     /// ```
     pub fn pop(&self) -> Option<T> {
+        let (block, offset) = self.claim()?;
+        unsafe {
+            // Read the value.
+            let slot = (*block).slots.get_unchecked(offset);
+            slot.wait_write();
+            let value = slot.value.get().read().assume_init();
+            self.release(block, offset);
+            Some(value)
+        }
+    }
+
+    /// Pops an element from the queue and leaves it in its slot until the returned guard is
+    /// dropped. The slot is not reused and its block is not freed while the guard is alive, the
+    /// element is dropped in place with the guard unless it's moved out by `PopRef::take`.
+    ///
+    /// If the queue is empty, `None` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mco::std::queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    /// q.push(vec![1, 2]);
+    ///
+    /// let mut v = q.pop_ref().unwrap();
+    /// v.push(3);
+    /// assert_eq!(*v, [1, 2, 3]);
+    /// drop(v);
+    /// assert!(q.pop_ref().is_none());
+    /// ```
+    pub fn pop_ref(&self) -> Option<PopRef<'_, T>> {
+        let (block, offset) = self.claim()?;
+        unsafe { (*block).slots.get_unchecked(offset).wait_write() };
+        Some(PopRef {
+            queue: self,
+            block,
+            offset,
+        })
+    }
+
+    // move the head past one slot, return the block and the offset of the slot that is claimed
+    fn claim(&self) -> Option<(*mut Block<T>, usize)> {
         let backoff = Backoff::new();
         let mut head = self.head.index.load(Ordering::Acquire);
         let mut block = self.head.block.load(Ordering::Acquire);
@@ -354,21 +578,7 @@ impl<T> SegQueue<T> {
                         self.head.block.store(next, Ordering::Release);
                         self.head.index.store(next_index, Ordering::Release);
                     }
-
-                    // Read the value.
-                    let slot = (*block).slots.get_unchecked(offset);
-                    slot.wait_write();
-                    let value = slot.value.get().read().assume_init();
-
-                    // Destroy the block if we've reached the end, or if another thread wanted to
-                    // destroy but couldn't because we were busy reading from the slot.
-                    if offset + 1 == BLOCK_CAP {
-                        Block::destroy(block, 0);
-                    } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
-                        Block::destroy(block, offset + 1);
-                    }
-
-                    return Some(value);
+                    return Some((block, offset));
                 },
                 Err(h) => {
                     head = h;
@@ -379,6 +589,19 @@ impl<T> SegQueue<T> {
         }
     }
 
+    // the value of a claimed slot is read or dropped, the slot is done
+    unsafe fn release(&self, block: *mut Block<T>, offset: usize) {
+        let slot = (*block).slots.get_unchecked(offset);
+        // Destroy the block if we've reached the end, or if another thread wanted to
+        // destroy but couldn't because we were busy reading from the slot.
+        if offset + 1 == BLOCK_CAP {
+            self.blocks.fetch_sub(1, Ordering::Relaxed);
+            Block::destroy(block, 0);
+        } else if slot.state.fetch_or(READ, Ordering::AcqRel) & DESTROY != 0 {
+            Block::destroy(block, offset + 1);
+        }
+    }
+
     /// Returns `true` if the queue is empty.
     ///
     /// # Examples
@@ -448,6 +671,40 @@ impl<T> SegQueue<T> {
             }
         }
     }
+
+    /// Returns the number of the segments held by the queue.
+    ///
+    /// A segment is released as soon as all its elements are popped, so a drained queue holds at
+    /// most one segment no matter how many elements it held before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mco::std::queue::seg_queue::SegQueue;
+    ///
+    /// let q = SegQueue::new();
+    /// assert_eq!(q.segments(), 0);
+    ///
+    /// for i in 0..1000 {
+    ///     q.push(i);
+    /// }
+    /// assert!(q.segments() > 1);
+    ///
+    /// while q.pop().is_some() {}
+    /// assert_eq!(q.segments(), 1);
+    /// ```
+    pub fn segments(&self) -> usize {
+        self.blocks.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of the bytes allocated for the segments.
+    pub fn allocated_bytes(&self) -> usize {
+        self.segments() * Self::SEGMENT_BYTES
+    }
+
+    // the heap size of one block, the slots are a separate allocation
+    const SEGMENT_BYTES: usize =
+        core::mem::size_of::<Block<T>>() + BLOCK_CAP * core::mem::size_of::<Slot<T>>();
 }
 
 impl<T> Drop for SegQueue<T> {
@@ -484,10 +741,74 @@ impl<T> Drop for SegQueue<T> {
             if !block.is_null() {
                 drop(Box::from_raw(block));
             }
+
+            // Deallocate the spare blocks.
+            let mut spare = self.spare.load(Ordering::Relaxed);
+            while !spare.is_null() {
+                let next = (*spare).next.load(Ordering::Relaxed);
+                drop(Box::from_raw(spare));
+                spare = next;
+            }
         }
     }
 }
 
+/// An element of `SegQueue::pop_ref` in its slot of the queue.
+pub struct PopRef<'a, T> {
+    queue: &'a SegQueue<T>,
+    block: *mut Block<T>,
+    offset: usize,
+}
+
+unsafe impl<'a, T: Send> Send for PopRef<'a, T> {}
+
+unsafe impl<'a, T: Sync> Sync for PopRef<'a, T> {}
+
+impl<'a, T> PopRef<'a, T> {
+    fn value(&self) -> *mut T {
+        unsafe { (*(*self.block).slots.get_unchecked(self.offset).value.get()).as_mut_ptr() }
+    }
+
+    /// Moves the element out of its slot, the slot is released.
+    pub fn take(self) -> T {
+        let this = ManuallyDrop::new(self);
+        unsafe {
+            let value = this.value().read();
+            this.queue.release(this.block, this.offset);
+            value
+        }
+    }
+}
+
+impl<'a, T> Deref for PopRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.value() }
+    }
+}
+
+impl<'a, T> DerefMut for PopRef<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.value() }
+    }
+}
+
+impl<'a, T> Drop for PopRef<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            self.value().drop_in_place();
+            self.queue.release(self.block, self.offset);
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for PopRef<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
 impl<T> fmt::Debug for SegQueue<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.pad("SegQueue { .. }")
@@ -0,0 +1,219 @@
+//! fast random numbers for the coroutines, not for cryptography
+//!
+//! each coroutine has its own generator in the coroutine local storage, so it
+//! never contends with the others and it's not affected when the coroutine
+//! migrates between the workers. in a thread context the thread has its own
+//! generator. a generator is seeded at its first use from the OS entropy of
+//! the process, mixed with a global counter so that no two get the same seed.
+//! with `config().set_deterministic_seed` they are seeded from that seed and
+//! the counter instead, so a run replays the same numbers
+//! for example:
+//! ```
+//! use std::time::Duration;
+//! use mco::std::rand;
+//!
+//! let dice = rand::range(1..7);
+//! assert!((1..7).contains(&dice));
+//! let mut v = vec![1, 2, 3, 4];
+//! rand::shuffle(&mut v);
+//! let d = rand::jitter(Duration::from_millis(100), 0.1);
+//! assert!(d >= Duration::from_millis(90) && d <= Duration::from_millis(110));
+//! ```
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::determinism;
+use crate::std::lazy::sync::Lazy;
+
+// the hasher keys of `RandomState` come from the OS entropy
+static SEED: Lazy<RandomState> = Lazy::new(RandomState::new);
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+coroutine_local!(static STATE: Cell<u64> = Cell::new(new_seed()));
+
+fn new_seed() -> u64 {
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    // the generators of a deterministic run replay with the seed
+    if let Some(seed) = determinism::seed() {
+        return determinism::mix(seed ^ determinism::mix(n));
+    }
+    let mut h = SEED.build_hasher();
+    h.write_u64(n);
+    h.finish()
+}
+
+// the generators made after a new deterministic seed start over
+pub(crate) fn restart_seeds() {
+    COUNTER.store(0, Ordering::Relaxed);
+}
+
+// splitmix64, one add and a mix per number
+#[inline]
+fn next(state: &Cell<u64>) -> u64 {
+    let s = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+    state.set(s);
+    let mut z = s;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// reseed the generator of the current coroutine, the numbers after it are
+/// the same for the same seed
+pub fn seed(seed: u64) {
+    STATE.with(|s| s.set(seed));
+}
+
+/// a random u64
+#[inline]
+pub fn u64() -> u64 {
+    STATE.with(next)
+}
+
+/// a random f64 in `[0, 1)`
+#[inline]
+pub fn f64() -> f64 {
+    // the 53 high bits fill the mantissa
+    (u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+/// a random bool
+#[inline]
+pub fn bool() -> bool {
+    u64() & 1 == 1
+}
+
+/// the integers that `range` samples
+pub trait SampleRange: Copy {
+    /// a uniform number in `[low, high)`, `low < high`
+    fn sample(low: Self, high: Self) -> Self;
+}
+
+// a uniform number in `[0, n)` without the modulo bias
+#[inline]
+fn below(n: u64) -> u64 {
+    // the rejection zone of the widening multiply, see Lemire's method
+    let zone = n.wrapping_neg() % n;
+    loop {
+        let m = (u64() as u128) * (n as u128);
+        if (m as u64) >= zone {
+            return (m >> 64) as u64;
+        }
+    }
+}
+
+macro_rules! impl_sample_range {
+    ($($t:ty => $u:ty),* $(,)?) => {
+        $(
+            impl SampleRange for $t {
+                #[inline]
+                fn sample(low: Self, high: Self) -> Self {
+                    let span = (high as $u).wrapping_sub(low as $u) as u64;
+                    (low as $u).wrapping_add(below(span) as $u) as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_sample_range! {
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, usize => usize,
+    i8 => u8, i16 => u16, i32 => u32, i64 => u64, isize => usize,
+}
+
+/// a uniform random number in the range
+///
+/// panics if the range is empty
+pub fn range<T: SampleRange + PartialOrd>(r: Range<T>) -> T {
+    assert!(r.start < r.end, "rand::range called with an empty range");
+    T::sample(r.start, r.end)
+}
+
+/// shuffle the slice in place
+pub fn shuffle<T>(s: &mut [T]) {
+    for i in (1..s.len()).rev() {
+        s.swap(i, below(i as u64 + 1) as usize);
+    }
+}
+
+/// a random duration in `[d * (1 - frac), d * (1 + frac)]`, it spreads the
+/// retries and the timers of many coroutines. `frac` is clamped to `[0, 1]`
+pub fn jitter(d: Duration, frac: f64) -> Duration {
+    let frac = if frac.is_nan() {
+        0.0
+    } else {
+        frac.max(0.0).min(1.0)
+    };
+    let factor = 1.0 - frac + 2.0 * frac * f64();
+    d.mul_f64(factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_sequence() {
+        seed(42);
+        let a: Vec<u64> = (0..4).map(|_| u64()).collect();
+        seed(42);
+        let b: Vec<u64> = (0..4).map(|_| u64()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a[0], a[1]);
+    }
+
+    #[test]
+    fn per_coroutine_seed() {
+        let hs: Vec<_> = (0..4).map(|_| co!(|| u64())).collect();
+        let mut v: Vec<u64> = hs.into_iter().map(|h| h.join().unwrap()).collect();
+        v.sort_unstable();
+        v.dedup();
+        assert_eq!(v.len(), 4);
+    }
+
+    #[test]
+    fn ranges() {
+        let mut seen = [false; 6];
+        for _ in 0..1000 {
+            let d = range(1..7);
+            assert!((1..7).contains(&d));
+            seen[d as usize - 1] = true;
+            let n = range(-3i8..-1);
+            assert!(n == -3 || n == -2);
+            assert_eq!(range(5u64..6), 5);
+            let x = range(i64::MIN..i64::MAX);
+            assert!(x < i64::MAX);
+        }
+        assert!(seen.iter().all(|s| *s));
+        let f = f64();
+        assert!((0.0..1.0).contains(&f));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_range() {
+        range(3..3);
+    }
+
+    #[test]
+    fn shuffle_and_jitter() {
+        let mut v: Vec<u32> = (0..100).collect();
+        shuffle(&mut v);
+        assert_ne!(v, (0..100).collect::<Vec<_>>());
+        v.sort_unstable();
+        assert_eq!(v, (0..100).collect::<Vec<_>>());
+
+        let d = Duration::from_secs(1);
+        for _ in 0..100 {
+            let j = jitter(d, 0.2);
+            assert!(j >= Duration::from_millis(800) && j <= Duration::from_millis(1200));
+        }
+        assert_eq!(jitter(d, 0.0), d);
+        assert!(jitter(d, 5.0) <= Duration::from_secs(2));
+    }
+}
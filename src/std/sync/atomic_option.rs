@@ -18,9 +18,10 @@ impl<T: std::fmt::Debug> Debug for AtomicOption<T> {
     }
 }
 
-unsafe impl<T> Send for AtomicOption<T> {}
+// the value moves to the thread that takes it, so it must be `Send`
+unsafe impl<T: Send> Send for AtomicOption<T> {}
 
-unsafe impl<T> Sync for AtomicOption<T> {}
+unsafe impl<T: Send> Sync for AtomicOption<T> {}
 
 impl<T> AtomicOption<T> {
     pub fn none() -> AtomicOption<T> {
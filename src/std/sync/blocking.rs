@@ -3,7 +3,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use crate::coroutine_impl::is_coroutine;
+use crate::coroutine_impl::{assert_not_scheduler, is_coroutine};
 use crate::park::{Park, ParkError};
 
 #[derive(Debug)]
@@ -23,6 +23,7 @@ impl ThreadPark {
     }
 
     fn park_timeout(&self, dur: Option<Duration>) -> Result<(), ParkError> {
+        assert_not_scheduler("a blocking wait of mco::std::sync");
         let mut result = Ok(());
         let mut guard = self.lock.lock();
         while !*guard && result.is_ok() {
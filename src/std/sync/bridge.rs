@@ -0,0 +1,226 @@
+//! the bridges between the channels of this crate and the `std::sync::mpsc`
+//! and crossbeam channels, for a code base that moves to the coroutines one
+//! part at a time
+//!
+//! each bridge is a pump on a thread of its own named `mco-bridge`, it waits
+//! in the blocking recv of the source side and moves the messages in batches
+//! of up to `BRIDGE_BATCH`. the channel that a bridge returns has the bound
+//! of the source, so a full sink blocks the pump, the source fills up and its
+//! senders block in turn. besides the two bounds up to one batch is in the
+//! pump
+//!
+//! the shutdown goes both ways: when the senders of the source are gone the
+//! pump drains it and drops the sink, whose receivers see the disconnect
+//! after the buffered messages. when the receivers of the sink are gone the
+//! pump drops the source, whose senders see the disconnect. the pump notices
+//! the gone receivers in its next send, so a pump that waits on a quiet
+//! source keeps its thread until the source gets a message or its senders
+//! are gone
+
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+
+use crossbeam::channel as cb;
+
+use super::channel::{bounded, Receiver, Sender};
+
+/// the most messages that a bridge moves at once
+pub const BRIDGE_BATCH: usize = 64;
+
+/// a receiver of the messages of a crossbeam receiver
+///
+/// the returned channel is bounded as the crossbeam one, a zero capacity is
+/// taken as one
+///
+/// ```
+/// use mco::std::sync::bridge;
+///
+/// let (tx, rx) = crossbeam::channel::bounded(4);
+/// let rx = bridge::from_crossbeam(rx);
+/// std::thread::spawn(move || (0..10).for_each(|i| tx.send(i).unwrap()));
+/// let h = mco::co!(move || rx.iter().sum::<i32>());
+/// assert_eq!(h.join().unwrap(), 45);
+/// ```
+pub fn from_crossbeam<T: Send + 'static>(rx: cb::Receiver<T>) -> Receiver<T> {
+    let (tx, out) = bounded(rx.capacity().map_or(usize::MAX, |n| n.max(1)));
+    spawn_pump(move || {
+        pump_in(tx, |block| {
+            if block {
+                rx.recv().ok()
+            } else {
+                rx.try_recv().ok()
+            }
+        })
+    });
+    out
+}
+
+/// a receiver of the messages of a `std::sync::mpsc` receiver
+///
+/// the std receiver doesn't tell its bound, so it's given in `buf`, use
+/// `usize::MAX` for an unbounded one
+pub fn from_std<T: Send + 'static>(rx: std_mpsc::Receiver<T>, buf: usize) -> Receiver<T> {
+    let (tx, out) = bounded(buf.max(1));
+    spawn_pump(move || {
+        pump_in(tx, |block| {
+            if block {
+                rx.recv().ok()
+            } else {
+                rx.try_recv().ok()
+            }
+        })
+    });
+    out
+}
+
+/// a crossbeam receiver of the messages of a receiver of this crate, for
+/// the threads that select on crossbeam channels
+///
+/// the returned channel is bounded as the given one
+pub fn to_crossbeam<T: Send + 'static>(rx: Receiver<T>) -> cb::Receiver<T> {
+    let (tx, out) = match rx.capacity() {
+        Some(n) => cb::bounded(n),
+        None => cb::unbounded(),
+    };
+    spawn_pump(move || pump_out(rx, |t| tx.send(t).is_ok()));
+    out
+}
+
+/// a `std::sync::mpsc` receiver of the messages of a receiver of this crate
+///
+/// the returned channel is a `sync_channel` with the bound of the given one,
+/// or an unbounded `channel`
+pub fn to_std<T: Send + 'static>(rx: Receiver<T>) -> std_mpsc::Receiver<T> {
+    match rx.capacity() {
+        Some(n) => {
+            let (tx, out) = std_mpsc::sync_channel(n);
+            spawn_pump(move || pump_out(rx, |t| tx.send(t).is_ok()));
+            out
+        }
+        None => {
+            let (tx, out) = std_mpsc::channel();
+            spawn_pump(move || pump_out(rx, |t| tx.send(t).is_ok()));
+            out
+        }
+    }
+}
+
+fn spawn_pump<F: FnOnce() + Send + 'static>(f: F) {
+    thread::Builder::new()
+        .name(crate::thread_names::name("bridge"))
+        .spawn(f)
+        .expect("can't start the bridge thread");
+}
+
+// `next(true)` blocks for a message, `next(false)` doesn't, both are `None`
+// when there is nothing to take
+fn pump_in<T, F: FnMut(bool) -> Option<T>>(tx: Sender<T>, mut next: F) {
+    let mut batch = Vec::with_capacity(BRIDGE_BATCH);
+    while let Some(t) = next(true) {
+        batch.push(t);
+        while batch.len() < BRIDGE_BATCH {
+            match next(false) {
+                Some(t) => batch.push(t),
+                None => break,
+            }
+        }
+        // waits for room in a bounded channel, fails when the receivers are
+        // gone or the channel is closed
+        if tx.send_all(batch.drain(..)).is_err() {
+            return;
+        }
+    }
+}
+
+// `send` is false when the receivers of the sink are gone
+fn pump_out<T, F: FnMut(T) -> bool>(rx: Receiver<T>, mut send: F) {
+    let mut batch = Vec::with_capacity(BRIDGE_BATCH);
+    // it blocks only when there is no message
+    while rx.recv_many(&mut batch, BRIDGE_BATCH).is_ok() {
+        for t in batch.drain(..) {
+            if !send(t) {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::sync::channel::{channel, channel_buf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn crossbeam_round_trip() {
+        let (tx, rx) = cb::unbounded();
+        let rx = to_crossbeam(from_crossbeam(rx));
+        let h = thread::spawn(move || rx.iter().collect::<Vec<_>>());
+        (0..1000).for_each(|i| tx.send(i).unwrap());
+        drop(tx);
+        assert_eq!(h.join().unwrap(), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn std_round_trip() {
+        let (tx, rx) = std_mpsc::sync_channel(8);
+        let rx = from_std(rx, 8);
+        let h = co!(move || rx.iter().collect::<Vec<_>>());
+        (0..200).for_each(|i| tx.send(i).unwrap());
+        drop(tx);
+        assert_eq!(h.join().unwrap(), (0..200).collect::<Vec<_>>());
+
+        let (tx, rx) = channel_buf(4);
+        let rx = to_std(rx);
+        let h = co!(move || (0..200).for_each(|i| tx.send(i).unwrap()));
+        assert_eq!(rx.iter().collect::<Vec<_>>(), (0..200).collect::<Vec<_>>());
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn backpressure() {
+        let (tx, rx) = cb::bounded(4);
+        let rx = from_crossbeam(rx);
+        let sent = Arc::new(AtomicUsize::new(0));
+        let n = sent.clone();
+        let h = thread::spawn(move || {
+            for i in 0..1000 {
+                if tx.send(i).is_err() {
+                    break;
+                }
+                n.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        thread::sleep(Duration::from_millis(50));
+        // the bound of the source, a batch in the pump and the bound of
+        // the sink
+        let stuck = sent.load(Ordering::Relaxed);
+        assert!(stuck < 4 + 4 + BRIDGE_BATCH + 1, "{} sent", stuck);
+        assert_eq!(rx.iter().count(), 1000);
+        h.join().unwrap();
+    }
+
+    #[test]
+    fn shutdown_from_sink() {
+        // the crossbeam senders see the gone receiver
+        let (tx, rx) = cb::bounded(1);
+        drop(from_crossbeam(rx));
+        tx.send(1).unwrap();
+        let ret = (0..1000).find_map(|i| {
+            thread::sleep(Duration::from_millis(1));
+            tx.send(i).err()
+        });
+        assert!(ret.is_some());
+
+        // and so do the senders of this crate
+        let (tx, rx) = channel();
+        drop(to_crossbeam(rx));
+        let ret = (0..1000).find_map(|i| {
+            thread::sleep(Duration::from_millis(1));
+            tx.send(i).err()
+        });
+        assert!(ret.is_some());
+    }
+}
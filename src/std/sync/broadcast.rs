@@ -0,0 +1,226 @@
+//! broadcast channel implementation
+//! every receiver gets each message that is sent after it subscribed
+//!
+//! each clone of the `Receiver` is a subscriber with a bounded queue of its
+//! own, a send puts a clone of the message in every queue and blocks while
+//! one of them is full. so the payloads are best sent as `Bytes` or an `Arc`,
+//! then a fan-out to many subscribers only bumps a reference count instead of
+//! copying the data for each one
+//!
+//! ```
+//! use mco::std::bytes::Bytes;
+//! use mco::std::sync::broadcast;
+//!
+//! let (tx, rx) = broadcast::channel::<Bytes>(16);
+//! let rx2 = rx.clone();
+//! tx.send(Bytes::from(vec![7u8; 1024])).unwrap();
+//! let (a, b) = (rx.recv().unwrap(), rx2.recv().unwrap());
+//! // the two subscribers share the one buffer
+//! assert_eq!(a.as_ptr(), b.as_ptr());
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::channel::{self, bounded};
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// Create a broadcast channel, each receiver buffers up to `cap` messages
+pub fn channel<T: Clone>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        subs: Mutex::new(Vec::new()),
+        cap,
+        sender_num: AtomicUsize::new(1),
+        next_id: AtomicUsize::new(0),
+    });
+    let rx = Receiver::subscribe(&inner);
+    (Sender { inner }, rx)
+}
+
+struct Inner<T> {
+    // the queues of the subscribed receivers
+    subs: Mutex<Vec<(usize, channel::Sender<T>)>>,
+    cap: usize,
+    sender_num: AtomicUsize,
+    next_id: AtomicUsize,
+}
+
+/// The sending half of the broadcast channel
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// send the message to all the receivers, it blocks while the queue of
+    /// one of them is full. return error if there is no receiver
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        // the queues are not locked while a full one blocks the send
+        let subs: Vec<_> = self.inner.subs.lock().iter().map(|s| s.1.clone()).collect();
+        let (last, rest) = match subs.split_last() {
+            Some(s) => s,
+            None => return Err(SendError(t)),
+        };
+        // a receiver that is gone in the meantime is skipped
+        for s in rest {
+            let _ = s.send(t.clone());
+        }
+        let _ = last.send(t);
+        Ok(())
+    }
+}
+
+impl<T> Sender<T> {
+    /// the number of the subscribed receivers
+    pub fn receivers(&self) -> usize {
+        self.inner.subs.lock().len()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.sender_num.fetch_add(1, Ordering::AcqRel);
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.inner.sender_num.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // the receivers get what is queued and then the disconnect
+            self.inner.subs.lock().clear();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// The receiving half of the broadcast channel
+///
+/// a clone subscribes a new receiver that gets the messages sent after it,
+/// a drop unsubscribes it
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+    id: usize,
+    rx: channel::Receiver<T>,
+}
+
+impl<T> Receiver<T> {
+    fn subscribe(inner: &Arc<Inner<T>>) -> Receiver<T> {
+        let (tx, rx) = bounded(inner.cap);
+        let id = inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut subs = inner.subs.lock();
+        // with the senders gone it's disconnected at once
+        if inner.sender_num.load(Ordering::Acquire) != 0 {
+            subs.push((id, tx));
+        }
+        Receiver {
+            inner: inner.clone(),
+            id,
+            rx,
+        }
+    }
+
+    /// try to receive the next message without blocking
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.rx.try_recv()
+    }
+
+    /// receive the next message, it blocks while the queue is empty
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.rx.recv()
+    }
+
+    /// receive the next message, wait for it at most `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    /// the number of the queued messages of the receiver
+    pub fn remain(&self) -> usize {
+        self.rx.remain()
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        Receiver::subscribe(&self.inner)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.subs.lock().retain(|s| s.0 != self.id);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::bytes::Bytes;
+
+    #[test]
+    fn fan_out() {
+        let (tx, rx) = channel::<Bytes>(4);
+        let hs: Vec<_> = (0..4)
+            .map(|_| {
+                let rx = rx.clone();
+                co!(move || {
+                    let mut n = 0;
+                    while let Ok(b) = rx.recv() {
+                        n += b.len();
+                    }
+                    n
+                })
+            })
+            .collect();
+        drop(rx);
+        assert_eq!(tx.receivers(), 4);
+        let payload = Bytes::from(vec![1u8; 1024]);
+        for _ in 0..100 {
+            tx.send(payload.clone()).unwrap();
+        }
+        drop(tx);
+        for h in hs {
+            assert_eq!(h.join().unwrap(), 100 * 1024);
+        }
+    }
+
+    #[test]
+    fn subscribe_and_leave() {
+        let (tx, rx) = channel::<i32>(4);
+        tx.send(1).unwrap();
+        // a new subscriber only gets the later messages
+        let rx2 = rx.clone();
+        tx.send(2).unwrap();
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx2.try_recv(), Ok(2));
+        drop(rx);
+        drop(rx2);
+        assert_eq!(tx.send(3), Err(SendError(3)));
+
+        let (tx, rx) = channel::<i32>(4);
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.clone().try_recv(), Err(TryRecvError::Disconnected));
+    }
+}
@@ -0,0 +1,463 @@
+//! the memory accounting of the channels and the coroutines
+//!
+//! a `MemoryBudget` is charged by the messages queued in the channels made
+//! by `channel_with_budget` and by the stacks of the coroutines spawned with
+//! `Builder::budget`, the charge is given back when the message is received
+//! or dropped and when the coroutine is done. one budget can be shared by the
+//! whole process or made for each tenant
+//!
+//! the bytes are counted in per thread shards that are flushed to the global
+//! count in batches, so the charges don't contend on one atomic. when the
+//! count comes near the limit the shards are summed up and the global count
+//! is charged directly, so the limit holds within the concurrent charges
+//! racing at that moment
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crossbeam_utils::CachePadded;
+use parking_lot::Mutex;
+
+use super::blocking::SyncBlocker;
+use super::channel::{channel, Receiver, Sender};
+use crate::cancel::trigger_cancel_panic;
+use crate::park::ParkError;
+
+const SHARDS: usize = 16;
+// the bytes that a shard keeps before they are flushed to the global count
+const BATCH: isize = 16 * 1024;
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+}
+
+/// the bytes that a queued value takes, the default is `size_of::<T>()`
+///
+/// override `mem_size` for the values that own a heap allocation
+///
+/// ```
+/// use mco::std::sync::MemSize;
+///
+/// struct Frame(Vec<u8>);
+///
+/// impl MemSize for Frame {
+///     fn mem_size(&self) -> usize {
+///         std::mem::size_of::<Self>() + self.0.capacity()
+///     }
+/// }
+/// ```
+pub trait MemSize {
+    /// the bytes of the value
+    fn mem_size(&self) -> usize
+    where
+        Self: Sized,
+    {
+        std::mem::size_of::<Self>()
+    }
+}
+
+macro_rules! impl_mem_size {
+    ($($t:ty),*) => {
+        $(impl MemSize for $t {})*
+    };
+}
+
+impl_mem_size!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64
+);
+
+impl<T, const N: usize> MemSize for [T; N] {}
+
+impl MemSize for String {
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.capacity()
+    }
+}
+
+impl<T> MemSize for Vec<T> {
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.capacity() * std::mem::size_of::<T>()
+    }
+}
+
+impl<T: MemSize> MemSize for Box<T> {
+    fn mem_size(&self) -> usize {
+        std::mem::size_of::<Self>() + (**self).mem_size()
+    }
+}
+
+impl<T: MemSize> MemSize for Option<T> {
+    fn mem_size(&self) -> usize {
+        match self {
+            Some(t) => std::mem::size_of::<Self>() - std::mem::size_of::<T>() + t.mem_size(),
+            None => std::mem::size_of::<Self>(),
+        }
+    }
+}
+
+/// what a send does when the budget of the channel is used up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// `send` waits until the budget is given back, this is the default
+    Block,
+    /// `send` returns the message in the error at once, the channel is still
+    /// open, `Sender::is_closed` tells the two errors apart
+    Error,
+}
+
+impl Default for BudgetPolicy {
+    fn default() -> Self {
+        BudgetPolicy::Block
+    }
+}
+
+struct Inner {
+    limit: usize,
+    policy: BudgetPolicy,
+    // the flushed bytes, it may be below the truth by the unflushed shards
+    used: CachePadded<AtomicIsize>,
+    // the bytes not flushed yet, a shard is negative when it gives back more
+    // than it's charged
+    shards: [CachePadded<AtomicIsize>; SHARDS],
+    // the senders blocked by `BudgetPolicy::Block`
+    waiting: AtomicUsize,
+    waiters: Mutex<Vec<Arc<SyncBlocker>>>,
+    // the watermarks in bytes, notified when the count goes up across them
+    has_marks: AtomicBool,
+    marks: Mutex<Vec<(usize, Sender<usize>)>>,
+}
+
+impl Inner {
+    fn used(&self) -> usize {
+        let pending: isize = self.shards.iter().map(|s| s.load(Ordering::Acquire)).sum();
+        (self.used.load(Ordering::Acquire) + pending).max(0) as usize
+    }
+
+    fn try_charge(&self, bytes: usize) -> bool {
+        let limit = self.limit as isize;
+        let bytes = bytes as isize;
+        let global = self.used.load(Ordering::Acquire);
+        // far below the limit even when all the shards are full
+        if global + BATCH * SHARDS as isize + bytes <= limit {
+            let shard = &self.shards[SHARD.with(|s| *s)];
+            if shard.fetch_add(bytes, Ordering::AcqRel) + bytes >= BATCH {
+                self.flush(shard);
+            }
+            return true;
+        }
+        // near the limit, count the shards and charge the global count
+        let pending: isize = self.shards.iter().map(|s| s.load(Ordering::Acquire)).sum();
+        let ret = self
+            .used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |g| {
+                if g + pending + bytes <= limit {
+                    Some(g + bytes)
+                } else {
+                    None
+                }
+            });
+        match ret {
+            Ok(old) => {
+                self.crossed(old + pending, old + pending + bytes);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn release(&self, bytes: usize) {
+        let shard = &self.shards[SHARD.with(|s| *s)];
+        if shard.fetch_sub(bytes as isize, Ordering::AcqRel) - (bytes as isize) <= -BATCH {
+            self.flush(shard);
+        }
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.wake();
+        }
+    }
+
+    fn flush(&self, shard: &AtomicIsize) {
+        let delta = shard.swap(0, Ordering::AcqRel);
+        let old = self.used.fetch_add(delta, Ordering::AcqRel);
+        if delta > 0 {
+            self.crossed(old, old + delta);
+        }
+    }
+
+    // the count goes from `old` to `new`, notify the watermarks in between
+    fn crossed(&self, old: isize, new: isize) {
+        if !self.has_marks.load(Ordering::Acquire) {
+            return;
+        }
+        let mut marks = self.marks.lock();
+        marks.retain(|(mark, tx)| {
+            let mark = *mark as isize;
+            if old < mark && mark <= new {
+                return tx.send(mark as usize).is_ok();
+            }
+            tx.receiver_num() > 0
+        });
+        if marks.is_empty() {
+            self.has_marks.store(false, Ordering::Release);
+        }
+    }
+
+    // wake all the blocked senders, they charge again
+    fn wake(&self) {
+        let waiters = std::mem::take(&mut *self.waiters.lock());
+        for w in waiters {
+            let _ = w.unpark();
+        }
+    }
+
+    // `give_up` is checked before each wait, see `MemoryBudget::wake`
+    fn charge_blocking(&self, bytes: usize, give_up: &dyn Fn() -> bool) -> bool {
+        loop {
+            if self.try_charge(bytes) {
+                return true;
+            }
+            if give_up() {
+                return false;
+            }
+            let cur = SyncBlocker::current();
+            self.waiting.fetch_add(1, Ordering::SeqCst);
+            self.waiters.lock().push(cur.clone());
+            // re-check after the registration, a release in between wakes us
+            let charged = self.try_charge(bytes);
+            let ret = if charged || give_up() {
+                Ok(())
+            } else {
+                cur.park(None)
+            };
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+            if !cur.is_unparked() {
+                self.waiters.lock().retain(|w| !Arc::ptr_eq(w, &cur));
+            }
+            if ret == Err(ParkError::Canceled) {
+                if charged {
+                    self.release(bytes);
+                }
+                trigger_cancel_panic();
+            }
+            if charged {
+                return true;
+            }
+        }
+    }
+}
+
+/// a memory limit shared by the channels and the coroutines charged to it
+///
+/// the clones are the same budget
+///
+/// ```
+/// use mco::std::sync::{channel_with_budget, BudgetPolicy, MemoryBudget};
+///
+/// let budget = MemoryBudget::with_policy(64, BudgetPolicy::Error);
+/// let (tx, rx) = channel_with_budget::<u64>(&budget);
+/// for i in 0..8 {
+///     tx.send(i).unwrap();
+/// }
+/// assert_eq!(budget.used(), 64);
+/// // the budget is used up
+/// assert!(tx.send(8).is_err());
+/// assert_eq!(rx.recv().unwrap(), 0);
+/// assert_eq!(budget.used(), 56);
+/// ```
+#[derive(Clone)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+impl MemoryBudget {
+    /// a budget of `limit` bytes, the sends block when it's used up
+    pub fn new(limit: usize) -> Self {
+        Self::with_policy(limit, BudgetPolicy::Block)
+    }
+
+    /// a budget of `limit` bytes with the policy of the sends
+    pub fn with_policy(limit: usize, policy: BudgetPolicy) -> Self {
+        MemoryBudget {
+            inner: Arc::new(Inner {
+                limit: limit.min(isize::MAX as usize),
+                policy,
+                used: CachePadded::new(AtomicIsize::new(0)),
+                shards: Default::default(),
+                waiting: AtomicUsize::new(0),
+                waiters: Mutex::new(Vec::new()),
+                has_marks: AtomicBool::new(false),
+                marks: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// the bytes charged now
+    pub fn used(&self) -> usize {
+        self.inner.used()
+    }
+
+    /// the limit in bytes
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// the policy of the sends
+    pub fn policy(&self) -> BudgetPolicy {
+        self.inner.policy
+    }
+
+    /// a receiver that gets the watermark each time the used bytes go up
+    /// across it, the marks are in bytes
+    ///
+    /// far below the limit the count is flushed in batches, so a mark may be
+    /// notified after some more bytes are charged
+    ///
+    /// ```
+    /// use mco::std::sync::{channel_with_budget, MemoryBudget};
+    ///
+    /// let budget = MemoryBudget::new(1024);
+    /// let marks = budget.watermarks(&[512, 768]);
+    /// let (tx, _rx) = channel_with_budget::<[u8; 256]>(&budget);
+    /// for _ in 0..3 {
+    ///     tx.send([0; 256]).unwrap();
+    /// }
+    /// assert_eq!(marks.try_recv().unwrap(), 512);
+    /// assert_eq!(marks.try_recv().unwrap(), 768);
+    /// ```
+    pub fn watermarks(&self, marks: &[usize]) -> Receiver<usize> {
+        let (tx, rx) = channel();
+        let mut all = self.inner.marks.lock();
+        all.extend(marks.iter().map(|m| (*m, tx.clone())));
+        self.inner.has_marks.store(true, Ordering::Release);
+        rx
+    }
+
+    /// charge `bytes`, `None` if the budget is used up
+    pub(crate) fn try_charge(&self, bytes: usize) -> Option<Charge> {
+        if self.inner.try_charge(bytes) {
+            Some(Charge {
+                budget: self.inner.clone(),
+                bytes,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// charge `bytes` by the policy, a blocked charge comes back with `None`
+    /// when `give_up` returns true after a `wake`
+    pub(crate) fn charge(&self, bytes: usize, give_up: &dyn Fn() -> bool) -> Option<Charge> {
+        match self.inner.policy {
+            BudgetPolicy::Error => self.try_charge(bytes),
+            BudgetPolicy::Block => {
+                if self.inner.charge_blocking(bytes, give_up) {
+                    Some(Charge {
+                        budget: self.inner.clone(),
+                        bytes,
+                    })
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// wake the blocked charges, so that they check `give_up`
+    pub(crate) fn wake(&self) {
+        self.inner.wake();
+    }
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("used", &self.used())
+            .field("limit", &self.limit())
+            .field("policy", &self.policy())
+            .finish()
+    }
+}
+
+// the bytes charged to a budget, given back on drop
+pub(crate) struct Charge {
+    budget: Arc<Inner>,
+    bytes: usize,
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sharded_count() {
+        // far below the limit the charges stay in the shards
+        let budget = MemoryBudget::new(usize::MAX);
+        let charges: Vec<_> = (0..1000).map(|_| budget.try_charge(100).unwrap()).collect();
+        assert_eq!(budget.used(), 100_000);
+        drop(charges);
+        assert_eq!(budget.used(), 0);
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let budget = budget.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        drop(budget.try_charge(64).unwrap());
+                    }
+                })
+            })
+            .collect();
+        threads.into_iter().for_each(|t| t.join().unwrap());
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn limit() {
+        let budget = MemoryBudget::new(1000);
+        let a = budget.try_charge(600).unwrap();
+        assert!(budget.try_charge(500).is_none());
+        let b = budget.try_charge(400).unwrap();
+        assert_eq!(budget.used(), 1000);
+        drop(a);
+        assert!(budget.try_charge(500).is_some());
+        drop(b);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn blocking_charge() {
+        let budget = MemoryBudget::new(100);
+        let full = budget.try_charge(100).unwrap();
+        let b = budget.clone();
+        let h = co!(move || b.charge(50, &|| false).is_some());
+        crate::coroutine::sleep(std::time::Duration::from_millis(10));
+        assert!(!h.is_done());
+        drop(full);
+        assert!(h.join().unwrap());
+        assert_eq!(budget.used(), 0);
+    }
+}
@@ -0,0 +1,342 @@
+//! a cancellation token, a lighter `Context` without deadline and values
+//!
+//! `cancel` wakes every coroutine or thread that waits by `cancelled`, and
+//! the child tokens are cancelled with their parent but can also be cancelled
+//! on their own. the clones share the same state and dropping them never
+//! cancels the token
+//!
+//! the flag is set and the waiters are taken under one lock, a waiter checks
+//! the flag again under the same lock before it registers, so no wakeup is
+//! lost when `cancel` races with a new waiter
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::blocking::SyncBlocker;
+use super::oneshot;
+use crate::cancel::trigger_cancel_panic;
+use crate::park::ParkError;
+use crate::select::{Selectable, Waker};
+
+enum Waiter {
+    Blocker(Arc<SyncBlocker>),
+    Notify(oneshot::Sender<()>),
+    Select(Waker),
+}
+
+struct State {
+    waiters: Vec<Waiter>,
+    children: Vec<Weak<Inner>>,
+}
+
+struct Inner {
+    // keep the parents alive, they cancel the children
+    _parent: Option<Arc<Inner>>,
+    cancelled: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl Inner {
+    fn new(parent: Option<Arc<Inner>>) -> Arc<Inner> {
+        let inner = Arc::new(Inner {
+            _parent: parent.clone(),
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new(State {
+                waiters: Vec::new(),
+                children: Vec::new(),
+            }),
+        });
+        if let Some(parent) = parent {
+            let mut state = parent.state.lock();
+            if parent.cancelled.load(Ordering::Acquire) {
+                inner.cancelled.store(true, Ordering::Release);
+            } else {
+                let children = &mut state.children;
+                // drop the dead children before the vec grows
+                if children.len() == children.capacity() {
+                    children.retain(|c| c.strong_count() > 0);
+                }
+                children.push(Arc::downgrade(&inner));
+            }
+        }
+        inner
+    }
+
+    fn cancel(&self) {
+        let (waiters, children) = {
+            let mut state = self.state.lock();
+            if self.cancelled.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            (
+                std::mem::take(&mut state.waiters),
+                std::mem::take(&mut state.children),
+            )
+        };
+        for w in waiters {
+            match w {
+                Waiter::Blocker(b) => {
+                    let _ = b.unpark();
+                }
+                Waiter::Notify(tx) => {
+                    let _ = tx.send(());
+                }
+                Waiter::Select(w) => w.wake(),
+            }
+        }
+        for child in children {
+            if let Some(child) = child.upgrade() {
+                child.cancel();
+            }
+        }
+    }
+
+    // return false if it times out before the cancellation
+    fn wait(&self, dur: Option<Duration>) -> bool {
+        if self.cancelled.load(Ordering::Acquire) {
+            return true;
+        }
+        let cur = SyncBlocker::current();
+        {
+            let mut state = self.state.lock();
+            // re-check under the lock that `cancel` takes
+            if self.cancelled.load(Ordering::Acquire) {
+                return true;
+            }
+            state.waiters.push(Waiter::Blocker(cur.clone()));
+        }
+        let ret = cur.park(dur);
+        if !cur.is_unparked() {
+            // timed out or the coroutine is cancelled, leave the wait list
+            self.state.lock().waiters.retain(|w| match w {
+                Waiter::Blocker(b) => !Arc::ptr_eq(b, &cur),
+                _ => true,
+            });
+        }
+        if ret == Err(ParkError::Canceled) {
+            trigger_cancel_panic();
+        }
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut state = self.state.lock();
+        if self.cancelled.load(Ordering::Acquire) {
+            drop(state);
+            return waker.wake();
+        }
+        state.waiters.push(Waiter::Select(waker.clone()));
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.state.lock().waiters.retain(|w| match w {
+            Waiter::Select(w) => !w.same(waker),
+            _ => true,
+        });
+    }
+
+    fn receiver(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let mut state = self.state.lock();
+        if self.cancelled.load(Ordering::Acquire) {
+            drop(state);
+            let _ = tx.send(());
+            return rx;
+        }
+        let waiters = &mut state.waiters;
+        // drop the receivers that are gone before the vec grows
+        if waiters.len() == waiters.capacity() {
+            waiters.retain(|w| match w {
+                Waiter::Notify(tx) => !tx.is_closed(),
+                _ => true,
+            });
+        }
+        waiters.push(Waiter::Notify(tx));
+        rx
+    }
+}
+
+/// a cancellation signal that is cheap to clone, the clones are the same token
+///
+/// ```
+/// use mco::std::sync::CancellationToken;
+///
+/// let token = CancellationToken::new();
+/// let child = token.child();
+/// let h = mco::co!(move || child.cancelled());
+/// token.cancel();
+/// h.join().unwrap();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    /// a new token that is not cancelled
+    pub fn new() -> Self {
+        CancellationToken {
+            inner: Inner::new(None),
+        }
+    }
+
+    /// a child token that is cancelled with this one, cancelling the child
+    /// doesn't affect the parent. the child of a cancelled token is cancelled
+    pub fn child(&self) -> Self {
+        CancellationToken {
+            inner: Inner::new(Some(self.inner.clone())),
+        }
+    }
+
+    /// cancel the token and its children, wake all the waiters
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// return true if the token is cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// block until the token is cancelled
+    pub fn cancelled(&self) {
+        self.inner.wait(None);
+    }
+
+    /// block until the token is cancelled or the timeout, return true if
+    /// it's cancelled
+    pub fn cancelled_timeout(&self, timeout: Duration) -> bool {
+        self.inner.wait(Some(timeout))
+    }
+
+    /// a oneshot receiver that gets `()` when the token is cancelled, so
+    /// that the cancellation can be an arm of `select!`
+    ///
+    /// ```
+    /// use mco::select;
+    /// use mco::std::sync::CancellationToken;
+    ///
+    /// let token = CancellationToken::new();
+    /// let (_tx, rx) = mco::chan!();
+    /// let stop = token.cancelled_receiver();
+    /// token.cancel();
+    /// let id = select! {
+    ///     _ = rx.recv() => {},
+    ///     _ = stop.recv() => {}
+    /// };
+    /// assert_eq!(id, 1);
+    /// # let _: &mco::std::sync::channel::Sender<()> = &_tx;
+    /// ```
+    pub fn cancelled_receiver(&self) -> oneshot::Receiver<()> {
+        self.inner.receiver()
+    }
+}
+
+// the cancellation for `select`, it's ready once the token is cancelled
+impl<'a> Selectable for &'a CancellationToken {
+    type Output = ();
+
+    fn try_select(&mut self) -> Option<()> {
+        if self.is_cancelled() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.inner.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.inner.deregister(waker);
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn cancel_tree() {
+        let root = CancellationToken::new();
+        let mids: Vec<_> = (0..4).map(|_| root.child()).collect();
+        let leaves: Vec<_> = mids
+            .iter()
+            .flat_map(|m| (0..5).map(move |_| m.child()))
+            .collect();
+        let woken = Arc::new(AtomicUsize::new(0));
+        let hs: Vec<_> = (0..100)
+            .map(|i| {
+                let token = leaves[i % leaves.len()].clone();
+                let woken = woken.clone();
+                co!(move || {
+                    token.cancelled();
+                    woken.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        // cancelling a child leaves the parent and the siblings alone
+        let h = co!({
+            let token = leaves[0].clone();
+            move || token.cancelled()
+        });
+        leaves[0].cancel();
+        h.join().unwrap();
+        assert!(!mids[0].is_cancelled());
+        assert!(!leaves[1].is_cancelled());
+        // dropping the clones doesn't cancel
+        drop(mids[1].clone());
+        assert!(!mids[1].is_cancelled());
+
+        root.cancel();
+        hs.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(woken.load(Ordering::SeqCst), 100);
+        assert!(leaves.iter().all(|l| l.is_cancelled()));
+        // the child of a cancelled token is cancelled at once
+        assert!(leaves[3].child().is_cancelled());
+    }
+
+    #[test]
+    fn cancelled_timeout() {
+        let token = CancellationToken::new();
+        assert!(!token.cancelled_timeout(Duration::from_millis(10)));
+        let t = token.clone();
+        std::thread::spawn(move || t.cancel());
+        assert!(token.cancelled_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn cancelled_receiver() {
+        let token = CancellationToken::new();
+        let rx = token.cancelled_receiver();
+        assert!(rx.try_recv().is_err());
+        token.child().cancel();
+        assert!(rx.try_recv().is_err());
+        token.cancel();
+        assert_eq!(rx.recv(), Ok(()));
+        // it fires at once after the cancellation
+        assert_eq!(token.cancelled_receiver().recv(), Ok(()));
+    }
+}
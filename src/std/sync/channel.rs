@@ -4,37 +4,356 @@
 //! it's almost the same as `mpsc` except that we support multi receivers
 //! each receiver would consume one data each time so that other receivers
 //! would not see that the same data any more
-
+//!
+//! by default a woken receiver races with newly arriving receivers for the
+//! next message, which gives the best throughput. a channel created by
+//! `with_fairness(buf, Fairness::Fifo)` instead hands each message directly to
+//! the receiver that has been blocked the longest, and for bounded channels
+//! admits blocked senders in the order they blocked. exactly one waiter is
+//! woken for each message.
+//!
+//! the messages are received in the order they are sent. a channel created
+//! by `channel_with_order(buf, Order::Lifo)` hands out the newest queued
+//! message first instead, like a stack, for the work queues whose fresh jobs
+//! are hot in the cache. its bound and its wakeups are the same, only the
+//! order of the messages differs, and it's the order that the iterators and
+//! the `select!` arms see too
+//!
+//! the messages that are still queued when the last receiver is dropped can
+//! never be received, they are dropped in the order they were sent by the
+//! thread or coroutine that drops the last receiver. no lock of the channel
+//! is held while they are dropped, so their destructors may use channels and
+//! locks, this one included. a message that is sent after that is given back
+//! in the error of the send
+
+use std::collections::VecDeque;
+use std::error::Error;
 use std::fmt;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+#[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+use std::io;
+use std::mem::{self, ManuallyDrop};
+use std::ops::{Deref, DerefMut};
+#[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+use std::os::unix::io::BorrowedFd;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use super::Semphore;
-use crate::std::queue::seg_queue::SegQueue;
+use parking_lot::Mutex;
+
+use super::blocking::SyncBlocker;
+use super::budget::{Charge, MemSize, MemoryBudget};
+use super::hooks::{ChanHooks, ChannelHooks, Trace};
+use super::readiness::Readiness;
+#[cfg(feature = "chan-registry")]
+use super::registry::{self, ChanStat};
+use super::{AtomicOption, Semphore};
+use crate::cancel::trigger_cancel_panic;
+use crate::coroutine::{Builder, Coroutine, JoinHandle};
+use crate::coroutine_impl::current_cancel_data;
+use crate::cqueue::{select_commit, select_wait};
+use crate::maintenance::{register_maintenance, MaintenanceGuard, MAINTENANCE_TICK};
+use crate::park::ParkError;
+use crate::scheduler::batch_wakes;
+use crate::select::{Selectable, Waker, WakerList};
+use crate::std::context::{is_cancel_panic, Context, ContextError};
+use crate::std::queue::seg_queue::{PopRef, SegQueue};
+use crate::timeout_list::now_instant;
+
+// the channels share the error types of `std::sync::mpsc`, they implement
+// `Display` and `Error`, `TryRecvError` and `RecvTimeoutError` tell an empty
+// channel or a timeout from a closed one
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
 
 /// Create an unbounded channel. if If you want to limit the number of messages, use bounded channel_buf()
+#[cfg_attr(feature = "chan-registry", track_caller)]
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     bounded(usize::MAX)
 }
 
 /// Create a bounded mpmc channel(More producers, more consumers)
+#[cfg_attr(feature = "chan-registry", track_caller)]
 pub fn channel_buf<T>(buf: usize) -> (Sender<T>, Receiver<T>) {
     bounded(buf)
 }
 
 /// Create an unbounded channel. if If you want to limit the number of messages, use bounded channel_buf()
+#[cfg_attr(feature = "chan-registry", track_caller)]
 pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
     bounded(usize::MAX)
 }
 
 /// Create a bounded channel
+#[cfg_attr(feature = "chan-registry", track_caller)]
 pub fn bounded<T>(buf: usize) -> (Sender<T>, Receiver<T>) {
     let a = Arc::new(MPMCBuffer::new_buffer(buf));
     (Sender::new(a.clone()), Receiver::new(a))
 }
 
+/// Create a bounded channel with the given wakeup policy, use `usize::MAX` as buf for an unbounded one
+#[cfg_attr(feature = "chan-registry", track_caller)]
+pub fn with_fairness<T>(buf: usize, fairness: Fairness) -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(MPMCBuffer::new_with_fairness(buf, fairness));
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Create a bounded channel that hands out the messages in the order, use
+/// `usize::MAX` as buf for an unbounded one
+///
+/// ```
+/// use mco::std::sync::channel::{channel_with_order, Order};
+///
+/// let (tx, rx) = channel_with_order(16, Order::Lifo);
+/// for job in 0..3 {
+///     tx.send(job).unwrap();
+/// }
+/// assert_eq!(rx.try_iter().collect::<Vec<_>>(), [2, 1, 0]);
+/// ```
+#[cfg_attr(feature = "chan-registry", track_caller)]
+pub fn channel_with_order<T>(buf: usize, order: Order) -> (Sender<T>, Receiver<T>) {
+    let mut a = MPMCBuffer::new_buffer(buf);
+    if order == Order::Lifo {
+        a.buffer = MsgQueue::Lifo(Lifo::default());
+    }
+    let a = Arc::new(a);
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Create an unbounded channel that charges each queued message to `budget`
+///
+/// the charge is the `MemSize` of the message, it's given back when the
+/// message is received or dropped. when the budget is used up `send` blocks
+/// or fails by the `BudgetPolicy` of the budget, `try_send` always fails
+#[cfg_attr(feature = "chan-registry", track_caller)]
+pub fn channel_with_budget<T: MemSize>(budget: &MemoryBudget) -> (Sender<T>, Receiver<T>) {
+    let mut buf = MPMCBuffer::new_buffer(usize::MAX);
+    buf.budget = Some(ChanBudget {
+        budget: budget.clone(),
+        size: T::mem_size,
+    });
+    let a = Arc::new(buf);
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Create an unbounded channel that runs the `hooks` on each message
+///
+/// the hooks see the messages that are sent, received and dropped unreceived
+/// with their sequence number and send time, see `hooks::ChannelHooks`
+#[cfg_attr(feature = "chan-registry", track_caller)]
+pub fn channel_with_hooks<T, H>(hooks: H) -> (Sender<T>, Receiver<T>)
+where
+    H: ChannelHooks<T> + 'static,
+{
+    let mut buf = MPMCBuffer::new_buffer(usize::MAX);
+    buf.hooks = Some(ChanHooks::new(hooks));
+    let a = Arc::new(buf);
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Create a channel that counts the messages sent and received for `stats`
+///
+/// the other channels leave the totals of their stats as `None`, so that
+/// their sends and receives don't pay for the counters
+#[cfg_attr(feature = "chan-registry", track_caller)]
+pub fn channel_with_stats<T>(buf: usize, fairness: Fairness) -> (Sender<T>, Receiver<T>) {
+    let mut buf = MPMCBuffer::new_with_fairness(buf, fairness);
+    buf.counters = Some(ChanCounters::default());
+    let a = Arc::new(buf);
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// a snapshot of a channel, see `Sender::stats` and `Receiver::stats`
+///
+/// the fields are read one by one with relaxed loads while the channel is
+/// in use, so they are approximate and may not add up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// the queued messages
+    pub len: usize,
+    /// the bound of the channel, `None` for an unbounded one
+    pub capacity: Option<usize>,
+    /// the receivers that are blocked on an empty channel
+    pub waiting_receivers: usize,
+    /// the senders that are blocked on a full channel
+    pub waiting_senders: usize,
+    /// the messages sent so far, `None` if it's not a `channel_with_stats`
+    pub total_sent: Option<u64>,
+    /// the messages received so far, `None` if it's not a `channel_with_stats`
+    pub total_received: Option<u64>,
+}
+
+/// the counts of a pump of `Receiver::forward`, returned when it exits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ForwardStats {
+    /// the messages sent downstream
+    pub forwarded: u64,
+    /// the messages that are received but can't be sent, the downstream
+    /// receivers are gone
+    pub dropped: u64,
+}
+
+/// The wakeup policy of a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fairness {
+    /// blocked receivers (and blocked senders of a bounded channel) are served
+    /// strictly in the order they blocked, the message is handed to the woken
+    /// receiver directly so a later arrival can't overtake it
+    Fifo,
+    /// a woken receiver competes with new arrivals for the next message,
+    /// this is the default policy
+    Throughput,
+}
+
+impl Default for Fairness {
+    fn default() -> Self {
+        Fairness::Throughput
+    }
+}
+
+/// The order that a channel hands out its queued messages in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// the oldest message first, this is the default order
+    Fifo,
+    /// the newest message first
+    Lifo,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Fifo
+    }
+}
+
+/// the error of `Sender::send_all` when all the receivers are gone
+///
+/// `sent` messages are already in the channel, the others are returned in `remain`
+#[derive(PartialEq, Eq, Clone)]
+pub struct SendAllError<T> {
+    pub sent: usize,
+    pub remain: Vec<T>,
+}
+
+impl<T> fmt::Debug for SendAllError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendAllError")
+            .field("sent", &self.sent)
+            .field("remain", &self.remain.len())
+            .finish()
+    }
+}
+
+impl<T> fmt::Display for SendAllError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sending on a closed channel, sent {} messages, {} left",
+            self.sent,
+            self.remain.len()
+        )
+    }
+}
+
+impl<T: Send> Error for SendAllError<T> {}
+
+/// the error of `Receiver::recv_ctx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvCtxError {
+    /// the channel is empty and all the senders are gone
+    Disconnected,
+    /// the context is done before a message is received
+    Done(ContextError),
+}
+
+impl fmt::Display for RecvCtxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecvCtxError::Disconnected => f.write_str("receiving on a closed channel"),
+            RecvCtxError::Done(e) => write!(f, "receiving on a channel: {}", e),
+        }
+    }
+}
+
+impl Error for RecvCtxError {}
+
+/// the error of `Sender::send_ctx`
+///
+/// the message is dropped if the context is done while the sender waits
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum SendCtxError<T> {
+    /// all the receivers are gone, the message is returned
+    Disconnected(T),
+    /// the context is done before the message is sent
+    Done(ContextError),
+}
+
+impl<T> fmt::Debug for SendCtxError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendCtxError::Disconnected(_) => f.write_str("Disconnected(..)"),
+            SendCtxError::Done(e) => write!(f, "Done({:?})", e),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendCtxError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendCtxError::Disconnected(_) => f.write_str("sending on a closed channel"),
+            SendCtxError::Done(e) => write!(f, "sending on a channel: {}", e),
+        }
+    }
+}
+
+impl<T: Send> Error for SendCtxError<T> {}
+
+/// the error of `Sender::send_and_wait`
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum AckError<T> {
+    /// all the receivers are gone, the message is returned
+    Disconnected(T),
+    /// the receiver dropped the `Ack` without `complete`, or the message is
+    /// dropped with the channel
+    Dropped,
+    /// the message is not acknowledged in time, it may still be processed
+    Timeout,
+}
+
+impl<T> fmt::Debug for AckError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AckError::Disconnected(_) => f.write_str("Disconnected(..)"),
+            AckError::Dropped => f.write_str("Dropped"),
+            AckError::Timeout => f.write_str("Timeout"),
+        }
+    }
+}
+
+impl<T> fmt::Display for AckError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AckError::Disconnected(_) => f.write_str("sending on a closed channel"),
+            AckError::Dropped => f.write_str("the message is dropped without acknowledgement"),
+            AckError::Timeout => f.write_str("timed out waiting for the acknowledgement"),
+        }
+    }
+}
+
+impl<T: Send> Error for AckError<T> {}
+
+/// recover the message from a `SendError`
+pub trait SendErrorExt<T> {
+    /// the message that is not sent
+    fn into_inner(self) -> T;
+}
+
+impl<T> SendErrorExt<T> for SendError<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
 /// create an channel(mpmc)
 ///  for example:
 /// ```
@@ -66,226 +385,1621 @@ macro_rules! chan {
 /// MPMCBuffer
 /// /////////////////////////////////////////////////////////////////////////////
 struct MPMCBuffer<T> {
-    buffer: SegQueue<T>,
+    buffer: MsgQueue<T>,
     // chan buffer length limit. Exceeding this limit will be wait.
     buffer_limit: usize,
     // thread/coroutine for wake up
     wake_recv: Semphore,
     // thread/coroutine for wake up
     wake_sender: Semphore,
+    // the slots held by the permits of `Sender::reserve`
+    reserved: AtomicUsize,
     // The number of sender channels which are currently using this queue.
     sender_num: AtomicUsize,
     // The number of receiver
     receiver_num: AtomicUsize,
+    // set by `close`, it's disconnected for both sides while the handles are alive
+    closed: AtomicBool,
+    // the wait lists for `Fairness::Fifo`, when set the other buffer fields are not used
+    fifo: Option<Mutex<FifoState<T>>>,
+    // the pump coroutines that feed this channel, canceled when all the receivers are gone
+    pumps: Mutex<Vec<Coroutine>>,
+    // the notification for the external event loops, see `Receiver::on_ready`
+    ready: Readiness,
+    // the select wakers of the receive side and the send side
+    recv_selectors: WakerList,
+    send_selectors: WakerList,
+    // the entry in the channel registry, see `channel_dump`
+    #[cfg(feature = "chan-registry")]
+    stat: Arc<ChanStat>,
+    // the budget that the queued messages are charged to
+    budget: Option<ChanBudget<T>>,
+    // the hooks of `channel_with_hooks`, the fifo channels have none
+    hooks: Option<ChanHooks<T>>,
+    // the messages of the `RtSender`s whose receive wakeups are deferred
+    rt_pending: AtomicUsize,
+    // the `RtSender`s sent without notifying the selects and the readiness
+    rt_notify: AtomicBool,
+    // the totals of `channel_with_stats`
+    counters: Option<ChanCounters>,
 }
 
-impl<T> MPMCBuffer<T> {
-    /// have buffer channel. If the buffered message exceeds the limit, the sender blocks until the message is consumed
-    pub fn new_buffer(buffer: usize) -> MPMCBuffer<T> {
-        MPMCBuffer {
-            buffer: SegQueue::new(),
-            wake_recv: Semphore::new(0),
-            wake_sender: Semphore::new(0),
-            buffer_limit: buffer,
-            sender_num: AtomicUsize::new(1),
-            receiver_num: AtomicUsize::new(1),
+// the queued messages of a channel that is not `Fairness::Fifo`
+enum MsgQueue<T> {
+    Fifo(SegQueue<Msg<T>>),
+    Lifo(Lifo<Msg<T>>),
+}
+
+// the stack of `Order::Lifo`, the length is read without the lock
+struct Lifo<T> {
+    stack: Mutex<Vec<T>>,
+    len: AtomicUsize,
+}
+
+impl<T> Default for Lifo<T> {
+    fn default() -> Self {
+        Lifo {
+            stack: Mutex::new(Vec::new()),
+            len: AtomicUsize::new(0),
         }
     }
+}
 
-    /// send one message. If the length limit is exceeded or chan closed, wait for the message to be consumed
-    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
-        if self.receiver_num.load(Ordering::Acquire) == 0 {
-            return Err(SendError(t));
-        }
-        loop {
-            if self.buffer.len() >= self.buffer_limit {
-                self.wake_sender.wait();
-            } else {
-                break;
+impl<T> MsgQueue<T> {
+    #[inline]
+    fn push(&self, t: Msg<T>) {
+        match self {
+            MsgQueue::Fifo(q) => q.push(t),
+            MsgQueue::Lifo(l) => {
+                let mut stack = l.stack.lock();
+                stack.push(t);
+                l.len.store(stack.len(), Ordering::Release);
             }
         }
-        self.buffer.push(t);
-        self.wake_recv.post();
-        Ok(())
     }
 
-    /// try send one message.If the length limit is exceeded or chan closed, return a error
-    pub fn try_send(&self, t: T) -> Result<(), SendError<T>> {
-        if self.receiver_num.load(Ordering::Acquire) == 0 {
-            return Err(SendError(t));
-        }
-        if self.buffer.len() >= self.buffer_limit {
-            return Err(SendError(t));
+    // only the fifo queue has the blocks to push into without allocating
+    #[inline]
+    fn push_no_alloc(&self, t: Msg<T>) -> Result<(), Msg<T>> {
+        match self {
+            MsgQueue::Fifo(q) => q.push_no_alloc(t),
+            MsgQueue::Lifo(_) => Err(t),
         }
-        self.buffer.push(t);
-        self.wake_recv.post();
-        Ok(())
     }
 
-    /// wake one sender
     #[inline]
-    fn wake_sender(&self) {
-        self.wake_sender.post();
+    fn pop(&self) -> Option<Msg<T>> {
+        match self {
+            MsgQueue::Fifo(q) => q.pop(),
+            MsgQueue::Lifo(l) => {
+                let mut stack = l.stack.lock();
+                let t = stack.pop();
+                l.len.store(stack.len(), Ordering::Release);
+                t
+            }
+        }
     }
 
-    /// received a message. If the message is empty, a wait is entered, and an error is returned if the channel is closed
-    /// If you want to try to receive a message, use try_recv
-    pub fn recv(&self, dur: Option<Duration>) -> Result<T, RecvTimeoutError> {
-        match self.try_recv() {
-            Ok(data) => return Ok(data),
-            Err(TryRecvError::Empty) => {}
-            Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+    // the fifo queue leaves the message in its slot
+    #[inline]
+    fn claim(&self) -> Option<Claimed<'_, T>> {
+        match self {
+            MsgQueue::Fifo(q) => q.pop_ref().map(Claimed::Slot),
+            MsgQueue::Lifo(_) => self.pop().map(Claimed::Moved),
         }
+    }
 
-        match dur {
-            None => self.wake_recv.wait(),
-            Some(t) => {
-                if !self.wake_recv.wait_timeout(t) {
-                    return Err(RecvTimeoutError::Timeout);
-                }
-            }
+    #[inline]
+    fn len(&self) -> usize {
+        match self {
+            MsgQueue::Fifo(q) => q.len(),
+            MsgQueue::Lifo(l) => l.len.load(Ordering::Acquire),
         }
+    }
 
-        match self.buffer.pop() {
-            Some(data) => {
-                self.wake_sender();
-                Ok(data)
-            }
-            None => match self.sender_num.load(Ordering::Acquire) {
-                0 => Err(RecvTimeoutError::Disconnected),
-                _n => unreachable!("mpmc recv found no data"),
-            },
+    fn reserve_blocks(&self, n: usize) {
+        if let MsgQueue::Fifo(q) = self {
+            q.reserve_blocks(n);
         }
     }
 
-    pub fn try_recv(&self) -> Result<T, TryRecvError> {
-        if !self.wake_recv.try_wait() {
-            return match self.sender_num.load(Ordering::Acquire) {
-                0 => Err(TryRecvError::Disconnected),
-                _ => Err(TryRecvError::Empty),
-            };
+    fn allocated_bytes(&self) -> usize {
+        match self {
+            MsgQueue::Fifo(q) => q.allocated_bytes(),
+            MsgQueue::Lifo(l) => l.stack.lock().capacity() * mem::size_of::<Msg<T>>(),
         }
+    }
 
-        match self.buffer.pop() {
-            Some(data) => {
-                self.wake_sender();
-                Ok(data)
-            }
-            None => match self.sender_num.load(Ordering::Acquire) {
-                0 => Err(TryRecvError::Disconnected),
-                _ => unreachable!("mpmc try_recv found no data"),
-            },
+    fn shrink_to_fit(&self) {
+        if let MsgQueue::Lifo(l) = self {
+            l.stack.lock().shrink_to_fit();
         }
     }
 
-    pub fn clone_send(&self) {
-        self.sender_num.fetch_add(1, Ordering::SeqCst);
+    #[cfg(test)]
+    fn segments(&self) -> usize {
+        match self {
+            MsgQueue::Fifo(q) => q.segments(),
+            MsgQueue::Lifo(_) => 1,
+        }
     }
+}
 
-    pub fn drop_send(&self) {
-        match self.sender_num.fetch_sub(1, Ordering::SeqCst) {
-            1 => {
-                // there is no send_ports any more
-                // should tell all the waited recv to come back
-                while self.wake_recv.get_value() == 0 {
-                    self.wake_recv.post();
-                }
-            }
-            n if n > 1 => {}
-            n => panic!("bad number of send_ports left {}", n),
+struct ChanBudget<T> {
+    budget: MemoryBudget,
+    size: fn(&T) -> usize,
+}
+
+// the totals of `channel_with_stats`
+#[derive(Default)]
+struct ChanCounters {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+/// a queued message, with the acknowledgement of `Sender::send_and_wait`,
+/// the charge of `channel_with_budget` that is given back on drop and the
+/// trace of `channel_with_hooks`
+struct Msg<T> {
+    t: T,
+    ack: Ack,
+    charge: Option<Charge>,
+    trace: Option<Trace>,
+}
+
+impl<T> Msg<T> {
+    #[inline]
+    fn new(t: T) -> Self {
+        Msg {
+            t,
+            ack: Ack(None),
+            charge: None,
+            trace: None,
         }
     }
 
-    pub fn clone_recv(&self) {
-        self.receiver_num.fetch_add(1, Ordering::SeqCst);
+    // the message is taken by a plain receive, that's the acknowledgement
+    #[inline]
+    fn take(self) -> T {
+        self.ack.complete();
+        self.t
     }
 
-    pub fn drop_recv(&self) {
-        match self.receiver_num.fetch_sub(1, Ordering::SeqCst) {
-            1 => {
-                // there is no receiver any more, clear the data
-                while self.buffer.pop().is_some() {}
-            }
-            n if n > 1 => {}
-            n => panic!("bad number of recv_ports left {}", n),
+    // the message is not sent, the sender handles the error itself
+    #[inline]
+    fn into_inner(self) -> T {
+        if let Some(slot) = &self.ack.0 {
+            slot.state.store(ACK_ABANDONED, Ordering::Release);
         }
+        self.t
     }
+}
 
-    /// return remain msg len
-    pub fn remain(&self) -> usize {
-        self.buffer.len()
-    }
+// a message that a receiver has claimed, in its slot of the buffer or moved
+// out of the fifo lists
+enum Claimed<'a, T> {
+    Slot(PopRef<'a, Msg<T>>),
+    Moved(Msg<T>),
+}
 
-    pub fn sender_num(&self) -> usize {
-        self.sender_num.load(Ordering::SeqCst)
+impl<'a, T> Claimed<'a, T> {
+    #[inline]
+    fn into_msg(self) -> Msg<T> {
+        match self {
+            Claimed::Slot(m) => m.take(),
+            Claimed::Moved(m) => m,
+        }
     }
 
-    pub fn receiver_num(&self) -> usize {
-        self.receiver_num.load(Ordering::SeqCst)
+    #[inline]
+    fn msg(&self) -> &Msg<T> {
+        match self {
+            Claimed::Slot(m) => m,
+            Claimed::Moved(m) => m,
+        }
     }
-}
 
-impl<T> Drop for MPMCBuffer<T> {
-    fn drop(&mut self) {
-        assert_eq!(self.sender_num.load(Ordering::Acquire), 0);
-        assert_eq!(self.receiver_num.load(Ordering::Acquire), 0);
+    #[inline]
+    fn msg_mut(&mut self) -> &mut Msg<T> {
+        match self {
+            Claimed::Slot(m) => m,
+            Claimed::Moved(m) => m,
+        }
     }
 }
 
-pub struct Receiver<T> {
-    inner: Arc<MPMCBuffer<T>>,
+const ACK_PENDING: usize = 0;
+const ACK_DONE: usize = 1;
+const ACK_DROPPED: usize = 2;
+// the sender doesn't wait any more
+const ACK_ABANDONED: usize = 3;
+
+// the acknowledgement that one sender waits for, reused across its calls
+struct AckSlot {
+    state: AtomicUsize,
+    blocker: AtomicOption<Arc<SyncBlocker>>,
 }
 
-impl<T> Receiver<T> {
-    /// return remain msg len
-    pub fn remain(&self) -> usize {
-        self.inner.remain()
+impl AckSlot {
+    fn finish(&self, state: usize) {
+        if self
+            .state
+            .compare_exchange(ACK_PENDING, state, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            if let Some(b) = self.blocker.take() {
+                let _ = b.unpark();
+            }
+        }
     }
+}
 
-    /// Number of channel senders
-    pub fn sender_num(&self) -> usize {
-        self.inner.sender_num()
+/// the acknowledgement of a message received by `Receiver::recv_ack`
+///
+/// the sender that waits in `Sender::send_and_wait` is woken by `complete`,
+/// or with `AckError::Dropped` when the `Ack` is dropped without it. the
+/// `Ack` of a message sent by the plain `send` does nothing
+#[must_use = "dropping the ack without `complete` fails the sender"]
+pub struct Ack(Option<Arc<AckSlot>>);
+
+impl Ack {
+    /// acknowledge the message, the sender returns `Ok`
+    pub fn complete(mut self) {
+        if let Some(slot) = self.0.take() {
+            slot.finish(ACK_DONE);
+        }
     }
 
-    /// Number of channel receiver
-    pub fn receiver_num(&self) -> usize {
-        self.inner.receiver_num()
+    /// return true if a sender waits for this acknowledgement
+    pub fn is_waited(&self) -> bool {
+        match &self.0 {
+            Some(slot) => slot.state.load(Ordering::Acquire) == ACK_PENDING,
+            None => false,
+        }
     }
 }
 
-unsafe impl<T: Send> Send for Receiver<T> {}
-// impl<T> !Sync for Receiver<T> {}
-
-pub struct Iter<'a, T: 'a> {
-    inner: &'a Receiver<T>,
+impl Drop for Ack {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0.take() {
+            slot.finish(ACK_DROPPED);
+        }
+    }
 }
 
-pub struct TryIter<'a, T: 'a> {
-    inner: &'a Receiver<T>,
+impl fmt::Debug for Ack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ack")
+            .field("waited", &self.is_waited())
+            .finish()
+    }
 }
 
-pub struct IntoIter<T> {
-    inner: Receiver<T>,
+/// the state of a `Fairness::Fifo` channel, protected by one lock
+struct FifoState<T> {
+    buffer: VecDeque<Msg<T>>,
+    // receivers blocked on an empty channel, in blocking order
+    recv_waiters: VecDeque<Arc<FifoWaiter<T>>>,
+    // senders and reservers blocked on a full channel, in blocking order
+    send_waiters: VecDeque<Arc<FifoWaiter<T>>>,
+    // the slots held by the permits of `Sender::reserve`
+    reserved: usize,
 }
 
-pub struct Sender<T> {
-    inner: Arc<MPMCBuffer<T>>,
+struct FifoWaiter<T> {
+    // the message handed to a receiver, or the pending message of a sender
+    slot: AtomicOption<Msg<T>>,
+    // set by the other side when the waiter is removed from the wait list
+    woken: AtomicBool,
+    // set under the lock when a reserver gets its slot
+    admitted: AtomicBool,
+    blocker: Arc<SyncBlocker>,
 }
 
-impl<T> Sender<T> {
-    /// return remain msg len
-    pub fn remain(&self) -> usize {
-        self.inner.remain()
-    }
-
-    /// Number of channel senders
-    pub fn sender_num(&self) -> usize {
-        self.inner.sender_num()
+impl<T> FifoWaiter<T> {
+    fn new(slot: Option<Msg<T>>) -> Arc<Self> {
+        Arc::new(FifoWaiter {
+            slot: match slot {
+                Some(t) => AtomicOption::some(t),
+                None => AtomicOption::none(),
+            },
+            woken: AtomicBool::new(false),
+            admitted: AtomicBool::new(false),
+            blocker: SyncBlocker::current(),
+        })
     }
 
-    /// Number of channel receiver
-    pub fn receiver_num(&self) -> usize {
-        self.inner.receiver_num()
+    // must be called after the waiter is removed from the wait list
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        let _ = self.blocker.unpark();
+    }
+}
+
+impl<T> FifoState<T> {
+    // pop the oldest message and admit the oldest blocked sender
+    fn pop(&mut self) -> Option<(Msg<T>, Option<Arc<FifoWaiter<T>>>)> {
+        let t = self.buffer.pop_front()?;
+        let sender = self.send_waiters.pop_front().map(|w| {
+            match w.slot.take() {
+                Some(v) => self.buffer.push_back(v),
+                // a reserver takes the slot
+                None => {
+                    self.reserved += 1;
+                    w.admitted.store(true, Ordering::Release);
+                }
+            }
+            w
+        });
+        Some((t, sender))
+    }
+
+    // the slots that are taken by the messages and the permits
+    #[inline]
+    fn occupied(&self) -> usize {
+        self.buffer.len() + self.reserved
+    }
+
+    // admit the blocked senders and reservers while there is room, return
+    // the waiters to wake
+    fn admit(&mut self, limit: usize) -> Vec<Arc<FifoWaiter<T>>> {
+        let mut woken = Vec::new();
+        while self.occupied() < limit {
+            let w = match self.send_waiters.pop_front() {
+                Some(w) => w,
+                None => break,
+            };
+            match w.slot.take() {
+                Some(v) => woken.extend(self.push(v, false)),
+                None => {
+                    self.reserved += 1;
+                    w.admitted.store(true, Ordering::Release);
+                }
+            }
+            woken.push(w);
+        }
+        woken
+    }
+
+    // hand the message to the oldest blocked receiver or buffer it
+    fn push(&mut self, t: Msg<T>, front: bool) -> Option<Arc<FifoWaiter<T>>> {
+        match self.recv_waiters.pop_front() {
+            Some(w) => {
+                w.slot.store(t);
+                Some(w)
+            }
+            None => {
+                if front {
+                    self.buffer.push_front(t);
+                } else {
+                    self.buffer.push_back(t);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl<T> MPMCBuffer<T> {
+    /// have buffer channel. If the buffered message exceeds the limit, the sender blocks until the message is consumed
+    #[cfg_attr(feature = "chan-registry", track_caller)]
+    pub fn new_buffer(buffer: usize) -> MPMCBuffer<T> {
+        Self::new_with_fairness(buffer, Fairness::Throughput)
+    }
+
+    /// have buffer channel with the given wakeup policy
+    #[cfg_attr(feature = "chan-registry", track_caller)]
+    pub fn new_with_fairness(buffer: usize, fairness: Fairness) -> MPMCBuffer<T> {
+        let fifo = match fairness {
+            Fairness::Fifo => Some(Mutex::new(FifoState {
+                buffer: VecDeque::new(),
+                recv_waiters: VecDeque::new(),
+                send_waiters: VecDeque::new(),
+                reserved: 0,
+            })),
+            Fairness::Throughput => None,
+        };
+        MPMCBuffer {
+            buffer: MsgQueue::Fifo(SegQueue::new()),
+            wake_recv: Semphore::new(0),
+            wake_sender: Semphore::new(0),
+            buffer_limit: buffer,
+            reserved: AtomicUsize::new(0),
+            sender_num: AtomicUsize::new(1),
+            receiver_num: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            fifo,
+            pumps: Mutex::new(Vec::new()),
+            ready: Readiness::new(),
+            recv_selectors: WakerList::new(),
+            send_selectors: WakerList::new(),
+            #[cfg(feature = "chan-registry")]
+            stat: registry::register(),
+            budget: None,
+            hooks: None,
+            rt_pending: AtomicUsize::new(0),
+            rt_notify: AtomicBool::new(false),
+            counters: None,
+        }
+    }
+
+    // wrap the message, it's traced when the channel has hooks
+    #[inline]
+    fn msg(&self, t: T) -> Msg<T> {
+        let mut m = Msg::new(t);
+        if let Some(h) = &self.hooks {
+            m.trace = Some(h.sent(&m.t));
+        }
+        m
+    }
+
+    // run the receive hook
+    #[inline]
+    fn traced(&self, m: &Msg<T>) {
+        if let Some(h) = &self.hooks {
+            if let Some(trace) = &m.trace {
+                h.received(&m.t, trace);
+            }
+        }
+    }
+
+    // the message is taken by a plain receive
+    #[inline]
+    fn received(&self, m: Msg<T>) -> T {
+        self.traced(&m);
+        self.count_received(1);
+        m.take()
+    }
+
+    #[inline]
+    fn count_sent(&self, n: usize) {
+        if let Some(c) = &self.counters {
+            c.sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    fn count_received(&self, n: usize) {
+        if let Some(c) = &self.counters {
+            c.received.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+
+    // drop a message that is never received
+    fn discard(&self, m: Msg<T>) {
+        if let Some(h) = &self.hooks {
+            if let Some(trace) = &m.trace {
+                h.dropped(&m.t, trace);
+            }
+        }
+        drop(m);
+    }
+
+    // charge the message to the budget, a blocked charge gives up when the
+    // channel is closed
+    fn charge(&self, mut t: Msg<T>, block: bool) -> Result<Msg<T>, SendError<Msg<T>>> {
+        if let Some(b) = &self.budget {
+            let bytes = (b.size)(&t.t);
+            let charge = if block {
+                b.budget.charge(bytes, &|| self.send_closed())
+            } else {
+                b.budget.try_charge(bytes)
+            };
+            match charge {
+                Some(c) => t.charge = Some(c),
+                None => return Err(SendError(t)),
+            }
+        }
+        Ok(t)
+    }
+
+    // wake the senders blocked by the budget, they see the channel closed
+    fn wake_budget(&self) {
+        if let Some(b) = &self.budget {
+            b.budget.wake();
+        }
+    }
+
+    fn fifo_send(
+        &self,
+        fifo: &Mutex<FifoState<T>>,
+        t: Msg<T>,
+        block: bool,
+    ) -> Result<(), SendError<Msg<T>>> {
+        let mut state = fifo.lock();
+        if self.send_closed() {
+            return Err(SendError(t));
+        }
+        if !state.recv_waiters.is_empty() || state.occupied() < self.buffer_limit {
+            let waiter = state.push(t, false);
+            drop(state);
+            self.count_sent(1);
+            match waiter {
+                Some(w) => w.wake(),
+                None => self.notify_recv(),
+            }
+            return Ok(());
+        }
+        if !block {
+            return Err(SendError(t));
+        }
+
+        // the channel is full, wait in line with the message
+        let cur = FifoWaiter::new(Some(t));
+        state.send_waiters.push_back(cur.clone());
+        drop(state);
+        #[cfg(feature = "chan-registry")]
+        let _parked = self.stat.parked(true);
+        let ret = cur.blocker.park(None);
+
+        let mut state = fifo.lock();
+        if !cur.woken.load(Ordering::Acquire) {
+            state.send_waiters.retain(|w| !Arc::ptr_eq(w, &cur));
+        }
+        drop(state);
+        // the message is left in the slot when all the receivers are gone
+        let left = cur.slot.take();
+        if ret == Err(ParkError::Canceled) {
+            trigger_cancel_panic();
+        }
+        match left {
+            None => {
+                self.count_sent(1);
+                Ok(())
+            }
+            Some(t) => Err(SendError(t)),
+        }
+    }
+
+    fn fifo_recv(
+        &self,
+        fifo: &Mutex<FifoState<T>>,
+        dur: Option<Duration>,
+    ) -> Result<Msg<T>, RecvTimeoutError> {
+        loop {
+            match self.fifo_recv_once(fifo, dur) {
+                // another arm of the select won, the retry waits the whole
+                // timeout again
+                Err(None) => select_wait(),
+                Err(Some(e)) => return Err(e),
+                Ok(t) => return Ok(t),
+            }
+        }
+    }
+
+    // `Err(None)` if the message is left to the others because another arm
+    // of the select won
+    fn fifo_recv_once(
+        &self,
+        fifo: &Mutex<FifoState<T>>,
+        dur: Option<Duration>,
+    ) -> Result<Msg<T>, Option<RecvTimeoutError>> {
+        let mut state = fifo.lock();
+        if !state.buffer.is_empty() {
+            if !select_commit() {
+                if dur == Some(Duration::from_nanos(0)) {
+                    return Err(Some(RecvTimeoutError::Timeout));
+                }
+                return Err(None);
+            }
+            let (t, sender) = state.pop().expect("fifo buffer is empty");
+            drop(state);
+            match sender {
+                Some(w) => w.wake(),
+                None => self.send_selectors.wake_all(),
+            }
+            return Ok(t);
+        }
+        if self.is_disconnected() {
+            return Err(Some(RecvTimeoutError::Disconnected));
+        }
+        // the senders notify after the lock is released, nothing is missed
+        self.ready.arm(|| false);
+        if dur == Some(Duration::from_nanos(0)) {
+            return Err(Some(RecvTimeoutError::Timeout));
+        }
+
+        // wait in line until a sender hands us a message
+        let cur = FifoWaiter::new(None);
+        state.recv_waiters.push_back(cur.clone());
+        drop(state);
+        #[cfg(feature = "chan-registry")]
+        let _parked = self.stat.parked(false);
+        let ret = cur.blocker.park(dur);
+
+        let mut state = fifo.lock();
+        if !cur.woken.load(Ordering::Acquire) {
+            state.recv_waiters.retain(|w| !Arc::ptr_eq(w, &cur));
+        }
+        let got = cur.slot.take();
+        let canceled = ret == Err(ParkError::Canceled);
+        if canceled || (got.is_some() && !select_commit()) {
+            // give the message to the next one in line
+            let waiter = got.and_then(|t| state.push(t, true));
+            drop(state);
+            if let Some(w) = waiter {
+                w.wake();
+            }
+            if canceled {
+                trigger_cancel_panic();
+            }
+            return Err(None);
+        }
+        drop(state);
+        match got {
+            Some(t) => Ok(t),
+            None if self.is_disconnected() => Err(Some(RecvTimeoutError::Disconnected)),
+            None => Err(Some(RecvTimeoutError::Timeout)),
+        }
+    }
+
+    /// send one message. If the length limit is exceeded or chan closed, wait for the message to be consumed
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.send_msg(self.msg(t))
+            .map_err(|SendError(m)| SendError(m.into_inner()))
+    }
+
+    fn send_msg(&self, t: Msg<T>) -> Result<(), SendError<Msg<T>>> {
+        if self.send_closed() {
+            return Err(SendError(t));
+        }
+        let t = self.charge(t, true)?;
+        if let Some(fifo) = &self.fifo {
+            return self.fifo_send(fifo, t, true);
+        }
+        loop {
+            if self.occupied() >= self.buffer_limit {
+                #[cfg(feature = "chan-registry")]
+                let _parked = self.stat.parked(true);
+                self.wake_sender.wait();
+                if self.send_closed() {
+                    return Err(SendError(t));
+                }
+            } else {
+                break;
+            }
+        }
+        self.buffer.push(t);
+        self.count_sent(1);
+        self.wake_recv.post();
+        self.notify_recv();
+        Ok(())
+    }
+
+    /// try send one message.If the length limit is exceeded or chan closed, return a error
+    pub fn try_send(&self, t: T) -> Result<(), SendError<T>> {
+        let ret = self
+            .charge(self.msg(t), false)
+            .and_then(|t| match &self.fifo {
+                Some(fifo) => self.fifo_send(fifo, t, false),
+                None => self.try_send_msg(t),
+            });
+        ret.map_err(|SendError(m)| SendError(m.into_inner()))
+    }
+
+    fn try_send_msg(&self, t: Msg<T>) -> Result<(), SendError<Msg<T>>> {
+        if self.send_closed() {
+            return Err(SendError(t));
+        }
+        if self.occupied() >= self.buffer_limit {
+            return Err(SendError(t));
+        }
+        self.buffer.push(t);
+        self.count_sent(1);
+        self.wake_recv.post();
+        self.notify_recv();
+        Ok(())
+    }
+
+    // the send of an `RtSender`, only atomics and a push into a block that is
+    // already allocated
+    fn rt_send(&self, t: T, wakeup: RtWakeup) -> Result<(), RtSendError<T>> {
+        if self.send_closed() {
+            return Err(RtSendError::Disconnected(t));
+        }
+        if self.buffer.len() + self.reserved.load(Ordering::Acquire) >= self.buffer_limit {
+            return Err(RtSendError::Full(t));
+        }
+        if let Err(m) = self.buffer.push_no_alloc(Msg::new(t)) {
+            return Err(RtSendError::WouldAllocate(m.into_inner()));
+        }
+        self.count_sent(1);
+        match wakeup {
+            RtWakeup::Immediate => {
+                self.wake_recv.post();
+                self.notify_recv();
+            }
+            RtWakeup::Deferred => {
+                // a parked receiver is woken by the next tick
+                if !self.wake_recv.try_post_quiet() {
+                    self.rt_pending.fetch_add(1, Ordering::AcqRel);
+                }
+                self.rt_notify.store(true, Ordering::Release);
+            }
+        }
+        Ok(())
+    }
+
+    // the deferred part of the `RtSender` sends, out of the real time context
+    fn rt_flush(&self, spare_blocks: usize) {
+        let n = self.rt_pending.swap(0, Ordering::AcqRel);
+        self.wake_recv.post_many(n);
+        if self.rt_notify.swap(false, Ordering::AcqRel) || n > 0 {
+            self.notify_recv();
+        }
+        self.buffer.reserve_blocks(spare_blocks);
+    }
+
+    // push the messages that fit under one lock, then wait in line for the rest
+    fn fifo_send_all<I: Iterator<Item = T>>(
+        &self,
+        fifo: &Mutex<FifoState<T>>,
+        mut iter: I,
+    ) -> Result<usize, SendAllError<T>> {
+        let mut sent = 0;
+        let mut waiters = Vec::new();
+        let mut state = fifo.lock();
+        let mut pending = None;
+        let mut buffered = false;
+        for t in iter.by_ref() {
+            if self.send_closed() {
+                pending = Some(t);
+                break;
+            }
+            if state.recv_waiters.is_empty() && state.occupied() >= self.buffer_limit {
+                pending = Some(t);
+                break;
+            }
+            match state.push(self.msg(t), false) {
+                Some(w) => waiters.push(w),
+                None => buffered = true,
+            }
+            sent += 1;
+        }
+        drop(state);
+        self.count_sent(sent);
+        batch_wakes(|| waiters.iter().for_each(|w| w.wake()));
+        if buffered {
+            self.notify_recv();
+        }
+
+        // the channel is full, the rest are sent one by one
+        while let Some(t) = pending.take().or_else(|| iter.next()) {
+            if let Err(SendError(t)) = self.fifo_send(fifo, self.msg(t), true) {
+                let mut remain = vec![t.into_inner()];
+                remain.extend(iter);
+                return Err(SendAllError { sent, remain });
+            }
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// send all the messages, return how many are sent
+    /// for a bounded channel it waits for room when the channel is full
+    pub fn send_all<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<usize, SendAllError<T>> {
+        if self.budget.is_some() {
+            // each message is charged on its own
+            let mut iter = iter.into_iter();
+            let mut sent = 0;
+            while let Some(t) = iter.next() {
+                if let Err(SendError(t)) = self.send_msg(self.msg(t)) {
+                    let mut remain = vec![t.into_inner()];
+                    remain.extend(iter);
+                    return Err(SendAllError { sent, remain });
+                }
+                sent += 1;
+            }
+            return Ok(sent);
+        }
+        if let Some(fifo) = &self.fifo {
+            return self.fifo_send_all(fifo, iter.into_iter());
+        }
+        let mut iter = iter.into_iter().peekable();
+        let mut sent = 0;
+        while iter.peek().is_some() {
+            if self.send_closed() {
+                return Err(SendAllError {
+                    sent,
+                    remain: iter.collect(),
+                });
+            }
+            let room = self.buffer_limit.saturating_sub(self.occupied());
+            if room == 0 {
+                #[cfg(feature = "chan-registry")]
+                let _parked = self.stat.parked(true);
+                self.wake_sender.wait();
+                continue;
+            }
+            let mut n = 0;
+            for t in iter.by_ref().take(room) {
+                self.buffer.push(self.msg(t));
+                n += 1;
+            }
+            self.count_sent(n);
+            // wake the receivers for the whole batch at once
+            self.wake_recv.post_many(n);
+            self.notify_recv();
+            sent += n;
+        }
+        Ok(sent)
+    }
+
+    // the slots that are taken by the messages and the permits
+    #[inline]
+    fn occupied(&self) -> usize {
+        match &self.fifo {
+            Some(fifo) => fifo.lock().occupied(),
+            None => self.buffer.len() + self.reserved.load(Ordering::Acquire),
+        }
+    }
+
+    // take a slot if there is room
+    fn try_reserve_slot(&self) -> bool {
+        let mut r = self.reserved.load(Ordering::Acquire);
+        loop {
+            if self.buffer.len() + r >= self.buffer_limit {
+                return false;
+            }
+            match self
+                .reserved
+                .compare_exchange_weak(r, r + 1, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return true,
+                Err(v) => r = v,
+            }
+        }
+    }
+
+    /// take a slot for a later `send_reserved`, wait for room when `block`
+    pub fn reserve(&self, block: bool) -> Result<(), TrySendError<()>> {
+        if self.send_closed() {
+            return Err(TrySendError::Disconnected(()));
+        }
+        if let Some(fifo) = &self.fifo {
+            return self.fifo_reserve(fifo, block);
+        }
+        loop {
+            if self.try_reserve_slot() {
+                if self.send_closed() {
+                    self.release();
+                    return Err(TrySendError::Disconnected(()));
+                }
+                return Ok(());
+            }
+            if !block {
+                return Err(TrySendError::Full(()));
+            }
+            // nothing is taken yet, a cancel panic here leaks no slot
+            #[cfg(feature = "chan-registry")]
+            let _parked = self.stat.parked(true);
+            self.wake_sender.wait();
+            if self.send_closed() {
+                return Err(TrySendError::Disconnected(()));
+            }
+        }
+    }
+
+    fn fifo_reserve(
+        &self,
+        fifo: &Mutex<FifoState<T>>,
+        block: bool,
+    ) -> Result<(), TrySendError<()>> {
+        let mut state = fifo.lock();
+        if self.send_closed() {
+            return Err(TrySendError::Disconnected(()));
+        }
+        if state.send_waiters.is_empty() && state.occupied() < self.buffer_limit {
+            state.reserved += 1;
+            return Ok(());
+        }
+        if !block {
+            return Err(TrySendError::Full(()));
+        }
+
+        // wait in line with the senders, a reserver has no message
+        let cur = FifoWaiter::new(None);
+        state.send_waiters.push_back(cur.clone());
+        drop(state);
+        #[cfg(feature = "chan-registry")]
+        let _parked = self.stat.parked(true);
+        let ret = cur.blocker.park(None);
+
+        let mut state = fifo.lock();
+        if !cur.woken.load(Ordering::Acquire) {
+            state.send_waiters.retain(|w| !Arc::ptr_eq(w, &cur));
+        }
+        let admitted = cur.admitted.load(Ordering::Acquire);
+        if ret == Err(ParkError::Canceled) {
+            // give the slot to the next one in line before unwinding
+            let woken = if admitted {
+                state.reserved -= 1;
+                state.admit(self.buffer_limit)
+            } else {
+                Vec::new()
+            };
+            drop(state);
+            woken.iter().for_each(|w| w.wake());
+            trigger_cancel_panic();
+        }
+        drop(state);
+        if admitted {
+            Ok(())
+        } else {
+            // woken by the close or the last receiver that is gone
+            Err(TrySendError::Disconnected(()))
+        }
+    }
+
+    /// give back a reserved slot
+    pub fn release(&self) {
+        if let Some(fifo) = &self.fifo {
+            let mut state = fifo.lock();
+            state.reserved -= 1;
+            let woken = state.admit(self.buffer_limit);
+            drop(state);
+            if !woken.is_empty() {
+                woken.iter().for_each(|w| w.wake());
+                self.notify_recv();
+            }
+            self.send_selectors.wake_all();
+            return;
+        }
+        self.reserved.fetch_sub(1, Ordering::AcqRel);
+        self.wake_sender();
+    }
+
+    /// send the message in a reserved slot, it's dropped if the channel is
+    /// closed since the slot was taken
+    pub fn send_reserved(&self, t: T) {
+        let t = match self.charge(self.msg(t), true) {
+            Ok(t) => t,
+            Err(_) => return self.release(),
+        };
+        if self.send_closed() {
+            return self.release();
+        }
+        if let Some(fifo) = &self.fifo {
+            let mut state = fifo.lock();
+            state.reserved -= 1;
+            let mut woken: Vec<_> = state.push(t, false).into_iter().collect();
+            self.count_sent(1);
+            let received = !woken.is_empty();
+            // a message handed to a receiver frees the slot
+            if received {
+                woken.extend(state.admit(self.buffer_limit));
+            }
+            drop(state);
+            woken.iter().for_each(|w| w.wake());
+            if !received || woken.len() > 1 {
+                self.notify_recv();
+            }
+            return;
+        }
+        // push first so that the channel never goes over the limit
+        self.buffer.push(t);
+        self.count_sent(1);
+        self.reserved.fetch_sub(1, Ordering::AcqRel);
+        self.wake_recv.post();
+        self.notify_recv();
+    }
+
+    /// wake one sender
+    #[inline]
+    fn wake_sender(&self) {
+        self.wake_sender.post();
+        self.send_selectors.wake_all();
+    }
+
+    // a message is queued for the receive side
+    #[inline]
+    fn notify_recv(&self) {
+        self.ready.notify();
+        self.recv_selectors.wake_all();
+    }
+
+    /// received a message. If the message is empty, a wait is entered, and an error is returned if the channel is closed
+    /// If you want to try to receive a message, use try_recv
+    pub fn recv(&self, dur: Option<Duration>) -> Result<T, RecvTimeoutError> {
+        self.recv_msg(dur).map(|m| self.received(m))
+    }
+
+    fn recv_msg(&self, dur: Option<Duration>) -> Result<Msg<T>, RecvTimeoutError> {
+        self.claim(dur).map(Claimed::into_msg)
+    }
+
+    // wait for a message and claim it, it's in its slot of the buffer
+    fn claim(&self, dur: Option<Duration>) -> Result<Claimed<'_, T>, RecvTimeoutError> {
+        if let Some(fifo) = &self.fifo {
+            return self.fifo_recv(fifo, dur).map(Claimed::Moved);
+        }
+        loop {
+            match self.try_claim() {
+                Ok(data) => return Ok(data),
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+            }
+
+            #[cfg(feature = "chan-registry")]
+            let _parked = self.stat.parked(false);
+            match dur {
+                None => self.wake_recv.wait(),
+                Some(t) => {
+                    if !self.wake_recv.wait_timeout(t) {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                }
+            }
+            if select_commit() {
+                break;
+            }
+            // another arm of the select won, leave the message to the others.
+            // the retry waits the whole timeout again
+            self.wake_recv.post();
+            select_wait();
+        }
+
+        match self.buffer.claim() {
+            Some(data) => {
+                self.wake_sender();
+                Ok(data)
+            }
+            None if self.is_disconnected() => Err(RecvTimeoutError::Disconnected),
+            None => unreachable!("mpmc recv found no data"),
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.try_recv_msg().map(|m| self.received(m))
+    }
+
+    fn try_recv_msg(&self) -> Result<Msg<T>, TryRecvError> {
+        self.try_claim().map(Claimed::into_msg)
+    }
+
+    fn try_claim(&self) -> Result<Claimed<'_, T>, TryRecvError> {
+        if let Some(fifo) = &self.fifo {
+            return match self.fifo_recv(fifo, Some(Duration::from_nanos(0))) {
+                Ok(t) => Ok(Claimed::Moved(t)),
+                Err(RecvTimeoutError::Timeout) => Err(TryRecvError::Empty),
+                Err(RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+            };
+        }
+        if !self.wake_recv.try_wait() {
+            if self.is_disconnected() {
+                return Err(TryRecvError::Disconnected);
+            }
+            self.ready.arm(|| self.buffer.len() > 0);
+            return Err(TryRecvError::Empty);
+        }
+        if !select_commit() {
+            // another arm of the select won
+            self.wake_recv.post();
+            return Err(TryRecvError::Empty);
+        }
+
+        match self.buffer.claim() {
+            Some(data) => {
+                self.wake_sender();
+                Ok(data)
+            }
+            None if self.is_disconnected() => Err(TryRecvError::Disconnected),
+            None => unreachable!("mpmc try_recv found no data"),
+        }
+    }
+
+    // move up to `max` buffered messages into `buf`
+    fn fifo_drain(&self, fifo: &Mutex<FifoState<T>>, buf: &mut Vec<T>, max: usize) -> usize {
+        let mut n = 0;
+        let mut waiters = Vec::new();
+        let mut state = fifo.lock();
+        while n < max {
+            match state.pop() {
+                Some((t, sender)) => {
+                    buf.push(t.take());
+                    waiters.extend(sender);
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        drop(state);
+        self.count_received(n);
+        waiters.iter().for_each(|w| w.wake());
+        if n > 0 {
+            self.send_selectors.wake_all();
+        }
+        n
+    }
+
+    /// move up to `max` available messages into `buf`, return how many are received
+    /// it blocks only when there is no message at all
+    pub fn recv_many(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        if let Some(fifo) = &self.fifo {
+            let n = self.fifo_drain(fifo, buf, max);
+            if n > 0 {
+                return Ok(n);
+            }
+            let t = self.fifo_recv(fifo, None).map_err(|_| RecvError)?;
+            buf.push(self.received(t));
+            return Ok(1 + self.fifo_drain(fifo, buf, max - 1));
+        }
+
+        let t = self.recv(None).map_err(|_| RecvError)?;
+        buf.push(t);
+        let permits = self.wake_recv.try_wait_many(max - 1);
+        let mut n = 0;
+        while n < permits {
+            match self.buffer.pop() {
+                Some(t) => {
+                    buf.push(self.received(t));
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        // the disconnect permits are given back for the other receivers
+        self.wake_recv.post_many(permits - n);
+        self.wake_sender.post_many(n);
+        self.send_selectors.wake_all();
+        Ok(1 + n)
+    }
+
+    // the senders fail once all the receivers are gone or it's closed
+    #[inline]
+    fn send_closed(&self) -> bool {
+        self.receiver_num.load(Ordering::Acquire) == 0 || self.closed.load(Ordering::Acquire)
+    }
+
+    // the receivers fail on an empty channel once all the senders are gone or it's closed
+    #[inline]
+    fn is_disconnected(&self) -> bool {
+        self.sender_num.load(Ordering::Acquire) == 0 || self.closed.load(Ordering::Acquire)
+    }
+
+    /// close the channel, the sends fail and the receivers drain the buffered
+    /// messages before they get the disconnect. the blocked calls come back
+    pub fn close(&self) {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.ready.notify_closed();
+        self.wake_budget();
+        self.recv_selectors.wake_all();
+        self.send_selectors.wake_all();
+        if let Some(fifo) = &self.fifo {
+            let mut state = fifo.lock();
+            let mut waiters: Vec<_> = state.recv_waiters.drain(..).collect();
+            waiters.extend(state.send_waiters.drain(..));
+            drop(state);
+            waiters.iter().for_each(|w| w.wake());
+            return;
+        }
+        while self.wake_recv.get_value() == 0 {
+            self.wake_recv.post();
+        }
+        while self.wake_sender.get_value() == 0 {
+            self.wake_sender.post();
+        }
+    }
+
+    /// return true if the channel is closed by `close`
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub fn clone_send(&self) {
+        #[cfg(feature = "chan-registry")]
+        self.stat.clone_send();
+        self.sender_num.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn drop_send(&self) {
+        #[cfg(feature = "chan-registry")]
+        self.stat.drop_send();
+        match self.sender_num.fetch_sub(1, Ordering::SeqCst) {
+            1 => {
+                // there is no send_ports any more
+                // should tell all the waited recv to come back
+                self.ready.notify_closed();
+                self.recv_selectors.wake_all();
+                if let Some(fifo) = &self.fifo {
+                    let waiters: Vec<_> = fifo.lock().recv_waiters.drain(..).collect();
+                    waiters.iter().for_each(|w| w.wake());
+                    return;
+                }
+                while self.wake_recv.get_value() == 0 {
+                    self.wake_recv.post();
+                }
+            }
+            n if n > 1 => {}
+            n => panic!("bad number of send_ports left {}", n),
+        }
+    }
+
+    pub fn clone_recv(&self) {
+        #[cfg(feature = "chan-registry")]
+        self.stat.clone_recv();
+        self.receiver_num.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn drop_recv(&self) {
+        #[cfg(feature = "chan-registry")]
+        self.stat.drop_recv();
+        match self.receiver_num.fetch_sub(1, Ordering::SeqCst) {
+            1 => {
+                // stop the pumps so that they release the upstream channels
+                let pumps: Vec<_> = self.pumps.lock().drain(..).collect();
+                pumps.iter().for_each(|co| co.cancel());
+                self.wake_budget();
+                self.send_selectors.wake_all();
+                // there is no receiver any more, clear the data
+                if let Some(fifo) = &self.fifo {
+                    let mut state = fifo.lock();
+                    let buffer = mem::take(&mut state.buffer);
+                    // the blocked senders get their message back
+                    let waiters: Vec<_> = state.send_waiters.drain(..).collect();
+                    drop(state);
+                    waiters.iter().for_each(|w| w.wake());
+                    // out of the lock, the destructors may use the channel
+                    buffer.into_iter().for_each(|t| self.discard(t));
+                    return;
+                }
+                // the blocked senders should come back
+                while self.wake_sender.get_value() == 0 {
+                    self.wake_sender.post();
+                }
+                while let Some(t) = self.buffer.pop() {
+                    self.discard(t);
+                }
+            }
+            n if n > 1 => {}
+            n => panic!("bad number of recv_ports left {}", n),
+        }
+    }
+
+    /// return remain msg len
+    pub fn remain(&self) -> usize {
+        match &self.fifo {
+            Some(fifo) => fifo.lock().buffer.len(),
+            None => self.buffer.len(),
+        }
+    }
+
+    pub fn sender_num(&self) -> usize {
+        self.sender_num.load(Ordering::SeqCst)
+    }
+
+    /// return true if a recv would not block, arm the notification if not
+    pub fn recv_ready(&self) -> bool {
+        if self.remain() > 0 || self.is_disconnected() {
+            return true;
+        }
+        self.arm_ready();
+        false
+    }
+
+    // a message or the disconnect in between fires at once
+    fn arm_ready(&self) {
+        self.ready
+            .arm(|| self.remain() > 0 || self.is_disconnected());
+    }
+
+    /// return true if a send would not block
+    pub fn send_ready(&self) -> bool {
+        self.receiver_num() == 0 || self.occupied() < self.buffer_limit
+    }
+
+    /// the bytes allocated for the queued messages and the wait lists
+    pub fn allocated_bytes(&self) -> usize {
+        match &self.fifo {
+            Some(fifo) => {
+                let fifo = fifo.lock();
+                let waiters = fifo.recv_waiters.capacity() + fifo.send_waiters.capacity();
+                fifo.buffer.capacity() * std::mem::size_of::<Msg<T>>()
+                    + waiters * std::mem::size_of::<Arc<FifoWaiter<T>>>()
+            }
+            None => self.buffer.allocated_bytes(),
+        }
+    }
+
+    /// release the spare room of the fifo buffers and the lifo stack, the
+    /// segments of the default queue are released as soon as they are
+    /// consumed
+    pub fn shrink_to_fit(&self) {
+        if let Some(fifo) = &self.fifo {
+            let mut fifo = fifo.lock();
+            fifo.buffer.shrink_to_fit();
+            fifo.recv_waiters.shrink_to_fit();
+            fifo.send_waiters.shrink_to_fit();
+        }
+        self.buffer.shrink_to_fit();
+    }
+
+    pub fn receiver_num(&self) -> usize {
+        self.receiver_num.load(Ordering::SeqCst)
+    }
+
+    // the receivers blocked on an empty channel
+    fn waiting_receivers(&self) -> usize {
+        match &self.fifo {
+            Some(fifo) => fifo.lock().recv_waiters.len(),
+            None => self.wake_recv.waiters(),
+        }
+    }
+
+    // the senders and the reservers blocked on a full channel
+    fn waiting_senders(&self) -> usize {
+        match &self.fifo {
+            Some(fifo) => fifo.lock().send_waiters.len(),
+            None => self.wake_sender.waiters(),
+        }
+    }
+
+    fn stats(&self) -> ChannelStats {
+        let counters = self.counters.as_ref();
+        ChannelStats {
+            len: self.remain(),
+            capacity: match self.buffer_limit {
+                usize::MAX => None,
+                n => Some(n),
+            },
+            waiting_receivers: self.waiting_receivers(),
+            waiting_senders: self.waiting_senders(),
+            total_sent: counters.map(|c| c.sent.load(Ordering::Relaxed)),
+            total_received: counters.map(|c| c.received.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<T> Drop for MPMCBuffer<T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "chan-registry")]
+        registry::unregister(&self.stat);
+        assert_eq!(self.sender_num.load(Ordering::Acquire), 0);
+        assert_eq!(self.receiver_num.load(Ordering::Acquire), 0);
+        // a send that raced with the drop of the last receiver may leave a
+        // message, drop them in order as well
+        while let Some(t) = self.buffer.pop() {
+            self.discard(t);
+        }
+        let buffer = self
+            .fifo
+            .as_mut()
+            .map(|fifo| mem::take(&mut fifo.get_mut().buffer));
+        buffer.into_iter().flatten().for_each(|t| self.discard(t));
+    }
+}
+
+pub struct Receiver<T> {
+    inner: Arc<MPMCBuffer<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// return remain msg len
+    pub fn remain(&self) -> usize {
+        self.inner.remain()
+    }
+
+    /// close the channel while the handles are alive, the sends fail at once
+    /// and the receivers drain the buffered messages before they get
+    /// `Disconnected`. the blocked sends and receives come back
+    pub fn close(&self) {
+        self.inner.close()
+    }
+
+    /// return true if the channel is closed by `close`
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Number of channel senders
+    pub fn sender_num(&self) -> usize {
+        self.inner.sender_num()
+    }
+
+    /// Number of channel receiver
+    pub fn receiver_num(&self) -> usize {
+        self.inner.receiver_num()
+    }
+
+    /// the bytes allocated by the channel for the messages that are not consumed
+    pub fn allocated_bytes(&self) -> usize {
+        self.inner.allocated_bytes()
+    }
+
+    /// release the memory the channel holds beyond the queued messages
+    pub fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    /// return true if a message is queued or all the senders are gone,
+    /// so that `recv` would not block. the message is not consumed
+    pub fn ready(&self) -> bool {
+        self.inner.recv_ready()
+    }
+
+    /// the receivers that are blocked on an empty channel, e.g. the idle
+    /// workers of a pool. it's a relaxed read of the wait list that may be
+    /// stale at once, the selects are not counted
+    pub fn waiting_receivers(&self) -> usize {
+        self.inner.waiting_receivers()
+    }
+
+    /// a snapshot of the channel, see `ChannelStats`
+    pub fn stats(&self) -> ChannelStats {
+        self.inner.stats()
+    }
+
+    /// call `f` when the channel becomes readable, it replaces the old hook
+    ///
+    /// the hook is edge triggered, it's armed whenever a receiver sees the
+    /// channel empty (`try_recv` returns `Empty`, `ready` returns false or
+    /// `recv` is about to block) and is called once by the next message, a
+    /// burst of messages is coalesced into one call. it's also called when
+    /// the last sender is dropped. after a call, drain the channel with
+    /// `try_recv` until `Empty` to get the next one. a spurious call is
+    /// possible. the hook is shared by all the receivers of the channel and
+    /// runs in the context of the sender, it must not block
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use mco::chan;
+    ///
+    /// let (tx, rx) = chan!();
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    /// let c = calls.clone();
+    /// rx.on_ready(move || {
+    ///     c.fetch_add(1, Ordering::SeqCst);
+    /// });
+    /// tx.send(1).unwrap();
+    /// tx.send(2).unwrap();
+    /// // coalesced until the channel is drained
+    /// assert_eq!(calls.load(Ordering::SeqCst), 1);
+    /// while rx.try_recv().is_ok() {}
+    /// tx.send(3).unwrap();
+    /// assert_eq!(calls.load(Ordering::SeqCst), 2);
+    /// ```
+    pub fn on_ready<F: Fn() + Send + Sync + 'static>(&self, f: F) {
+        self.inner.ready.set_hook(Arc::new(f));
+        // the channel may be readable already
+        self.inner.arm_ready();
+    }
+
+    /// an `eventfd` that is signaled when the channel becomes readable, for
+    /// registering the channel in an external `epoll` loop
+    ///
+    /// it's signaled the same way `on_ready` calls the hook. the fd is
+    /// non-blocking, read it to clear the counter, then drain the channel with
+    /// `try_recv` until `Empty`. the fd is owned by the channel and closed
+    /// with it. it's linux only and needs the `readiness-fd` feature
+    #[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+    pub fn readiness_fd(&self) -> io::Result<BorrowedFd<'_>> {
+        let fd = self.inner.ready.fd()?;
+        self.inner.arm_ready();
+        Ok(fd)
+    }
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+// impl<T> !Sync for Receiver<T> {}
+
+/// a message of `Receiver::recv_ref` in its slot of the channel
+pub struct RecvGuard<'a, T> {
+    msg: ManuallyDrop<Claimed<'a, T>>,
+}
+
+impl<'a, T> Deref for RecvGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.msg.msg().t
+    }
+}
+
+impl<'a, T> DerefMut for RecvGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.msg.msg_mut().t
+    }
+}
+
+impl<'a, T> Drop for RecvGuard<'a, T> {
+    fn drop(&mut self) {
+        let mut c = unsafe { ManuallyDrop::take(&mut self.msg) };
+        // acknowledged once the message is dropped
+        let ack = mem::replace(&mut c.msg_mut().ack, Ack(None));
+        drop(c);
+        ack.complete();
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for RecvGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    inner: &'a Receiver<T>,
+}
+
+pub struct TryIter<'a, T: 'a> {
+    inner: &'a Receiver<T>,
+}
+
+pub struct IntoIter<T> {
+    inner: Receiver<T>,
+}
+
+pub struct Sender<T> {
+    inner: Arc<MPMCBuffer<T>>,
+    // the acknowledgement of the last `send_and_wait`, reused when it's free
+    ack: AtomicOption<Arc<AckSlot>>,
+}
+
+impl<T> Sender<T> {
+    /// return remain msg len
+    pub fn remain(&self) -> usize {
+        self.inner.remain()
+    }
+
+    /// close the channel while the handles are alive, the sends fail at once
+    /// and the receivers drain the buffered messages before they get
+    /// `Disconnected`. the blocked sends and receives come back
+    pub fn close(&self) {
+        self.inner.close()
+    }
+
+    /// return true if the channel is closed by `close`
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_closed()
+    }
+
+    /// Number of channel senders
+    pub fn sender_num(&self) -> usize {
+        self.inner.sender_num()
+    }
+
+    /// Number of channel receiver
+    pub fn receiver_num(&self) -> usize {
+        self.inner.receiver_num()
+    }
+
+    /// the bytes allocated by the channel for the messages that are not consumed
+    pub fn allocated_bytes(&self) -> usize {
+        self.inner.allocated_bytes()
+    }
+
+    /// release the memory the channel holds beyond the queued messages
+    pub fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    /// return true if there is room for a message or all the receivers are
+    /// gone, so that `send` would not block
+    pub fn ready(&self) -> bool {
+        self.inner.send_ready()
+    }
+
+    /// the id of the channel in the events of its hooks, `None` if it's not
+    /// created by `channel_with_hooks`
+    pub fn channel_id(&self) -> Option<u64> {
+        self.inner.hooks.as_ref().map(|h| h.id())
+    }
+
+    /// the senders that are blocked on a full channel, a relaxed read of the
+    /// wait list that may be stale at once. the selects are not counted
+    pub fn waiting_senders(&self) -> usize {
+        self.inner.waiting_senders()
+    }
+
+    /// a snapshot of the channel, see `ChannelStats`
+    pub fn stats(&self) -> ChannelStats {
+        self.inner.stats()
     }
 }
 
@@ -295,22 +2009,350 @@ impl<T> Sender<T> {
 
 impl<T> Sender<T> {
     fn new(inner: Arc<MPMCBuffer<T>>) -> Sender<T> {
-        Sender { inner }
+        Sender {
+            inner,
+            ack: AtomicOption::none(),
+        }
     }
 
     /// send one message. If the length limit is exceeded or chan closed, wait for the message to be consumed
+    #[must_use = "the message is returned in the error if the channel is closed"]
     pub fn send(&self, t: T) -> Result<(), SendError<T>> {
         self.inner.send(t)
     }
 
     /// try send one message.If the length limit is exceeded or chan closed, return a error
+    ///
+    /// it never parks, but it may allocate a segment of the queue, take the
+    /// lock of a `Fairness::Fifo` channel or of the budget, and unpark a
+    /// receiver thread. see `rt_sender` for the sends from a signal handler
+    /// or a real time thread
+    #[must_use = "the message is returned in the error if the channel is closed"]
     pub fn try_send(&self, t: T) -> Result<(), SendError<T>> {
         self.inner.try_send(t)
     }
 
+    /// a sender for the contexts that must not park, lock, allocate nor make
+    /// a syscall, e.g. a signal handler or an audio callback
+    ///
+    /// the channel keeps `RT_SPARE_BLOCKS` spare segments of its queue and a
+    /// maintenance tick of the runtime refills them, see
+    /// `runtime::register_maintenance`. with `RtWakeup::Deferred` the tick
+    /// wakes the receivers as well. it's `None` for the channels that take a
+    /// lock on each send: the `Fairness::Fifo` and the `Order::Lifo` ones
+    /// and the ones with a budget or hooks. creating and dropping it allocates, do that outside
+    /// of the real time context
+    ///
+    /// ```
+    /// use mco::std::sync::channel::{channel, RtWakeup};
+    ///
+    /// let (tx, rx) = channel();
+    /// let rt = tx.rt_sender(RtWakeup::Deferred).unwrap();
+    /// std::thread::spawn(move || {
+    ///     // the real time thread
+    ///     rt.try_send(1).unwrap();
+    /// })
+    /// .join()
+    /// .unwrap();
+    /// assert_eq!(rx.recv(), Ok(1));
+    /// ```
+    pub fn rt_sender(&self, wakeup: RtWakeup) -> Option<RtSender<T>>
+    where
+        T: Send + 'static,
+    {
+        let inner = &self.inner;
+        let lifo = matches!(inner.buffer, MsgQueue::Lifo(_));
+        if inner.fifo.is_some() || inner.budget.is_some() || inner.hooks.is_some() || lifo {
+            return None;
+        }
+        inner.buffer.reserve_blocks(RT_SPARE_BLOCKS);
+        let chan = inner.clone();
+        let tick = register_maintenance(MAINTENANCE_TICK, move || chan.rt_flush(RT_SPARE_BLOCKS));
+        Some(RtSender {
+            tx: self.clone(),
+            wakeup,
+            _tick: tick,
+        })
+    }
+
+    /// send one message, it returns early if the context is done while it
+    /// waits for room in a bounded channel
+    #[must_use = "the message is returned in the error if the channel is closed"]
+    pub fn send_ctx(&self, ctx: &Context, t: T) -> Result<(), SendCtxError<T>> {
+        match ctx.guard(true, || self.inner.send(t)) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(SendError(t))) => Err(SendCtxError::Disconnected(t)),
+            Err(e) => Err(SendCtxError::Done(e)),
+        }
+    }
+
+    /// send one message and wait until a receiver acknowledges it by
+    /// `Ack::complete`, see `Receiver::recv_ack`. a plain receive of the
+    /// message is the acknowledgement as well
+    ///
+    /// the handoff is synchronous, each sender has at most one message in
+    /// flight. if the sender is canceled while it waits, the message stays in
+    /// the channel and its acknowledgement is ignored
+    ///
+    /// ```
+    /// use mco::std::sync::channel::channel;
+    ///
+    /// let (tx, rx) = channel();
+    /// let h = mco::co!(move || {
+    ///     let (v, ack) = rx.recv_ack().unwrap();
+    ///     assert_eq!(v, 42);
+    ///     ack.complete();
+    /// });
+    /// tx.send_and_wait(42).unwrap();
+    /// h.join().unwrap();
+    /// ```
+    pub fn send_and_wait(&self, t: T) -> Result<(), AckError<T>> {
+        self.send_and_wait_impl(t, None)
+    }
+
+    /// the same as `send_and_wait`, but wait at most `timeout` for the
+    /// acknowledgement. the time waiting for room in a bounded channel
+    /// doesn't count
+    pub fn send_and_wait_timeout(&self, t: T, timeout: Duration) -> Result<(), AckError<T>> {
+        self.send_and_wait_impl(t, Some(timeout))
+    }
+
+    fn send_and_wait_impl(&self, t: T, dur: Option<Duration>) -> Result<(), AckError<T>> {
+        // reuse the last slot unless a message still holds it
+        let slot = match self.ack.take() {
+            Some(slot) if Arc::strong_count(&slot) == 1 => slot,
+            _ => Arc::new(AckSlot {
+                state: AtomicUsize::new(ACK_PENDING),
+                blocker: AtomicOption::none(),
+            }),
+        };
+        slot.state.store(ACK_PENDING, Ordering::Release);
+        let cur = SyncBlocker::current();
+        slot.blocker.store(cur.clone());
+        let mut msg = self.inner.msg(t);
+        msg.ack = Ack(Some(slot.clone()));
+        if let Err(SendError(m)) = self.inner.send_msg(msg) {
+            slot.blocker.take();
+            return Err(AckError::Disconnected(m.into_inner()));
+        }
+
+        let ret = cur.park(dur);
+        // timed out or canceled, the receiver can't wake us any more
+        let state = match slot.state.compare_exchange(
+            ACK_PENDING,
+            ACK_ABANDONED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => {
+                slot.blocker.take();
+                ACK_ABANDONED
+            }
+            Err(state) => state,
+        };
+        self.ack.store(slot);
+        if ret == Err(ParkError::Canceled) {
+            trigger_cancel_panic();
+        }
+        match state {
+            ACK_DONE => Ok(()),
+            ACK_DROPPED => Err(AckError::Dropped),
+            _ => Err(AckError::Timeout),
+        }
+    }
+
+    /// send all the messages in one batch, return how many are sent
+    ///
+    /// for a bounded channel it waits for room when the channel is full.
+    /// if all the receivers are gone, the error holds the number of sent
+    /// messages and the ones not sent
+    #[must_use = "the messages not sent are returned in the error"]
+    pub fn send_all<I: IntoIterator<Item = T>>(&self, iter: I) -> Result<usize, SendAllError<T>> {
+        self.inner.send_all(iter)
+    }
+
     /// return how many elements in the queue that are not consumed by receivers
     pub fn pressure(&self) -> usize {
-        self.inner.wake_recv.get_value()
+        match &self.inner.fifo {
+            Some(_) => self.inner.remain(),
+            None => self.inner.wake_recv.get_value(),
+        }
+    }
+
+    /// take a slot in the channel before the message is made, wait until
+    /// there is room. the message is sent by `Permit::send` without blocking,
+    /// the slot is given back when the permit is dropped
+    ///
+    /// it fails when the channel is closed or all the receivers are gone,
+    /// before or while it waits. a canceled wait takes no slot
+    /// ```
+    /// let (tx, rx) = mco::chan!(1);
+    /// let permit = tx.reserve().unwrap();
+    /// assert!(tx.try_reserve().is_err());
+    /// permit.send(1);
+    /// assert_eq!(rx.recv(), Ok(1));
+    /// ```
+    pub fn reserve(&self) -> Result<Permit<'_, T>, SendError<()>> {
+        match self.inner.reserve(true) {
+            Ok(()) => Ok(Permit { tx: self }),
+            Err(_) => Err(SendError(())),
+        }
+    }
+
+    /// take a slot in the channel if there is room, see `reserve`
+    pub fn try_reserve(&self) -> Result<Permit<'_, T>, TrySendError<()>> {
+        self.inner.reserve(false).map(|()| Permit { tx: self })
+    }
+
+    /// take `n` slots in the channel, wait until all of them are taken
+    ///
+    /// the slots are taken one by one, the taken ones are given back when it
+    /// fails or is canceled. it panics if `n` is more than the capacity of
+    /// the channel, that would never be done
+    pub fn reserve_many(&self, n: usize) -> Result<Permits<'_, T>, SendError<()>> {
+        assert!(
+            n <= self.inner.buffer_limit,
+            "reserve {} slots in a channel of {}",
+            n,
+            self.inner.buffer_limit
+        );
+        let mut permits = Permits { tx: self, n: 0 };
+        for _ in 0..n {
+            // the taken slots are given back by the drop of `permits`
+            self.reserve()?.forget();
+            permits.n += 1;
+        }
+        Ok(permits)
+    }
+
+    /// the send of `t` as a source of a `SelectSet`, it's done once
+    ///
+    /// it's ready when there is room in the channel or it's closed, the
+    /// error gives the message back
+    pub fn select_send(&self, t: T) -> SendOp<'_, T> {
+        SendOp {
+            tx: self,
+            msg: Some(t),
+        }
+    }
+}
+
+/// a send for `select`, see `Sender::select_send`
+pub struct SendOp<'a, T> {
+    tx: &'a Sender<T>,
+    msg: Option<T>,
+}
+
+impl<'a, T> Selectable for SendOp<'a, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        let t = self.msg.take()?;
+        match self.tx.try_send(t) {
+            Ok(()) => Some(Ok(())),
+            Err(SendError(t)) if self.tx.inner.send_closed() => Some(Err(SendError(t))),
+            Err(SendError(t)) => {
+                self.msg = Some(t);
+                None
+            }
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.tx.inner.send_selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.tx.inner.send_selectors.deregister(waker);
+    }
+}
+
+/// a slot taken in the channel by `Sender::reserve`
+///
+/// the slot is given back when it's dropped without a send
+#[must_use = "the slot is given back at once if the permit is dropped"]
+pub struct Permit<'a, T> {
+    tx: &'a Sender<T>,
+}
+
+impl<'a, T> Permit<'a, T> {
+    /// send the message in the slot, it never blocks
+    ///
+    /// the message is dropped if the channel is closed or all the receivers
+    /// are gone since the slot is taken
+    pub fn send(self, t: T) {
+        // a canceled charge of the budget gives back the slot by the drop
+        self.tx.inner.send_reserved(t);
+        mem::forget(self);
+    }
+
+    // hand over the slot without giving it back
+    fn forget(self) -> &'a Sender<T> {
+        let tx = self.tx;
+        mem::forget(self);
+        tx
+    }
+}
+
+impl<'a, T> Drop for Permit<'a, T> {
+    fn drop(&mut self) {
+        self.tx.inner.release();
+    }
+}
+
+impl<'a, T> fmt::Debug for Permit<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Permit {{ .. }}")
+    }
+}
+
+/// the slots taken by `Sender::reserve_many`, each one is given out as a
+/// `Permit`
+///
+/// the slots that are not given out are given back when it's dropped
+#[must_use = "the slots are given back at once if the permits are dropped"]
+pub struct Permits<'a, T> {
+    tx: &'a Sender<T>,
+    n: usize,
+}
+
+impl<'a, T> Iterator for Permits<'a, T> {
+    type Item = Permit<'a, T>;
+
+    fn next(&mut self) -> Option<Permit<'a, T>> {
+        if self.n == 0 {
+            return None;
+        }
+        self.n -= 1;
+        Some(Permit { tx: self.tx })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.n, Some(self.n))
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Permits<'a, T> {}
+
+impl<'a, T> Drop for Permits<'a, T> {
+    fn drop(&mut self) {
+        for _ in 0..self.n {
+            self.tx.inner.release();
+        }
+    }
+}
+
+impl<'a, T> fmt::Debug for Permits<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Permits").field("n", &self.n).finish()
+    }
+}
+
+impl<'a, T> fmt::Debug for SendOp<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SendOp")
+            .field("sent", &self.msg.is_none())
+            .finish()
     }
 }
 
@@ -327,6 +2369,112 @@ impl<T> Drop for Sender<T> {
     }
 }
 
+/// the spare segments of the queue that a channel keeps for its `RtSender`s,
+/// a segment holds 31 messages
+pub const RT_SPARE_BLOCKS: usize = 4;
+
+/// how an `RtSender` wakes the receivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtWakeup {
+    /// the send never wakes anyone, a parked receiver, the selects and the
+    /// `on_ready` callbacks are woken by the next maintenance tick, or by
+    /// `RtSender::flush`. a receiver that polls sees the message at once
+    /// unless another receiver is parked
+    Deferred,
+    /// wake them in the send as `try_send` does, it may unpark a receiver
+    /// thread, which is a syscall
+    Immediate,
+}
+
+/// the error of `RtSender::try_send`, the message is returned
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum RtSendError<T> {
+    /// the bounded channel is full
+    Full(T),
+    /// the message would take a new segment of the queue and there is no
+    /// spare one, the receivers are behind or the ticks don't run
+    WouldAllocate(T),
+    /// all the receivers are gone or the channel is closed
+    Disconnected(T),
+}
+
+impl<T> RtSendError<T> {
+    /// the message that is not sent
+    pub fn into_inner(self) -> T {
+        match self {
+            RtSendError::Full(t) | RtSendError::WouldAllocate(t) | RtSendError::Disconnected(t) => {
+                t
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for RtSendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RtSendError::Full(_) => f.write_str("Full(..)"),
+            RtSendError::WouldAllocate(_) => f.write_str("WouldAllocate(..)"),
+            RtSendError::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for RtSendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RtSendError::Full(_) => f.write_str("sending on a full channel"),
+            RtSendError::WouldAllocate(_) => f.write_str("sending would allocate"),
+            RtSendError::Disconnected(_) => f.write_str("sending on a closed channel"),
+        }
+    }
+}
+
+impl<T: Send> Error for RtSendError<T> {}
+
+/// the sender of the real time contexts, see `Sender::rt_sender`
+///
+/// `try_send` is lock free: it never parks, takes a lock, allocates nor makes
+/// a syscall with `RtWakeup::Deferred`, it fails fast instead. it counts as a
+/// sender of the channel until it's dropped
+pub struct RtSender<T: Send + 'static> {
+    tx: Sender<T>,
+    wakeup: RtWakeup,
+    _tick: MaintenanceGuard,
+}
+
+impl<T: Send + 'static> RtSender<T> {
+    /// send a message if there is room in the channel and in the segments
+    /// of its queue
+    pub fn try_send(&self, t: T) -> Result<(), RtSendError<T>> {
+        self.tx.inner.rt_send(t, self.wakeup)
+    }
+
+    /// wake the receivers of the deferred sends now and refill the spare
+    /// segments, it allocates and wakes, so not in the real time context
+    pub fn flush(&self) {
+        self.tx.inner.rt_flush(RT_SPARE_BLOCKS)
+    }
+
+    /// the plain sender of the channel
+    pub fn sender(&self) -> &Sender<T> {
+        &self.tx
+    }
+}
+
+impl<T: Send + 'static> Drop for RtSender<T> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<T: Send + 'static> fmt::Debug for RtSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RtSender")
+            .field("wakeup", &self.wakeup)
+            .finish()
+    }
+}
+
 impl<T> fmt::Debug for Sender<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Sender {{ .. }}")
@@ -342,12 +2490,24 @@ impl<T> Receiver<T> {
         Receiver { inner }
     }
 
+    // the bound of the channel, `None` for an unbounded one
+    pub(crate) fn capacity(&self) -> Option<usize> {
+        match self.inner.buffer_limit {
+            usize::MAX => None,
+            n => Some(n),
+        }
+    }
+
+    /// try to receive a message without blocking, the error tells
+    /// an empty channel from a closed one
+    #[must_use = "the received message is dropped if the result is ignored"]
     pub fn try_recv(&self) -> Result<T, TryRecvError> {
         self.inner.try_recv()
     }
 
     /// received a message. If the message is empty, a wait is entered, and an error is returned if the channel is closed
     /// If you want to try to receive a message, use try_recv
+    #[must_use = "the received message is dropped if the result is ignored"]
     pub fn recv(&self) -> Result<T, RecvError> {
         match self.inner.recv(None) {
             Err(RecvTimeoutError::Timeout) => unreachable!("mpmc recv timeout"),
@@ -355,16 +2515,385 @@ impl<T> Receiver<T> {
         }
     }
 
+    /// wait for a message with a timeout
+    #[must_use = "the received message is dropped if the result is ignored"]
     pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
         self.inner.recv(Some(timeout))
     }
 
-    pub fn iter(&self) -> Iter<T> {
-        Iter { inner: self }
+    /// wait for a message till the context is done, the deadline of the
+    /// context is the timeout
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_ctx(&self, ctx: &Context) -> Result<T, RecvCtxError> {
+        match ctx.guard(false, || self.inner.recv(ctx.remaining())) {
+            Ok(Ok(t)) => Ok(t),
+            Ok(Err(RecvTimeoutError::Disconnected)) => Err(RecvCtxError::Disconnected),
+            Ok(Err(RecvTimeoutError::Timeout)) => {
+                Err(RecvCtxError::Done(ContextError::DeadlineExceeded))
+            }
+            Err(e) => Err(RecvCtxError::Done(e)),
+        }
+    }
+
+    /// receive a message with its acknowledgement, the sender of
+    /// `Sender::send_and_wait` waits until `Ack::complete` is called
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_ack(&self) -> Result<(T, Ack), RecvError> {
+        match self.inner.recv_msg(None) {
+            Ok(m) => {
+                self.inner.traced(&m);
+                Ok((m.t, m.ack))
+            }
+            Err(_) => Err(RecvError),
+        }
+    }
+
+    /// the same as `recv_ack` with a timeout
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_ack_timeout(&self, timeout: Duration) -> Result<(T, Ack), RecvTimeoutError> {
+        self.inner.recv_msg(Some(timeout)).map(|m| {
+            self.inner.traced(&m);
+            (m.t, m.ack)
+        })
+    }
+
+    /// receive a message and leave it in its slot of the channel, the guard
+    /// derefs to it, so a large message is not moved out. the slot is
+    /// released and the message is dropped with the guard
+    ///
+    /// the slot is claimed before it returns, the other receivers never see
+    /// the message, and the slot isn't reused while the guard is alive. for a
+    /// bounded channel the room is given to the senders when the slot is
+    /// claimed. the sender of `send_and_wait` is acknowledged when the guard
+    /// is dropped. a channel of `Fairness::Fifo` hands the messages over, so
+    /// there the message is moved into the guard
+    ///
+    /// ```
+    /// use mco::std::sync::channel::channel;
+    ///
+    /// let (tx, rx) = channel();
+    /// tx.send([7u8; 4096]).unwrap();
+    /// let mut page = rx.recv_ref().unwrap();
+    /// page[0] = 0;
+    /// assert_eq!(page[..2], [0, 7]);
+    /// ```
+    pub fn recv_ref(&self) -> Result<RecvGuard<'_, T>, RecvError> {
+        match self.inner.claim(None) {
+            Ok(c) => Ok(self.guard(c)),
+            Err(_) => Err(RecvError),
+        }
+    }
+
+    /// the same as `recv_ref` with a timeout
+    pub fn recv_ref_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<RecvGuard<'_, T>, RecvTimeoutError> {
+        self.inner.claim(Some(timeout)).map(|c| self.guard(c))
+    }
+
+    /// the same as `recv_ref` without blocking
+    pub fn try_recv_ref(&self) -> Result<RecvGuard<'_, T>, TryRecvError> {
+        self.inner.try_claim().map(|c| self.guard(c))
+    }
+
+    /// receive a message and run `f` on it in its slot of the channel, the
+    /// message is dropped after `f`, see `recv_ref`
+    pub fn recv_with<F, R>(&self, f: F) -> Result<R, RecvError>
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.recv_ref().map(|mut m| f(&mut m))
+    }
+
+    fn guard<'a>(&'a self, c: Claimed<'a, T>) -> RecvGuard<'a, T> {
+        self.inner.traced(c.msg());
+        self.inner.count_received(1);
+        RecvGuard {
+            msg: ManuallyDrop::new(c),
+        }
+    }
+
+    /// move up to `max` available messages into `buf` in one shot, return how many are received
+    ///
+    /// it blocks only when there is no message, an error is returned if the channel is closed and empty
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_many(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        self.inner.recv_many(buf, max)
+    }
+
+    pub fn iter(&self) -> Iter<T> {
+        Iter { inner: self }
+    }
+
+    pub fn try_iter(&self) -> TryIter<T> {
+        TryIter { inner: self }
+    }
+}
+
+/// /////////////////////////////////////////////////////////////////////////////
+/// Combinators
+/// /////////////////////////////////////////////////////////////////////////////
+
+// the buffer size of the channels that are created by the combinators
+const PUMP_BUFFER: usize = 64;
+
+// spawn a named pump coroutine that feeds `tx`
+// the pump is canceled when all the receivers of `tx` are dropped
+fn spawn_pump<U, R, F>(name: &str, tx: Sender<U>, f: F) -> JoinHandle<R>
+where
+    U: Send + 'static,
+    R: Send + 'static,
+    F: FnOnce(Sender<U>) -> R + Send + 'static,
+{
+    let inner = tx.inner.clone();
+    let h = Builder::new().name(name.to_owned()).spawn(move || f(tx));
+    let co = h.coroutine().clone();
+    inner.pumps.lock().push(co.clone());
+    // the receivers may be dropped before the pump is registered
+    if inner.receiver_num() == 0 {
+        co.cancel();
+    }
+    h
+}
+
+impl<T: Send + 'static> Receiver<T> {
+    /// move all the messages to `sender` in a pump coroutine named
+    /// `chan_forward`, the handle returns the counts when it exits
+    ///
+    /// the pump waits on a full bounded `sender`, so the backpressure goes
+    /// upstream. when the upstream senders are gone the queued messages are
+    /// forwarded and then `sender` is dropped, which closes the downstream
+    /// if it's the last sender. when the downstream receivers are gone the
+    /// pump is canceled and drops this receiver, which closes the upstream if
+    /// it's the last receiver. a message that is given up on the way is
+    /// counted as dropped
+    ///
+    /// ```
+    /// use mco::std::sync::channel::{bounded, channel};
+    ///
+    /// let (tx, rx) = channel();
+    /// let (tx2, rx2) = bounded(1);
+    /// let h = rx.forward(tx2);
+    /// mco::co!(move || {
+    ///     for i in 0..3 {
+    ///         tx.send(i).unwrap();
+    ///     }
+    /// });
+    /// assert_eq!(rx2.iter().collect::<Vec<_>>(), [0, 1, 2]);
+    /// assert_eq!(h.join().unwrap().forwarded, 3);
+    /// ```
+    pub fn forward(self, sender: Sender<T>) -> JoinHandle<ForwardStats> {
+        self.forward_map(sender, |t| t)
+    }
+
+    /// `forward` the messages mapped by `f`
+    pub fn forward_map<U, F>(self, sender: Sender<U>, mut f: F) -> JoinHandle<ForwardStats>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> U + Send + 'static,
+    {
+        spawn_pump("chan_forward", sender, move |tx| {
+            let mut stats = ForwardStats::default();
+            // a message is given to a send that may be canceled
+            let mut sending = false;
+            let ret = panic::catch_unwind(AssertUnwindSafe(|| {
+                for t in self.iter() {
+                    let u = f(t);
+                    sending = true;
+                    let sent = tx.send(u).is_ok();
+                    sending = false;
+                    if !sent {
+                        stats.dropped += 1;
+                        break;
+                    }
+                    stats.forwarded += 1;
+                }
+            }));
+            if let Err(p) = ret {
+                if !is_cancel_panic(&*p) {
+                    panic::resume_unwind(p);
+                }
+                // canceled by the downstream, it's the end of the pump
+                current_cancel_data().clear_cancel_bit();
+                if sending {
+                    stats.dropped += 1;
+                }
+            }
+            stats
+        })
+    }
+
+    /// return a receiver of the messages mapped by `f`
+    ///
+    /// the messages are moved by a pump coroutine named `chan_map`. the pump
+    /// stops when either the upstream senders or the downstream receivers are gone
+    pub fn map<U, F>(self, mut f: F) -> Receiver<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> U + Send + 'static,
+    {
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        spawn_pump("chan_map", tx, move |tx| {
+            for t in self.iter() {
+                if tx.send(f(t)).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    /// return a receiver of the messages that `pred` returns true for
+    ///
+    /// the pump coroutine is named `chan_filter`
+    pub fn filter<F>(self, mut pred: F) -> Receiver<T>
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        spawn_pump("chan_filter", tx, move |tx| {
+            for t in self.iter().filter(|t| pred(t)) {
+                if tx.send(t).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    /// return a receiver of the messages from both receivers, in no particular order
+    ///
+    /// each side has a pump coroutine named `chan_merge`, the merged receiver
+    /// is disconnected when both sides are disconnected
+    pub fn merge(self, other: Receiver<T>) -> Receiver<T> {
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        for up in [self, other] {
+            spawn_pump("chan_merge", tx.clone(), move |tx| {
+                for t in up.iter() {
+                    if tx.send(t).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        rx
+    }
+
+    /// return a receiver of the pairs of the messages from both receivers
+    ///
+    /// the pump coroutine is named `chan_zip`, the zipped receiver is
+    /// disconnected when either side is disconnected
+    pub fn zip<U: Send + 'static>(self, other: Receiver<U>) -> Receiver<(T, U)> {
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        spawn_pump("chan_zip", tx, move |tx| {
+            while let (Ok(t), Ok(u)) = (self.recv(), other.recv()) {
+                if tx.send((t, u)).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    /// return a receiver of the message batches
+    ///
+    /// a batch is sent when it has `n` messages or `max_wait` is passed since
+    /// its first message. the last partial batch is flushed on disconnect.
+    /// the pump coroutine is named `chan_chunked`
+    pub fn chunked(self, n: usize, max_wait: Duration) -> Receiver<Vec<T>> {
+        let n = n.max(1);
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        spawn_pump("chan_chunked", tx, move |tx| {
+            while let Ok(t) = self.recv() {
+                let deadline = now_instant() + max_wait;
+                let mut batch = Vec::with_capacity(n);
+                batch.push(t);
+                let mut closed = false;
+                while batch.len() < n {
+                    let now = now_instant();
+                    if now >= deadline {
+                        break;
+                    }
+                    match self.recv_timeout(deadline - now) {
+                        Ok(t) => batch.push(t),
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => {
+                            closed = true;
+                            break;
+                        }
+                    }
+                }
+                if tx.send(batch).is_err() || closed {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    /// return a receiver of the last message before each `window` of quiet
+    ///
+    /// every new message restarts the window, the pending message is flushed
+    /// on disconnect. a message that arrives as the window expires starts
+    /// the next window. the pump coroutine is named `chan_debounce`
+    pub fn debounce(self, window: Duration) -> Receiver<T> {
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        spawn_pump("chan_debounce", tx, move |tx| {
+            while let Ok(mut last) = self.recv() {
+                let closed = loop {
+                    match self.recv_timeout(window) {
+                        Ok(t) => last = t,
+                        Err(RecvTimeoutError::Timeout) => break false,
+                        Err(RecvTimeoutError::Disconnected) => break true,
+                    }
+                };
+                if tx.send(last).is_err() || closed {
+                    break;
+                }
+            }
+        });
+        rx
     }
 
-    pub fn try_iter(&self) -> TryIter<T> {
-        TryIter { inner: self }
+    /// return a receiver of at most one message per `interval`
+    ///
+    /// a message after a quiet interval is sent at once, the others are held
+    /// back till the interval is passed and only the latest one is sent. the
+    /// held message is flushed on disconnect. the pump coroutine is named
+    /// `chan_throttle`
+    pub fn throttle(self, interval: Duration) -> Receiver<T> {
+        let (tx, rx) = bounded(PUMP_BUFFER);
+        spawn_pump("chan_throttle", tx, move |tx| {
+            // the earliest time of the next send, and the message held back
+            let mut next = now_instant();
+            let mut latest = None;
+            loop {
+                let now = now_instant();
+                if now >= next {
+                    if let Some(t) = latest.take() {
+                        if tx.send(t).is_err() {
+                            return;
+                        }
+                        next = now + interval;
+                        continue;
+                    }
+                }
+                let ret = match latest {
+                    None => self.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                    Some(_) => self.recv_timeout(next - now),
+                };
+                match ret {
+                    Ok(t) => latest = Some(t),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            if let Some(t) = latest {
+                let _ = tx.send(t);
+            }
+        });
+        rx
     }
 }
 
@@ -428,6 +2957,44 @@ impl<T> fmt::Debug for Receiver<T> {
     }
 }
 
+// a receive for `select`, it's ready when a message is queued or the
+// channel is disconnected, like `recv`
+impl<'a, T> Selectable for &'a Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        match self.try_recv() {
+            Ok(t) => Some(Ok(t)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError)),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.inner.recv_selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.inner.recv_selectors.deregister(waker);
+    }
+}
+
+impl<T> Selectable for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        (&*self).try_select()
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.inner.recv_selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.inner.recv_selectors.deregister(waker);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1197,4 +3764,712 @@ mod tests {
         }
         assert_eq!(rx1.try_recv().is_err(), true);
     }
+    #[test]
+    fn fifo_recv_order() {
+        let (tx, rx) = with_fairness::<usize>(usize::MAX, Fairness::Fifo);
+        let (done_tx, done_rx) = channel();
+        for i in 0..100 {
+            let rx2 = rx.clone();
+            let done_tx = done_tx.clone();
+            co!(move || {
+                let v = rx2.recv().unwrap();
+                done_tx.send((i, v)).unwrap();
+            });
+            // queue the waiters one by one
+            while rx.waiting_receivers() < i + 1 {
+                thread::yield_now();
+            }
+        }
+        for i in 0..100 {
+            tx.send(i).unwrap();
+        }
+        for _ in 0..100 {
+            let (i, v) = done_rx.recv().unwrap();
+            assert_eq!(i, v);
+        }
+    }
+
+    #[test]
+    fn fifo_send_order() {
+        let (tx, rx) = with_fairness::<usize>(1, Fairness::Fifo);
+        tx.send(0).unwrap();
+        let mut handles = vec![];
+        for i in 1..=10 {
+            let tx2 = tx.clone();
+            handles.push(co!(move || tx2.send(i).unwrap()));
+            // queue the waiters one by one
+            while tx.waiting_senders() < i {
+                thread::yield_now();
+            }
+        }
+        assert_eq!(tx.try_send(11), Err(SendError(11)));
+        for i in 0..=10 {
+            assert_eq!(rx.recv().unwrap(), i);
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn fifo_disconnect() {
+        let (tx, rx) = with_fairness::<i32>(1, Fairness::Fifo);
+        tx.send(1).unwrap();
+        let h = co!(move || tx.send(2));
+        drop(rx);
+        assert_eq!(h.join().unwrap(), Err(SendError(2)));
+
+        let (tx, rx) = with_fairness::<i32>(usize::MAX, Fairness::Fifo);
+        let h = co!(move || rx.recv());
+        drop(tx);
+        assert_eq!(h.join().unwrap(), Err(RecvError));
+    }
+
+    #[test]
+    fn fifo_recv_timeout() {
+        let (tx, rx) = with_fairness::<i32>(usize::MAX, Fairness::Fifo);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Ok(1));
+    }
+
+    #[test]
+    fn send_all_recv_many() {
+        let (tx, rx) = channel::<i32>();
+        assert_eq!(tx.send_all(0..10), Ok(10));
+        let mut buf = Vec::new();
+        assert_eq!(rx.recv_many(&mut buf, 4), Ok(4));
+        assert_eq!(rx.recv_many(&mut buf, 100), Ok(6));
+        assert_eq!(buf, (0..10).collect::<Vec<_>>());
+        drop(tx);
+        assert_eq!(rx.recv_many(&mut buf, 4), Err(RecvError));
+    }
+
+    #[test]
+    fn send_all_bounded() {
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = with_fairness::<i32>(3, fairness);
+            let h = co!(move || tx.send_all(0..1000));
+            let mut buf = Vec::new();
+            while buf.len() < 1000 {
+                let n = rx.recv_many(&mut buf, 16).unwrap();
+                assert!(n > 0 && n <= 16);
+            }
+            assert_eq!(h.join().unwrap(), Ok(1000));
+            assert_eq!(buf, (0..1000).collect::<Vec<_>>());
+            assert_eq!(rx.recv_many(&mut buf, 16), Err(RecvError));
+        }
+    }
+
+    #[test]
+    fn send_all_disconnect() {
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = with_fairness::<i32>(2, fairness);
+            let h = co!(move || tx.send_all(0..10));
+            let mut buf = Vec::new();
+            rx.recv_many(&mut buf, 1).unwrap();
+            sleep(Duration::from_millis(50));
+            drop(rx);
+            let err = h.join().unwrap().unwrap_err();
+            assert!(err.sent >= 2);
+            assert_eq!(err.sent + err.remain.len(), 10);
+            assert_eq!(err.remain[0], err.sent as i32);
+        }
+    }
+
+    #[test]
+    fn recv_many_wakeup() {
+        // every receiver blocked in recv_many must see a message
+        let (tx, rx) = channel::<i32>();
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let rx = rx.clone();
+                co!(move || {
+                    let mut buf = Vec::new();
+                    rx.recv_many(&mut buf, 1).unwrap();
+                    buf[0]
+                })
+            })
+            .collect();
+        sleep(Duration::from_millis(50));
+        tx.send_all(0..4).unwrap();
+        let mut v: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        v.sort_unstable();
+        assert_eq!(v, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn map_filter() {
+        let (tx, rx) = channel::<i32>();
+        let rx = rx.map(|i| i * 2).filter(|i| i % 3 == 0);
+        tx.send_all(0..10).unwrap();
+        drop(tx);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![0, 6, 12, 18]);
+    }
+
+    #[test]
+    fn merge_zip() {
+        let (tx1, rx1) = channel::<i32>();
+        let (tx2, rx2) = channel::<i32>();
+        let merged = rx1.merge(rx2);
+        tx1.send_all(0..5).unwrap();
+        tx2.send_all(5..10).unwrap();
+        drop(tx1);
+        drop(tx2);
+        let mut v: Vec<_> = merged.iter().collect();
+        v.sort_unstable();
+        assert_eq!(v, (0..10).collect::<Vec<_>>());
+
+        let (tx1, rx1) = channel::<i32>();
+        let (tx2, rx2) = channel::<&str>();
+        let zipped = rx1.zip(rx2);
+        tx1.send_all(0..3).unwrap();
+        tx2.send_all(vec!["a", "b"]).unwrap();
+        drop(tx2);
+        assert_eq!(zipped.iter().collect::<Vec<_>>(), vec![(0, "a"), (1, "b")]);
+    }
+
+    #[test]
+    fn chunked() {
+        let (tx, rx) = channel::<i32>();
+        let rx = rx.chunked(4, Duration::from_millis(100));
+        tx.send_all(0..6).unwrap();
+        assert_eq!(rx.recv(), Ok(vec![0, 1, 2, 3]));
+        // the partial batch is sent after the deadline
+        assert_eq!(rx.recv(), Ok(vec![4, 5]));
+        tx.send(6).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(vec![6]));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn debounce() {
+        let (tx, rx) = channel::<i32>();
+        let rx = rx.debounce(Duration::from_millis(50));
+        tx.send_all(0..5).unwrap();
+        assert_eq!(rx.recv(), Ok(4));
+        let quiet = rx.recv_timeout(Duration::from_millis(200));
+        assert_eq!(quiet, Err(RecvTimeoutError::Timeout));
+        // the pending message is flushed on disconnect
+        tx.send(5).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(5));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn throttle() {
+        let (tx, rx) = channel::<i32>();
+        let rx = rx.throttle(Duration::from_millis(200));
+        tx.send(0).unwrap();
+        assert_eq!(rx.recv(), Ok(0));
+        // held back till the interval is passed, the latest wins
+        tx.send_all(1..5).unwrap();
+        assert_eq!(rx.recv(), Ok(4));
+        tx.send(5).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(5));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn pump_drop_downstream() {
+        let (tx, rx) = channel::<i32>();
+        let rx = rx.map(|i| i + 1);
+        assert_eq!(rx.receiver_num(), 1);
+        drop(rx);
+        // the canceled pump releases the upstream receiver
+        for _ in 0..100 {
+            if tx.receiver_num() == 0 {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(tx.receiver_num(), 0);
+        assert!(tx.send(1).is_err());
+    }
+
+    #[test]
+    fn forward_upstream_close() {
+        let (tx, rx) = channel();
+        let (tx2, rx2) = bounded(2);
+        let h = rx.forward(tx2);
+        co!(move || {
+            for i in 0..5 {
+                tx.send(i).unwrap();
+            }
+        });
+        // drained, then closed
+        assert_eq!(rx2.iter().collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+        let stats = h.join().unwrap();
+        assert_eq!(
+            stats,
+            ForwardStats {
+                forwarded: 5,
+                dropped: 0
+            }
+        );
+    }
+
+    #[test]
+    fn forward_downstream_drop() {
+        let (tx, rx) = channel();
+        let (tx2, rx2) = bounded(1);
+        let h = rx.forward_map(tx2, |i: i32| i * 2);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        // the pump holds the second one on the full downstream
+        while tx.remain() != 0 || rx2.remain() != 1 {
+            sleep(Duration::from_millis(1));
+        }
+        drop(rx2);
+        let stats = h.join().unwrap();
+        assert_eq!(
+            stats,
+            ForwardStats {
+                forwarded: 1,
+                dropped: 1
+            }
+        );
+        // the upstream is closed
+        assert!(tx.send(3).is_err());
+    }
+
+    #[test]
+    fn error_messages() {
+        let (tx, rx) = channel::<i32>();
+        let empty = rx.try_recv().unwrap_err();
+        assert_eq!(empty, TryRecvError::Empty);
+        drop(tx);
+        let closed = rx.try_recv().unwrap_err();
+        assert_eq!(closed, TryRecvError::Disconnected);
+        assert_ne!(empty.to_string(), closed.to_string());
+        let (tx, rx) = channel::<i32>();
+        drop(rx);
+        let e: Box<dyn Error> = Box::new(tx.send(1).unwrap_err());
+        assert!(e.to_string().contains("closed channel"));
+        assert_eq!(tx.send(2).unwrap_err().into_inner(), 2);
+    }
+
+    #[test]
+    fn lifo_order() {
+        let (tx, rx) = channel_with_order(2, Order::Lifo);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        // the bound is the same
+        assert!(tx.try_send(3).is_err());
+        let tx2 = tx.clone();
+        let h = co!(move || {
+            tx2.send(3).unwrap();
+            tx2.send(4).unwrap();
+        });
+        while tx.waiting_senders() < 1 {
+            thread::yield_now();
+        }
+        assert_eq!(rx.recv(), Ok(2));
+        // the blocked sender pushes on top and blocks again on the next one
+        while tx.ready() || tx.waiting_senders() < 1 {
+            thread::yield_now();
+        }
+        assert_eq!(rx.recv(), Ok(3));
+        h.join().unwrap();
+        drop(tx);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), [4, 1]);
+        assert!(rx.allocated_bytes() > 0);
+    }
+
+    #[test]
+    fn release_after_burst() {
+        let (tx, rx) = channel::<usize>();
+        tx.send(0).unwrap();
+        let one = rx.allocated_bytes();
+        for i in 1..1_000_000 {
+            tx.send(i).unwrap();
+        }
+        assert!(tx.allocated_bytes() > 1000 * one);
+        assert_eq!(rx.iter().take(1_000_000).count(), 1_000_000);
+        assert_eq!(rx.inner.buffer.segments(), 1);
+        assert_eq!(rx.allocated_bytes(), one);
+
+        let (tx, rx) = with_fairness::<usize>(usize::MAX, Fairness::Fifo);
+        for i in 0..100_000 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.iter().take(100_000).count(), 100_000);
+        assert!(rx.allocated_bytes() >= 100_000 * std::mem::size_of::<usize>());
+        rx.shrink_to_fit();
+        assert_eq!(rx.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn ready_hook() {
+        use std::sync::atomic::AtomicUsize;
+
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = with_fairness::<i32>(2, fairness);
+            assert!(!rx.ready());
+            assert!(tx.ready());
+            let calls = Arc::new(AtomicUsize::new(0));
+            let c = calls.clone();
+            rx.on_ready(move || {
+                c.fetch_add(1, Ordering::SeqCst);
+            });
+            assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+            // the sends from another thread are coalesced
+            let tx2 = tx.clone();
+            std::thread::spawn(move || {
+                tx2.send(1).unwrap();
+                tx2.send(2).unwrap();
+            })
+            .join()
+            .unwrap();
+            assert!(rx.ready());
+            assert!(!tx.ready());
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+            assert_eq!(rx.try_recv(), Ok(1));
+            tx.send(3).unwrap();
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+            // drained, armed again
+            while rx.try_recv().is_ok() {}
+            tx.send(4).unwrap();
+            assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+            // the disconnect is readable too
+            drop(tx);
+            assert_eq!(calls.load(Ordering::SeqCst), 3);
+            assert!(rx.ready());
+        }
+    }
+
+    #[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+    #[test]
+    fn readiness_fd() {
+        use std::os::unix::io::AsRawFd;
+
+        fn poll(fd: i32) -> bool {
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            unsafe { libc::poll(&mut pfd, 1, 0) == 1 }
+        }
+
+        fn clear(fd: i32) {
+            let mut v = 0u64;
+            unsafe { libc::read(fd, &mut v as *mut u64 as *mut libc::c_void, 8) };
+        }
+
+        let (tx, rx) = channel::<i32>();
+        tx.send(0).unwrap();
+        // readable already when the fd is created
+        let fd = rx.readiness_fd().unwrap().as_raw_fd();
+        assert!(poll(fd));
+        clear(fd);
+        assert!(!poll(fd));
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        let h = co!(move || tx.send(1).unwrap());
+        h.join().unwrap();
+        assert!(poll(fd));
+        clear(fd);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn reserve_permit() {
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = with_fairness::<i32>(2, fairness);
+            let p1 = tx.reserve().unwrap();
+            tx.send(1).unwrap();
+            // the permit holds a slot as a message does
+            assert_eq!(tx.try_send(2), Err(SendError(2)));
+            assert!(matches!(tx.try_reserve(), Err(TrySendError::Full(()))));
+            p1.send(3);
+            assert_eq!(rx.recv(), Ok(1));
+            assert_eq!(rx.recv(), Ok(3));
+
+            // a dropped permit gives back the slot
+            let mut permits = tx.reserve_many(2).unwrap();
+            assert_eq!(permits.len(), 2);
+            assert!(tx.try_reserve().is_err());
+            permits.next().unwrap().send(4);
+            drop(permits);
+            tx.try_send(5).unwrap();
+            assert_eq!(rx.try_recv(), Ok(4));
+            assert_eq!(rx.try_recv(), Ok(5));
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        }
+    }
+
+    #[test]
+    fn reserve_wait() {
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = with_fairness::<i32>(1, fairness);
+            tx.send(0).unwrap();
+            let tx1 = tx.clone();
+            let h = co!(move || tx1.reserve().map(|p| p.send(1)));
+            sleep(Duration::from_millis(10));
+            assert_eq!(rx.recv(), Ok(0));
+            h.join().unwrap().unwrap();
+            assert_eq!(rx.recv(), Ok(1));
+
+            // the close wakes the reservers with an error
+            tx.send(2).unwrap();
+            let tx1 = tx.clone();
+            let h = co!(move || tx1.reserve().map(|_| ()));
+            sleep(Duration::from_millis(10));
+            tx.close();
+            assert_eq!(h.join().unwrap(), Err(SendError(())));
+            assert!(matches!(
+                tx.try_reserve(),
+                Err(TrySendError::Disconnected(()))
+            ));
+            assert_eq!(rx.recv(), Ok(2));
+        }
+    }
+
+    #[test]
+    fn reserve_cancel() {
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = with_fairness::<i32>(1, fairness);
+            let p = tx.reserve().unwrap();
+            let tx1 = tx.clone();
+            let h = co!(move || tx1.reserve().map(|p| p.send(1)));
+            sleep(Duration::from_millis(10));
+            unsafe { h.coroutine().cancel() };
+            assert!(h.join().is_err());
+            // the canceled reserve takes no slot
+            drop(p);
+            tx.try_reserve().unwrap().send(2);
+            assert_eq!(rx.try_recv(), Ok(2));
+            assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+            tx.try_send(3).unwrap();
+        }
+    }
+
+    #[test]
+    fn drop_queued_in_order() {
+        use crate::std::sync::Mutex;
+
+        struct Item {
+            i: usize,
+            log: Sender<usize>,
+            lock: Arc<Mutex<Vec<usize>>>,
+            // the channel that the item is queued in
+            own: Option<Sender<Item>>,
+        }
+
+        impl Drop for Item {
+            fn drop(&mut self) {
+                self.lock.lock().unwrap().push(self.i);
+                self.log.send(self.i).unwrap();
+                if let Some(own) = self.own.take() {
+                    // the receivers are gone, the send fails at once
+                    assert!(own.try_send(self.item(100)).is_err());
+                }
+            }
+        }
+
+        impl Item {
+            fn item(&self, i: usize) -> Item {
+                Item {
+                    i,
+                    log: self.log.clone(),
+                    lock: self.lock.clone(),
+                    own: None,
+                }
+            }
+        }
+
+        for fairness in [Fairness::Throughput, Fairness::Fifo] {
+            let (log_tx, log_rx) = channel();
+            let lock = Arc::new(Mutex::new(Vec::new()));
+            let (tx, rx) = with_fairness::<Item>(usize::MAX, fairness);
+            for i in 0..10 {
+                let item = Item {
+                    i,
+                    log: log_tx.clone(),
+                    lock: lock.clone(),
+                    own: Some(tx.clone()),
+                };
+                tx.send(item).unwrap();
+            }
+            drop(tx);
+            let h = co!(move || drop(rx));
+            h.join().unwrap();
+            let want: Vec<_> = (0..10).flat_map(|i| vec![i, 100]).collect();
+            assert_eq!(log_rx.try_iter().collect::<Vec<_>>(), want);
+            assert_eq!(*lock.lock().unwrap(), want);
+        }
+    }
+
+    #[test]
+    fn hooks_trace_messages() {
+        use crate::std::sync::hooks::{HookEvent, Hooks};
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (on_send, on_recv, on_drop) = (log.clone(), log.clone(), log.clone());
+        let (tx, rx) = channel_with_hooks(Hooks {
+            on_send: Some(Box::new(move |t: &u32, ev: &HookEvent| {
+                assert_eq!(ev.at, ev.sent);
+                on_send.lock().push(("send", *t, ev.seq));
+            })),
+            on_recv: Some(Box::new(move |t: &u32, ev: &HookEvent| {
+                assert!(ev.at >= ev.sent);
+                on_recv.lock().push(("recv", *t, ev.seq));
+            })),
+            on_drop: Some(Box::new(move |t: &u32, ev: &HookEvent| {
+                on_drop.lock().push(("drop", *t, ev.seq));
+            })),
+        });
+        assert!(tx.channel_id().is_some());
+        assert_eq!(channel::<u32>().0.channel_id(), None);
+
+        tx.send(1).unwrap();
+        tx.send_all(vec![2, 3]).unwrap();
+        assert_eq!(rx.recv().unwrap(), 1);
+        let mut buf = Vec::new();
+        rx.recv_many(&mut buf, 1).unwrap();
+        tx.send(4).unwrap();
+        drop(rx);
+        let want = vec![
+            ("send", 1, 0),
+            ("send", 2, 1),
+            ("send", 3, 2),
+            ("recv", 1, 0),
+            ("recv", 2, 1),
+            ("send", 4, 3),
+            ("drop", 3, 2),
+            ("drop", 4, 3),
+        ];
+        assert_eq!(*log.lock(), want);
+    }
+
+    #[test]
+    fn latency_recorder_per_channel() {
+        use crate::std::sync::hooks::latency_recorder;
+
+        let recorder = latency_recorder();
+        let (tx1, rx1) = channel_with_hooks(recorder.clone());
+        let (tx2, rx2) = channel_with_hooks(recorder.clone());
+        tx1.send(1).unwrap();
+        tx2.send(2).unwrap();
+        tx2.send(3).unwrap();
+        sleep(Duration::from_millis(10));
+        rx1.recv().unwrap();
+        rx2.recv().unwrap();
+        rx2.recv().unwrap();
+
+        let (id1, id2) = (tx1.channel_id().unwrap(), tx2.channel_id().unwrap());
+        assert_eq!(recorder.channels(), vec![id1, id2]);
+        let h1 = recorder.histogram(id1).unwrap();
+        assert_eq!(h1.count(), 1);
+        assert!(h1.percentile(100.0) >= Duration::from_millis(10));
+        assert_eq!(recorder.histogram(id2).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn rt_sender_deferred_wakeup() {
+        let (tx, rx) = channel::<usize>();
+        let rt = tx.rt_sender(RtWakeup::Deferred).unwrap();
+        let h = co!(move || (0..1000).map(|_| rx.recv().unwrap()).collect::<Vec<_>>());
+        thread::sleep(Duration::from_millis(10));
+        // the real time thread backs off when the spares run out
+        thread::spawn(move || {
+            for i in 0..1000 {
+                let mut m = i;
+                loop {
+                    match rt.try_send(m) {
+                        Ok(()) => break,
+                        Err(RtSendError::WouldAllocate(t)) => {
+                            m = t;
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                        Err(e) => panic!("{}", e),
+                    }
+                }
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!(h.join().unwrap(), (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rt_sender_errors() {
+        let (tx, rx) = channel_buf::<usize>(2);
+        let rt = tx.rt_sender(RtWakeup::Immediate).unwrap();
+        rt.try_send(1).unwrap();
+        rt.try_send(2).unwrap();
+        assert_eq!(rt.try_send(3), Err(RtSendError::Full(3)));
+        assert_eq!(rx.recv(), Ok(1));
+        drop(rx);
+        assert_eq!(rt.try_send(4), Err(RtSendError::Disconnected(4)));
+
+        // no receiver drains the queue, the spares run out
+        let (tx, _rx) = channel::<usize>();
+        let rt = tx.rt_sender(RtWakeup::Deferred).unwrap();
+        let full = (0..100_000).find(|&i| rt.try_send(i).is_err());
+        assert!(full.is_some());
+
+        let (tx, _rx) = with_fairness::<usize>(1, Fairness::Fifo);
+        assert!(tx.rt_sender(RtWakeup::Deferred).is_none());
+    }
+
+    #[test]
+    fn waiter_stats() {
+        for &fairness in &[Fairness::Throughput, Fairness::Fifo] {
+            let (tx, rx) = channel_with_stats::<usize>(2, fairness);
+            tx.send_all(0..2).unwrap();
+            let blocked = {
+                let tx = tx.clone();
+                co!(move || tx.send(2).unwrap())
+            };
+            sleep(Duration::from_millis(50));
+            let stats = tx.stats();
+            assert_eq!(stats.len, 2);
+            assert_eq!(stats.capacity, Some(2));
+            assert_eq!(stats.waiting_senders, 1);
+            assert_eq!(stats.waiting_receivers, 0);
+            assert_eq!(stats.total_sent, Some(2));
+            assert_eq!(stats.total_received, Some(0));
+
+            let mut buf = Vec::new();
+            rx.recv_many(&mut buf, 2).unwrap();
+            blocked.join().unwrap();
+            assert_eq!(rx.recv().unwrap(), 2);
+            assert_eq!(tx.waiting_senders(), 0);
+
+            let idle: Vec<_> = (0..2)
+                .map(|_| {
+                    let rx = rx.clone();
+                    co!(move || rx.recv().unwrap())
+                })
+                .collect();
+            sleep(Duration::from_millis(50));
+            assert_eq!(rx.waiting_receivers(), 2);
+            tx.send_all(3..5).unwrap();
+            idle.into_iter().for_each(|h| drop(h.join().unwrap()));
+            let stats = rx.stats();
+            assert_eq!(stats.waiting_receivers, 0);
+            assert_eq!((stats.total_sent, stats.total_received), (Some(5), Some(5)));
+        }
+
+        // the other channels don't count
+        let (tx, rx) = channel::<usize>();
+        tx.send(1).unwrap();
+        let stats = rx.stats();
+        assert_eq!((stats.len, stats.capacity), (1, None));
+        assert_eq!((stats.total_sent, stats.total_received), (None, None));
+    }
 }
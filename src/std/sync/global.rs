@@ -0,0 +1,146 @@
+//! channels that can be declared as statics
+//!
+//! a `GlobalChannel` is created on the first use, so there is no init order
+//! to care about, the runtime doesn't have to be started when the static is
+//! declared. `Runtime::shutdown` closes all the global channels that are in
+//! use, the receivers drain the buffered messages and then see the disconnect
+
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use super::channel::{self, Receiver, Sender};
+use crate::std::lazy::sync::OnceCell;
+
+// the global channels created so far, closed by `close_globals`
+static GLOBALS: Lazy<Mutex<Vec<&'static dyn Close>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+trait Close: Sync {
+    fn close(&self);
+}
+
+/// a mpmc channel for a static, created on the first use
+///
+/// ```
+/// use mco::std::sync::GlobalChannel;
+///
+/// static EVENTS: GlobalChannel<u32> = GlobalChannel::new();
+///
+/// let rx = EVENTS.receiver();
+/// let h = mco::co!(|| EVENTS.sender().send(42).unwrap());
+/// assert_eq!(rx.recv().unwrap(), 42);
+/// h.join().unwrap();
+/// ```
+pub struct GlobalChannel<T> {
+    // 0 is unbounded
+    buf: usize,
+    chan: OnceCell<(Sender<T>, Receiver<T>)>,
+}
+
+unsafe impl<T: Send> Sync for GlobalChannel<T> {}
+
+impl<T: Send + 'static> GlobalChannel<T> {
+    /// an unbounded global channel
+    pub const fn new() -> Self {
+        GlobalChannel {
+            buf: 0,
+            chan: OnceCell::new(),
+        }
+    }
+
+    /// a global channel that holds at most `buf` messages
+    pub const fn bounded(buf: usize) -> Self {
+        GlobalChannel {
+            buf,
+            chan: OnceCell::new(),
+        }
+    }
+
+    fn get(&'static self) -> &(Sender<T>, Receiver<T>) {
+        let mut created = false;
+        let chan = self.chan.get_or_init(|| {
+            created = true;
+            match self.buf {
+                0 => channel::channel(),
+                n => channel::channel_buf(n),
+            }
+        });
+        if created {
+            GLOBALS.lock().push(self);
+        }
+        chan
+    }
+
+    /// a sender of the channel
+    pub fn sender(&'static self) -> Sender<T> {
+        self.get().0.clone()
+    }
+
+    /// a receiver of the channel, the receivers share the messages
+    pub fn receiver(&'static self) -> Receiver<T> {
+        self.get().1.clone()
+    }
+
+    /// close the channel, see `Sender::close`
+    pub fn close(&'static self) {
+        self.get().0.close();
+    }
+
+    /// return true if the channel is closed
+    pub fn is_closed(&self) -> bool {
+        self.chan.get().map_or(false, |c| c.0.is_closed())
+    }
+}
+
+impl<T: Send + 'static> Close for GlobalChannel<T> {
+    fn close(&self) {
+        if let Some(c) = self.chan.get() {
+            c.0.close();
+        }
+    }
+}
+
+impl<T> fmt::Debug for GlobalChannel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GlobalChannel")
+            .field("buf", &self.buf)
+            .field("created", &self.chan.get().is_some())
+            .finish()
+    }
+}
+
+// close all the global channels in use
+pub(crate) fn close_globals() {
+    let globals = GLOBALS.lock().clone();
+    for g in globals {
+        g.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_drains() {
+        static CHAN: GlobalChannel<usize> = GlobalChannel::bounded(16);
+        assert!(!CHAN.is_closed());
+        let rx = CHAN.receiver();
+        let h = co!(move || {
+            let mut sum = 0;
+            while let Ok(v) = rx.recv() {
+                sum += v;
+            }
+            sum
+        });
+        let tx = CHAN.sender();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        close_globals();
+        assert!(CHAN.is_closed());
+        assert!(tx.send(1).is_err());
+        assert_eq!(h.join().unwrap(), 45);
+    }
+}
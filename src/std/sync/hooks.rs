@@ -0,0 +1,245 @@
+//! the instrumentation hooks of a channel, see `channel_with_hooks`
+//!
+//! the hooks see each message when it's sent, received or dropped unreceived,
+//! with its sequence number in the channel and the time it's sent, so that
+//! they can feed a tracing span or a flight recorder. they are kept once per
+//! channel, a channel without hooks pays one branch on each send and receive
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+
+use crate::std::time::Histogram;
+use crate::timeout_list::now_instant;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// what a hook sees of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HookEvent {
+    /// the id of the channel, see `Sender::channel_id`
+    pub channel: u64,
+    /// the sequence number of the message in the channel, from 0
+    pub seq: u64,
+    /// when the message is sent
+    pub sent: Instant,
+    /// when the hook is called, the same as `sent` for `on_send`
+    pub at: Instant,
+}
+
+impl HookEvent {
+    /// how long the message is in the channel
+    pub fn latency(&self) -> Duration {
+        self.at.saturating_duration_since(self.sent)
+    }
+}
+
+/// the hooks of a channel
+///
+/// they run on the side that sends, receives or drops the message, outside
+/// of the locks of the channel. they should not block for long nor panic
+pub trait ChannelHooks<T>: Send + Sync {
+    /// a message is sent, it runs for each try of `try_send` as well
+    fn on_send(&self, _t: &T, _ev: &HookEvent) {}
+
+    /// a message is received
+    fn on_recv(&self, _t: &T, _ev: &HookEvent) {}
+
+    /// a queued message is dropped since all the receivers are gone
+    fn on_drop(&self, _t: &T, _ev: &HookEvent) {}
+}
+
+type Hook<T> = Box<dyn Fn(&T, &HookEvent) + Send + Sync>;
+
+/// the hooks made of closures, the missing ones do nothing
+///
+/// ```
+/// use mco::std::sync::channel_with_hooks;
+/// use mco::std::sync::hooks::{HookEvent, Hooks};
+///
+/// let (tx, rx) = channel_with_hooks(Hooks {
+///     on_recv: Some(Box::new(|t: &u32, ev: &HookEvent| {
+///         println!("{} took {:?}", t, ev.latency())
+///     })),
+///     ..Hooks::default()
+/// });
+/// tx.send(1).unwrap();
+/// assert_eq!(rx.recv().unwrap(), 1);
+/// ```
+pub struct Hooks<T> {
+    pub on_send: Option<Hook<T>>,
+    pub on_recv: Option<Hook<T>>,
+    pub on_drop: Option<Hook<T>>,
+}
+
+impl<T> Default for Hooks<T> {
+    fn default() -> Self {
+        Hooks {
+            on_send: None,
+            on_recv: None,
+            on_drop: None,
+        }
+    }
+}
+
+impl<T> ChannelHooks<T> for Hooks<T> {
+    fn on_send(&self, t: &T, ev: &HookEvent) {
+        if let Some(f) = &self.on_send {
+            f(t, ev)
+        }
+    }
+
+    fn on_recv(&self, t: &T, ev: &HookEvent) {
+        if let Some(f) = &self.on_recv {
+            f(t, ev)
+        }
+    }
+
+    fn on_drop(&self, t: &T, ev: &HookEvent) {
+        if let Some(f) = &self.on_drop {
+            f(t, ev)
+        }
+    }
+}
+
+impl<T> fmt::Debug for Hooks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_send", &self.on_send.is_some())
+            .field("on_recv", &self.on_recv.is_some())
+            .field("on_drop", &self.on_drop.is_some())
+            .finish()
+    }
+}
+
+/// the hooks that record the latency from the send to the receive of each
+/// channel, see `latency_recorder`
+#[derive(Clone, Default)]
+pub struct LatencyRecorder {
+    channels: Arc<RwLock<HashMap<u64, Arc<Histogram>>>>,
+}
+
+impl LatencyRecorder {
+    /// the latency of a channel, `None` if it has received nothing yet
+    pub fn histogram(&self, channel: u64) -> Option<Arc<Histogram>> {
+        self.channels.read().get(&channel).cloned()
+    }
+
+    /// the ids of the channels that have received a message, in order
+    pub fn channels(&self) -> Vec<u64> {
+        let mut ids: Vec<_> = self.channels.read().keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn record(&self, ev: &HookEvent) {
+        if let Some(h) = self.channels.read().get(&ev.channel) {
+            return h.record(ev.latency());
+        }
+        let h = self
+            .channels
+            .write()
+            .entry(ev.channel)
+            .or_insert_with(|| Arc::new(Histogram::new()))
+            .clone();
+        h.record(ev.latency());
+    }
+}
+
+impl<T> ChannelHooks<T> for LatencyRecorder {
+    fn on_recv(&self, _t: &T, ev: &HookEvent) {
+        self.record(ev)
+    }
+}
+
+impl fmt::Debug for LatencyRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LatencyRecorder")
+            .field("channels", &self.channels())
+            .finish()
+    }
+}
+
+/// the hooks that record the send to receive latency into a `Histogram`
+/// for each channel, a clone can be given to many channels
+///
+/// ```
+/// use mco::std::sync::channel_with_hooks;
+/// use mco::std::sync::hooks::latency_recorder;
+///
+/// let recorder = latency_recorder();
+/// let (tx, rx) = channel_with_hooks(recorder.clone());
+/// tx.send(1).unwrap();
+/// rx.recv().unwrap();
+/// let id = tx.channel_id().unwrap();
+/// assert_eq!(recorder.histogram(id).unwrap().count(), 1);
+/// ```
+pub fn latency_recorder() -> LatencyRecorder {
+    LatencyRecorder::default()
+}
+
+// when and in which order a message is sent
+#[derive(Clone, Copy)]
+pub(crate) struct Trace {
+    seq: u64,
+    sent: Instant,
+}
+
+// the hooks of one channel
+pub(crate) struct ChanHooks<T> {
+    id: u64,
+    seq: AtomicU64,
+    hooks: Box<dyn ChannelHooks<T>>,
+}
+
+impl<T> ChanHooks<T> {
+    pub fn new<H: ChannelHooks<T> + 'static>(hooks: H) -> Self {
+        ChanHooks {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            seq: AtomicU64::new(0),
+            hooks: Box::new(hooks),
+        }
+    }
+
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn event(&self, trace: &Trace) -> HookEvent {
+        HookEvent {
+            channel: self.id,
+            seq: trace.seq,
+            sent: trace.sent,
+            at: now_instant(),
+        }
+    }
+
+    pub fn sent(&self, t: &T) -> Trace {
+        let trace = Trace {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            sent: now_instant(),
+        };
+        let ev = HookEvent {
+            channel: self.id,
+            seq: trace.seq,
+            sent: trace.sent,
+            at: trace.sent,
+        };
+        self.hooks.on_send(t, &ev);
+        trace
+    }
+
+    pub fn received(&self, t: &T, trace: &Trace) {
+        self.hooks.on_recv(t, &self.event(trace));
+    }
+
+    pub fn dropped(&self, t: &T, trace: &Trace) {
+        self.hooks.on_drop(t, &self.event(trace));
+    }
+}
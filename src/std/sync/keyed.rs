@@ -0,0 +1,427 @@
+//! keyed channel implementation
+//! an mpmc channel where the messages of the same key go to the same
+//! receiver in the sending order, the different keys spread over the
+//! receivers
+//!
+//! each clone of the `Receiver` is a subscriber with a queue of its own. a
+//! key is bound to a receiver by the rendezvous hash of the key over the
+//! subscribed receivers, so a receiver that joins or leaves moves only its
+//! share of the keys. a key that has queued messages stays bound to its
+//! receiver until they are all received, only then the next message of the
+//! key picks a receiver again, that's how a rebalance keeps the order. the
+//! queue of a receiver that is dropped is handed to the remaining ones in
+//! order before any new message
+//!
+//! the order is the order of the receiving, once the last message of a key
+//! is taken the next one may go to another receiver and the two may be
+//! handled at the same time for a moment
+//!
+//! ```
+//! use mco::std::sync::keyed::channel_keyed;
+//!
+//! let (tx, rx) = channel_keyed::<&str, i32>(16);
+//! let rx2 = rx.clone();
+//! for i in 0..4 {
+//!     tx.send("a", i).unwrap();
+//! }
+//! // all of "a" went to one receiver, in order
+//! let rx = if rx.remain() > 0 { rx } else { rx2 };
+//! assert_eq!((0..4).map(|_| rx.recv().unwrap()).collect::<Vec<_>>(), [0, 1, 2, 3]);
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::Semphore;
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// Create a keyed channel that buffers up to `cap` messages over all the
+/// receivers, `usize::MAX` for an unbounded one
+pub fn channel_keyed<K: Hash, T>(cap: usize) -> (Sender<K, T>, Receiver<K, T>) {
+    let mut state = State {
+        queues: HashMap::new(),
+        bound: HashMap::new(),
+        len: 0,
+        next_id: 0,
+    };
+    let (id, ready) = state.subscribe();
+    let inner = Arc::new(Inner {
+        state: Mutex::new(state),
+        cap,
+        hasher: RandomState::new(),
+        wake_sender: Semphore::new(0),
+        sender_num: AtomicUsize::new(1),
+        _key: PhantomData,
+    });
+    let tx = Sender {
+        inner: inner.clone(),
+    };
+    (tx, Receiver { inner, id, ready })
+}
+
+struct Queue<T> {
+    // the messages with the hash of their key
+    items: VecDeque<(u64, T)>,
+    // one permit for each queued message
+    ready: Arc<Semphore>,
+}
+
+struct State<T> {
+    // the queues of the subscribed receivers, by the receiver id
+    queues: HashMap<u64, Queue<T>>,
+    // the receiver of each key that has queued messages, with their number
+    bound: HashMap<u64, (u64, usize)>,
+    // the queued messages of all the receivers
+    len: usize,
+    next_id: u64,
+}
+
+impl<T> State<T> {
+    fn subscribe(&mut self) -> (u64, Arc<Semphore>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let ready = Arc::new(Semphore::new(0));
+        let queue = Queue {
+            items: VecDeque::new(),
+            ready: ready.clone(),
+        };
+        self.queues.insert(id, queue);
+        (id, ready)
+    }
+
+    // the receiver with the highest score for the key, `queues` is not empty
+    fn pick(&self, hash: u64) -> u64 {
+        let score = |id: u64| mix(hash ^ mix(id));
+        let ids = self.queues.keys().copied();
+        ids.max_by_key(|&id| score(id)).expect("no receiver")
+    }
+
+    fn push(&mut self, hash: u64, t: T) {
+        let id = match self.bound.get_mut(&hash) {
+            Some((id, n)) => {
+                *n += 1;
+                *id
+            }
+            None => {
+                let id = self.pick(hash);
+                self.bound.insert(hash, (id, 1));
+                id
+            }
+        };
+        let queue = self.queues.get_mut(&id).expect("bound to a gone receiver");
+        queue.items.push_back((hash, t));
+        self.len += 1;
+        // the message is queued before the permit is visible
+        queue.ready.post();
+    }
+
+    fn pop(&mut self, id: u64) -> Option<T> {
+        let (hash, t) = self.queues.get_mut(&id)?.items.pop_front()?;
+        if let Some((_, n)) = self.bound.get_mut(&hash) {
+            *n -= 1;
+            if *n == 0 {
+                self.bound.remove(&hash);
+            }
+        }
+        self.len -= 1;
+        Some(t)
+    }
+}
+
+// the finalizer of splitmix64
+#[inline]
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+struct Inner<K, T> {
+    state: Mutex<State<T>>,
+    cap: usize,
+    hasher: RandomState,
+    // thread/coroutine for wake up
+    wake_sender: Semphore,
+    sender_num: AtomicUsize,
+    _key: PhantomData<fn(&K)>,
+}
+
+impl<K: Hash, T> Inner<K, T> {
+    fn hash(&self, key: &K) -> u64 {
+        let mut h = self.hasher.build_hasher();
+        key.hash(&mut h);
+        h.finish()
+    }
+
+    fn send(&self, key: &K, t: T, block: bool) -> Result<(), SendError<T>> {
+        let hash = self.hash(key);
+        loop {
+            let mut state = self.state.lock();
+            if state.queues.is_empty() {
+                return Err(SendError(t));
+            }
+            if state.len < self.cap {
+                state.push(hash, t);
+                return Ok(());
+            }
+            drop(state);
+            if !block {
+                return Err(SendError(t));
+            }
+            self.wake_sender.wait();
+        }
+    }
+}
+
+impl<K, T> Inner<K, T> {
+    // take one message of the receiver, must be called with a permit of its
+    // `ready`, it's none if all the senders are gone
+    fn recv_permit(&self, id: u64, ready: &Semphore) -> Result<T, TryRecvError> {
+        match self.state.lock().pop(id) {
+            Some(t) => {
+                self.wake_sender.post();
+                Ok(t)
+            }
+            None => {
+                // keep the disconnect for the next call
+                ready.post();
+                Err(TryRecvError::Disconnected)
+            }
+        }
+    }
+
+    fn drop_recv(&self, id: u64) {
+        let mut state = self.state.lock();
+        let queue = match state.queues.remove(&id) {
+            Some(queue) => queue,
+            None => return,
+        };
+        if state.queues.is_empty() {
+            state.bound.clear();
+            state.len = 0;
+            drop(state);
+            drop(queue);
+            // the blocked senders should come back
+            while self.wake_sender.get_value() == 0 {
+                self.wake_sender.post();
+            }
+            return;
+        }
+        // the keys of the queue are bound to it only, hand them over in order
+        for (hash, _) in queue.items.iter() {
+            state.bound.remove(hash);
+        }
+        state.len -= queue.items.len();
+        for (hash, t) in queue.items {
+            state.push(hash, t);
+        }
+    }
+}
+
+/// The sending half of the keyed channel
+pub struct Sender<K, T> {
+    inner: Arc<Inner<K, T>>,
+}
+
+impl<K: Hash, T> Sender<K, T> {
+    /// send the message to the receiver of the key, it blocks when the
+    /// channel is full
+    pub fn send(&self, key: K, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(&key, t, true)
+    }
+
+    /// send the message to the receiver of the key, return error if the
+    /// channel is full or all the receivers are gone
+    pub fn try_send(&self, key: K, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(&key, t, false)
+    }
+
+    /// the number of the buffered messages of all the receivers
+    pub fn remain(&self) -> usize {
+        self.inner.state.lock().len
+    }
+
+    /// the number of the subscribed receivers
+    pub fn receivers(&self) -> usize {
+        self.inner.state.lock().queues.len()
+    }
+}
+
+impl<K, T> Clone for Sender<K, T> {
+    fn clone(&self) -> Sender<K, T> {
+        self.inner.sender_num.fetch_add(1, Ordering::AcqRel);
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K, T> Drop for Sender<K, T> {
+    fn drop(&mut self) {
+        if self.inner.sender_num.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // wake the blocked receivers, each one keeps its disconnect
+            let state = self.inner.state.lock();
+            state.queues.values().for_each(|q| q.ready.post());
+        }
+    }
+}
+
+impl<K, T> fmt::Debug for Sender<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// The receiving half of the keyed channel
+///
+/// a clone subscribes a new receiver that takes its share of the keys, a
+/// drop unsubscribes it
+pub struct Receiver<K, T> {
+    inner: Arc<Inner<K, T>>,
+    id: u64,
+    ready: Arc<Semphore>,
+}
+
+impl<K, T> Receiver<K, T> {
+    /// try to receive the next message of the receiver without blocking
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if !self.ready.try_wait() {
+            return match self.inner.sender_num.load(Ordering::Acquire) {
+                0 => Err(TryRecvError::Disconnected),
+                _ => Err(TryRecvError::Empty),
+            };
+        }
+        self.inner.recv_permit(self.id, &self.ready)
+    }
+
+    /// wait for the next message of the receiver, return error if its queue
+    /// is empty and all the senders are gone
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.ready.wait();
+        self.inner
+            .recv_permit(self.id, &self.ready)
+            .map_err(|_| RecvError)
+    }
+
+    /// wait for the next message of the receiver with a timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        if !self.ready.wait_timeout(timeout) {
+            return Err(RecvTimeoutError::Timeout);
+        }
+        self.inner
+            .recv_permit(self.id, &self.ready)
+            .map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    /// the number of the buffered messages of this receiver
+    pub fn remain(&self) -> usize {
+        let state = self.inner.state.lock();
+        state.queues.get(&self.id).map_or(0, |q| q.items.len())
+    }
+}
+
+impl<K, T> Clone for Receiver<K, T> {
+    fn clone(&self) -> Receiver<K, T> {
+        let mut state = self.inner.state.lock();
+        let (id, ready) = state.subscribe();
+        if self.inner.sender_num.load(Ordering::Acquire) == 0 {
+            ready.post();
+        }
+        Receiver {
+            inner: self.inner.clone(),
+            id,
+            ready,
+        }
+    }
+}
+
+impl<K, T> Drop for Receiver<K, T> {
+    fn drop(&mut self) {
+        self.inner.drop_recv(self.id);
+    }
+}
+
+impl<K, T> fmt::Debug for Receiver<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn keyed_order() {
+        let (tx, rx) = channel_keyed::<usize, (usize, usize)>(8);
+        let hs: Vec<_> = (0..4)
+            .map(|_| {
+                let rx = rx.clone();
+                co!(move || {
+                    let mut got = Vec::new();
+                    while let Ok(m) = rx.recv() {
+                        got.push(m);
+                    }
+                    got
+                })
+            })
+            .collect();
+        drop(rx);
+        for seq in 0..100 {
+            for key in 0..16 {
+                tx.send(key, (key, seq)).unwrap();
+            }
+        }
+        drop(tx);
+        let mut owners = HashMap::new();
+        for (i, h) in hs.into_iter().enumerate() {
+            let got = h.join().unwrap();
+            for key in 0..16 {
+                let seqs: Vec<_> = got.iter().filter(|m| m.0 == key).map(|m| m.1).collect();
+                if !seqs.is_empty() {
+                    // the same receiver in the sending order
+                    assert_eq!(seqs, (0..100).collect::<Vec<_>>());
+                    assert!(owners.insert(key, i).is_none());
+                }
+            }
+        }
+        assert_eq!(owners.len(), 16);
+    }
+
+    #[test]
+    fn rebalance_keeps_order() {
+        let (tx, rx) = channel_keyed::<u32, u32>(usize::MAX);
+        let rx2 = rx.clone();
+        for i in 0..50 {
+            tx.send(i % 5, i).unwrap();
+        }
+        // the queue of a gone receiver moves to the other in order
+        drop(rx2);
+        assert_eq!(rx.remain(), 50);
+        assert_eq!(tx.receivers(), 1);
+        let got: Vec<_> = (0..50).map(|_| rx.recv().unwrap()).collect();
+        for key in 0..5 {
+            let seqs: Vec<_> = got.iter().filter(|&&i| i % 5 == key).collect();
+            assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+        }
+        // a new receiver takes the keys that have no queued message
+        let rx3 = rx.clone();
+        let h = thread::spawn(move || rx3.recv_timeout(Duration::from_secs(5)));
+        for i in 0..64 {
+            tx.send(i, i).unwrap();
+        }
+        assert!(h.join().unwrap().is_ok());
+        drop(rx);
+        assert_eq!(tx.send(1, 1), Err(SendError(1)));
+    }
+}
@@ -0,0 +1,352 @@
+//! channel for the values that are not `Send`, within a single worker
+//!
+//! the endpoints are `!Send` and bound to the worker they are created on, the
+//! coroutines that share them must be pinned to that worker, see `Builder::pin`
+//! and `coroutine::spawn_pinned`. there is no atomic on the fast path, only a
+//! blocked receiver is parked with a `Blocker`
+//! for example:
+//! ```
+//! use std::rc::Rc;
+//! use mco::coroutine::{self, Builder};
+//! use mco::std::sync::local;
+//!
+//! Builder::new().pin(0).spawn(|| {
+//!     let (tx, rx) = local::channel();
+//!     let h = coroutine::spawn_pinned(move || {
+//!         for i in 0..10 {
+//!             tx.send(Rc::new(i)).unwrap();
+//!         }
+//!     });
+//!     let sum: i32 = rx.iter().map(|v| *v).sum();
+//!     assert_eq!(sum, 45);
+//!     h.join().unwrap();
+//! }).join().unwrap();
+//! ```
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::Blocker;
+use crate::coroutine_impl::try_current;
+use crate::scheduler::worker_id;
+use crate::timeout_list::now_instant;
+
+/// create a local channel, the endpoints can only be used on the current worker
+///
+/// panic if it's called in a coroutine that is not pinned, in a thread context
+/// the endpoints are bound to the thread
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    if let Ok(co) = try_current() {
+        assert!(
+            co.pinned_worker().is_some(),
+            "local channel must be created in a pinned coroutine"
+        );
+    }
+    let inner = Rc::new(Inner {
+        chan: RefCell::new(Chan {
+            queue: VecDeque::new(),
+            waiters: Vec::new(),
+            senders: 1,
+            rx_gone: false,
+        }),
+        worker: worker_id(),
+    });
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+struct Chan<T> {
+    queue: VecDeque<T>,
+    // the blocked receivers, more than one when selecting on the same receiver
+    waiters: Vec<Arc<Blocker>>,
+    senders: usize,
+    rx_gone: bool,
+}
+
+struct Inner<T> {
+    chan: RefCell<Chan<T>>,
+    // the worker that the channel is created on
+    worker: usize,
+}
+
+impl<T> Inner<T> {
+    #[inline]
+    fn check_worker(&self) {
+        // the pinned coroutines may be moved together by a shrink
+        debug_assert_eq!(
+            worker_id(),
+            crate::scheduler::get_scheduler().pin_target(self.worker),
+            "local channel used on another worker"
+        );
+    }
+
+    fn wake_all(&self) {
+        let waiters = std::mem::take(&mut self.chan.borrow_mut().waiters);
+        for w in waiters {
+            let _ = w.unpark();
+        }
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.check_worker();
+        let mut chan = self.chan.borrow_mut();
+        match chan.queue.pop_front() {
+            Some(t) => Ok(t),
+            None if chan.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn recv(&self, dur: Option<Duration>) -> Result<T, RecvTimeoutError> {
+        let deadline = dur.map(|d| now_instant() + d);
+        loop {
+            match self.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let timeout = match deadline {
+                None => None,
+                Some(d) => {
+                    let now = now_instant();
+                    if now >= d {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    Some(d - now)
+                }
+            };
+
+            // the senders run on the same worker, nothing can happen
+            // between the check and the registration
+            let cur = Blocker::current();
+            self.chan.borrow_mut().waiters.push(cur.clone());
+            if cur.park(timeout).is_err() {
+                self.chan
+                    .borrow_mut()
+                    .waiters
+                    .retain(|w| !Arc::ptr_eq(w, &cur));
+                return match self.try_recv() {
+                    Ok(t) => Ok(t),
+                    Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+                    Err(TryRecvError::Empty) => Err(RecvTimeoutError::Timeout),
+                };
+            }
+        }
+    }
+}
+
+/// The sending half of the local channel
+pub struct Sender<T> {
+    inner: Rc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// send the value, it never blocks
+    /// return error if the receiver is gone
+    #[must_use = "the message is returned in the error if the channel is closed"]
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.check_worker();
+        {
+            let mut chan = self.inner.chan.borrow_mut();
+            if chan.rx_gone {
+                return Err(SendError(t));
+            }
+            chan.queue.push_back(t);
+            if chan.waiters.is_empty() {
+                return Ok(());
+            }
+        }
+        self.inner.wake_all();
+        Ok(())
+    }
+
+    /// return true if the receiver is dropped
+    pub fn is_closed(&self) -> bool {
+        self.inner.chan.borrow().rx_gone
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.inner.chan.borrow_mut().senders += 1;
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let senders = {
+            let mut chan = self.inner.chan.borrow_mut();
+            chan.senders -= 1;
+            chan.senders
+        };
+        if senders == 0 {
+            self.inner.wake_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// The receiving half of the local channel
+pub struct Receiver<T> {
+    inner: Rc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// try to receive a value without blocking
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// wait for a value, return error if all the senders are dropped
+    /// and the channel is empty
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.inner.recv(None) {
+            Err(RecvTimeoutError::Timeout) => unreachable!("local recv timeout"),
+            data => data.map_err(|_| RecvError),
+        }
+    }
+
+    /// wait for a value with a timeout
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv(Some(timeout))
+    }
+
+    /// iterate over the values until all the senders are dropped
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || self.recv().ok())
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // the values are dropped after the borrow is released
+        let _queue = {
+            let mut chan = self.inner.chan.borrow_mut();
+            chan.rx_gone = true;
+            std::mem::take(&mut chan.queue)
+        };
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::{spawn_pinned, Builder};
+    use std::cell::Cell;
+
+    // run `f` in a coroutine pinned to worker 0
+    fn pinned<F: FnOnce() + Send + 'static>(f: F) {
+        Builder::new().pin(0).spawn(f).join().unwrap();
+    }
+
+    #[test]
+    fn thread_context() {
+        let (tx, rx) = channel::<i32>();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx.send(1).unwrap();
+        tx.clone().send(2).unwrap();
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+        let (tx, rx) = channel::<i32>();
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+
+    #[test]
+    fn ping_pong_rc() {
+        pinned(|| {
+            let hits = Rc::new(Cell::new(0));
+            let (tx, rx) = channel::<Rc<Cell<i32>>>();
+            let (back_tx, back_rx) = channel();
+            let h = spawn_pinned(move || {
+                for v in rx.iter() {
+                    v.set(v.get() + 1);
+                    assert_eq!(crate::scheduler::worker_id(), 0);
+                    back_tx.send(v).unwrap();
+                }
+            });
+            for _ in 0..100 {
+                tx.send(hits.clone()).unwrap();
+                back_rx.recv().unwrap();
+            }
+            drop(tx);
+            h.join().unwrap();
+            assert_eq!(hits.get(), 100);
+        });
+    }
+
+    #[test]
+    fn recv_timeout() {
+        pinned(|| {
+            let (tx, rx) = channel::<i32>();
+            let r = rx.recv_timeout(Duration::from_millis(10));
+            assert_eq!(r, Err(RecvTimeoutError::Timeout));
+            let h = spawn_pinned(move || {
+                crate::coroutine::sleep(Duration::from_millis(10));
+                tx.send(1).unwrap();
+            });
+            assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+            h.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn select_local() {
+        pinned(|| {
+            let (tx1, rx1) = channel::<Rc<i32>>();
+            let (tx2, rx2) = channel::<Rc<i32>>();
+            // keep rx1 connected so that only rx2 has an event
+            let _tx1 = tx1;
+            let h = spawn_pinned(move || {
+                crate::coroutine::sleep(Duration::from_millis(10));
+                tx2.send(Rc::new(2)).unwrap();
+            });
+            let got = Cell::new(0);
+            let id = select! { local;
+                Ok(v) = rx1.recv() => got.set(*v),
+                Ok(v) = rx2.recv() => got.set(*v),
+            };
+            assert_eq!(id, 1);
+            assert_eq!(got.get(), 2);
+            h.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn unpinned_creation_panics() {
+        let h = Builder::new().spawn(|| {
+            channel::<i32>();
+        });
+        assert!(h.join().is_err());
+    }
+}
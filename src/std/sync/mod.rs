@@ -1,12 +1,53 @@
+//! the synchronization primitives for the coroutines and the threads
+//!
+//! every type here works from the coroutines and from the plain threads, in
+//! any mix. a blocking call parks the coroutine when it's called in one, and
+//! blocks the OS thread with a lock-and-condvar style waiter otherwise, the
+//! wakeup side doesn't care which kind it wakes. so a thread that the
+//! runtime doesn't own, like a C callback thread, can feed a coroutine
+//! pipeline with `Sender::send` and a thread can `recv` what the coroutines
+//! produce
+//!
+//! | type | `Send` when | notes |
+//! |------|-------------|-------|
+//! | `channel::Sender`, `channel::Receiver` | `T: Send` | both ends are `Sync` and can be cloned anywhere |
+//! | `channel::RtSender` | `T: Send` | `Sync`, its `try_send` is safe in a signal handler |
+//! | `mpsc::Sender` | `T: Send` | `Sync`, can be cloned anywhere |
+//! | `mpsc::Receiver` | `T: Send` | not `Sync`, one side pops at a time |
+//! | `spsc::Producer`, `spsc::Consumer` | `T: Send` | each end is owned by one side |
+//! | `oneshot::Sender`, `oneshot::Receiver` | `T: Send` | |
+//! | `priority::Sender`, `priority::Receiver` | `T: Send` | `Sync` |
+//! | `keyed::Sender`, `keyed::Receiver` | `T: Send` | `Sync`, a receiver clone is a new subscriber |
+//! | `broadcast::Sender`, `broadcast::Receiver` | `T: Send` | `Sync`, a receiver clone is a new subscriber that gets every message |
+//! | `Mutex`, `RwLock` | `T: Send` | the guards are released on the side that locked |
+//! | `ShardedLock` | `T: Send` | the readers lock the shard of their worker |
+//! | `Swap`, `SwapOption` | `T: Send + Sync` | a lock free load of an `Arc<T>`, the writers replace it |
+//! | `Promise` | `T: Send` | `Sync` when `T: Send + Sync`, the value is read in place once it's set |
+//! | `Condvar`, `Semphore`, `WaitGroup`, `CancellationToken`, `ShardedCounter` | always | `Sync` |
+//!
+//! a message or a lock is never tied to the side that waits for it: a thread
+//! may block on a channel that a coroutine sends to and the other way round.
+//! the `select!` arms and `Receiver::on_ready` hooks run on the runtime
+//! whatever side feeds them
 #[macro_use]
 mod atomic_option;
 mod blocking;
+mod budget;
+mod cancel_token;
 mod condvar;
+mod global;
 mod mutex;
 mod once;
+mod parallel;
 mod poison;
+mod promise;
+mod readiness;
+#[cfg(feature = "chan-registry")]
+pub(crate) mod registry;
 mod rwlock;
 mod semphore;
+mod sharded;
+mod swap;
 mod sync_array_queue;
 mod sync_flag;
 mod sync_map;
@@ -17,17 +58,41 @@ mod wait_group;
 pub(crate) mod atomic_dur;
 #[cfg(not(unix))]
 pub(crate) mod delay_drop;
+pub mod bridge;
+pub mod broadcast;
 #[macro_use]
 pub mod channel;
+pub mod hooks;
+pub mod keyed;
+pub mod local;
+pub mod mpsc;
+pub mod oneshot;
+pub mod priority;
+pub mod router;
+pub mod spsc;
 
 pub use self::atomic_option::*;
 pub use self::blocking::{Blocker, FastBlocker};
+pub use self::budget::{BudgetPolicy, MemSize, MemoryBudget};
+pub use self::cancel_token::CancellationToken;
 pub use self::channel::*;
 pub use self::condvar::{Condvar, WaitTimeoutResult};
-pub use self::mutex::{Mutex, MutexGuard};
+pub(crate) use self::blocking::SyncBlocker;
+pub(crate) use self::global::close_globals;
+pub use self::global::GlobalChannel;
+pub use self::mutex::{MappedMutexGuard, Mutex, MutexGuard, MutexLock, OwnedMutexGuard};
 pub use self::once::*;
-pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
-pub use self::semphore::Semphore;
+pub use self::parallel::{parallel_for, try_parallel_for};
+pub use self::promise::{Promise, PromiseReady};
+#[cfg(feature = "chan-registry")]
+pub use self::registry::{channel_dump, ChannelInfo, ChannelWait, ParkedInfo};
+pub use self::rwlock::{
+    MappedRwLockReadGuard, MappedRwLockWriteGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard,
+    RwLock, RwLockReadGuard, RwLockWrite, RwLockWriteGuard,
+};
+pub use self::semphore::{Semphore, SemphoreAcquire, SemphorePermit};
+pub use self::sharded::{ShardedCounter, ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
+pub use self::swap::{Projected, Swap, SwapMap, SwapOption};
 pub use self::sync_array_queue::*;
 pub use self::sync_flag::SyncFlag;
 pub use self::sync_map::*;
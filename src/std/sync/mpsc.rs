@@ -0,0 +1,480 @@
+//! mpsc synchronized channel implementation
+//! a specialized channel for the case that there is only one consumer
+//! the `Receiver` is not cloneable, so the data is stored in an intrusive
+//! mpsc list and the recv side doesn't need any CAS loop
+//! the API is the same as the mpmc channel, switching between them is only a type change
+
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{AtomicOption, Blocker, Semphore};
+use crate::std::queue::mpsc_list::Queue;
+use crate::timeout_list::now_instant;
+
+/// Create an unbounded mpsc channel, senders would not block
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(Inner::new(None));
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// Create a bounded mpsc channel, senders would block when `cap` messages are not consumed
+/// the minimum cap is 1
+pub fn sync_channel<T>(cap: usize) -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(Inner::new(Some(cap.max(1))));
+    (Sender::new(a.clone()), Receiver::new(a))
+}
+
+/// /////////////////////////////////////////////////////////////////////////////
+/// Inner
+/// /////////////////////////////////////////////////////////////////////////////
+struct Inner<T> {
+    queue: Queue<T>,
+    // the receiver that waiting for data
+    to_wake: AtomicOption<Arc<Blocker>>,
+    // the free slots of a bounded channel
+    slots: Option<Semphore>,
+    // the number of messages that are not consumed
+    len: AtomicUsize,
+    // The number of sender channels which are currently using this queue.
+    sender_num: AtomicUsize,
+    // set when the receiver is dropped
+    receiver_gone: AtomicBool,
+}
+
+impl<T> Inner<T> {
+    fn new(cap: Option<usize>) -> Self {
+        Inner {
+            queue: Queue::new(),
+            to_wake: AtomicOption::none(),
+            slots: cap.map(Semphore::new),
+            len: AtomicUsize::new(0),
+            sender_num: AtomicUsize::new(1),
+            receiver_gone: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn push(&self, t: T) {
+        // counted first, a pop that takes it at once can't wrap the len
+        self.len.fetch_add(1, Ordering::AcqRel);
+        self.queue.push(t);
+        if let Some(w) = self.to_wake.take() {
+            let _ = w.unpark();
+        }
+    }
+
+    fn send(&self, t: T) -> Result<(), SendError<T>> {
+        if self.receiver_gone.load(Ordering::Acquire) {
+            return Err(SendError(t));
+        }
+        if let Some(slots) = &self.slots {
+            slots.wait();
+            if self.receiver_gone.load(Ordering::Acquire) {
+                slots.post();
+                return Err(SendError(t));
+            }
+        }
+        self.push(t);
+        Ok(())
+    }
+
+    fn try_send(&self, t: T) -> Result<(), SendError<T>> {
+        if self.receiver_gone.load(Ordering::Acquire) {
+            return Err(SendError(t));
+        }
+        if let Some(slots) = &self.slots {
+            if !slots.try_wait() {
+                return Err(SendError(t));
+            }
+        }
+        self.push(t);
+        Ok(())
+    }
+
+    #[inline]
+    fn pop(&self) -> Option<T> {
+        let t = self.queue.pop()?;
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        if let Some(slots) = &self.slots {
+            slots.post();
+        }
+        Some(t)
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(t) = self.pop() {
+            return Ok(t);
+        }
+        if self.sender_num.load(Ordering::Acquire) != 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // the last sender may push data before it's gone
+        self.pop().ok_or(TryRecvError::Disconnected)
+    }
+
+    fn recv(&self, dur: Option<Duration>) -> Result<T, RecvTimeoutError> {
+        let deadline = dur.map(|d| now_instant() + d);
+        loop {
+            match self.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let cur = Blocker::current();
+            // register the waiter
+            self.to_wake.swap(cur.clone());
+            // re-check the queue
+            if !self.queue.is_empty() || self.sender_num.load(Ordering::Acquire) == 0 {
+                self.to_wake.take();
+                continue;
+            }
+
+            let timeout = match deadline {
+                None => None,
+                Some(d) => {
+                    let now = now_instant();
+                    if now >= d {
+                        self.to_wake.take();
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    Some(d - now)
+                }
+            };
+
+            if cur.park(timeout).is_err() {
+                self.to_wake.take();
+                return match self.try_recv() {
+                    Ok(t) => Ok(t),
+                    Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+                    Err(TryRecvError::Empty) => Err(RecvTimeoutError::Timeout),
+                };
+            }
+        }
+    }
+
+    fn drop_send(&self) {
+        match self.sender_num.fetch_sub(1, Ordering::SeqCst) {
+            1 => {
+                // there is no sender any more, tell the receiver to come back
+                if let Some(w) = self.to_wake.take() {
+                    let _ = w.unpark();
+                }
+            }
+            n if n > 1 => {}
+            n => panic!("bad number of send_ports left {}", n),
+        }
+    }
+
+    fn drop_recv(&self) {
+        self.receiver_gone.store(true, Ordering::Release);
+        // there is no receiver any more, clear the data
+        while self.pop().is_some() {}
+        // wake up all the blocked senders, each sender can only block once
+        if let Some(slots) = &self.slots {
+            for _ in 0..self.sender_num.load(Ordering::Acquire) {
+                slots.post();
+            }
+        }
+    }
+}
+
+/// /////////////////////////////////////////////////////////////////////////////
+/// Sender
+/// /////////////////////////////////////////////////////////////////////////////
+
+/// The sending half of the mpsc channel, can be cloned to send from many coroutines
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+
+unsafe impl<T: Send> Sync for Sender<T> {}
+
+impl<T> Sender<T> {
+    fn new(inner: Arc<Inner<T>>) -> Sender<T> {
+        Sender { inner }
+    }
+
+    /// send one message. If the length limit is exceeded, wait for the message to be consumed
+    /// return error if the receiver is gone
+    #[must_use = "the message is returned in the error if the channel is closed"]
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(t)
+    }
+
+    /// try send one message. If the length limit is exceeded or chan closed, return a error
+    #[must_use = "the message is returned in the error if the channel is closed"]
+    pub fn try_send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.try_send(t)
+    }
+
+    /// return remain msg len
+    pub fn remain(&self) -> usize {
+        self.inner.len.load(Ordering::Acquire)
+    }
+
+    /// Number of channel senders
+    pub fn sender_num(&self) -> usize {
+        self.inner.sender_num.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.sender_num.fetch_add(1, Ordering::SeqCst);
+        Sender::new(self.inner.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.drop_send();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// /////////////////////////////////////////////////////////////////////////////
+/// Receiver
+/// /////////////////////////////////////////////////////////////////////////////
+
+/// The receiving half of the mpsc channel, it's not cloneable
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+    // only one coroutine can pop the queue at a time
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+impl<T> Receiver<T> {
+    fn new(inner: Arc<Inner<T>>) -> Receiver<T> {
+        Receiver {
+            inner,
+            _not_sync: PhantomData,
+        }
+    }
+
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// received a message. If the message is empty, a wait is entered, and an error is returned if the channel is closed
+    /// If you want to try to receive a message, use try_recv
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.inner.recv(None) {
+            Err(RecvTimeoutError::Timeout) => unreachable!("mpsc recv timeout"),
+            data => data.map_err(|_| RecvError),
+        }
+    }
+
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv(Some(timeout))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { inner: self }
+    }
+
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { inner: self }
+    }
+
+    /// return remain msg len
+    pub fn remain(&self) -> usize {
+        self.inner.len.load(Ordering::Acquire)
+    }
+
+    /// Number of channel senders
+    pub fn sender_num(&self) -> usize {
+        self.inner.sender_num.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.drop_recv();
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ .. }}")
+    }
+}
+
+pub struct Iter<'a, T: 'a> {
+    inner: &'a Receiver<T>,
+}
+
+pub struct TryIter<'a, T: 'a> {
+    inner: &'a Receiver<T>,
+}
+
+pub struct IntoIter<T> {
+    inner: Receiver<T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.recv().ok()
+    }
+}
+
+impl<'a, T> Iterator for TryIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.inner.try_recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.inner.recv().ok()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { inner: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn smoke() {
+        let (tx, rx) = channel::<i32>();
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv().unwrap(), 1);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn smoke_port_gone() {
+        let (tx, rx) = channel::<i32>();
+        drop(rx);
+        assert!(tx.send(1).is_err());
+    }
+
+    #[test]
+    fn smoke_chan_gone() {
+        let (tx, rx) = channel::<i32>();
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (tx, rx) = channel::<i32>();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Ok(1));
+    }
+
+    #[test]
+    fn recv_in_coroutine() {
+        let (tx, rx) = channel::<i32>();
+        let h = co!(move || rx.iter().sum::<i32>());
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(h.join().unwrap(), 45);
+    }
+
+    #[test]
+    fn sync_channel_block() {
+        let (tx, rx) = sync_channel::<i32>(1);
+        tx.send(1).unwrap();
+        assert_eq!(tx.try_send(2), Err(SendError(2)));
+        let h = co!(move || tx.send(2));
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(h.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn sync_channel_port_gone() {
+        let (tx, rx) = sync_channel::<i32>(1);
+        tx.send(1).unwrap();
+        let h = co!(move || tx.send(2));
+        drop(rx);
+        assert_eq!(h.join().unwrap(), Err(SendError(2)));
+    }
+
+    #[test]
+    fn stress_4_producers() {
+        const AMT: usize = 10000;
+        let (tx, rx) = sync_channel::<usize>(128);
+        for _ in 0..4 {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for i in 0..AMT {
+                    tx.send(i).unwrap();
+                }
+            });
+        }
+        drop(tx);
+        assert_eq!(rx.iter().count(), AMT * 4);
+    }
+
+    #[test]
+    fn remain_never_wraps() {
+        const AMT: usize = 100_000;
+        let (tx, rx) = channel::<usize>();
+        let probe = tx.clone();
+        let h = thread::spawn(move || {
+            for i in 0..AMT {
+                tx.send(i).unwrap();
+            }
+        });
+        // the receiver takes each message as soon as it's pushed
+        for _ in 0..AMT {
+            rx.recv().unwrap();
+            assert!(rx.remain() <= AMT);
+            assert!(probe.remain() <= AMT);
+        }
+        h.join().unwrap();
+        assert_eq!(rx.remain(), 0);
+    }
+}
@@ -1,24 +1,42 @@
 //! compatible with std::sync::mutex except for both thread and coroutine
 //! please ref the doc from std::sync::mutex
+//!
+//! the waiters with a priority, see `Builder::priority`, get the lock first,
+//! the higher one first. the holder is raised to the priority of its waiters
+//! till it releases the lock, so the coroutines with a priority in between
+//! don't keep it from running. a holder of more than one lock gives the lent
+//! priority back when it releases any of them
 use crate::std::queue::mpsc_list::Queue as WaitList;
 use std::cell::UnsafeCell;
 use std::fmt;
+use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{fence, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::sync::{LockResult, TryLockError, TryLockResult};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
 
 use super::blocking::SyncBlocker;
 use super::poison;
 use crate::cancel::trigger_cancel_panic;
+use crate::coroutine_impl::{current, is_coroutine, priority_enabled, Coroutine};
 use crate::park::ParkError;
+use crate::select::{Selectable, Waker, WakerList};
+use crate::stats;
 
 pub struct Mutex<T: ?Sized> {
     // the waiting blocker list
     to_wake: WaitList<Arc<SyncBlocker>>,
+    // the waiters with a priority, they are woken before `to_wake`
+    prio_wake: parking_lot::Mutex<Vec<(u8, Arc<SyncBlocker>)>>,
+    prio_waiters: AtomicUsize,
+    // the coroutine that holds the lock and if it's raised by a waiter, only
+    // tracked when there are coroutines with a priority
+    holder: parking_lot::Mutex<Option<(Coroutine, bool)>>,
     // track how many blockers are waiting on the mutex
     cnt: AtomicUsize,
+    // the selects that wait for the lock, they are not counted in `cnt`
+    selectors: WakerList,
     poison: poison::Flag,
     data: UnsafeCell<T>,
 }
@@ -40,6 +58,24 @@ pub struct MutexGuard<'a, T: ?Sized + 'a> {
 
 // impl<'a, T: ?Sized> !Send for MutexGuard<'a, T> {}
 
+/// a guard for a component of the locked data, created by `MutexGuard::map`
+#[must_use]
+pub struct MappedMutexGuard<'a, T: ?Sized + 'a, U: ?Sized + 'a> {
+    __lock: &'a Mutex<T>,
+    __poison: poison::Guard,
+    __data: *mut U,
+}
+
+/// a guard that owns an `Arc` of the mutex, created by `Mutex::lock_owned`
+/// it's not borrow tied so it can be sent to another coroutine or stored
+#[must_use]
+pub struct OwnedMutexGuard<T: ?Sized> {
+    __lock: Arc<Mutex<T>>,
+    __poison: poison::Guard,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for OwnedMutexGuard<T> {}
+
 impl<T> Mutex<T> {
     /// Creates a new mutex in an unlocked state ready for use.
     pub fn new_arc(t: T) -> Arc<Mutex<T>> {
@@ -50,7 +86,11 @@ impl<T> Mutex<T> {
     pub fn new(t: T) -> Mutex<T> {
         Mutex {
             to_wake: WaitList::new(),
+            prio_wake: parking_lot::Mutex::new(Vec::new()),
+            prio_waiters: AtomicUsize::new(0),
+            holder: parking_lot::Mutex::new(None),
             cnt: AtomicUsize::new(0),
+            selectors: WakerList::new(),
             poison: poison::Flag::new(),
             data: UnsafeCell::new(t),
         }
@@ -67,15 +107,26 @@ impl<T: ?Sized> Mutex<T> {
         }
 
         let cur = SyncBlocker::current();
+        let priority = if priority_enabled() && is_coroutine() {
+            current().priority()
+        } else {
+            0
+        };
         // register blocker first
-        self.to_wake.push(cur.clone());
+        if priority == 0 {
+            self.to_wake.push(cur.clone());
+        } else {
+            self.prio_wake.lock().push((priority, cur.clone()));
+            self.prio_waiters.fetch_add(1, Ordering::Release);
+        }
         // inc the cnt, if it's the first grab, unpark the first waiter
         if self.cnt.fetch_add(1, Ordering::SeqCst) == 0 {
             let _ = self
-                .to_wake
-                .pop()
+                .pop_waiter()
                 .map(|w| self.unpark_one(&w))
                 .expect("got null blocker!");
+        } else if priority != 0 {
+            self.boost_holder(priority);
         }
         loop {
             match cur.park(None) {
@@ -121,6 +172,23 @@ impl<T: ?Sized> Mutex<T> {
         MutexGuard::new(self)
     }
 
+    /// lock the mutex of the `Arc`, the guard keeps the mutex alive
+    pub fn lock_owned(self: Arc<Self>) -> LockResult<OwnedMutexGuard<T>> {
+        let (poison, poisoned) = match self.lock() {
+            Ok(g) => (g.into_poison(), false),
+            Err(e) => (e.into_inner().into_poison(), true),
+        };
+        let guard = OwnedMutexGuard {
+            __lock: self,
+            __poison: poison,
+        };
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
     pub fn try_lock(&self) -> TryLockResult<MutexGuard<T>> {
         if self.cnt.load(Ordering::SeqCst) == 0 {
             match self
@@ -144,11 +212,76 @@ impl<T: ?Sized> Mutex<T> {
     }
 
     fn unlock(&self) {
+        if priority_enabled() {
+            self.release_holder();
+        }
         if self.cnt.fetch_sub(1, Ordering::SeqCst) > 1 {
-            self.to_wake.pop().map(|w| self.unpark_one(&w));
+            self.pop_waiter().map(|w| self.unpark_one(&w));
+        } else {
+            self.selectors.wake_all();
         }
     }
 
+    // the first waiter of the highest priority, then the others in order
+    fn pop_waiter(&self) -> Option<Arc<SyncBlocker>> {
+        if self.prio_waiters.load(Ordering::Acquire) != 0 {
+            let mut waiters = self.prio_wake.lock();
+            let first = (0..waiters.len()).rev().max_by_key(|&i| waiters[i].0);
+            if let Some(i) = first {
+                self.prio_waiters.fetch_sub(1, Ordering::Relaxed);
+                return Some(waiters.remove(i).1);
+            }
+        }
+        self.to_wake.pop()
+    }
+
+    // the lock is taken by the current coroutine
+    fn set_holder(&self) {
+        if priority_enabled() && is_coroutine() {
+            *self.holder.lock() = Some((current(), false));
+        }
+    }
+
+    // lend the priority of a waiter to the holder
+    fn boost_holder(&self, priority: u8) {
+        if let Some((co, boosted)) = self.holder.lock().as_mut() {
+            if co.boost(priority) {
+                *boosted = true;
+                stats::PRIORITY_BOOSTS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // the holder is releasing the lock, it gives the lent priority back
+    fn release_holder(&self) {
+        if let Some((co, true)) = self.holder.lock().take() {
+            co.unboost();
+        }
+    }
+
+    /// lock the mutex as a source of a `SelectSet` or an arm of `select!`
+    ///
+    /// a select only takes the lock when it's free, it never queues up
+    /// with the `lock` callers. so a select that loses holds nothing and the
+    /// waiters keep their order, while a select may wait longer than a
+    /// `lock` that comes later
+    ///
+    /// ```rust
+    /// use mco::select::Selectable;
+    /// use mco::std::sync::{CancellationToken, Mutex};
+    ///
+    /// let m = Mutex::new(0);
+    /// let shutdown = CancellationToken::new();
+    /// mco::select! {
+    ///     g = m.lock_select().wait() => *g.unwrap() += 1,
+    ///     _ = shutdown.cancelled() => {}
+    /// };
+    /// assert_eq!(*m.lock().unwrap(), 1);
+    /// ```
+    pub fn lock_select(&self) -> MutexLock<'_, T> {
+        MutexLock { lock: self }
+    }
+
     #[inline]
     pub fn is_poisoned(&self) -> bool {
         self.poison.get()
@@ -188,16 +321,71 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+/// the lock of a mutex as a source of a select, created by
+/// `Mutex::lock_select`
+pub struct MutexLock<'a, T: ?Sized + 'a> {
+    lock: &'a Mutex<T>,
+}
+
+impl<'a, T: ?Sized> Selectable for MutexLock<'a, T> {
+    type Output = LockResult<MutexGuard<'a, T>>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        match self.lock.try_lock() {
+            Ok(g) => Some(Ok(g)),
+            Err(TryLockError::Poisoned(e)) => Some(Err(e)),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.lock.selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.lock.selectors.deregister(waker);
+    }
+}
+
+impl<'a, T: ?Sized> fmt::Debug for MutexLock<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MutexLock {{ .. }}")
+    }
+}
+
 impl<'mutex, T: ?Sized> MutexGuard<'mutex, T> {
     fn new(lock: &'mutex Mutex<T>) -> LockResult<MutexGuard<'mutex, T>> {
         // after get the lock we should sync the mem
         fence(Ordering::SeqCst);
+        lock.set_holder();
 
         poison::map_result(lock.poison.borrow(), |guard| MutexGuard {
             __lock: lock,
             __poison: guard,
         })
     }
+
+    /// make a guard for a component of the locked data, the lock is still held
+    /// by the returned guard
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedMutexGuard<'mutex, T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        // if `f` panics the lock is released by `this`
+        let data = f(unsafe { &mut *this.__lock.data.get() }) as *mut U;
+        let lock = this.__lock;
+        MappedMutexGuard {
+            __lock: lock,
+            __poison: this.into_poison(),
+            __data: data,
+        }
+    }
+
+    // take the poison guard without unlocking
+    fn into_poison(self) -> poison::Guard {
+        let this = ManuallyDrop::new(self);
+        unsafe { std::ptr::read(&this.__poison) }
+    }
 }
 
 impl<'mutex, T: ?Sized> Deref for MutexGuard<'mutex, T> {
@@ -232,6 +420,85 @@ impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'a, T> {
     }
 }
 
+impl<'a, T: ?Sized, U: ?Sized> MappedMutexGuard<'a, T, U> {
+    /// make a guard for a component of the mapped data
+    pub fn map<V: ?Sized, F>(this: Self, f: F) -> MappedMutexGuard<'a, T, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        let data = f(unsafe { &mut *this.__data }) as *mut V;
+        let this = ManuallyDrop::new(this);
+        MappedMutexGuard {
+            __lock: this.__lock,
+            __poison: unsafe { std::ptr::read(&this.__poison) },
+            __data: data,
+        }
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> Deref for MappedMutexGuard<'a, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { &*self.__data }
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> DerefMut for MappedMutexGuard<'a, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.__data }
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> Drop for MappedMutexGuard<'a, T, U> {
+    #[inline]
+    fn drop(&mut self) {
+        self.__lock.poison.done(&self.__poison);
+        self.__lock.unlock();
+        fence(Ordering::SeqCst);
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug for MappedMutexGuard<'a, T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedMutexGuard")
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedMutexGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.__lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.__lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedMutexGuard<T> {
+    #[inline]
+    fn drop(&mut self) {
+        // the poison flag is set if the holder panics
+        self.__lock.poison.done(&self.__poison);
+        self.__lock.unlock();
+        fence(Ordering::SeqCst);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedMutexGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedMutexGuard")
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
 // below functions are used by condvar but not exported to user
 pub fn unlock_mutex<T: ?Sized>(lock: &Mutex<T>) {
     lock.unlock();
@@ -494,6 +761,52 @@ mod tests {
         assert_eq!(&*mutex.lock().unwrap(), comp);
     }
 
+    #[test]
+    fn test_mutex_guard_map() {
+        struct Conn {
+            id: usize,
+            buf: Vec<u8>,
+        }
+
+        let m = Mutex::new(Conn { id: 1, buf: vec![] });
+        {
+            let g = m.lock().unwrap();
+            let mut buf = MutexGuard::map(g, |c| &mut c.buf);
+            buf.push(1);
+            // the lock is held by the mapped guard
+            assert!(m.try_lock().is_err());
+            let mut first = MappedMutexGuard::map(buf, |b| &mut b[0]);
+            *first += 1;
+        }
+        let g = m.lock().unwrap();
+        assert_eq!((g.id, &g.buf[..]), (1, &[2u8][..]));
+    }
+
+    #[test]
+    fn test_mutex_lock_owned() {
+        let m = Arc::new(Mutex::new(0));
+        let mut g = m.clone().lock_owned().unwrap();
+        *g += 1;
+        // the guard is moved to another coroutine and released there
+        let h = co!(move || {
+            *g += 1;
+            drop(g);
+        });
+        h.join().unwrap();
+        assert_eq!(*m.lock().unwrap(), 2);
+
+        // the owned guard is released and poisons the mutex on panic
+        let m = Arc::new(Mutex::new(0));
+        let g = m.clone().lock_owned().unwrap();
+        let h = co!(move || {
+            let _g = g;
+            panic!("panic with the owned guard");
+        });
+        assert!(h.join().is_err());
+        assert!(m.is_poisoned());
+        assert!(m.clone().lock_owned().is_err());
+    }
+
     #[test]
     fn test_mutex_canceled() {
         use crate::sleep::sleep;
@@ -567,4 +880,53 @@ mod tests {
         let g = mutex1.lock().unwrap();
         assert_eq!(*g, 1);
     }
+
+    #[test]
+    fn select_lock_no_leak() {
+        use crate::select::{After, SelectSet, Selectable};
+        use std::time::Duration;
+
+        const SELECTS: usize = 10_000;
+        const WORKERS: usize = 8;
+        let m = Arc::new(Mutex::new(0));
+        let done = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..WORKERS)
+            .map(|i| {
+                let m = m.clone();
+                let done = done.clone();
+                co!(move || {
+                    // the count is taken with the lock held
+                    let bump = |mut g: MutexGuard<usize>| {
+                        *g += 1;
+                        done.fetch_add(1, Ordering::SeqCst);
+                    };
+                    for j in 0..SELECTS / WORKERS {
+                        match (i + j) % 4 {
+                            // the plain lockers queue up among the selects
+                            0 => bump(m.lock().unwrap()),
+                            1 => {
+                                let mut set = SelectSet::new();
+                                set.add(m.lock_select(), Some);
+                                set.add(After::new(Duration::from_micros(50)), |_| None);
+                                if let Some(g) = set.select() {
+                                    bump(g.unwrap());
+                                }
+                            }
+                            // the guard of a select that loses is unlocked
+                            _ => {
+                                select! {
+                                    g = m.lock_select().wait() => bump(g.unwrap()),
+                                    _ = After::new(Duration::from_micros(50)).wait() => {}
+                                };
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*m.try_lock().unwrap(), done.load(Ordering::SeqCst));
+    }
 }
@@ -0,0 +1,243 @@
+//! oneshot channel implementation
+//! a channel that transfers only one value, useful for request/response pairs
+//! the shared state is a single allocation, the `Sender` is consumed by `send`
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{AtomicOption, Blocker};
+use crate::timeout_list::now_instant;
+
+// the value is sent
+const SENT: usize = 1;
+// the sender is dropped
+const TX_GONE: usize = 2;
+// the receiver is dropped
+const RX_GONE: usize = 4;
+
+/// Create a oneshot channel
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(Inner {
+        value: AtomicOption::none(),
+        state: AtomicUsize::new(0),
+        to_wake: AtomicOption::none(),
+    });
+    (Sender { inner: a.clone() }, Receiver { inner: a })
+}
+
+struct Inner<T> {
+    // the transferred value
+    value: AtomicOption<T>,
+    // the SENT/TX_GONE/RX_GONE flags
+    state: AtomicUsize,
+    // the receiver that waiting for the value
+    to_wake: AtomicOption<Arc<Blocker>>,
+}
+
+impl<T> Inner<T> {
+    #[inline]
+    fn wake(&self) {
+        if let Some(w) = self.to_wake.take() {
+            let _ = w.unpark();
+        }
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        if self.state.load(Ordering::Acquire) & (SENT | TX_GONE) == 0 {
+            return Err(TryRecvError::Empty);
+        }
+        // the value is only taken once, after that the channel is disconnected
+        self.value.take().ok_or(TryRecvError::Disconnected)
+    }
+
+    fn recv(&self, dur: Option<Duration>) -> Result<T, RecvTimeoutError> {
+        let deadline = dur.map(|d| now_instant() + d);
+        loop {
+            match self.try_recv() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+
+            let cur = Blocker::current();
+            // register the waiter
+            self.to_wake.swap(cur.clone());
+            // re-check the state
+            if self.state.load(Ordering::Acquire) & (SENT | TX_GONE) != 0 {
+                self.to_wake.take();
+                continue;
+            }
+
+            let timeout = match deadline {
+                None => None,
+                Some(d) => {
+                    let now = now_instant();
+                    if now >= d {
+                        self.to_wake.take();
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    Some(d - now)
+                }
+            };
+
+            if cur.park(timeout).is_err() {
+                self.to_wake.take();
+                return match self.try_recv() {
+                    Ok(t) => Ok(t),
+                    Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+                    Err(TryRecvError::Empty) => Err(RecvTimeoutError::Timeout),
+                };
+            }
+        }
+    }
+}
+
+/// The sending half of the oneshot channel
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+
+impl<T> Sender<T> {
+    /// send the value, this consumes the sender
+    /// return error if the receiver is gone
+    #[must_use = "the message is returned in the error if the channel is closed"]
+    pub fn send(self, t: T) -> Result<(), SendError<T>> {
+        if self.is_closed() {
+            return Err(SendError(t));
+        }
+        self.inner.value.store(t);
+        self.inner.state.fetch_or(SENT, Ordering::AcqRel);
+        self.inner.wake();
+        Ok(())
+    }
+
+    /// return true if the receiver is dropped, the value would never be received
+    pub fn is_closed(&self) -> bool {
+        self.inner.state.load(Ordering::Acquire) & RX_GONE != 0
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.state.fetch_or(TX_GONE, Ordering::AcqRel);
+        self.inner.wake();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// The receiving half of the oneshot channel
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+
+impl<T> Receiver<T> {
+    /// try to receive the value without blocking
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// wait for the value, return error if the sender is dropped without sending
+    /// or the value is already received
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.inner.recv(None) {
+            Err(RecvTimeoutError::Timeout) => unreachable!("oneshot recv timeout"),
+            data => data.map_err(|_| RecvError),
+        }
+    }
+
+    /// wait for the value with a timeout
+    #[must_use = "the received message is dropped if the result is ignored"]
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv(Some(timeout))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.state.fetch_or(RX_GONE, Ordering::AcqRel);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn smoke() {
+        let (tx, rx) = channel::<i32>();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn sender_gone() {
+        let (tx, rx) = channel::<i32>();
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn receiver_gone() {
+        let (tx, rx) = channel::<i32>();
+        assert!(!tx.is_closed());
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+
+    #[test]
+    fn recv_timeout() {
+        let (tx, rx) = channel::<i32>();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Ok(1));
+    }
+
+    #[test]
+    fn recv_in_coroutine() {
+        let (tx, rx) = channel::<i32>();
+        let h = co!(move || rx.recv());
+        thread::spawn(move || tx.send(42).unwrap());
+        assert_eq!(h.join().unwrap(), Ok(42));
+    }
+
+    #[test]
+    fn select_oneshot() {
+        let (tx1, rx1) = channel::<i32>();
+        let (tx2, rx2) = channel::<&str>();
+        co!(move || tx2.send("hello").unwrap());
+        let id = select!(
+            _ = rx1.recv() => {},
+            Ok(v) = rx2.recv() => assert_eq!(v, "hello")
+        );
+        assert_eq!(id, 1);
+        drop(tx1);
+    }
+}
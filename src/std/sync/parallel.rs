@@ -0,0 +1,160 @@
+//! bounded concurrency combinators
+//! map a function over the items with at most `max_concurrency` coroutines in flight
+//! the items are processed in chunks, so a huge input doesn't spawn one coroutine per item
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+
+use super::Semphore;
+use crate::coroutine::scope;
+
+// each coroutine in flight would process about this many chunks
+const CHUNKS_PER_WORKER: usize = 4;
+
+/// map `f` over the items with at most `max_concurrency` coroutines in flight
+/// the output keeps the order of the input
+///
+/// if `f` panics, the panic is propagated after all the in flight work is finished
+/// for example:
+/// ```
+/// use mco::std::sync::parallel_for;
+///
+/// let v = parallel_for((0..100).collect(), 8, |i: usize| i * 2);
+/// assert_eq!(v[10], 20);
+/// ```
+pub fn parallel_for<T, R, F>(items: Vec<T>, max_concurrency: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let ret: Result<Vec<R>, ()> = try_parallel_for(items, max_concurrency, |t| Ok(f(t)));
+    match ret {
+        Ok(v) => v,
+        Err(_) => unreachable!("parallel_for never fail"),
+    }
+}
+
+/// map the fallible `f` over the items with at most `max_concurrency` coroutines in flight
+/// the output keeps the order of the input
+///
+/// after the first error no more items are started, the error is returned
+/// when all the in flight work is finished
+pub fn try_parallel_for<T, R, E, F>(
+    items: Vec<T>,
+    max_concurrency: usize,
+    f: F,
+) -> Result<Vec<R>, E>
+where
+    T: Send,
+    R: Send,
+    E: Send,
+    F: Fn(T) -> Result<R, E> + Sync,
+{
+    let len = items.len();
+    let max_concurrency = max_concurrency.max(1);
+    let chunk_size = ((len + max_concurrency * CHUNKS_PER_WORKER - 1)
+        / (max_concurrency * CHUNKS_PER_WORKER))
+        .max(1);
+
+    let mut results: Vec<Option<R>> = (0..len).map(|_| None).collect();
+    let sem = Semphore::new(max_concurrency);
+    let failed = AtomicBool::new(false);
+    let error: Mutex<Option<E>> = Mutex::new(None);
+    let panicked: Mutex<Option<Box<dyn Any + Send>>> = Mutex::new(None);
+
+    scope(|s| {
+        let mut items = items.into_iter();
+        for out in results.chunks_mut(chunk_size) {
+            let chunk: Vec<T> = items.by_ref().take(out.len()).collect();
+            sem.wait();
+            if failed.load(Ordering::Acquire) {
+                sem.post();
+                break;
+            }
+            let (f, sem, failed, error, panicked) = (&f, &sem, &failed, &error, &panicked);
+            co!(s, move || {
+                let run = panic::catch_unwind(AssertUnwindSafe(|| {
+                    for (t, slot) in chunk.into_iter().zip(out.iter_mut()) {
+                        if failed.load(Ordering::Acquire) {
+                            return;
+                        }
+                        match f(t) {
+                            Ok(r) => *slot = Some(r),
+                            Err(e) => {
+                                failed.store(true, Ordering::Release);
+                                error.lock().get_or_insert(e);
+                                return;
+                            }
+                        }
+                    }
+                }));
+                if let Err(p) = run {
+                    failed.store(true, Ordering::Release);
+                    panicked.lock().get_or_insert(p);
+                }
+                sem.post();
+            });
+        }
+    });
+
+    if let Some(p) = panicked.into_inner() {
+        panic::resume_unwind(p);
+    }
+    if let Some(e) = error.into_inner() {
+        return Err(e);
+    }
+    Ok(results
+        .into_iter()
+        .map(|r| r.expect("parallel_for result not set"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::yield_now;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn order_and_bound() {
+        let live = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..10000).collect();
+        let v = parallel_for(items, 8, |i| {
+            let n = live.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(n, Ordering::SeqCst);
+            yield_now();
+            live.fetch_sub(1, Ordering::SeqCst);
+            i * 2
+        });
+        assert_eq!(v, (0..10000).map(|i| i * 2).collect::<Vec<_>>());
+        assert!(peak.load(Ordering::SeqCst) <= 8);
+    }
+
+    #[test]
+    fn first_error() {
+        let ret = try_parallel_for((0..1000).collect(), 4, |i: usize| {
+            if i == 500 {
+                Err(i)
+            } else {
+                Ok(i)
+            }
+        });
+        assert_eq!(ret, Err(500));
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_propagate() {
+        parallel_for((0..100).collect(), 4, |i: usize| {
+            if i == 50 {
+                panic!("panic in parallel_for");
+            }
+            i
+        });
+    }
+}
@@ -0,0 +1,432 @@
+//! priority channel implementation
+//! an mpmc channel where the messages with a higher priority are received
+//! first, messages with the same priority are received in the sending order
+//!
+//! the `u8` priorities are mapped to a small fixed number of levels, each
+//! level is a plain fifo queue so that sending and receiving stay O(1). one
+//! semaphore counts the messages of all the levels, a receiver that took a
+//! permit scans the levels from high to low, so a high priority message wakes
+//! a receiver no matter which levels it checked before blocking
+//!
+//! with `Config::aging` a message that waited longer than the given time is
+//! received before the higher levels, so the low priorities are not starved.
+//! aging is off by default
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+
+use super::Semphore;
+use crate::timeout_list::now_instant;
+
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+
+/// the default number of the priority levels
+pub const DEFAULT_LEVELS: usize = 8;
+
+/// the options of a priority channel
+#[derive(Debug, Clone)]
+pub struct Config {
+    levels: usize,
+    buf: usize,
+    aging: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            levels: DEFAULT_LEVELS,
+            buf: usize::MAX,
+            aging: None,
+        }
+    }
+}
+
+impl Config {
+    /// an unbounded channel with `DEFAULT_LEVELS` levels and no aging
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// set the number of the levels that the `u8` priorities are mapped to,
+    /// it must be in `1..=256`
+    pub fn levels(mut self, levels: usize) -> Self {
+        assert!(levels > 0 && levels <= 256, "the levels must be in 1..=256");
+        self.levels = levels;
+        self
+    }
+
+    /// limit the number of the buffered messages, the senders block when
+    /// the channel is full
+    pub fn bounded(mut self, buf: usize) -> Self {
+        self.buf = buf;
+        self
+    }
+
+    /// receive the messages that waited longer than `after` before the
+    /// higher levels, the oldest one first
+    pub fn aging(mut self, after: Duration) -> Self {
+        self.aging = Some(after);
+        self
+    }
+}
+
+/// Create an unbounded priority channel
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    with_config(Config::new())
+}
+
+/// Create a bounded priority channel
+pub fn bounded<T>(buf: usize) -> (Sender<T>, Receiver<T>) {
+    with_config(Config::new().bounded(buf))
+}
+
+/// Create a priority channel with the given options
+pub fn with_config<T>(config: Config) -> (Sender<T>, Receiver<T>) {
+    let a = Arc::new(Inner::new(config));
+    (Sender { inner: a.clone() }, Receiver { inner: a })
+}
+
+struct Entry<T> {
+    t: T,
+    // the sending time, only recorded when aging is on
+    at: Option<Instant>,
+}
+
+struct Inner<T> {
+    // indexed by level, the last one is the highest priority
+    levels: Vec<Mutex<VecDeque<Entry<T>>>>,
+    // the buffered messages of all the levels
+    len: AtomicUsize,
+    buffer_limit: usize,
+    aging: Option<Duration>,
+    // one permit for each buffered message
+    wake_recv: Semphore,
+    // thread/coroutine for wake up
+    wake_sender: Semphore,
+    sender_num: AtomicUsize,
+    receiver_num: AtomicUsize,
+}
+
+impl<T> Inner<T> {
+    fn new(config: Config) -> Self {
+        Inner {
+            levels: (0..config.levels).map(|_| Mutex::new(VecDeque::new())).collect(),
+            len: AtomicUsize::new(0),
+            buffer_limit: config.buf,
+            aging: config.aging,
+            wake_recv: Semphore::new(0),
+            wake_sender: Semphore::new(0),
+            sender_num: AtomicUsize::new(1),
+            receiver_num: AtomicUsize::new(1),
+        }
+    }
+
+    #[inline]
+    fn level(&self, priority: u8) -> usize {
+        priority as usize * self.levels.len() / 256
+    }
+
+    fn push(&self, t: T, priority: u8) {
+        let at = self.aging.map(|_| now_instant());
+        self.len.fetch_add(1, Ordering::AcqRel);
+        self.levels[self.level(priority)].lock().push_back(Entry { t, at });
+        // the message is queued before the permit is visible
+        self.wake_recv.post();
+    }
+
+    fn send(&self, t: T, priority: u8, block: bool) -> Result<(), SendError<T>> {
+        loop {
+            if self.receiver_num.load(Ordering::Acquire) == 0 {
+                return Err(SendError(t));
+            }
+            if self.len.load(Ordering::Acquire) < self.buffer_limit {
+                break;
+            }
+            if !block {
+                return Err(SendError(t));
+            }
+            self.wake_sender.wait();
+        }
+        self.push(t, priority);
+        Ok(())
+    }
+
+    // the oldest head that waited longer than the aging time
+    fn pop_aged(&self, after: Duration) -> Option<T> {
+        let now = now_instant();
+        let mut oldest: Option<(usize, Instant)> = None;
+        for (i, level) in self.levels.iter().enumerate() {
+            let at = match level.lock().front().and_then(|e| e.at) {
+                Some(at) => at,
+                None => continue,
+            };
+            if now.saturating_duration_since(at) >= after && oldest.map_or(true, |o| at < o.1) {
+                oldest = Some((i, at));
+            }
+        }
+        let (i, _) = oldest?;
+        self.levels[i].lock().pop_front().map(|e| e.t)
+    }
+
+    // take one message, must be called with a permit of `wake_recv`
+    // it's none if the channel is drained and all the senders are gone
+    fn pop(&self) -> Option<T> {
+        loop {
+            let aged = self.aging.and_then(|after| self.pop_aged(after));
+            let t = aged.or_else(|| {
+                self.levels
+                    .iter()
+                    .rev()
+                    .find_map(|level| level.lock().pop_front().map(|e| e.t))
+            });
+            if let Some(t) = t {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                self.wake_sender.post();
+                return Some(t);
+            }
+            // the permit guarantees a message unless it's the disconnect one,
+            // the message may move between the levels we scanned when the
+            // other receivers race with us
+            if self.sender_num.load(Ordering::Acquire) == 0
+                && self.len.load(Ordering::Acquire) == 0
+            {
+                return None;
+            }
+        }
+    }
+
+    fn recv_permit(&self) -> Result<T, TryRecvError> {
+        self.pop().ok_or_else(|| {
+            // pass the disconnect on to the next receiver
+            self.wake_recv.post();
+            TryRecvError::Disconnected
+        })
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        if !self.wake_recv.try_wait() {
+            return match self.sender_num.load(Ordering::Acquire) {
+                0 => Err(TryRecvError::Disconnected),
+                _ => Err(TryRecvError::Empty),
+            };
+        }
+        self.recv_permit()
+    }
+
+    fn recv(&self, dur: Option<Duration>) -> Result<T, RecvTimeoutError> {
+        match dur {
+            None => self.wake_recv.wait(),
+            Some(d) => {
+                if !self.wake_recv.wait_timeout(d) {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+            }
+        }
+        self.recv_permit().map_err(|_| RecvTimeoutError::Disconnected)
+    }
+
+    fn drop_send(&self) {
+        if self.sender_num.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // wake the blocked receivers, they pass it on one by one
+            self.wake_recv.post();
+        }
+    }
+
+    fn drop_recv(&self) {
+        if self.receiver_num.fetch_sub(1, Ordering::AcqRel) == 1 {
+            for level in self.levels.iter() {
+                level.lock().clear();
+            }
+            self.len.store(0, Ordering::Release);
+            // the blocked senders should come back
+            while self.wake_sender.get_value() == 0 {
+                self.wake_sender.post();
+            }
+        }
+    }
+}
+
+/// The sending half of the priority channel
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Sender<T> {}
+unsafe impl<T: Send> Sync for Sender<T> {}
+
+impl<T> Sender<T> {
+    /// send the message with the lowest priority 0
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        self.inner.send(t, 0, true)
+    }
+
+    /// send the message with the priority, the higher one is received first
+    ///
+    /// it blocks when a bounded channel is full
+    pub fn send_with_priority(&self, t: T, priority: u8) -> Result<(), SendError<T>> {
+        self.inner.send(t, priority, true)
+    }
+
+    /// send the message with the priority, return error if the channel is
+    /// full or all the receivers are gone
+    pub fn try_send_with_priority(&self, t: T, priority: u8) -> Result<(), SendError<T>> {
+        self.inner.send(t, priority, false)
+    }
+
+    /// the number of the buffered messages
+    pub fn remain(&self) -> usize {
+        self.inner.len.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.inner.sender_num.fetch_add(1, Ordering::AcqRel);
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.drop_send();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sender {{ .. }}")
+    }
+}
+
+/// The receiving half of the priority channel
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Receiver<T> {}
+unsafe impl<T: Send> Sync for Receiver<T> {}
+
+impl<T> Receiver<T> {
+    /// try to receive the highest priority message without blocking
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.inner.try_recv()
+    }
+
+    /// wait for the highest priority message, return error if the channel
+    /// is empty and all the senders are gone
+    pub fn recv(&self) -> Result<T, RecvError> {
+        match self.inner.recv(None) {
+            Err(RecvTimeoutError::Timeout) => unreachable!("priority recv timeout"),
+            data => data.map_err(|_| RecvError),
+        }
+    }
+
+    /// wait for the highest priority message with a timeout
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.inner.recv(Some(timeout))
+    }
+
+    /// the number of the buffered messages
+    pub fn remain(&self) -> usize {
+        self.inner.len.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.inner.receiver_num.fetch_add(1, Ordering::AcqRel);
+        Receiver {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.drop_recv();
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn priority_order() {
+        let (tx, rx) = unbounded::<i32>();
+        tx.send_with_priority(1, 0).unwrap();
+        tx.send_with_priority(2, 255).unwrap();
+        tx.send_with_priority(3, 0).unwrap();
+        tx.send_with_priority(4, 255).unwrap();
+        tx.send_with_priority(5, 100).unwrap();
+        let got: Vec<_> = (0..5).map(|_| rx.recv().unwrap()).collect();
+        assert_eq!(got, vec![2, 4, 5, 1, 3]);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn wake_on_high_priority() {
+        let (tx, rx) = unbounded::<i32>();
+        let h = co!(move || rx.recv());
+        thread::sleep(Duration::from_millis(10));
+        tx.send_with_priority(7, 200).unwrap();
+        assert_eq!(h.join().unwrap(), Ok(7));
+    }
+
+    #[test]
+    fn bounded_blocks() {
+        let (tx, rx) = bounded::<i32>(1);
+        tx.send(1).unwrap();
+        assert_eq!(tx.try_send_with_priority(2, 9), Err(SendError(2)));
+        let h = co!(move || tx.send_with_priority(2, 9));
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(h.join().unwrap(), Ok(()));
+        assert_eq!(rx.recv(), Ok(2));
+        assert_eq!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn aging_promotes() {
+        let config = Config::new().levels(2).aging(Duration::from_millis(20));
+        let (tx, rx) = with_config::<i32>(config);
+        tx.send_with_priority(1, 0).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        tx.send_with_priority(2, 255).unwrap();
+        // the old low priority message is promoted
+        assert_eq!(rx.recv(), Ok(1));
+        assert_eq!(rx.recv(), Ok(2));
+    }
+
+    #[test]
+    fn select_priority() {
+        let (tx1, rx1) = unbounded::<i32>();
+        let (tx2, rx2) = unbounded::<&str>();
+        tx2.send_with_priority("hello", 3).unwrap();
+        let id = select!(
+            _ = rx1.recv() => {},
+            Ok(v) = rx2.recv() => assert_eq!(v, "hello")
+        );
+        assert_eq!(id, 1);
+        drop(tx1);
+    }
+}
@@ -0,0 +1,277 @@
+//! a value that is set once and read by any number of waiters
+//!
+//! `get` parks the coroutine or the thread until the value is set, after that
+//! it's one atomic load and the value is read in place for as long as the
+//! promise lives. there is no state per reader, unlike a broadcast channel,
+//! and the waiters are woken across the workers, unlike a `OnceCell`
+//!
+//! the value is stored before `set` takes the lock of the waiters, a waiter
+//! checks the value again under the same lock before it registers, so no
+//! wakeup is lost when `set` races with a new waiter
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+
+use super::blocking::SyncBlocker;
+use crate::cancel::trigger_cancel_panic;
+use crate::park::ParkError;
+use crate::select::{Selectable, Waker};
+
+enum Waiter {
+    Blocker(Arc<SyncBlocker>),
+    Select(Waker),
+}
+
+/// a write once value that blocks its readers until it's set
+///
+/// ```
+/// use mco::std::sync::Promise;
+/// use std::sync::Arc;
+///
+/// let config = Arc::new(Promise::new());
+/// let hs: Vec<_> = (0..10)
+///     .map(|_| {
+///         let config = config.clone();
+///         mco::co!(move || config.get().len())
+///     })
+///     .collect();
+/// config.set("db=primary".to_owned()).unwrap();
+/// for h in hs {
+///     assert_eq!(h.join().unwrap(), 10);
+/// }
+/// assert!(config.set(String::new()).is_err());
+/// ```
+pub struct Promise<T> {
+    value: OnceCell<T>,
+    waiters: Mutex<Vec<Waiter>>,
+}
+
+impl<T> Default for Promise<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Promise<T> {
+    /// a promise that is not set
+    pub fn new() -> Self {
+        Promise {
+            value: OnceCell::new(),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// set the value and wake all the waiters, the value is given back if
+    /// it's already set
+    pub fn set(&self, value: T) -> Result<(), T> {
+        self.value.set(value)?;
+        let waiters = std::mem::take(&mut *self.waiters.lock());
+        for w in waiters {
+            match w {
+                Waiter::Blocker(b) => {
+                    let _ = b.unpark();
+                }
+                Waiter::Select(w) => w.wake(),
+            }
+        }
+        Ok(())
+    }
+
+    /// the value, `None` if it's not set yet
+    #[inline]
+    pub fn try_get(&self) -> Option<&T> {
+        self.value.get()
+    }
+
+    /// return true if the value is set
+    pub fn is_set(&self) -> bool {
+        self.value.get().is_some()
+    }
+
+    /// block until the value is set
+    #[inline]
+    pub fn get(&self) -> &T {
+        if let Some(v) = self.value.get() {
+            return v;
+        }
+        loop {
+            if let Some(v) = self.wait(None) {
+                return v;
+            }
+        }
+    }
+
+    /// block until the value is set or the timeout, `None` if it times out
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<&T> {
+        match self.value.get() {
+            Some(v) => Some(v),
+            None => self.wait(Some(timeout)),
+        }
+    }
+
+    /// the value as a source of `SelectSet` and the `select!` arms, it's
+    /// ready once the value is set
+    ///
+    /// ```
+    /// use mco::select::SelectSet;
+    /// use mco::std::sync::Promise;
+    ///
+    /// let p = Promise::new();
+    /// let (_tx, rx) = mco::chan!();
+    /// p.set(7).unwrap();
+    /// let mut set = SelectSet::new();
+    /// set.add(p.ready(), |v| *v);
+    /// set.add(&rx, |_: Result<(), _>| 0);
+    /// assert_eq!(set.select(), 7);
+    /// ```
+    pub fn ready(&self) -> PromiseReady<'_, T> {
+        PromiseReady(self)
+    }
+
+    /// the value, `None` if it's not set
+    pub fn into_inner(self) -> Option<T> {
+        self.value.into_inner()
+    }
+
+    #[cold]
+    fn wait(&self, dur: Option<Duration>) -> Option<&T> {
+        let cur = SyncBlocker::current();
+        {
+            let mut waiters = self.waiters.lock();
+            // re-check under the lock that `set` takes
+            if let Some(v) = self.value.get() {
+                return Some(v);
+            }
+            waiters.push(Waiter::Blocker(cur.clone()));
+        }
+        let ret = cur.park(dur);
+        if !cur.is_unparked() {
+            // timed out or the coroutine is cancelled, leave the wait list
+            self.waiters.lock().retain(|w| match w {
+                Waiter::Blocker(b) => !Arc::ptr_eq(b, &cur),
+                _ => true,
+            });
+        }
+        if ret == Err(ParkError::Canceled) {
+            trigger_cancel_panic();
+        }
+        self.value.get()
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut waiters = self.waiters.lock();
+        if self.value.get().is_some() {
+            drop(waiters);
+            return waker.wake();
+        }
+        waiters.push(Waiter::Select(waker.clone()));
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.waiters.lock().retain(|w| match w {
+            Waiter::Select(w) => !w.same(waker),
+            _ => true,
+        });
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Promise<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Promise")
+            .field("value", &self.value.get())
+            .finish()
+    }
+}
+
+/// the value of a `Promise` as a source of a select, see `Promise::ready`
+pub struct PromiseReady<'a, T>(&'a Promise<T>);
+
+impl<'a, T> Selectable for PromiseReady<'a, T> {
+    type Output = &'a T;
+
+    fn try_select(&mut self) -> Option<&'a T> {
+        self.0.try_get()
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.0.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.0.deregister(waker);
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for PromiseReady<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("PromiseReady").field(self.0).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::select::SelectSet;
+
+    #[test]
+    fn no_lost_wakeup() {
+        for round in 0..100 {
+            let p = Arc::new(Promise::new());
+            let hs: Vec<_> = (0..8)
+                .map(|_| {
+                    let p = p.clone();
+                    co!(move || *p.get())
+                })
+                .collect();
+            let threads: Vec<_> = (0..2)
+                .map(|_| {
+                    let p = p.clone();
+                    std::thread::spawn(move || *p.get())
+                })
+                .collect();
+            let setter = p.clone();
+            // races the set with the waiters that are still registering
+            std::thread::spawn(move || setter.set(round).unwrap())
+                .join()
+                .unwrap();
+            for h in hs {
+                assert_eq!(h.join().unwrap(), round);
+            }
+            for t in threads {
+                assert_eq!(t.join().unwrap(), round);
+            }
+            assert_eq!(p.set(0), Err(0));
+        }
+    }
+
+    #[test]
+    fn timeout_and_select() {
+        let p = Arc::new(Promise::new());
+        assert!(p.try_get().is_none());
+        assert!(p.wait_timeout(Duration::from_millis(10)).is_none());
+        // the timed out waiter is gone from the list
+        assert!(p.waiters.lock().is_empty());
+
+        let (tx, rx) = crate::std::sync::channel::<u32>();
+        tx.send(1).unwrap();
+        let mut set = SelectSet::new();
+        set.add(p.ready(), |v| *v);
+        set.add(&rx, |v| v.unwrap());
+        assert_eq!(set.select(), 1);
+
+        let setter = p.clone();
+        let t = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            setter.set(5).unwrap();
+        });
+        assert_eq!(set.select(), 5);
+        t.join().unwrap();
+        assert_eq!(p.wait_timeout(Duration::from_millis(1)), Some(&5));
+        drop(set);
+        assert_eq!(Arc::try_unwrap(p).unwrap().into_inner(), Some(5));
+    }
+}
@@ -0,0 +1,111 @@
+//! the readiness notification of a channel for the external event loops
+//!
+//! the notification is edge triggered. it's armed when the receiver side sees
+//! the channel empty and fired by the next message, so a burst of messages
+//! is coalesced into one notification. the receivers should drain the channel
+//! with `try_recv` until `Empty`, which re-arms it. a spurious notification
+//! is possible when a message races with the arming
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+#[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+use once_cell::sync::OnceCell;
+#[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+use std::io;
+#[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+
+type Hook = Arc<dyn Fn() + Send + Sync>;
+
+pub(crate) struct Readiness {
+    // set once a hook or an fd is installed, the senders only load it
+    active: AtomicBool,
+    // the receiver side saw the channel empty and waits for the next message
+    armed: AtomicBool,
+    hook: Mutex<Option<Hook>>,
+    #[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+    fd: OnceCell<OwnedFd>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Readiness {
+            active: AtomicBool::new(false),
+            armed: AtomicBool::new(false),
+            hook: Mutex::new(None),
+            #[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+            fd: OnceCell::new(),
+        }
+    }
+
+    /// install the hook, it replaces the old one
+    pub fn set_hook(&self, hook: Hook) {
+        *self.hook.lock() = Some(hook);
+        self.active.store(true, Ordering::SeqCst);
+    }
+
+    /// the eventfd that is signaled by the notification, created on the first call
+    #[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+    pub fn fd(&self) -> io::Result<BorrowedFd<'_>> {
+        let fd = self.fd.get_or_try_init(|| {
+            let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+        })?;
+        self.active.store(true, Ordering::SeqCst);
+        Ok(fd.as_fd())
+    }
+
+    /// a message is queued, fire if the receiver side is waiting for it
+    #[inline]
+    pub fn notify(&self) {
+        if self.active.load(Ordering::SeqCst) && self.armed.swap(false, Ordering::SeqCst) {
+            self.fire();
+        }
+    }
+
+    /// all the senders are gone, fire no matter it's armed or not
+    pub fn notify_closed(&self) {
+        if self.active.load(Ordering::SeqCst) {
+            self.armed.store(false, Ordering::SeqCst);
+            self.fire();
+        }
+    }
+
+    /// the receiver side saw the channel empty. `pending` re-checks the
+    /// channel after arming, a message queued in between fires at once
+    #[inline]
+    pub fn arm<F: FnOnce() -> bool>(&self, pending: F) {
+        if !self.active.load(Ordering::SeqCst) {
+            return;
+        }
+        self.armed.store(true, Ordering::SeqCst);
+        if pending() && self.armed.swap(false, Ordering::SeqCst) {
+            self.fire();
+        }
+    }
+
+    fn fire(&self) {
+        // don't hold the lock, the hook may install a new one
+        let hook = self.hook.lock().clone();
+        if let Some(hook) = hook {
+            hook();
+        }
+        #[cfg(all(feature = "readiness-fd", target_os = "linux"))]
+        if let Some(fd) = self.fd.get() {
+            let one = 1u64;
+            // EAGAIN means the counter is already far from zero
+            unsafe {
+                libc::write(
+                    fd.as_raw_fd(),
+                    &one as *const u64 as *const libc::c_void,
+                    std::mem::size_of::<u64>(),
+                )
+            };
+        }
+    }
+}
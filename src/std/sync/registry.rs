@@ -0,0 +1,246 @@
+//! the registry of the live channels, enabled by the `chan-registry` feature
+//!
+//! each `channel` records where it's created, the live senders and receivers
+//! and the coroutines or threads that are parked on it. `channel_dump` lists
+//! them so that a coroutine parked forever can be traced back to the channel
+//! and the forgotten sender. `coroutine::dump` lists the parked coroutines
+//! with the channel that they wait on
+
+use std::collections::BTreeMap;
+use std::panic::Location;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::coroutine_impl::{current, is_coroutine, Coroutine, CoroutineId};
+use crate::timeout_list::now_instant;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static CHANNELS: Lazy<Mutex<BTreeMap<u64, Arc<ChanStat>>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// a coroutine or thread parked on a channel
+#[derive(Debug, Clone)]
+pub struct ParkedInfo {
+    /// the id of the coroutine, `None` when it's not a coroutine
+    pub id: Option<CoroutineId>,
+    /// the name of the coroutine, or of the thread when it's not a coroutine
+    pub name: Option<String>,
+    /// true if it's a coroutine
+    pub is_coroutine: bool,
+    /// true if it waits to send on a full channel, false if it waits to receive
+    pub sending: bool,
+    /// how long it's been parked
+    pub parked_for: Duration,
+}
+
+/// a snapshot of a live channel
+#[derive(Debug, Clone)]
+pub struct ChannelInfo {
+    /// the id of the channel, in creation order
+    pub id: u64,
+    /// where the channel is created
+    pub location: &'static Location<'static>,
+    /// the live senders
+    pub senders: usize,
+    /// the live receivers
+    pub receivers: usize,
+    /// the coroutines and threads parked on the channel, the longest first
+    pub parked: Vec<ParkedInfo>,
+}
+
+/// the channel that a coroutine is parked on, see `coroutine::dump`
+#[derive(Debug, Clone)]
+pub struct ChannelWait {
+    /// the id of the channel
+    pub channel: u64,
+    /// where the channel is created
+    pub location: &'static Location<'static>,
+    /// the live senders of the channel
+    pub senders: usize,
+    /// the live receivers of the channel
+    pub receivers: usize,
+    /// true if it waits to send on a full channel, false if it waits to receive
+    pub sending: bool,
+    /// how long it's been parked
+    pub parked_for: Duration,
+}
+
+struct Waiter {
+    key: usize,
+    co: Option<Coroutine>,
+    id: Option<CoroutineId>,
+    name: Option<String>,
+    is_coroutine: bool,
+    sending: bool,
+    since: Instant,
+}
+
+// the registry entry of one channel
+pub(crate) struct ChanStat {
+    id: u64,
+    location: &'static Location<'static>,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    waiters: Mutex<Vec<Waiter>>,
+    next_key: AtomicUsize,
+}
+
+impl ChanStat {
+    #[inline]
+    pub fn clone_send(&self) {
+        self.senders.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn drop_send(&self) {
+        self.senders.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn clone_recv(&self) {
+        self.receivers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn drop_recv(&self) {
+        self.receivers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// record the current coroutine or thread as parked till the guard is dropped
+    pub fn parked(&self, sending: bool) -> ParkedGuard<'_> {
+        let key = self.next_key.fetch_add(1, Ordering::Relaxed);
+        let is_coroutine = is_coroutine();
+        let (co, id, name) = if is_coroutine {
+            let co = current();
+            let (id, name) = (Some(co.id()), co.name().map(|s| s.to_owned()));
+            (Some(co), id, name)
+        } else {
+            (None, None, thread::current().name().map(|s| s.to_owned()))
+        };
+        self.waiters.lock().push(Waiter {
+            key,
+            co,
+            id,
+            name,
+            is_coroutine,
+            sending,
+            since: now_instant(),
+        });
+        ParkedGuard { stat: self, key }
+    }
+
+    fn info(&self) -> ChannelInfo {
+        let now = now_instant();
+        let parked = self
+            .waiters
+            .lock()
+            .iter()
+            .map(|w| ParkedInfo {
+                id: w.id,
+                name: w.name.clone(),
+                is_coroutine: w.is_coroutine,
+                sending: w.sending,
+                parked_for: now.saturating_duration_since(w.since),
+            })
+            .collect();
+        ChannelInfo {
+            id: self.id,
+            location: self.location,
+            senders: self.senders.load(Ordering::Relaxed),
+            receivers: self.receivers.load(Ordering::Relaxed),
+            parked,
+        }
+    }
+
+    fn parked_coroutines(&self, now: Instant, out: &mut Vec<(Coroutine, ChannelWait)>) {
+        let senders = self.senders.load(Ordering::Relaxed);
+        let receivers = self.receivers.load(Ordering::Relaxed);
+        for w in self.waiters.lock().iter() {
+            if let Some(co) = &w.co {
+                let wait = ChannelWait {
+                    channel: self.id,
+                    location: self.location,
+                    senders,
+                    receivers,
+                    sending: w.sending,
+                    parked_for: now.saturating_duration_since(w.since),
+                };
+                out.push((co.clone(), wait));
+            }
+        }
+    }
+}
+
+pub(crate) struct ParkedGuard<'a> {
+    stat: &'a ChanStat,
+    key: usize,
+}
+
+impl<'a> Drop for ParkedGuard<'a> {
+    fn drop(&mut self) {
+        let key = self.key;
+        self.stat.waiters.lock().retain(|w| w.key != key);
+    }
+}
+
+// register a new channel with one sender and one receiver
+#[track_caller]
+pub(crate) fn register() -> Arc<ChanStat> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let stat = Arc::new(ChanStat {
+        id,
+        location: Location::caller(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        waiters: Mutex::new(Vec::new()),
+        next_key: AtomicUsize::new(0),
+    });
+    CHANNELS.lock().insert(id, stat.clone());
+    stat
+}
+
+// the channel is freed
+pub(crate) fn unregister(stat: &ChanStat) {
+    CHANNELS.lock().remove(&stat.id);
+}
+
+/// a snapshot of all the live channels, in creation order
+///
+/// it's only available with the `chan-registry` feature
+///
+/// ```
+/// use mco::std::sync::{channel, channel_dump};
+///
+/// let (tx, rx) = channel::<u32>();
+/// let info = channel_dump().into_iter().last().unwrap();
+/// assert_eq!(info.location.file(), file!());
+/// assert_eq!((info.senders, info.receivers), (1, 1));
+/// # drop((tx, rx));
+/// ```
+pub fn channel_dump() -> Vec<ChannelInfo> {
+    let channels: Vec<_> = CHANNELS.lock().values().cloned().collect();
+    channels
+        .iter()
+        .map(|c| {
+            let mut info = c.info();
+            info.parked.sort_by(|a, b| b.parked_for.cmp(&a.parked_for));
+            info
+        })
+        .collect()
+}
+
+// the coroutines parked on the live channels, for `coroutine::dump`
+pub(crate) fn parked_coroutines() -> Vec<(Coroutine, ChannelWait)> {
+    let channels: Vec<_> = CHANNELS.lock().values().cloned().collect();
+    let now = now_instant();
+    let mut parked = Vec::new();
+    for c in channels.iter() {
+        c.parked_coroutines(now, &mut parked);
+    }
+    parked
+}
@@ -0,0 +1,324 @@
+//! a router from one inbound channel to the outbound channels of the routes
+//!
+//! `Router::new` takes the inbound receiver and a function from a message to
+//! its key, each key has a route with a small buffer and a pump coroutine
+//! that moves the buffer to the outbound sender of the route. the dispatch
+//! coroutine only puts the messages in the buffers, so an outbound that is
+//! full blocks the pump of its route and none of the other routes. when the
+//! buffer of a route is full the `FullPolicy` of the route decides, only
+//! `FullPolicy::Block` waits for the room and so holds the dispatch
+//!
+//! the messages that have no route are dropped and counted by
+//! `Router::unrouted`. when the inbound is disconnected the dispatch ends,
+//! the pumps deliver what is buffered and end in turn, `Router::join` waits
+//! for all of them
+//!
+//! ```
+//! use mco::std::sync::channel::{bounded, channel};
+//! use mco::std::sync::router::{FullPolicy, Router};
+//!
+//! let (tx, rx) = channel::<(u8, &str)>();
+//! let router = Router::new(rx, |m: &(u8, &str)| m.0);
+//! let (even_tx, even_rx) = bounded(16);
+//! let (odd_tx, odd_rx) = bounded(16);
+//! router.add_route(0, even_tx);
+//! router.add_route_with(1, odd_tx, 4, FullPolicy::DropOldest);
+//! tx.send((0, "zero")).unwrap();
+//! tx.send((1, "one")).unwrap();
+//! tx.send((2, "two")).unwrap();
+//! drop(tx);
+//! router.join();
+//! assert_eq!(even_rx.recv().unwrap(), (0, "zero"));
+//! assert_eq!(odd_rx.recv().unwrap(), (1, "one"));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use super::channel::{bounded, Receiver, Sender};
+use crate::coroutine_impl::Builder;
+use crate::join::JoinHandle;
+
+/// the default buffer of a route
+pub const ROUTE_BUF: usize = 16;
+
+/// what the dispatch does with a message for a route whose buffer is full
+pub enum FullPolicy<T> {
+    /// wait for the room in the buffer, the other routes wait too
+    Block,
+    /// drop the new message
+    DropNewest,
+    /// drop the oldest buffered message to make room for the new one
+    DropOldest,
+    /// send the new message to the overflow channel, it's dropped if that
+    /// one is full too
+    Spill(Sender<T>),
+}
+
+impl<T> fmt::Debug for FullPolicy<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FullPolicy::Block => f.write_str("Block"),
+            FullPolicy::DropNewest => f.write_str("DropNewest"),
+            FullPolicy::DropOldest => f.write_str("DropOldest"),
+            FullPolicy::Spill(_) => f.write_str("Spill(..)"),
+        }
+    }
+}
+
+/// the counters of a route
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouteStats {
+    /// the messages sent to the outbound
+    pub delivered: usize,
+    /// the messages dropped by the policy, or because the outbound is gone
+    pub dropped: usize,
+    /// the messages sent to the overflow channel
+    pub spilled: usize,
+    /// the messages in the buffer of the route
+    pub depth: usize,
+}
+
+struct Route<T> {
+    buf: Sender<T>,
+    // the dispatch side of the buffer, to drop the oldest message
+    oldest: Receiver<T>,
+    policy: FullPolicy<T>,
+    delivered: Arc<AtomicUsize>,
+    dropped: Arc<AtomicUsize>,
+    spilled: AtomicUsize,
+}
+
+impl<T> Route<T> {
+    // put the message in the buffer as the policy says, it never blocks but
+    // for `Block`
+    fn push(&self, t: T) {
+        let t = match self.buf.try_send(t) {
+            Ok(()) => return,
+            Err(e) => e.0,
+        };
+        match &self.policy {
+            FullPolicy::Block => {
+                if self.buf.send(t).is_err() {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            FullPolicy::DropNewest => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            FullPolicy::DropOldest => {
+                let mut t = t;
+                // the pump may take the room first
+                loop {
+                    if self.oldest.try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    match self.buf.try_send(t) {
+                        Ok(()) => return,
+                        Err(e) => t = e.0,
+                    }
+                }
+            }
+            FullPolicy::Spill(spill) => match spill.try_send(t) {
+                Ok(()) => {
+                    self.spilled.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(_) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+        }
+    }
+
+    fn stats(&self) -> RouteStats {
+        RouteStats {
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            depth: self.oldest.remain(),
+        }
+    }
+}
+
+struct Inner<K, T> {
+    routes: Mutex<HashMap<K, Arc<Route<T>>>>,
+    unrouted: AtomicUsize,
+    pumps: Mutex<Vec<JoinHandle<()>>>,
+}
+
+/// the router of an inbound channel, see the module docs
+pub struct Router<K, T> {
+    inner: Arc<Inner<K, T>>,
+    dispatch: JoinHandle<()>,
+}
+
+impl<K, T> Router<K, T>
+where
+    K: Hash + Eq + Send + 'static,
+    T: Send + 'static,
+{
+    /// start the dispatch of the inbound messages by the key of `route`, the
+    /// routes are added by `add_route`
+    pub fn new<F>(inbound: Receiver<T>, route: F) -> Self
+    where
+        F: Fn(&T) -> K + Send + 'static,
+    {
+        let inner = Arc::new(Inner {
+            routes: Mutex::new(HashMap::new()),
+            unrouted: AtomicUsize::new(0),
+            pumps: Mutex::new(Vec::new()),
+        });
+        let shared = inner.clone();
+        let dispatch = Builder::new().name("router".to_owned()).spawn(move || {
+            for t in inbound.iter() {
+                let r = shared.routes.lock().get(&route(&t)).cloned();
+                match r {
+                    Some(r) => r.push(t),
+                    None => {
+                        shared.unrouted.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            // the pumps drain their buffers and end
+            shared.routes.lock().clear();
+        });
+        Router { inner, dispatch }
+    }
+
+    /// route the messages of the key to `out` with a buffer of `ROUTE_BUF`
+    /// that drops the oldest message when it's full
+    pub fn add_route(&self, key: K, out: Sender<T>) {
+        self.add_route_with(key, out, ROUTE_BUF, FullPolicy::DropOldest)
+    }
+
+    /// route the messages of the key to `out` with a buffer of `buf` and the
+    /// policy for a full buffer. it replaces the route of the key, whose
+    /// pump delivers its buffer before it ends
+    pub fn add_route_with(&self, key: K, out: Sender<T>, buf: usize, policy: FullPolicy<T>) {
+        let (tx, rx) = bounded(buf.max(1));
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let route = Route {
+            buf: tx,
+            oldest: rx.clone(),
+            policy,
+            delivered: delivered.clone(),
+            dropped: dropped.clone(),
+            spilled: AtomicUsize::new(0),
+        };
+        let pump = Builder::new()
+            .name("router-pump".to_owned())
+            .spawn(move || {
+                for t in rx.iter() {
+                    // only this route waits for a full outbound
+                    match out.send(t) {
+                        Ok(()) => delivered.fetch_add(1, Ordering::Relaxed),
+                        Err(_) => dropped.fetch_add(1, Ordering::Relaxed),
+                    };
+                }
+            });
+        let mut pumps = self.inner.pumps.lock();
+        pumps.retain(|h| !h.is_done());
+        pumps.push(pump);
+        drop(pumps);
+        self.inner.routes.lock().insert(key, Arc::new(route));
+    }
+
+    /// remove the route of the key, its buffer is still delivered. the
+    /// messages of the key are unrouted after it
+    pub fn remove_route(&self, key: &K) -> bool {
+        self.inner.routes.lock().remove(key).is_some()
+    }
+
+    /// the counters of the route of the key
+    pub fn stats(&self, key: &K) -> Option<RouteStats> {
+        self.inner.routes.lock().get(key).map(|r| r.stats())
+    }
+
+    /// the messages that had no route, they are dropped
+    pub fn unrouted(&self) -> usize {
+        self.inner.unrouted.load(Ordering::Relaxed)
+    }
+
+    /// wait for the inbound to be disconnected and all the buffered messages
+    /// to be delivered
+    pub fn join(self) {
+        let _ = self.dispatch.join();
+        let pumps = std::mem::take(&mut *self.inner.pumps.lock());
+        for h in pumps {
+            let _ = h.join();
+        }
+    }
+}
+
+impl<K, T> fmt::Debug for Router<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Router {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::std::sync::channel::channel;
+    use std::time::Duration;
+
+    #[test]
+    fn stuck_route_doesnt_stall() {
+        let (tx, rx) = channel::<(u8, u32)>();
+        let router = Router::new(rx, |m: &(u8, u32)| m.0);
+        // nobody reads the stuck outbound
+        let (stuck_tx, stuck_rx) = bounded(1);
+        let (spill_tx, spill_rx) = channel();
+        let (live_tx, live_rx) = channel();
+        router.add_route_with(0, stuck_tx, 2, FullPolicy::Spill(spill_tx));
+        router.add_route(1, live_tx);
+        for i in 0..10 {
+            tx.send((0, i)).unwrap();
+            tx.send((1, i)).unwrap();
+        }
+        tx.send((2, 0)).unwrap();
+        for i in 0..10 {
+            let m = live_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(m, (1, i));
+        }
+        let stats = router.stats(&1).unwrap();
+        assert_eq!((stats.delivered, stats.dropped), (10, 0));
+        // one in the outbound, one in the pump and two buffered, the rest
+        // are spilled
+        let stats = router.stats(&0).unwrap();
+        assert!(stats.spilled >= 6, "{:?}", stats);
+        assert_eq!(spill_rx.try_iter().count(), stats.spilled);
+        drop(tx);
+        drop(stuck_rx);
+        router.join();
+    }
+
+    #[test]
+    fn drop_policies() {
+        let (tx, rx) = channel::<u32>();
+        let router = Router::new(rx, |_: &u32| ());
+        let (out_tx, out_rx) = bounded(1);
+        router.add_route_with((), out_tx, 1, FullPolicy::DropNewest);
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        // the first one is never dropped
+        assert_eq!(out_rx.recv().unwrap(), 0);
+        std::thread::sleep(Duration::from_millis(20));
+        let stats = router.stats(&()).unwrap();
+        assert!(stats.dropped >= 6, "{:?}", stats);
+        assert!(stats.depth <= 1);
+        drop(tx);
+        // the pump can't deliver the rest
+        drop(out_rx);
+        let inner = router.inner.clone();
+        router.join();
+        assert!(inner.routes.lock().is_empty());
+    }
+}
@@ -3,6 +3,7 @@
 use crate::std::queue::mpsc_list::Queue as WaitList;
 use std::cell::UnsafeCell;
 use std::fmt;
+use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -14,6 +15,7 @@ use super::mutex::{self, Mutex};
 use super::poison;
 use crate::cancel::trigger_cancel_panic;
 use crate::park::ParkError;
+use crate::select::{Selectable, Waker, WakerList};
 
 /// A reader-writer lock
 ///
@@ -25,6 +27,8 @@ pub struct RwLock<T: ?Sized> {
     to_wake: WaitList<Arc<SyncBlocker>>,
     // track how many blockers are waiting on the mutex
     cnt: AtomicUsize,
+    // the selects that wait for the write lock, they are not counted in `cnt`
+    selectors: WakerList,
 
     // the reader mutex that track the reader count
     rlock: Mutex<usize>,
@@ -56,11 +60,40 @@ pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
 
 // impl<'a, T: ?Sized> !marker::Send for RwLockWriteGuard<'a, T> {}
 
+/// a read guard for a component of the locked data, created by `RwLockReadGuard::map`
+#[must_use]
+pub struct MappedRwLockReadGuard<'a, T: ?Sized + 'a, U: ?Sized + 'a> {
+    __lock: &'a RwLock<T>,
+    __data: *const U,
+}
+
+/// a write guard for a component of the locked data, created by `RwLockWriteGuard::map`
+#[must_use]
+pub struct MappedRwLockWriteGuard<'a, T: ?Sized + 'a, U: ?Sized + 'a> {
+    __lock: &'a RwLock<T>,
+    __poison: poison::Guard,
+    __data: *mut U,
+}
+
+/// a read guard that owns an `Arc` of the lock, created by `RwLock::read_owned`
+#[must_use]
+pub struct OwnedRwLockReadGuard<T: ?Sized> {
+    __lock: Arc<RwLock<T>>,
+}
+
+/// a write guard that owns an `Arc` of the lock, created by `RwLock::write_owned`
+#[must_use]
+pub struct OwnedRwLockWriteGuard<T: ?Sized> {
+    __lock: Arc<RwLock<T>>,
+    __poison: poison::Guard,
+}
+
 impl<T> RwLock<T> {
     pub fn new(t: T) -> RwLock<T> {
         RwLock {
             to_wake: WaitList::new(),
             cnt: AtomicUsize::new(0),
+            selectors: WakerList::new(),
             rlock: Mutex::new(0),
             poison: poison::Flag::new(),
             data: UnsafeCell::new(t),
@@ -136,6 +169,8 @@ impl<T: ?Sized> RwLock<T> {
                 .pop()
                 .map(|w| self.unpark_one(&w))
                 .expect("got null blocker!");
+        } else {
+            self.selectors.wake_all();
         }
     }
 
@@ -187,6 +222,27 @@ impl<T: ?Sized> RwLock<T> {
         Ok(g)
     }
 
+    /// lock the `Arc` for read, the guard keeps the lock alive
+    pub fn read_owned(self: Arc<Self>) -> LockResult<OwnedRwLockReadGuard<T>> {
+        // the read count is released by the owned guard
+        let poisoned = match self.read() {
+            Ok(g) => {
+                ::std::mem::forget(g);
+                false
+            }
+            Err(e) => {
+                ::std::mem::forget(e.into_inner());
+                true
+            }
+        };
+        let guard = OwnedRwLockReadGuard { __lock: self };
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
     fn read_unlock(&self) {
         let mut r = self.rlock.lock().expect("rwlock read_unlock");
         *r -= 1;
@@ -210,6 +266,44 @@ impl<T: ?Sized> RwLock<T> {
         Ok(RwLockWriteGuard::new(self)?)
     }
 
+    /// lock for write as a source of a `SelectSet` or an arm of `select!`
+    ///
+    /// like `Mutex::lock_select`, a select only takes the lock when no
+    /// reader or writer holds it and never queues up with the others
+    ///
+    /// ```rust
+    /// use mco::select::Selectable;
+    /// use mco::std::sync::{CancellationToken, RwLock};
+    ///
+    /// let lock = RwLock::new(0);
+    /// let shutdown = CancellationToken::new();
+    /// mco::select! {
+    ///     g = lock.write_select().wait() => *g.unwrap() += 1,
+    ///     _ = shutdown.cancelled() => {}
+    /// };
+    /// assert_eq!(*lock.read().unwrap(), 1);
+    /// ```
+    pub fn write_select(&self) -> RwLockWrite<'_, T> {
+        RwLockWrite { lock: self }
+    }
+
+    /// lock the `Arc` for write, the guard keeps the lock alive
+    pub fn write_owned(self: Arc<Self>) -> LockResult<OwnedRwLockWriteGuard<T>> {
+        let (poison, poisoned) = match self.write() {
+            Ok(g) => (g.into_poison(), false),
+            Err(e) => (e.into_inner().into_poison(), true),
+        };
+        let guard = OwnedRwLockWriteGuard {
+            __lock: self,
+            __poison: poison,
+        };
+        if poisoned {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
     fn write_unlock(&self) {
         self.unlock();
     }
@@ -255,10 +349,55 @@ impl<T: Default> Default for RwLock<T> {
     }
 }
 
+/// the write lock of a `RwLock` as a source of a select, created by
+/// `RwLock::write_select`
+pub struct RwLockWrite<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+}
+
+impl<'a, T: ?Sized> Selectable for RwLockWrite<'a, T> {
+    type Output = LockResult<RwLockWriteGuard<'a, T>>;
+
+    fn try_select(&mut self) -> Option<Self::Output> {
+        // the lock is not taken on any error, the guard carries the poison
+        match self.lock.try_lock() {
+            Ok(()) => Some(RwLockWriteGuard::new(self.lock)),
+            Err(_) => None,
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.lock.selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.lock.selectors.deregister(waker);
+    }
+}
+
+impl<'a, T: ?Sized> fmt::Debug for RwLockWrite<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RwLockWrite {{ .. }}")
+    }
+}
+
 impl<'rwlock, T: ?Sized> RwLockReadGuard<'rwlock, T> {
     fn new(lock: &'rwlock RwLock<T>) -> LockResult<RwLockReadGuard<'rwlock, T>> {
         poison::map_result(lock.poison.borrow(), |_| RwLockReadGuard { __lock: lock })
     }
+
+    /// make a read guard for a component of the locked data
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedRwLockReadGuard<'rwlock, T, U>
+    where
+        F: FnOnce(&T) -> &U,
+    {
+        let data = f(unsafe { &*this.__lock.data.get() }) as *const U;
+        let this = ManuallyDrop::new(this);
+        MappedRwLockReadGuard {
+            __lock: this.__lock,
+            __data: data,
+        }
+    }
 }
 
 impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
@@ -268,6 +407,27 @@ impl<'rwlock, T: ?Sized> RwLockWriteGuard<'rwlock, T> {
             __poison: guard,
         })
     }
+
+    /// make a write guard for a component of the locked data
+    pub fn map<U: ?Sized, F>(this: Self, f: F) -> MappedRwLockWriteGuard<'rwlock, T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        // if `f` panics the lock is released by `this`
+        let data = f(unsafe { &mut *this.__lock.data.get() }) as *mut U;
+        let lock = this.__lock;
+        MappedRwLockWriteGuard {
+            __lock: lock,
+            __poison: this.into_poison(),
+            __data: data,
+        }
+    }
+
+    // take the poison guard without unlocking
+    fn into_poison(self) -> poison::Guard {
+        let this = ManuallyDrop::new(self);
+        unsafe { ::std::ptr::read(&this.__poison) }
+    }
 }
 
 impl<'a, T: fmt::Debug> fmt::Debug for RwLockReadGuard<'a, T> {
@@ -321,12 +481,115 @@ impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
     }
 }
 
+impl<'a, T: ?Sized, U: ?Sized> Deref for MappedRwLockReadGuard<'a, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { &*self.__data }
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> Drop for MappedRwLockReadGuard<'a, T, U> {
+    fn drop(&mut self) {
+        self.__lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug for MappedRwLockReadGuard<'a, T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedRwLockReadGuard")
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> Deref for MappedRwLockWriteGuard<'a, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { &*self.__data }
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> DerefMut for MappedRwLockWriteGuard<'a, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.__data }
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized> Drop for MappedRwLockWriteGuard<'a, T, U> {
+    fn drop(&mut self) {
+        self.__lock.poison.done(&self.__poison);
+        self.__lock.write_unlock();
+    }
+}
+
+impl<'a, T: ?Sized, U: ?Sized + fmt::Debug> fmt::Debug for MappedRwLockWriteGuard<'a, T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MappedRwLockWriteGuard")
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedRwLockReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.__lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedRwLockReadGuard<T> {
+    fn drop(&mut self) {
+        self.__lock.read_unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRwLockReadGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedRwLockReadGuard")
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedRwLockWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.__lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedRwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.__lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedRwLockWriteGuard<T> {
+    fn drop(&mut self) {
+        // the poison flag is set if the holder panics
+        self.__lock.poison.done(&self.__poison);
+        self.__lock.write_unlock();
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for OwnedRwLockWriteGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedRwLockWriteGuard")
+            .field("data", &&**self)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![feature(test)]
 
     use crate::std::sync::channel::channel;
-    use crate::std::sync::{Condvar, Mutex, RwLock};
+    use crate::std::sync::{Condvar, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::{Arc, TryLockError};
     use std::thread;
@@ -581,6 +844,44 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rwlock_guard_map() {
+        let lock = RwLock::new((1, String::from("a")));
+        {
+            let r = RwLockReadGuard::map(lock.read().unwrap(), |t| &t.1);
+            assert_eq!(&*r, "a");
+            assert!(lock.try_write().is_err());
+        }
+        {
+            let mut w = RwLockWriteGuard::map(lock.write().unwrap(), |t| &mut t.1);
+            w.push('b');
+            assert!(lock.try_read().is_err());
+        }
+        assert_eq!(lock.read().unwrap().1, "ab");
+    }
+
+    #[test]
+    fn test_rwlock_owned() {
+        let lock = Arc::new(RwLock::new(0));
+        let r1 = lock.clone().read_owned().unwrap();
+        let r2 = lock.clone().read_owned().unwrap();
+        assert!(lock.try_write().is_err());
+        let h = co!(move || *r1 + *r2);
+        assert_eq!(h.join().unwrap(), 0);
+
+        let mut w = lock.clone().write_owned().unwrap();
+        let h = co!(move || {
+            *w += 1;
+            panic!("panic with the owned write guard");
+        });
+        assert!(h.join().is_err());
+        assert!(lock.is_poisoned());
+        match lock.clone().read_owned() {
+            Err(e) => assert_eq!(**e.get_ref(), 1),
+            Ok(_) => panic!("should be poisoned"),
+        }
+    }
+
     #[test]
     fn test_rwlock_write_canceled() {
         const N: usize = 10;
@@ -690,4 +991,46 @@ mod tests {
         assert_eq!(a, 10);
         assert_eq!(rx.try_recv().is_err(), true);
     }
+
+    #[test]
+    fn select_write_no_leak() {
+        use crate::select::{After, Selectable};
+        use std::time::Duration;
+
+        const SELECTS: usize = 10_000;
+        const WORKERS: usize = 8;
+        let lock = Arc::new(RwLock::new(0));
+        let done = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..WORKERS)
+            .map(|i| {
+                let lock = lock.clone();
+                let done = done.clone();
+                co!(move || {
+                    // the count is taken with the lock held
+                    let bump = |mut g: RwLockWriteGuard<usize>| {
+                        *g += 1;
+                        done.fetch_add(1, Ordering::SeqCst);
+                    };
+                    for j in 0..SELECTS / WORKERS {
+                        match (i + j) % 4 {
+                            // the readers and the writers queue up among the selects
+                            0 => drop(lock.read().unwrap()),
+                            1 => bump(lock.write().unwrap()),
+                            // the guard of a select that loses is unlocked
+                            _ => {
+                                select! {
+                                    g = lock.write_select().wait() => bump(g.unwrap()),
+                                    _ = After::new(Duration::from_micros(50)).wait() => {}
+                                };
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.try_write().unwrap(), done.load(Ordering::SeqCst));
+    }
 }
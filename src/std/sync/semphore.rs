@@ -6,6 +6,8 @@ use std::time::Duration;
 use super::blocking::SyncBlocker;
 use crate::cancel::trigger_cancel_panic;
 use crate::park::ParkError;
+use crate::scheduler::batch_wakes;
+use crate::select::{Selectable, Waker, WakerList};
 use crate::std::queue::seg_queue::SegQueue as WaitList;
 
 /// Semphore primitive
@@ -45,6 +47,8 @@ pub struct Semphore {
     cnt: AtomicIsize,
     // the waiting blocker list, must be mpmc
     to_wake: WaitList<Arc<SyncBlocker>>,
+    // the selects that wait for a resource, they are not in the wait list
+    selectors: WakerList,
 }
 
 impl Semphore {
@@ -54,6 +58,7 @@ impl Semphore {
         Semphore {
             to_wake: WaitList::new(),
             cnt: AtomicIsize::new(init as isize),
+            selectors: WakerList::new(),
         }
     }
 
@@ -140,6 +145,47 @@ impl Semphore {
         false
     }
 
+    /// return how many semphore resources are acquired, at most `max`
+    /// it would not block if no resource is available
+    pub fn try_wait_many(&self, max: usize) -> usize {
+        let mut cnt = self.cnt.load(Ordering::SeqCst);
+        while cnt > 0 {
+            let n = std::cmp::min(cnt as usize, max) as isize;
+            match self
+                .cnt
+                .compare_exchange(cnt, cnt - n, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return n as usize,
+                Err(x) => cnt = x,
+            }
+        }
+        0
+    }
+
+    /// increment the semphore value by `n`
+    /// and would wakeup up to `n` threads/coroutines that are calling `wait`
+    pub fn post_many(&self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        assert!(n < ::std::isize::MAX as usize);
+        let cnt = self.cnt.fetch_add(n as isize, Ordering::SeqCst);
+        assert!(cnt < ::std::isize::MAX - n as isize);
+
+        // wakeup the waiters that are covered by the new resources
+        if cnt < 0 {
+            batch_wakes(|| {
+                for _ in 0..std::cmp::min((-cnt) as usize, n) {
+                    self.wakeup_one();
+                }
+            });
+        }
+        // the rest are left for the selects
+        if cnt + (n as isize) > 0 {
+            self.selectors.wake_all();
+        }
+    }
+
     /// increment the semphore value
     /// and would wakeup a thread/coroutine that is calling `wait`
     pub fn post(&self) {
@@ -149,7 +195,63 @@ impl Semphore {
         // try to wakeup one waiter first
         if cnt < 0 {
             self.wakeup_one();
+        } else {
+            self.selectors.wake_all();
+        }
+    }
+
+    // increment the value only if no one waits for it, so it never wakes
+    // anyone nor takes a lock
+    pub(crate) fn try_post_quiet(&self) -> bool {
+        let mut cnt = self.cnt.load(Ordering::SeqCst);
+        while cnt >= 0 {
+            match self
+                .cnt
+                .compare_exchange(cnt, cnt + 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return true,
+                Err(x) => cnt = x,
+            }
         }
+        false
+    }
+
+    /// acquire one resource as a source of a `SelectSet` or an arm of
+    /// `select!`, the resource is released when the permit is dropped
+    ///
+    /// a select never queues up with the `wait` callers, it only takes a
+    /// resource that no waiter is waiting for. so a select that loses holds
+    /// nothing and the waiters keep their order, while a select may wait
+    /// longer than a `wait` that comes later
+    ///
+    /// ```rust
+    /// use mco::select::Selectable;
+    /// use mco::std::sync::{CancellationToken, Semphore};
+    ///
+    /// let sem = Semphore::new(1);
+    /// let shutdown = CancellationToken::new();
+    /// let got = mco::select! {
+    ///     permit = sem.acquire_select().wait() => {
+    ///         assert_eq!(sem.get_value(), 0);
+    ///         drop(permit);
+    ///     },
+    ///     _ = shutdown.cancelled() => {}
+    /// };
+    /// assert_eq!(got, 0);
+    /// assert_eq!(sem.get_value(), 1);
+    /// ```
+    pub fn acquire_select(&self) -> SemphoreAcquire<'_> {
+        SemphoreAcquire { sem: self }
+    }
+
+    /// return how many threads and coroutines wait in `wait`, it's a relaxed
+    /// read that may be stale at once. the selects are not counted
+    pub fn waiters(&self) -> usize {
+        let cnt = self.cnt.load(Ordering::Relaxed);
+        if cnt < 0 {
+            return -cnt as usize;
+        }
+        0
     }
 
     /// return the current semphore value
@@ -169,6 +271,67 @@ impl fmt::Debug for Semphore {
     }
 }
 
+/// the acquire of a semphore resource as a source of a select, created by
+/// `Semphore::acquire_select`
+pub struct SemphoreAcquire<'a> {
+    sem: &'a Semphore,
+}
+
+impl<'a> Selectable for SemphoreAcquire<'a> {
+    type Output = SemphorePermit<'a>;
+
+    fn try_select(&mut self) -> Option<SemphorePermit<'a>> {
+        if self.sem.try_wait() {
+            Some(SemphorePermit { sem: self.sem })
+        } else {
+            None
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        self.sem.selectors.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.sem.selectors.deregister(waker);
+    }
+}
+
+impl<'a> fmt::Debug for SemphoreAcquire<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SemphoreAcquire")
+            .field("sem", self.sem)
+            .finish()
+    }
+}
+
+/// a semphore resource taken by a select, it's posted back when dropped
+#[must_use]
+pub struct SemphorePermit<'a> {
+    sem: &'a Semphore,
+}
+
+impl<'a> SemphorePermit<'a> {
+    /// keep the resource taken, it's not posted back
+    pub fn forget(self) {
+        ::std::mem::forget(self)
+    }
+}
+
+impl<'a> Drop for SemphorePermit<'a> {
+    fn drop(&mut self) {
+        self.sem.post();
+    }
+}
+
+impl<'a> fmt::Debug for SemphorePermit<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SemphorePermit")
+            .field("sem", self.sem)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![feature(test)]
@@ -315,4 +478,92 @@ mod tests {
         sem1.post();
         h2.join().unwrap();
     }
+
+    #[test]
+    fn post_many() {
+        let sem = Arc::new(Semphore::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let sem = sem.clone();
+                thread::spawn(move || sem.wait())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+        sem.post_many(6);
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(sem.get_value(), 2);
+        assert_eq!(sem.try_wait_many(5), 2);
+        assert_eq!(sem.try_wait_many(5), 0);
+    }
+
+    #[test]
+    fn select_acquire_no_leak() {
+        use crate::coroutine::yield_now;
+        use crate::select::{After, SelectSet, Selectable};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const SELECTS: usize = 10_000;
+        const WORKERS: usize = 8;
+        let sem = Arc::new(Semphore::new(2));
+        let held = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..WORKERS)
+            .map(|i| {
+                let sem = sem.clone();
+                let held = held.clone();
+                co!(move || {
+                    for j in 0..SELECTS / WORKERS {
+                        let hold = || {
+                            assert!(held.fetch_add(1, Ordering::SeqCst) < 2);
+                            yield_now();
+                            held.fetch_sub(1, Ordering::SeqCst);
+                        };
+                        match (i + j) % 4 {
+                            // the plain waiters queue up among the selects
+                            0 => {
+                                sem.wait();
+                                hold();
+                                sem.post();
+                            }
+                            1 => {
+                                let mut set = SelectSet::new();
+                                set.add(sem.acquire_select(), Some);
+                                set.add(After::new(Duration::from_micros(50)), |_| None);
+                                if let Some(_permit) = set.select() {
+                                    hold();
+                                }
+                            }
+                            // the permit of a select that loses is posted back
+                            _ => {
+                                select! {
+                                    _permit = sem.acquire_select().wait() => hold(),
+                                    _ = After::new(Duration::from_micros(50)).wait() => {}
+                                };
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(held.load(Ordering::SeqCst), 0);
+        assert_eq!(sem.get_value(), 2);
+        assert_eq!(sem.try_wait_many(3), 2);
+    }
+
+    #[test]
+    fn select_acquire_wakeup() {
+        use crate::select::Selectable;
+
+        let sem = Arc::new(Semphore::new(0));
+        let sem2 = sem.clone();
+        let h = co!(move || sem2.acquire_select().wait().forget());
+        thread::sleep(Duration::from_millis(10));
+        sem.post();
+        h.join().unwrap();
+        assert_eq!(sem.get_value(), 0);
+    }
 }
@@ -0,0 +1,396 @@
+//! the read-mostly shared state, sharded by the worker threads
+//!
+//! a `ShardedLock` keeps one reader lock for each worker, a reader only
+//! touches the shard of the worker it runs on, so the readers on different
+//! workers never share a cache line. a writer locks all the shards in order,
+//! the writes are much more expensive than with a plain `RwLock`.
+//!
+//! the shards are allocated for `config().get_max_workers()` workers when the
+//! lock is created, the workers that are added later and the threads that
+//! don't belong to the runtime share the last overflow shard
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+use crossbeam_utils::CachePadded;
+
+use super::poison;
+use super::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::config::config;
+use crate::scheduler::worker_id;
+
+// one shard for each worker and the last one for the others
+#[inline]
+fn shard_count() -> usize {
+    config().get_max_workers() + 1
+}
+
+#[inline]
+fn shard_index(shards: usize) -> usize {
+    let id = worker_id();
+    let last = shards - 1;
+    if id < last {
+        id
+    } else {
+        last
+    }
+}
+
+/// A reader-writer lock with a reader shard for each worker
+///
+/// the readers are cheap and scale with the workers, the writers lock every
+/// shard. use it for the state that is read on every request and seldom
+/// changed, like a routing table
+/// ```
+/// use mco::std::sync::ShardedLock;
+/// use std::sync::Arc;
+///
+/// let routes = Arc::new(ShardedLock::new(vec!["/"]));
+/// let r = routes.clone();
+/// let h = mco::co!(move || r.read().unwrap().len());
+/// routes.write().unwrap().push("/index");
+/// assert!(h.join().unwrap() >= 1);
+/// ```
+pub struct ShardedLock<T: ?Sized> {
+    shards: Box<[CachePadded<RwLock<()>>]>,
+    poison: poison::Flag,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for ShardedLock<T> {}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for ShardedLock<T> {}
+
+impl<T: ?Sized> UnwindSafe for ShardedLock<T> {}
+
+impl<T: ?Sized> RefUnwindSafe for ShardedLock<T> {}
+
+/// the read guard of a `ShardedLock`
+#[must_use]
+pub struct ShardedLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a ShardedLock<T>,
+    // the guard remembers its shard, the coroutine may run on another worker
+    // when it's released
+    _guard: RwLockReadGuard<'a, ()>,
+}
+
+/// the write guard of a `ShardedLock`
+#[must_use]
+pub struct ShardedLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a ShardedLock<T>,
+    poison: poison::Guard,
+    _guards: Vec<RwLockWriteGuard<'a, ()>>,
+}
+
+impl<T> ShardedLock<T> {
+    pub fn new(t: T) -> ShardedLock<T> {
+        ShardedLock {
+            shards: (0..shard_count())
+                .map(|_| CachePadded::new(RwLock::new(())))
+                .collect(),
+            poison: poison::Flag::new(),
+            data: UnsafeCell::new(t),
+        }
+    }
+
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.poison.get();
+        let data = self.data.into_inner();
+        if poisoned {
+            Err(PoisonError::new(data))
+        } else {
+            Ok(data)
+        }
+    }
+}
+
+impl<T: ?Sized> ShardedLock<T> {
+    #[inline]
+    fn read_guard(&self, guard: RwLockReadGuard<'_, ()>) -> LockResult<ShardedLockReadGuard<T>> {
+        let guard = ShardedLockReadGuard {
+            lock: self,
+            _guard: guard,
+        };
+        if self.poison.get() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// lock the shard of the current worker for read
+    pub fn read(&self) -> LockResult<ShardedLockReadGuard<T>> {
+        let shard = &self.shards[shard_index(self.shards.len())];
+        // the poison of the shards is tracked by our own flag
+        let guard = shard.read().unwrap_or_else(PoisonError::into_inner);
+        self.read_guard(guard)
+    }
+
+    pub fn try_read(&self) -> TryLockResult<ShardedLockReadGuard<T>> {
+        let shard = &self.shards[shard_index(self.shards.len())];
+        let guard = match shard.try_read() {
+            Ok(g) => g,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Err(TryLockError::WouldBlock),
+        };
+        Ok(self.read_guard(guard)?)
+    }
+
+    /// lock all the shards for write, in the shard order
+    ///
+    /// if the coroutine is canceled while waiting, the shards that are
+    /// already locked are released before the cancel panic goes on
+    pub fn write(&self) -> LockResult<ShardedLockWriteGuard<T>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            guards.push(shard.write().unwrap_or_else(PoisonError::into_inner));
+        }
+        poison::map_result(self.poison.borrow(), |poison| ShardedLockWriteGuard {
+            lock: self,
+            poison,
+            _guards: guards,
+        })
+    }
+
+    pub fn try_write(&self) -> TryLockResult<ShardedLockWriteGuard<T>> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in self.shards.iter() {
+            match shard.try_write() {
+                Ok(g) => guards.push(g),
+                Err(TryLockError::Poisoned(e)) => guards.push(e.into_inner()),
+                Err(TryLockError::WouldBlock) => return Err(TryLockError::WouldBlock),
+            }
+        }
+        Ok(poison::map_result(self.poison.borrow(), |poison| {
+            ShardedLockWriteGuard {
+                lock: self,
+                poison,
+                _guards: guards,
+            }
+        })?)
+    }
+
+    /// the number of the reader shards, fixed when the lock is created
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[inline]
+    pub fn is_poisoned(&self) -> bool {
+        self.poison.get()
+    }
+
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let data = unsafe { &mut *self.data.get() };
+        poison::map_result(self.poison.borrow(), |_| data)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ShardedLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Ok(guard) => write!(f, "ShardedLock {{ data: {:?} }}", &*guard),
+            Err(_) => write!(f, "ShardedLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: Default> Default for ShardedLock<T> {
+    fn default() -> ShardedLock<T> {
+        ShardedLock::new(Default::default())
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for ShardedLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedLockReadGuard")
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
+
+impl<'a, T: fmt::Debug> fmt::Debug for ShardedLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedLockWriteGuard").finish()
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ShardedLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for ShardedLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for ShardedLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for ShardedLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        // the flag is set before the shards are released
+        self.lock.poison.done(&self.poison);
+    }
+}
+
+/// A counter with a cell for each worker
+///
+/// `add` only touches the cell of the current worker, `sum` adds up all the
+/// cells, so it's cheap to count from many coroutines and the sum is a bit
+/// behind the concurrent updates
+/// ```
+/// use mco::std::sync::ShardedCounter;
+/// use std::sync::Arc;
+///
+/// let served = Arc::new(ShardedCounter::new());
+/// let s = served.clone();
+/// mco::co!(move || s.add(1)).join().unwrap();
+/// served.add(2);
+/// assert_eq!(served.sum(), 3);
+/// ```
+pub struct ShardedCounter {
+    // one cell for each worker and the last one for the others
+    cells: Box<[CachePadded<AtomicU64>]>,
+}
+
+impl ShardedCounter {
+    pub fn new() -> Self {
+        ShardedCounter {
+            cells: (0..shard_count())
+                .map(|_| CachePadded::new(AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// add `n` to the cell of the current worker
+    #[inline]
+    pub fn add(&self, n: u64) {
+        self.cells[shard_index(self.cells.len())].fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// the sum of all the cells
+    pub fn sum(&self) -> u64 {
+        self.cells
+            .iter()
+            .fold(0u64, |s, c| s.wrapping_add(c.load(Ordering::Relaxed)))
+    }
+
+    /// return the sum and clear the cells
+    ///
+    /// the adds that race with it are counted either in the returned sum or
+    /// in the next one
+    pub fn reset(&self) -> u64 {
+        self.cells
+            .iter()
+            .fold(0u64, |s, c| s.wrapping_add(c.swap(0, Ordering::Relaxed)))
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        ShardedCounter::new()
+    }
+}
+
+impl fmt::Debug for ShardedCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedCounter")
+            .field("sum", &self.sum())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn read_write() {
+        let lock = Arc::new(ShardedLock::new(0usize));
+        assert_eq!(lock.shards(), config().get_max_workers() + 1);
+        let hs: Vec<_> = (0..16)
+            .map(|_| {
+                let lock = lock.clone();
+                co!(move || {
+                    for _ in 0..100 {
+                        *lock.write().unwrap() += 1;
+                        let v = *lock.read().unwrap();
+                        assert!(v > 0);
+                        crate::coroutine::yield_now();
+                    }
+                })
+            })
+            .collect();
+        for h in hs {
+            h.join().unwrap();
+        }
+        assert_eq!(*lock.read().unwrap(), 1600);
+    }
+
+    #[test]
+    fn try_lock() {
+        let lock = ShardedLock::new(1);
+        {
+            let _r = lock.read().unwrap();
+            assert!(lock.try_read().is_ok());
+            assert!(matches!(lock.try_write(), Err(TryLockError::WouldBlock)));
+        }
+        let w = lock.try_write().unwrap();
+        assert!(matches!(lock.try_read(), Err(TryLockError::WouldBlock)));
+        drop(w);
+        assert_eq!(lock.into_inner().unwrap(), 1);
+    }
+
+    #[test]
+    fn poison() {
+        let lock = Arc::new(ShardedLock::new(1));
+        let l = lock.clone();
+        let h = co!(move || {
+            let _w = l.write().unwrap();
+            panic!("poison");
+        });
+        assert!(h.join().is_err());
+        assert!(lock.is_poisoned());
+        assert!(lock.read().is_err());
+        // the shards are still usable
+        *lock.write().unwrap_err().into_inner() = 2;
+        assert_eq!(*lock.read().unwrap_err().into_inner(), 2);
+    }
+
+    #[test]
+    fn counter() {
+        let counter = Arc::new(ShardedCounter::new());
+        let hs: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                co!(move || {
+                    for _ in 0..1000 {
+                        counter.add(1);
+                    }
+                })
+            })
+            .collect();
+        counter.add(5);
+        for h in hs {
+            h.join().unwrap();
+        }
+        assert_eq!(counter.sum(), 8005);
+        assert_eq!(counter.reset(), 8005);
+        assert_eq!(counter.sum(), 0);
+    }
+}
@@ -0,0 +1,382 @@
+//! a bounded single producer single consumer ring
+//!
+//! `push` and `pop` are wait-free, they never lock or park and each of them
+//! only touches the index of the other side when the cached one says the ring
+//! is full or empty. the ring is for the latency critical handoff between two
+//! coroutines, e.g. the audio frames, use the channels for everything else.
+//! `push_blocking` and `pop_blocking` spin for a while and then park
+//! for example:
+//! ```
+//! use mco::std::sync::spsc;
+//!
+//! let (mut tx, mut rx) = spsc::ring(4);
+//! let h = mco::co!(move || {
+//!     for i in 0..100 {
+//!         tx.push_blocking(i).unwrap();
+//!     }
+//! });
+//! let sum: i32 = (0..100).map(|_| rx.pop_blocking().unwrap()).sum();
+//! assert_eq!(sum, 4950);
+//! h.join().unwrap();
+//! ```
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, SendError, TryRecvError, TrySendError};
+use std::sync::Arc;
+
+use crossbeam_utils::{Backoff, CachePadded};
+
+use super::{AtomicOption, Blocker};
+use crate::cancel::trigger_cancel_panic;
+use crate::park::ParkError;
+
+/// create a ring that holds at most `capacity` values
+///
+/// panics if `capacity` is 0
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "the capacity of the ring must be positive");
+    // the slots are a power of two so that the index is masked
+    let slots = capacity.next_power_of_two();
+    let buf = (0..slots)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+    let inner = Arc::new(Inner {
+        head: CachePadded::new(AtomicUsize::new(0)),
+        tail: CachePadded::new(AtomicUsize::new(0)),
+        buf,
+        mask: slots - 1,
+        cap: capacity,
+        closed: AtomicBool::new(false),
+        // the waiters are set only by the blocking adapters
+        rx_waiter: AtomicOption::none(),
+        tx_waiter: AtomicOption::none(),
+    });
+    let tx = Producer {
+        inner: inner.clone(),
+        tail: 0,
+        head: 0,
+    };
+    let rx = Consumer {
+        inner,
+        head: 0,
+        tail: 0,
+    };
+    (tx, rx)
+}
+
+struct Inner<T> {
+    // the next slot to pop, only written by the consumer
+    head: CachePadded<AtomicUsize>,
+    // the next slot to push, only written by the producer
+    tail: CachePadded<AtomicUsize>,
+    buf: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    cap: usize,
+    // one of the endpoints is dropped
+    closed: AtomicBool,
+    rx_waiter: AtomicOption<Arc<Blocker>>,
+    tx_waiter: AtomicOption<Arc<Blocker>>,
+}
+
+impl<T> Inner<T> {
+    #[inline]
+    fn wake(waiter: &AtomicOption<Arc<Blocker>>) {
+        // pairs with the fence after the waiter is registered
+        fence(Ordering::SeqCst);
+        if waiter.is_some() {
+            if let Some(w) = waiter.take() {
+                let _ = w.unpark();
+            }
+        }
+    }
+
+    // park till `ready` or the other side is dropped
+    fn park(&self, waiter: &AtomicOption<Arc<Blocker>>, ready: impl Fn() -> bool) {
+        let cur = Blocker::current();
+        waiter.swap(cur.clone());
+        fence(Ordering::SeqCst);
+        if ready() || self.closed.load(Ordering::Acquire) {
+            waiter.take();
+            return;
+        }
+        if cur.park(None) == Err(ParkError::Canceled) {
+            waiter.take();
+            trigger_cancel_panic();
+        }
+    }
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        for i in head..tail {
+            unsafe {
+                (*self.buf[i & self.mask].get())
+                    .as_mut_ptr()
+                    .drop_in_place()
+            };
+        }
+    }
+}
+
+/// the sending half of the ring
+pub struct Producer<T> {
+    inner: Arc<Inner<T>>,
+    // the local copy of the tail
+    tail: usize,
+    // the cached head, it's refreshed only when the ring looks full
+    head: usize,
+}
+
+unsafe impl<T: Send> Send for Producer<T> {}
+
+impl<T> Producer<T> {
+    /// push the value, return it in the error if the ring is full or the
+    /// consumer is dropped
+    #[inline]
+    pub fn push(&mut self, t: T) -> Result<(), TrySendError<T>> {
+        let inner = &*self.inner;
+        if self.tail - self.head == inner.cap {
+            self.head = inner.head.load(Ordering::Acquire);
+            if self.tail - self.head == inner.cap {
+                if inner.closed.load(Ordering::Acquire) {
+                    return Err(TrySendError::Disconnected(t));
+                }
+                return Err(TrySendError::Full(t));
+            }
+        }
+        unsafe {
+            (*inner.buf[self.tail & inner.mask].get())
+                .as_mut_ptr()
+                .write(t)
+        };
+        self.tail = self.tail.wrapping_add(1);
+        inner.tail.store(self.tail, Ordering::Release);
+        Inner::<T>::wake(&inner.rx_waiter);
+        Ok(())
+    }
+
+    /// push the value, it spins for a while and then parks if the ring is
+    /// full, return the value in the error if the consumer is dropped
+    pub fn push_blocking(&mut self, mut t: T) -> Result<(), SendError<T>> {
+        let backoff = Backoff::new();
+        loop {
+            match self.push(t) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(v)) => return Err(SendError(v)),
+                Err(TrySendError::Full(v)) => t = v,
+            }
+            if backoff.is_completed() {
+                let inner = &*self.inner;
+                let tail = self.tail;
+                inner.park(&inner.tx_waiter, || {
+                    tail - inner.head.load(Ordering::Acquire) < inner.cap
+                });
+            } else {
+                backoff.snooze();
+            }
+        }
+    }
+
+    /// the max number of the values in the ring
+    pub fn capacity(&self) -> usize {
+        self.inner.cap
+    }
+
+    /// return true if the consumer is dropped
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        Inner::<T>::wake(&self.inner.rx_waiter);
+    }
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Producer {{ .. }}")
+    }
+}
+
+/// the receiving half of the ring
+pub struct Consumer<T> {
+    inner: Arc<Inner<T>>,
+    // the local copy of the head
+    head: usize,
+    // the cached tail, it's refreshed only when the ring looks empty
+    tail: usize,
+}
+
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+impl<T> Consumer<T> {
+    /// pop a value, `Disconnected` is returned only when the ring is empty
+    /// and the producer is dropped
+    #[inline]
+    pub fn pop(&mut self) -> Result<T, TryRecvError> {
+        let inner = &*self.inner;
+        if self.head == self.tail {
+            self.tail = inner.tail.load(Ordering::Acquire);
+            if self.head == self.tail {
+                if !inner.closed.load(Ordering::Acquire) {
+                    return Err(TryRecvError::Empty);
+                }
+                // the values pushed before the producer is dropped
+                self.tail = inner.tail.load(Ordering::Acquire);
+                if self.head == self.tail {
+                    return Err(TryRecvError::Disconnected);
+                }
+            }
+        }
+        let t = unsafe { (*inner.buf[self.head & inner.mask].get()).as_ptr().read() };
+        self.head = self.head.wrapping_add(1);
+        inner.head.store(self.head, Ordering::Release);
+        Inner::<T>::wake(&inner.tx_waiter);
+        Ok(t)
+    }
+
+    /// pop a value, it spins for a while and then parks if the ring is empty,
+    /// return error if the the ring is empty and the producer is dropped
+    pub fn pop_blocking(&mut self) -> Result<T, RecvError> {
+        let backoff = Backoff::new();
+        loop {
+            match self.pop() {
+                Ok(t) => return Ok(t),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {}
+            }
+            if backoff.is_completed() {
+                let inner = &*self.inner;
+                let head = self.head;
+                inner.park(&inner.rx_waiter, || {
+                    inner.tail.load(Ordering::Acquire) != head
+                });
+            } else {
+                backoff.snooze();
+            }
+        }
+    }
+
+    /// the number of the values in the ring
+    pub fn len(&self) -> usize {
+        self.inner.tail.load(Ordering::Acquire) - self.head
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// return true if the producer is dropped, the values left in the ring
+    /// can still be popped
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+        Inner::<T>::wake(&self.inner.tx_waiter);
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Consumer {{ .. }}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use std::thread;
+
+    #[test]
+    fn push_pop() {
+        let (mut tx, mut rx) = ring(3);
+        assert_eq!(tx.capacity(), 3);
+        assert_eq!(rx.pop(), Err(TryRecvError::Empty));
+        for i in 0..3 {
+            tx.push(i).unwrap();
+        }
+        // the capacity is kept even though the slots are four
+        assert_eq!(tx.push(3), Err(TrySendError::Full(3)));
+        assert_eq!(rx.len(), 3);
+        assert_eq!(rx.pop(), Ok(0));
+        tx.push(3).unwrap();
+        drop(tx);
+        assert!(rx.is_closed());
+        assert_eq!((1..4).map(|_| rx.pop().unwrap()).sum::<i32>(), 6);
+        assert_eq!(rx.pop(), Err(TryRecvError::Disconnected));
+
+        let (mut tx, rx) = ring(1);
+        drop(rx);
+        assert_eq!(tx.push(1), Ok(()));
+        assert_eq!(tx.push(2), Err(TrySendError::Disconnected(2)));
+    }
+
+    #[test]
+    fn drop_left_values() {
+        let v = Rc::new(());
+        let (mut tx, mut rx) = ring(8);
+        for _ in 0..5 {
+            assert!(tx.push(v.clone()).is_ok());
+        }
+        drop(rx.pop());
+        drop(tx);
+        drop(rx);
+        assert_eq!(Rc::strong_count(&v), 1);
+    }
+
+    #[test]
+    fn blocking_across_threads() {
+        let (mut tx, mut rx) = ring(2);
+        let t = thread::spawn(move || {
+            for i in 0..100_000u64 {
+                tx.push_blocking(i).unwrap();
+            }
+        });
+        let mut sum = 0;
+        while let Ok(i) = rx.pop_blocking() {
+            sum += i;
+        }
+        t.join().unwrap();
+        assert_eq!(sum, 100_000 * 99_999 / 2);
+    }
+
+    #[test]
+    fn blocking_coroutines() {
+        let (mut tx, mut rx) = ring(16);
+        let p = co!(move || {
+            for i in 0..100_000u64 {
+                tx.push_blocking(i).unwrap();
+            }
+        });
+        let c = co!(move || {
+            let mut n = 0;
+            while rx.pop_blocking().is_ok() {
+                n += 1;
+            }
+            n
+        });
+        p.join().unwrap();
+        assert_eq!(c.join().unwrap(), 100_000);
+
+        // the blocked producer sees the consumer dropped
+        let (mut tx, rx) = ring(1);
+        tx.push(0).unwrap();
+        let p = co!(move || tx.push_blocking(1));
+        crate::coroutine::sleep(std::time::Duration::from_millis(10));
+        drop(rx);
+        assert_eq!(p.join().unwrap(), Err(SendError(1)));
+    }
+}
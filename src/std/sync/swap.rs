@@ -0,0 +1,431 @@
+//! the copy-on-write cells of the read-mostly shared state
+//!
+//! a `Swap` holds an `Arc<T>` that the readers load and the writers replace
+//! as a whole. a load is lock free, it pins the epoch of the thread and
+//! increments the count of the current value, so the readers never wait for
+//! each other nor for a writer. a replaced value is released when the
+//! readers that may still be loading it are unpinned, the memory is
+//! reclaimed by `crossbeam::epoch`. the pin never lasts across a park, so it
+//! works the same in the coroutines and in the threads
+//!
+//! a writer builds the new value out of the old one, `Swap::rcu` retries
+//! when another writer wins the race. a component that only needs a part
+//! of the value gets a `SwapMap` of the cell, it loads the current value
+//! and projects it
+//!
+//! ```
+//! use mco::std::sync::Swap;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! #[derive(Clone)]
+//! struct Config {
+//!     timeout: Duration,
+//!     upstreams: Vec<String>,
+//! }
+//!
+//! let config = Arc::new(Swap::new(Config {
+//!     timeout: Duration::from_secs(1),
+//!     upstreams: vec!["a".to_owned()],
+//! }));
+//! let timeout = config.map(|c: &Config| &c.timeout);
+//! let h = mco::co!(move || *timeout.load());
+//! config.rcu(|old| {
+//!     let mut new = Config::clone(old);
+//!     new.upstreams.push("b".to_owned());
+//!     new
+//! });
+//! assert_eq!(config.load().upstreams.len(), 2);
+//! assert_eq!(h.join().unwrap(), Duration::from_secs(1));
+//! ```
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Arc;
+
+use crossbeam::epoch::{self, Guard};
+
+/// a cell of an optional `Arc<T>` that is loaded and replaced as a whole,
+/// see the module docs
+pub struct SwapOption<T: Send + Sync + 'static> {
+    // from `Arc::into_raw`, null for `None`, the cell owns one count
+    ptr: AtomicPtr<T>,
+    _marker: PhantomData<Option<Arc<T>>>,
+}
+
+fn into_raw<T>(v: Option<Arc<T>>) -> *mut T {
+    v.map_or(ptr::null_mut(), |v| Arc::into_raw(v) as *mut T)
+}
+
+unsafe fn from_raw<T>(p: *mut T) -> Option<Arc<T>> {
+    if p.is_null() {
+        None
+    } else {
+        Some(Arc::from_raw(p))
+    }
+}
+
+impl<T: Send + Sync + 'static> SwapOption<T> {
+    /// create a cell of the value
+    pub fn new(v: Option<Arc<T>>) -> Self {
+        SwapOption {
+            ptr: AtomicPtr::new(into_raw(v)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// create an empty cell
+    pub fn empty() -> Self {
+        SwapOption::new(None)
+    }
+
+    /// the current value
+    pub fn load(&self) -> Option<Arc<T>> {
+        let _guard = epoch::pin();
+        let p = self.ptr.load(Ordering::Acquire);
+        if p.is_null() {
+            return None;
+        }
+        // the count of the cell is released only after we are unpinned
+        unsafe {
+            Arc::increment_strong_count(p);
+            Some(Arc::from_raw(p))
+        }
+    }
+
+    /// replace the value
+    pub fn store(&self, v: Option<Arc<T>>) {
+        drop(self.swap(v));
+    }
+
+    /// replace the value, return the old one
+    pub fn swap(&self, v: Option<Arc<T>>) -> Option<Arc<T>> {
+        let new = into_raw(v);
+        let guard = epoch::pin();
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+        unsafe { retire(old, &guard) }
+    }
+
+    /// take the value out and leave the cell empty
+    pub fn take(&self) -> Option<Arc<T>> {
+        self.swap(None)
+    }
+
+    /// replace the value with `new` if it's still `current`, compared by the
+    /// pointer. `new` is given back when it's not
+    pub fn compare_and_swap(
+        &self,
+        current: &Option<Arc<T>>,
+        new: Option<Arc<T>>,
+    ) -> Result<(), Option<Arc<T>>> {
+        let current = current
+            .as_ref()
+            .map_or(ptr::null_mut(), |c| Arc::as_ptr(c) as *mut T);
+        let new = into_raw(new);
+        let guard = epoch::pin();
+        // `current` is kept alive by the caller, so its address is not reused
+        match self
+            .ptr
+            .compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(old) => {
+                drop(unsafe { retire(old, &guard) });
+                Ok(())
+            }
+            Err(_) => Err(unsafe { from_raw(new) }),
+        }
+    }
+
+    /// replace the value with `f` of the current one, `f` is called again
+    /// when another writer replaces the value in the meantime. return the
+    /// value that is replaced
+    pub fn rcu<F, R>(&self, mut f: F) -> Option<Arc<T>>
+    where
+        F: FnMut(&Option<Arc<T>>) -> R,
+        R: Into<Option<Arc<T>>>,
+    {
+        loop {
+            let current = self.load();
+            if self.compare_and_swap(&current, f(&current).into()).is_ok() {
+                return current;
+            }
+        }
+    }
+}
+
+// give the replaced count of a cell to the caller, the count of the cell is
+// released when the readers that may have loaded the pointer are unpinned
+unsafe fn retire<T: Send + Sync + 'static>(old: *mut T, guard: &Guard) -> Option<Arc<T>> {
+    if old.is_null() {
+        return None;
+    }
+    Arc::increment_strong_count(old);
+    guard.defer_unchecked(move || drop(Arc::from_raw(old)));
+    Some(Arc::from_raw(old))
+}
+
+impl<T: Send + Sync + 'static> Drop for SwapOption<T> {
+    fn drop(&mut self) {
+        // no reader is left
+        drop(unsafe { from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+impl<T: Send + Sync + 'static> Default for SwapOption<T> {
+    fn default() -> Self {
+        SwapOption::empty()
+    }
+}
+
+impl<T: Send + Sync + 'static> From<Option<Arc<T>>> for SwapOption<T> {
+    fn from(v: Option<Arc<T>>) -> Self {
+        SwapOption::new(v)
+    }
+}
+
+impl<T: fmt::Debug + Send + Sync + 'static> fmt::Debug for SwapOption<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SwapOption").field(&self.load()).finish()
+    }
+}
+
+/// a cell of an `Arc<T>` that is loaded and replaced as a whole, see the
+/// module docs
+pub struct Swap<T: Send + Sync + 'static> {
+    // never empty
+    inner: SwapOption<T>,
+}
+
+impl<T: Send + Sync + 'static> Swap<T> {
+    /// create a cell of the value
+    pub fn new(v: T) -> Self {
+        Swap::from_arc(Arc::new(v))
+    }
+
+    /// create a cell of the shared value
+    pub fn from_arc(v: Arc<T>) -> Self {
+        Swap {
+            inner: SwapOption::new(Some(v)),
+        }
+    }
+
+    /// the current value
+    #[inline]
+    pub fn load(&self) -> Arc<T> {
+        self.inner.load().expect("the swap is never empty")
+    }
+
+    /// replace the value
+    pub fn store(&self, v: Arc<T>) {
+        self.inner.store(Some(v))
+    }
+
+    /// replace the value, return the old one
+    pub fn swap(&self, v: Arc<T>) -> Arc<T> {
+        self.inner.swap(Some(v)).expect("the swap is never empty")
+    }
+
+    /// replace the value with `f` of the current one, `f` is called again
+    /// when another writer replaces the value in the meantime. return the
+    /// value that is replaced
+    pub fn rcu<F, R>(&self, mut f: F) -> Arc<T>
+    where
+        F: FnMut(&Arc<T>) -> R,
+        R: Into<Arc<T>>,
+    {
+        loop {
+            let current = self.inner.load();
+            let new = f(current.as_ref().expect("the swap is never empty")).into();
+            if self.inner.compare_and_swap(&current, Some(new)).is_ok() {
+                return current.expect("the swap is never empty");
+            }
+        }
+    }
+
+    /// a projection of the cell to a part of its value, it loads the current
+    /// value on each `SwapMap::load`
+    pub fn map<U: ?Sized, F>(self: &Arc<Self>, f: F) -> SwapMap<T, U, F>
+    where
+        F: Fn(&T) -> &U,
+    {
+        SwapMap {
+            swap: self.clone(),
+            f,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Default + Send + Sync + 'static> Default for Swap<T> {
+    fn default() -> Self {
+        Swap::new(T::default())
+    }
+}
+
+impl<T: Send + Sync + 'static> From<Arc<T>> for Swap<T> {
+    fn from(v: Arc<T>) -> Self {
+        Swap::from_arc(v)
+    }
+}
+
+impl<T: fmt::Debug + Send + Sync + 'static> fmt::Debug for Swap<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Swap").field(&self.load()).finish()
+    }
+}
+
+/// a projection of a `Swap` to a part of its value, see `Swap::map`
+pub struct SwapMap<T: Send + Sync + 'static, U: ?Sized, F> {
+    swap: Arc<Swap<T>>,
+    f: F,
+    _marker: PhantomData<fn(&T) -> &U>,
+}
+
+impl<T, U, F> SwapMap<T, U, F>
+where
+    T: Send + Sync + 'static,
+    U: ?Sized,
+    F: Fn(&T) -> &U,
+{
+    /// the part of the current value
+    pub fn load(&self) -> Projected<T, U> {
+        let value = self.swap.load();
+        let part = (self.f)(&value) as *const U;
+        Projected { value, part }
+    }
+}
+
+impl<T: Send + Sync + 'static, U: ?Sized, F: Clone> Clone for SwapMap<T, U, F> {
+    fn clone(&self) -> Self {
+        SwapMap {
+            swap: self.swap.clone(),
+            f: self.f.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static, U: ?Sized, F> fmt::Debug for SwapMap<T, U, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SwapMap {{ .. }}")
+    }
+}
+
+/// a part of a loaded value, it keeps the whole value alive
+pub struct Projected<T, U: ?Sized> {
+    value: Arc<T>,
+    // points into `value`
+    part: *const U,
+}
+
+unsafe impl<T: Send + Sync, U: ?Sized + Sync> Send for Projected<T, U> {}
+unsafe impl<T: Send + Sync, U: ?Sized + Sync> Sync for Projected<T, U> {}
+
+impl<T, U: ?Sized> Projected<T, U> {
+    /// the whole value that the part is projected from
+    pub fn value(this: &Self) -> &Arc<T> {
+        &this.value
+    }
+}
+
+impl<T, U: ?Sized> Deref for Projected<T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { &*self.part }
+    }
+}
+
+impl<T, U: ?Sized + fmt::Debug> fmt::Debug for Projected<T, U> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        U::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    struct Counted(usize, Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn replaced_values_released() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let cell = SwapOption::new(Some(Arc::new(Counted(0, dropped.clone()))));
+        let first = cell.load().unwrap();
+        let old = cell
+            .swap(Some(Arc::new(Counted(1, dropped.clone()))))
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &old));
+        assert_eq!(cell.load().unwrap().0, 1);
+        assert!(cell.compare_and_swap(&Some(first), None).is_err());
+        drop(old);
+        let current = cell.load();
+        cell.compare_and_swap(&current, None).unwrap();
+        assert!(cell.load().is_none());
+        drop(current);
+        // the deferred counts are released by the later pins
+        for _ in 0..1000 {
+            if dropped.load(Ordering::SeqCst) == 2 {
+                break;
+            }
+            epoch::pin().flush();
+            std::thread::yield_now();
+        }
+        assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn rcu_race() {
+        let cell = Arc::new(Swap::new(0usize));
+        let hs: Vec<_> = (0..8)
+            .map(|_| {
+                let cell = cell.clone();
+                co!(move || {
+                    for _ in 0..1000 {
+                        cell.rcu(|n| **n + 1);
+                        crate::coroutine::yield_now();
+                    }
+                })
+            })
+            .collect();
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let cell = cell.clone();
+                std::thread::spawn(move || {
+                    let mut last = 0;
+                    while last < 8000 {
+                        let n = *cell.load();
+                        assert!(n >= last);
+                        last = n;
+                    }
+                })
+            })
+            .collect();
+        hs.into_iter().for_each(|h| h.join().unwrap());
+        readers.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(*cell.load(), 8000);
+    }
+
+    #[test]
+    fn map_projection() {
+        let cell = Arc::new(Swap::new((1, "one".to_owned())));
+        let name = cell.map(|v: &(i32, String)| v.1.as_str());
+        let before = name.load();
+        cell.store(Arc::new((2, "two".to_owned())));
+        // the old value lives as long as its projection
+        assert_eq!(&*before, "one");
+        assert_eq!(Projected::value(&before).0, 1);
+        assert_eq!(&*name.clone().load(), "two");
+    }
+}
@@ -1,8 +1,11 @@
 // Necessary for using `Mutex<usize>` for conditional variables
 #![allow(clippy::mutex_atomic)]
 
-use crate::std::sync::{Condvar, Mutex};
+use crate::std::sync::{Condvar, Mutex, SyncFlag};
 use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvError;
 use std::sync::Arc;
 
 /// Enables threads to synchronize the beginning or end of some computation.
@@ -56,7 +59,22 @@ use std::sync::Arc;
 /// wg.wait();
 /// ```
 ///
+/// # The counter
+///
+/// the count of a wait group is its references plus the units taken by
+/// [`add`] like the Go `WaitGroup`, which are given back by [`done`]. a
+/// `done` without a matching `add` would make the count of the units
+/// negative, it panics with the place that created the group
+///
+/// a reference that is still alive may `add` while others `wait`, e.g. a
+/// child that spawns its own children, the wait goes on until those units are
+/// done as well. a wait group can't be reused: its count reaches zero only
+/// when all the references are gone, an `add` after that is a bug that the
+/// debug builds assert
+///
 /// [`Barrier`]: std::sync::Barrier
+/// [`add`]: WaitGroup::add
+/// [`done`]: WaitGroup::done
 pub struct WaitGroup {
     inner: Arc<Inner>,
 }
@@ -64,15 +82,56 @@ pub struct WaitGroup {
 /// Inner state of a `WaitGroup`.
 struct Inner {
     cvar: Condvar,
+    // the references plus the units of `add`
     count: Mutex<usize>,
+    // the units of `add` that are not done, changed with the count locked
+    units: AtomicUsize,
+    // fired when the count reaches zero
+    done: SyncFlag,
+    created: &'static Location<'static>,
+}
+
+impl Inner {
+    // change the count by `delta`, the units by `units`
+    fn change(&self, delta: isize, units: isize) {
+        let mut count = self.count.lock().unwrap();
+        // panic without the lock, so that the group is not poisoned
+        let units = self.units.load(Ordering::Relaxed) as isize + units;
+        if units < 0 {
+            drop(count);
+            panic!(
+                "negative WaitGroup counter, the wait group is created at {}",
+                self.created
+            );
+        }
+        // the count is zero for good once the flag is fired
+        if cfg!(debug_assertions) && self.done.is_fired() {
+            drop(count);
+            panic!(
+                "WaitGroup is reused after its count reached zero, the wait group is created at {}",
+                self.created
+            );
+        }
+        self.units.store(units as usize, Ordering::Relaxed);
+        *count = (*count as isize + delta) as usize;
+
+        if *count == 0 {
+            let _ = self.cvar.notify_all();
+            self.done.fire();
+        }
+    }
 }
 
 impl Default for WaitGroup {
+    #[track_caller]
     fn default() -> Self {
         Self {
             inner: Arc::new(Inner {
                 cvar: Condvar::new(),
                 count: Mutex::new(1),
+                units: AtomicUsize::new(0),
+                done: SyncFlag::new(),
+                created: Location::caller(),
             }),
         }
     }
@@ -88,10 +147,53 @@ impl WaitGroup {
     ///
     /// let wg = WaitGroup::new();
     /// ```
+    #[track_caller]
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// add `delta` units to the count, a negative `delta` gives them back
+    ///
+    /// # Panics
+    ///
+    /// panics if more units are given back than are added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mco::std::sync::WaitGroup;
+    ///
+    /// let wg = WaitGroup::new();
+    /// wg.add(4);
+    /// for _ in 0..4 {
+    ///     let wg = wg.clone();
+    ///     mco::co!(move || wg.done());
+    /// }
+    /// // waits for the 4 units as well as the clones
+    /// wg.wait();
+    /// ```
+    pub fn add(&self, delta: isize) {
+        self.inner.change(delta, delta);
+    }
+
+    /// give back one unit of `add`
+    ///
+    /// # Panics
+    ///
+    /// panics if there is no unit to give back
+    pub fn done(&self) {
+        self.add(-1);
+    }
+
+    /// the references and the units of the group
+    ///
+    /// the count may change right after it's read, it's only for observation.
+    /// it goes down to zero in the end once the references are dropped and
+    /// the units are done
+    pub fn count(&self) -> usize {
+        *self.inner.count.lock().unwrap()
+    }
+
     /// Drops this reference and waits until all other references are dropped.
     ///
     /// # Examples
@@ -126,16 +228,95 @@ impl WaitGroup {
             count = inner.cvar.wait(count).unwrap();
         }
     }
+
+    /// Drops this reference and returns a receiver that is ready when all
+    /// other references are dropped, so it can be used in `select!`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mco::select;
+    /// use mco::std::sync::WaitGroup;
+    /// use mco::std::sync::channel::channel;
+    ///
+    /// let (tx, rx) = channel::<usize>();
+    /// let wg = WaitGroup::new();
+    /// for _ in 0..4 {
+    ///     let wg = wg.clone();
+    ///     mco::co!(move || drop(wg));
+    /// }
+    ///
+    /// let done = wg.done_receiver();
+    /// loop {
+    ///     let id = select! {
+    ///         Ok(v) = rx.recv() => println!("got {}", v),
+    ///         Ok(()) = done.recv() => println!("all done"),
+    ///     };
+    ///     if id == 1 {
+    ///         break;
+    ///     }
+    /// }
+    /// drop(tx);
+    /// ```
+    pub fn done_receiver(self) -> DoneReceiver {
+        DoneReceiver {
+            inner: self.inner.clone(),
+            seen: AtomicBool::new(false),
+        }
+    }
 }
 
-impl Drop for WaitGroup {
-    fn drop(&mut self) {
-        let mut count = self.inner.count.lock().unwrap();
-        *count -= 1;
+/// the receiver returned by [`WaitGroup::done_receiver`]
+///
+/// `recv` returns `Ok(())` once when the count of the wait group reaches zero,
+/// even if it's already zero, the later calls return `Err(RecvError)` like a
+/// disconnected channel. each clone sees the completion once
+///
+/// [`WaitGroup::done_receiver`]: ./struct.WaitGroup.html#method.done_receiver
+pub struct DoneReceiver {
+    inner: Arc<Inner>,
+    seen: AtomicBool,
+}
 
-        if *count == 0 {
-            let _ = self.inner.cvar.notify_all();
+impl DoneReceiver {
+    /// wait until the count of the wait group reaches zero
+    pub fn recv(&self) -> Result<(), RecvError> {
+        if self.seen.load(Ordering::Acquire) {
+            return Err(RecvError);
         }
+        self.inner.done.wait();
+        if self.seen.swap(true, Ordering::AcqRel) {
+            return Err(RecvError);
+        }
+        Ok(())
+    }
+
+    /// return true if the count of the wait group reached zero
+    pub fn is_done(&self) -> bool {
+        self.inner.done.is_fired()
+    }
+}
+
+impl Clone for DoneReceiver {
+    fn clone(&self) -> Self {
+        DoneReceiver {
+            inner: self.inner.clone(),
+            seen: AtomicBool::new(false),
+        }
+    }
+}
+
+impl fmt::Debug for DoneReceiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DoneReceiver")
+            .field("done", &self.is_done())
+            .finish()
+    }
+}
+
+impl Drop for WaitGroup {
+    fn drop(&mut self) {
+        self.inner.change(-1, 0);
     }
 }
 
@@ -156,3 +337,73 @@ impl fmt::Debug for WaitGroup {
         f.debug_struct("WaitGroup").field("count", count).finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::thread;
+
+    #[test]
+    fn add_and_done() {
+        let wg = WaitGroup::new();
+        assert_eq!(wg.count(), 1);
+        wg.add(3);
+        assert_eq!(wg.count(), 4);
+        let c = wg.clone();
+        assert_eq!(wg.count(), 5);
+        c.add(-2);
+        c.done();
+        drop(c);
+        assert_eq!(wg.count(), 1);
+        wg.wait();
+    }
+
+    #[test]
+    fn done_without_add() {
+        let wg = WaitGroup::new();
+        let line = line!() - 1;
+        let err = catch_unwind(AssertUnwindSafe(|| wg.done())).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("negative WaitGroup counter"), "{}", msg);
+        assert!(msg.contains(&format!("{}:{}", file!(), line)), "{}", msg);
+        // the failed call changes nothing
+        assert_eq!(wg.count(), 1);
+
+        wg.add(2);
+        let err = catch_unwind(AssertUnwindSafe(|| wg.add(-3))).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("negative WaitGroup counter"), "{}", msg);
+        assert_eq!(wg.count(), 3);
+        wg.add(-2);
+        wg.wait();
+    }
+
+    #[test]
+    fn wait_for_the_adds_of_the_children() {
+        for _ in 0..200 {
+            let wg = WaitGroup::new();
+            let finished = Arc::new(AtomicUsize::new(0));
+            for _ in 0..4 {
+                let wg = wg.clone();
+                let finished = finished.clone();
+                thread::spawn(move || {
+                    // each child adds units for its own children while the
+                    // parent may be in `wait` already
+                    wg.add(2);
+                    for _ in 0..2 {
+                        let wg = wg.clone();
+                        let finished = finished.clone();
+                        co!(move || {
+                            finished.fetch_add(1, Ordering::Relaxed);
+                            wg.done();
+                        });
+                    }
+                    finished.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+            wg.wait();
+            assert_eq!(finished.load(Ordering::Relaxed), 4 * 3);
+        }
+    }
+}
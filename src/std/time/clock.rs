@@ -0,0 +1,147 @@
+//! virtual time for the tests, needs the `test-util` feature
+//!
+//! `pause` freezes the clock that the timers run on, the coroutine `sleep`,
+//! `tick` and the `recv_timeout`s only expire when the clock is moved by
+//! `advance`, so a test of a 30 seconds timeout runs instantly:
+//! ```
+//! # #[macro_use] extern crate mco;
+//! use std::time::Duration;
+//! use mco::coroutine;
+//! use mco::std::time;
+//!
+//! let _paused = time::pause();
+//! let timers = time::pending_timers();
+//! let h = co!(|| coroutine::sleep(Duration::from_secs(30)));
+//! // the coroutine starts its sleep from the clock of when it gets to it
+//! while time::pending_timers() == timers {
+//!     std::thread::yield_now();
+//! }
+//! time::advance(Duration::from_secs(30));
+//! h.join().unwrap();
+//! ```
+//!
+//! a new coroutine runs on a worker later, `advance` doesn't wait for it. a
+//! timer that is registered after an `advance` only expires with the next one,
+//! so wait for `pending_timers` to go up before moving the clock
+//!
+//! the clock is process wide, the paused sections are serialized by `pause`.
+//! the tests that don't pause the clock would see the frozen time too, and
+//! the time that jumps with an `advance`. put the paused tests in a test
+//! binary of their own and serialize the ones that sleep on the real clock
+//! with them. only the coroutines follow the virtual clock, the timeouts in
+//! the thread context use the real time
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::scheduler::get_scheduler;
+use crate::timeout_list::real_now;
+
+// whether the clock is paused
+static PAUSED: AtomicBool = AtomicBool::new(false);
+// the virtual time in ns when paused
+static VIRTUAL: AtomicU64 = AtomicU64::new(0);
+// added to the real time after a resume, the clock never goes back
+static OFFSET: AtomicU64 = AtomicU64::new(0);
+// the id of the current paused section
+static GEN: AtomicUsize = AtomicUsize::new(0);
+
+// true when a section is paused, the waiters block on the condvar
+static OWNER: Mutex<bool> = parking_lot::const_mutex(false);
+static OWNER_CV: Condvar = Condvar::new();
+
+// the wall clock and the virtual time when `Time::now` started to follow
+static WALL: Mutex<Option<(time::OffsetDateTime, u64)>> = parking_lot::const_mutex(None);
+
+// the virtual time in ns, given the real time since the start
+#[inline]
+pub(crate) fn adjust(real: u64) -> u64 {
+    if PAUSED.load(Ordering::Acquire) {
+        VIRTUAL.load(Ordering::Acquire)
+    } else {
+        real + OFFSET.load(Ordering::Acquire)
+    }
+}
+
+// the wall clock that follows the virtual time, if enabled
+pub(crate) fn wall_now() -> Option<time::OffsetDateTime> {
+    let (base, at) = (*WALL.lock())?;
+    let elapsed = adjust(real_now()).saturating_sub(at);
+    Some(base + Duration::from_nanos(elapsed))
+}
+
+/// the guard of a paused section, the clock is resumed when it's dropped
+#[derive(Debug)]
+pub struct Paused {
+    gen: usize,
+}
+
+impl Paused {
+    /// let `Time::now` and `Time::now_utc` follow the virtual clock too,
+    /// they start from the wall clock of now and only move with `advance`
+    pub fn with_wall_clock(self) -> Self {
+        *WALL.lock() = Some((time::OffsetDateTime::now_utc(), adjust(real_now())));
+        self
+    }
+}
+
+impl Drop for Paused {
+    fn drop(&mut self) {
+        // the section may already be resumed by `resume`
+        if GEN.load(Ordering::Acquire) == self.gen {
+            resume();
+        }
+    }
+}
+
+/// freeze the clock, block if another section is paused
+///
+/// the clock is resumed when the returned guard is dropped
+pub fn pause() -> Paused {
+    let mut paused = OWNER.lock();
+    while *paused {
+        OWNER_CV.wait(&mut paused);
+    }
+    *paused = true;
+    VIRTUAL.store(adjust(real_now()), Ordering::Release);
+    PAUSED.store(true, Ordering::Release);
+    let gen = GEN.fetch_add(1, Ordering::AcqRel) + 1;
+    Paused { gen }
+}
+
+/// move the paused clock forward, the timers that are due fire
+///
+/// it returns after the timer thread has seen the new time
+/// panic if the clock is not paused
+pub fn advance(dur: Duration) {
+    assert!(PAUSED.load(Ordering::Acquire), "advance on a running clock");
+    let ns = dur.as_nanos().min(u64::MAX as u128) as u64;
+    VIRTUAL.fetch_add(ns, Ordering::AcqRel);
+    get_scheduler().run_timers();
+}
+
+/// the timers that are waiting for the clock, e.g. of a sleeping coroutine
+///
+/// they are the timers of the coroutine sleeps, parks and timeouts. a timer
+/// that is canceled may still be counted for a moment
+pub fn pending_timers() -> usize {
+    get_scheduler().pending_timers()
+}
+
+/// resume the clock from the virtual time, do nothing if it's not paused
+pub fn resume() {
+    let mut paused = OWNER.lock();
+    if !*paused {
+        return;
+    }
+    let now = VIRTUAL.load(Ordering::Acquire);
+    let offset = now.saturating_sub(real_now());
+    OFFSET.fetch_max(offset, Ordering::AcqRel);
+    PAUSED.store(false, Ordering::Release);
+    GEN.fetch_add(1, Ordering::AcqRel);
+    WALL.lock().take();
+    *paused = false;
+    OWNER_CV.notify_one();
+}
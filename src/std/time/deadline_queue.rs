@@ -0,0 +1,596 @@
+//! the deadlines of many keys in one timing wheel, see `DeadlineQueue`
+//!
+//! a server with an idle timeout per connection would otherwise have a timer
+//! per connection, and each request of the connection cancels its timer and
+//! registers a new one. the queue keeps all of them in one hierarchical
+//! wheel of 1ms ticks and one waiter that sleeps until the earliest slot, so
+//! the connections only write their new deadline and one sweeper closes what
+//! is due
+//!
+//! a deadline that is put off is not moved in the wheel, only the deadline of
+//! the key is changed. the old entry is checked when its slot is reached and
+//! is placed again for the new deadline, so the keys that are active are
+//! placed about once per timeout, no matter how often they are put off
+//!
+//! ```
+//! use mco::std::time::DeadlineQueue;
+//! use std::time::{Duration, Instant};
+//!
+//! let idle = DeadlineQueue::new();
+//! let now = Instant::now();
+//! idle.insert("a", now + Duration::from_secs(30));
+//! idle.insert("b", now + Duration::from_secs(30));
+//! // "a" has a request
+//! idle.update(&"a", now + Duration::from_secs(60));
+//! assert_eq!(idle.expired(now + Duration::from_secs(31)), vec!["b"]);
+//! assert_eq!(idle.expired(now + Duration::from_secs(61)), vec!["a"]);
+//! assert!(idle.is_empty());
+//! ```
+//!
+//! the due keys are also a source of a select, see
+//! `DeadlineQueue::wait_expired`
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::select::{Selectable, Waker, WakerList};
+use crate::std::sync::Semphore;
+use crate::thread_names;
+use crate::timeout_list::now_instant;
+
+// the nanoseconds of a tick of the wheel
+const TICK_NANOS: u128 = 1_000_000;
+// the slots of a level, and the levels
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 6;
+// the ticks of the top level, a later deadline is placed at the end of it
+// and placed again when that's reached
+const WHEEL_MASK: u64 = (1 << (SLOT_BITS * LEVELS)) - 1;
+
+struct Level<K> {
+    // a bit for each slot that has any entry
+    occupied: u64,
+    slots: Vec<Vec<(K, u64)>>,
+}
+
+// the entries of the wheel as (key, tick), some of them are stale
+struct Levels<K> {
+    levels: Vec<Level<K>>,
+    // the entries that are due at the current tick
+    due: Vec<(K, u64)>,
+}
+
+impl<K> Levels<K> {
+    fn new() -> Self {
+        let levels = (0..LEVELS)
+            .map(|_| Level {
+                occupied: 0,
+                slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            })
+            .collect();
+        Levels {
+            levels,
+            due: Vec::new(),
+        }
+    }
+
+    // place the entry for the tick, return the tick where it's placed
+    fn place(&mut self, elapsed: u64, key: K, tick: u64) -> u64 {
+        let tick = tick.min(elapsed | WHEEL_MASK);
+        if tick <= elapsed {
+            self.due.push((key, tick));
+            return tick;
+        }
+        // the level of the highest bit that differs from the current tick
+        let significant = 63 - ((elapsed ^ tick) | (SLOTS as u64 - 1)).leading_zeros() as usize;
+        let level = significant / SLOT_BITS;
+        let slot = ((tick >> (level * SLOT_BITS)) as usize) & (SLOTS - 1);
+        let l = &mut self.levels[level];
+        l.slots[slot].push((key, tick));
+        l.occupied |= 1 << slot;
+        tick
+    }
+
+    // the next slot to be reached as (level, slot, start tick), the lower
+    // levels are all reached before the higher ones
+    fn next_slot(&self, elapsed: u64) -> Option<(usize, usize, u64)> {
+        for (level, l) in self.levels.iter().enumerate() {
+            let shift = level * SLOT_BITS;
+            let now = ((elapsed >> shift) as usize) & (SLOTS - 1);
+            let occupied = l.occupied & (!0u64 << now);
+            if occupied != 0 {
+                let slot = occupied.trailing_zeros() as usize;
+                let level_start = elapsed & !((1u64 << (shift + SLOT_BITS)) - 1);
+                return Some((level, slot, level_start + ((slot as u64) << shift)));
+            }
+        }
+        None
+    }
+
+    fn take(&mut self, level: usize, slot: usize) -> Vec<(K, u64)> {
+        let l = &mut self.levels[level];
+        l.occupied &= !(1 << slot);
+        std::mem::take(&mut l.slots[slot])
+    }
+}
+
+struct Deadline {
+    at: Instant,
+    tick: u64,
+    // the tick of the live entry of the key, the others are stale
+    scheduled: u64,
+}
+
+struct Wheel<K> {
+    start: Instant,
+    // the ticks up to this one are reached
+    elapsed: u64,
+    levels: Levels<K>,
+    keys: HashMap<K, Deadline>,
+    // the tick that the waiter of `wait_next` or `wait_expired` wakes at,
+    // `None` if nobody waits
+    wake: Option<u64>,
+}
+
+impl<K: Hash + Eq + Clone> Wheel<K> {
+    // the first tick that is not before the instant
+    fn tick_of(&self, at: Instant) -> u64 {
+        let nanos = at.saturating_duration_since(self.start).as_nanos();
+        ((nanos + TICK_NANOS - 1) / TICK_NANOS).min(u64::MAX as u128) as u64
+    }
+
+    // the last tick that is not after the instant
+    fn now_tick(&self, now: Instant) -> u64 {
+        let nanos = now.saturating_duration_since(self.start).as_nanos();
+        (nanos / TICK_NANOS).min(u64::MAX as u128) as u64
+    }
+
+    fn instant_of(&self, tick: u64) -> Instant {
+        self.start + Duration::from_millis(tick)
+    }
+
+    // set the deadline of the key, true if the waiter must wake earlier
+    fn set(&mut self, key: K, at: Instant) -> (Option<Instant>, bool) {
+        let tick = self.tick_of(at);
+        let old = match self.keys.get_mut(&key) {
+            Some(d) => {
+                let old = std::mem::replace(&mut d.at, at);
+                d.tick = tick;
+                // a later deadline keeps its entry, it's placed again when
+                // the entry is reached
+                if tick >= d.scheduled {
+                    return (Some(old), false);
+                }
+                Some(old)
+            }
+            None => None,
+        };
+        let placed = self.levels.place(self.elapsed, key.clone(), tick);
+        let d = self.keys.entry(key).or_insert(Deadline {
+            at,
+            tick,
+            scheduled: placed,
+        });
+        d.scheduled = placed;
+        let wake = match self.wake {
+            Some(wake) if placed < wake => {
+                self.wake = Some(placed);
+                true
+            }
+            _ => false,
+        };
+        (old, wake)
+    }
+
+    // the entry of the key is reached
+    fn fire(&mut self, key: K, tick: u64, now_tick: u64, expired: &mut Vec<K>) {
+        let d = match self.keys.get_mut(&key) {
+            Some(d) if d.scheduled == tick => d,
+            _ => return,
+        };
+        if d.tick <= now_tick {
+            self.keys.remove(&key);
+            expired.push(key);
+        } else {
+            // it's put off or beyond the wheel
+            let tick = d.tick;
+            d.scheduled = self.levels.place(self.elapsed, key, tick);
+        }
+    }
+
+    // reach the ticks up to `now_tick` and collect the keys that are due
+    fn advance(&mut self, now_tick: u64, expired: &mut Vec<K>) {
+        for (key, tick) in std::mem::take(&mut self.levels.due) {
+            self.fire(key, tick, now_tick, expired);
+        }
+        while let Some((level, slot, start)) = self.levels.next_slot(self.elapsed) {
+            if start > now_tick {
+                break;
+            }
+            self.elapsed = self.elapsed.max(start);
+            for (key, tick) in self.levels.take(level, slot) {
+                self.fire(key, tick, now_tick, expired);
+            }
+        }
+        self.elapsed = self.elapsed.max(now_tick);
+    }
+
+    // the earliest tick that may have a due key
+    fn next_tick(&self) -> Option<u64> {
+        if !self.levels.due.is_empty() {
+            return Some(self.elapsed);
+        }
+        self.levels
+            .next_slot(self.elapsed)
+            .map(|(_, _, start)| start)
+    }
+}
+
+/// the deadlines of a set of keys, in a timing wheel with one waiter
+///
+/// the deadlines are rounded up to the next 1ms, so a key is due up to 1ms
+/// after its deadline. `insert`, `update` and `remove` are cheap, most of
+/// them don't touch the wheel at all. the queue is shared by reference, the
+/// keys are changed by any thread or coroutine while one sweeper waits in
+/// `wait_next`
+pub struct DeadlineQueue<K> {
+    wheel: Mutex<Wheel<K>>,
+    // wakes the waiter for an earlier deadline
+    waker: Semphore,
+    // the selects of `wait_expired`, woken for an earlier deadline too
+    wakers: WakerList,
+}
+
+impl<K: Hash + Eq + Clone> DeadlineQueue<K> {
+    /// create an empty queue
+    pub fn new() -> Self {
+        DeadlineQueue {
+            wheel: Mutex::new(Wheel {
+                start: now_instant(),
+                elapsed: 0,
+                levels: Levels::new(),
+                keys: HashMap::new(),
+                wake: None,
+            }),
+            waker: Semphore::new(0),
+            wakers: WakerList::new(),
+        }
+    }
+
+    /// set the deadline of the key, return the old one if the key is in the
+    /// queue. a deadline in the past is due at the next check
+    pub fn insert(&self, key: K, deadline: Instant) -> Option<Instant> {
+        let (old, wake) = self.wheel.lock().set(key, deadline);
+        if wake {
+            self.wake();
+        }
+        old
+    }
+
+    /// change the deadline of a key that is in the queue, false if it's not.
+    /// a later deadline only changes the key, so it's the cheap one
+    pub fn update(&self, key: &K, deadline: Instant) -> bool {
+        let mut wheel = self.wheel.lock();
+        if !wheel.keys.contains_key(key) {
+            return false;
+        }
+        let (_, wake) = wheel.set(key.clone(), deadline);
+        drop(wheel);
+        if wake {
+            self.wake();
+        }
+        true
+    }
+
+    // wake the waiter for an earlier deadline
+    fn wake(&self) {
+        self.waker.post();
+        self.wakers.wake_all();
+    }
+
+    /// remove the key, return its deadline. its entry in the wheel is left
+    /// stale and dropped when it's reached
+    pub fn remove(&self, key: &K) -> Option<Instant> {
+        self.wheel.lock().keys.remove(key).map(|d| d.at)
+    }
+
+    /// the deadline of the key
+    pub fn deadline(&self, key: &K) -> Option<Instant> {
+        self.wheel.lock().keys.get(key).map(|d| d.at)
+    }
+
+    /// the number of the keys
+    pub fn len(&self) -> usize {
+        self.wheel.lock().keys.len()
+    }
+
+    /// there is no key
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// remove and return the keys that are due at `now`, in the order of
+    /// their deadlines by the tick
+    pub fn expired(&self, now: Instant) -> Vec<K> {
+        let mut wheel = self.wheel.lock();
+        let now_tick = wheel.now_tick(now);
+        let mut expired = Vec::new();
+        wheel.advance(now_tick, &mut expired);
+        expired
+    }
+
+    /// park until any key is due, then remove and return the due keys as
+    /// `expired` does
+    ///
+    /// it sleeps with one timer until the earliest slot of the wheel, or
+    /// until an earlier deadline is inserted. a slot of the keys that are
+    /// all put off or removed only places them again and goes on sleeping.
+    /// the queue is meant for one waiter at a time, the others may wait for
+    /// the next due keys
+    pub fn wait_next(&self) -> Vec<K> {
+        loop {
+            let timeout = {
+                let mut wheel = self.wheel.lock();
+                let now = now_instant();
+                let now_tick = wheel.now_tick(now);
+                let mut expired = Vec::new();
+                wheel.advance(now_tick, &mut expired);
+                if !expired.is_empty() {
+                    wheel.wake = None;
+                    return expired;
+                }
+                let next = wheel.next_tick();
+                wheel.wake = Some(next.unwrap_or(u64::MAX));
+                next.map(|t| wheel.instant_of(t).saturating_duration_since(now))
+            };
+            match timeout {
+                Some(d) => {
+                    self.waker.wait_timeout(d);
+                }
+                None => self.waker.wait(),
+            }
+        }
+    }
+
+    /// the due keys as a source of `SelectSet` and the `select!` arms, it's
+    /// ready with all the keys that are due when it's tried, as `expired`
+    ///
+    /// the keys that are due together are one result, so a burst of them is
+    /// one run of the arm. the select parks with its one timer until the
+    /// earliest slot of the wheel, an earlier deadline that is inserted wakes
+    /// it. it's the one waiter of the queue like `wait_next`
+    ///
+    /// ```
+    /// use mco::select::SelectSet;
+    /// use mco::std::time::DeadlineQueue;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let idle = DeadlineQueue::new();
+    /// let at = Instant::now() + Duration::from_millis(10);
+    /// for conn in 0..1000 {
+    ///     idle.insert(conn, at);
+    /// }
+    /// let (_tx, control) = mco::chan!();
+    /// let mut set = SelectSet::new();
+    /// set.add(idle.wait_expired(), |keys| keys.len());
+    /// set.add(&control, |_: Result<(), _>| 0);
+    /// assert_eq!(set.select(), 1000);
+    /// ```
+    pub fn wait_expired(&self) -> WaitExpired<'_, K> {
+        WaitExpired(self)
+    }
+}
+
+/// the due keys of a `DeadlineQueue` as a source of a select, see
+/// `DeadlineQueue::wait_expired`
+pub struct WaitExpired<'a, K>(&'a DeadlineQueue<K>);
+
+impl<'a, K: Hash + Eq + Clone> Selectable for WaitExpired<'a, K> {
+    type Output = Vec<K>;
+
+    fn try_select(&mut self) -> Option<Vec<K>> {
+        let mut wheel = self.0.wheel.lock();
+        let now_tick = wheel.now_tick(now_instant());
+        let mut expired = Vec::new();
+        wheel.advance(now_tick, &mut expired);
+        if expired.is_empty() {
+            return None;
+        }
+        wheel.wake = None;
+        Some(expired)
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut wheel = self.0.wheel.lock();
+        // an entry placed before the slot that the select parks for wakes it
+        let next = wheel.next_tick().unwrap_or(u64::MAX);
+        wheel.wake = Some(wheel.wake.map_or(next, |w| w.min(next)));
+        self.0.wakers.register(waker);
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        self.0.wakers.deregister(waker);
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let wheel = self.0.wheel.lock();
+        wheel.next_tick().map(|t| wheel.instant_of(t))
+    }
+}
+
+impl<'a, K> fmt::Debug for WaitExpired<'a, K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("WaitExpired").field(self.0).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone> Default for DeadlineQueue<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K> fmt::Debug for DeadlineQueue<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let wheel = self.wheel.lock();
+        f.debug_struct("DeadlineQueue")
+            .field("len", &wheel.keys.len())
+            .field("elapsed", &wheel.elapsed)
+            .finish()
+    }
+}
+
+/// a deadline of the crate, e.g. of a context or an idle stream, it's fired
+/// by the one `mco-deadline` thread of all of them
+pub(crate) trait Due: Send + Sync {
+    /// the deadline is reached, it may be put off by `due_at` again
+    fn due(self: Arc<Self>);
+}
+
+/// the key of a `Due` in the queue, by the address of the `Arc`. the queue
+/// only keeps it weakly, a dropped one is skipped when it fires
+#[derive(Clone)]
+pub(crate) struct DueKey {
+    addr: usize,
+    due: Weak<dyn Due>,
+}
+
+impl DueKey {
+    pub fn new<D: Due + 'static>(d: &Arc<D>) -> Self {
+        let due: Weak<dyn Due> = Arc::downgrade(d);
+        DueKey {
+            addr: Arc::as_ptr(d) as usize,
+            due,
+        }
+    }
+}
+
+// the weak keeps the allocation, so the address is not reused by another one
+impl PartialEq for DueKey {
+    fn eq(&self, other: &DueKey) -> bool {
+        self.addr == other.addr
+    }
+}
+
+impl Eq for DueKey {}
+
+impl Hash for DueKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.addr.hash(state)
+    }
+}
+
+static DUE: Lazy<DeadlineQueue<DueKey>> = Lazy::new(|| {
+    thread::Builder::new()
+        .name(thread_names::name("deadline"))
+        .spawn(|| loop {
+            for key in DUE.wait_next() {
+                if let Some(due) = key.due.upgrade() {
+                    due.due();
+                }
+            }
+        })
+        .expect("failed to spawn the deadline thread");
+    DeadlineQueue::new()
+});
+
+/// fire the key at the deadline, it replaces the old deadline of the key
+pub(crate) fn due_at(key: DueKey, deadline: Instant) {
+    DUE.insert(key, deadline);
+}
+
+/// the key is not fired anymore
+pub(crate) fn due_cancel(key: &DueKey) {
+    DUE.remove(key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn postpone_and_cascade() {
+        let q = DeadlineQueue::new();
+        let base = now_instant();
+        for i in 0..100u64 {
+            q.insert(i, base + ms(10 + i));
+        }
+        // far ones go through the higher levels
+        q.insert(1000, base + Duration::from_secs(3600));
+        q.insert(1001, base + Duration::from_secs(5));
+        for i in (0..100).step_by(2) {
+            assert!(q.update(&i, base + ms(500 + i)));
+        }
+        assert!(q.remove(&1).is_some());
+        assert!(!q.update(&1, base));
+        assert_eq!(q.expired(base + ms(5)), Vec::<u64>::new());
+        let odd: Vec<u64> = (3..100).step_by(2).collect();
+        assert_eq!(q.expired(base + ms(200)), odd);
+        let even: Vec<u64> = (0..100).step_by(2).collect();
+        assert_eq!(q.expired(base + ms(700)), even);
+        // an earlier deadline is placed again
+        q.update(&1000, base + ms(800));
+        assert_eq!(q.expired(base + ms(900)), vec![1000]);
+        assert_eq!(q.expired(base + Duration::from_secs(4)), Vec::<u64>::new());
+        assert_eq!(q.expired(base + Duration::from_secs(6)), vec![1001]);
+        assert!(q.is_empty());
+        // in the past
+        q.insert(7, base);
+        assert_eq!(q.expired(base + Duration::from_secs(6)), vec![7]);
+    }
+
+    #[test]
+    fn wait_next_wakes_early() {
+        let q = Arc::new(DeadlineQueue::new());
+        q.insert("late", now_instant() + Duration::from_secs(60));
+        let waiter = q.clone();
+        let h = co!(move || waiter.wait_next());
+        crate::coroutine::sleep(ms(20));
+        // the waiter sleeps for the late one, this wakes it
+        q.insert("soon", now_instant() + ms(10));
+        q.update(&"late", now_instant() + Duration::from_secs(120));
+        assert_eq!(h.join().unwrap(), vec!["soon"]);
+        assert_eq!(q.len(), 1);
+    }
+
+    #[test]
+    fn wait_expired_in_select() {
+        use crate::select::SelectSet;
+
+        let q = Arc::new(DeadlineQueue::new());
+        let at = now_instant() + ms(20);
+        for i in 0..10_000u32 {
+            q.insert(i, at);
+        }
+        let (tx, rx) = crate::std::sync::channel::<u32>();
+        let mut set = SelectSet::new();
+        set.add(q.wait_expired(), |keys| keys.len());
+        set.add(&rx, |v| v.unwrap() as usize);
+        // the burst is one result
+        assert_eq!(set.select(), 10_000);
+        assert!(q.is_empty());
+        tx.send(3).unwrap();
+        assert_eq!(set.select(), 3);
+
+        // an earlier key wakes the parked select
+        q.insert(2, now_instant() + Duration::from_secs(60));
+        let waiter = q.clone();
+        let h = co!(move || waiter.wait_expired().wait());
+        crate::coroutine::sleep(ms(20));
+        q.insert(3, now_instant() + ms(10));
+        assert_eq!(h.join().unwrap(), vec![3]);
+        assert_eq!(q.len(), 1);
+    }
+}
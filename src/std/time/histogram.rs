@@ -0,0 +1,213 @@
+use crate::config::config;
+use crate::scheduler::worker_id;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// bucket i holds the durations in [2^i, 2^(i+1)) nanoseconds
+const BUCKETS: usize = 64;
+
+#[repr(align(128))]
+struct Shard {
+    buckets: [AtomicU64; BUCKETS],
+    sum: AtomicU64,
+}
+
+impl Shard {
+    fn new() -> Self {
+        Shard {
+            buckets: [(); BUCKETS].map(|_| AtomicU64::new(0)),
+            sum: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A Histogram records durations into fixed exponential buckets
+///
+/// the counters are sharded by worker thread, so it's cheap to record from
+/// many coroutines. the shards are merged on read, the readers can take
+/// percentiles while the writers continue recording
+/// for example:
+/// ```
+///         use mco::std::time::{Histogram, Stopwatch};
+///
+///         let h = Histogram::new();
+///         let sw = Stopwatch::start();
+///         // handle the request
+///         h.record(sw.elapsed());
+///         println!("p99 {:?}", h.percentile(99.0));
+/// ```
+pub struct Histogram {
+    // one shard for each worker and the last one for the other threads
+    shards: Box<[Shard]>,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        let shards = (0..config().get_workers() + 1)
+            .map(|_| Shard::new())
+            .collect();
+        Self { shards }
+    }
+
+    #[inline]
+    fn shard(&self) -> &Shard {
+        let id = worker_id();
+        let last = self.shards.len() - 1;
+        &self.shards[if id < last { id } else { last }]
+    }
+
+    #[inline]
+    fn bucket(d: Duration) -> usize {
+        let nanos = d.as_nanos().min(u64::MAX as u128) as u64;
+        (63 - (nanos | 1).leading_zeros()) as usize
+    }
+
+    /// record a duration
+    #[inline]
+    pub fn record(&self, d: Duration) {
+        let shard = self.shard();
+        shard.buckets[Self::bucket(d)].fetch_add(1, Ordering::Relaxed);
+        let nanos = d.as_nanos().min(u64::MAX as u128) as u64;
+        shard.sum.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// the merged bucket counts
+    pub fn buckets(&self) -> [u64; BUCKETS] {
+        let mut ret = [0u64; BUCKETS];
+        for shard in self.shards.iter() {
+            for (r, b) in ret.iter_mut().zip(shard.buckets.iter()) {
+                *r += b.load(Ordering::Relaxed);
+            }
+        }
+        ret
+    }
+
+    /// the number of recorded durations
+    pub fn count(&self) -> u64 {
+        self.buckets().iter().sum()
+    }
+
+    /// the mean of the recorded durations
+    pub fn mean(&self) -> Duration {
+        let count = self.count();
+        if count == 0 {
+            return Duration::from_nanos(0);
+        }
+        let sum: u64 = self
+            .shards
+            .iter()
+            .map(|s| s.sum.load(Ordering::Relaxed))
+            .sum();
+        Duration::from_nanos(sum / count)
+    }
+
+    /// the percentile of the recorded durations, `p` is in [0, 100]
+    /// return the upper bound of the bucket that holds the percentile
+    pub fn percentile(&self, p: f64) -> Duration {
+        let buckets = self.buckets();
+        let count: u64 = buckets.iter().sum();
+        if count == 0 {
+            return Duration::from_nanos(0);
+        }
+        let p = p.max(0.0).min(100.0);
+        let rank = ((p / 100.0) * count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, b) in buckets.iter().enumerate() {
+            seen += b;
+            if seen >= rank {
+                let upper = if i == BUCKETS - 1 {
+                    u64::MAX
+                } else {
+                    (1u64 << (i + 1)) - 1
+                };
+                return Duration::from_nanos(upper);
+            }
+        }
+        Duration::from_nanos(u64::MAX)
+    }
+
+    /// add the records of the other histogram into this one
+    pub fn merge(&self, other: &Histogram) {
+        let shard = self.shard();
+        for (b, o) in shard.buckets.iter().zip(other.buckets().iter()) {
+            b.fetch_add(*o, Ordering::Relaxed);
+        }
+        let sum: u64 = other
+            .shards
+            .iter()
+            .map(|s| s.sum.load(Ordering::Relaxed))
+            .sum();
+        shard.sum.fetch_add(sum, Ordering::Relaxed);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Histogram {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Histogram")
+            .field("count", &self.count())
+            .field("mean", &self.mean())
+            .field("p50", &self.percentile(50.0))
+            .field("p99", &self.percentile(99.0))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::std::time::Histogram;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_percentile() {
+        let h = Histogram::new();
+        assert_eq!(h.percentile(50.0), Duration::from_nanos(0));
+        for i in 1..=100 {
+            h.record(Duration::from_millis(i));
+        }
+        assert_eq!(h.count(), 100);
+        let p50 = h.percentile(50.0);
+        assert!(p50 >= Duration::from_millis(50) && p50 < Duration::from_millis(100));
+        let p100 = h.percentile(100.0);
+        assert!(p100 >= Duration::from_millis(100) && p100 < Duration::from_millis(200));
+        assert!(h.mean() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_merge() {
+        let h1 = Histogram::new();
+        let h2 = Histogram::new();
+        h1.record(Duration::from_micros(1));
+        h2.record(Duration::from_micros(2));
+        h2.record(Duration::from_micros(3));
+        h1.merge(&h2);
+        assert_eq!(h1.count(), 3);
+        assert_eq!(h2.count(), 2);
+    }
+
+    #[test]
+    fn test_record_in_coroutines() {
+        let h = Arc::new(Histogram::new());
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let h = h.clone();
+                co!(move || {
+                    for i in 0..100 {
+                        h.record(Duration::from_micros(i));
+                    }
+                })
+            })
+            .collect();
+        for j in handles {
+            j.join().unwrap();
+        }
+        assert_eq!(h.count(), 1000);
+    }
+}
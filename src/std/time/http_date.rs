@@ -0,0 +1,410 @@
+//! the HTTP `Date` header, RFC 7231 section 7.1.1.1
+//!
+//! `Time::to_http_date` writes the IMF-fixdate and `Time::parse_http_date`
+//! reads it and the obsolete RFC 850 and asctime forms. `CachedHttpDate`
+//! keeps the header of the current second, so that a server doesn't format
+//! it for each response
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{fence, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::coroutine::{sleep, Builder, Coroutine};
+use crate::std::errors::Result;
+use crate::std::time::format::{LONG_DAY_NAMES, SHORT_DAY_NAMES, SHORT_MONTH_NAMES};
+use crate::std::time::time::Time;
+use time::{PrimitiveDateTime, UtcOffset};
+
+/// the length of an IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`
+pub const HTTP_DATE_LEN: usize = 29;
+
+impl Time {
+    /// the IMF-fixdate of the time, always in GMT, like
+    /// `Sun, 06 Nov 1994 08:49:37 GMT`. the year is clamped to 0000-9999
+    ///
+    /// ```
+    /// use mco::std::time::{Time, UtcOffset};
+    ///
+    /// let offset = UtcOffset::from_hms(8, 0, 0).unwrap();
+    /// let t = Time::from_date(1994, 11, 6, 16, 49, 37, 0, offset);
+    /// assert_eq!(t.to_http_date(), "Sun, 06 Nov 1994 08:49:37 GMT");
+    /// ```
+    pub fn to_http_date(&self) -> String {
+        HttpDate(http_date_bytes(self)).as_str().to_owned()
+    }
+
+    /// append the IMF-fixdate of the time to `buf`, see `to_http_date`
+    pub fn format_http_date_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&http_date_bytes(self));
+    }
+
+    /// parse a HTTP date in any of the three forms of RFC 7231, the time is
+    /// in utc
+    ///
+    /// the two digits year of RFC 850 that looks more than 50 years in the
+    /// future is taken as the most recent past year with the same digits
+    ///
+    /// ```
+    /// use mco::std::time::Time;
+    ///
+    /// let t = Time::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+    /// assert_eq!(Time::parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT").unwrap(), t);
+    /// assert_eq!(Time::parse_http_date("Sun Nov  6 08:49:37 1994").unwrap(), t);
+    /// assert_eq!(t.unix(), 784111777);
+    /// ```
+    pub fn parse_http_date(value: &str) -> Result<Time> {
+        let b = value.as_bytes();
+        parse_imf_fixdate(b)
+            .or_else(|| parse_rfc850(b))
+            .or_else(|| parse_asctime(b))
+            .ok_or_else(|| err!("invalid http date: {:?}", value))
+    }
+}
+
+fn http_date_bytes(t: &Time) -> [u8; HTTP_DATE_LEN] {
+    let t = t.inner.to_offset(UtcOffset::UTC);
+    let year = t.year().max(0).min(9999) as u32;
+    let mut b = *b"Sun, 00 Jan 0000 00:00:00 GMT";
+    let weekday = t.weekday().number_days_from_sunday() as usize;
+    b[..3].copy_from_slice(SHORT_DAY_NAMES[weekday].as_bytes());
+    put2(&mut b[5..7], t.day() as u32);
+    b[8..11].copy_from_slice(SHORT_MONTH_NAMES[t.month() as usize - 1].as_bytes());
+    put2(&mut b[12..14], year / 100);
+    put2(&mut b[14..16], year % 100);
+    put2(&mut b[17..19], t.hour() as u32);
+    put2(&mut b[20..22], t.minute() as u32);
+    put2(&mut b[23..25], t.second() as u32);
+    b
+}
+
+#[inline]
+fn put2(b: &mut [u8], n: u32) {
+    b[0] = b'0' + (n / 10) as u8;
+    b[1] = b'0' + (n % 10) as u8;
+}
+
+// the number of the ascii digits
+fn num(b: &[u8]) -> Option<u32> {
+    if b.is_empty() || !b.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(b.iter().fold(0, |n, d| n * 10 + (d - b'0') as u32))
+}
+
+fn month(b: &[u8]) -> Option<u8> {
+    let i = SHORT_MONTH_NAMES.iter().position(|m| m.as_bytes() == b)?;
+    Some(i as u8 + 1)
+}
+
+// `08:49:37`
+fn hms(b: &[u8]) -> Option<(u8, u8, u8)> {
+    if b.len() != 8 || b[2] != b':' || b[5] != b':' {
+        return None;
+    }
+    let (h, m, s) = (num(&b[..2])?, num(&b[3..5])?, num(&b[6..])?);
+    // a leap second is the last second of the minute
+    Some((h as u8, m as u8, s.min(59) as u8))
+}
+
+fn make(year: i32, month: u8, day: u32, (h, m, s): (u8, u8, u8)) -> Option<Time> {
+    let month = time::Month::try_from(month).ok()?;
+    let date = time::Date::from_calendar_date(year, month, day as u8).ok()?;
+    let t = time::Time::from_hms(h, m, s).ok()?;
+    Some(Time {
+        inner: PrimitiveDateTime::new(date, t).assume_utc(),
+    })
+}
+
+// `Sun, 06 Nov 1994 08:49:37 GMT`
+fn parse_imf_fixdate(b: &[u8]) -> Option<Time> {
+    if b.len() != HTTP_DATE_LEN
+        || &b[3..5] != b", "
+        || b[7] != b' '
+        || b[11] != b' '
+        || b[16] != b' '
+        || &b[25..] != b" GMT"
+    {
+        return None;
+    }
+    SHORT_DAY_NAMES.iter().find(|d| d.as_bytes() == &b[..3])?;
+    make(
+        num(&b[12..16])? as i32,
+        month(&b[8..11])?,
+        num(&b[5..7])?,
+        hms(&b[17..25])?,
+    )
+}
+
+// `Sunday, 06-Nov-94 08:49:37 GMT`
+fn parse_rfc850(b: &[u8]) -> Option<Time> {
+    let comma = b.iter().position(|c| *c == b',')?;
+    LONG_DAY_NAMES
+        .iter()
+        .find(|d| d.as_bytes() == &b[..comma])?;
+    let b = &b[comma..];
+    if b.len() != 24
+        || b[1] != b' '
+        || b[4] != b'-'
+        || b[8] != b'-'
+        || b[11] != b' '
+        || &b[20..] != b" GMT"
+    {
+        return None;
+    }
+    let yy = num(&b[9..11])? as i32;
+    let now = Time::now_utc().year();
+    let mut year = now - now % 100 + yy;
+    if year > now + 50 {
+        year -= 100;
+    }
+    make(year, month(&b[5..8])?, num(&b[2..4])?, hms(&b[12..20])?)
+}
+
+// `Sun Nov  6 08:49:37 1994`
+fn parse_asctime(b: &[u8]) -> Option<Time> {
+    if b.len() != 24 || b[3] != b' ' || b[7] != b' ' || b[10] != b' ' || b[19] != b' ' {
+        return None;
+    }
+    SHORT_DAY_NAMES.iter().find(|d| d.as_bytes() == &b[..3])?;
+    // the day is padded by a space
+    let day = match b[8] {
+        b' ' => num(&b[9..10])?,
+        _ => num(&b[8..10])?,
+    };
+    make(
+        num(&b[20..])? as i32,
+        month(&b[4..7])?,
+        day,
+        hms(&b[11..19])?,
+    )
+}
+
+/// a formatted IMF-fixdate, it's `Copy` and needs no allocation
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HttpDate([u8; HTTP_DATE_LEN]);
+
+impl HttpDate {
+    /// the text of the date
+    pub fn as_str(&self) -> &str {
+        // only the ascii names and digits are written
+        unsafe { std::str::from_utf8_unchecked(&self.0) }
+    }
+
+    /// the bytes of the date
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<str> for HttpDate {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("HttpDate").field(&self.as_str()).finish()
+    }
+}
+
+// the date in words behind a seqlock, the readers retry when the updater
+// writes in between. the last 3 bytes are padding
+struct Slot {
+    seq: AtomicUsize,
+    words: [AtomicU64; 4],
+}
+
+impl Slot {
+    fn new(date: &[u8; HTTP_DATE_LEN]) -> Self {
+        let slot = Slot {
+            seq: AtomicUsize::new(0),
+            words: Default::default(),
+        };
+        slot.store(date);
+        slot
+    }
+
+    // there is only one writer, the update coroutine
+    fn store(&self, date: &[u8; HTTP_DATE_LEN]) {
+        let mut bytes = [0u8; 32];
+        bytes[..HTTP_DATE_LEN].copy_from_slice(date);
+        let seq = self.seq.load(Ordering::Relaxed);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (w, b) in self.words.iter().zip(bytes.chunks(8)) {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(b);
+            w.store(u64::from_ne_bytes(word), Ordering::Relaxed);
+        }
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+
+    fn load(&self) -> HttpDate {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                let mut bytes = [0u8; 32];
+                for (w, b) in self.words.iter().zip(bytes.chunks_mut(8)) {
+                    b.copy_from_slice(&w.load(Ordering::Relaxed).to_ne_bytes());
+                }
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    let mut date = [0u8; HTTP_DATE_LEN];
+                    date.copy_from_slice(&bytes[..HTTP_DATE_LEN]);
+                    return HttpDate(date);
+                }
+            }
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// the IMF-fixdate of now, updated each second by a coroutine named
+/// `http_date` on the timer
+///
+/// `get` never locks nor formats, any coroutine or thread can call it. the
+/// coroutine is canceled when the cache is dropped. it can be shared by a
+/// static:
+///
+/// ```
+/// use mco::std::lazy::sync::Lazy;
+/// use mco::std::time::{CachedHttpDate, Time};
+///
+/// static DATE: Lazy<CachedHttpDate> = Lazy::new(CachedHttpDate::new);
+///
+/// let date = DATE.get();
+/// let t = Time::parse_http_date(date.as_str()).unwrap();
+/// assert!((Time::now_utc().unix() - t.unix()).abs() <= 1);
+/// ```
+pub struct CachedHttpDate {
+    slot: Arc<Slot>,
+    co: Coroutine,
+}
+
+impl CachedHttpDate {
+    /// start the cache with the date of now
+    pub fn new() -> Self {
+        let slot = Arc::new(Slot::new(&http_date_bytes(&Time::now_utc())));
+        let updated = slot.clone();
+        let h = Builder::new()
+            .name("http_date".to_owned())
+            .spawn(move || loop {
+                // wake at the start of the next second, an early wake only
+                // sleeps the rest of it once more
+                let nanos = Time::now_utc().nanosecond() as u64;
+                sleep(Duration::from_nanos(1_000_000_000 - nanos));
+                updated.store(&http_date_bytes(&Time::now_utc()));
+            });
+        CachedHttpDate {
+            slot,
+            co: h.coroutine().clone(),
+        }
+    }
+
+    /// the date of the current second
+    pub fn get(&self) -> HttpDate {
+        self.slot.load()
+    }
+}
+
+impl Default for CachedHttpDate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for CachedHttpDate {
+    fn drop(&mut self) {
+        self.co.cancel();
+    }
+}
+
+impl fmt::Debug for CachedHttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("CachedHttpDate").field(&self.get()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_dates() {
+        let cases = [
+            ((1994, 11, 6, 8, 49, 37), "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ((1970, 1, 1, 0, 0, 0), "Thu, 01 Jan 1970 00:00:00 GMT"),
+            ((2000, 2, 29, 23, 59, 59), "Tue, 29 Feb 2000 23:59:59 GMT"),
+            ((2015, 10, 21, 7, 28, 0), "Wed, 21 Oct 2015 07:28:00 GMT"),
+            ((9999, 12, 31, 23, 59, 59), "Fri, 31 Dec 9999 23:59:59 GMT"),
+        ];
+        for ((y, mo, d, h, mi, s), text) in cases.iter().cloned() {
+            let t = Time::from_date(y, mo, d, h, mi, s, 0, UtcOffset::UTC);
+            assert_eq!(t.to_http_date(), text);
+            let mut buf = b"Date: ".to_vec();
+            t.format_http_date_into(&mut buf);
+            assert_eq!(&buf[6..], text.as_bytes());
+            assert_eq!(Time::parse_http_date(text).unwrap(), t);
+        }
+        // the offset is converted to GMT
+        let offset = UtcOffset::from_hms(-5, 0, 0).unwrap();
+        let t = Time::from_date(2015, 10, 21, 2, 28, 0, 0, offset);
+        assert_eq!(t.to_http_date(), "Wed, 21 Oct 2015 07:28:00 GMT");
+    }
+
+    #[test]
+    fn legacy_forms() {
+        let t = Time::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let rfc850 = Time::parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT").unwrap();
+        let asctime = Time::parse_http_date("Sun Nov  6 08:49:37 1994").unwrap();
+        assert_eq!(rfc850, t);
+        assert_eq!(asctime, t);
+        assert_eq!(
+            Time::parse_http_date("Thu Feb 29 12:00:00 2024").unwrap(),
+            Time::from_date(2024, 2, 29, 12, 0, 0, 0, UtcOffset::UTC)
+        );
+        // a year in the near future is kept
+        let yy = (Time::now_utc().year() + 1) % 100;
+        let text = format!("Monday, 01-Jan-{:02} 00:00:00 GMT", yy);
+        let t = Time::parse_http_date(&text).unwrap();
+        assert_eq!(t.year(), Time::now_utc().year() + 1);
+    }
+
+    #[test]
+    fn invalid_dates() {
+        for text in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 31 Feb 1994 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:49:37 GMT",
+            "Sun,  6 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            "Sun Nov 06 08:49:37 94",
+            "Sün, 06 Nov 1994 08:49:37 GMT",
+        ]
+        .iter()
+        {
+            assert!(Time::parse_http_date(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn cached_date() {
+        let cached = CachedHttpDate::new();
+        let first = Time::parse_http_date(cached.get().as_str()).unwrap();
+        assert!((Time::now_utc().unix() - first.unix()).abs() <= 1);
+        sleep(Duration::from_millis(1100));
+        let next = Time::parse_http_date(cached.get().as_str()).unwrap();
+        assert!(next.unix() > first.unix());
+    }
+}
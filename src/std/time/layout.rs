@@ -0,0 +1,150 @@
+//!
+//! for example:
+//! ```rust
+//!     use mco::std::time::{Layout, Time, UtcOffset};
+//!
+//!     let t = Time::from_date(2022, 2, 3, 13, 4, 5, 0, UtcOffset::UTC);
+//!     let layout = Layout::compile("[month repr:short] [day padding:none], [hour12]:[minute] [period case:lower]").unwrap();
+//!     assert_eq!(t.format_with(&layout), "Feb 3, 01:04 pm");
+//!     assert_eq!(t.format("[[[unix]]"), "[1643893445]");
+//!     assert!(Layout::compile("[hour13]").is_err());
+//! ```
+use crate::std::errors::Result;
+use std::fmt::{Debug, Formatter};
+use time::format_description::{self, OwnedFormatItem};
+
+macro_rules! token_map {
+    ($(($name:literal, $target:literal, $desc:literal)),* $(,)?) => {
+        &[$(($name, $target),)*]
+    };
+}
+
+// the token and its component of the time crate
+const TOKENS: &[(&str, &str)] = layout_tokens!(token_map);
+
+/// a compiled layout, compile it once to format or parse many times
+#[derive(Clone)]
+pub struct Layout {
+    src: String,
+    items: OwnedFormatItem,
+}
+
+impl Layout {
+    /// compile the layout, an unknown token or a bad modifier is an error
+    pub fn compile(layout: &str) -> Result<Layout> {
+        let mut desc = String::with_capacity(layout.len());
+        let mut rest = layout;
+        while let Some(i) = rest.find('[') {
+            desc.push_str(&rest[..i]);
+            rest = &rest[i..];
+            if rest.starts_with("[[") {
+                desc.push_str("[[");
+                rest = &rest[2..];
+                continue;
+            }
+            let end = match rest.find(']') {
+                Some(end) => end,
+                None => return Err(err!("unclosed `[` in layout `{}`", layout)),
+            };
+            let token = rest[1..end].trim();
+            let (name, modifiers) = match token.find(char::is_whitespace) {
+                Some(i) => (&token[..i], token[i..].trim_start()),
+                None => (token, ""),
+            };
+            let target = match TOKENS.iter().find(|(n, _)| *n == name) {
+                Some((_, target)) => *target,
+                None => return Err(err!("unknown layout token `{}`", name)),
+            };
+            let component = if modifiers.is_empty() {
+                format!("[{}]", target)
+            } else {
+                format!("[{} {}]", target, modifiers)
+            };
+            // report the bad modifiers with the token
+            if let Err(e) = format_description::parse_owned::<1>(&component) {
+                return Err(err!("invalid layout token `[{}]`, {}", token, e));
+            }
+            desc.push_str(&component);
+            rest = &rest[end + 1..];
+        }
+        desc.push_str(rest);
+        let items = format_description::parse_owned::<1>(&desc)
+            .map_err(|e| err!("invalid layout `{}`, {}", layout, e))?;
+        Ok(Layout {
+            src: layout.to_string(),
+            items,
+        })
+    }
+
+    /// the source of the layout
+    pub fn as_str(&self) -> &str {
+        &self.src
+    }
+
+    pub(crate) fn items(&self) -> &OwnedFormatItem {
+        &self.items
+    }
+}
+
+impl Debug for Layout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Layout").field(&self.src).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Layout;
+    use crate::std::time::{Time, UtcOffset, RFC3339, RFC3339_NANO, TIME_FORMAT};
+
+    #[test]
+    fn test_tokens() {
+        let t = Time::from_date(2022, 2, 3, 0, 4, 5, 6_000_000, UtcOffset::UTC);
+        let cases = [
+            ("[hour12] [period]", "12 AM"),
+            ("[hour12 padding:none][period case:lower]", "12am"),
+            ("[day padding:none]/[month padding:none]", "3/2"),
+            ("[day_of_year] [ordinal padding:none]", "034 34"),
+            ("[week_of_year] [week_number]", "05 05"),
+            ("[unix]", "1643846645"),
+            ("[unix_millis]", "1643846645006"),
+            ("[[[year]] [[", "[2022] ["),
+            ("no tokens]", "no tokens]"),
+        ];
+        for (layout, expect) in cases.iter() {
+            assert_eq!(&t.format(layout), expect, "{}", layout);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let e = Layout::compile("[year]-[hour13]").unwrap_err();
+        assert!(e.to_string().contains("`hour13`"), "{}", e);
+        let e = Layout::compile("[day padding:zeros]").unwrap_err();
+        assert!(e.to_string().contains("[day padding:zeros]"), "{}", e);
+        assert!(Layout::compile("[year").is_err());
+    }
+
+    #[test]
+    fn test_rfc_layouts() {
+        let t = Time::from_date(2006, 1, 2, 15, 4, 5, 999_999_999, UtcOffset::UTC);
+        assert_eq!(t.format(TIME_FORMAT), "Mon, 02 Jan 2006 15:04:05 GMT");
+        assert_eq!(t.format(RFC3339), "2006-01-02T15:04:05+00:00");
+        assert_eq!(
+            t.format(RFC3339_NANO),
+            "2006-01-02T15:04:05.999999999+00:00"
+        );
+        let layout = Layout::compile(RFC3339_NANO).unwrap();
+        assert_eq!(
+            Time::parse_with(&layout, &t.format(RFC3339_NANO)).unwrap(),
+            t
+        );
+    }
+
+    #[test]
+    fn test_parse_unix() {
+        let t = Time::parse("[unix_millis]", "1643846645006").unwrap();
+        assert_eq!(t.millisecond(), 6);
+        assert_eq!(t.format("[unix]"), "1643846645");
+    }
+}
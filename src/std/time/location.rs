@@ -0,0 +1,696 @@
+//! named time zones just like golang
+//!
+//! a `Location` maps each instant to the offset in effect in a zone. the fixed
+//! zones are always available, loading the zones of the tz database by name
+//! needs the `tzdb` feature, the data is read from the system zoneinfo files.
+//!
+//! converting a wall clock to an instant is ambiguous during the transitions,
+//! the rule is:
+//! - a wall clock that happens twice (clocks set back) uses the earlier
+//!   instant, that is the offset in effect before the transition
+//! - a wall clock that never happens (clocks set forward) is interpreted with
+//!   the offset in effect before the transition, e.g. 02:30 in a zone that jumps
+//!   from 02:00 to 03:00 becomes 03:30 of the new offset
+
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+
+#[cfg(feature = "tzdb")]
+use crate::std::errors::Result;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// a zone of a location, such as CET or CEST
+#[derive(Clone, PartialEq, Eq)]
+struct Zone {
+    name: String,
+    // seconds east of UTC
+    offset: i32,
+    is_dst: bool,
+}
+
+/// a location maps the instants to the offsets in use in a geographical area
+#[derive(Clone, PartialEq, Eq)]
+pub struct Location {
+    name: Cow<'static, str>,
+    zones: Vec<Zone>,
+    // unix seconds of the transition and the index of the zone after it, sorted
+    transitions: Vec<(i64, usize)>,
+    // the rule for the instants after the last transition
+    #[cfg(feature = "tzdb")]
+    extend: Option<Rule>,
+}
+
+impl Location {
+    /// the UTC location
+    pub const UTC: Location = Location {
+        name: Cow::Borrowed("UTC"),
+        zones: Vec::new(),
+        transitions: Vec::new(),
+        #[cfg(feature = "tzdb")]
+        extend: None,
+    };
+
+    /// a location that always uses the given offset, in seconds east of UTC
+    pub fn fixed(offset: i32) -> Location {
+        let name = fixed_name(offset);
+        Location {
+            name: Cow::Owned(name.clone()),
+            zones: vec![Zone {
+                name,
+                offset,
+                is_dst: false,
+            }],
+            transitions: Vec::new(),
+            #[cfg(feature = "tzdb")]
+            extend: None,
+        }
+    }
+
+    /// load the location with the given name from the tz database,
+    /// such as "America/New_York"
+    ///
+    /// "UTC" and "" return the UTC location. the zoneinfo files are looked up in
+    /// the directory of the `ZONEINFO` environment variable first, then in the
+    /// system directories
+    #[cfg(feature = "tzdb")]
+    pub fn load(name: &str) -> Result<Location> {
+        if name.is_empty() || name == "UTC" {
+            return Ok(Location::UTC);
+        }
+        if name.starts_with('/') || name.split('/').any(|p| p == ".." || p.is_empty()) {
+            return Err(err!("time: invalid location name {:?}", name));
+        }
+        let dirs = std::env::var("ZONEINFO")
+            .ok()
+            .into_iter()
+            .chain(ZONEINFO_DIRS.iter().map(|d| d.to_string()));
+        for dir in dirs {
+            let path = std::path::Path::new(&dir).join(name);
+            if let Ok(data) = std::fs::read(&path) {
+                return tzif::parse(name, &data);
+            }
+        }
+        Err(err!("time: unknown time zone {}", name))
+    }
+
+    /// the name of the location
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the zone name and the offset in seconds east of UTC at the unix time
+    pub fn lookup(&self, unix: i64) -> (&str, i32) {
+        let zone = self.zone(unix);
+        match zone {
+            Some(z) => (&z.name, z.offset),
+            None => ("UTC", 0),
+        }
+    }
+
+    /// the offset in seconds east of UTC at the unix time
+    pub fn offset_at(&self, unix: i64) -> i32 {
+        self.lookup(unix).1
+    }
+
+    fn zone(&self, unix: i64) -> Option<&Zone> {
+        if self.zones.is_empty() {
+            return None;
+        }
+        if self.transitions.is_empty() || unix < self.transitions[0].0 {
+            // before the first transition use the first standard zone
+            return self
+                .zones
+                .iter()
+                .find(|z| !z.is_dst)
+                .or_else(|| self.zones.first());
+        }
+        let idx = self.transitions.partition_point(|(t, _)| *t <= unix) - 1;
+        #[cfg(feature = "tzdb")]
+        if idx == self.transitions.len() - 1 {
+            if let Some(rule) = &self.extend {
+                return Some(rule.zone(unix));
+            }
+        }
+        Some(&self.zones[self.transitions[idx].1])
+    }
+
+    /// the offset to convert the wall clock in this location to an instant,
+    /// `wall` is the wall clock in seconds as if it were UTC
+    pub(crate) fn wall_offset(&self, wall: i64) -> i32 {
+        // the transitions are far more than a day apart
+        let before = self.offset_at(wall - SECONDS_PER_DAY);
+        let after = self.offset_at(wall + SECONDS_PER_DAY);
+        // the smaller unix time wins when both offsets are valid
+        let mut valid = [before, after]
+            .iter()
+            .copied()
+            .filter(|off| self.offset_at(wall - *off as i64) == *off)
+            .collect::<Vec<_>>();
+        valid.sort_unstable_by(|a, b| b.cmp(a));
+        valid.first().copied().unwrap_or(before)
+    }
+}
+
+impl Debug for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Location").field(&self.name()).finish()
+    }
+}
+
+impl Default for Location {
+    fn default() -> Self {
+        Location::UTC
+    }
+}
+
+// the name of a fixed zone, such as "+08:00"
+fn fixed_name(offset: i32) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    let abs = offset.unsigned_abs();
+    let name = format!("{}{:02}:{:02}", sign, abs / 3600, abs / 60 % 60);
+    match abs % 60 {
+        0 => name,
+        s => format!("{}:{:02}", name, s),
+    }
+}
+
+#[cfg(feature = "tzdb")]
+const ZONEINFO_DIRS: &[&str] = &[
+    "/usr/share/zoneinfo/",
+    "/usr/share/lib/zoneinfo/",
+    "/usr/lib/locale/TZ/",
+];
+
+////////////////////////////////////////////////////////////////////////////////
+// calendar
+////////////////////////////////////////////////////////////////////////////////
+
+/// the days since 1970-01-01 of the date, the month and the day are normalized
+/// so that month 13 is January of the next year and day 0 is the last day of
+/// the previous month
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = year + (month - 1).div_euclid(12);
+    let month = (month - 1).rem_euclid(12) + 1;
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468 + (day - 1)
+}
+
+/// the (year, month, day) of the days since 1970-01-01
+#[cfg(any(test, feature = "tzdb"))]
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(feature = "tzdb")]
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+#[cfg(feature = "tzdb")]
+fn days_in_month(year: i64, month: i64) -> i64 {
+    days_from_civil(year, month + 1, 1) - days_from_civil(year, month, 1)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// POSIX TZ rule
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(feature = "tzdb")]
+/// the day of a daylight saving transition in a POSIX TZ rule
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RuleDay {
+    /// Jn, 1 to 365, February 29 is never counted
+    Julian(i64),
+    /// n, 0 to 365, February 29 is counted in leap years
+    DayOfYear(i64),
+    /// Mm.w.d, day d (0 is Sunday) of week w (5 is the last) of month m
+    MonthWeekDay(i64, i64, i64),
+}
+
+#[cfg(feature = "tzdb")]
+impl RuleDay {
+    // the days since 1970-01-01 of the day in the year
+    fn days(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        match *self {
+            RuleDay::Julian(n) => {
+                let day = n - 1;
+                if is_leap(year) && n >= 60 {
+                    jan1 + day + 1
+                } else {
+                    jan1 + day
+                }
+            }
+            RuleDay::DayOfYear(n) => jan1 + n,
+            RuleDay::MonthWeekDay(m, w, d) => {
+                let first = days_from_civil(year, m, 1);
+                // 1970-01-01 is a Thursday
+                let wday = (first + 4).rem_euclid(7);
+                let mut day = (d - wday).rem_euclid(7) + (w - 1) * 7;
+                while day >= days_in_month(year, m) {
+                    day -= 7;
+                }
+                first + day
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tzdb")]
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Transition {
+    day: RuleDay,
+    // seconds of the local time of the day
+    time: i64,
+}
+
+#[cfg(feature = "tzdb")]
+/// a POSIX TZ rule, such as "CET-1CEST,M3.5.0,M10.5.0/3"
+#[derive(Clone, PartialEq, Eq)]
+struct Rule {
+    std: Zone,
+    dst: Option<(Zone, Transition, Transition)>,
+}
+
+#[cfg(feature = "tzdb")]
+impl Debug for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rule")
+            .field("std", &self.std.name)
+            .field("dst", &self.dst.as_ref().map(|d| &d.0.name))
+            .finish()
+    }
+}
+
+#[cfg(feature = "tzdb")]
+impl Rule {
+    fn zone(&self, unix: i64) -> &Zone {
+        let (dst, start, end) = match &self.dst {
+            None => return &self.std,
+            Some(v) => v,
+        };
+        let (year, _, _) =
+            civil_from_days((unix + self.std.offset as i64).div_euclid(SECONDS_PER_DAY));
+        // the transitions happen at the local time of the zone before them
+        let start = start.day.days(year) * SECONDS_PER_DAY + start.time - self.std.offset as i64;
+        let end = end.day.days(year) * SECONDS_PER_DAY + end.time - dst.offset as i64;
+        let in_dst = if start < end {
+            unix >= start && unix < end
+        } else {
+            // southern hemisphere
+            unix >= start || unix < end
+        };
+        if in_dst {
+            dst
+        } else {
+            &self.std
+        }
+    }
+
+    fn parse(s: &str) -> Option<Rule> {
+        let mut p = RuleParser { s: s.as_bytes() };
+        let std_name = p.name()?;
+        let std_offset = -p.offset()?;
+        let std = Zone {
+            name: std_name,
+            offset: std_offset as i32,
+            is_dst: false,
+        };
+        if p.s.is_empty() {
+            return Some(Rule { std, dst: None });
+        }
+        let dst_name = p.name()?;
+        let dst_offset = match p.s.first() {
+            Some(b',') | None => std_offset + 3600,
+            _ => -p.offset()?,
+        };
+        let dst = Zone {
+            name: dst_name,
+            offset: dst_offset as i32,
+            is_dst: true,
+        };
+        if p.s.is_empty() {
+            // the default rule of the US
+            p.s = b",M3.2.0,M11.1.0";
+        }
+        if !p.eat(b',') {
+            return None;
+        }
+        let start = p.transition()?;
+        if !p.eat(b',') {
+            return None;
+        }
+        let end = p.transition()?;
+        if !p.s.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            std,
+            dst: Some((dst, start, end)),
+        })
+    }
+}
+
+#[cfg(feature = "tzdb")]
+struct RuleParser<'a> {
+    s: &'a [u8],
+}
+
+#[cfg(feature = "tzdb")]
+impl<'a> RuleParser<'a> {
+    fn eat(&mut self, c: u8) -> bool {
+        if self.s.first() == Some(&c) {
+            self.s = &self.s[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    // an alphabetic name or a quoted one such as <+03>
+    fn name(&mut self) -> Option<String> {
+        let (name, rest) = if self.eat(b'<') {
+            let end = self.s.iter().position(|c| *c == b'>')?;
+            (&self.s[..end], &self.s[end + 1..])
+        } else {
+            let end = self
+                .s
+                .iter()
+                .position(|c| !c.is_ascii_alphabetic())
+                .unwrap_or(self.s.len());
+            (&self.s[..end], &self.s[end..])
+        };
+        if name.len() < 3 {
+            return None;
+        }
+        self.s = rest;
+        String::from_utf8(name.to_vec()).ok()
+    }
+
+    fn num(&mut self) -> Option<i64> {
+        let end = self
+            .s
+            .iter()
+            .position(|c| !c.is_ascii_digit())
+            .unwrap_or(self.s.len());
+        if end == 0 {
+            return None;
+        }
+        let n = std::str::from_utf8(&self.s[..end]).ok()?.parse().ok()?;
+        self.s = &self.s[end..];
+        Some(n)
+    }
+
+    // [+-]hh[:mm[:ss]] in seconds
+    fn offset(&mut self) -> Option<i64> {
+        let neg = if self.eat(b'-') {
+            true
+        } else {
+            self.eat(b'+');
+            false
+        };
+        let mut secs = self.num()? * 3600;
+        if self.eat(b':') {
+            secs += self.num()? * 60;
+            if self.eat(b':') {
+                secs += self.num()?;
+            }
+        }
+        Some(if neg { -secs } else { secs })
+    }
+
+    fn transition(&mut self) -> Option<Transition> {
+        let day = if self.eat(b'J') {
+            RuleDay::Julian(self.num().filter(|n| (1..=365).contains(n))?)
+        } else if self.eat(b'M') {
+            let m = self.num().filter(|n| (1..=12).contains(n))?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let w = self.num().filter(|n| (1..=5).contains(n))?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let d = self.num().filter(|n| (0..=6).contains(n))?;
+            RuleDay::MonthWeekDay(m, w, d)
+        } else {
+            RuleDay::DayOfYear(self.num().filter(|n| (0..=365).contains(n))?)
+        };
+        let time = if self.eat(b'/') {
+            self.offset()?
+        } else {
+            2 * 3600
+        };
+        Some(Transition { day, time })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// TZif
+////////////////////////////////////////////////////////////////////////////////
+
+/// the parser of the TZif files, see RFC 8536
+#[cfg(feature = "tzdb")]
+mod tzif {
+    use super::{Location, Rule, Zone};
+    use crate::std::errors::Result;
+    use std::borrow::Cow;
+
+    struct Reader<'a> {
+        data: &'a [u8],
+    }
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+            if self.data.len() < n {
+                return Err(err!("time: invalid tzif data"));
+            }
+            let (b, rest) = self.data.split_at(n);
+            self.data = rest;
+            Ok(b)
+        }
+
+        fn u32(&mut self) -> Result<u32> {
+            let b = self.bytes(4)?;
+            Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        }
+
+        fn i64(&mut self) -> Result<i64> {
+            let b = self.bytes(8)?;
+            let mut v = [0; 8];
+            v.copy_from_slice(b);
+            Ok(i64::from_be_bytes(v))
+        }
+    }
+
+    struct Header {
+        version: u8,
+        isutcnt: usize,
+        isstdcnt: usize,
+        leapcnt: usize,
+        timecnt: usize,
+        typecnt: usize,
+        charcnt: usize,
+    }
+
+    fn header(r: &mut Reader) -> Result<Header> {
+        if r.bytes(4)? != b"TZif" {
+            return Err(err!("time: invalid tzif magic"));
+        }
+        let version = r.bytes(16)?[0];
+        Ok(Header {
+            version,
+            isutcnt: r.u32()? as usize,
+            isstdcnt: r.u32()? as usize,
+            leapcnt: r.u32()? as usize,
+            timecnt: r.u32()? as usize,
+            typecnt: r.u32()? as usize,
+            charcnt: r.u32()? as usize,
+        })
+    }
+
+    pub(super) fn parse(name: &str, data: &[u8]) -> Result<Location> {
+        let mut r = Reader { data };
+        let mut h = header(&mut r)?;
+        // the 32 bit time size of version 1
+        let mut time_size = 4;
+        if h.version >= b'2' {
+            // skip the version 1 data block
+            r.bytes(
+                h.timecnt * 5 + h.typecnt * 6 + h.charcnt + h.leapcnt * 8 + h.isstdcnt + h.isutcnt,
+            )?;
+            h = header(&mut r)?;
+            time_size = 8;
+        }
+        if h.typecnt == 0 {
+            return Err(err!("time: tzif without local time types"));
+        }
+
+        let mut times = Vec::with_capacity(h.timecnt);
+        for _ in 0..h.timecnt {
+            times.push(match time_size {
+                8 => r.i64()?,
+                _ => r.u32()? as i32 as i64,
+            });
+        }
+        let idxs = r.bytes(h.timecnt)?;
+        let mut types = Vec::with_capacity(h.typecnt);
+        for _ in 0..h.typecnt {
+            let offset = r.u32()? as i32;
+            let b = r.bytes(2)?;
+            types.push((offset, b[0] != 0, b[1] as usize));
+        }
+        let chars = r.bytes(h.charcnt)?;
+        r.bytes(h.leapcnt * (time_size + 4) + h.isstdcnt + h.isutcnt)?;
+
+        let zones = types
+            .iter()
+            .map(|(offset, is_dst, idx)| {
+                let name = chars.get(*idx..).unwrap_or_default();
+                let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+                Zone {
+                    name: String::from_utf8_lossy(&name[..end]).into_owned(),
+                    offset: *offset,
+                    is_dst: *is_dst,
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut transitions = Vec::with_capacity(h.timecnt);
+        for (t, idx) in times.into_iter().zip(idxs.iter()) {
+            let idx = *idx as usize;
+            if idx >= zones.len() {
+                return Err(err!("time: invalid tzif transition"));
+            }
+            transitions.push((t, idx));
+        }
+
+        // the footer is the POSIX TZ rule for the instants after the last transition
+        let mut extend = None;
+        if h.version >= b'2' && r.data.first() == Some(&b'\n') {
+            let footer = &r.data[1..];
+            let end = footer
+                .iter()
+                .position(|c| *c == b'\n')
+                .unwrap_or(footer.len());
+            if let Ok(s) = std::str::from_utf8(&footer[..end]) {
+                extend = Rule::parse(s);
+            }
+        }
+        if transitions.is_empty() {
+            // a zone that only has the rule, use it for all the instants
+            if let Some(rule) = extend.take() {
+                return Ok(Location {
+                    name: Cow::Owned(name.to_owned()),
+                    zones: vec![rule.std.clone()],
+                    transitions: vec![(i64::MIN, 0)],
+                    extend: Some(rule),
+                });
+            }
+        }
+        Ok(Location {
+            name: Cow::Owned(name.to_owned()),
+            zones,
+            transitions,
+            extend,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_civil() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        // normalization
+        assert_eq!(days_from_civil(2021, 13, 1), days_from_civil(2022, 1, 1));
+        assert_eq!(days_from_civil(2021, 3, 0), days_from_civil(2021, 2, 28));
+        assert_eq!(days_from_civil(2020, 0, 1), days_from_civil(2019, 12, 1));
+        for days in -800000..800000 {
+            let (y, m, d) = civil_from_days(days);
+            assert_eq!(days_from_civil(y, m, d), days);
+        }
+    }
+
+    #[test]
+    fn test_fixed() {
+        let loc = Location::fixed(8 * 3600);
+        assert_eq!(loc.name(), "+08:00");
+        assert_eq!(loc.lookup(0), ("+08:00", 8 * 3600));
+        assert_eq!(Location::fixed(-(5 * 3600 + 30 * 60)).name(), "-05:30");
+        assert_eq!(Location::UTC.lookup(12345), ("UTC", 0));
+        assert_eq!(Location::UTC.wall_offset(12345), 0);
+    }
+
+    #[cfg(feature = "tzdb")]
+    #[test]
+    fn test_rule() {
+        let rule = Rule::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        // 2021-03-14 07:00 UTC is 02:00 EST
+        let start = days_from_civil(2021, 3, 14) * SECONDS_PER_DAY + 7 * 3600;
+        assert_eq!(rule.zone(start - 1).name, "EST");
+        assert_eq!(rule.zone(start).name, "EDT");
+        // 2021-11-07 06:00 UTC is 02:00 EDT
+        let end = days_from_civil(2021, 11, 7) * SECONDS_PER_DAY + 6 * 3600;
+        assert_eq!(rule.zone(end - 1).offset, -4 * 3600);
+        assert_eq!(rule.zone(end).offset, -5 * 3600);
+
+        // southern hemisphere
+        let rule = Rule::parse("<+1030>-10:30<+11>-11,M10.1.0,M4.1.0").unwrap();
+        let jan = days_from_civil(2021, 1, 1) * SECONDS_PER_DAY;
+        assert_eq!(rule.zone(jan).name, "+11");
+        let jul = days_from_civil(2021, 7, 1) * SECONDS_PER_DAY;
+        assert_eq!(rule.zone(jul).offset, 10 * 3600 + 1800);
+
+        assert!(Rule::parse("X5").is_none());
+        assert!(Rule::parse("UTC0").unwrap().dst.is_none());
+    }
+
+    #[cfg(feature = "tzdb")]
+    #[test]
+    fn test_load() {
+        let loc = match Location::load("America/New_York") {
+            Ok(loc) => loc,
+            // the system has no zoneinfo
+            Err(_) => return,
+        };
+        assert_eq!(loc.name(), "America/New_York");
+        let winter = days_from_civil(2021, 1, 1) * SECONDS_PER_DAY;
+        assert_eq!(loc.lookup(winter), ("EST", -5 * 3600));
+        let summer = days_from_civil(2021, 7, 1) * SECONDS_PER_DAY;
+        assert_eq!(loc.lookup(summer), ("EDT", -4 * 3600));
+        // far after the last transition, it's from the footer rule
+        let future = days_from_civil(2100, 7, 1) * SECONDS_PER_DAY;
+        assert_eq!(loc.lookup(future), ("EDT", -4 * 3600));
+
+        // 01:30 of 2021-11-07 happens twice, the earlier one is EDT
+        let wall = days_from_civil(2021, 11, 7) * SECONDS_PER_DAY + 5400;
+        assert_eq!(loc.wall_offset(wall), -4 * 3600);
+        // 02:30 of 2021-03-14 never happens, use EST before the transition
+        let wall = days_from_civil(2021, 3, 14) * SECONDS_PER_DAY + 9000;
+        assert_eq!(loc.wall_offset(wall), -5 * 3600);
+
+        assert!(Location::load("../etc/passwd").is_err());
+        assert!(Location::load("No/Such_Zone").is_err());
+        assert_eq!(Location::load("UTC").unwrap(), Location::UTC);
+    }
+}
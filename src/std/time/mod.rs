@@ -1,8 +1,77 @@
+#[cfg(feature = "test-util")]
+pub mod clock;
+pub mod deadline_queue;
 pub mod format;
+pub mod histogram;
+pub mod http_date;
+
+// the tokens of the layouts as (token, time-crate component, meaning), the
+// parser and the reference table in the docs of `layout` are both made from it
+#[cfg(feature = "time-format")]
+macro_rules! layout_tokens {
+    ($m:ident) => {
+        $m! {
+            ("year", "year", "the year, at least 4 digits, `repr:last_two` for 2 digits"),
+            ("month", "month", "the month 01-12, `repr:short` or `repr:long` for the name"),
+            ("day", "day", "the day of the month 01-31"),
+            ("ordinal", "ordinal", "the day of the year 001-366"),
+            ("day_of_year", "ordinal", "the same as `[ordinal]`"),
+            ("weekday", "weekday", "the weekday name, `repr:short` for Mon"),
+            ("week_number", "week_number", "the ISO week of the year 01-53, `repr:sunday` or `repr:monday` for the others"),
+            ("week_of_year", "week_number", "the same as `[week_number]`"),
+            ("hour", "hour", "the hour of the 24-hour clock 00-23"),
+            ("hour12", "hour repr:12", "the hour of the 12-hour clock 01-12"),
+            ("minute", "minute", "the minute 00-59"),
+            ("second", "second", "the second 00-59"),
+            ("subsecond", "subsecond", "the fraction of the second, `digits:3` for milliseconds"),
+            ("period", "period", "AM or PM, `case:lower` for am or pm"),
+            ("offset_hour", "offset_hour", "the hour of the utc offset, `sign:mandatory` for +08"),
+            ("offset_minute", "offset_minute", "the minute of the utc offset"),
+            ("offset_second", "offset_second", "the second of the utc offset"),
+            ("unix", "unix_timestamp", "the seconds since the unix epoch"),
+            ("unix_millis", "unix_timestamp precision:millisecond", "the milliseconds since the unix epoch"),
+        }
+    };
+}
+
+#[cfg(feature = "time-format")]
+macro_rules! layout_table {
+    ($(($name:literal, $target:literal, $desc:literal)),* $(,)?) => {
+        concat!(
+            "| token | meaning |\n",
+            "|-------|---------|\n",
+            $("| `[", $name, "]` | ", $desc, " |\n",)*
+        )
+    };
+}
+
+/// the layouts of `Time::format` and `Time::parse`
+///
+/// a layout is the literal text with the tokens in brackets, like
+/// `[year]-[month]-[day]`. the modifiers follow the name of a token, like
+/// `[day padding:none]` for the unpadded day, the numbers take `padding:none`
+/// or `padding:space`. `[[` is a literal `[`. an unknown token is an error
+///
+#[cfg(feature = "time-format")]
+#[doc = layout_tokens!(layout_table)]
+pub mod layout;
+pub mod location;
+pub mod source;
+pub mod stopwatch;
 pub mod sys;
 pub mod tick;
 pub mod time;
 
+#[cfg(feature = "test-util")]
+pub use self::clock::{advance, pause, pending_timers, resume, Paused};
+pub use self::deadline_queue::{DeadlineQueue, WaitExpired};
 pub use self::format::*;
+pub use self::histogram::*;
+pub use self::http_date::*;
+#[cfg(feature = "time-format")]
+pub use self::layout::Layout;
+pub use self::location::Location;
+pub use self::source::{clock_source, override_clock, set_clock_source, ClockGuard, ClockSource};
+pub use self::stopwatch::*;
 pub use self::tick::*;
 pub use self::time::*;
@@ -0,0 +1,111 @@
+//! the source of the wall clock, see `set_clock_source`
+//!
+//! all the wall clock reads of the crate go through `wall_clock`, that is
+//! `Time::now`, `Time::now_utc` and the times that are made from them, like
+//! the ticks of a `Ticker`, the cached http dates and the `Time` deadlines.
+//! the monotonic clock of the timers and the scheduler is not changed, so a
+//! `Time` deadline of a simulated clock is waited for by the real time left
+//!
+//! ```
+//! use mco::std::time::{self, Time};
+//!
+//! fn y2k() -> (i64, u32) {
+//!     (946_684_800, 0)
+//! }
+//!
+//! let _clock = time::override_clock(y2k);
+//! assert_eq!(Time::now_utc().year(), 2000);
+//! ```
+
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::{Mutex, MutexGuard};
+
+/// a wall clock, it returns the seconds since the unix epoch and the
+/// nanoseconds of the second, 0 to 999_999_999
+pub type ClockSource = fn() -> (i64, u32);
+
+// the `ClockSource`, null for the system clock
+static SOURCE: AtomicPtr<()> = AtomicPtr::new(std::ptr::null_mut());
+
+// serializes the scoped overrides of the tests
+static SCOPED: Mutex<()> = parking_lot::const_mutex(());
+
+/// let `Time::now` and the other wall clock reads of the crate call `source`,
+/// `None` for the system clock. it returns the source that is replaced
+///
+/// the source is swapped atomically and is process wide, it can be changed
+/// while the runtime runs, a read that races with the swap sees either of
+/// them. set it once before the runtime starts to see the same clock in all
+/// the times. the source is called on each read, so it must be cheap and
+/// must not read `Time::now` itself
+pub fn set_clock_source(source: Option<ClockSource>) -> Option<ClockSource> {
+    let ptr = source.map_or(std::ptr::null_mut(), |f| f as *mut ());
+    to_source(SOURCE.swap(ptr, Ordering::AcqRel))
+}
+
+/// the source that is set by `set_clock_source`, if any
+pub fn clock_source() -> Option<ClockSource> {
+    to_source(SOURCE.load(Ordering::Acquire))
+}
+
+fn to_source(ptr: *mut ()) -> Option<ClockSource> {
+    if ptr.is_null() {
+        None
+    } else {
+        // only the `ClockSource`s are stored
+        Some(unsafe { std::mem::transmute::<*mut (), ClockSource>(ptr) })
+    }
+}
+
+/// the guard of `override_clock`, the previous source is set back when it's
+/// dropped
+#[must_use = "the clock is set back when the guard is dropped"]
+pub struct ClockGuard {
+    prev: Option<ClockSource>,
+    _scoped: MutexGuard<'static, ()>,
+}
+
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        set_clock_source(self.prev);
+    }
+}
+
+/// set the source of the wall clock until the returned guard is dropped, for
+/// the tests
+///
+/// the clock is process wide, an override blocks while another one is alive,
+/// so the tests that override it run one by one. the other tests would see
+/// the overridden clock too, so put these tests in a test binary of their own
+pub fn override_clock(source: ClockSource) -> ClockGuard {
+    let scoped = SCOPED.lock();
+    let prev = set_clock_source(Some(source));
+    ClockGuard {
+        prev,
+        _scoped: scoped,
+    }
+}
+
+/// the unix time of the wall clock, from the source if it's set
+pub(crate) fn wall_clock() -> (i64, u32) {
+    if let Some(source) = clock_source() {
+        return source();
+    }
+    #[cfg(feature = "test-util")]
+    if let Some(now) = crate::std::time::clock::wall_now() {
+        return (now.unix_timestamp(), now.nanosecond());
+    }
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        // the clock is set before 1970
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::time::{Duration, Instant};
+
+/// A Stopwatch measures the elapsed time on the monotonic clock
+/// for example:
+/// ```
+///         use mco::std::time::Stopwatch;
+///
+///         let sw = Stopwatch::start();
+///         // do some work
+///         println!("elapsed {:?}", sw.elapsed());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    start: Instant,
+}
+
+impl Stopwatch {
+    /// start a new stopwatch
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+
+    /// the elapsed time since the stopwatch is started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// return the elapsed time and restart the stopwatch
+    pub fn restart(&mut self) -> Duration {
+        let now = Instant::now();
+        let d = now - self.start;
+        self.start = now;
+        d
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::std::time::Stopwatch;
+    use std::time::Duration;
+
+    #[test]
+    fn test_stopwatch() {
+        let mut sw = Stopwatch::start();
+        crate::coroutine::sleep(Duration::from_millis(10));
+        assert!(sw.elapsed() >= Duration::from_millis(10));
+        assert!(sw.restart() >= Duration::from_millis(10));
+        assert!(sw.elapsed() < Duration::from_millis(10));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::coroutine::sleep;
+use crate::select::{Selectable, Waker};
 use crate::std::errors::Result;
 use crate::std::sync::channel::Receiver;
 use crate::std::sync::Mutex;
@@ -109,6 +110,40 @@ impl Iterator for &Ticker {
     }
 }
 
+// the next tick for `select`, `None` once the ticker is stopped
+impl<'a> Selectable for &'a Ticker {
+    type Output = Option<Time>;
+
+    fn try_select(&mut self) -> Option<Option<Time>> {
+        (&self.recv).try_select().map(Result::ok)
+    }
+
+    fn register(&self, waker: &Waker) {
+        (&self.recv).register(waker)
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        (&self.recv).deregister(waker)
+    }
+}
+
+// the owned ticker, so that a `SelectSet` can hold it
+impl Selectable for Ticker {
+    type Output = Option<Time>;
+
+    fn try_select(&mut self) -> Option<Option<Time>> {
+        (&*self).try_select()
+    }
+
+    fn register(&self, waker: &Waker) {
+        (&self).register(waker)
+    }
+
+    fn deregister(&self, waker: &Waker) {
+        (&self).deregister(waker)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::sleep::sleep;
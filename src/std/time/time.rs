@@ -1,12 +1,21 @@
+#[cfg(feature = "time-format")]
 use crate::std::errors::Result;
 use crate::std::lazy::sync::Lazy;
 use crate::std::time::format::{LONG_DAY_NAMES, LONG_MONTH_NAMES};
+#[cfg(feature = "time-format")]
+use crate::std::time::layout::Layout;
+use crate::std::time::location::{days_from_civil, Location};
+use crate::std::time::source::wall_clock;
 use crate::std::time::sys::Timespec;
+use crate::timeout_list::now_instant;
 use serde::de::Error;
+use std::convert::TryFrom;
 use std::fmt::{Debug, Display, Formatter};
-use std::ops::{Add, Deref, Sub};
+use std::ops::Deref;
+#[cfg(feature = "time-format")]
 use std::str::FromStr;
-use time::{format_description, OffsetDateTime};
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
 
 pub use time::UtcOffset;
 
@@ -24,17 +33,145 @@ pub const RFC3339_NANO: &'static str = "[year]-[month]-[day]T[hour]:[minute]:[se
 pub const RFC1123: &'static str =
     "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] MST";
 
+// the unix nanoseconds of `Time::MIN` and `Time::MAX`
+const MIN_UNIX_NANOS: i128 = -377_705_203_200 * 1_000_000_000;
+const MAX_UNIX_NANOS: i128 = 253_402_300_799 * 1_000_000_000 + 999_999_999;
+
+/// the unix epoch, the same as `Time::UNIX_EPOCH`
+pub const UNIX_EPOCH: Time = Time::UNIX_EPOCH;
+
 /// Obtain the offset of Utc time and Local time in seconds, using Lazy only once to improve performance
 pub static GLOBAL_OFFSET: Lazy<UtcOffset> =
     Lazy::new(|| UtcOffset::from_whole_seconds(Timespec::now().local().tm_utcoff).unwrap());
 
 /// a time wrapper just like golang
+///
+/// the instant and the offset are plain integers, so the construction from
+/// the unix time, the unix getters, the arithmetic with a `Duration` and the
+/// comparisons of the instants are `const fn`, the calendar and the
+/// formatting are not:
+/// ```rust
+///     use mco::std::time::Time;
+///     use std::cmp::Ordering;
+///     use std::time::Duration;
+///
+///     const BOOT: Time = match Time::from_unix(1_600_000_000, 0) {
+///         Some(t) => t,
+///         None => panic!("out of range"),
+///     };
+///     const EXPIRY: Time = BOOT.add(Duration::from_secs(3600));
+///     const _: () = assert!(matches!(EXPIRY.cmp_instant(&BOOT), Ordering::Greater));
+///     assert_eq!(EXPIRY.unix(), 1_600_003_600);
+/// ```
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone)]
 pub struct Time {
     pub inner: OffsetDateTime,
 }
 
 impl Time {
+    /// the zero time, January 1, year 1, 00:00:00 UTC
+    pub const ZERO: Time = Time {
+        inner: match time::Date::from_calendar_date(1, time::Month::January, 1) {
+            Ok(date) => time::PrimitiveDateTime::new(date, time::Time::MIDNIGHT).assume_utc(),
+            Err(_) => panic!("invalid zero time"),
+        },
+    };
+
+    /// the earliest time, January 1, year -9999, 00:00:00 UTC
+    pub const MIN: Time = Time {
+        inner: match time::Date::from_calendar_date(-9999, time::Month::January, 1) {
+            Ok(date) => time::PrimitiveDateTime::new(date, time::Time::MIDNIGHT).assume_utc(),
+            Err(_) => panic!("invalid min time"),
+        },
+    };
+
+    /// the latest time, December 31, year 9999, 23:59:59.999999999 UTC
+    pub const MAX: Time = Time {
+        inner: match (
+            time::Date::from_calendar_date(9999, time::Month::December, 31),
+            time::Time::from_hms_nano(23, 59, 59, 999_999_999),
+        ) {
+            (Ok(date), Ok(t)) => time::PrimitiveDateTime::new(date, t).assume_utc(),
+            _ => panic!("invalid max time"),
+        },
+    };
+
+    /// the unix epoch, January 1, 1970, 00:00:00 UTC
+    pub const UNIX_EPOCH: Time = Time {
+        inner: OffsetDateTime::UNIX_EPOCH,
+    };
+
+    /// January 1, 2000, 00:00:00 UTC, the epoch of the Postgres timestamps
+    pub const Y2K: Time = Time {
+        inner: match time::Date::from_calendar_date(2000, time::Month::January, 1) {
+            Ok(date) => time::PrimitiveDateTime::new(date, time::Time::MIDNIGHT).assume_utc(),
+            Err(_) => panic!("invalid y2k time"),
+        },
+    };
+
+    /// the time of the unix seconds and the nanoseconds of the second in
+    /// UTC, `None` if it's out of the range or `nanos` is not below 1e9
+    pub const fn from_unix(secs: i64, nanos: u32) -> Option<Time> {
+        if nanos >= 1_000_000_000 {
+            return None;
+        }
+        Time::from_unix_nano_i128(secs as i128 * 1_000_000_000 + nanos as i128)
+    }
+
+    /// the time of the unix nanoseconds in UTC
+    pub const fn from_unix_nano(nanos: i64) -> Time {
+        match Time::from_unix_nano_i128(nanos as i128) {
+            Some(t) => t,
+            None => panic!("the i64 nanoseconds are in the range"),
+        }
+    }
+
+    /// the time of the exact unix nanoseconds in UTC, `None` if it's out of
+    /// the range
+    pub const fn from_unix_nano_i128(nanos: i128) -> Option<Time> {
+        if nanos < MIN_UNIX_NANOS || nanos > MAX_UNIX_NANOS {
+            return None;
+        }
+        match OffsetDateTime::from_unix_timestamp_nanos(nanos) {
+            Ok(inner) => Some(Time { inner }),
+            Err(_) => None,
+        }
+    }
+
+    /// from_date returns the time of `yyyy-mm-dd hh:mm:ss + nsec nanoseconds`
+    /// at the given offset, just like golang's `time.Date`.
+    ///
+    /// the values may be outside their usual ranges and are normalized,
+    /// for example month 13 is January of the next year, day 0 is the last day
+    /// of the previous month and 25:00 is 01:00 of the next day.
+    ///
+    /// it saturates at `Time::MIN` and `Time::MAX`
+    ///
+    /// for example:
+    /// ```rust
+    ///     use mco::std::time::{Time, UtcOffset, RFC3339};
+    ///
+    ///     let t = Time::from_date(2021, 13, 0, 12, 30, 0, 0, UtcOffset::UTC);
+    ///     assert_eq!(t.format(RFC3339), "2021-12-31T12:30:00+00:00");
+    /// ```
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_date(
+        year: i32,
+        month: i32,
+        day: i32,
+        hour: i32,
+        min: i32,
+        sec: i32,
+        nsec: i64,
+        offset: UtcOffset,
+    ) -> Time {
+        let days = days_from_civil(year as i64, month as i64, day as i64);
+        let secs =
+            hour as i64 * 3600 + min as i64 * 60 + sec as i64 + nsec.div_euclid(1_000_000_000);
+        let loc = Location::fixed(offset.whole_seconds());
+        Time::from_wall(days * 86400 + secs, nsec.rem_euclid(1_000_000_000), &loc)
+    }
+
     /// convert self to utc time
     pub fn utc(&self) -> Self {
         Self {
@@ -50,44 +187,173 @@ impl Time {
     }
 
     /// return new offset
-    pub fn to_offset(self, offset: UtcOffset) -> Time {
+    pub const fn to_offset(self, offset: UtcOffset) -> Time {
         Self {
             inner: self.inner.to_offset(offset),
         }
     }
 
-    pub fn unix_timestamp(&self) -> i64 {
+    pub const fn unix_timestamp(&self) -> i64 {
         self.inner.unix_timestamp()
     }
 
-    pub fn unix_timestamp_nano(&self) -> i64 {
-        self.inner.unix_timestamp_nanos() as i64
+    /// same as `unix_nano`
+    pub const fn unix_timestamp_nano(&self) -> i64 {
+        self.unix_nano()
     }
 
-    // unix_sec returns the time's seconds since Jan 1 1970 (Unix time).
-    pub fn unix(&self) -> i64 {
+    /// unix returns the time's seconds since Jan 1 1970 (Unix time), it's
+    /// exact for all the times from `Time::MIN` to `Time::MAX`
+    pub const fn unix(&self) -> i64 {
         self.inner.unix_timestamp()
     }
 
-    // unix_sec returns the time's seconds since Jan 1 1970 (Unix time).
-    pub fn unix_nano(&self) -> i64 {
-        self.inner.unix_timestamp_nanos() as i64
+    /// unix_nano returns the time's nanoseconds since Jan 1 1970 (Unix time).
+    ///
+    /// an i64 of nanoseconds only covers the years 1678 to 2262, the times out
+    /// of that saturate at `i64::MIN` and `i64::MAX`. see `unix_nano_i128`
+    /// for the exact value
+    pub const fn unix_nano(&self) -> i64 {
+        let nanos = self.inner.unix_timestamp_nanos();
+        if nanos < i64::MIN as i128 {
+            i64::MIN
+        } else if nanos > i64::MAX as i128 {
+            i64::MAX
+        } else {
+            nanos as i64
+        }
     }
 
-    pub fn add(mut self, d: std::time::Duration) -> Self {
-        self.inner = self.inner.add(d);
-        self
+    /// the exact nanoseconds since Jan 1 1970 (Unix time)
+    pub const fn unix_nano_i128(&self) -> i128 {
+        self.inner.unix_timestamp_nanos()
     }
 
-    pub fn sub(mut self, d: std::time::Duration) -> Self {
-        self.inner = self.inner.sub(d);
-        self
+    /// add returns the time t+d, it saturates at `Time::MAX`
+    pub const fn add(self, d: std::time::Duration) -> Self {
+        match self.checked_add(d) {
+            Some(t) => t,
+            None => Self::MAX,
+        }
     }
 
-    // add_sec adds d seconds to the time.
-    pub fn add_sec(mut self, d: i64) -> Self {
-        self.inner = self.inner.add(time::Duration::seconds(d));
-        self
+    /// sub returns the time t-d, it saturates at `Time::MIN`
+    pub const fn sub(self, d: std::time::Duration) -> Self {
+        match self.checked_sub(d) {
+            Some(t) => t,
+            None => Self::MIN,
+        }
+    }
+
+    /// checked_add returns the time t+d in the offset of t, or `None` if it's
+    /// out of the range from `Time::MIN` to `Time::MAX` in UTC or in the offset
+    pub const fn checked_add(&self, d: std::time::Duration) -> Option<Self> {
+        self.add_nanos(d.as_nanos() as i128)
+    }
+
+    /// checked_sub returns the time t-d in the offset of t, or `None` if it's
+    /// out of the range, see `checked_add`
+    pub const fn checked_sub(&self, d: std::time::Duration) -> Option<Self> {
+        self.add_nanos(-(d.as_nanos() as i128))
+    }
+
+    /// add_sec adds d seconds to the time, it saturates at `Time::MIN` and
+    /// `Time::MAX`
+    pub const fn add_sec(self, d: i64) -> Self {
+        match self.add_nanos(d as i128 * 1_000_000_000) {
+            Some(t) => t,
+            None if d < 0 => Self::MIN,
+            None => Self::MAX,
+        }
+    }
+
+    /// the exact duration from `earlier` to t, or `Negative` with the
+    /// duration from t to `earlier` if t is before it
+    pub fn duration_since(&self, earlier: &Time) -> std::result::Result<Duration, Negative> {
+        match self.to_epoch(earlier) {
+            (Sign::Positive, d) => Ok(d),
+            (Sign::Negative, d) => Err(Negative(d)),
+        }
+    }
+
+    /// the time `offset` after the epoch in the offset of the epoch, or
+    /// `None` if it's out of the range, see `checked_add`
+    pub fn from_epoch(epoch: Time, offset: Duration) -> Option<Time> {
+        epoch.checked_add(offset)
+    }
+
+    /// the time `offset` after the epoch, or before it for `Sign::Negative`,
+    /// the inverse of `to_epoch`
+    pub fn from_epoch_signed(epoch: Time, sign: Sign, offset: Duration) -> Option<Time> {
+        match sign {
+            Sign::Positive => epoch.checked_add(offset),
+            Sign::Negative => epoch.checked_sub(offset),
+        }
+    }
+
+    /// the exact distance of t from the epoch, with the side of the epoch
+    /// that t is on. the epoch itself is `Sign::Positive`
+    ///
+    /// for example, the microseconds of a Postgres timestamp:
+    /// ```rust
+    ///     use mco::std::time::{Sign, Time, UtcOffset};
+    ///
+    ///     let t = Time::from_date(1999, 12, 31, 23, 59, 59, 999_999_000, UtcOffset::UTC);
+    ///     let (sign, d) = t.to_epoch(&Time::Y2K);
+    ///     assert_eq!((sign, d.as_micros()), (Sign::Negative, 1));
+    ///     let micros = sign.apply(d.as_micros() as i64);
+    ///     assert_eq!(micros, -1);
+    ///     assert_eq!(Time::from_epoch_signed(Time::Y2K, sign, d), Some(t));
+    /// ```
+    pub fn to_epoch(&self, epoch: &Time) -> (Sign, Duration) {
+        let nanos = self.unix_nano_i128() - epoch.unix_nano_i128();
+        let sign = if nanos < 0 {
+            Sign::Negative
+        } else {
+            Sign::Positive
+        };
+        // the range of the times is far less than `Duration::MAX`
+        let nanos = nanos.unsigned_abs();
+        let d = Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        );
+        (sign, d)
+    }
+
+    // the instant of the timer clock at the time, the wall clock is read
+    // once. `None` if it's too far away for an `Instant`
+    pub(crate) fn instant(&self) -> Option<Instant> {
+        let now = now_instant();
+        let left = self.unix_nano_i128() - Time::now_utc().unix_nano_i128();
+        if left <= 0 {
+            return Some(now);
+        }
+        now.checked_add(Duration::from_nanos(left.min(u64::MAX as i128) as u64))
+    }
+
+    // the time `nanos` after t, both the instant and the wall clock in the
+    // offset of t must be in the range
+    const fn add_nanos(&self, nanos: i128) -> Option<Self> {
+        let unix = match self.inner.unix_timestamp_nanos().checked_add(nanos) {
+            Some(unix) => unix,
+            None => return None,
+        };
+        let offset = self.inner.offset();
+        let wall = unix + offset.whole_seconds() as i128 * 1_000_000_000;
+        if unix < MIN_UNIX_NANOS
+            || unix > MAX_UNIX_NANOS
+            || wall < MIN_UNIX_NANOS
+            || wall > MAX_UNIX_NANOS
+        {
+            return None;
+        }
+        match OffsetDateTime::from_unix_timestamp_nanos(unix) {
+            Ok(inner) => Some(Time {
+                inner: inner.to_offset(offset),
+            }),
+            Err(_) => None,
+        }
     }
 
     /// set_loc sets the location associated with the time.
@@ -96,14 +362,78 @@ impl Time {
         self
     }
 
+    /// in_location returns the same instant with the offset in effect in the location.
+    pub fn in_location(&self, loc: &Location) -> Time {
+        let offset = loc.offset_at(self.unix());
+        self.clone().to_offset(fixed_offset(offset))
+    }
+
+    /// add_date returns the time corresponding to adding the given number of
+    /// years, months and days to the wall clock of t.
+    /// the result is normalized, for example October 31 plus one month is December 1.
+    /// it saturates at `Time::MIN` and `Time::MAX`
+    pub fn add_date(&self, years: i32, months: i32, days: i32) -> Time {
+        let loc = Location::fixed(self.inner.offset().whole_seconds());
+        self.add_date_in(years, months, days, &loc)
+    }
+
+    /// add_date_in is the same as add_date, except that the wall clock is the one
+    /// in the location, so that the daylight saving transitions are respected.
+    /// see the module `location` for how the ambiguous wall clocks are resolved.
+    pub fn add_date_in(&self, years: i32, months: i32, days: i32, loc: &Location) -> Time {
+        let t = self.in_location(loc);
+        let days = days_from_civil(
+            t.year() as i64 + years as i64,
+            t.inner.month() as i64 + months as i64,
+            t.day() as i64 + days as i64,
+        );
+        let secs = t.hour() as i64 * 3600 + t.minute() as i64 * 60 + t.second() as i64;
+        Time::from_wall(days * 86400 + secs, t.nanosecond() as i64, loc)
+    }
+
+    // the time of the wall clock in the location,
+    // `wall` is the wall clock in seconds as if it were UTC.
+    // it saturates at `Time::MIN` and `Time::MAX`, the instant and the wall
+    // clock must both be in the range like `add_nanos`
+    pub(crate) fn from_wall(wall: i64, nanos: i64, loc: &Location) -> Time {
+        let offset = loc.wall_offset(wall);
+        let wall = wall as i128 * 1_000_000_000 + nanos as i128;
+        let unix = wall - offset as i128 * 1_000_000_000;
+        if unix < MIN_UNIX_NANOS || wall < MIN_UNIX_NANOS {
+            return Time::MIN;
+        }
+        if unix > MAX_UNIX_NANOS || wall > MAX_UNIX_NANOS {
+            return Time::MAX;
+        }
+        match OffsetDateTime::from_unix_timestamp_nanos(unix) {
+            Ok(inner) => Time {
+                inner: inner.to_offset(fixed_offset(offset)),
+            },
+            Err(_) if unix < 0 => Time::MIN,
+            Err(_) => Time::MAX,
+        }
+    }
+
+    /// the order of the instants of t and u, the offsets don't count
+    pub const fn cmp_instant(&self, u: &Time) -> std::cmp::Ordering {
+        let (a, b) = (self.unix_nano_i128(), u.unix_nano_i128());
+        if a < b {
+            std::cmp::Ordering::Less
+        } else if a > b {
+            std::cmp::Ordering::Greater
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }
+
     /// after reports whether the time instant t is after u.
-    pub fn after(&self, u: &Time) -> bool {
-        self.inner.sub(u.inner).is_positive()
+    pub const fn after(&self, u: &Time) -> bool {
+        matches!(self.cmp_instant(u), std::cmp::Ordering::Greater)
     }
 
     /// before reports whether the time instant t is before u.
-    pub fn before(&self, u: &Time) -> bool {
-        u.inner.sub(self.inner).is_positive()
+    pub const fn before(&self, u: &Time) -> bool {
+        matches!(self.cmp_instant(u), std::cmp::Ordering::Less)
     }
 
     /// equal reports whether t and u represent the same time instanself.
@@ -111,14 +441,14 @@ impl Time {
     /// For example, 6:00 +0200 and 4:00 UTC are equal.
     /// See the documentation on the Time type for the pitfalls of using == with
     /// Time values; most code should use equal instead.
-    pub fn equal(&self, u: &Time) -> bool {
-        self.inner.eq(&u.inner)
+    pub const fn equal(&self, u: &Time) -> bool {
+        matches!(self.cmp_instant(u), std::cmp::Ordering::Equal)
     }
 
     /// is_zero reports whether t represents the zero time instant,
     /// January 1, year 1, 00:00:00 UTC.
-    pub fn is_zero(&self) -> bool {
-        return self.nanosecond() == 0;
+    pub const fn is_zero(&self) -> bool {
+        self.equal(&Self::ZERO)
     }
 
     /// date returns the (year, month,  day) in which t occurs.
@@ -180,21 +510,8 @@ impl Time {
         self.inner.nanosecond() as i32
     }
 
-    /// format a time to string
-    /// [year]
-    /// [month]
-    /// [ordinal]
-    /// [weekday]
-    /// [week_number]
-    /// [day]
-    /// [hour]
-    /// [minute]
-    /// [period]
-    /// [second]
-    /// [subsecond]
-    /// [offset_hour sign:mandatory]
-    /// [offset_minute]
-    /// [offset_second]
+    /// format a time to string, see the `layout` module for the tokens.
+    /// an empty string is returned if the layout is invalid
     ///
     /// for example:
     /// ```rust
@@ -206,36 +523,53 @@ impl Time {
     ///     );
     ///     println!("formatted: {}", formatted);
     /// ```
+    #[cfg(feature = "time-format")]
     pub fn format(&self, layout: &str) -> String {
-        let f = {
-            match format_description::parse(layout) {
-                Ok(v) => v,
-                Err(_) => {
-                    vec![]
-                }
-            }
-        };
-        if f.is_empty() {
-            return String::new();
-        }
-        self.inner.format(&f).unwrap_or_default()
-    }
-
-    /// parse a string value to Time。
-    /// [year]
-    /// [month]
-    /// [ordinal]
-    /// [weekday]
-    /// [week_number]
-    /// [day]
-    /// [hour]
-    /// [minute]
-    /// [period]
-    /// [second]
-    /// [subsecond]
-    /// [offset_hour sign:mandatory]
-    /// [offset_minute]
-    /// [offset_second]
+        match Layout::compile(layout) {
+            Ok(layout) => self.format_with(&layout),
+            Err(_) => String::new(),
+        }
+    }
+
+    /// format a time to string with a compiled layout
+    #[cfg(feature = "time-format")]
+    pub fn format_with(&self, layout: &Layout) -> String {
+        self.inner.format(layout.items()).unwrap_or_default()
+    }
+
+    /// a lazy RFC3339 display at second precision, like
+    /// `2006-01-02T15:04:05+08:00`, see `display_with`
+    pub fn display_secs(&self) -> TimeDisplay<'_> {
+        self.display_with(Precision::Secs)
+    }
+
+    /// a lazy RFC3339 display at millisecond precision, like
+    /// `2006-01-02T15:04:05.120+08:00`, see `display_with`
+    pub fn display_millis(&self) -> TimeDisplay<'_> {
+        self.display_with(Precision::Millis)
+    }
+
+    /// a lazy RFC3339 display at `precision`, it's written into the formatter
+    /// without a `String` and without a layout
+    ///
+    /// the fraction has all the digits of the precision, the trailing zeros
+    /// included, and the finer digits are truncated, not rounded, so that a
+    /// time is never shown as a later millisecond or second than it is
+    ///
+    /// ```rust
+    /// use mco::std::time::{Precision, Time};
+    ///
+    /// let t = Time::now();
+    /// println!("{} {}", t.display_millis(), t.display_with(Precision::Micros));
+    /// ```
+    pub fn display_with(&self, precision: Precision) -> TimeDisplay<'_> {
+        TimeDisplay {
+            time: self,
+            precision,
+        }
+    }
+
+    /// parse a string value to Time, see the `layout` module for the tokens
     ///
     /// for example:
     /// ```rust
@@ -245,57 +579,251 @@ impl Time {
     ///
     ///     let parsed = Time::parse(RFC3339_NANO, "2022-02-03T01:51:00.9335458+08:00").unwrap();
     /// ```
+    #[cfg(feature = "time-format")]
     pub fn parse(layout: &str, value: &str) -> Result<Self> {
-        match format_description::parse(layout) {
-            Ok(v) => Ok(Self {
-                inner: time::OffsetDateTime::parse(value, &v)?,
-            }),
-            Err(e) => {
-                return Err(e.into());
-            }
-        }
+        Self::parse_with(&Layout::compile(layout)?, value)
+    }
+
+    /// parse a string value to Time with a compiled layout
+    #[cfg(feature = "time-format")]
+    pub fn parse_with(layout: &Layout, value: &str) -> Result<Self> {
+        Ok(Self {
+            inner: time::OffsetDateTime::parse(value, layout.items())?,
+        })
     }
 
     /// now returns the current local time.
+    ///
+    /// it reads the realtime clock once and decomposes it at the local offset
+    /// directly, without going through the utc time
     pub fn now() -> Time {
-        let mut now = time::OffsetDateTime::now_utc();
-        now = now.to_offset(GLOBAL_OFFSET.clone());
-        return Time { inner: now };
+        let (secs, nanos) = wall_clock();
+        Time {
+            inner: unix_at_offset(secs, nanos, *GLOBAL_OFFSET),
+        }
     }
 
     /// current utc time
     pub fn now_utc() -> Time {
-        let now = time::OffsetDateTime::now_utc();
-        return Time { inner: now };
+        let (secs, nanos) = wall_clock();
+        Time {
+            inner: unix_at_offset(secs, nanos, UtcOffset::UTC),
+        }
+    }
+}
+
+impl Time {
+    // the RFC3339 text with `digits` of the fraction, written by hand without
+    // the formatter. with `trim` the trailing zeros of the fraction are cut
+    // down to one digit, like the `[subsecond]` of `RFC3339_NANO`
+    fn write_rfc3339<W: std::fmt::Write>(
+        &self,
+        w: &mut W,
+        digits: u32,
+        trim: bool,
+    ) -> std::fmt::Result {
+        // the sign of a year before 1 is written apart, the width is of the digits
+        if self.year() < 0 {
+            w.write_char('-')?;
+        }
+        write!(
+            w,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year().abs(),
+            self.inner.month() as u8,
+            self.day(),
+            self.hour(),
+            self.minute(),
+            self.second()
+        )?;
+        let mut frac = self.nanosecond() as u32 / 10u32.pow(9 - digits);
+        let mut digits = digits;
+        if trim {
+            while digits > 1 && frac % 10 == 0 {
+                frac /= 10;
+                digits -= 1;
+            }
+        }
+        if digits > 0 {
+            write!(w, ".{:0width$}", frac, width = digits as usize)?;
+        }
+        let offset = self.inner.offset();
+        let (h, m, _) = offset.as_hms();
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        write!(w, "{}{:02}:{:02}", sign, h.abs(), m.abs())
+    }
+
+    // the RFC3339_NANO text, the same with and without the formatter
+    fn rfc3339_nano(&self) -> String {
+        let mut s = String::with_capacity(35);
+        let _ = self.write_rfc3339(&mut s, 9, true);
+        s
+    }
+
+    // the RFC3339 text of `write_rfc3339` or with a `Z` offset, parsed by
+    // hand without the formatter
+    fn parse_rfc3339(s: &str) -> Option<Time> {
+        let (neg, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let b = s.as_bytes();
+        let num = |from: usize, to: usize| -> Option<u32> {
+            let t = s.get(from..to)?;
+            if t.is_empty() || !t.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            t.parse().ok()
+        };
+        let seps = [(4, b'-'), (7, b'-'), (10, b'T'), (13, b':'), (16, b':')];
+        if b.len() < 20 || seps.iter().any(|&(i, c)| b[i] != c) {
+            return None;
+        }
+        let (year, month, day) = (num(0, 4)?, num(5, 7)?, num(8, 10)?);
+        let (hour, minute, second) = (num(11, 13)?, num(14, 16)?, num(17, 19)?);
+        let (mut i, mut nanos) = (19, 0);
+        if b[i] == b'.' {
+            i += 1;
+            while i < b.len() && b[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits = (i - 20) as u32;
+            if digits == 0 || digits > 9 {
+                return None;
+            }
+            nanos = num(20, i)? * 10u32.pow(9 - digits);
+        }
+        let rest = &b[i..];
+        let offset = if rest == b"Z" {
+            time::UtcOffset::UTC
+        } else if rest.len() == 6 && (rest[0] == b'+' || rest[0] == b'-') && rest[3] == b':' {
+            let (h, m) = (num(i + 1, i + 3)? as i8, num(i + 4, i + 6)? as i8);
+            let sign = if rest[0] == b'-' { -1 } else { 1 };
+            time::UtcOffset::from_hms(sign * h, sign * m, 0).ok()?
+        } else {
+            return None;
+        };
+        let month = time::Month::try_from(month as u8).ok()?;
+        let year = if neg { -(year as i32) } else { year as i32 };
+        let date = time::Date::from_calendar_date(year, month, day as u8).ok()?;
+        let time = time::Time::from_hms_nano(hour as u8, minute as u8, second as u8, nanos).ok()?;
+        Some(Time {
+            inner: date.with_time(time).assume_offset(offset),
+        })
     }
 }
 
 impl Debug for Time {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("Time")
-            .field(&self.format(RFC3339_NANO))
-            .finish()
+        f.debug_tuple("Time").field(&self.rfc3339_nano()).finish()
     }
 }
 
 impl Display for Time {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Display::fmt(&self.format(RFC3339_NANO), f)
+        std::fmt::Display::fmt(&self.rfc3339_nano(), f)
+    }
+}
+
+/// the digits of the fraction of a second in `Time::display_with` and
+/// `display_duration`, the finer digits are truncated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Precision {
+    Secs,
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl Precision {
+    /// the digits of the fraction of a second
+    pub const fn digits(self) -> u32 {
+        match self {
+            Precision::Secs => 0,
+            Precision::Millis => 3,
+            Precision::Micros => 6,
+            Precision::Nanos => 9,
+        }
+    }
+}
+
+/// the display of `Time::display_with`
+#[derive(Debug, Clone, Copy)]
+pub struct TimeDisplay<'a> {
+    time: &'a Time,
+    precision: Precision,
+}
+
+impl Display for TimeDisplay<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.time.write_rfc3339(f, self.precision.digits(), false)
+    }
+}
+
+/// a lazy display of `d` in its largest unit as the `Debug` of `Duration`,
+/// like `1.5s` or `120ms`, cut at `precision`
+///
+/// the finer digits are truncated as in `Time::display_with`, and the
+/// trailing zeros are trimmed as in the `Debug` of `Duration`
+///
+/// ```rust
+/// use mco::std::time::{display_duration, Precision};
+/// use std::time::Duration;
+///
+/// let d = Duration::from_nanos(1_234_567_890);
+/// assert_eq!(display_duration(d, Precision::Millis).to_string(), "1.234s");
+/// let d = Duration::from_micros(1500);
+/// assert_eq!(display_duration(d, Precision::Millis).to_string(), "1ms");
+/// ```
+pub fn display_duration(d: Duration, precision: Precision) -> DurationDisplay {
+    DurationDisplay { d, precision }
+}
+
+/// the display of `display_duration`
+#[derive(Debug, Clone, Copy)]
+pub struct DurationDisplay {
+    d: Duration,
+    precision: Precision,
+}
+
+impl Display for DurationDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cap = 10u128.pow(9 - self.precision.digits());
+        let nanos = self.d.as_nanos() / cap * cap;
+        // the largest unit of the value, a zero is in the unit of the cap
+        let (unit, scale, width) = match nanos.max(cap) {
+            n if n >= 1_000_000_000 => ("s", 1_000_000_000, 9),
+            n if n >= 1_000_000 => ("ms", 1_000_000, 6),
+            n if n >= 1_000 => ("µs", 1_000, 3),
+            _ => ("ns", 1, 0),
+        };
+        write!(f, "{}", nanos / scale)?;
+        let (mut frac, mut width) = (nanos % scale, width);
+        while width > 0 && frac % 10 == 0 {
+            frac /= 10;
+            width -= 1;
+        }
+        if width > 0 {
+            write!(f, ".{:0width$}", frac, width = width)?;
+        }
+        f.write_str(unit)
     }
 }
 
 impl Default for Time {
     fn default() -> Self {
-        Self::parse(RFC3339_NANO, "0001-01-01T00:00:00.000000+00:00").unwrap()
+        Self::ZERO
     }
 }
 
+/// it's the RFC3339_NANO string whatever the features, see `unix_nanos` for
+/// the integer form
 impl serde::Serialize for Time {
     fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&self.format(RFC3339_NANO))
+        serializer.serialize_str(&self.rfc3339_nano())
     }
 }
 
@@ -304,13 +832,42 @@ impl<'de> serde::Deserialize<'de> for Time {
     where
         D: serde::Deserializer<'de>,
     {
-        match Time::parse(RFC3339_NANO, &String::deserialize(deserializer)?) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(D::Error::custom(e.to_string())),
+        let s = String::deserialize(deserializer)?;
+        match Time::parse_rfc3339(&s) {
+            Some(v) => Ok(v),
+            None => Err(D::Error::custom(format!("not a RFC3339 time: {:?}", s))),
         }
     }
 }
 
+/// the nanoseconds since the unix epoch as the serde form of a `Time` field,
+/// the same with and without the `time-format` feature
+///
+/// ```
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Event {
+///     #[serde(with = "mco::std::time::unix_nanos")]
+///     at: mco::std::time::Time,
+/// }
+/// ```
+pub mod unix_nanos {
+    use super::Time;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(t: &Time, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i128(t.unix_nano_i128())
+    }
+
+    /// the time is in UTC
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Time, D::Error> {
+        let nanos = i128::deserialize(deserializer)?;
+        Time::from_unix_nano_i128(nanos)
+            .ok_or_else(|| D::Error::custom(format!("unix nanos out of range: {}", nanos)))
+    }
+}
+
+#[cfg(feature = "time-format")]
 impl FromStr for Time {
     type Err = crate::std::errors::Error;
 
@@ -458,6 +1015,31 @@ impl Month {
     }
 }
 
+// the UtcOffset of the offset in seconds east of UTC
+fn fixed_offset(offset: i32) -> UtcOffset {
+    UtcOffset::from_whole_seconds(offset).unwrap_or(UtcOffset::UTC)
+}
+
+// the julian day of 1970-01-01
+const UNIX_EPOCH_JULIAN_DAY: i64 = 2_440_588;
+
+// the time of the unix `secs` and `nanos` at `offset`, the calendar is
+// computed only once for the wall clock of the offset
+fn unix_at_offset(secs: i64, nanos: u32, offset: UtcOffset) -> OffsetDateTime {
+    let wall = secs + offset.whole_seconds() as i64;
+    let day = wall.div_euclid(86400) + UNIX_EPOCH_JULIAN_DAY;
+    let sec = wall.rem_euclid(86400) as u32;
+    let date = time::Date::from_julian_day(day as i32).expect("time out of range");
+    let clock = time::Time::from_hms_nano(
+        (sec / 3600) as u8,
+        (sec / 60 % 60) as u8,
+        (sec % 60) as u8,
+        nanos,
+    )
+    .expect("invalid time of day");
+    time::PrimitiveDateTime::new(date, clock).assume_offset(offset)
+}
+
 // fmt_int formats v into the tail of buf.
 // It returns the index where the output begins.
 fn fmt_int(buf: &mut Vec<u8>, mut v: u64) -> i64 {
@@ -528,12 +1110,180 @@ impl Weekday {
     }
 }
 
+/// the side of an epoch that a time is on, see `Time::to_epoch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Sign {
+    /// at or after the epoch
+    Positive,
+    /// before the epoch
+    Negative,
+}
+
+impl Sign {
+    /// `v` with the sign, e.g. the microseconds of a duration
+    pub const fn apply(self, v: i64) -> i64 {
+        match self {
+            Sign::Positive => v,
+            Sign::Negative => -v,
+        }
+    }
+}
+
+/// the error of `Time::duration_since` when the time is before the other,
+/// it keeps the duration by which it's before
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negative(pub Duration);
+
+impl Negative {
+    /// the duration from the time to the later one
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl Display for Negative {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the time is {:?} before the other", self.0)
+    }
+}
+
+impl std::error::Error for Negative {}
+
 #[cfg(test)]
 mod test {
     use crate::coroutine::sleep;
     use crate::std::time::time::{Month, Time, RFC3339, RFC3339_NANO, TIME_FORMAT};
     use std::time::Duration;
 
+    #[test]
+    fn test_unix_at_offset() {
+        use super::unix_at_offset;
+        use time::{OffsetDateTime, UtcOffset};
+
+        let offsets = [0, 8 * 3600, -(5 * 3600 + 30 * 60), 14 * 3600, -12 * 3600];
+        let secs = [
+            0,
+            1,
+            -1,
+            86399,
+            86400,
+            951_782_400,
+            1_700_000_000,
+            -2_208_988_800,
+        ];
+        for &off in &offsets {
+            let offset = UtcOffset::from_whole_seconds(off).unwrap();
+            for &s in &secs {
+                let want = OffsetDateTime::from_unix_timestamp_nanos(s as i128 * 1_000_000_000 + 5)
+                    .unwrap()
+                    .to_offset(offset);
+                let got = unix_at_offset(s, 5, offset);
+                assert_eq!(got, want);
+                assert_eq!(
+                    (got.date(), got.time(), got.offset()),
+                    (want.date(), want.time(), want.offset())
+                );
+            }
+        }
+        let now = Time::now();
+        assert_eq!(now.inner.offset(), *super::GLOBAL_OFFSET);
+        assert!((Time::now_utc().unix() - now.unix()).abs() <= 1);
+    }
+
+    #[test]
+    fn test_display_precision() {
+        use super::{display_duration, Precision};
+        use time::OffsetDateTime;
+
+        let at = |nanos: i128| Time {
+            inner: OffsetDateTime::from_unix_timestamp_nanos(nanos).unwrap(),
+        };
+        // 2006-01-02T10:00:00.120Z
+        let t = at(1_136_196_000_120_000_000);
+        assert_eq!(
+            t.display_millis().to_string(),
+            "2006-01-02T10:00:00.120+00:00"
+        );
+        assert_eq!(t.display_secs().to_string(), "2006-01-02T10:00:00+00:00");
+        assert_eq!(
+            t.display_with(Precision::Micros).to_string(),
+            "2006-01-02T10:00:00.120000+00:00"
+        );
+        assert_eq!(
+            t.display_with(Precision::Nanos).to_string(),
+            "2006-01-02T10:00:00.120000000+00:00"
+        );
+        // truncated, not rounded into the next second
+        let t =
+            at(1_136_196_000_999_999_999).to_offset(time::UtcOffset::from_hms(8, 0, 0).unwrap());
+        assert_eq!(
+            t.display_millis().to_string(),
+            "2006-01-02T18:00:00.999+08:00"
+        );
+        assert_eq!(t.display_secs().to_string(), "2006-01-02T18:00:00+08:00");
+
+        let ms = |d: Duration| display_duration(d, Precision::Millis).to_string();
+        assert_eq!(ms(Duration::from_nanos(1_234_567_890)), "1.234s");
+        assert_eq!(ms(Duration::from_millis(1200)), "1.2s");
+        assert_eq!(ms(Duration::from_millis(120)), "120ms");
+        assert_eq!(ms(Duration::from_micros(1999)), "1ms");
+        assert_eq!(ms(Duration::from_micros(999)), "0ms");
+        assert_eq!(
+            display_duration(Duration::from_micros(1500), Precision::Micros).to_string(),
+            "1.5ms"
+        );
+        assert_eq!(
+            display_duration(Duration::from_secs(90), Precision::Secs).to_string(),
+            "90s"
+        );
+        assert_eq!(
+            display_duration(Duration::from_nanos(1), Precision::Secs).to_string(),
+            "0s"
+        );
+        assert_eq!(
+            display_duration(Duration::from_nanos(1_000_000_001), Precision::Nanos).to_string(),
+            "1.000000001s"
+        );
+        assert_eq!(
+            display_duration(Duration::from_nanos(7), Precision::Nanos).to_string(),
+            "7ns"
+        );
+    }
+
+    #[test]
+    fn test_serde_form() {
+        use serde::de::value::{Error, I128Deserializer, StrDeserializer};
+        use serde::de::IntoDeserializer;
+        use serde::Deserialize;
+
+        let east = time::UtcOffset::from_hms(8, 0, 0).unwrap();
+        let west = time::UtcOffset::from_hms(-3, -30, 0).unwrap();
+        let times = [
+            Time::ZERO,
+            Time::MIN,
+            Time::MAX,
+            Time::from_date(2022, 2, 3, 1, 51, 0, 933_545_800, east),
+            Time::from_date(-5, 6, 7, 8, 9, 10, 11, west),
+        ];
+        for t in times.iter() {
+            // the string doesn't depend on the formatter
+            let s = t.rfc3339_nano();
+            #[cfg(feature = "time-format")]
+            assert_eq!(s, t.format(super::RFC3339_NANO));
+            let d: StrDeserializer<Error> = s.as_str().into_deserializer();
+            assert_eq!(Time::deserialize(d).unwrap(), *t);
+            let d: I128Deserializer<Error> = t.unix_nano_i128().into_deserializer();
+            assert!(super::unix_nanos::deserialize(d).unwrap().equal(t));
+        }
+        assert_eq!(
+            Time::parse_rfc3339("2006-01-02T10:00:00Z"),
+            Time::from_unix_nano_i128(1_136_196_000_000_000_000)
+        );
+        assert_eq!(Time::parse_rfc3339("2006-01-02T10:00:00.1234567891Z"), None);
+        assert_eq!(Time::parse_rfc3339("2006-13-02T10:00:00+00:00"), None);
+        assert_eq!(Time::parse_rfc3339("2006-01-02 10:00:00+00:00"), None);
+    }
+
     #[test]
     fn test_mon() {
         let m = Month::May;
@@ -541,6 +1291,7 @@ mod test {
         assert_eq!("May", m.string());
     }
 
+    #[cfg(feature = "time-format")]
     #[test]
     fn test_parse() {
         let now = Time::now();
@@ -553,6 +1304,7 @@ mod test {
         )
     }
 
+    #[cfg(feature = "time-format")]
     #[test]
     fn test_eq() {
         let now = Time::now();
@@ -561,6 +1313,7 @@ mod test {
         assert_eq!(true, now.before(&Time::now()));
     }
 
+    #[cfg(feature = "time-format")]
     #[test]
     fn test_smoke() {
         let mut now = Time::now();
@@ -582,4 +1335,232 @@ mod test {
         println!("default(): {}", Time::default());
         assert_eq!(true, Time::default().is_zero());
     }
+
+    #[cfg(feature = "time-format")]
+    #[test]
+    fn test_add_date() {
+        let t = Time::parse(RFC3339, "2021-10-31T12:30:00+08:00").unwrap();
+        // October 31 plus one month is December 1
+        assert_eq!(
+            t.add_date(0, 1, 0).format(RFC3339),
+            "2021-12-01T12:30:00+08:00"
+        );
+        assert_eq!(
+            t.add_date(-1, 0, -31).format(RFC3339),
+            "2020-09-30T12:30:00+08:00"
+        );
+    }
+
+    #[cfg(all(feature = "tzdb", feature = "time-format"))]
+    #[test]
+    fn test_in_location() {
+        use crate::std::time::Location;
+
+        let ny = match Location::load("America/New_York") {
+            Ok(loc) => loc,
+            Err(_) => return,
+        };
+        let t = Time::parse(RFC3339, "2021-03-13T12:00:00+00:00").unwrap();
+        assert_eq!(
+            t.in_location(&ny).format(RFC3339),
+            "2021-03-13T07:00:00-05:00"
+        );
+        // the wall clock is kept across the transition
+        let next = t.add_date_in(0, 0, 1, &ny);
+        assert_eq!(next.format(RFC3339), "2021-03-14T07:00:00-04:00");
+        assert_eq!(next.unix() - t.unix(), 23 * 3600);
+    }
+
+    #[test]
+    fn test_from_date() {
+        use time::UtcOffset;
+
+        let pst = UtcOffset::from_whole_seconds(-8 * 3600).unwrap();
+        // the normalization table of golang, the many names for Fri Nov 18 7:56:35 PST 2011
+        let dates = [
+            (2011, 11, 18, 7, 56, 35, 0),
+            (2011, 11, 19, -17, 56, 35, 0),
+            (2011, 11, 17, 31, 56, 35, 0),
+            (2011, 11, 18, 6, 116, 35, 0),
+            (2011, 10, 49, 7, 56, 35, 0),
+            (2011, 11, 18, 7, 55, 95, 0),
+            (2011, 11, 18, 7, 56, 34, 1_000_000_000),
+            (2011, 12, -12, 7, 56, 35, 0),
+            (2012, 1, -43, 7, 56, 35, 0),
+            (2012, 1 - 2, 18, 7, 56, 35, 0),
+            (2010, 12 + 11, 18, 7, 56, 35, 0),
+        ];
+        for (y, m, d, h, min, s, ns) in dates.iter() {
+            let t = Time::from_date(*y, *m, *d, *h, *min, *s, *ns, pst);
+            assert_eq!(t.unix(), 1321631795, "{:?}", (y, m, d, h, min, s, ns));
+            assert_eq!(t.inner.offset(), pst);
+        }
+        assert_eq!(
+            Time::from_date(2012, 12, 24, 0, 0, 0, 0, pst).unix(),
+            1356336000
+        );
+
+        // negative nanoseconds borrow from the seconds
+        let t = Time::from_date(2011, 11, 18, 7, 56, 36, -1_000_000_000, pst);
+        assert_eq!(t.unix(), 1321631795);
+        let t = Time::from_date(2011, 11, 18, 7, 56, 36, -1, pst);
+        assert_eq!(t.unix(), 1321631795);
+        assert_eq!(t.nanosecond(), 999_999_999);
+    }
+
+    #[cfg(feature = "time-format")]
+    #[test]
+    fn test_zero() {
+        assert!(Time::ZERO.is_zero());
+        assert!(Time::default().is_zero());
+        assert_eq!(Time::ZERO.format(RFC3339), "0001-01-01T00:00:00+00:00");
+        assert_eq!(
+            Time::from_date(1, 1, 1, 0, 0, 0, 0, time::UtcOffset::UTC),
+            Time::ZERO
+        );
+        // the zero instant at another offset is still zero
+        let offset = time::UtcOffset::from_whole_seconds(3600).unwrap();
+        assert!(Time::ZERO.to_offset(offset).is_zero());
+        assert!(!Time::now().is_zero());
+        let t = Time::parse(RFC3339, "2021-10-31T12:30:00+08:00").unwrap();
+        assert!(!t.is_zero());
+    }
+
+    #[cfg(feature = "time-format")]
+    #[test]
+    fn test_saturate() {
+        let never = Time::now().add(Duration::from_secs(u64::MAX / 2));
+        assert_eq!(never, Time::MAX);
+        assert_eq!(Time::now().sub(Duration::from_secs(u64::MAX)), Time::MIN);
+        assert_eq!(Time::MAX.format(RFC3339), "9999-12-31T23:59:59+00:00");
+        assert_eq!(Time::MIN.year(), -9999);
+        assert_eq!(Time::MAX.checked_add(Duration::from_nanos(1)), None);
+        assert_eq!(Time::MIN.checked_sub(Duration::from_nanos(1)), None);
+        assert_eq!(Time::ZERO.add_sec(i64::MAX), Time::MAX);
+        assert_eq!(Time::ZERO.add_sec(i64::MIN), Time::MIN);
+
+        // the wall clock in the offset must be in the range as well
+        let offset = time::UtcOffset::from_whole_seconds(3600).unwrap();
+        let t = Time::MAX.sub(Duration::from_secs(7200)).to_offset(offset);
+        assert!(t.checked_add(Duration::from_secs(3601)).is_none());
+        assert!(t.checked_add(Duration::from_secs(3600)).is_some());
+
+        // so are the dates
+        let utc = time::UtcOffset::UTC;
+        assert_eq!(Time::from_date(10000, 1, 1, 0, 0, 0, 0, utc), Time::MAX);
+        assert_eq!(Time::from_date(-10000, 1, 1, 0, 0, 0, 0, utc), Time::MIN);
+        assert_eq!(
+            Time::from_date(i32::MAX, i32::MAX, 1, 0, 0, 0, 0, utc),
+            Time::MAX
+        );
+        assert_eq!(Time::MAX.add_date(0, 0, 1), Time::MAX);
+        assert_eq!(Time::MIN.add_date(0, -1, 0), Time::MIN);
+        assert_eq!(Time::now().add_date(i32::MIN, 0, 0), Time::MIN);
+        assert_eq!(t.add_date(0, 0, 1), Time::MAX);
+        assert_ne!(t.add_date(0, 0, -1), Time::MAX);
+
+        // nanoseconds in an i64 end in 2262, the seconds go on
+        assert_eq!(Time::MAX.unix_nano(), i64::MAX);
+        assert_eq!(Time::MIN.unix_nano(), i64::MIN);
+        assert_eq!(Time::MAX.unix(), 253_402_300_799);
+        assert_eq!(Time::MAX.unix_nano_i128(), 253_402_300_799_999_999_999);
+        let t = Time::from_date(2262, 4, 11, 23, 47, 16, 854_775_807, time::UtcOffset::UTC);
+        assert_eq!(t.unix_nano(), i64::MAX);
+        assert_eq!(t.add(Duration::from_nanos(1)).unix_nano(), i64::MAX);
+    }
+
+    #[test]
+    fn test_epochs() {
+        use super::{Negative, Sign};
+        use time::UtcOffset;
+
+        assert_eq!(Time::UNIX_EPOCH.unix_nano_i128(), 0);
+        assert_eq!(Time::Y2K.unix(), 946_684_800);
+        assert_eq!(
+            Time::Y2K.duration_since(&Time::UNIX_EPOCH),
+            Ok(Duration::from_secs(946_684_800))
+        );
+        assert_eq!(
+            Time::UNIX_EPOCH.duration_since(&Time::Y2K),
+            Err(Negative(Duration::from_secs(946_684_800)))
+        );
+        assert_eq!(
+            Time::Y2K.to_epoch(&Time::Y2K),
+            (Sign::Positive, Duration::ZERO)
+        );
+
+        // the days, seconds and micros of a time before the epoch
+        let t = Time::from_date(1999, 12, 30, 22, 0, 0, 500_001_000, UtcOffset::UTC);
+        let (sign, d) = t.to_epoch(&Time::Y2K);
+        assert_eq!(sign, Sign::Negative);
+        let (days, secs) = (d.as_secs() / 86400, d.as_secs() % 86400);
+        assert_eq!((days, secs, d.subsec_micros()), (1, 7199, 499_999));
+        assert_eq!(
+            sign.apply(d.as_micros() as i64),
+            -(26 * 3600 * 1_000_000 - 500_001)
+        );
+        assert_eq!(Time::from_epoch_signed(Time::Y2K, sign, d), Some(t.clone()));
+
+        // the nanos and the offset of the epoch are kept
+        let t = Time::from_date(1969, 12, 31, 23, 59, 59, 1, UtcOffset::UTC);
+        let (sign, d) = t.to_epoch(&Time::UNIX_EPOCH);
+        assert_eq!((sign, d), (Sign::Negative, Duration::new(0, 999_999_999)));
+        assert_eq!(Time::from_epoch_signed(Time::UNIX_EPOCH, sign, d), Some(t));
+        let east = Time::Y2K.to_offset(UtcOffset::from_whole_seconds(8 * 3600).unwrap());
+        let t = Time::from_epoch(east, Duration::new(1, 7)).unwrap();
+        assert_eq!((t.hour(), t.second(), t.nanosecond()), (8, 1, 7));
+
+        assert_eq!(Time::from_epoch(Time::MAX, Duration::from_nanos(1)), None);
+        assert_eq!(
+            Time::from_epoch_signed(Time::MIN, Sign::Negative, Duration::from_nanos(1)),
+            None
+        );
+        let (sign, d) = Time::MIN.to_epoch(&Time::MAX);
+        assert_eq!(sign, Sign::Negative);
+        assert_eq!(Time::from_epoch_signed(Time::MAX, sign, d), Some(Time::MIN));
+    }
+
+    #[test]
+    fn test_const() {
+        use std::cmp::Ordering;
+
+        const START: Time = match Time::from_unix(1_000, 5) {
+            Some(t) => t,
+            None => panic!(),
+        };
+        const END: Time = START.add(Duration::new(10, 1)).add_sec(-2);
+        const _: () = assert!(END.unix() == 1_008 && END.unix_nano() == 1_008_000_000_006);
+        const _: () = assert!(END.after(&START) && START.before(&END));
+        const _: () = assert!(Time::ZERO.is_zero() && !super::UNIX_EPOCH.is_zero());
+        const _: () = assert!(Time::from_unix(0, 1_000_000_000).is_none());
+        const _: () = assert!(Time::MAX.checked_add(Duration::from_nanos(1)).is_none());
+        const SAT: Time = Time::MIN.sub(Duration::from_secs(1));
+        const _: () = assert!(SAT.equal(&Time::MIN));
+
+        assert_eq!(START, Time::from_unix_nano(1_000_000_000_005));
+        assert_eq!(START.cmp_instant(&END), Ordering::Less);
+        assert_eq!(super::UNIX_EPOCH, Time::from_unix_nano(0));
+        assert_eq!(Time::from_unix_nano_i128(i128::MAX), None);
+        // the same instant in another offset
+        let east = START.to_offset(time::UtcOffset::from_hms(8, 0, 0).unwrap());
+        assert_eq!(east.cmp_instant(&START), Ordering::Equal);
+        assert!(east.equal(&START));
+    }
+}
+
+// the crate is checked with `--no-default-features` as well, `Time` must keep
+// working without the formatter
+#[cfg(all(test, not(feature = "time-format")))]
+mod no_format_test {
+    use crate::std::time::Time;
+    use std::time::Duration;
+
+    #[test]
+    fn test_without_format() {
+        let t = Time::from_date(2022, 2, 3, 1, 51, 0, 933_545_800, time::UtcOffset::UTC);
+        assert_eq!(t.to_string(), "2022-02-03T01:51:00.9335458+00:00");
+        assert_eq!(t.clone().add(Duration::from_secs(60)).minute(), 52);
+        assert_eq!(Time::ZERO.to_string(), "0001-01-01T00:00:00.0+00:00");
+        assert!(Time::now().after(&t));
+    }
 }
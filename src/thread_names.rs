@@ -0,0 +1,95 @@
+//! the names of the threads of the runtime, see
+//! `config().set_thread_name_prefix`
+//!
+//! the threads are named `<prefix>-worker-<id>`, `<prefix>-timer`,
+//! `<prefix>-blocking-<n>` and so on, std hands the names to the os so that
+//! they show in `top -H`, gdb and perf. linux cuts them at 15 bytes
+//!
+//! with the `co-thread-names` feature on linux a worker can take the name of
+//! the coroutine that it runs, see `config().set_coroutine_thread_names`
+
+use parking_lot::Mutex;
+
+const DEFAULT_PREFIX: &str = "mco";
+
+// empty for the default one
+static PREFIX: Mutex<String> = parking_lot::const_mutex(String::new());
+
+pub(crate) fn set_prefix(prefix: &str) {
+    *PREFIX.lock() = prefix.to_owned();
+}
+
+pub(crate) fn prefix() -> String {
+    let prefix = PREFIX.lock();
+    if prefix.is_empty() {
+        DEFAULT_PREFIX.to_owned()
+    } else {
+        prefix.clone()
+    }
+}
+
+// the name of a thread of `kind`, like `mco-timer`
+pub(crate) fn name(kind: &str) -> String {
+    format!("{}-{}", prefix(), kind)
+}
+
+pub(crate) fn worker_name(id: usize) -> String {
+    name(&format!("worker-{}", id))
+}
+
+#[cfg(all(feature = "co-thread-names", target_os = "linux"))]
+pub(crate) use self::comm::{comm_enabled, set_comm, set_comm_enabled};
+
+#[cfg(all(feature = "co-thread-names", target_os = "linux"))]
+mod comm {
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    // the bytes of a `comm` without the nul
+    const COMM_LEN: usize = 15;
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+
+    thread_local! {
+        // the coroutine name that the thread has, all zero for its own name
+        static COMM: Cell<[u8; COMM_LEN + 1]> = Cell::new([0; COMM_LEN + 1]);
+    }
+
+    pub(crate) fn set_comm_enabled(enable: bool) {
+        ENABLED.store(enable, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn comm_enabled() -> bool {
+        ENABLED.load(Ordering::Relaxed)
+    }
+
+    // the name cut to the bytes of a `comm` on a char boundary
+    fn comm(name: &str) -> [u8; COMM_LEN + 1] {
+        let name = name.split('\0').next().unwrap_or("");
+        let mut end = name.len().min(COMM_LEN);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut buf = [0; COMM_LEN + 1];
+        buf[..end].copy_from_slice(&name.as_bytes()[..end]);
+        buf
+    }
+
+    // name the thread after the coroutine that it's about to run, an unnamed
+    // one gives the thread its own name back. the syscall is only made when
+    // the name changes, so a worker that runs the coroutines of the same
+    // name doesn't pay for it
+    pub(crate) fn set_comm(name: Option<&str>) {
+        let want = name.map_or([0; COMM_LEN + 1], comm);
+        if COMM.with(|c| c.replace(want)) == want {
+            return;
+        }
+        let buf = match name {
+            Some(_) => want,
+            None => comm(thread::current().name().unwrap_or("")),
+        };
+        unsafe { libc::prctl(libc::PR_SET_NAME, buf.as_ptr() as libc::c_ulong, 0, 0, 0) };
+    }
+}
@@ -1,11 +1,13 @@
+use std::cell::RefCell;
 use std::cmp;
 use std::collections::{BinaryHeap, HashMap};
 use std::mem;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::determinism;
 use crate::std::queue::mpsc_list_v1::Entry;
 use crate::std::queue::mpsc_list_v1::Queue as TimeoutQueue;
 use crate::std::queue::seg_queue::SegQueue as mpsc;
@@ -39,13 +41,28 @@ pub fn ns_to_ms(ns: u64) -> u64 {
 
 pub static START_TIME: Lazy<Instant> = Lazy::new(|| Instant::now());
 
-// get the current wall clock in ns
+// get the real clock in ns since the start
 #[inline]
-pub fn now() -> u64 {
+pub fn real_now() -> u64 {
     // we need a Monotonic Clock here
     START_TIME.elapsed().as_nanos() as u64
 }
 
+// get the current clock in ns that the timers run on
+#[inline]
+pub fn now() -> u64 {
+    #[cfg(feature = "test-util")]
+    return crate::std::time::clock::adjust(real_now());
+    #[cfg(not(feature = "test-util"))]
+    real_now()
+}
+
+// the `Instant` of the timer clock, for the deadline checks of the timeouts
+#[inline]
+pub fn now_instant() -> Instant {
+    *START_TIME + ns_to_dur(now())
+}
+
 // timeout event data
 pub struct TimeoutData<T> {
     time: u64,
@@ -86,11 +103,11 @@ impl<T> IntervalEntry<T> {
     // return next expire time
     pub fn pop_timeout<F>(&self, now: u64, f: &F) -> Option<u64>
     where
-        F: Fn(T),
+        F: Fn(u64, T),
     {
         let p = |v: &TimeoutData<T>| v.time <= now;
         while let Some(timeout) = self.list.inner.pop_if(&p) {
-            f(timeout.data);
+            f(timeout.time, timeout.data);
         }
         self.list.inner.peek().map(|t| t.time)
     }
@@ -206,6 +223,24 @@ impl<T> TimeOutList<T> {
     // and call the supplied function with registered data
     // return the time in ns for the next expiration
     pub fn schedule_timer<F: Fn(T)>(&self, now: u64, f: &F) -> Option<u64> {
+        if !determinism::enabled() {
+            return self.expire(now, &|_, data| f(data));
+        }
+        // a deterministic run fires the due timers by their deadlines, and
+        // the ties in a seeded order instead of the order of the heap
+        let due = RefCell::new(Vec::new());
+        let next = self.expire(now, &|time, data| {
+            due.borrow_mut()
+                .push((time, determinism::next_u64(!0), data))
+        });
+        let mut due = due.into_inner();
+        due.sort_by_key(|d| (d.0, d.1));
+        due.into_iter().for_each(|(_, _, data)| f(data));
+        next
+    }
+
+    // remove the expired timeout events and give them to `f` with their time
+    fn expire<F: Fn(u64, T)>(&self, now: u64, f: &F) -> Option<u64> {
         loop {
             // first peek the BH to see if there is any timeout event
             let mut entry = {
@@ -267,6 +302,16 @@ pub struct TimerThread<T> {
     remove_list: mpsc<TimeoutHandle<T>>,
     // the timer thread wakeup handler
     wakeup: AtomicCell<Option<thread::Thread>>,
+    // the timer thread exits when it's set
+    stopped: AtomicBool,
+    // the sync requests and the last one that the timer thread has seen
+    #[cfg(feature = "test-util")]
+    sync_req: AtomicUsize,
+    #[cfg(feature = "test-util")]
+    sync_done: AtomicUsize,
+    // the timers that are not fired or removed yet
+    #[cfg(feature = "test-util")]
+    pending: AtomicUsize,
 }
 
 impl<T> TimerThread<T> {
@@ -275,10 +320,38 @@ impl<T> TimerThread<T> {
             timer_list: TimeOutList::new(),
             remove_list: mpsc::new(),
             wakeup: AtomicCell::new(None),
+            stopped: AtomicBool::new(false),
+            #[cfg(feature = "test-util")]
+            sync_req: AtomicUsize::new(0),
+            #[cfg(feature = "test-util")]
+            sync_done: AtomicUsize::new(0),
+            #[cfg(feature = "test-util")]
+            pending: AtomicUsize::new(0),
+        }
+    }
+
+    // the timers that are not fired or removed yet, a removed timer is
+    // counted till the timer thread takes the remove request
+    #[cfg(feature = "test-util")]
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Acquire)
+    }
+
+    // wait until the timer thread has run the timers with the current clock
+    #[cfg(feature = "test-util")]
+    pub fn sync(&self) {
+        let req = self.sync_req.fetch_add(1, Ordering::AcqRel) + 1;
+        while self.sync_done.load(Ordering::Acquire) < req {
+            if let Some(t) = self.wakeup.take() {
+                t.unpark();
+            }
+            thread::yield_now();
         }
     }
 
     pub fn add_timer(&self, dur: Duration, data: T) -> TimeoutHandle<T> {
+        #[cfg(feature = "test-util")]
+        self.pending.fetch_add(1, Ordering::AcqRel);
         let (h, is_recal) = self.timer_list.add_timer(dur, data);
         // wake up the timer thread if it's a new queue
         if is_recal {
@@ -289,6 +362,14 @@ impl<T> TimerThread<T> {
         h
     }
 
+    // stop the timer thread, the timers left never fire
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(t) = self.wakeup.take() {
+            t.unpark();
+        }
+    }
+
     pub fn del_timer(&self, handle: TimeoutHandle<T>) {
         self.remove_list.push(handle);
         if let Some(t) = self.wakeup.take() {
@@ -301,11 +382,18 @@ impl<T> TimerThread<T> {
         let current_thread = thread::current();
         loop {
             while let Some(h) = self.remove_list.pop() {
-                h.remove();
+                let _removed = h.remove();
+                #[cfg(feature = "test-util")]
+                if _removed.is_some() {
+                    self.pending.fetch_sub(1, Ordering::AcqRel);
+                }
             }
             // we must register the thread handle first
             // or there will be no signal to wakeup the timer thread
             self.wakeup.swap(Some(current_thread.clone()));
+            if self.stopped.load(Ordering::SeqCst) {
+                return;
+            }
 
             if !self.remove_list.is_empty() {
                 if let Some(t) = self.wakeup.take() {
@@ -313,7 +401,17 @@ impl<T> TimerThread<T> {
                 }
             }
 
-            match self.timer_list.schedule_timer(now(), f) {
+            #[cfg(feature = "test-util")]
+            let req = self.sync_req.load(Ordering::Acquire);
+            #[cfg(feature = "test-util")]
+            let f = &|data: T| {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                f(data)
+            };
+            let next = self.timer_list.schedule_timer(now(), f);
+            #[cfg(feature = "test-util")]
+            self.sync_done.store(req, Ordering::Release);
+            match next {
                 Some(time) => thread::park_timeout(ns_to_dur(time)),
                 None => thread::park(),
             }
@@ -0,0 +1,773 @@
+//! the watchdog of the long running coroutines
+//!
+//! a coroutine that computes without calling into the crate is never
+//! switched out, it holds its worker until it's done. with a time slice set
+//! by `config().set_time_slice()`, a watchdog thread samples what the
+//! workers run, a coroutine that runs longer than the slice is logged, listed
+//! by `coroutine::dump()` and flagged, so that its next `checkpoint()` yields
+//!
+//! it also watches for the stalled workers. with a threshold set by
+//! `config().set_stall_threshold()`, each worker heartbeats when it switches
+//! in a coroutine and on each round of its event loop, and marks itself idle
+//! before it waits for the io events. a busy worker whose heartbeat is older
+//! than the threshold is stuck, e.g. in a blocking ffi call: it's logged with
+//! the coroutine that it runs, counted by `stats().stalls`, reported to the
+//! `config().set_on_stall()` callback and, with `config().set_stall_rescue()`,
+//! the coroutines queued on it are moved to the global queue so that the
+//! other workers run them. the stack of another thread can't be captured, the
+//! name of the coroutine is all that is logged
+//!
+//! and it catches the blocking calls on the workers. a resume that holds its
+//! worker longer than `config().set_blocking_threshold()` before it gets to a
+//! yield point of the crate, e.g. in `std::thread::sleep` or the lock of a
+//! `std::sync::Mutex`, is logged with the coroutine when it switches out and
+//! counted by `stats().blocking_resumes`. it's measured on the worker from
+//! the same slot, no watchdog thread is needed. a resume that enters a
+//! `coroutine::blocking_zone` is not checked
+//!
+//! the workers only bump a counter, heartbeat and record the running coroutine
+//! when the slice, the stall threshold or the blocking threshold is set. the
+//! blocking threshold is `DEBUG_BLOCKING_THRESHOLD` in the debug builds, the
+//! others are off by default
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+
+use crate::coroutine_impl::{Coroutine, CoroutineId, Tag};
+use crate::scheduler::{get_scheduler, worker_id, SchedRef, Scheduler};
+#[cfg(feature = "chan-registry")]
+use crate::std::sync::registry::{self, ChannelWait};
+use crate::thread_names;
+use crate::yield_now::yield_now;
+
+// the time slice in nanoseconds, 0 for no watchdog
+static SLICE: AtomicU64 = AtomicU64::new(0);
+// the coroutines that are flagged as overrunning
+pub(crate) static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+// the stall threshold in nanoseconds, 0 for off
+static STALL: AtomicU64 = AtomicU64::new(0);
+// move the queued coroutines off a stalled worker
+static RESCUE: AtomicBool = AtomicBool::new(false);
+// the stalls found and the coroutines moved off the stalled workers
+pub(crate) static STALLS: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static RESCUED: AtomicUsize = AtomicUsize::new(0);
+type OnStall = Box<dyn Fn(&Stall) + Send + Sync>;
+static ON_STALL: RwLock<Option<OnStall>> = parking_lot::const_rwlock(None);
+// the blocking threshold in nanoseconds, 0 for off
+static BLOCKING: AtomicU64 = AtomicU64::new(if cfg!(debug_assertions) {
+    DEBUG_BLOCKING_THRESHOLD.as_nanos() as u64
+} else {
+    0
+});
+// the resumes over the blocking threshold
+pub(crate) static BLOCKED: AtomicUsize = AtomicUsize::new(0);
+// the clock of the heartbeats
+static BASE: Lazy<Instant> = Lazy::new(Instant::now);
+// the schedulers to watch, they are never freed
+static WATCHED: Mutex<Vec<usize>> = parking_lot::const_mutex(Vec::new());
+
+// the flag of the threads that are not workers, it's never set
+static NEVER: AtomicBool = AtomicBool::new(false);
+
+// the overrun flag of the worker that the thread runs
+#[cfg(nightly)]
+#[thread_local]
+static OVERRUN: AtomicPtr<AtomicBool> = AtomicPtr::new(&NEVER as *const _ as *mut _);
+
+#[cfg(not(nightly))]
+thread_local! {
+    static OVERRUN: AtomicPtr<AtomicBool> = AtomicPtr::new(&NEVER as *const _ as *mut _);
+}
+
+#[inline]
+fn overrun_flag() -> &'static AtomicBool {
+    #[cfg(nightly)]
+    let p = OVERRUN.load(Ordering::Relaxed);
+    #[cfg(not(nightly))]
+    let p = OVERRUN.with(|p| p.load(Ordering::Relaxed));
+    unsafe { &*p }
+}
+
+pub(crate) fn set_time_slice(slice: Duration) {
+    let nanos = slice.as_nanos().min(u64::MAX as u128) as u64;
+    SLICE.store(nanos, Ordering::Relaxed);
+    if nanos != 0 {
+        start();
+    }
+}
+
+pub(crate) fn get_time_slice() -> Duration {
+    Duration::from_nanos(SLICE.load(Ordering::Relaxed))
+}
+
+pub(crate) fn set_stall_threshold(threshold: Duration) {
+    let nanos = threshold.as_nanos().min(u64::MAX as u128) as u64;
+    STALL.store(nanos, Ordering::Relaxed);
+    if nanos != 0 {
+        start();
+    }
+}
+
+pub(crate) fn get_stall_threshold() -> Duration {
+    Duration::from_nanos(STALL.load(Ordering::Relaxed))
+}
+
+/// the default blocking threshold of the debug builds, see
+/// `config().set_blocking_threshold()`
+pub const DEBUG_BLOCKING_THRESHOLD: Duration = Duration::from_millis(10);
+
+pub(crate) fn set_blocking_threshold(threshold: Duration) {
+    let nanos = threshold.as_nanos().min(u64::MAX as u128) as u64;
+    BLOCKING.store(nanos, Ordering::Relaxed);
+}
+
+pub(crate) fn get_blocking_threshold() -> Duration {
+    Duration::from_nanos(BLOCKING.load(Ordering::Relaxed))
+}
+
+pub(crate) fn set_stall_rescue(rescue: bool) {
+    RESCUE.store(rescue, Ordering::Relaxed);
+}
+
+pub(crate) fn get_stall_rescue() -> bool {
+    RESCUE.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_on_stall(f: Option<OnStall>) {
+    *ON_STALL.write() = f;
+}
+
+// the watchdog thread is needed
+#[inline]
+fn watching() -> bool {
+    SLICE.load(Ordering::Relaxed) != 0 || STALL.load(Ordering::Relaxed) != 0
+}
+
+#[inline]
+pub(crate) fn enabled() -> bool {
+    watching() || BLOCKING.load(Ordering::Relaxed) != 0
+}
+
+// the heartbeat clock, never 0 that is the idle mark
+#[inline]
+fn beat_now() -> u64 {
+    BASE.elapsed().as_nanos() as u64 + 1
+}
+
+// what the watchdog saw on a worker
+struct Seen {
+    seq: usize,
+    since: Instant,
+    flagged: bool,
+}
+
+/// the running state of a worker, watched by the watchdog
+pub(crate) struct RunSlot {
+    // odd while a coroutine is running on the worker
+    seq: AtomicUsize,
+    overrun: AtomicBool,
+    current: Mutex<Option<Coroutine>>,
+    seen: Mutex<Seen>,
+    // the last heartbeat of the worker, 0 while it's idle
+    beat: AtomicU64,
+    // the heartbeat of the last stall that is reported
+    reported: AtomicU64,
+    // when the running coroutine is switched in, 0 if it's not checked for
+    // the blocking threshold
+    resumed: AtomicU64,
+}
+
+impl RunSlot {
+    pub(crate) fn new() -> Self {
+        RunSlot {
+            seq: AtomicUsize::new(0),
+            overrun: AtomicBool::new(false),
+            current: Mutex::new(None),
+            seen: Mutex::new(Seen {
+                seq: 0,
+                since: Instant::now(),
+                flagged: false,
+            }),
+            beat: AtomicU64::new(0),
+            reported: AtomicU64::new(0),
+            resumed: AtomicU64::new(0),
+        }
+    }
+
+    // the worker is idle, it's never stalled. a resume that blocks on
+    // purpose is not checked for the blocking threshold either
+    #[inline]
+    pub(crate) fn idle(&self) {
+        self.beat.store(0, Ordering::Release);
+        self.resumed.store(0, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn heartbeat(&self) {
+        if STALL.load(Ordering::Relaxed) != 0 {
+            self.beat.store(beat_now(), Ordering::Release);
+        }
+    }
+
+    // the coroutine that the worker runs, only recorded with the watchdog on
+    #[cfg(feature = "diagnostics")]
+    pub(crate) fn running(&self) -> Option<(CoroutineId, Option<String>)> {
+        if self.seq.load(Ordering::Acquire) & 1 == 0 {
+            return None;
+        }
+        let current = self.current.lock();
+        let co = current.as_ref()?;
+        Some((co.id(), co.name().map(String::from)))
+    }
+
+    // the coroutine that's running on the worker, only recorded with the
+    // watchdog on
+    fn current(&self) -> Option<Coroutine> {
+        if self.seq.load(Ordering::Acquire) & 1 == 0 {
+            return None;
+        }
+        self.current.lock().clone()
+    }
+
+    // the coroutine and how long it's running if it's overrunning the slice
+    fn overrun(&self, slice: Duration) -> Option<(Coroutine, Duration)> {
+        let seq = self.seq.load(Ordering::Acquire);
+        let seen = self.seen.lock();
+        if seq & 1 == 0 || seq != seen.seq {
+            return None;
+        }
+        let running = seen.since.elapsed();
+        if running < slice {
+            return None;
+        }
+        let co = self.current.lock().clone()?;
+        Some((co, running))
+    }
+}
+
+// let the thread see the overrun flag of its worker
+pub(crate) fn set_worker_slot(slot: &'static RunSlot) {
+    let p = &slot.overrun as *const _ as *mut _;
+    #[cfg(nightly)]
+    OVERRUN.store(p, Ordering::Relaxed);
+    #[cfg(not(nightly))]
+    OVERRUN.with(|o| o.store(p, Ordering::Relaxed));
+}
+
+// mark that the worker starts running the coroutine
+#[inline]
+pub(crate) fn enter(co: &Coroutine) -> Option<&'static RunSlot> {
+    let id = worker_id();
+    if id == !1 {
+        return None;
+    }
+    let slot = get_scheduler().run_slot(id)?;
+    slot.heartbeat();
+    let resumed = if BLOCKING.load(Ordering::Relaxed) != 0 {
+        beat_now()
+    } else {
+        0
+    };
+    slot.resumed.store(resumed, Ordering::Relaxed);
+    *slot.current.lock() = Some(co.clone());
+    slot.overrun.store(false, Ordering::Relaxed);
+    slot.seq.fetch_add(1, Ordering::Release);
+    Some(slot)
+}
+
+// mark that the coroutine is switched out
+#[inline]
+pub(crate) fn leave(slot: &RunSlot) {
+    slot.seq.fetch_add(1, Ordering::Release);
+    slot.overrun.store(false, Ordering::Relaxed);
+    let co = slot.current.lock().take();
+    let resumed = slot.resumed.swap(0, Ordering::Relaxed);
+    let threshold = BLOCKING.load(Ordering::Relaxed);
+    if resumed != 0 && threshold != 0 {
+        let held = beat_now().saturating_sub(resumed);
+        if held > threshold {
+            blocked(co, held, threshold);
+        }
+    }
+}
+
+#[cold]
+fn blocked(co: Option<Coroutine>, held: u64, threshold: u64) {
+    BLOCKED.fetch_add(1, Ordering::Relaxed);
+    let (held, threshold) = (Duration::from_nanos(held), Duration::from_nanos(threshold));
+    if let Some(co) = co {
+        warn!(
+            "coroutine #{} {:?} held worker {} for {:?} without a yield point, over the \
+             blocking threshold {:?}. a blocking call like std::thread::sleep or \
+             std::sync::Mutex::lock? see mco::std::compat",
+            co.id(),
+            co.name(),
+            worker_id(),
+            held,
+            threshold
+        );
+    }
+}
+
+// watch the workers of the scheduler
+pub(crate) fn watch(s: &'static Scheduler) {
+    WATCHED.lock().push(s as *const Scheduler as usize);
+    if watching() {
+        start();
+    }
+}
+
+// stop watching the scheduler of a runtime that is dropped
+pub(crate) fn unwatch(s: &Scheduler) {
+    let s = s as *const Scheduler as usize;
+    WATCHED.lock().retain(|w| *w != s);
+}
+
+// the schedulers are referenced while they are checked, a runtime may be
+// dropped in the meantime
+fn watched() -> Vec<SchedRef> {
+    let watched = WATCHED.lock();
+    watched
+        .iter()
+        .map(|s| unsafe { &*(*s as *const Scheduler) })
+        .filter(|s| !s.is_shutdown())
+        .map(SchedRef::new)
+        .collect()
+}
+
+fn start() {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        thread::Builder::new()
+            .name(thread_names::name("watchdog"))
+            .spawn(run)
+            .expect("can't start the watchdog thread");
+    });
+}
+
+fn run() {
+    let zero = Duration::from_nanos(0);
+    loop {
+        let slice = get_time_slice();
+        let stall = get_stall_threshold();
+        let shortest = match (slice, stall) {
+            (s, t) if s == zero && t == zero => {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            (s, t) if s == zero => t,
+            (s, t) if t == zero => s,
+            (s, t) => s.min(t),
+        };
+        // the running time is up to a period shorter than the real one
+        let period = (shortest / 4).max(Duration::from_millis(1));
+        thread::sleep(period.min(Duration::from_millis(100)));
+        for s in watched() {
+            for id in 0..s.max_workers() {
+                if let Some(slot) = s.run_slot(id) {
+                    if slice != zero {
+                        check(slot, id, slice);
+                    }
+                    if stall != zero && s.is_active(id) {
+                        check_stall(&s, slot, id, stall);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn check_stall(s: &Scheduler, slot: &RunSlot, id: usize, threshold: Duration) {
+    let beat = slot.beat.load(Ordering::Acquire);
+    if beat == 0 || slot.reported.load(Ordering::Relaxed) == beat {
+        return;
+    }
+    let stalled = Duration::from_nanos(beat_now().saturating_sub(beat));
+    if stalled < threshold {
+        return;
+    }
+    // one report for each stall
+    slot.reported.store(beat, Ordering::Relaxed);
+    STALLS.fetch_add(1, Ordering::Relaxed);
+    let co = slot
+        .current
+        .lock()
+        .as_ref()
+        .map(|c| (c.id(), c.name().map(String::from)));
+    let rescued = if get_stall_rescue() { s.rescue(id) } else { 0 };
+    RESCUED.fetch_add(rescued, Ordering::Relaxed);
+    match &co {
+        Some((co_id, name)) => warn!(
+            "worker {} is stalled for {:?} running coroutine #{} {:?}, {} coroutines moved off it",
+            id, stalled, co_id, name, rescued
+        ),
+        None => warn!(
+            "worker {} is stalled for {:?} outside of a coroutine, {} coroutines moved off it",
+            id, stalled, rescued
+        ),
+    }
+    if let Some(f) = ON_STALL.read().as_ref() {
+        f(&Stall {
+            worker: id,
+            running: co.is_some(),
+            id: co.as_ref().map(|c| c.0),
+            name: co.and_then(|c| c.1),
+            stalled,
+            rescued,
+        });
+    }
+}
+
+fn check(slot: &RunSlot, id: usize, slice: Duration) {
+    let seq = slot.seq.load(Ordering::Acquire);
+    let mut seen = slot.seen.lock();
+    if seq & 1 == 0 || seq != seen.seq {
+        *seen = Seen {
+            seq,
+            since: Instant::now(),
+            flagged: false,
+        };
+        return;
+    }
+    let running = seen.since.elapsed();
+    if running < slice || seen.flagged {
+        return;
+    }
+    seen.flagged = true;
+    slot.overrun.store(true, Ordering::Relaxed);
+    OVERRUNS.fetch_add(1, Ordering::Relaxed);
+    let co = slot
+        .current
+        .lock()
+        .as_ref()
+        .map(|c| (c.id(), c.name().map(String::from)));
+    if let Some((co_id, name)) = co {
+        warn!(
+            "coroutine #{} {:?} is running on worker {} for {:?}, over the time slice {:?}",
+            co_id, name, id, running, slice
+        );
+    }
+}
+
+/// yield if the watchdog flagged the coroutine as overrunning its time slice
+///
+/// it's one relaxed load when not flagged, cheap enough for the hot loops of
+/// a long computation. it does nothing in the thread context or without a
+/// time slice set, see `config().set_time_slice()`
+/// ```
+/// let h = mco::co!(|| {
+///     let mut sum = 0u64;
+///     for i in 0..1_000_000u64 {
+///         sum = sum.wrapping_add(i * i);
+///         mco::coroutine::checkpoint();
+///     }
+///     sum
+/// });
+/// h.join().unwrap();
+/// ```
+#[inline]
+pub fn checkpoint() {
+    if overrun_flag().load(Ordering::Relaxed) {
+        yield_now();
+    }
+}
+
+/// a coroutine that runs longer than the time slice without switching out
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Overrun {
+    /// the id of the coroutine
+    pub id: CoroutineId,
+    /// the name of the coroutine
+    pub name: Option<String>,
+    /// the tag of the coroutine, see `Builder::tag`
+    pub tag: Option<Tag>,
+    /// the worker that runs it
+    pub worker: usize,
+    /// how long it's running since it's switched in, measured by the
+    /// watchdog so it may be a bit shorter than the real one
+    pub running: Duration,
+}
+
+/// a worker that is stuck, see `config().set_stall_threshold()`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Stall {
+    /// the stalled worker
+    pub worker: usize,
+    /// true if it's stuck in a coroutine
+    pub running: bool,
+    /// the id of the coroutine that it runs
+    pub id: Option<CoroutineId>,
+    /// the name of the coroutine that it runs
+    pub name: Option<String>,
+    /// how long since its last heartbeat, measured by the watchdog so it
+    /// may be a bit shorter than the real one
+    pub stalled: Duration,
+    /// the queued coroutines that are moved off it
+    pub rescued: usize,
+}
+
+/// a coroutine in the snapshot, see `dump`
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct CoroutineInfo {
+    /// the id of the coroutine
+    pub id: CoroutineId,
+    /// the name of the coroutine
+    pub name: Option<String>,
+    /// the tag of the coroutine, see `Builder::tag`
+    pub tag: Option<Tag>,
+    /// true if it's running on a worker when the snapshot is taken
+    pub running: bool,
+    /// the worker that it runs on, or last ran on. the last worker is only
+    /// tracked with the worker affinity set
+    pub worker: Option<usize>,
+    /// the cpu that it last ran on, tracked like the last worker
+    pub cpu: Option<usize>,
+    /// the channel that it's parked on
+    #[cfg(feature = "chan-registry")]
+    pub waits_on: Option<ChannelWait>,
+}
+
+impl CoroutineInfo {
+    fn new(co: &Coroutine, running: bool) -> Self {
+        CoroutineInfo {
+            id: co.id(),
+            name: co.name().map(String::from),
+            tag: co.tag().cloned(),
+            running,
+            worker: co.last_worker(),
+            cpu: co.last_cpu(),
+            #[cfg(feature = "chan-registry")]
+            waits_on: None,
+        }
+    }
+}
+
+/// a snapshot of the coroutines, see `dump`
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Dump {
+    /// the coroutines that overrun the time slice, empty if no time slice
+    /// is set
+    pub overruns: Vec<Overrun>,
+    /// the coroutines that are running on the workers, by id. they are only
+    /// recorded while the watchdog is on, see the module docs. with the
+    /// `chan-registry` feature the coroutines parked on a channel are listed
+    /// too
+    pub coroutines: Vec<CoroutineInfo>,
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "overrunning coroutines: {}", self.overruns.len())?;
+        for o in self.overruns.iter() {
+            write!(
+                f,
+                "  #{} {}",
+                o.id,
+                o.name.as_deref().unwrap_or("<unnamed>")
+            )?;
+            if let Some(tag) = &o.tag {
+                write!(f, " tag {}", tag)?;
+            }
+            writeln!(f, " on worker {} running for {:?}", o.worker, o.running)?;
+        }
+        writeln!(f, "coroutines: {}", self.coroutines.len())?;
+        for c in self.coroutines.iter() {
+            write!(
+                f,
+                "  #{} {}",
+                c.id,
+                c.name.as_deref().unwrap_or("<unnamed>")
+            )?;
+            if let Some(tag) = &c.tag {
+                write!(f, " tag {}", tag)?;
+            }
+            f.write_str(if c.running { " running" } else { " parked" })?;
+            if let Some(worker) = c.worker {
+                write!(f, " on worker {}", worker)?;
+            }
+            if let Some(cpu) = c.cpu {
+                write!(f, " cpu {}", cpu)?;
+            }
+            #[cfg(feature = "chan-registry")]
+            if let Some(w) = &c.waits_on {
+                write!(
+                    f,
+                    " for {:?} to {} on channel #{} created at {} with {} senders",
+                    w.parked_for,
+                    if w.sending { "send" } else { "receive" },
+                    w.channel,
+                    w.location,
+                    w.senders
+                )?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// take a snapshot of the coroutines of all the runtimes
+pub fn dump() -> Dump {
+    let mut dump = Dump::default();
+    let slice = get_time_slice();
+    for s in watched() {
+        for id in 0..s.max_workers() {
+            let slot = match s.run_slot(id) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            if let Some(co) = slot.current() {
+                let mut info = CoroutineInfo::new(&co, true);
+                info.worker = Some(id);
+                dump.coroutines.push(info);
+            }
+            if slice == Duration::from_nanos(0) {
+                continue;
+            }
+            if let Some((co, running)) = slot.overrun(slice) {
+                dump.overruns.push(Overrun {
+                    id: co.id(),
+                    name: co.name().map(String::from),
+                    tag: co.tag().cloned(),
+                    worker: id,
+                    running,
+                });
+            }
+        }
+    }
+    #[cfg(feature = "chan-registry")]
+    for (co, wait) in registry::parked_coroutines() {
+        match dump.coroutines.iter_mut().find(|c| c.id == co.id()) {
+            // it's still switching out on the worker
+            Some(c) => c.waits_on = Some(wait),
+            None => {
+                let mut info = CoroutineInfo::new(&co, false);
+                info.waits_on = Some(wait);
+                dump.coroutines.push(info);
+            }
+        }
+    }
+    dump.coroutines.sort_by_key(|c| c.id.as_u64());
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coroutine::Builder;
+    use std::sync::Arc;
+
+    #[test]
+    fn overrun() {
+        set_time_slice(Duration::from_millis(20));
+        let found = Arc::new(AtomicBool::new(false));
+        let f = found.clone();
+        let h = Builder::new()
+            .name("busy".to_owned())
+            .tag_str("tenant-a")
+            .try_spawn(move || {
+                // spin without switching out until the dump lists it
+                let mut flagged = false;
+                while !f.load(Ordering::Relaxed) {
+                    flagged |= overrun_flag().load(Ordering::Relaxed);
+                }
+                let flagged = flagged || overrun_flag().load(Ordering::Relaxed);
+                checkpoint();
+                (flagged, overrun_flag().load(Ordering::Relaxed))
+            })
+            .unwrap();
+
+        let start = Instant::now();
+        let (busy, entry) = loop {
+            let dump = dump();
+            let busy = dump
+                .overruns
+                .into_iter()
+                .find(|o| o.name.as_deref() == Some("busy"));
+            let entry = dump
+                .coroutines
+                .into_iter()
+                .find(|c| c.name.as_deref() == Some("busy"));
+            if busy.is_some() || start.elapsed() > Duration::from_secs(5) {
+                break (busy, entry);
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+        // let the watchdog flag it
+        thread::sleep(Duration::from_millis(50));
+        found.store(true, Ordering::Relaxed);
+        let (flagged, after_yield) = h.join().unwrap();
+        set_time_slice(Duration::from_nanos(0));
+
+        let busy = busy.unwrap();
+        assert!(busy.running >= Duration::from_millis(20));
+        assert_eq!(busy.tag, Some(Tag::Str("tenant-a".into())));
+        let entry = entry.unwrap();
+        assert!(entry.running);
+        assert_eq!(entry.worker, Some(busy.worker));
+        assert_eq!(entry.tag, busy.tag);
+        assert!(flagged);
+        assert!(!after_yield);
+        assert!(OVERRUNS.load(Ordering::Relaxed) >= 1);
+    }
+
+    #[test]
+    fn stall() {
+        set_stall_threshold(Duration::from_millis(50));
+        set_stall_rescue(true);
+        let stalls = Arc::new(Mutex::new(Vec::new()));
+        let found = stalls.clone();
+        set_on_stall(Some(Box::new(move |s: &Stall| {
+            if s.name.as_deref() == Some("stuck") {
+                found.lock().push(s.clone());
+            }
+        })));
+
+        let done = Arc::new(AtomicUsize::new(0));
+        let d = done.clone();
+        let h = Builder::new()
+            .name("stuck".to_owned())
+            .try_spawn(move || {
+                // the children are queued on this worker
+                for _ in 0..10 {
+                    let d = d.clone();
+                    crate::coroutine::spawn(move || {
+                        d.fetch_add(1, Ordering::Relaxed);
+                    });
+                }
+                // block the worker thread
+                thread::sleep(Duration::from_millis(300));
+            })
+            .unwrap();
+
+        let start = Instant::now();
+        while stalls.lock().is_empty() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        // the children run elsewhere while the worker is still stuck
+        while done.load(Ordering::Relaxed) < 10 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(5));
+        }
+        h.join().unwrap();
+        set_stall_threshold(Duration::from_nanos(0));
+        set_stall_rescue(false);
+        set_on_stall(None);
+
+        let stalls = stalls.lock();
+        assert_eq!(stalls.len(), 1);
+        assert!(stalls[0].running);
+        assert!(stalls[0].stalled >= Duration::from_millis(50));
+        assert_eq!(done.load(Ordering::Relaxed), 10);
+        assert!(STALLS.load(Ordering::Relaxed) >= 1);
+    }
+}
@@ -0,0 +1,50 @@
+//! the heap allocations of a spawn, counted by the global allocator
+//!
+//! the allocator is for the whole process, so the test has a binary of its own
+
+#[macro_use]
+extern crate mco;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct Counting;
+
+thread_local! {
+    // only the allocations of the test thread, not the ones of the workers
+    static ALLOCS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocs() -> usize {
+    ALLOCS.with(|n| n.get())
+}
+
+#[test]
+fn small_spawn_no_alloc() {
+    // each pooled coroutine gets its join, handle and local storage once
+    for i in 0..mco::config().get_pool_capacity() * 2 {
+        assert_eq!(co!(move || i).join().unwrap(), i);
+    }
+
+    let data = [1u64, 2, 3];
+    let before = allocs();
+    for _ in 0..1000 {
+        let sum = co!(move || data.iter().sum::<u64>()).join().unwrap();
+        assert_eq!(sum, 6);
+    }
+    assert_eq!(allocs() - before, 0);
+}
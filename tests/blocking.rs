@@ -0,0 +1,48 @@
+#[macro_use]
+extern crate mco;
+
+use std::time::Duration;
+
+use mco::coroutine;
+use mco::std::compat::assert_not_blocking_zone;
+
+fn blocking_resumes() -> usize {
+    mco::stats::stats().blocking_resumes
+}
+
+// the threshold and the counter are process wide, keep all the checks in one
+// test
+#[test]
+fn blocking_resume() {
+    if cfg!(debug_assertions) {
+        assert_eq!(
+            mco::config().get_blocking_threshold(),
+            coroutine::DEBUG_BLOCKING_THRESHOLD
+        );
+    }
+    mco::config().set_blocking_threshold(Duration::from_millis(20));
+    let dur = Duration::from_millis(100);
+
+    let before = blocking_resumes();
+    co!(move || std::thread::sleep(dur)).join().unwrap();
+    assert_eq!(blocking_resumes(), before + 1);
+
+    // the coroutine sleep and a blocking zone are fine
+    co!(move || coroutine::sleep(dur)).join().unwrap();
+    co!(move || coroutine::blocking_zone(|| std::thread::sleep(dur)))
+        .join()
+        .unwrap();
+    assert_eq!(blocking_resumes(), before + 1);
+
+    assert_not_blocking_zone();
+    // it only panics in the debug builds
+    let panicked = co!(assert_not_blocking_zone).join().is_err();
+    assert_eq!(panicked, cfg!(debug_assertions));
+    co!(|| coroutine::blocking_zone(assert_not_blocking_zone))
+        .join()
+        .unwrap();
+
+    mco::config().set_blocking_threshold(Duration::from_nanos(0));
+    co!(move || std::thread::sleep(dur)).join().unwrap();
+    assert_eq!(blocking_resumes(), before + 1);
+}
@@ -0,0 +1,53 @@
+#[macro_use]
+extern crate mco;
+
+use mco::coroutine::{self, Builder, BuilderDefaults};
+
+// the defaults are process wide, keep all the checks in one test
+#[test]
+fn builder_defaults() {
+    coroutine::set_builder_defaults(BuilderDefaults {
+        stack_size: Some(0x8000),
+        name_prefix: Some("worker".to_owned()),
+    });
+
+    let info = || {
+        let co = coroutine::current();
+        (co.name().map(ToOwned::to_owned), co.stack_size())
+    };
+
+    let (name, stack_size) = co!(info).join().unwrap();
+    assert_eq!(stack_size, 0x8000);
+    let name = name.unwrap();
+    assert!(name.starts_with("worker-"));
+
+    // each spawn gets a new sequence number
+    let (name2, _) = co!(info).join().unwrap();
+    assert_ne!(Some(name), name2);
+
+    // override the stack size only, the name prefix is kept
+    let (name, stack_size) = spawn_with!(Builder::new().stack_size(0x4000), info)
+        .join()
+        .unwrap();
+    assert_eq!(stack_size, 0x4000);
+    assert!(name.unwrap().starts_with("worker-"));
+
+    // override the name only
+    let (name, stack_size) = spawn_with!(Builder::new().name_fn(|| "f".to_owned()), info)
+        .join()
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("f"));
+    assert_eq!(stack_size, 0x8000);
+
+    let (name, stack_size) = co!(
+        Builder::default_template().name("explicit".to_owned()),
+        info
+    )
+    .join()
+    .unwrap();
+    assert_eq!(name.as_deref(), Some("explicit"));
+    assert_eq!(stack_size, 0x8000);
+
+    coroutine::set_builder_defaults(BuilderDefaults::default());
+    assert_eq!(co!(info).join().unwrap().0, None);
+}
@@ -1,8 +1,8 @@
 use mco::co;
 use mco::coroutine::sleep;
-use mco::std::sync::channel::channel;
+use mco::std::sync::channel::{bounded, channel, channel_buf, with_fairness, Fairness};
 use mco::std::sync::WaitGroup;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[test]
 fn channel_recv() {
@@ -35,3 +35,293 @@ fn channel_recv() {
     }
     wait_group.wait();
 }
+
+#[cfg(feature = "chan-registry")]
+#[test]
+fn channel_registry_dump() {
+    use mco::coroutine::Builder;
+    use mco::std::sync::channel_dump;
+
+    let (s, r) = channel::<u32>();
+    let line = line!() - 1;
+    let h = Builder::new()
+        .name("dump-recv".to_owned())
+        .spawn(move || r.recv());
+    sleep(Duration::from_millis(20));
+    let info = channel_dump()
+        .into_iter()
+        .find(|c| c.location.file() == file!() && c.location.line() == line)
+        .unwrap();
+    assert_eq!((info.senders, info.receivers), (1, 1));
+    assert_eq!(info.parked.len(), 1);
+    assert_eq!(info.parked[0].name.as_deref(), Some("dump-recv"));
+    assert_eq!(info.parked[0].id, Some(h.id()));
+    assert!(!info.parked[0].sending);
+    // the coroutine dump shows the channel that it waits on
+    let entry = mco::coroutine::dump()
+        .coroutines
+        .into_iter()
+        .find(|c| c.id == h.id())
+        .unwrap();
+    assert!(!entry.running);
+    let wait = entry.waits_on.unwrap();
+    assert_eq!(wait.channel, info.id);
+    assert_eq!(wait.location.line(), line);
+    assert_eq!(wait.senders, 1);
+    s.send(1).unwrap();
+    assert_eq!(h.join().unwrap(), Ok(1));
+    // the channel is gone with its last handle
+    drop(s);
+    assert!(channel_dump().iter().all(|c| c.id != info.id));
+}
+
+#[test]
+fn channel_close_wakes_both_sides() {
+    use mco::std::sync::channel::{bounded, with_fairness, Fairness};
+    for (s, r) in [bounded(1), with_fairness(1, Fairness::Fifo)] {
+        s.send(1).unwrap();
+        let s1 = s.clone();
+        // blocked on a full channel
+        let h = co!(move || s1.send(2));
+        sleep(Duration::from_millis(10));
+        r.close();
+        assert!(h.join().unwrap().is_err());
+        assert!(s.is_closed());
+        // the buffered message is drained before the disconnect
+        assert_eq!(r.recv(), Ok(1));
+        assert!(r.recv().is_err());
+        assert!(s.send(3).is_err());
+    }
+}
+
+#[test]
+fn channel_mixed_threads_and_coroutines() {
+    use mco::std::sync::channel::{with_fairness, Fairness, Receiver, Sender};
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Sender<String>>();
+    assert_send_sync::<Receiver<String>>();
+
+    for fairness in [Fairness::Throughput, Fairness::Fifo] {
+        let (tx, rx) = with_fairness::<usize>(4, fairness);
+        // a plain thread blocks in recv and is woken by the coroutines
+        let reader = {
+            let rx = rx.clone();
+            thread::spawn(move || rx.iter().take(100).sum::<usize>())
+        };
+        // a coroutine blocks in recv and is woken by a plain thread
+        let (back_tx, back_rx) = channel::<usize>();
+        let h = co!(move || back_rx.recv().unwrap());
+        thread::spawn(move || back_tx.send(7).unwrap());
+        assert_eq!(h.join().unwrap(), 7);
+
+        // the sender is cloned by the coroutines after the thread parks
+        thread::sleep(Duration::from_millis(10));
+        let hs: Vec<_> = (0..10)
+            .map(|i| {
+                let tx = tx.clone();
+                co!(move || (0..10).for_each(|j| tx.send(i * 10 + j).unwrap()))
+            })
+            .collect();
+        hs.into_iter().for_each(|h| h.join().unwrap());
+        assert_eq!(reader.join().unwrap(), (0..100).sum::<usize>());
+        drop((tx, rx));
+    }
+}
+
+#[test]
+fn channel_budget() {
+    use mco::std::sync::{channel_with_budget, MemoryBudget};
+
+    let budget = MemoryBudget::new(4 * 8);
+    let (tx, rx) = channel_with_budget::<u64>(&budget);
+    let (tx2, rx2) = channel_with_budget::<u64>(&budget);
+    for i in 0..4 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(budget.used(), 32);
+    // the budget is shared, the other channel is full too
+    assert!(tx2.try_send(0).is_err());
+    assert!(!tx2.is_closed());
+
+    // the blocked sender goes on once a message is received
+    let h = co!({
+        let tx = tx.clone();
+        move || tx.send(4).unwrap()
+    });
+    sleep(Duration::from_millis(10));
+    assert!(!h.is_done());
+    assert_eq!(rx.recv().unwrap(), 0);
+    h.join().unwrap();
+    assert_eq!(budget.used(), 32);
+
+    // closing the channel wakes the sender blocked by the budget
+    let h = co!(move || tx2.send(1).is_err());
+    sleep(Duration::from_millis(10));
+    rx2.close();
+    assert!(h.join().unwrap());
+
+    // dropping the receiver gives back the queued messages
+    drop(rx);
+    assert_eq!(budget.used(), 0);
+    assert!(tx.send(5).is_err());
+}
+
+#[test]
+fn channel_send_and_wait() {
+    use mco::std::sync::channel::AckError;
+
+    let (tx, rx) = channel::<usize>();
+    // the sender returns once the ack is completed
+    let h = co!({
+        let rx = rx.clone();
+        move || {
+            let (v, ack) = rx.recv_ack().unwrap();
+            assert!(ack.is_waited());
+            ack.complete();
+            v
+        }
+    });
+    tx.send_and_wait(1).unwrap();
+    assert_eq!(h.join().unwrap(), 1);
+
+    // dropping the ack fails the sender
+    let h = co!({
+        let rx = rx.clone();
+        move || drop(rx.recv_ack().unwrap())
+    });
+    assert_eq!(tx.send_and_wait(2), Err(AckError::Dropped));
+    h.join().unwrap();
+
+    // a plain recv is the acknowledgement
+    let h = co!({
+        let rx = rx.clone();
+        move || rx.recv().unwrap()
+    });
+    tx.send_and_wait(3).unwrap();
+    assert_eq!(h.join().unwrap(), 3);
+
+    // nobody receives in time, the message stays in the channel
+    let ret = tx.send_and_wait_timeout(4, Duration::from_millis(10));
+    assert_eq!(ret, Err(AckError::Timeout));
+    let (v, ack) = rx.recv_ack().unwrap();
+    assert_eq!(v, 4);
+    assert!(!ack.is_waited());
+    ack.complete();
+
+    // a sender canceled while it waits
+    let h = co!({
+        let tx = tx.clone();
+        move || tx.send_and_wait(5).unwrap()
+    });
+    sleep(Duration::from_millis(10));
+    h.coroutine().cancel();
+    assert!(h.join().is_err());
+    let (v, ack) = rx.recv_ack().unwrap();
+    assert_eq!(v, 5);
+    drop(ack);
+
+    drop(rx);
+    assert_eq!(tx.send_and_wait(6), Err(AckError::Disconnected(6)));
+}
+
+#[test]
+fn channel_recv_ref() {
+    let (tx, rx) = channel_buf::<[u32; 1024]>(usize::MAX);
+    tx.send([1; 1024]).unwrap();
+    // the first slot is held while its block is drained and left behind
+    let mut first = rx.recv_ref().unwrap();
+    first[0] = 7;
+    for i in 0..100 {
+        tx.send([i; 1024]).unwrap();
+    }
+    let sum: u32 = (0..100).map(|_| rx.recv_with(|m| m[1023]).unwrap()).sum();
+    assert_eq!(sum, (0..100).sum());
+    assert_eq!((first[0], first[1]), (7, 1));
+    drop(first);
+    assert!(rx.try_recv_ref().is_err());
+
+    // the concurrent receivers claim each message once
+    let hs: Vec<_> = (0..4)
+        .map(|_| {
+            let rx = rx.clone();
+            co!(move || {
+                let mut n = 0;
+                while let Ok(m) = rx.recv_ref_timeout(Duration::from_millis(200)) {
+                    n += m[0] as usize;
+                }
+                n
+            })
+        })
+        .collect();
+    for _ in 0..1000 {
+        tx.send([1; 1024]).unwrap();
+    }
+    let total: usize = hs.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(total, 1000);
+
+    // the sender is acknowledged when the guard is dropped
+    let h = co!({
+        let rx = rx.clone();
+        move || {
+            let m = rx.recv_ref().unwrap();
+            sleep(Duration::from_millis(20));
+            m[0]
+        }
+    });
+    let start = Instant::now();
+    tx.send_and_wait([3; 1024]).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(h.join().unwrap(), 3);
+
+    // the fifo channels move the message into the guard
+    let (tx, rx) = with_fairness(2, Fairness::Fifo);
+    tx.send(String::from("a")).unwrap();
+    let mut m = rx.recv_ref().unwrap();
+    m.push('b');
+    assert_eq!(*m, "ab");
+}
+
+#[test]
+fn channel_forward_pipeline() {
+    // source -> parse -> square -> sink
+    let (src_tx, src_rx) = channel::<String>();
+    let (parsed_tx, parsed_rx) = bounded(4);
+    let (squared_tx, squared_rx) = bounded(4);
+    let parse = src_rx.forward_map(parsed_tx, |s| s.parse::<u64>().unwrap());
+    let square = parsed_rx.forward_map(squared_tx, |i| i * i);
+    let source = co!(move || {
+        for i in 1..=100u64 {
+            src_tx.send(i.to_string()).unwrap();
+        }
+    });
+    assert_eq!(
+        squared_rx.iter().sum::<u64>(),
+        (1..=100u64).map(|i| i * i).sum()
+    );
+    source.join().unwrap();
+    assert_eq!(parse.join().unwrap().forwarded, 100);
+    assert_eq!(square.join().unwrap().forwarded, 100);
+
+    // the sink is gone, the close goes up to the source
+    let (src_tx, src_rx) = bounded::<u64>(1);
+    let (mid_tx, mid_rx) = bounded(1);
+    let (out_tx, out_rx) = bounded(1);
+    let first = src_rx.forward(mid_tx);
+    let second = mid_rx.forward(out_tx);
+    let source = co!(move || {
+        let mut sent = 0;
+        while src_tx.send(sent).is_ok() {
+            sent += 1;
+        }
+        sent
+    });
+    assert_eq!(out_rx.recv(), Ok(0));
+    drop(out_rx);
+    let sent = source.join().unwrap();
+    let (first, second) = (first.join().unwrap(), second.join().unwrap());
+    assert!(second.forwarded >= 1);
+    assert!(first.forwarded >= second.forwarded);
+    assert!(sent >= first.forwarded);
+}
@@ -0,0 +1,32 @@
+use mco::std::time::{self, Ticker, Time};
+
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::Duration;
+
+// the simulated clock in seconds
+static SIM: AtomicI64 = AtomicI64::new(0);
+
+fn sim() -> (i64, u32) {
+    (SIM.load(Ordering::Relaxed), 500_000_000)
+}
+
+#[test]
+fn override_and_restore() {
+    let real = Time::now_utc().unix();
+    {
+        let _clock = time::override_clock(sim);
+        SIM.store(86_400, Ordering::Relaxed);
+        assert_eq!(Time::now_utc().unix_nano(), 86_400_500_000_000);
+        assert_eq!(Time::now().unix(), 86_400);
+
+        // the times that the crate takes follow it too
+        let ticker = Ticker::new(Duration::from_millis(10));
+        assert_eq!(ticker.recv.recv().unwrap().unix(), 86_400);
+        ticker.stop().unwrap();
+
+        SIM.store(-1, Ordering::Relaxed);
+        assert_eq!(Time::now_utc().year(), 1969);
+    }
+    assert!(time::clock_source().is_none());
+    assert!((Time::now_utc().unix() - real).abs() <= 1);
+}
@@ -166,6 +166,207 @@ fn cqueue_select() {
     assert_eq!(rx1.recv(), Ok(42));
 }
 
+#[test]
+fn cqueue_select_complete() {
+    use mco::std::sync::channel::channel;
+
+    let (tx1, rx1) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+
+    tx1.send(1).unwrap();
+    co!(move || {
+        coroutine::sleep(Duration::from_millis(100));
+        drop(tx1);
+        drop(tx2);
+    });
+
+    let mut got = Vec::new();
+    loop {
+        let id = select! {
+            Ok(v) = rx1.recv() => got.push(v),
+            Ok(v) = rx2.recv() => got.push(v),
+            complete => break,
+        };
+        assert_eq!(id, Some(0));
+    }
+
+    // only the complete arm breaks the loop
+    assert_eq!(got, vec![1]);
+}
+
+#[test]
+fn cqueue_select_biased() {
+    use mco::std::sync::channel::channel;
+
+    let (tx1, rx1) = channel::<i32>();
+    let (tx2, rx2) = channel::<i32>();
+    let mut buf = Vec::new();
+    let mut probes = 0;
+    let mut probe = |rx: &mco::std::sync::channel::Receiver<i32>| {
+        probes += 1;
+        rx.try_recv()
+    };
+
+    // the first arm wins, the second one is not evaluated
+    tx1.send(1).unwrap();
+    tx2.send(2).unwrap();
+    let id = select! { biased;
+        Ok(v) = rx1.try_recv() => buf.push(v),
+        Ok(v) = probe(&rx2) => buf.push(v),
+    };
+    assert_eq!(id, 0);
+    assert_eq!(probes, 0);
+
+    // both bodies borrow `buf` mutably, it waits until an arm is ready
+    co!(move || {
+        coroutine::sleep(Duration::from_millis(50));
+        tx1.send(3).unwrap();
+    });
+    let mut rounds = 0;
+    'outer: loop {
+        select! { biased;
+            Ok(v) = rx1.try_recv() => {
+                buf.push(v);
+                break 'outer;
+            },
+            Ok(v) = rx2.try_recv() => buf.push(v),
+        };
+        rounds += 1;
+    }
+    assert_eq!(buf, vec![1, 2, 3]);
+    assert_eq!(rounds, 1);
+
+    // `default` runs when no arm is ready
+    let ret = select! { biased;
+        Ok(v) = rx2.try_recv() => buf.push(v),
+        default => buf.clear(),
+    };
+    assert_eq!(ret, None);
+    assert!(buf.is_empty());
+    drop(tx2);
+}
+
+#[test]
+fn cqueue_selector_stack() {
+    use mco::std::sync::channel::channel;
+
+    let (tx, rx) = channel::<i32>();
+    tx.send(1).unwrap();
+    let mut size = 0;
+    select! {
+        Ok(_) = rx.recv() => size = coroutine::current().stack_size(),
+    };
+    assert_eq!(size, mco::config().get_selector_stack_size());
+    assert!(size < mco::config().get_stack_size());
+
+    // the blocked selectors are counted as live
+    let (_tx1, rx1) = channel::<i32>();
+    let (_tx2, rx2) = channel::<i32>();
+    cqueue::scope(|cqueue| {
+        cqueue_add!(cqueue, 0, _ = rx1.recv() => {});
+        cqueue_add!(cqueue, 1, _ = rx2.recv() => {});
+        coroutine::sleep(Duration::from_millis(10));
+        assert!(mco::stats::stats().selectors >= 2);
+        rx1.close();
+        rx2.close();
+    });
+}
+
+#[test]
+fn cqueue_select_any_of() {
+    use mco::std::sync::channel::channel;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let (txs, rxs): (Vec<_>, Vec<_>) = (0..16).map(|_| channel::<usize>()).unzip();
+    let (tx, rx) = channel::<usize>();
+    let from = AtomicUsize::new(usize::MAX);
+
+    txs[11].send(42).unwrap();
+    let id = select! {
+        Ok(v) = rx.recv() => from.store(v, Ordering::Relaxed),
+        (i, Ok(v)) = any_of(&rxs).recv() => {
+            assert_eq!(v, 42);
+            from.store(i, Ordering::Relaxed);
+        },
+    };
+    assert_eq!(id, 1);
+    assert_eq!(from.load(Ordering::Relaxed), 11);
+
+    drop(txs);
+    drop(tx);
+    let id = select! {
+        (i, Ok(_)) = any_of(rxs.iter()).try_recv() => from.store(i, Ordering::Relaxed),
+        complete => {},
+    };
+    assert_eq!(id, None);
+    assert_eq!(from.load(Ordering::Relaxed), 11);
+}
+
+#[test]
+fn cqueue_select_50_arms() {
+    use mco::std::sync::channel::channel;
+    use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+    let (txs, r): (Vec<_>, Vec<_>) = (0..50).map(|_| channel::<usize>()).unzip();
+    let sum = AtomicUsize::new(0);
+    txs[49].send(7).unwrap();
+    #[rustfmt::skip]
+    let id = select! {
+        Ok(v) = r[0].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[1].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[2].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[3].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[4].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[5].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[6].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[7].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[8].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[9].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[10].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[11].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[12].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[13].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[14].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[15].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[16].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[17].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[18].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[19].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[20].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[21].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[22].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[23].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[24].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[25].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[26].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[27].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[28].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[29].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[30].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[31].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[32].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[33].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[34].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[35].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[36].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[37].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[38].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[39].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[40].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[41].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[42].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[43].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[44].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[45].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[46].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[47].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[48].recv() => sum.fetch_add(v, Relaxed),
+        Ok(v) = r[49].recv() => sum.fetch_add(v, Relaxed),
+    };
+    assert_eq!(id, 49);
+    assert_eq!(sum.load(Relaxed), 7);
+}
+
 #[test]
 fn cqueue_timeout() {
     cqueue::scope(|cqueue| {
@@ -229,3 +430,661 @@ fn cqueue_loop() {
 
     assert_eq!(result, 50);
 }
+
+#[test]
+fn cqueue_select_loop() {
+    use mco::std::sync::channel::channel;
+    use std::ops::ControlFlow;
+
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let mut sum = 0;
+
+    co!(move || {
+        for i in 1..=10 {
+            tx1.send(i).unwrap();
+        }
+        // wait for the loop to consume all the data
+        coroutine::sleep(Duration::from_millis(100));
+        tx2.send(()).unwrap();
+    });
+
+    select_loop! {
+        Ok(v) = rx1.recv() => sum += v,
+        _ = rx2.recv() => ControlFlow::Break(())
+    };
+
+    assert_eq!(sum, 55);
+}
+
+#[test]
+fn cqueue_select_loop_disconnect() {
+    use mco::std::sync::channel::channel;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let count = AtomicUsize::new(0);
+
+    tx1.send(1).unwrap();
+    tx2.send(2).unwrap();
+    tx2.send(3).unwrap();
+    drop(tx1);
+    drop(tx2);
+
+    // all the arms are deregistered when the channels are disconnected
+    select_loop! {
+        Ok(_) = rx1.recv() => { count.fetch_add(1, Ordering::Relaxed); },
+        Ok(_) = rx2.recv() => { count.fetch_add(1, Ordering::Relaxed); },
+    };
+
+    assert_eq!(count.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn cqueue_payload() {
+    use mco::std::sync::channel::channel;
+
+    let (tx, rx) = channel();
+    tx.send(String::from("hello")).unwrap();
+
+    cqueue::scope(|cqueue| {
+        co!(cqueue, 0, |es| {
+            let msg: String = rx.recv().unwrap();
+            es.send_with(es.get_token(), msg);
+        });
+
+        match cqueue.poll(None) {
+            Ok(mut ev) => {
+                // a type mismatch would not consume the payload
+                assert_eq!(ev.take::<usize>(), None);
+                assert_eq!(ev.take::<String>(), Some(String::from("hello")));
+                assert_eq!(ev.take::<String>(), None);
+            }
+            _ => unreachable!(),
+        }
+    });
+}
+
+#[test]
+fn cqueue_payload_drop() {
+    use std::sync::Arc;
+
+    let data = Arc::new(0);
+    cqueue::scope(|cqueue| {
+        let d = data.clone();
+        co!(cqueue, 0, move |es| es.send_with(0, d));
+        // the poller never takes the payload
+        assert!(cqueue.poll(None).is_ok());
+    });
+    assert_eq!(Arc::strong_count(&data), 1);
+}
+
+#[test]
+fn cqueue_owned() {
+    use mco::cqueue::{Cqueue, Owned};
+    use mco::std::sync::channel::channel;
+
+    struct Actor {
+        cqueue: Cqueue<Owned>,
+    }
+
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let actor = {
+        let cqueue = Cqueue::new();
+        for (token, rx) in vec![rx1, rx2].into_iter().enumerate() {
+            cqueue.add(token, move |es| {
+                for v in rx.iter() {
+                    es.send(v);
+                }
+            });
+        }
+        // the cqueue can be moved with the select coroutines registered
+        Actor { cqueue }
+    };
+
+    let h = co!(move || {
+        let mut sum = 0;
+        loop {
+            match actor.cqueue.poll_batch(8, None) {
+                Ok(events) => sum += events.iter().map(|ev| ev.extra).sum::<usize>(),
+                Err(Finished) => break,
+                Err(Timeout) => unreachable!(),
+            }
+        }
+        actor.cqueue.close();
+        sum
+    });
+
+    tx1.send_all(1..=5).unwrap();
+    tx2.send_all(6..=10).unwrap();
+    drop(tx1);
+    drop(tx2);
+    assert_eq!(h.join().unwrap(), 55);
+}
+
+#[test]
+fn cqueue_owned_close() {
+    use mco::cqueue::Cqueue;
+    use mco::std::sync::channel::channel;
+
+    let (tx, rx) = channel::<usize>();
+    let cqueue = Cqueue::new();
+    cqueue.add(0, move |es| {
+        for v in rx.iter() {
+            es.send(v);
+        }
+    });
+    assert_eq!(
+        cqueue.poll(Some(Duration::from_millis(10))).unwrap_err(),
+        Timeout
+    );
+    // the blocked select coroutine is canceled and unwound
+    cqueue.close();
+    assert!(tx.send(1).is_err());
+}
+
+#[test]
+fn cqueue_backpressure() {
+    use mco::cqueue::Cqueue;
+
+    let cqueue = Cqueue::with_capacity(4);
+    assert_eq!(cqueue.capacity(), 4);
+    cqueue.add(0, |es| {
+        // the poller is behind, the events beyond the capacity are dropped
+        let dropped = (0..10).filter(|i| es.try_send(*i).is_err()).count();
+        assert_eq!(dropped, 6);
+        assert_eq!(es.pending(), 4);
+        assert!(es.send_timeout(10, Duration::from_millis(10)).is_err());
+        // wait for the poller to make room
+        es.send_timeout(11, Duration::from_secs(5)).unwrap();
+    });
+    // the selector times out on the full queue before the poller starts
+    std::thread::sleep(Duration::from_millis(50));
+
+    let mut extras = vec![];
+    loop {
+        match cqueue.poll(None) {
+            Ok(ev) => extras.push(ev.extra),
+            Err(Finished) => break,
+            Err(Timeout) => unreachable!(),
+        }
+    }
+    assert_eq!(extras, vec![0, 1, 2, 3, 11]);
+}
+
+// a selector that queues 1M events against a poller that falls behind
+fn overflow_stress(overflow: cqueue::Overflow) -> (Vec<usize>, u64, usize) {
+    const EVENTS: usize = 1_000_000;
+    let config = cqueue::CqueueConfig {
+        capacity: 1024,
+        overflow,
+    };
+    cqueue::scope_with(config, |cqueue| {
+        cqueue.add(0, |es| {
+            for i in 0..EVENTS {
+                es.send_timeout(i, Duration::from_secs(60)).unwrap();
+            }
+        });
+        // let the selector run ahead
+        std::thread::sleep(Duration::from_millis(10));
+        let mut got = Vec::new();
+        loop {
+            match cqueue.poll(None) {
+                Ok(ev) => got.push(ev.extra),
+                Err(Finished) => break,
+                Err(Timeout) => unreachable!(),
+            }
+            if got.len() % 10_000 == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        assert_eq!(got.len() as u64 + cqueue.dropped_events(), EVENTS as u64);
+        assert!(got.windows(2).all(|w| w[0] < w[1]));
+        assert!(cqueue.high_watermark() <= 1024);
+        (got, cqueue.dropped_events(), cqueue.high_watermark())
+    })
+}
+
+#[test]
+fn cqueue_overflow() {
+    use mco::cqueue::Overflow;
+
+    // the selector waits for the poller, nothing is lost
+    let (got, dropped, high) = overflow_stress(Overflow::BlockSelector);
+    assert_eq!(dropped, 0);
+    assert_eq!(got.len(), 1_000_000);
+    assert_eq!(high, 1024);
+
+    // the first events fill the queue and stay
+    let (got, dropped, _) = overflow_stress(Overflow::DropNewest);
+    assert!(dropped > 0);
+    assert_eq!(&got[..1024], &(0..1024).collect::<Vec<_>>()[..]);
+
+    // the last events push the older ones out
+    let (got, dropped, _) = overflow_stress(Overflow::DropOldest);
+    assert!(dropped > 0);
+    let last: Vec<_> = (1_000_000 - 1024..1_000_000).collect();
+    assert!(got.ends_with(&last));
+
+    let cqueue = cqueue::Cqueue::with_config(Default::default());
+    assert_eq!(cqueue.capacity(), usize::MAX);
+    assert_eq!(cqueue.overflow(), Overflow::BlockSelector);
+}
+
+#[test]
+fn cqueue_select_wait_group() {
+    use mco::std::sync::channel::channel;
+    use mco::std::sync::WaitGroup;
+
+    let (tx, rx) = channel();
+    let wg = WaitGroup::new();
+    for i in 0..4 {
+        let (tx, wg) = (tx.clone(), wg.clone());
+        co!(move || {
+            tx.send(i).unwrap();
+            drop(wg);
+        });
+    }
+
+    let done = wg.done_receiver();
+    let mut got = 0;
+    loop {
+        let id = select! {
+            Ok(_) = rx.recv() => got += 1,
+            Ok(()) = done.recv() => {},
+        };
+        if id == 1 {
+            break;
+        }
+    }
+    assert!(got <= 4);
+    // the completion is seen only once for each receiver
+    assert!(done.recv().is_err());
+    // a clone registered after the count reached zero still sees it
+    let late = done.clone();
+    assert!(late.is_done());
+    assert_eq!(late.recv(), Ok(()));
+    assert!(late.recv().is_err());
+    drop(tx);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cqueue_readiness_fd() {
+    use std::os::unix::io::AsRawFd;
+
+    // the external loop, wait for the fd with poll(2)
+    fn readable(fd: i32, ms: i32) -> bool {
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pfd, 1, ms) == 1 }
+    }
+
+    let (tx, rx) = mco::std::sync::channel::channel();
+    let cqueue = cqueue::Cqueue::new();
+    cqueue.add(0, move |es| {
+        for v in rx.iter() {
+            es.try_send(v).unwrap();
+        }
+    });
+    let fd = cqueue.readiness_fd().unwrap().as_raw_fd();
+    assert!(!readable(fd, 0));
+
+    for i in 0..3 {
+        tx.send(i).unwrap();
+    }
+    assert!(readable(fd, 1000));
+    // it stays readable while the events remain
+    assert_eq!(cqueue.poll_nonblocking().unwrap().extra, 0);
+    while cqueue.poll_nonblocking().map(|e| e.extra).ok() != Some(2) {
+        assert!(readable(fd, 1000));
+    }
+    assert_eq!(cqueue.poll_nonblocking().err(), Some(Timeout));
+    assert!(!readable(fd, 0));
+
+    drop(tx);
+    assert!(readable(fd, 1000));
+    assert_eq!(cqueue.poll_nonblocking().err(), Some(Finished));
+}
+
+#[test]
+fn cqueue_typed_tokens() {
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Source {
+        Tick,
+        Data(u8),
+    }
+
+    let (tx, rx) = mco::std::sync::channel::channel();
+    cqueue::scope_typed(|cq| {
+        cq.add(Source::Tick, |es| {
+            coroutine::sleep(Duration::from_millis(10));
+            es.send(0);
+        });
+        cq.add(Source::Data(1), move |es| {
+            for v in rx.iter() {
+                es.send(v);
+            }
+        });
+        tx.send(7).unwrap();
+        let mut ticks = 0;
+        loop {
+            let ev = cq.poll(None).unwrap();
+            match ev.token {
+                Source::Tick => ticks += 1,
+                Source::Data(n) => {
+                    assert_eq!((n, ev.event.extra), (1, 7));
+                    break;
+                }
+            }
+        }
+        assert!(ticks <= 1);
+        assert_eq!(cq.token(1), Some(Source::Data(1)));
+        drop(tx);
+        while cq.poll(None).is_ok() {}
+        assert_eq!(cq.poll(None).err(), Some(Finished));
+    });
+}
+
+#[test]
+fn cqueue_add_exits_on_disconnect() {
+    use mco::std::sync::channel::channel;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    let (mut txs, rxs): (Vec<_>, Vec<_>) = (0..3).map(|_| channel::<usize>()).unzip();
+    let sum = AtomicUsize::new(0);
+    let start = Instant::now();
+    cqueue::scope(|cqueue| {
+        for (i, rx) in rxs.iter().enumerate() {
+            let sum = &sum;
+            cqueue_add!(cqueue, i, v = rx.recv() => {
+                sum.fetch_add(v.unwrap(), Ordering::Relaxed);
+            });
+        }
+        for (i, tx) in txs.drain(..).enumerate() {
+            tx.send(i + 1).unwrap();
+        }
+        // the selectors exit after the senders are dropped
+        let mut events = 0;
+        loop {
+            match cqueue.poll(Some(Duration::from_secs(5))) {
+                Ok(_) => events += 1,
+                Err(e) => {
+                    assert_eq!(e, Finished);
+                    break;
+                }
+            }
+        }
+        assert_eq!(events, 3);
+        let mut finished = cqueue.take_finished();
+        finished.sort_unstable();
+        assert_eq!(finished, vec![0, 1, 2]);
+    });
+    assert_eq!(sum.load(Ordering::Relaxed), 6);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn cqueue_poll_until() {
+    use mco::std::time::Time;
+    use std::time::Instant;
+
+    cqueue::scope(|cqueue| {
+        co!(cqueue, 0, |es| {
+            coroutine::sleep(Duration::from_millis(20));
+            es.send(0);
+        });
+        co!(cqueue, 1, |_es| coroutine::sleep(Duration::from_secs(10)));
+
+        // the deadline is fixed over the loop
+        let start = Instant::now();
+        let deadline = Time::now().add(Duration::from_millis(100));
+        assert_eq!(cqueue.poll_until(&deadline).unwrap().token, 0);
+        assert_eq!(cqueue.poll_until(&deadline).unwrap_err(), Timeout);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+        // a passed deadline takes the queued events only
+        let passed = Time::now().sub(Duration::from_secs(1));
+        assert_eq!(cqueue.poll_batch_until(4, &passed).unwrap_err(), Timeout);
+    });
+}
+
+#[test]
+fn cqueue_poll_ctx() {
+    use mco::cqueue::PollCtxError;
+    use mco::std::context::{Context, ContextError};
+
+    let ctx = Context::background().with_cancel();
+    let c = ctx.clone();
+    let h = co!(move || {
+        cqueue::scope(|cqueue| {
+            co!(cqueue, 0, |_es| coroutine::sleep(Duration::from_secs(10)));
+            cqueue.poll_ctx(&c).map(|ev| ev.token)
+        })
+    });
+    coroutine::sleep(Duration::from_millis(20));
+    ctx.cancel();
+    assert_eq!(
+        h.join().unwrap(),
+        Err(PollCtxError::Done(ContextError::Canceled))
+    );
+
+    cqueue::scope(|cqueue| {
+        let ctx = Context::background().with_timeout(Duration::from_millis(10));
+        co!(cqueue, 0, |_es| coroutine::sleep(Duration::from_secs(10)));
+        assert_eq!(
+            cqueue.poll_ctx(&ctx).unwrap_err(),
+            PollCtxError::Done(ContextError::DeadlineExceeded)
+        );
+    });
+}
+
+#[test]
+fn select_deadline_arm() {
+    use mco::std::time::Time;
+
+    let (tx, rx) = chan!();
+    let deadline = Time::now().add(Duration::from_millis(50));
+    let mut got = Vec::new();
+    for i in 0..100 {
+        if i < 3 {
+            tx.send(i).unwrap();
+        }
+        let ret = select! {
+            Ok(v) = rx.recv() => got.push(v),
+            deadline(deadline) => got.push(-1),
+        };
+        if ret.is_none() {
+            break;
+        }
+    }
+    assert_eq!(got, vec![0, 1, 2, -1]);
+}
+
+#[test]
+fn select_loses_no_message() {
+    use mco::std::sync::channel::{channel, with_fairness, Fairness};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    for fairness in [Fairness::Throughput, Fairness::Fifo] {
+        let (tx0, rx0) = with_fairness::<usize>(4, fairness);
+        let (tx1, rx1) = with_fairness::<usize>(4, fairness);
+        let stop = Arc::new(AtomicBool::new(false));
+        // the producers keep both channels full
+        let producers: Vec<_> = vec![tx0, tx1]
+            .into_iter()
+            .map(|tx| {
+                let stop = stop.clone();
+                co!(move || {
+                    let mut n = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        tx.send(n).unwrap();
+                        n += 1;
+                    }
+                    n
+                })
+            })
+            .collect();
+
+        let (out_tx, out_rx) = channel();
+        let mut seen = vec![Vec::new(), Vec::new()];
+        for _ in 0..10_000 {
+            let id = select! {
+                Ok(v) = rx0.recv() => out_tx.send((0, v)).unwrap(),
+                Ok(v) = rx1.recv() => out_tx.send((1, v)).unwrap(),
+            };
+            // only the body of the winner runs
+            let (i, v) = out_rx.try_recv().unwrap();
+            assert_eq!(i, id);
+            assert!(out_rx.try_recv().is_err());
+            seen[i].push(v);
+        }
+
+        stop.store(true, Ordering::Relaxed);
+        for (i, rx) in [rx0, rx1].iter().enumerate() {
+            seen[i].extend(rx.iter());
+        }
+        // each message is received once and in order
+        for (i, h) in producers.into_iter().enumerate() {
+            let n = h.join().unwrap();
+            assert_eq!(seen[i], (0..n).collect::<Vec<_>>());
+        }
+    }
+}
+
+#[test]
+fn cqueue_blocked_bottom() {
+    use mco::std::sync::channel::{bounded, channel};
+
+    let (full_tx, full_rx) = bounded(1);
+    full_tx.send(0).unwrap();
+    let (src_tx, src_rx) = channel();
+    let (live_tx, live_rx) = channel();
+    cqueue::scope(|cqueue| {
+        // the bottom half waits for the room of the full channel
+        cqueue_add!(cqueue, 0, v = src_rx.recv() => full_tx.send(v.unwrap()).unwrap());
+        cqueue_add!(cqueue, 1, v = live_rx.recv() => v.unwrap());
+        src_tx.send(1).unwrap();
+        assert_eq!(cqueue.poll(None).unwrap().token, 0);
+
+        // the live arm still flows
+        for i in 0..10 {
+            live_tx.send(i).unwrap();
+            let ev = cqueue.poll(Some(Duration::from_secs(5))).unwrap();
+            assert_eq!(ev.token, 1);
+        }
+
+        // the blocked arm goes on once there is room
+        assert_eq!(full_rx.recv().unwrap(), 0);
+        assert_eq!(full_rx.recv().unwrap(), 1);
+        drop(src_tx);
+        drop(live_tx);
+        assert_eq!(cqueue.poll(None).unwrap_err(), Finished);
+    });
+}
+
+#[test]
+fn cqueue_add_with_inflight() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let queued = AtomicUsize::new(0);
+    cqueue::scope(|cqueue| {
+        cqueue.add_with(0, 2, |es| {
+            for i in 0..10 {
+                es.send_timeout(i, Duration::from_secs(10)).unwrap();
+                queued.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        coroutine::sleep(Duration::from_millis(50));
+        // it stops at the limit
+        assert_eq!(queued.load(Ordering::Relaxed), 2);
+        assert_eq!(cqueue.poll(None).unwrap().extra, 0);
+        coroutine::sleep(Duration::from_millis(50));
+        assert_eq!(queued.load(Ordering::Relaxed), 3);
+
+        let mut got = vec![];
+        while let Ok(ev) = cqueue.poll(None) {
+            got.push(ev.extra);
+        }
+        assert_eq!(got, (1..10).collect::<Vec<_>>());
+    });
+}
+
+#[test]
+fn select_guards() {
+    use mco::std::sync::channel::channel_buf;
+    use mco::std::time::Time;
+    use std::time::Instant;
+
+    let (tx, rx) = chan!();
+    let (btx, brx) = channel_buf(1);
+    // the disabled send takes no room of the bounded channel
+    tx.send(1).unwrap();
+    let id = select! {
+        Ok(_) = btx.send(9), if false => unreachable!(),
+        Ok(v) = rx.recv() => assert_eq!(v, 1),
+    };
+    assert_eq!(id, 1);
+    assert!(brx.try_recv().is_err());
+
+    // the guards flip between the iterations
+    let mut got = Vec::new();
+    for i in 0..4 {
+        tx.send(i).unwrap();
+        btx.send(i * 10).unwrap();
+        let odd = i % 2 == 1;
+        select! {
+            Ok(v) = rx.recv(), if !odd => got.push(v),
+            Ok(v) = brx.recv(), if odd => got.push(v),
+        };
+        if odd {
+            assert_eq!(rx.recv().unwrap(), i);
+        } else {
+            assert_eq!(brx.recv().unwrap(), i * 10);
+        }
+    }
+    assert_eq!(got, vec![0, 10, 2, 30]);
+
+    // all the arms disabled
+    let mut done = false;
+    let ret = select! {
+        Ok(_) = rx.recv(), if false => {},
+        complete => done = true,
+    };
+    assert_eq!(ret, None);
+    assert!(done);
+
+    let start = Instant::now();
+    let deadline = Time::now().add(Duration::from_millis(30));
+    let ret = select! {
+        Ok(_) = rx.recv(), if false => {},
+        deadline(deadline) => {},
+    };
+    assert_eq!(ret, None);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    let ret = select! { biased;
+        Ok(_) = rx.try_recv(), if false => {},
+        default => {},
+    };
+    assert_eq!(ret, None);
+}
+
+#[test]
+#[should_panic(expected = "all the arms of `select!` are disabled")]
+fn select_all_disabled() {
+    let (_tx, rx) = chan!(i32, usize::MAX);
+    select! {
+        Ok(_) = rx.recv(), if false => {},
+    };
+}
@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate mco;
+
+use mco::coroutine;
+use mco::select::SelectSet;
+use mco::std::rand;
+use mco::std::sync::channel;
+
+use std::sync::{Arc, Mutex};
+
+// the coroutines yield at random and pick an arm of a set of ready channels,
+// the trace is the order that they run and pick in
+fn scenario() -> Vec<(usize, usize)> {
+    co!(|| {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let hs: Vec<_> = (0..16)
+            .map(|id| {
+                let trace = trace.clone();
+                co!(move || {
+                    let chans: Vec<_> = (0..3).map(|_| channel::<usize>()).collect();
+                    for step in 0..20 {
+                        chans.iter().for_each(|(tx, _)| tx.send(step).unwrap());
+                        let mut set = SelectSet::new();
+                        for (i, (_, rx)) in chans.iter().enumerate() {
+                            set.add(rx, move |_| i);
+                        }
+                        trace.lock().unwrap().push((id, set.select()));
+                        if rand::range(0..3) == 0 {
+                            coroutine::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        hs.into_iter().for_each(|h| h.join().unwrap());
+        Arc::try_unwrap(trace).unwrap().into_inner().unwrap()
+    })
+    .join()
+    .unwrap()
+}
+
+#[test]
+fn same_seed_replays() {
+    mco::config().set_deterministic_seed(42);
+    let seed = mco::config().get_deterministic_seed().unwrap();
+    assert_eq!(mco::config().get_workers(), 1);
+    let first = scenario();
+    mco::config().set_deterministic_seed(seed);
+    assert_eq!(scenario(), first);
+
+    // `MCO_SEED` replaces the seed of the code
+    if std::env::var("MCO_SEED").is_err() {
+        mco::config().set_deterministic_seed(43);
+        assert_ne!(scenario(), first);
+    }
+}
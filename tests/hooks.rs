@@ -0,0 +1,80 @@
+#[macro_use]
+extern crate mco;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use mco::coroutine::{self, Builder, Hooks, Tag};
+
+// the hooks are process wide, keep all the checks in one test
+#[test]
+fn tenant_accounting() {
+    let live = Arc::new(Mutex::new(HashMap::<Tag, isize>::new()));
+    let exits = Arc::new(Mutex::new(Vec::new()));
+    let (l1, l2, e) = (live.clone(), live.clone(), exits.clone());
+    coroutine::add_hooks(Hooks {
+        on_start: Some(Box::new(move |co| {
+            if let Some(tag) = co.tag() {
+                *l1.lock().unwrap().entry(tag.clone()).or_default() += 1;
+            }
+        })),
+        on_exit: Some(Box::new(move |co, exit| {
+            if let Some(tag) = co.tag() {
+                *l2.lock().unwrap().get_mut(tag).unwrap() -= 1;
+                e.lock().unwrap().push((tag.clone(), *exit));
+            }
+        })),
+    });
+    // a panic in a hook is caught
+    let calls = Arc::new(AtomicIsize::new(0));
+    let c = calls.clone();
+    coroutine::add_hooks(Hooks {
+        on_start: Some(Box::new(move |_| {
+            c.fetch_add(1, Ordering::Relaxed);
+            panic!("bad hook");
+        })),
+        on_exit: None,
+    });
+
+    let live_of = |tag: &Tag| live.lock().unwrap().get(tag).copied().unwrap_or(0);
+    let a = Tag::Num(1);
+    let b = Tag::Str("tenant-b".into());
+
+    let (tx, rx) = mco::chan!();
+    let h1 = Builder::new().tag(1).spawn(move || rx.recv().unwrap() + 1);
+    let h2 = Builder::new().tag_str("tenant-b").spawn(|| {
+        coroutine::park();
+    });
+    let h3 = Builder::new().tag(1).spawn(|| panic!("boom"));
+    assert!(h3.join().is_err());
+
+    // the other tenant and the untagged ones are not counted
+    co!(|| {}).join().unwrap();
+    assert_eq!(live_of(&a), 1);
+    assert_eq!(live_of(&b), 1);
+
+    coroutine::sleep(Duration::from_millis(20));
+    tx.send(1).unwrap();
+    assert_eq!(h1.join().unwrap(), 2);
+    assert_eq!(live_of(&a), 0);
+
+    // canceled
+    h2.coroutine().cancel();
+    assert!(h2.join().is_err());
+    assert_eq!(live_of(&b), 0);
+
+    let exits = exits.lock().unwrap();
+    assert_eq!(exits.len(), 3);
+    let (_, panicked) = &exits[0];
+    assert!(panicked.panicked);
+    let (tag, ok) = &exits[1];
+    assert_eq!(tag, &a);
+    assert!(!ok.panicked);
+    assert!(ok.elapsed >= Duration::from_millis(20));
+    let (tag, canceled) = &exits[2];
+    assert_eq!(tag, &b);
+    assert!(canceled.panicked);
+    assert_eq!(calls.load(Ordering::Relaxed), 4);
+}
@@ -175,6 +175,40 @@ fn wait_join() {
     j.join().unwrap();
 }
 
+#[test]
+fn join_on_complete() {
+    use mco::std::sync::channel::channel;
+
+    let (tx, rx) = channel();
+    // the callback runs in the coroutine once the closure returns
+    let tx1 = tx.clone();
+    co!(|| {
+        coroutine::sleep(Duration::from_millis(10));
+        1
+    })
+    .on_complete(move |ret| {
+        assert!(coroutine::is_coroutine());
+        tx1.send(ret.unwrap()).unwrap();
+    });
+    assert_eq!(rx.recv().unwrap(), 1);
+
+    // the coroutine is already done, the callback runs at once
+    let h = co!(|| 2);
+    h.wait();
+    let tx2 = tx.clone();
+    h.on_complete(move |ret| tx2.send(ret.unwrap()).unwrap());
+    assert_eq!(rx.try_recv().unwrap(), 2);
+
+    // the panic is passed to the callback
+    co!(|| panic!("boom")).on_complete(move |ret: thread::Result<u32>| {
+        tx.send(ret.is_err() as u32 + 2).unwrap();
+    });
+    assert_eq!(rx.recv().unwrap(), 3);
+    assert!(rx.recv().is_err());
+
+    co!(|| 4).detach();
+}
+
 #[test]
 fn scoped_coroutine() {
     let mut array = [1, 2, 3];
@@ -191,6 +225,107 @@ fn scoped_coroutine() {
     assert_eq!(array[2], 4);
 }
 
+#[test]
+fn scope_panic_cancels_children() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let exited = AtomicBool::new(false);
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        coroutine::scope(|scope| {
+            co!(scope, || {
+                // the guard runs when the cancel panic unwinds the child
+                mco::defer!(|| exited.store(true, Ordering::SeqCst));
+                loop {
+                    coroutine::sleep(Duration::from_millis(10));
+                }
+            });
+            panic!("body panic");
+        })
+    }));
+    let e = r.unwrap_err();
+    assert_eq!(e.downcast_ref::<&str>(), Some(&"body panic"));
+    assert!(exited.load(Ordering::SeqCst));
+}
+
+#[test]
+fn scope_timeout_stragglers() {
+    use mco::coroutine::{Builder, Straggler};
+
+    let mut done = false;
+    let r = coroutine::scope_timeout(Duration::from_millis(50), |scope| unsafe {
+        scope.spawn(|| done = true);
+        scope.spawn_with(Builder::new().name("stuck".to_owned()), || loop {
+            coroutine::sleep(Duration::from_millis(10));
+        });
+    });
+    let e = r.unwrap_err();
+    assert_eq!(
+        e.stragglers,
+        vec![Straggler {
+            index: 1,
+            name: Some("stuck".to_owned())
+        }]
+    );
+    assert_eq!(e.to_string(), "scope timed out, canceled #1(stuck)");
+    assert!(done);
+
+    let r = coroutine::scope_timeout(Duration::from_secs(5), |scope| {
+        co!(scope, || coroutine::sleep(Duration::from_millis(10)));
+        42
+    });
+    assert_eq!(r, Ok(42));
+}
+
+#[test]
+fn scope_detached_progress() {
+    use mco::std::sync::channel;
+
+    let (tx, rx) = channel::<()>();
+    let handle = coroutine::scope_detached(|scope| {
+        for _ in 0..3 {
+            let rx = rx.clone();
+            scope.spawn(move || rx.recv().unwrap());
+        }
+    });
+    assert_eq!(handle.remaining(), 3);
+    assert!(!handle.wait_timeout(Duration::from_millis(10)));
+
+    // a watcher outside of the scope sees the progress
+    let watch = handle.clone();
+    let h = co!(move || {
+        watch.wait();
+        watch.remaining()
+    });
+    tx.send(()).unwrap();
+    tx.send(()).unwrap();
+    let start = Instant::now();
+    while handle.remaining() > 1 && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(handle.remaining(), 1);
+    assert!(!handle.is_done());
+    tx.send(()).unwrap();
+    assert!(handle.wait_timeout(Duration::from_secs(5)));
+    assert_eq!(h.join().unwrap(), 0);
+}
+
+#[test]
+fn scope_detached_cancel() {
+    let handle = coroutine::scope_detached(|scope| {
+        scope.spawn(|| ());
+        for _ in 0..2 {
+            scope.spawn(|| loop {
+                coroutine::sleep(Duration::from_millis(10));
+            });
+        }
+    });
+    assert!(!handle.wait_timeout(Duration::from_millis(50)));
+    assert_eq!(handle.remaining(), 2);
+    handle.cancel();
+    assert!(handle.wait_timeout(Duration::from_secs(5)));
+    assert_eq!(handle.remaining(), 0);
+}
+
 #[test]
 fn yield_from_gen() {
     let mut a = 0;
@@ -343,3 +478,657 @@ fn co_with_macro() {
         assert_eq!(stack_size, 10240);
     }
 }
+
+#[test]
+fn co_stack_pool() {
+    // the finished coroutines with the same stack size class reuse the cached stacks
+    for i in 0..100 {
+        let stack_size = 0x3000 + i * 2;
+        let h = spawn_with!(stack_size, move || {
+            // touch some stack memory
+            let buf = [i as u8; 0x4000];
+            assert_eq!(coroutine::current().stack_size(), stack_size);
+            buf.iter().map(|v| *v as usize).sum::<usize>()
+        });
+        assert_eq!(h.join().unwrap(), i * 0x4000);
+    }
+}
+
+#[test]
+fn co_growable_stack() {
+    use mco::coroutine::{Builder, StackKind};
+
+    fn deep(n: usize) -> usize {
+        // use about 1KB stack for each call
+        let buf = [n as u8; 1024];
+        if n == 0 {
+            return buf[0] as usize;
+        }
+        std::hint::black_box(&buf);
+        deep(n - 1) + 1
+    }
+
+    let kind = StackKind::Growable {
+        initial: 0x400,
+        max: 0x10000,
+    };
+    let h = Builder::new().stack(kind).spawn(|| {
+        assert!(mco::stats::stats().growable_stacks >= 1);
+        deep(128)
+    });
+    assert_eq!(h.join().unwrap(), 128);
+    assert!(mco::stats::stats().growable_stack_committed >= 128 * 1024);
+}
+
+#[test]
+fn co_spawn_suspended() {
+    use mco::coroutine::Builder;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let ran = Arc::new(AtomicBool::new(false));
+    let r = ran.clone();
+    let (h, start) = Builder::new().spawn_suspended(move || r.store(true, Ordering::SeqCst));
+    thread::sleep(Duration::from_millis(10));
+    assert!(!ran.load(Ordering::SeqCst));
+    assert!(!start.is_started());
+    start.start();
+    // start is idempotent
+    start.start();
+    assert!(start.is_started());
+    h.join().unwrap();
+    assert!(ran.load(Ordering::SeqCst));
+
+    // dropping the start handle drops the captured values without running the body
+    let data = Arc::new(0);
+    let d = data.clone();
+    let r = ran.clone();
+    ran.store(false, Ordering::SeqCst);
+    let (h, start) = Builder::new().spawn_suspended(move || {
+        r.store(true, Ordering::SeqCst);
+        d
+    });
+    drop(start);
+    assert_eq!(Arc::strong_count(&data), 1);
+    assert!(h.join().is_err());
+    assert!(!ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn co_ping_pong_lifo() {
+    use mco::std::sync::channel::channel;
+
+    let (tx1, rx1) = channel();
+    let (tx2, rx2) = channel();
+    let h = co!(move || {
+        for i in rx1.iter() {
+            tx2.send(i).unwrap();
+        }
+    });
+    co!(move || {
+        for i in 0..1000 {
+            tx1.send(i).unwrap();
+            assert_eq!(rx2.recv().unwrap(), i);
+        }
+    })
+    .join()
+    .unwrap();
+    h.join().unwrap();
+    // the unblocked peer runs from the lifo slot of the worker
+    assert!(mco::stats::stats().lifo_hits > 0);
+}
+
+#[test]
+fn net_from_std() {
+    use std::io::{Read, Write};
+
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = std_listener.local_addr().unwrap();
+    let listener = mco::net::TcpListener::from_std(std_listener).unwrap();
+
+    let server = co!(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        let mut buf = [0; 5];
+        s.read_exact(&mut buf).unwrap();
+        s.write_all(&buf).unwrap();
+        // the std stream is blocking again
+        let mut s = s.into_std().unwrap();
+        s.read_exact(&mut buf).unwrap();
+        buf
+    });
+
+    let mut client = std::net::TcpStream::connect(addr).unwrap();
+    client.write_all(b"hello").unwrap();
+    let mut buf = [0; 5];
+    client.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    client.write_all(b"world").unwrap();
+    assert_eq!(&server.join().unwrap(), b"world");
+
+    let std_socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = std_socket.local_addr().unwrap();
+    let socket = mco::net::UdpSocket::from_std(std_socket).unwrap();
+    let h = co!(move || {
+        let mut buf = [0; 5];
+        let (n, _) = socket.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        socket.into_std().unwrap()
+    });
+    let client = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    client.send_to(b"hello", addr).unwrap();
+    let socket = h.join().unwrap();
+    assert_eq!(socket.local_addr().unwrap(), addr);
+}
+
+#[test]
+fn udp_mmsg_batch() {
+    use mco::net::{MsgBuf, OutMsg, UdpSocket};
+
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let from = client.local_addr().unwrap();
+
+    let h = co!(move || {
+        let mut bufs = vec![[0u8; 16]; 8];
+        let mut got = Vec::new();
+        while got.len() < 8 {
+            let mut msgs: Vec<MsgBuf> = bufs.iter_mut().map(|b| MsgBuf::new(b)).collect();
+            let n = server.recv_mmsg(&mut msgs).unwrap();
+            assert!(n > 0);
+            for m in &msgs[..n] {
+                assert_eq!(m.addr(), Some(from));
+                got.push(m.data().to_vec());
+            }
+        }
+        got
+    });
+
+    let data: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; i as usize + 1]).collect();
+    let msgs: Vec<OutMsg> = data.iter().map(|d| OutMsg::to(d, addr)).collect();
+    let mut sent = 0;
+    while sent < msgs.len() {
+        sent += client.send_mmsg(&msgs[sent..]).unwrap();
+    }
+    assert_eq!(h.join().unwrap(), data);
+}
+
+#[test]
+fn tcp_send_file() {
+    use std::io::{Read, Write};
+
+    let path = std::env::temp_dir().join(format!("mco_send_file_{}", std::process::id()));
+    let data: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+    std::fs::File::create(&path)
+        .unwrap()
+        .write_all(&data)
+        .unwrap();
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let file_len = data.len() as u64;
+    let h = co!(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        // far more than the socket buffer, so it's parked in between
+        let n = s.send_file(&file, 100, 3 * 1024 * 1024).unwrap();
+        assert_eq!(n, 3 * 1024 * 1024);
+        // stops at the end of the file
+        let n = s.send_file(&file, file_len - 10, 100).unwrap();
+        assert_eq!(n, 10);
+    });
+
+    let mut c = mco::net::TcpStream::connect(addr).unwrap();
+    coroutine::sleep(Duration::from_millis(50));
+    let mut got = Vec::new();
+    c.read_to_end(&mut got).unwrap();
+    h.join().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(got.len(), 3 * 1024 * 1024 + 10);
+    assert!(got[..3 * 1024 * 1024] == data[100..100 + 3 * 1024 * 1024]);
+    assert!(got[3 * 1024 * 1024..] == data[data.len() - 10..]);
+}
+
+#[test]
+fn accept_loop_echo() {
+    use std::io::{Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let served = Arc::new(AtomicUsize::new(0));
+    let count = served.clone();
+    let server = listener
+        .accept_loop_with(0, 16 * 1024, move |mut s| {
+            let mut buf = [0; 64];
+            loop {
+                match s.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => s.write_all(&buf[..n]).unwrap(),
+                }
+            }
+            count.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+    let addr = server.local_addr();
+
+    // 1k concurrent connections
+    let clients: Vec<_> = (0..1000)
+        .map(|i| {
+            co!(move || {
+                let mut s = mco::net::TcpStream::connect(addr).unwrap();
+                let msg = format!("hello {}", i);
+                s.write_all(msg.as_bytes()).unwrap();
+                let mut buf = vec![0; msg.len()];
+                s.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, msg.as_bytes());
+            })
+        })
+        .collect();
+    for c in clients {
+        c.join().unwrap();
+    }
+
+    server.shutdown();
+    server.join();
+    assert_eq!(served.load(Ordering::SeqCst), 1000);
+    assert!(std::net::TcpStream::connect(addr).is_err());
+}
+
+#[test]
+fn accept_loop_spawn_fails() {
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let served = Arc::new(AtomicUsize::new(0));
+    let count = served.clone();
+    // far beyond the max stack size, no handler can be spawned
+    let server = listener
+        .accept_loop_with(1, usize::MAX >> 4, move |_| {
+            count.fetch_add(1, Ordering::SeqCst);
+        })
+        .unwrap();
+    let addr = server.local_addr();
+
+    // the connections are closed and the acceptor keeps accepting
+    for _ in 0..3 {
+        let mut s = std::net::TcpStream::connect(addr).unwrap();
+        let mut buf = [0; 8];
+        assert!(matches!(s.read(&mut buf), Ok(0) | Err(_)));
+    }
+    server.shutdown();
+    server.join();
+    assert_eq!(served.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn tcp_bytes() {
+    use mco::std::bytes::{Bytes, BytesMut};
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // one shared payload behind many headers
+    let payload = Bytes::from(vec![7u8; 100 * 1024]);
+    let h = co!(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        let mut bufs = Vec::new();
+        for i in 0..20u32 {
+            bufs.push(Bytes::copy_from_slice(&i.to_le_bytes()));
+            bufs.push(payload.clone());
+        }
+        s.write_all_bytes(&bufs).unwrap();
+    });
+
+    let mut c = mco::net::TcpStream::connect(addr).unwrap();
+    let mut buf = BytesMut::new();
+    let mut frames = Vec::new();
+    loop {
+        while buf.len() >= 4 + 100 * 1024 {
+            let frame = buf.split_to(4 + 100 * 1024).freeze();
+            frames.push(frame);
+        }
+        if c.read_buf(&mut buf).unwrap() == 0 {
+            break;
+        }
+    }
+    h.join().unwrap();
+    assert!(buf.is_empty());
+    assert_eq!(frames.len(), 20);
+    for (i, f) in frames.iter().enumerate() {
+        assert_eq!(f[..4], (i as u32).to_le_bytes());
+        assert!(f[4..].iter().all(|b| *b == 7));
+    }
+}
+
+#[test]
+fn tcp_read_ctx() {
+    use mco::std::context::{Context, ContextError};
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mco::chan!();
+    let h = co!(move || {
+        let (_s, _) = listener.accept().unwrap();
+        // keep the peer silent till both reads are done
+        rx.recv().unwrap()
+    });
+
+    let ctx = Context::background().with_cancel();
+    let c = ctx.clone();
+    let reader = co!(move || {
+        let mut s = mco::net::TcpStream::connect(addr).unwrap();
+        let mut buf = [0; 16];
+        let e = s.read_ctx(&c, &mut buf).unwrap_err();
+        assert_eq!(
+            e.get_ref().and_then(|e| e.downcast_ref::<ContextError>()),
+            Some(&ContextError::Canceled)
+        );
+        // the stream and the coroutine are still usable after the cancel
+        let ctx = Context::background().with_timeout(Duration::from_millis(20));
+        let e = s.read_ctx(&ctx, &mut buf).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    });
+    coroutine::sleep(Duration::from_millis(20));
+    ctx.cancel();
+    reader.join().unwrap();
+    tx.send(()).unwrap();
+    h.join().unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn tcp_udp_peek() {
+    use std::io::{Read, Write};
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let h = co!(move || {
+        let (mut s, _) = listener.accept().unwrap();
+        assert!(s.take_error().unwrap().is_none());
+        // it waits for the data
+        let mut magic = [0; 4];
+        let mut n = 0;
+        while n < 4 {
+            n = s.peek(&mut magic).unwrap();
+        }
+        assert_eq!(&magic, b"MCO1");
+        // the whole message is still there
+        let mut msg = Vec::new();
+        s.read_to_end(&mut msg).unwrap();
+        msg
+    });
+    let mut c = mco::net::TcpStream::connect(addr).unwrap();
+    coroutine::sleep(Duration::from_millis(20));
+    c.write_all(b"MCO1 hello").unwrap();
+    drop(c);
+    assert_eq!(h.join().unwrap(), b"MCO1 hello");
+
+    let server = mco::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let client = mco::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let h = co!(move || {
+        let mut buf = [0; 16];
+        let (n, from) = server.peek_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        let (m, again) = server.recv_from(&mut buf).unwrap();
+        assert_eq!((m, again), (n, from));
+        from
+    });
+    coroutine::sleep(Duration::from_millis(20));
+    client.send_to(b"ping", addr).unwrap();
+    assert_eq!(h.join().unwrap(), client.local_addr().unwrap());
+}
+
+#[test]
+fn select_socket_channel_token() {
+    use mco::select::SelectSet;
+    use mco::std::sync::CancellationToken;
+    use std::io::{Read, Write};
+
+    #[derive(Debug, PartialEq)]
+    enum Ev {
+        Readable,
+        Msg(i32),
+        Canceled,
+    }
+
+    let listener = mco::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let peer = co!(move || listener.accept().unwrap().0);
+    let mut stream = mco::net::TcpStream::connect(addr).unwrap();
+    let mut peer = peer.join().unwrap();
+    let (tx, rx) = mco::chan!();
+    let token = CancellationToken::new();
+    let t = token.clone();
+
+    let h = co!(move || {
+        let mut evs = Vec::new();
+        let mut buf = [0; 16];
+        for _ in 0..4 {
+            let ev = {
+                let mut set = SelectSet::new();
+                set.add(stream.readable(), |r| r.map(|_| Ev::Readable).unwrap());
+                set.add(&rx, |m| Ev::Msg(m.unwrap()));
+                set.add(&t, |_| Ev::Canceled);
+                set.select()
+            };
+            if ev == Ev::Readable {
+                // the readiness takes no data
+                let n = stream.read(&mut buf).unwrap();
+                evs.push(Ev::Msg(n as i32));
+            }
+            evs.push(ev);
+        }
+        (stream, evs)
+    });
+
+    coroutine::sleep(Duration::from_millis(20));
+    tx.send(1).unwrap();
+    coroutine::sleep(Duration::from_millis(20));
+    peer.write_all(b"hello").unwrap();
+    coroutine::sleep(Duration::from_millis(20));
+    // the readiness of the last select is gone with the data
+    tx.send(2).unwrap();
+    coroutine::sleep(Duration::from_millis(20));
+    token.cancel();
+    let (stream, evs) = h.join().unwrap();
+    assert_eq!(
+        evs,
+        vec![
+            Ev::Msg(1),
+            Ev::Msg(5),
+            Ev::Readable,
+            Ev::Msg(2),
+            Ev::Canceled
+        ]
+    );
+
+    // the losers are deregistered, nothing is ready after the data is read
+    let mut set = SelectSet::new();
+    set.add(stream.readable(), |r| r.is_ok());
+    assert_eq!(set.select_timeout(Duration::from_millis(20)), None);
+    drop(set);
+    let mut set = SelectSet::new();
+    set.add(stream.writable(), |r| r.is_ok());
+    assert_eq!(set.try_select(), Some(true));
+
+    // a hang up is readable
+    drop(peer);
+    let mut set = SelectSet::new();
+    set.add(stream.readable(), |r| r.is_ok());
+    assert_eq!(set.select_timeout(Duration::from_secs(5)), Some(true));
+}
+
+#[test]
+fn join_select_first_done() {
+    let fast = co!(|| 1);
+    let slow = co!(|| {
+        coroutine::sleep(Duration::from_millis(100));
+        2
+    });
+    let mut first = 0;
+    select! {
+        Ok(Ok(v)) = fast.join_select() => first = v,
+        Ok(Ok(v)) = slow.join_select() => first = v
+    };
+    assert_eq!(first, 1);
+    // the result is taken only once
+    assert_eq!(fast.join_select().unwrap_err(), coroutine::AlreadyTaken);
+    assert!(fast.join().unwrap_err().is::<coroutine::AlreadyTaken>());
+    // the losing arm doesn't take the result
+    assert_eq!(slow.join().unwrap(), 2);
+
+    // it fires at once for the finished coroutine
+    let done = co!(|| panic!("dead"));
+    done.wait();
+    assert!(done.join_select().unwrap().is_err());
+}
+
+#[test]
+fn spawn_with_options() {
+    let info = || {
+        let co = coroutine::current();
+        (
+            co.name().map(ToOwned::to_owned),
+            co.stack_size(),
+            co.pinned_worker(),
+        )
+    };
+    let h = spawn_with!(stack: 0x4000, name: String::from("opts"), pin: 0, info);
+    assert_eq!(
+        h.join().unwrap(),
+        (Some("opts".to_owned()), 0x4000, Some(0))
+    );
+
+    // the options are in any order
+    let h = spawn_with!(name: "opts2", stack: 0x4000, info);
+    assert_eq!(h.join().unwrap(), (Some("opts2".to_owned()), 0x4000, None));
+
+    // the positional forms still work
+    let stack_size = 0x4000;
+    let h = spawn_with!(stack_size, info);
+    assert_eq!(h.join().unwrap().1, 0x4000);
+}
+
+#[test]
+#[should_panic(expected = "negative coroutine stack size: -1")]
+fn spawn_with_negative_stack() {
+    spawn_with!(-1, || {});
+}
+
+#[test]
+fn join_results_in_input_order() {
+    let (a, b, c) = join!(
+        {
+            coroutine::sleep(Duration::from_millis(50));
+            "a"
+        },
+        {
+            coroutine::sleep(Duration::from_millis(10));
+            "b"
+        },
+        "c"
+    );
+    assert_eq!((a, b, c), ("a", "b", "c"));
+}
+
+#[test]
+fn race_first_wins() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let late = AtomicBool::new(false);
+    let v = race!(
+        {
+            coroutine::sleep(Duration::from_millis(10));
+            10
+        },
+        {
+            coroutine::sleep(Duration::from_millis(50));
+            // canceled at the sleep, never gets here
+            late.store(true, Ordering::SeqCst);
+            50
+        }
+    );
+    assert_eq!(v, 10);
+    coroutine::sleep(Duration::from_millis(100));
+    assert!(!late.load(Ordering::SeqCst));
+}
+
+#[test]
+fn join_return_values_of_any_size() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Counted(Arc<AtomicUsize>, [u64; 8]);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[repr(align(64))]
+    #[derive(Debug, PartialEq)]
+    struct Aligned(u8);
+
+    co!(|| ()).join().unwrap();
+    assert_eq!(co!(|| (1usize, 2usize, 3usize)).join().unwrap(), (1, 2, 3));
+    assert_eq!(co!(|| [7u64; 32]).join().unwrap(), [7u64; 32]);
+    assert_eq!(co!(|| Aligned(9)).join().unwrap(), Aligned(9));
+    assert_eq!(co!(|| "small".to_owned()).join().unwrap(), "small");
+
+    // the return values that are never taken are dropped with the handles
+    let drops = Arc::new(AtomicUsize::new(0));
+    let d = drops.clone();
+    let big = co!(move || Counted(d, [0; 8]));
+    let d = drops.clone();
+    let small = co!(move || Box::new(Counted(d, [0; 8])));
+    big.wait();
+    small.wait();
+    assert_eq!(drops.load(Ordering::SeqCst), 0);
+    drop(big);
+    drop(small);
+    assert_eq!(drops.load(Ordering::SeqCst), 2);
+
+    // and the ones of the coroutines that finish after the handles are gone
+    let d = drops.clone();
+    drop(co!(move || {
+        coroutine::sleep(Duration::from_millis(10));
+        Counted(d, [0; 8])
+    }));
+    let start = Instant::now();
+    while drops.load(Ordering::SeqCst) != 3 && start.elapsed() < Duration::from_secs(5) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(drops.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn coroutine_ids() {
+    use std::collections::HashSet;
+
+    let hs: Vec<_> = (0..100)
+        .map(|_| co!(|| coroutine::current().id()))
+        .collect();
+    let mut ids = HashSet::new();
+    for h in hs {
+        let id = h.id();
+        assert_eq!(h.coroutine().id(), id);
+        // the handles hash and compare by the id
+        let co = h.coroutine().clone();
+        assert!(co == *h.coroutine());
+        assert_eq!(h.join().unwrap(), id);
+        assert!(ids.insert(co));
+    }
+    assert_eq!(ids.len(), 100);
+
+    // a later coroutine gets a larger id
+    let a = co!(|| ());
+    let b = co!(|| ());
+    assert!(a.id() < b.id());
+    assert_eq!(a.id().to_string(), a.id().as_u64().to_string());
+    a.join().unwrap();
+    b.join().unwrap();
+}
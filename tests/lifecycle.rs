@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate mco;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use mco::runtime::{self, Config, Runtime};
+use mco::std::sync::channel;
+
+// the hooks are process wide, keep all the checks in one test
+#[test]
+fn start_and_shutdown_order() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let push = |log: &Arc<Mutex<Vec<String>>>, s: &str| log.lock().unwrap().push(s.to_owned());
+
+    let l = log.clone();
+    let start_a = runtime::on_start("a", move || push(&l, "start a"));
+    let _bad_start = runtime::on_start("bad", || panic!("bad start hook"));
+    let l = log.clone();
+    let start_b = runtime::on_start("b", move || {
+        // the coroutines of the hook run on the new runtime
+        let (tx, rx) = channel();
+        co!(move || tx.send(()).unwrap());
+        rx.recv().unwrap();
+        push(&l, "start b")
+    });
+
+    let l = log.clone();
+    let down_a = runtime::on_shutdown("a", move || push(&l, "down a"));
+    let stuck = runtime::on_shutdown_timeout("stuck", Duration::from_millis(50), || {
+        mco::coroutine::sleep(Duration::from_secs(1))
+    });
+    let _bad_down = runtime::on_shutdown("bad", || panic!("bad shutdown hook"));
+    let l = log.clone();
+    let down_b = runtime::on_shutdown("b", move || {
+        // a teardown can wait on the other coroutines
+        let (tx, rx) = channel();
+        let h = co!(move || tx.send("down b").unwrap());
+        push(&l, rx.recv().unwrap());
+        h.join().unwrap();
+    });
+
+    let rt = Runtime::new(Config::new().workers(2)).unwrap();
+    assert_eq!(*log.lock().unwrap(), ["start a", "start b"]);
+    log.lock().unwrap().clear();
+
+    let start = Instant::now();
+    // the stuck hook is still running after its timeout
+    assert!(!rt.shutdown(Duration::from_millis(10)));
+    assert!(start.elapsed() < Duration::from_millis(900));
+    assert_eq!(*log.lock().unwrap(), ["down b", "down a"]);
+    log.lock().unwrap().clear();
+
+    // the unregistered hooks don't run
+    drop(start_a);
+    drop(start_b);
+    drop(down_a);
+    drop(down_b);
+    let rt = Runtime::new(Config::new().workers(1)).unwrap();
+    drop(stuck);
+    assert!(rt.shutdown(Duration::from_secs(1)));
+    assert!(log.lock().unwrap().is_empty());
+}
@@ -50,3 +50,71 @@ fn coroutine_local_many() {
         assert_eq!(f.load(Ordering::Relaxed), 0);
     });
 }
+
+#[test]
+fn builder_local_drop_order() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    struct Event(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Drop for Event {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    coroutine_local!(static LAST: Mutex<Option<Event>> = Mutex::new(None));
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let (e1, e2, e3) = (events.clone(), events.clone(), events.clone());
+    let h = coroutine::Builder::new()
+        .with_local(move || Event("first", e1))
+        .with_local(move || Event("second", e2))
+        .with_local(|| 42u32)
+        .spawn(move || {
+            let name = coroutine::with_local_value(|e: &Event| e.0);
+            assert_eq!(name, Some("second"));
+            assert_eq!(coroutine::with_local_value(|v: &u32| *v), Some(42));
+            assert!(coroutine::with_local_value(|_: &i64| ()).is_none());
+            LAST.with(|l| *l.lock().unwrap() = Some(Event("coroutine_local", e3)));
+        });
+    h.join().unwrap();
+    // dropped before the join handle sees the result
+    assert_eq!(events.lock().unwrap()[..2], ["second", "first"]);
+
+    // the coroutine_local values are dropped when the storage is released
+    for _ in 0..100 {
+        if events.lock().unwrap().len() == 3 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(
+        *events.lock().unwrap(),
+        ["second", "first", "coroutine_local"]
+    );
+    assert!(coroutine::with_local_value(|_: &u32| ()).is_none());
+}
+
+#[test]
+fn builder_local_drop_on_panic() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct Flag(Arc<AtomicBool>);
+
+    impl Drop for Flag {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    let dropped = Arc::new(AtomicBool::new(false));
+    let d = dropped.clone();
+    let h = coroutine::Builder::new()
+        .with_local(move || Flag(d))
+        .spawn(|| panic!("body panics"));
+    assert!(h.join().is_err());
+    assert!(dropped.load(Ordering::SeqCst));
+}
@@ -0,0 +1,597 @@
+#[macro_use]
+extern crate mco;
+
+use mco::coroutine::{self, Builder};
+use mco::net::TcpListener;
+use mco::runtime::{Config, PinnedPolicy, ResizeError, Runtime};
+use mco::std::sync::channel;
+
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+fn runtime(workers: usize) -> Runtime {
+    Runtime::new(Config::new().workers(workers)).unwrap()
+}
+
+#[test]
+fn runtime_spawn() {
+    let rt = runtime(2);
+    assert_eq!(rt.workers(), 2);
+    let h = rt.spawn(|| {
+        // the children stay in the runtime
+        let h = co!(|| 1 + 1);
+        h.join().unwrap()
+    });
+    assert_eq!(h.join().unwrap(), 2);
+    assert!(rt.shutdown(Duration::from_secs(1)));
+}
+
+#[test]
+fn runtime_cross_channel() {
+    let a = runtime(1);
+    let b = runtime(3);
+    let (tx, rx) = channel::<usize>();
+    let (back_tx, back_rx) = channel::<usize>();
+    let ha = a.spawn(move || {
+        for i in 0..100 {
+            tx.send(i).unwrap();
+            assert_eq!(back_rx.recv().unwrap(), i + 1);
+        }
+    });
+    let hb = b.spawn(move || {
+        for i in rx.iter() {
+            back_tx.send(i + 1).unwrap();
+        }
+    });
+    ha.join().unwrap();
+    hb.join().unwrap();
+    // the default runtime still works
+    assert_eq!(co!(|| 3).join().unwrap(), 3);
+}
+
+#[test]
+fn runtime_block_on_scope() {
+    let rt = runtime(2);
+    let hits = AtomicUsize::new(0);
+    let n = rt.block_on_scope(|s| {
+        for _ in 0..10 {
+            unsafe {
+                s.spawn(|| {
+                    coroutine::sleep(Duration::from_millis(5));
+                    hits.fetch_add(1, Ordering::Relaxed);
+                })
+            };
+        }
+        10
+    });
+    assert_eq!(n, 10);
+    assert_eq!(hits.load(Ordering::Relaxed), 10);
+}
+
+#[test]
+fn runtime_shutdown_timeout() {
+    let rt = runtime(1);
+    rt.spawn(|| coroutine::sleep(Duration::from_secs(10)));
+    assert_eq!(rt.live_coroutines(), 1);
+    assert!(!rt.shutdown(Duration::from_millis(20)));
+}
+
+#[test]
+fn runtime_io_after_shutdown() {
+    let rt = runtime(1);
+    let listener = rt
+        .spawn(|| TcpListener::bind("127.0.0.1:0").unwrap())
+        .join()
+        .unwrap();
+    drop(rt);
+    let err = co!(move || listener.accept().map(|_| ()))
+        .join()
+        .unwrap()
+        .unwrap_err();
+    assert!(err.to_string().contains("shut down"));
+}
+
+// the fds of the io drivers are closed with the runtimes
+#[cfg(target_os = "linux")]
+#[test]
+fn runtime_drop_frees_fds() {
+    fn open_fds() -> usize {
+        std::fs::read_dir("/proc/self/fd").unwrap().count()
+    }
+
+    // the first runtime starts the process wide threads
+    drop(runtime(2));
+    let before = open_fds();
+    for _ in 0..50 {
+        let rt = runtime(2);
+        let listener = rt
+            .spawn(|| TcpListener::bind("127.0.0.1:0").unwrap())
+            .join()
+            .unwrap();
+        drop(listener);
+        assert_eq!(rt.spawn(|| 1).join().unwrap(), 1);
+        drop(rt);
+    }
+    // each runtime holds 4 fds, some slack for the other tests
+    let after = open_fds();
+    assert!(after < before + 20, "fds before {} after {}", before, after);
+}
+
+#[test]
+fn runtime_set_workers() {
+    let rt = Runtime::new(Config::new().workers(1).max_workers(4)).unwrap();
+    assert!(matches!(
+        rt.set_workers(5),
+        Err(ResizeError::OutOfRange { workers: 5, max: 4 })
+    ));
+    rt.set_workers(4).unwrap();
+    assert_eq!(rt.workers(), 4);
+    // the blocking coroutines run on the new threads in parallel
+    let start = Instant::now();
+    let hs: Vec<_> = (0..4)
+        .map(|_| rt.spawn(|| std::thread::sleep(Duration::from_millis(100))))
+        .collect();
+    hs.into_iter().for_each(|h| h.join().unwrap());
+    assert!(start.elapsed() < Duration::from_millis(300));
+
+    // the socket stays on the parked worker and still works
+    let (mut s, h) = rt
+        .spawn(|| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let h = co!(move || {
+                let (mut s, _) = listener.accept().unwrap();
+                let mut buf = [0; 4];
+                s.read_exact(&mut buf).unwrap();
+                s.write_all(&buf).unwrap();
+            });
+            (mco::net::TcpStream::connect(addr).unwrap(), h)
+        })
+        .join()
+        .unwrap();
+    rt.set_workers(1).unwrap();
+    assert_eq!(rt.workers(), 1);
+    let echo = rt.spawn(move || {
+        s.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        s.read_exact(&mut buf).unwrap();
+        buf
+    });
+    assert_eq!(&echo.join().unwrap(), b"ping");
+    h.join().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while rt.draining_workers() != 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(rt.draining_workers(), 0);
+    assert!(rt.shutdown(Duration::from_secs(1)));
+}
+
+#[test]
+fn runtime_shrink_pinned() {
+    let rt = Runtime::new(Config::new().workers(3).max_workers(3)).unwrap();
+    let (tx, rx) = channel::<usize>();
+    let h = rt.spawn_with(Builder::new().pin(2), move || {
+        let first = rx.recv().unwrap();
+        first + rx.recv().unwrap()
+    });
+    coroutine::sleep(Duration::from_millis(10));
+    match rt.set_workers(1) {
+        Err(ResizeError::Pinned { worker, coroutines }) => {
+            assert_eq!((worker, coroutines), (2, 1));
+        }
+        r => panic!("unexpected {:?}", r),
+    }
+    assert_eq!(rt.workers(), 3);
+    tx.send(1).unwrap();
+
+    // the pinned coroutine is moved to the active worker
+    rt.set_workers_with(1, PinnedPolicy::Migrate).unwrap();
+    tx.send(2).unwrap();
+    assert_eq!(h.join().unwrap(), 3);
+    // the worker takes the pinned coroutines again after it grows back
+    rt.set_workers(3).unwrap();
+    let h = rt.spawn_with(Builder::new().pin(2), || 42);
+    assert_eq!(h.join().unwrap(), 42);
+}
+
+#[cfg(unix)]
+#[test]
+fn runtime_io_fd_closed_under_reader() {
+    use mco::io::CoIo;
+    use std::io::{self, Read};
+    use std::os::unix::io::{AsRawFd, RawFd};
+
+    // doesn't close the fd on drop, the test closes it by hand
+    struct Pipe(RawFd);
+
+    impl AsRawFd for Pipe {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(n as usize)
+        }
+    }
+
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let mut reader = CoIo::new(Pipe(fds[0])).unwrap();
+    // a second wrapper of the same fd is rejected
+    let err = CoIo::new(Pipe(fds[0])).err().unwrap();
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::AlreadyExists);
+
+    let h = mco::co!(move || {
+        let mut buf = [0u8; 8];
+        reader.read(&mut buf)
+    });
+    mco::coroutine::sleep(Duration::from_millis(50));
+    let start = Instant::now();
+    unsafe { libc::close(fds[0]) };
+    assert!(h.join().unwrap().is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(mco::runtime::last_driver_error().is_some());
+    unsafe { libc::close(fds[1]) };
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn runtime_worker_panic() {
+    use mco::{config, stats::stats, WorkerPanic};
+
+    let rt = runtime(2);
+    // the worker is respawned, the coroutines pinned to it fail
+    config().set_worker_panic(WorkerPanic::Respawn);
+    let h = rt.spawn_with(Builder::new().pin(1), || loop {
+        coroutine::sleep(Duration::from_millis(1));
+    });
+    let panics = stats().worker_panics;
+    rt.inject_worker_panic(1);
+    assert!(h.join().is_err());
+    assert!(stats().worker_panics > panics);
+    assert_eq!(rt.workers(), 2);
+    let h = rt.spawn_with(Builder::new().pin(1), || 42);
+    assert_eq!(h.join().unwrap(), 42);
+
+    // the dead worker is left alone, the others keep running
+    config().set_worker_panic(WorkerPanic::Log);
+    rt.inject_worker_panic(1);
+    let start = Instant::now();
+    while rt.workers() != 1 && start.elapsed() < Duration::from_secs(5) {
+        coroutine::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(rt.workers(), 1);
+    assert_eq!(rt.spawn(|| 7).join().unwrap(), 7);
+}
+
+#[cfg(all(unix, feature = "test-util"))]
+#[test]
+fn runtime_worker_panic_io() {
+    use mco::io::CoIo;
+    use mco::{config, WorkerPanic};
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::FromRawFd;
+
+    // a pipe whose read end is registered on the selector of worker 1
+    fn pipe() -> (File, File) {
+        loop {
+            let mut fds = [0; 2];
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            let pipe = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+            if fds[0] % 2 == 1 {
+                return pipe;
+            }
+        }
+    }
+
+    let rt = runtime(2);
+    config().set_worker_panic(WorkerPanic::Log);
+    let h = rt.spawn(|| {
+        let (r, w) = pipe();
+        let mut reader = CoIo::new(r).unwrap();
+        let (r, idle_w) = pipe();
+        let mut idle = CoIo::new(r).unwrap();
+        idle.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let read = co!(move || {
+            let mut buf = [0u8; 8];
+            reader.read(&mut buf).unwrap()
+        });
+        let timed = co!(move || {
+            let mut buf = [0u8; 8];
+            idle.read(&mut buf).unwrap_err().kind()
+        });
+        coroutine::sleep(Duration::from_millis(50));
+        (read, timed, w, idle_w)
+    });
+    let (read, timed, mut w, _idle_w) = h.join().unwrap();
+    rt.inject_worker_panic(1);
+    let start = Instant::now();
+    while rt.workers() != 1 && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // the io and the io timers of the dead worker are run by the other one
+    w.write_all(b"hi").unwrap();
+    assert_eq!(read.join().unwrap(), 2);
+    assert_eq!(timed.join().unwrap(), io::ErrorKind::TimedOut);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn runtime_mutex_priority_boost() {
+    use mco::stats::stats;
+    use mco::std::sync::Mutex;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    // the yields of the critical section
+    const SECTION: usize = 20;
+    let rt = runtime(1);
+    let boosts = stats().priority_boosts;
+    let h = rt.spawn(move || {
+        let lock = Arc::new(Mutex::new(()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let mid_runs = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = channel();
+        let l = lock.clone();
+        let low = Builder::new().priority(1).spawn(move || {
+            let _g = l.lock().unwrap();
+            tx.send(()).unwrap();
+            for _ in 0..SECTION {
+                coroutine::yield_now();
+            }
+        });
+        rx.recv().unwrap();
+        // they run before the low one unless it's raised by the high one
+        let mids: Vec<_> = (0..2)
+            .map(|_| {
+                let (stop, mid_runs) = (stop.clone(), mid_runs.clone());
+                Builder::new().priority(2).spawn(move || {
+                    // bounded, so a missing boost fails instead of hangs
+                    while !stop.load(Ordering::Relaxed)
+                        && mid_runs.load(Ordering::Relaxed) < 100_000
+                    {
+                        mid_runs.fetch_add(1, Ordering::Relaxed);
+                        coroutine::yield_now();
+                    }
+                })
+            })
+            .collect();
+        let runs = mid_runs.clone();
+        let high = Builder::new().priority(3).spawn(move || {
+            let start = runs.load(Ordering::Relaxed);
+            let _g = lock.lock().unwrap();
+            runs.load(Ordering::Relaxed) - start
+        });
+        let waited = high.join().unwrap();
+        stop.store(true, Ordering::Relaxed);
+        low.join().unwrap();
+        for h in mids {
+            h.join().unwrap();
+        }
+        waited
+    });
+    // the high one waits for the rest of the critical section only
+    assert!(h.join().unwrap() < SECTION);
+    assert!(stats().priority_boosts > boosts);
+}
+
+#[test]
+fn spawn_from_fresh_threads() {
+    // the first spawns race to start the default runtime, it's started once
+    let hs: Vec<_> = (0..8)
+        .map(|i| std::thread::spawn(move || co!(move || i * 2).join().unwrap()))
+        .collect();
+    let sum: usize = hs.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(sum, (0..8).map(|i| i * 2).sum());
+}
+
+#[test]
+fn spawn_in_early_init() {
+    use std::sync::Once;
+
+    // like a static initializer that runs before anything else
+    static INIT: Once = Once::new();
+    static mut VALUE: usize = 0;
+    let hs: Vec<_> = (0..4)
+        .map(|_| {
+            std::thread::spawn(|| {
+                INIT.call_once(|| unsafe { VALUE = co!(|| 7).join().unwrap() });
+                unsafe { VALUE }
+            })
+        })
+        .collect();
+    for h in hs {
+        assert_eq!(h.join().unwrap(), 7);
+    }
+}
+
+#[test]
+fn spawn_after_shutdown() {
+    use mco::coroutine::SpawnError;
+    use std::sync::mpsc;
+
+    struct SpawnOnDrop(mpsc::Sender<bool>);
+
+    impl Drop for SpawnOnDrop {
+        fn drop(&mut self) {
+            // a panic here would abort, the handle carries the error instead
+            let err = co!(|| ()).join().unwrap_err();
+            let shutting = matches!(
+                err.downcast_ref::<SpawnError>(),
+                Some(SpawnError::ShuttingDown)
+            );
+            self.0.send(shutting).unwrap();
+        }
+    }
+
+    let rt = runtime(1);
+    let (go_tx, go_rx) = mpsc::channel::<()>();
+    let (ret_tx, ret_rx) = mpsc::channel();
+    rt.spawn(move || {
+        // block the worker thread until the runtime is stopped
+        go_rx.recv().unwrap();
+        let r = Builder::new().try_spawn(|| ());
+        ret_tx
+            .send(matches!(r, Err(SpawnError::ShuttingDown)))
+            .unwrap();
+        let plain = std::panic::catch_unwind(|| co!(|| ()));
+        ret_tx.send(plain.is_err()).unwrap();
+        let tx = ret_tx.clone();
+        let unwinding = std::panic::catch_unwind(move || {
+            let _guard = SpawnOnDrop(tx);
+            panic!("unwind through the guard");
+        });
+        ret_tx.send(unwinding.is_err()).unwrap();
+    });
+    let stop = std::thread::spawn(move || rt.shutdown(Duration::from_millis(10)));
+    // the runtime is stopped once the shutdown times out
+    std::thread::sleep(Duration::from_millis(200));
+    go_tx.send(()).unwrap();
+    let got: Vec<bool> = ret_rx.iter().take(4).collect();
+    assert_eq!(got, vec![true, true, true, true]);
+    assert!(!stop.join().unwrap());
+}
+
+#[test]
+fn maintenance_runs_while_idle() {
+    use mco::runtime::register_maintenance;
+    use std::sync::Arc;
+
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let t = ticks.clone();
+    let guard = register_maintenance(Duration::from_millis(20), move || {
+        t.fetch_add(1, Ordering::SeqCst);
+    });
+    // no coroutine is runnable, the worker wakes up for the callback
+    std::thread::sleep(Duration::from_millis(500));
+    let n = ticks.load(Ordering::SeqCst);
+    assert!(n >= 5 && n <= 30, "ran {} times", n);
+    assert_eq!(guard.runs(), n);
+    assert!(mco::stats::stats().maintenance_runs >= n);
+
+    drop(guard);
+    let n = ticks.load(Ordering::SeqCst);
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(ticks.load(Ordering::SeqCst), n);
+}
+
+#[test]
+fn maintenance_panic_isolated() {
+    use mco::runtime::register_maintenance;
+
+    let before = mco::stats::stats().maintenance_panics;
+    let bad = register_maintenance(Duration::from_millis(10), || panic!("bad callback"));
+    let good = register_maintenance(Duration::from_millis(10), || {
+        std::thread::sleep(Duration::from_millis(1))
+    });
+    std::thread::sleep(Duration::from_millis(200));
+    // the panicking one stays registered and the other keeps running
+    assert!(bad.runs() >= 2);
+    assert!(good.runs() >= 2);
+    assert!(good.total_time() >= Duration::from_millis(2));
+    let stats = mco::stats::stats();
+    assert!(stats.maintenance_panics >= before + 2);
+    assert!(stats.maintenance_time >= good.total_time());
+    // the coroutines of the worker still run
+    assert_eq!(co!(|| 6 * 7).join().unwrap(), 42);
+}
+
+#[test]
+fn thread_names() {
+    let current = || std::thread::current().name().map(str::to_owned);
+    mco::config().set_thread_name_prefix("svc");
+    let rt = runtime(2);
+    let name = rt.spawn(current).join().unwrap().unwrap();
+    assert!(name.starts_with("svc-worker-"), "{}", name);
+    let name = mco::std::blocking::spawn_blocking(current)
+        .unwrap()
+        .unwrap();
+    assert!(name.starts_with("svc-blocking-"), "{}", name);
+
+    co!(|| ()).join().unwrap();
+    let names = mco::stats::thread_names();
+    assert_eq!(names[0], "svc-timer");
+    for (i, name) in names[1..].iter().enumerate() {
+        assert_eq!(*name, format!("svc-worker-{}", i));
+    }
+    assert!(rt.shutdown(Duration::from_secs(1)));
+}
+
+#[test]
+fn runtime_worker_init() {
+    use mco::runtime::{self, WorkerContext};
+
+    let rt = Runtime::new(Config::new().workers(2).max_workers(3)).unwrap();
+    let inits = rt
+        .spawn(|| {
+            runtime::register_worker_init(|ctx: WorkerContext| {
+                let local = runtime::worker_local::<AtomicUsize>();
+                local.get_or_init(|| AtomicUsize::new(ctx.id() + 1));
+            });
+            // each active worker has its slot before the registration returns
+            let local = runtime::worker_local::<AtomicUsize>();
+            let ids: Vec<_> = local.iter().map(|(id, _)| id).collect();
+            assert_eq!(ids, [0, 1]);
+            runtime::for_each_worker(|ctx| {
+                let n = local.get().unwrap().load(Ordering::Relaxed);
+                assert_eq!(n, ctx.id() + 1);
+            });
+            local
+        })
+        .join()
+        .unwrap();
+    // the new worker runs the initializer too
+    rt.set_workers(3).unwrap();
+    let start = Instant::now();
+    while inits.get_worker(2).is_none() {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(inits.get_worker(2).unwrap().load(Ordering::Relaxed), 3);
+    assert!(inits.get().is_none());
+}
+
+#[test]
+fn runtime_exec_context() {
+    use coroutine::ExecContext;
+    use std::sync::Mutex;
+
+    assert_eq!(coroutine::context(), ExecContext::Foreign);
+    let ctx = co!(coroutine::context).join().unwrap();
+    assert!(matches!(ctx, ExecContext::Coroutine { worker: Some(_) }));
+    assert!(mco::runtime::is_running());
+
+    // the maintenance callbacks run in the scheduler context of a worker
+    let seen = std::sync::Arc::new(Mutex::new(None));
+    let s = seen.clone();
+    let guard = mco::runtime::register_maintenance(Duration::from_millis(10), move || {
+        let slept = std::panic::catch_unwind(|| coroutine::sleep(Duration::from_millis(1)));
+        s.lock()
+            .unwrap()
+            .get_or_insert((coroutine::context(), slept.is_err()));
+    });
+    let start = Instant::now();
+    while seen.lock().unwrap().is_none() && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    drop(guard);
+    let (ctx, slept_panicked) = seen.lock().unwrap().take().unwrap();
+    assert!(matches!(ctx, ExecContext::Scheduler { .. }));
+    // the sleep would block the worker, it panics instead
+    assert!(slept_panicked);
+
+    let rt = runtime(1);
+    let h = rt.spawn(mco::runtime::is_running);
+    assert!(h.join().unwrap());
+    assert!(rt.shutdown(Duration::from_secs(1)));
+}
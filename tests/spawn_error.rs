@@ -0,0 +1,63 @@
+//! the spawn errors of the real allocation failures
+//!
+//! the address space limit is for the whole process, so the test has a binary
+//! of its own
+#![cfg(all(target_os = "linux", target_env = "gnu"))]
+
+#[macro_use]
+extern crate mco;
+
+use mco::coroutine::{Builder, SpawnError};
+
+// the stack of the test, far beyond what the limit leaves, in usize
+const STACK: usize = 1 << 25;
+
+fn vm_size() -> usize {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let line = status.lines().find(|l| l.starts_with("VmSize:")).unwrap();
+    let kb: usize = line.split_whitespace().nth(1).unwrap().parse().unwrap();
+    kb * 1024
+}
+
+fn rlimit(res: libc::__rlimit_resource_t) -> libc::rlimit {
+    let mut lim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    assert_eq!(unsafe { libc::getrlimit(res, &mut lim) }, 0);
+    lim
+}
+
+#[test]
+fn try_spawn_stack_mmap_fails() {
+    let bytes = STACK * std::mem::size_of::<usize>();
+    // the max stack size is checked before the mmap
+    let stack = rlimit(libc::RLIMIT_STACK);
+    if stack.rlim_max != libc::RLIM_INFINITY && (stack.rlim_max as usize) < bytes * 2 {
+        return;
+    }
+    // the workers and their allocations are there before the limit
+    co!(|| ()).join().unwrap();
+
+    let old = rlimit(libc::RLIMIT_AS);
+    let cur = (vm_size() + bytes / 4) as libc::rlim_t;
+    if old.rlim_max != libc::RLIM_INFINITY && old.rlim_max < cur {
+        return;
+    }
+    let lim = libc::rlimit {
+        rlim_cur: cur,
+        rlim_max: old.rlim_max,
+    };
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_AS, &lim) }, 0);
+    let r = Builder::new().stack_size(STACK).try_spawn(|| 1);
+    assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_AS, &old) }, 0);
+
+    match r {
+        Err(SpawnError::StackAllocation(e)) => assert_eq!(e.raw_os_error(), Some(libc::ENOMEM)),
+        Err(e) => panic!("not the mmap failure: {}", e),
+        Ok(_) => panic!("spawned over the address space limit"),
+    }
+    // nothing is left behind by the failure
+    let h = Builder::new().stack_size(STACK).try_spawn(|| 1).unwrap();
+    assert_eq!(h.join().unwrap(), 1);
+}
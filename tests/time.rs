@@ -0,0 +1,84 @@
+use mco::std::time::{Sign, Time, UtcOffset};
+use proptest::prelude::*;
+use std::time::Duration;
+
+// the years 2 to 9998, so that the wall clock in any offset is in range
+const MIN_SECS: i64 = -62_104_060_800;
+const MAX_SECS: i64 = 253_370_764_799;
+
+fn any_time() -> impl Strategy<Value = Time> {
+    (
+        MIN_SECS..=MAX_SECS,
+        0..1_000_000_000u64,
+        -14 * 60..=14 * 60i32,
+    )
+        .prop_map(|(secs, nanos, minutes)| {
+            let offset = UtcOffset::from_whole_seconds(minutes * 60).unwrap();
+            let epoch = Time::ZERO.add_sec(-Time::ZERO.unix());
+            epoch
+                .add_sec(secs)
+                .add(Duration::from_nanos(nanos))
+                .to_offset(offset)
+        })
+}
+
+fn any_duration() -> impl Strategy<Value = Duration> {
+    prop_oneof![
+        (0..u64::MAX).prop_map(Duration::from_nanos),
+        (0..u64::MAX, 0..1_000_000_000u32).prop_map(|(s, n)| Duration::new(s, n)),
+    ]
+}
+
+proptest! {
+    #[cfg(feature = "time-format")]
+    #[test]
+    fn format_parse_round_trip(t in any_time()) {
+        let s = t.to_string();
+        let parsed: Time = s.parse().unwrap();
+        prop_assert_eq!(parsed.unix_nano_i128(), t.unix_nano_i128());
+        prop_assert_eq!(parsed.to_string(), s);
+    }
+
+    #[test]
+    fn add_sub_inverse(t in any_time(), d in any_duration()) {
+        if let Some(u) = t.checked_add(d) {
+            prop_assert_eq!(u.checked_sub(d), Some(t.clone()));
+            prop_assert_eq!(u.unix_nano_i128() - t.unix_nano_i128(), d.as_nanos() as i128);
+        }
+        if let Some(u) = t.checked_sub(d) {
+            prop_assert_eq!(u.checked_add(d), Some(t.clone()));
+        }
+    }
+
+    #[test]
+    fn add_monotonic(t in any_time(), a in any_duration(), b in any_duration()) {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        prop_assert!(t.clone().add(a) <= t.clone().add(b));
+        prop_assert!(t.clone().sub(a) >= t.clone().sub(b));
+        prop_assert!(t.clone().add(b) <= Time::MAX);
+        prop_assert!(t.clone().sub(b) >= Time::MIN);
+    }
+
+    #[test]
+    fn order_matches_unix(a in any_time(), b in any_time()) {
+        let by_unix = a.unix_nano_i128().cmp(&b.unix_nano_i128());
+        prop_assert_eq!(a.cmp(&b), by_unix);
+        prop_assert_eq!(a.after(&b), by_unix.is_gt());
+        prop_assert_eq!(a.before(&b), by_unix.is_lt());
+        prop_assert!(a.unix() <= b.unix() || by_unix.is_gt());
+    }
+
+    #[test]
+    fn epoch_round_trip(t in any_time(), e in any_time()) {
+        for epoch in [Time::UNIX_EPOCH, Time::Y2K, e].iter() {
+            let (sign, d) = t.to_epoch(epoch);
+            prop_assert_eq!(sign == Sign::Negative, t.before(epoch));
+            let back = Time::from_epoch_signed(epoch.clone(), sign, d).unwrap();
+            prop_assert_eq!(back.unix_nano_i128(), t.unix_nano_i128());
+            match t.duration_since(epoch) {
+                Ok(since) => prop_assert_eq!((sign, since), (Sign::Positive, d)),
+                Err(neg) => prop_assert_eq!((sign, neg.duration()), (Sign::Negative, d)),
+            }
+        }
+    }
+}
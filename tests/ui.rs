@@ -0,0 +1,7 @@
+//! the compile errors of the macros, `TRYBUILD=overwrite cargo test --test ui`
+//! writes the expected output again
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
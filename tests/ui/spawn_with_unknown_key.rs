@@ -0,0 +1,3 @@
+fn main() {
+    mco::spawn_with!(nmae: "conn", || {});
+}
@@ -0,0 +1,3 @@
+fn main() {
+    mco::spawn_with!(name: "conn", stak: 0x4000, || {});
+}
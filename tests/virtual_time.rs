@@ -0,0 +1,85 @@
+#![cfg(feature = "test-util")]
+#[macro_use]
+extern crate mco;
+
+use mco::coroutine;
+use mco::std::sync::channel;
+use mco::std::time::{self, Ticker, Time};
+use parking_lot::{Mutex, MutexGuard};
+
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// the clock is process wide, the tests of this binary run one by one
+fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = parking_lot::const_mutex(());
+    SERIAL.lock()
+}
+
+// wait till `n` more timers than `base` are registered
+fn wait_timers(base: usize, n: usize) {
+    while time::pending_timers() < base + n {
+        thread::yield_now();
+    }
+}
+
+#[test]
+fn virtual_sleep() {
+    let _serial = serial();
+    let _paused = time::pause();
+    let start = Instant::now();
+    let base = time::pending_timers();
+    let h = co!(|| coroutine::sleep(Duration::from_secs(30)));
+    wait_timers(base, 1);
+    time::advance(Duration::from_secs(29));
+    assert!(!h.is_done());
+    time::advance(Duration::from_secs(1));
+    h.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn virtual_recv_timeout() {
+    let _serial = serial();
+    let _paused = time::pause();
+    let (tx, rx) = channel::<i32>();
+    let base = time::pending_timers();
+    let h = co!(move || rx.recv_timeout(Duration::from_secs(30)));
+    wait_timers(base, 1);
+    time::advance(Duration::from_secs(30));
+    assert_eq!(h.join().unwrap(), Err(RecvTimeoutError::Timeout));
+    drop(tx);
+}
+
+#[test]
+fn virtual_ticker() {
+    let _serial = serial();
+    let _paused = time::pause().with_wall_clock();
+    let t0 = Time::now();
+    let base = time::pending_timers();
+    let ticker = Ticker::new(Duration::from_secs(60));
+    // the ticker sleeps in a coroutine of its own
+    wait_timers(base, 1);
+    time::advance(Duration::from_secs(60));
+    let tick = ticker.recv.recv().unwrap();
+    assert_eq!(tick.unix_nano() - t0.unix_nano(), 60_000_000_000);
+    // `stop` waits for the sleeping ticker, which never wakes up here
+    drop(ticker);
+}
+
+#[test]
+fn resume_keeps_monotonic() {
+    let _serial = serial();
+    let before = Instant::now();
+    {
+        let _paused = time::pause();
+        time::advance(Duration::from_secs(3600));
+    }
+    // the sleeps after a resume still take the real time
+    let h = co!(|| coroutine::sleep(Duration::from_millis(20)));
+    h.join().unwrap();
+    assert!(before.elapsed() >= Duration::from_millis(20));
+    // do nothing when not paused
+    time::resume();
+}